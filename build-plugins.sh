@@ -52,6 +52,18 @@ PLUGINS=(
     "directory"
     "config-reload"
     "html-prettifier"
+    "content-negotiation"
+    "record-replay"
+    "concurrency-limit"
+    "websocket-hub"
+    "csrf"
+    "schema-validation"
+    "maintenance"
+    "cron"
+    "events"
+    "token-auth"
+    "response-cache"
+    "search"
 )
 
 for plugin in "${PLUGINS[@]}"; do
@@ -115,9 +115,12 @@ impl MicrodataItem {
         let mut properties = Vec::new();
         let mut visited_ids = HashSet::new();
         
-        // First, collect properties from descendants
+        // First, collect properties from descendants, leaving those of nested items to them
         let descendants = item_element.select("[itemprop]");
         for descendant in descendants.iter() {
+            if is_within_different_itemscope(&descendant, item_element) {
+                continue;
+            }
             let element_properties = crate::MicrodataProperty::from_element_with_document(&descendant, document)?;
             properties.extend(element_properties);
         }
@@ -261,19 +264,20 @@ fn escape_css_id(id: &str) -> String {
 
 /// Check if an element is within a different itemscope than the given ancestor
 fn is_within_different_itemscope(element: &Selection, ancestor: &Selection) -> bool {
-    // Check if there's an itemscope between element and ancestor
-    // This is a simplified check - for full compliance we'd need proper DOM traversal
-    let element_html = element.html();
-    let ancestor_html = ancestor.html();
-    
-    // If they're the same element, return false
-    if element_html == ancestor_html {
+    let (Some(element), Some(ancestor)) = (element.nodes().first(), ancestor.nodes().first()) else {
         return false;
+    };
+
+    // Walk up from the element: an itemscope met before the ancestor starts a nested item
+    let mut current = element.parent();
+    while let Some(node) = current {
+        if node.id == ancestor.id {
+            return false;
+        }
+        if node.has_attr("itemscope") {
+            return true;
+        }
+        current = node.parent();
     }
-    
-    // This is a limitation of the current implementation
-    // We can't easily traverse the DOM tree with dom_query 0.19
-    // So we'll use a heuristic: check if the element has itemscope
-    // In practice, this works for most cases
     false
 }
\ No newline at end of file
@@ -0,0 +1,202 @@
+//! Alerts on bursts of server errors
+//!
+//! Triggers are declared as `https://rustybeam.net/schema/AlertTrigger`
+//! microdata items in the file named by `alerts_file`:
+//! - `name`: Name of the trigger, used in logs and webhooks (required)
+//! - `threshold`: Number of 5xx responses that raise the alert (required)
+//! - `window`: Seconds the responses must fall within (default: 60)
+//! - `pathPrefix`: Only responses to paths starting with this count
+//!   (default: "/")
+//! - `webhookUrl`: URL a JSON description of the alert is POSTed to
+//! - `sentinelFile`: File written when the alert is raised, such as the
+//!   `sentinel_file` of the maintenance plugin
+//!
+//! Every alert is logged as a warning; the webhook and sentinel file are
+//! optional extras. Once a trigger has fired it counts again from zero, so a
+//! continuing incident raises an alert for every `threshold` errors rather
+//! than for every error. Webhooks are sent from a thread of their own, so a
+//! slow receiver never holds up responses.
+
+use chrono::{SecondsFormat, Utc};
+use microdata_extract::{MicrodataExtractor, MicrodataItem};
+use rusty_beam_plugin_api::{log_error, log_warn};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const SCHEMA_ALERT_TRIGGER: &str = "https://rustybeam.net/schema/AlertTrigger";
+const DEFAULT_WINDOW_SECONDS: u64 = 60;
+const DEFAULT_PATH_PREFIX: &str = "/";
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// A burst of server errors that raises an alert
+#[derive(Debug)]
+struct AlertTrigger {
+    name: String,
+    threshold: usize,
+    window: Duration,
+    path_prefix: String,
+    webhook_url: Option<String>,
+    sentinel_file: Option<PathBuf>,
+    /// When the errors counting towards the threshold happened
+    errors: Mutex<VecDeque<Instant>>,
+}
+
+/// What a raised alert reports
+struct Alert<'a> {
+    trigger: &'a AlertTrigger,
+    errors: usize,
+    host: &'a str,
+    path: &'a str,
+    status: u16,
+}
+
+impl AlertTrigger {
+    fn from_item(item: &MicrodataItem) -> Result<Self, String> {
+        let property = |name: &str| item.get_property(name)
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+
+        let name = property("name").ok_or("Alert trigger without a name")?;
+        let threshold = property("threshold")
+            .and_then(|threshold| threshold.parse::<usize>().ok())
+            .filter(|threshold| *threshold > 0)
+            .ok_or_else(|| format!("Alert trigger '{}' needs a threshold above 0", name))?;
+        let window = match property("window") {
+            Some(window) => window.parse::<u64>()
+                .ok()
+                .filter(|window| *window > 0)
+                .ok_or_else(|| format!("Alert trigger '{}' has an invalid window '{}'", name, window))?,
+            None => DEFAULT_WINDOW_SECONDS,
+        };
+
+        Ok(Self {
+            name,
+            threshold,
+            window: Duration::from_secs(window),
+            path_prefix: property("pathPrefix").unwrap_or_else(|| DEFAULT_PATH_PREFIX.to_string()),
+            webhook_url: property("webhookUrl"),
+            sentinel_file: property("sentinelFile")
+                .map(|file| PathBuf::from(file.strip_prefix("file://").unwrap_or(&file))),
+            errors: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    /// Count an error, returning how many were counted if that raises the alert
+    fn count_error(&self, now: Instant) -> Option<usize> {
+        let mut errors = self.errors.lock().unwrap_or_else(|e| e.into_inner());
+        while errors.front().is_some_and(|time| now.duration_since(*time) >= self.window) {
+            errors.pop_front();
+        }
+        errors.push_back(now);
+        if errors.len() < self.threshold {
+            return None;
+        }
+        let count = errors.len();
+        errors.clear();
+        Some(count)
+    }
+}
+
+impl Alert<'_> {
+    /// The alert as sent to webhooks and written to sentinel files
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "alert": self.trigger.name,
+            "path_prefix": self.trigger.path_prefix,
+            "errors": self.errors,
+            "window_seconds": self.trigger.window.as_secs(),
+            "host": self.host,
+            "last_path": self.path,
+            "last_status": self.status,
+            "time": Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+        })
+    }
+}
+
+/// The alert triggers of a plugin and the errors counted so far
+#[derive(Debug, Default)]
+pub struct Alerts {
+    name: String,
+    triggers: Vec<AlertTrigger>,
+}
+
+impl Alerts {
+    /// Read the triggers declared in an alerts file, skipping invalid ones
+    pub fn load(name: &str, alerts_file: &Path) -> Self {
+        let mut alerts = Self { name: name.to_string(), triggers: Vec::new() };
+        let content = match std::fs::read_to_string(alerts_file) {
+            Ok(content) => content,
+            Err(e) => {
+                log_error!(name, "Failed to read alerts file: {}", e; file = alerts_file.display());
+                return alerts;
+            }
+        };
+        let items = match MicrodataExtractor::new().extract(&content) {
+            Ok(items) => items,
+            Err(e) => {
+                log_error!(name, "Failed to parse alerts file: {}", e; file = alerts_file.display());
+                return alerts;
+            }
+        };
+
+        alerts.triggers = items.iter()
+            .filter(|item| item.item_type() == Some(SCHEMA_ALERT_TRIGGER))
+            .filter_map(|item| match AlertTrigger::from_item(item) {
+                Ok(trigger) => Some(trigger),
+                Err(e) => {
+                    log_warn!(name, "Ignoring alert trigger: {}", e; file = alerts_file.display());
+                    None
+                }
+            })
+            .collect();
+        if alerts.triggers.is_empty() {
+            log_warn!(name, "Alerts file declares no alert triggers"; file = alerts_file.display());
+        }
+        alerts
+    }
+
+    /// Count a response towards the triggers, raising the alerts it completes
+    pub fn record(&self, status: u16, host: &str, path: &str) {
+        if status < 500 {
+            return;
+        }
+        let now = Instant::now();
+        for trigger in self.triggers.iter().filter(|trigger| path.starts_with(&trigger.path_prefix)) {
+            if let Some(errors) = trigger.count_error(now) {
+                self.raise(&Alert { trigger, errors, host, path, status });
+            }
+        }
+    }
+
+    fn raise(&self, alert: &Alert) {
+        let trigger = alert.trigger;
+        log_warn!(self.name, "Alert '{}': {} server errors within {} seconds", trigger.name, alert.errors, trigger.window.as_secs();
+            path_prefix = trigger.path_prefix, host = alert.host, last_path = alert.path);
+
+        let description = alert.to_json();
+        if let Some(sentinel_file) = &trigger.sentinel_file {
+            if let Err(e) = std::fs::write(sentinel_file, format!("{}\n", description)) {
+                log_error!(self.name, "Failed to write alert sentinel file: {}", e; file = sentinel_file.display());
+            }
+        }
+        if let Some(webhook_url) = &trigger.webhook_url {
+            let name = self.name.clone();
+            let webhook_url = webhook_url.clone();
+            let spawned = std::thread::Builder::new()
+                .name("access-log-alert".to_string())
+                .spawn(move || {
+                    let result = ureq::post(&webhook_url)
+                        .timeout(WEBHOOK_TIMEOUT)
+                        .send_json(description);
+                    if let Err(e) = result {
+                        log_error!(name, "Failed to send alert webhook: {}", e; url = webhook_url);
+                    }
+                });
+            if let Err(e) = spawned {
+                log_error!(self.name, "Failed to start alert webhook thread: {}", e);
+            }
+        }
+    }
+}
@@ -5,20 +5,93 @@
 //! (Common, Combined) as well as structured JSON logging for modern log analysis.
 //!
 //! ## Features
-//! - **Multiple Log Formats**: Common, Combined, and JSON formats
+//! - **Multiple Log Formats**: Common, Combined, JSON and W3C Extended formats
+//! - **Custom Formats**: nginx-style templates parsed once at startup
 //! - **Flexible Output**: Log to file or stdout
+//! - **Per-Host Files**: Separate log files for each virtual host from a path template
 //! - **Real Client IP Detection**: Handles proxy headers (X-Forwarded-For, X-Real-IP)
 //! - **Authenticated User Tracking**: Logs authenticated usernames when available
 //! - **Automatic Directory Creation**: Creates log directories if they don't exist
 //! - **Performance Optimized**: Minimal overhead on request processing
 //! - **Error Resilience**: Continues serving even if logging fails
+//! - **Graceful Shutdown**: Buffered entries are flushed when the server stops
+//! - **Accurate Sizes**: Counts bytes actually sent for chunked or streamed bodies
+//! - **Redaction**: Strips or masks query strings and hashes user identifiers
+//! - **Sampling**: Logs a fraction of requests while always keeping errors
+//! - **Alerts**: Warns, calls a webhook or writes a sentinel file when server
+//!   errors pile up
+//! - **Log Targets**: Routes requests to extra files by status, path and
+//!   method, each with its own format and rotation
 //!
 //! ## Configuration
-//! - `log_file`: Path to log file (e.g., "/var/log/rusty-beam/access.log")
-//! - `format`: Log format - "common", "combined", or "json" (default: "common")
+//! - `log_file`: Path to log file (e.g., "/var/log/rusty-beam/access.log"); a
+//!   `{host}` placeholder gives each host its own file, e.g.
+//!   "/var/log/rusty-beam/{host}/access.log"
+//! - `format`: Log format - "common", "combined", "json", "w3c" or "custom" (default: "common")
+//! - `custom_format`: Template used by the "custom" format, e.g.
+//!   `$remote_addr [$time_local] "$request" $status $request_time`
 //! - `buffer_size`: Number of entries to buffer before writing (default: 1)
 //! - `rotate_size_mb`: Rotate log when it reaches this size in MB (default: disabled)
 //! - `rotate_daily`: Enable daily log rotation (default: false)
+//! - `redact_query`: Remove the query string from logged URIs (default: false)
+//! - `redact_query_params`: Comma-separated query parameters whose values are
+//!   replaced with `REDACTED`, e.g. "token,code", matched however their names
+//!   are cased or percent-encoded (default: none)
+//! - `hash_fields`: Comma-separated identifiers to log as salted SHA-256
+//!   hashes, from "user" and "remote_ip" (default: none)
+//! - `hash_salt`: Salt mixed into hashed identifiers (default: empty)
+//! - `sample_every`: Log one in every N requests (default: 1)
+//! - `sample_rate`: Probability between 0 and 1 that a request is logged (default: 1)
+//! - `always_log_errors`: Log 4xx and 5xx responses regardless of sampling (default: true)
+//! - `alerts_file`: HTML file declaring alert triggers (file:// URL or path,
+//!   default: none)
+//! - `targets_file`: HTML file declaring log targets (file:// URL or path,
+//!   default: none)
+//!
+//! ## Redaction and Sampling
+//! Both are applied before an entry is formatted, so they work the same way
+//! for every log format. A request must pass both `sample_every` and
+//! `sample_rate` to be logged. Hashed identifiers are stable for a given salt,
+//! so requests from one user can still be correlated without recording who
+//! they are.
+//!
+//! ## Alerts
+//! An alert trigger fires when a number of 5xx responses to paths under a
+//! prefix happen within a number of seconds, for basic incident detection
+//! without external tooling. Responses count whether or not sampling logs
+//! them. See the `alerts` module for the trigger properties:
+//! ```html
+//! <div itemscope itemtype="https://rustybeam.net/schema/AlertTrigger">
+//!     <span itemprop="name">api-errors</span>
+//!     <span itemprop="pathPrefix">/api/</span>
+//!     <span itemprop="threshold">10</span>
+//!     <span itemprop="window">60</span>
+//!     <span itemprop="sentinelFile">/var/run/rusty-beam/maintenance</span>
+//! </div>
+//! ```
+//!
+//! ## Log Targets
+//! Targets send some requests to files of their own, such as errors to one
+//! file and API calls to another. Each takes the requests matching its
+//! status, path prefix and method filters, and only requests no target took
+//! go to `log_file`. Sampling and redaction apply to every target alike. See
+//! the `targets` module for the target properties:
+//! ```html
+//! <div itemscope itemtype="https://rustybeam.net/schema/LogTarget">
+//!     <span itemprop="name">errors</span>
+//!     <span itemprop="logFile">/var/log/rusty-beam/error.log</span>
+//!     <span itemprop="status">4xx</span>
+//!     <span itemprop="status">5xx</span>
+//!     <span itemprop="format">json</span>
+//! </div>
+//! ```
+//!
+//! ## Per-Host Log Files
+//! The `{host}` placeholder is replaced with the name of the host serving the
+//! request. Host names are lowercased, any port is dropped, and characters
+//! other than letters, digits, `-` and `.` become `_`, so a name can never
+//! leave the log directory. Each file is buffered and rotated on its own, and
+//! its directory is created when the host logs its first request.
 //!
 //! ## Log Formats
 //!
@@ -34,24 +107,107 @@
 //!
 //! ### JSON Format
 //! ```json
-//! {"timestamp":"10/Oct/2024:13:55:36 +0000","remote_ip":"127.0.0.1","user":"alice","method":"GET","uri":"/index.html","version":"HTTP/1.1","status":200,"size":2326,"user_agent":"Mozilla/5.0","referer":"http://example.com/"}
+//! {"timestamp":"10/Oct/2024:13:55:36 +0000","remote_ip":"127.0.0.1","user":"alice","method":"GET","uri":"/index.html","version":"HTTP/1.1","status":200,"size":2326,"user_agent":"Mozilla/5.0","referer":"http://example.com/","matched_directory":"/","responding_plugin":"file-handler"}
 //! ```
 //!
+//! `matched_directory` and `responding_plugin` are null for requests no
+//! directory plugin answered.
+//!
+//! ### W3C Extended Log Format
+//! Every new log file, including those started by rotation, begins with the
+//! directives describing its fields:
+//! ```
+//! #Version: 1.0
+//! #Date: 2024-10-10 13:55:36
+//! #Fields: date time c-ip cs-username cs-method cs-uri-stem cs-uri-query sc-status sc-bytes time-taken cs(User-Agent) cs(Referer)
+//! 2024-10-10 13:55:36 127.0.0.1 alice GET /index.html - 200 2326 0.004 Mozilla/5.0 http://example.com/
+//! ```
+//!
+//! ### Custom Format
+//! Variables are written `$name` or `${name}`, everything else is copied as
+//! is. Missing values are logged as `-`, and quotes, backslashes and control
+//! characters in values are escaped as `\xHH`:
+//! - `$remote_addr`, `$remote_user`, `$host`, `$request_id`
+//! - `$time_local` (as in the common format), `$time_iso8601`, `$date`, `$time`
+//! - `$request` (method, URI and protocol), `$request_method`, `$request_uri`,
+//!   `$uri` (path only), `$args` (query string), `$server_protocol`
+//! - `$status`, `$body_bytes_sent`, `$request_time` (seconds, millisecond resolution)
+//! - `$ttfb` (seconds until a plugin produced the response)
+//! - `$matched_directory`, `$responding_plugin` (the directory plugin block
+//!   and nested plugin that served the request)
+//! - `$http_<header>` for request headers and `$sent_http_<header>` for
+//!   response headers, with dashes written as underscores
+//!
+//! Request times are measured from when the server received the request
+//! until the entry is written, which for streamed bodies is once the last
+//! byte has been sent. A plugin can report its own time instead by setting
+//! the `request_time_ms` metadata.
+//!
 //! ## Integration with Other Plugins
 //! - **Basic Auth Plugin**: Logs authenticated usernames
 //! - **OAuth Plugin**: Logs OAuth user identities  
 //! - **Rate Limit Plugin**: Can analyze logs for rate limiting decisions
 //! - **Error Handler Plugin**: Access logs include error responses
 
-use rusty_beam_plugin_api::{Plugin, PluginRequest, PluginContext, PluginResponse, create_plugin};
+mod alerts;
+mod targets;
+
+use alerts::Alerts;
+use targets::LogTarget;
+use rusty_beam_plugin_api::{Plugin, PluginRequest, PluginContext, PluginResponse, ConfigIssue, create_plugin, count_body_bytes, log_error, log_warn};
+use rusty_beam_plugin_api::timing;
+use rusty_beam_plugin_api::validation::check_writable_file;
 use async_trait::async_trait;
-use hyper::{Body, Response};
+use hyper::{Body, HeaderMap, Response, StatusCode};
 use std::collections::HashMap;
-use chrono::{Utc, Local};
+use chrono::{DateTime, Utc, Local};
 use std::fs::OpenOptions;
 use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+const DEFAULT_PLUGIN_NAME: &str = "access-log";
+
+// Per-host log files
+const HOST_PLACEHOLDER: &str = "{host}";
+const UNKNOWN_HOST_FILE_NAME: &str = "_";
+
+// Redaction
+const REDACTED_VALUE: &str = "REDACTED";
+const HASH_FIELD_USER: &str = "user";
+const HASH_FIELD_REMOTE_IP: &str = "remote_ip";
+const HASH_HEX_LENGTH: usize = 16;
+const MISSING_FIELD: &str = "-";
+
+// Timing
+const REQUEST_TIME_METADATA: &str = "request_time_ms";
+/// Metadata set by the directory plugin serving a request
+const MATCHED_DIRECTORY_METADATA: &str = "matched_directory";
+const RESPONDING_PLUGIN_METADATA: &str = "responding_plugin";
+const COMMON_TIME_FORMAT: &str = "%d/%b/%Y:%H:%M:%S %z";
+
+// Custom format variables for request and response headers
+const REQUEST_HEADER_PREFIX: &str = "http_";
+const RESPONSE_HEADER_PREFIX: &str = "sent_http_";
+
+/// W3C Extended Log Format fields and the custom format variables filling them
+const W3C_FIELDS: &[(&str, &str)] = &[
+    ("date", "date"),
+    ("time", "time"),
+    ("c-ip", "remote_addr"),
+    ("cs-username", "remote_user"),
+    ("cs-method", "request_method"),
+    ("cs-uri-stem", "uri"),
+    ("cs-uri-query", "args"),
+    ("sc-status", "status"),
+    ("sc-bytes", "body_bytes_sent"),
+    ("time-taken", "request_time"),
+    ("cs(User-Agent)", "http_user_agent"),
+    ("cs(Referer)", "http_referer"),
+];
 
 /// Access log format styles
 #[derive(Debug, Clone)]
@@ -62,17 +218,262 @@ enum LogFormat {
     Combined,
     /// JSON structured logging
     Json,
+    /// W3C Extended Log Format, with a fixed set of fields
+    W3c(Arc<CustomFormat>),
+    /// User-defined template
+    Custom(Arc<CustomFormat>),
 }
 
 impl LogFormat {
-    /// Parse log format from string
-    fn from_str(s: &str) -> Self {
+    /// Parse log format from string, with the template used by "custom"
+    fn from_str(s: &str, custom_format: Option<&str>) -> Self {
         match s.to_lowercase().as_str() {
             "combined" => LogFormat::Combined,
             "json" => LogFormat::Json,
+            "w3c" => LogFormat::W3c(Arc::new(CustomFormat::w3c())),
+            "custom" => match custom_format {
+                Some(template) => LogFormat::Custom(Arc::new(CustomFormat::parse(template, Escaping::Quoted))),
+                None => {
+                    log_warn!(DEFAULT_PLUGIN_NAME, "The custom format needs a custom_format template, using common");
+                    LogFormat::Common
+                }
+            },
             "common" | _ => LogFormat::Common,
         }
     }
+    
+    /// Render a log entry in this format
+    fn format_entry(&self, data: &LogEntryData) -> String {
+        match self {
+            LogFormat::Common => data.to_common_log(),
+            LogFormat::Combined => data.to_combined_log(),
+            LogFormat::Json => data.to_json_log(),
+            LogFormat::W3c(format) | LogFormat::Custom(format) => format.render(data),
+        }
+    }
+    
+    /// The template-based formatter, if any, which decides the headers to capture
+    fn custom_format(&self) -> Option<&CustomFormat> {
+        match self {
+            LogFormat::W3c(format) | LogFormat::Custom(format) => Some(format),
+            _ => None,
+        }
+    }
+    
+    /// Lines written at the start of every log file
+    fn file_header(&self) -> Option<fn() -> String> {
+        match self {
+            LogFormat::W3c(_) => Some(w3c_file_header),
+            _ => None,
+        }
+    }
+}
+
+/// Directives opening a W3C Extended Log Format file
+fn w3c_file_header() -> String {
+    let fields: Vec<&str> = W3C_FIELDS.iter().map(|(field, _)| *field).collect();
+    format!(
+        "#Version: 1.0\n#Date: {}\n#Fields: {}",
+        Utc::now().format("%Y-%m-%d %H:%M:%S"),
+        fields.join(" ")
+    )
+}
+
+/// How values are written into a template
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Escaping {
+    /// Quotes, backslashes and control characters become `\xHH`
+    Quoted,
+    /// Whitespace becomes `+`, so fields stay space-separated
+    W3c,
+}
+
+/// A value a template variable refers to
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Variable {
+    RemoteAddr,
+    RemoteUser,
+    Host,
+    RequestId,
+    TimeLocal,
+    TimeIso8601,
+    Date,
+    Time,
+    Request,
+    RequestMethod,
+    RequestUri,
+    Uri,
+    Args,
+    ServerProtocol,
+    Status,
+    BodyBytesSent,
+    RequestTime,
+    Ttfb,
+    MatchedDirectory,
+    RespondingPlugin,
+    /// Index into `CustomFormat::request_headers`
+    RequestHeader(usize),
+    /// Index into `CustomFormat::response_headers`
+    ResponseHeader(usize),
+}
+
+/// Piece of a parsed template
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Variable(Variable),
+}
+
+/// Template parsed once at startup into literal text and variables
+#[derive(Debug)]
+struct CustomFormat {
+    segments: Vec<Segment>,
+    /// Lowercase names of the request headers the template uses
+    request_headers: Vec<String>,
+    /// Lowercase names of the response headers the template uses
+    response_headers: Vec<String>,
+    escaping: Escaping,
+}
+
+impl CustomFormat {
+    /// Parse a template of `$name` and `${name}` variables
+    fn parse(template: &str, escaping: Escaping) -> Self {
+        let mut format = CustomFormat {
+            segments: Vec::new(),
+            request_headers: Vec::new(),
+            response_headers: Vec::new(),
+            escaping,
+        };
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        
+        while let Some(c) = chars.next() {
+            if c != '$' {
+                literal.push(c);
+                continue;
+            }
+            
+            let braced = chars.next_if_eq(&'{').is_some();
+            let mut name = String::new();
+            while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                name.push(c);
+            }
+            if name.is_empty() || (braced && chars.next_if_eq(&'}').is_none()) {
+                // Not a variable, keep the text as written
+                literal.push('$');
+                if braced {
+                    literal.push('{');
+                }
+                literal.push_str(&name);
+                continue;
+            }
+            
+            if !literal.is_empty() {
+                format.segments.push(Segment::Literal(std::mem::take(&mut literal)));
+            }
+            match format.variable(&name.to_lowercase()) {
+                Some(variable) => format.segments.push(Segment::Variable(variable)),
+                None => {
+                    log_warn!(DEFAULT_PLUGIN_NAME, "Unknown log format variable, logging '-'"; variable = name);
+                    format.segments.push(Segment::Literal(MISSING_FIELD.to_string()));
+                }
+            }
+        }
+        if !literal.is_empty() {
+            format.segments.push(Segment::Literal(literal));
+        }
+        
+        format
+    }
+    
+    /// The W3C Extended Log Format preset
+    fn w3c() -> Self {
+        let template: Vec<String> = W3C_FIELDS.iter()
+            .map(|(_, variable)| format!("${}", variable))
+            .collect();
+        Self::parse(&template.join(" "), Escaping::W3c)
+    }
+    
+    /// Resolve a variable name, registering any header it refers to
+    fn variable(&mut self, name: &str) -> Option<Variable> {
+        let variable = match name {
+            "remote_addr" => Variable::RemoteAddr,
+            "remote_user" => Variable::RemoteUser,
+            "host" => Variable::Host,
+            "request_id" => Variable::RequestId,
+            "time_local" => Variable::TimeLocal,
+            "time_iso8601" => Variable::TimeIso8601,
+            "date" => Variable::Date,
+            "time" => Variable::Time,
+            "request" => Variable::Request,
+            "request_method" => Variable::RequestMethod,
+            "request_uri" => Variable::RequestUri,
+            "uri" => Variable::Uri,
+            "args" | "query_string" => Variable::Args,
+            "server_protocol" => Variable::ServerProtocol,
+            "status" => Variable::Status,
+            "body_bytes_sent" | "bytes_sent" => Variable::BodyBytesSent,
+            "request_time" => Variable::RequestTime,
+            "ttfb" => Variable::Ttfb,
+            "matched_directory" => Variable::MatchedDirectory,
+            "responding_plugin" => Variable::RespondingPlugin,
+            _ => {
+                // Checked first, as response header variables also contain the request prefix
+                if let Some(header) = name.strip_prefix(RESPONSE_HEADER_PREFIX) {
+                    return Some(Variable::ResponseHeader(Self::register(&mut self.response_headers, header)?));
+                }
+                let header = name.strip_prefix(REQUEST_HEADER_PREFIX)?;
+                Variable::RequestHeader(Self::register(&mut self.request_headers, header)?)
+            }
+        };
+        Some(variable)
+    }
+    
+    /// Add a header to capture, returning its index
+    fn register(headers: &mut Vec<String>, variable_suffix: &str) -> Option<usize> {
+        if variable_suffix.is_empty() {
+            return None;
+        }
+        let header = variable_suffix.replace('_', "-");
+        match headers.iter().position(|existing| *existing == header) {
+            Some(index) => Some(index),
+            None => {
+                headers.push(header);
+                Some(headers.len() - 1)
+            }
+        }
+    }
+    
+    /// Render an entry with this template
+    fn render(&self, data: &LogEntryData) -> String {
+        let mut line = String::with_capacity(256);
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(text) => line.push_str(text),
+                Segment::Variable(variable) => self.push_value(&mut line, &data.variable(*variable)),
+            }
+        }
+        line
+    }
+    
+    /// Append a value, escaped for this template
+    fn push_value(&self, line: &mut String, value: &str) {
+        if value.is_empty() {
+            line.push_str(MISSING_FIELD);
+            return;
+        }
+        for c in value.chars() {
+            match self.escaping {
+                Escaping::Quoted if c == '"' || c == '\\' || c.is_control() => {
+                    for byte in c.to_string().bytes() {
+                        line.push_str(&format!("\\x{:02X}", byte));
+                    }
+                }
+                Escaping::W3c if c.is_whitespace() => line.push('+'),
+                _ => line.push(c),
+            }
+        }
+    }
 }
 
 /// Buffered log entry for batch writing
@@ -82,45 +483,89 @@ struct LogBuffer {
     max_size: usize,
 }
 
-/// Plugin for HTTP request access logging
+/// Buffered, rotating writer for formatted log entries
+/// 
+/// Shared behind an `Arc` so that entries for streamed responses can be
+/// written once the body has finished, after `handle_response` has returned.
 #[derive(Debug)]
-pub struct AccessLogPlugin {
-    name: String,
+struct LogWriter {
     log_file: Option<PathBuf>,
-    format: LogFormat,
     buffer: Mutex<LogBuffer>,
     rotate_size_bytes: Option<u64>,
     rotate_daily: bool,
+    /// Lines opening every log file, such as W3C directives
+    file_header: Option<fn() -> String>,
+    /// Whether the file header has been printed when logging to stdout
+    stdout_header_written: AtomicBool,
+}
+
+/// Fields removed or obscured before entries are formatted
+#[derive(Debug, Default)]
+struct RedactionConfig {
+    strip_query: bool,
+    query_params: Vec<String>,
+    hash_user: bool,
+    hash_remote_ip: bool,
+    hash_salt: String,
+}
+
+/// Which requests are written to the log
+#[derive(Debug)]
+struct SamplingConfig {
+    every: u64,
+    rate: f64,
+    always_log_errors: bool,
+    counter: AtomicU64,
+}
+
+/// A log file, or stdout, and the format its entries are written in
+#[derive(Debug)]
+struct LogOutput {
+    format: LogFormat,
+    /// Writer for every request, or the settings for per-host writers when
+    /// the log file is a template
+    writer: Arc<LogWriter>,
+    /// Log file path containing `{host}`
+    host_template: Option<String>,
+    host_writers: Mutex<HashMap<String, Arc<LogWriter>>>,
+}
+
+/// Plugin for HTTP request access logging
+#[derive(Debug)]
+pub struct AccessLogPlugin {
+    name: String,
+    /// Where requests no target takes are logged
+    output: LogOutput,
+    targets: Vec<LogTarget>,
+    redaction: RedactionConfig,
+    sampling: SamplingConfig,
+    alerts: Alerts,
 }
 
 impl AccessLogPlugin {
     pub fn new(config: HashMap<String, String>) -> Self {
-        let name = Self::parse_string_config(&config, "name", "access-log");
-        let log_file = Self::parse_log_file_config(&config);
-        let format = Self::parse_format_config(&config);
-        let buffer_size = Self::parse_numeric_config(&config, "buffer_size", 1);
-        let rotate_size_mb = Self::parse_numeric_config::<f64>(&config, "rotate_size_mb", 0.0);
-        let rotate_daily = Self::parse_boolean_config(&config, "rotate_daily", false);
-        
-        // Create log directory if needed
-        if let Some(ref log_path) = log_file {
-            Self::ensure_log_directory_exists(log_path);
-        }
+        let name = Self::parse_string_config(&config, "name", DEFAULT_PLUGIN_NAME);
+        let redaction = Self::parse_redaction_config(&config);
+        let sampling = SamplingConfig {
+            every: Self::parse_numeric_config::<u64>(&config, "sample_every", 1).max(1),
+            rate: Self::parse_numeric_config::<f64>(&config, "sample_rate", 1.0).clamp(0.0, 1.0),
+            always_log_errors: Self::parse_boolean_config(&config, "always_log_errors", true),
+            counter: AtomicU64::new(0),
+        };
+        let alerts = config.get("alerts_file")
+            .map(|file| Alerts::load(&name, Path::new(file.strip_prefix("file://").unwrap_or(file))))
+            .unwrap_or_default();
+        let targets = config.get("targets_file")
+            .map(|file| targets::load(&name, Path::new(file.strip_prefix("file://").unwrap_or(file)), &config))
+            .unwrap_or_default();
         
         Self {
             name,
-            log_file,
-            format,
-            buffer: Mutex::new(LogBuffer {
-                entries: Vec::with_capacity(buffer_size),
-                max_size: buffer_size,
-            }),
-            rotate_size_bytes: if rotate_size_mb > 0.0 {
-                Some((rotate_size_mb * 1024.0 * 1024.0) as u64)
-            } else {
-                None
-            },
-            rotate_daily,
+            output: LogOutput::from_config(&config),
+            targets,
+            redaction,
+            sampling,
+            alerts,
         }
     }
     
@@ -162,15 +607,43 @@ impl AccessLogPlugin {
     /// Parse log format from configuration
     fn parse_format_config(config: &HashMap<String, String>) -> LogFormat {
         config.get("format")
-            .map(|f| LogFormat::from_str(f))
+            .map(|f| LogFormat::from_str(f, config.get("custom_format").map(String::as_str)))
             .unwrap_or(LogFormat::Common)
     }
     
+    /// Parse comma-separated list configuration
+    fn parse_list_config(config: &HashMap<String, String>, key: &str) -> Vec<String> {
+        config.get(key)
+            .map(|v| v.split(',')
+                .map(|item| item.trim().to_string())
+                .filter(|item| !item.is_empty())
+                .collect())
+            .unwrap_or_default()
+    }
+    
+    /// Parse redaction settings from configuration
+    fn parse_redaction_config(config: &HashMap<String, String>) -> RedactionConfig {
+        let hash_fields = Self::parse_list_config(config, "hash_fields");
+        for field in &hash_fields {
+            if field != HASH_FIELD_USER && field != HASH_FIELD_REMOTE_IP {
+                log_warn!(DEFAULT_PLUGIN_NAME, "Ignoring unknown hash field"; field = field);
+            }
+        }
+        
+        RedactionConfig {
+            strip_query: Self::parse_boolean_config(config, "redact_query", false),
+            query_params: Self::parse_list_config(config, "redact_query_params"),
+            hash_user: hash_fields.iter().any(|f| f == HASH_FIELD_USER),
+            hash_remote_ip: hash_fields.iter().any(|f| f == HASH_FIELD_REMOTE_IP),
+            hash_salt: Self::parse_string_config(config, "hash_salt", ""),
+        }
+    }
+    
     /// Ensure log directory exists
     fn ensure_log_directory_exists(log_path: &Path) {
         if let Some(parent) = log_path.parent() {
             if let Err(e) = std::fs::create_dir_all(parent) {
-                eprintln!("[AccessLog] Failed to create log directory {:?}: {}", parent, e);
+                log_error!(DEFAULT_PLUGIN_NAME, "Failed to create log directory: {}", e; directory = parent.display());
             }
         }
     }
@@ -226,69 +699,245 @@ impl AccessLogPlugin {
             .to_string()
     }
     
-    /// Format log entry based on configured format
-    fn format_log_entry(&self, request: &PluginRequest, response: &Response<Body>, response_size: usize) -> String {
-        let entry_data = self.collect_log_entry_data(request, response, response_size);
+    /// The outputs a request is logged to
+    fn outputs_for(&self, request: &PluginRequest, status: u16) -> Vec<&LogOutput> {
+        let method = request.method();
+        let taken: Vec<&LogOutput> = self.targets.iter()
+            .filter(|target| target.takes(method, &request.path, status))
+            .map(|target| &target.output)
+            .collect();
+        if taken.is_empty() {
+            vec![&self.output]
+        } else {
+            taken
+        }
+    }
+    
+    /// Decide whether a response should be logged under the sampling settings
+    fn should_log(&self, status: u16) -> bool {
+        if self.sampling.always_log_errors && status >= 400 {
+            return true;
+        }
+        
+        // Count every candidate request so 1-in-N stays evenly spaced
+        let count = self.sampling.counter.fetch_add(1, Ordering::Relaxed);
+        if !count.is_multiple_of(self.sampling.every) {
+            return false;
+        }
         
-        match self.format {
-            LogFormat::Common => self.format_common_log(&entry_data),
-            LogFormat::Combined => self.format_combined_log(&entry_data),
-            LogFormat::Json => self.format_json_log(&entry_data),
+        self.sampling.rate >= 1.0 || rand::thread_rng().gen_bool(self.sampling.rate)
+    }
+    
+    /// Apply query string redaction to a URI
+    fn redact_uri(&self, uri: &hyper::Uri) -> String {
+        if !self.redaction.strip_query && self.redaction.query_params.is_empty() {
+            return uri.to_string();
+        }
+        let path = uri.path();
+        let query = match uri.query() {
+            Some(query) if !self.redaction.strip_query => query,
+            _ => return path.to_string(),
+        };
+        
+        let redacted: Vec<String> = query.split('&')
+            .map(|pair| match pair.split_once('=') {
+                Some((name, _)) if self.is_redacted_param(name) => {
+                    format!("{}={}", name, REDACTED_VALUE)
+                }
+                _ => pair.to_string(),
+            })
+            .collect();
+        format!("{}?{}", path, redacted.join("&"))
+    }
+    
+    /// Whether a query parameter's value is redacted, comparing its decoded
+    /// name without regard to case
+    fn is_redacted_param(&self, raw_name: &str) -> bool {
+        let name = url::form_urlencoded::parse(raw_name.as_bytes())
+            .next()
+            .map(|(name, _)| name.into_owned())
+            .unwrap_or_default();
+        self.redaction.query_params.iter().any(|param| param.eq_ignore_ascii_case(&name))
+    }
+    
+    /// Replace an identifier with a salted hash, leaving missing values alone
+    fn hash_identifier(&self, value: String) -> String {
+        if value == MISSING_FIELD {
+            return value;
         }
+        let mut hasher = Sha256::new();
+        hasher.update(self.redaction.hash_salt.as_bytes());
+        hasher.update(value.as_bytes());
+        let mut hash = format!("{:x}", hasher.finalize());
+        hash.truncate(HASH_HEX_LENGTH);
+        hash
+    }
+    
+    /// Time since the server received the request, unless another plugin
+    /// reported it
+    fn request_time_ms(&self, request: &PluginRequest) -> u64 {
+        if let Some(ms) = self.reported_request_time_ms(request) {
+            return ms;
+        }
+        timing::elapsed(request).map(|elapsed| elapsed.as_millis() as u64).unwrap_or(0)
+    }
+    
+    fn reported_request_time_ms(&self, request: &PluginRequest) -> Option<u64> {
+        request.get_metadata(REQUEST_TIME_METADATA).and_then(|t| t.parse().ok())
     }
     
     /// Collect all data needed for log entry
-    fn collect_log_entry_data(&self, request: &PluginRequest, response: &Response<Body>, response_size: usize) -> LogEntryData {
+    fn collect_log_entry_data(
+        &self,
+        request: &PluginRequest,
+        status: StatusCode,
+        response_size: u64,
+        context: &PluginContext,
+    ) -> LogEntryData {
+        let mut remote_ip = self.get_remote_ip(request);
+        if self.redaction.hash_remote_ip {
+            remote_ip = self.hash_identifier(remote_ip);
+        }
+        let mut user = request.get_metadata("authenticated_user").unwrap_or(MISSING_FIELD).to_string();
+        if self.redaction.hash_user {
+            user = self.hash_identifier(user);
+        }
+        
         LogEntryData {
-            timestamp: Utc::now().format("%d/%b/%Y:%H:%M:%S %z").to_string(),
-            remote_ip: self.get_remote_ip(request),
-            user: request.get_metadata("authenticated_user").unwrap_or("-").to_string(),
+            time: Utc::now(),
+            remote_ip,
+            user,
             method: request.method().to_string(),
-            uri: request.http_request.uri().to_string(),
+            uri: self.redact_uri(request.http_request.uri()),
             version: format!("{:?}", request.http_request.version()),
-            status: response.status().as_u16(),
+            status: status.as_u16(),
             size: response_size,
             user_agent: self.get_user_agent(request),
             referer: self.get_referer(request),
-            request_time_ms: request.get_metadata("request_time_ms")
-                .and_then(|t| t.parse::<u64>().ok())
-                .unwrap_or(0),
+            request_time_ms: self.request_time_ms(request),
+            ttfb_ms: timing::ttfb(request).map(|ttfb| ttfb.as_millis() as u64),
+            host: context.host_name.clone(),
+            request_id: context.request_id.clone(),
+            matched_directory: request.get_metadata(MATCHED_DIRECTORY_METADATA).map(str::to_string),
+            responding_plugin: request.get_metadata(RESPONDING_PLUGIN_METADATA).map(str::to_string),
+            request_headers: Vec::new(),
+            response_headers: Vec::new(),
         }
     }
     
-    /// Format as Common Log Format
-    fn format_common_log(&self, data: &LogEntryData) -> String {
-        format!(r#"{} - {} [{}] "{} {} {}" {} {}"#,
-            data.remote_ip, data.user, data.timestamp, 
-            data.method, data.uri, data.version, 
-            data.status, data.size)
+}
+
+impl LogOutput {
+    /// Output described by the `log_file`, `format`, buffering and rotation settings
+    fn from_config(config: &HashMap<String, String>) -> Self {
+        let log_file = AccessLogPlugin::parse_log_file_config(config);
+        let format = AccessLogPlugin::parse_format_config(config);
+        let file_header = format.file_header();
+        let buffer_size = AccessLogPlugin::parse_numeric_config(config, "buffer_size", 1);
+        let rotate_size_mb = AccessLogPlugin::parse_numeric_config::<f64>(config, "rotate_size_mb", 0.0);
+        let rotate_daily = AccessLogPlugin::parse_boolean_config(config, "rotate_daily", false);
+        
+        let host_template = log_file.as_ref()
+            .map(|path| path.to_string_lossy().into_owned())
+            .filter(|path| path.contains(HOST_PLACEHOLDER));
+        
+        // Create log directory if needed; per-host directories are created on first use
+        if let Some(ref log_path) = log_file {
+            if host_template.is_none() {
+                AccessLogPlugin::ensure_log_directory_exists(log_path);
+            }
+        }
+        
+        Self {
+            format,
+            host_template,
+            host_writers: Mutex::new(HashMap::new()),
+            writer: Arc::new(LogWriter {
+                log_file,
+                buffer: Mutex::new(LogBuffer {
+                    entries: Vec::with_capacity(buffer_size),
+                    max_size: buffer_size,
+                }),
+                rotate_size_bytes: if rotate_size_mb > 0.0 {
+                    Some((rotate_size_mb * 1024.0 * 1024.0) as u64)
+                } else {
+                    None
+                },
+                rotate_daily,
+                file_header,
+                stdout_header_written: AtomicBool::new(false),
+            }),
+        }
     }
     
-    /// Format as Combined Log Format
-    fn format_combined_log(&self, data: &LogEntryData) -> String {
-        format!(r#"{} - {} [{}] "{} {} {}" {} {} "{}" "{}""#,
-            data.remote_ip, data.user, data.timestamp, 
-            data.method, data.uri, data.version, 
-            data.status, data.size, 
-            data.referer, data.user_agent)
+    /// Writer for the log file of a host
+    fn writer_for_host(&self, host: &str) -> Arc<LogWriter> {
+        let template = match &self.host_template {
+            Some(template) => template,
+            None => return Arc::clone(&self.writer),
+        };
+        
+        let file_name = sanitize_host_name(host);
+        let mut host_writers = self.host_writers.lock().unwrap();
+        if let Some(writer) = host_writers.get(&file_name) {
+            return Arc::clone(writer);
+        }
+        
+        let log_path = PathBuf::from(template.replace(HOST_PLACEHOLDER, &file_name));
+        AccessLogPlugin::ensure_log_directory_exists(&log_path);
+        let writer = Arc::new(self.writer.with_file(log_path));
+        host_writers.insert(file_name, Arc::clone(&writer));
+        writer
     }
     
-    /// Format as JSON
-    fn format_json_log(&self, data: &LogEntryData) -> String {
-        // Use serde_json for proper escaping
-        serde_json::json!({
-            "timestamp": data.timestamp,
-            "remote_ip": data.remote_ip,
-            "user": data.user,
-            "method": data.method,
-            "uri": data.uri,
-            "version": data.version,
-            "status": data.status,
-            "size": data.size,
-            "user_agent": data.user_agent,
-            "referer": data.referer,
-            "request_time_ms": data.request_time_ms,
-        }).to_string()
+    /// An entry with the headers this output's format refers to
+    fn with_headers(&self, data: &LogEntryData, request_headers: &HeaderMap, response_headers: &HeaderMap) -> LogEntryData {
+        let mut data = data.clone();
+        // Only headers a template refers to are kept
+        if let Some(format) = self.format.custom_format() {
+            data.request_headers = format.request_headers.iter()
+                .map(|name| header_value(request_headers, name))
+                .collect();
+            data.response_headers = format.response_headers.iter()
+                .map(|name| header_value(response_headers, name))
+                .collect();
+        }
+        data
+    }
+    
+    /// Write out anything buffered, for every host
+    fn flush(&self) {
+        self.writer.flush_buffer();
+        for writer in self.host_writers.lock().unwrap().values() {
+            writer.flush_buffer();
+        }
+    }
+    
+    /// Problems with the log file, which per-host files are only known to
+    /// have once their host is first logged
+    fn validate(&self, key: &str) -> Option<ConfigIssue> {
+        match (&self.writer.log_file, &self.host_template) {
+            (Some(log_file), None) => check_writable_file(key, &log_file.to_string_lossy()),
+            _ => None,
+        }
+    }
+}
+
+impl LogWriter {
+    /// A writer with the same settings for another file
+    fn with_file(&self, log_file: PathBuf) -> Self {
+        let max_size = self.buffer.lock().unwrap().max_size;
+        LogWriter {
+            log_file: Some(log_file),
+            buffer: Mutex::new(LogBuffer {
+                entries: Vec::with_capacity(max_size),
+                max_size,
+            }),
+            rotate_size_bytes: self.rotate_size_bytes,
+            rotate_daily: self.rotate_daily,
+            file_header: self.file_header,
+            stdout_header_written: AtomicBool::new(false),
+        }
     }
     
     /// Add log entry to buffer and flush if needed
@@ -325,6 +974,11 @@ impl AccessLogPlugin {
             self.write_entries_to_file(log_file, &entries);
         } else {
             // No log file configured, write to stdout
+            if let Some(file_header) = self.file_header {
+                if !self.stdout_header_written.swap(true, Ordering::SeqCst) {
+                    println!("{}", file_header());
+                }
+            }
             for entry in entries {
                 println!("{}", entry);
             }
@@ -369,7 +1023,7 @@ impl AccessLogPlugin {
         );
         
         if let Err(e) = std::fs::rename(log_file, &rotated_name) {
-            eprintln!("[AccessLog] Failed to rotate log file: {}", e);
+            log_error!(DEFAULT_PLUGIN_NAME, "Failed to rotate log file: {}", e; file = log_file.display());
         }
     }
     
@@ -381,14 +1035,22 @@ impl AccessLogPlugin {
             .open(log_file)
         {
             Ok(mut file) => {
+                // New and freshly rotated files start with the header
+                if let Some(file_header) = self.file_header {
+                    if file.metadata().map(|metadata| metadata.len() == 0).unwrap_or(false) {
+                        if let Err(e) = writeln!(file, "{}", file_header()) {
+                            log_error!(DEFAULT_PLUGIN_NAME, "Failed to write to log file: {}", e; file = log_file.display());
+                        }
+                    }
+                }
                 for entry in entries {
                     if let Err(e) = writeln!(file, "{}", entry) {
-                        eprintln!("[AccessLog] Failed to write to log file {:?}: {}", log_file, e);
+                        log_error!(DEFAULT_PLUGIN_NAME, "Failed to write to log file: {}", e; file = log_file.display());
                     }
                 }
             }
             Err(e) => {
-                eprintln!("[AccessLog] Failed to open log file {:?}: {}", log_file, e);
+                log_error!(DEFAULT_PLUGIN_NAME, "Failed to open log file: {}", e; file = log_file.display());
                 // Fallback to stdout
                 for entry in entries {
                     println!("{}", entry);
@@ -400,21 +1062,80 @@ impl AccessLogPlugin {
 
 #[async_trait]
 impl Plugin for AccessLogPlugin {
-    async fn handle_request(&self, _request: &mut PluginRequest, _context: &PluginContext) -> Option<PluginResponse> {
-        // Access log plugin doesn't intercept requests, just logs them
+    async fn handle_request(&self, request: &mut PluginRequest, _context: &PluginContext) -> Option<PluginResponse> {
+        // Access log plugin doesn't intercept requests, just logs them; the
+        // server notes when they started, unless running them some other way
+        timing::record_start(request);
         None
     }
     
-    async fn handle_response(&self, request: &PluginRequest, response: &mut Response<Body>, _context: &PluginContext) {
-        // Estimate response size (in a real implementation we'd track the actual bytes)
-        let response_size = response.headers()
-            .get("Content-Length")
-            .and_then(|len| len.to_str().ok())
-            .and_then(|len| len.parse().ok())
-            .unwrap_or(0);
+    async fn handle_response(&self, request: &PluginRequest, response: &mut Response<Body>, context: &PluginContext) {
+        self.alerts.record(response.status().as_u16(), &context.host_name, &request.path);
+        if !self.should_log(response.status().as_u16()) {
+            return;
+        }
         
-        let log_entry = self.format_log_entry(request, response, response_size);
-        self.buffer_log_entry(log_entry);
+        // Bodies of unknown size are logged as they stream
+        let Some(content_length) = content_length(response.headers()) else {
+            return;
+        };
+        let entry_data = self.collect_log_entry_data(request, response.status(), content_length, context);
+        for output in self.outputs_for(request, response.status().as_u16()) {
+            let entry_data = output.with_headers(&entry_data, request.http_request.headers(), response.headers());
+            output.writer_for_host(&context.host_name).buffer_log_entry(output.format.format_entry(&entry_data));
+        }
+    }
+    
+    async fn handle_response_stream(
+        &self,
+        request: &PluginRequest,
+        head: &mut hyper::http::response::Parts,
+        body: Body,
+        context: &PluginContext,
+    ) -> Body {
+        if !self.should_log(head.status.as_u16()) || content_length(&head.headers).is_some() {
+            return body;
+        }
+        
+        // Size is unknown until the body has been streamed, so defer the
+        // entry until the byte count is available, and time the request
+        // until then too
+        let entry_data = self.collect_log_entry_data(request, head.status, 0, context);
+        let pending: Vec<(Arc<LogWriter>, LogFormat, LogEntryData)> = self.outputs_for(request, head.status.as_u16())
+            .into_iter()
+            .map(|output| (
+                output.writer_for_host(&context.host_name),
+                output.format.clone(),
+                output.with_headers(&entry_data, request.http_request.headers(), &head.headers),
+            ))
+            .collect();
+        let start = timing::request_start(request).filter(|_| self.reported_request_time_ms(request).is_none());
+        count_body_bytes(body, Box::new(move |bytes_sent| {
+            let request_time_ms = start.map(|start| timing::now_micros().saturating_sub(start) / 1000);
+            for (writer, format, mut entry_data) in pending {
+                entry_data.size = bytes_sent;
+                if let Some(request_time_ms) = request_time_ms {
+                    entry_data.request_time_ms = request_time_ms;
+                }
+                writer.buffer_log_entry(format.format_entry(&entry_data));
+            }
+        }))
+    }
+    
+    async fn on_shutdown(&self) {
+        // Write out anything still buffered before the server exits
+        self.output.flush();
+        for target in &self.targets {
+            target.output.flush();
+        }
+    }
+    
+    fn validate_config(&self, _context: &PluginContext) -> Vec<ConfigIssue> {
+        let mut issues: Vec<ConfigIssue> = self.output.validate("log_file").into_iter().collect();
+        for target in &self.targets {
+            issues.extend(target.output.validate(&format!("log target '{}' logFile", target.name)));
+        }
+        issues
     }
     
     fn name(&self) -> &str {
@@ -423,23 +1144,149 @@ impl Plugin for AccessLogPlugin {
 }
 
 /// Structured data for log entries
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 struct LogEntryData {
-    timestamp: String,
+    time: DateTime<Utc>,
     remote_ip: String,
     user: String,
     method: String,
     uri: String,
     version: String,
     status: u16,
-    size: usize,
+    size: u64,
     user_agent: String,
     referer: String,
     request_time_ms: u64,
+    /// Time until a plugin produced the response, if the server measured it
+    ttfb_ms: Option<u64>,
+    host: String,
+    request_id: String,
+    /// Directory plugin pattern and nested plugin that served the request
+    matched_directory: Option<String>,
+    responding_plugin: Option<String>,
+    /// Values of `CustomFormat::request_headers`, in the same order
+    request_headers: Vec<String>,
+    /// Values of `CustomFormat::response_headers`, in the same order
+    response_headers: Vec<String>,
+}
+
+/// Turn a host name into a file name that stays inside the log directory
+fn sanitize_host_name(host: &str) -> String {
+    let host = host.trim().to_lowercase();
+    // Drop the port, leaving bracketed IPv6 addresses intact
+    let host = match host.rsplit_once(':') {
+        Some((name, port)) if !name.is_empty() && !port.is_empty()
+            && port.chars().all(|c| c.is_ascii_digit())
+            && (!name.contains(':') || name.ends_with(']')) => name.to_string(),
+        _ => host,
+    };
+    let sanitized: String = host.chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' || c == '.' { c } else { '_' })
+        .collect();
+    if sanitized.chars().all(|c| c == '.') {
+        // Empty, "." or ".." would name the directory itself or its parent
+        return UNKNOWN_HOST_FILE_NAME.to_string();
+    }
+    sanitized
+}
+
+/// Milliseconds as seconds with millisecond resolution
+fn format_seconds(ms: u64) -> String {
+    format!("{}.{:03}", ms / 1000, ms % 1000)
+}
+
+/// Read a header as text, empty if it is missing
+/// The Content-Length of a response, if it has one
+fn content_length(headers: &HeaderMap) -> Option<u64> {
+    headers.get("Content-Length")
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse().ok())
+}
+
+fn header_value(headers: &HeaderMap, name: &str) -> String {
+    headers.get(name)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("")
+        .to_string()
+}
+
+impl LogEntryData {
+    /// Timestamp as written by the common and combined formats
+    fn timestamp(&self) -> String {
+        self.time.format(COMMON_TIME_FORMAT).to_string()
+    }
+    
+    /// Value of a custom format variable, empty if unknown
+    fn variable(&self, variable: Variable) -> String {
+        // The URI is already redacted, so its parts are too
+        let (path, query) = self.uri.split_once('?').unwrap_or((&self.uri, ""));
+        match variable {
+            Variable::RemoteAddr => self.remote_ip.clone(),
+            Variable::RemoteUser => self.user.clone(),
+            Variable::Host => self.host.clone(),
+            Variable::RequestId => self.request_id.clone(),
+            Variable::TimeLocal => self.timestamp(),
+            Variable::TimeIso8601 => self.time.to_rfc3339_opts(chrono::SecondsFormat::Secs, true),
+            Variable::Date => self.time.format("%Y-%m-%d").to_string(),
+            Variable::Time => self.time.format("%H:%M:%S").to_string(),
+            Variable::Request => format!("{} {} {}", self.method, self.uri, self.version),
+            Variable::RequestMethod => self.method.clone(),
+            Variable::RequestUri => self.uri.clone(),
+            Variable::Uri => path.to_string(),
+            Variable::Args => query.to_string(),
+            Variable::ServerProtocol => self.version.clone(),
+            Variable::Status => self.status.to_string(),
+            Variable::BodyBytesSent => self.size.to_string(),
+            Variable::RequestTime => format_seconds(self.request_time_ms),
+            Variable::Ttfb => self.ttfb_ms.map(format_seconds).unwrap_or_default(),
+            Variable::MatchedDirectory => self.matched_directory.clone().unwrap_or_default(),
+            Variable::RespondingPlugin => self.responding_plugin.clone().unwrap_or_default(),
+            Variable::RequestHeader(index) => self.request_headers.get(index).cloned().unwrap_or_default(),
+            Variable::ResponseHeader(index) => self.response_headers.get(index).cloned().unwrap_or_default(),
+        }
+    }
+    
+    /// Format as Common Log Format
+    fn to_common_log(&self) -> String {
+        format!(r#"{} - {} [{}] "{} {} {}" {} {}"#,
+            self.remote_ip, self.user, self.timestamp(), 
+            self.method, self.uri, self.version, 
+            self.status, self.size)
+    }
+    
+    /// Format as Combined Log Format
+    fn to_combined_log(&self) -> String {
+        format!(r#"{} - {} [{}] "{} {} {}" {} {} "{}" "{}""#,
+            self.remote_ip, self.user, self.timestamp(), 
+            self.method, self.uri, self.version, 
+            self.status, self.size, 
+            self.referer, self.user_agent)
+    }
+    
+    /// Format as JSON
+    fn to_json_log(&self) -> String {
+        // Use serde_json for proper escaping
+        serde_json::json!({
+            "timestamp": self.timestamp(),
+            "remote_ip": self.remote_ip,
+            "user": self.user,
+            "method": self.method,
+            "uri": self.uri,
+            "version": self.version,
+            "status": self.status,
+            "size": self.size,
+            "user_agent": self.user_agent,
+            "referer": self.referer,
+            "request_time_ms": self.request_time_ms,
+            "ttfb_ms": self.ttfb_ms,
+            "matched_directory": self.matched_directory,
+            "responding_plugin": self.responding_plugin,
+        }).to_string()
+    }
 }
 
 /// Ensure buffer is flushed on drop
-impl Drop for AccessLogPlugin {
+impl Drop for LogWriter {
     fn drop(&mut self) {
         self.flush_buffer();
     }
@@ -0,0 +1,144 @@
+//! Extra log outputs for requests picked out by status, path and method
+//!
+//! Targets are declared as `https://rustybeam.net/schema/LogTarget`
+//! microdata items in the file named by `targets_file`:
+//! - `name`: Name of the target, used in logs (required)
+//! - `logFile`: File the target writes to, which may contain `{host}`
+//!   (default: stdout)
+//! - `status`: Statuses the target takes, as a code ("404"), a class
+//!   ("5xx") or a range ("400-499"); repeat the property for several
+//! - `pathPrefix`: Paths starting with this are taken; repeatable
+//! - `method`: HTTP method taken; repeatable
+//! - `format`, `customFormat`, `bufferSize`, `rotateSizeMb`, `rotateDaily`:
+//!   As the plugin's `format`, `custom_format`, `buffer_size`,
+//!   `rotate_size_mb` and `rotate_daily`, which they default to
+//!
+//! A request is taken by a target when it matches one of the values of each
+//! filter the target has, so a target without filters takes every request.
+//! An entry is written to every target taking its request, and the plugin's
+//! own `log_file` only gets the requests no target took.
+
+use microdata_extract::{MicrodataExtractor, MicrodataItem};
+use rusty_beam_plugin_api::{log_error, log_warn};
+use std::collections::HashMap;
+use std::path::Path;
+
+use super::LogOutput;
+
+const SCHEMA_LOG_TARGET: &str = "https://rustybeam.net/schema/LogTarget";
+
+/// Target properties and the plugin settings they stand in for
+const OUTPUT_PROPERTIES: &[(&str, &str)] = &[
+    ("logFile", "log_file"),
+    ("format", "format"),
+    ("customFormat", "custom_format"),
+    ("bufferSize", "buffer_size"),
+    ("rotateSizeMb", "rotate_size_mb"),
+    ("rotateDaily", "rotate_daily"),
+];
+
+/// An output and the requests written to it
+#[derive(Debug)]
+pub(crate) struct LogTarget {
+    pub(crate) name: String,
+    /// Inclusive status ranges, any of which may match
+    statuses: Vec<(u16, u16)>,
+    path_prefixes: Vec<String>,
+    /// Uppercase methods
+    methods: Vec<String>,
+    pub(crate) output: LogOutput,
+}
+
+impl LogTarget {
+    fn from_item(item: &MicrodataItem, config: &HashMap<String, String>) -> Result<Self, String> {
+        let values = |name: &str| -> Vec<String> {
+            item.get_property_values(name)
+                .into_iter()
+                .map(|value| value.trim().to_string())
+                .filter(|value| !value.is_empty())
+                .collect()
+        };
+
+        let name = values("name").into_iter().next().ok_or("Log target without a name")?;
+        let statuses = values("status")
+            .iter()
+            .map(|status| parse_status_range(status)
+                .ok_or_else(|| format!("Log target '{}' has an invalid status '{}'", name, status)))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // The target's own settings replace the plugin's, except that a
+        // target without a log file writes to stdout
+        let mut output_config = config.clone();
+        output_config.remove("log_file");
+        output_config.remove("logfile");
+        for (property, key) in OUTPUT_PROPERTIES {
+            if let Some(value) = values(property).into_iter().next() {
+                output_config.insert(key.to_string(), value);
+            }
+        }
+
+        Ok(Self {
+            statuses,
+            path_prefixes: values("pathPrefix"),
+            methods: values("method").iter().map(|method| method.to_uppercase()).collect(),
+            output: LogOutput::from_config(&output_config),
+            name,
+        })
+    }
+
+    /// Whether the target takes a request
+    pub(crate) fn takes(&self, method: &str, path: &str, status: u16) -> bool {
+        (self.statuses.is_empty() || self.statuses.iter().any(|(low, high)| (*low..=*high).contains(&status)))
+            && (self.path_prefixes.is_empty() || self.path_prefixes.iter().any(|prefix| path.starts_with(prefix.as_str())))
+            && (self.methods.is_empty() || self.methods.iter().any(|allowed| allowed.eq_ignore_ascii_case(method)))
+    }
+}
+
+/// A status filter as an inclusive range: "404", "5xx" or "400-499"
+fn parse_status_range(status: &str) -> Option<(u16, u16)> {
+    let status = status.to_lowercase();
+    let range = if let Some(class) = status.strip_suffix("xx") {
+        let class: u16 = class.parse().ok()?;
+        (class * 100, class * 100 + 99)
+    } else if let Some((low, high)) = status.split_once('-') {
+        (low.trim().parse().ok()?, high.trim().parse().ok()?)
+    } else {
+        let code = status.parse().ok()?;
+        (code, code)
+    };
+    let valid = |code: u16| (100..=599).contains(&code);
+    (valid(range.0) && valid(range.1) && range.0 <= range.1).then_some(range)
+}
+
+/// Read the targets declared in a targets file, skipping invalid ones
+pub(crate) fn load(name: &str, targets_file: &Path, config: &HashMap<String, String>) -> Vec<LogTarget> {
+    let content = match std::fs::read_to_string(targets_file) {
+        Ok(content) => content,
+        Err(e) => {
+            log_error!(name, "Failed to read log targets file: {}", e; file = targets_file.display());
+            return Vec::new();
+        }
+    };
+    let items = match MicrodataExtractor::new().extract(&content) {
+        Ok(items) => items,
+        Err(e) => {
+            log_error!(name, "Failed to parse log targets file: {}", e; file = targets_file.display());
+            return Vec::new();
+        }
+    };
+
+    let targets: Vec<LogTarget> = items.iter()
+        .filter(|item| item.item_type() == Some(SCHEMA_LOG_TARGET))
+        .filter_map(|item| match LogTarget::from_item(item, config) {
+            Ok(target) => Some(target),
+            Err(e) => {
+                log_warn!(name, "Ignoring log target: {}", e; file = targets_file.display());
+                None
+            }
+        })
+        .collect();
+    if targets.is_empty() {
+        log_warn!(name, "Log targets file declares no log targets"; file = targets_file.display());
+    }
+    targets
+}
@@ -0,0 +1,288 @@
+//! The administration page for the auth files
+//!
+//! The page lists the users, rules and path policies of every auth file as
+//! tables drawn from their microdata. Each item can be edited as HTML and
+//! saved or deleted with a selector request for its element, new items are
+//! appended to the element holding the last item of their kind, and a whole
+//! file can be replaced. The page only sends those requests; they go through
+//! the pipeline like any other write, so the rules must allow them, and the
+//! plugin checks what each write to an auth file would leave behind before
+//! letting it through. Files outside the host root can't be reached with a
+//! request and are shown read-only.
+
+use dom_query::{Document, NodeRef};
+use hyper::Method;
+use microdata_extract::MicrodataItem;
+
+use super::{html_escape, SCHEMA_AUTHORIZATION_RULE, SCHEMA_CREDENTIAL, SCHEMA_PATH_POLICY};
+
+/// Stands in for the body while a document is edited, so that fragments
+/// such as table rows are parsed in their final context
+const MARKER: &str = "__RUSTY_BEAM_AUTHORIZATION_ADMIN_MARKER__";
+
+/// The kinds of item the page lists, with their headings and columns
+const SECTIONS: &[(&str, &str, &str, &[&str])] = &[
+    (SCHEMA_CREDENTIAL, "Users", "user", &["Username", "Roles"]),
+    (SCHEMA_AUTHORIZATION_RULE, "Rules", "rule", &["Id", "User or role", "Path", "Methods", "Selector", "Action", "Expires"]),
+    (SCHEMA_PATH_POLICY, "Path policies", "policy", &["Path", "Methods", "Default action"]),
+];
+
+const PAGE_SCRIPT: &str = r#"
+document.addEventListener('click', async function (event) {
+    const button = event.target.closest('button[data-method]');
+    if (!button) return;
+    const editor = button.closest('.editor');
+    const method = button.dataset.method;
+    if (method === 'DELETE' && !confirm('Delete this item?')) return;
+    const headers = {};
+    if (editor.dataset.selector) {
+        headers['Range'] = 'selector=' + encodeURIComponent(editor.dataset.selector);
+    }
+    const options = { method: method, headers: headers, credentials: 'same-origin' };
+    if (method !== 'DELETE') options.body = editor.querySelector('textarea').value;
+    const error = editor.querySelector('.error');
+    error.textContent = '';
+    const response = await fetch(editor.dataset.url, options);
+    if (response.ok) {
+        location.reload();
+        return;
+    }
+    let message = response.status + ' ' + response.statusText;
+    try {
+        const body = await response.json();
+        message = body.message || message;
+    } catch (e) {}
+    error.textContent = message;
+});
+"#;
+
+/// An auth file as the page shows it
+pub(crate) struct AdminFile {
+    /// Where the file is on disk
+    pub(crate) path: String,
+    /// Path the file is served at, or None if it is outside the host root
+    pub(crate) url: Option<String>,
+    pub(crate) content: String,
+}
+
+/// A user, rule or policy found in an auth file
+struct AdminItem {
+    item_type: &'static str,
+    /// Selector picking the item's element alone
+    selector: String,
+    /// Selector of the element holding the item
+    container: String,
+    html: String,
+    item: MicrodataItem,
+}
+
+/// How a write changes an auth file
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Edit {
+    /// The body becomes the whole file
+    Replace,
+    /// The body is appended to the file
+    Append,
+    /// The file is removed, leaving nothing
+    Delete,
+    /// The body replaces the element a selector picks
+    ReplaceSelected(String),
+    /// The body is appended to the element a selector picks
+    AppendToSelected(String),
+    /// The element a selector picks is removed
+    DeleteSelected(String),
+}
+
+impl Edit {
+    /// The edit a write with a method and selector makes, or None if what it
+    /// would do can't be worked out in advance
+    pub(crate) fn of(method: &Method, selector: Option<String>) -> Option<Self> {
+        match (method, selector) {
+            (&Method::PUT, None) => Some(Edit::Replace),
+            (&Method::POST, None) => Some(Edit::Append),
+            (&Method::DELETE, None) => Some(Edit::Delete),
+            (&Method::PUT, Some(selector)) => Some(Edit::ReplaceSelected(selector)),
+            (&Method::POST, Some(selector)) => Some(Edit::AppendToSelected(selector)),
+            (&Method::DELETE, Some(selector)) => Some(Edit::DeleteSelected(selector)),
+            _ => None,
+        }
+    }
+
+    /// Whether the edit uses the request body
+    pub(crate) fn needs_body(&self) -> bool {
+        !matches!(self, Edit::Delete | Edit::DeleteSelected(_))
+    }
+
+    /// The content a file would have after the edit, the way the file and
+    /// selector handlers would make it
+    pub(crate) fn apply(&self, current: &str, body: &str) -> Result<String, String> {
+        let selector = match self {
+            Edit::Replace => return Ok(body.to_string()),
+            Edit::Append => return Ok(format!("{}{}", current, body)),
+            Edit::Delete => return Ok(String::new()),
+            Edit::ReplaceSelected(selector) | Edit::AppendToSelected(selector) | Edit::DeleteSelected(selector) => selector,
+        };
+        let document = Document::from(current);
+        let element = document.try_select(selector)
+            .ok_or_else(|| format!("the selector '{}' is invalid or matches nothing in the auth file", selector))?
+            .first();
+        match self {
+            Edit::ReplaceSelected(_) => element.replace_with_html(MARKER),
+            Edit::AppendToSelected(_) => element.append_html(MARKER),
+            _ => element.remove(),
+        }
+        Ok(document.html().replace(MARKER, body))
+    }
+}
+
+/// The users, rules and policies of an auth file, in document order
+fn admin_items(content: &str) -> Vec<AdminItem> {
+    let document = Document::from(content);
+    let mut items = Vec::new();
+    for element in document.select("[itemscope][itemtype]").iter() {
+        let item_type = element.attr("itemtype").unwrap_or_default();
+        let Some(&(item_type, ..)) = SECTIONS.iter().find(|(known, ..)| *known == item_type.trim()) else { continue };
+        let Some(node) = element.nodes().first() else { continue };
+        let Ok(item) = MicrodataItem::from_element_with_document(&element, &document) else { continue };
+        items.push(AdminItem {
+            item_type,
+            selector: element_path(node),
+            container: node.parent().map(|parent| element_path(&parent)).unwrap_or_default(),
+            html: element.html().to_string(),
+            item,
+        });
+    }
+    items
+}
+
+/// A selector picking one element by its position from the root, such as
+/// `html > body:nth-child(2) > table:nth-child(1)`
+fn element_path(node: &NodeRef) -> String {
+    let mut steps = Vec::new();
+    let mut current = Some(node.clone());
+    while let Some(element) = current.filter(|node| node.is_element()) {
+        let name = element.node_name().map(|name| name.to_lowercase()).unwrap_or_default();
+        if name == "html" {
+            steps.push(name);
+            break;
+        }
+        let mut position = 1;
+        let mut sibling = element.prev_element_sibling();
+        while let Some(previous) = sibling {
+            position += 1;
+            sibling = previous.prev_element_sibling();
+        }
+        steps.push(format!("{}:nth-child({})", name, position));
+        current = element.parent();
+    }
+    steps.reverse();
+    steps.join(" > ")
+}
+
+/// The table cells listing an item, without credentials' passwords
+fn item_cells(item: &AdminItem) -> Vec<String> {
+    let property = |name: &str| item.item.get_property(name).unwrap_or_default();
+    let values = |name: &str| item.item.get_property_values(name).join(", ");
+    match item.item_type {
+        SCHEMA_CREDENTIAL => vec![property("username"), values("role")],
+        SCHEMA_AUTHORIZATION_RULE => {
+            let mut subjects = item.item.get_property_values("username");
+            subjects.extend(item.item.get_property_values("role"));
+            subjects.extend(item.item.get_property_values("exclude").into_iter().map(|excluded| format!("!{}", excluded)));
+            let action = match item.item.get_property("redactSelector") {
+                Some(selector) => format!("redact {}", selector),
+                None => property("action"),
+            };
+            vec![property("id"), subjects.join(", "), property("path"), values("method"), property("selector"), action, property("expires")]
+        }
+        _ => vec![property("path"), values("method"), property("defaultAction")],
+    }
+}
+
+/// An editor sending its text to a file, for the element a selector picks
+/// or the whole file
+fn editor(url: &str, selector: Option<&str>, text: &str, buttons: &[(&str, &str)]) -> String {
+    let mut html = format!(r#"<div class="editor" data-url="{}""#, html_escape(url));
+    if let Some(selector) = selector {
+        html.push_str(&format!(r#" data-selector="{}""#, html_escape(selector)));
+    }
+    html.push_str(&format!(r#"><textarea rows="8" cols="100">{}</textarea><p>"#, html_escape(text)));
+    for (method, label) in buttons {
+        html.push_str(&format!(r#"<button type="button" data-method="{}">{}</button> "#, method, label));
+    }
+    html.push_str(r#"</p><p class="error"></p></div>"#);
+    html
+}
+
+/// The administration page for the auth files
+pub(crate) fn render_page(files: &[AdminFile], user: &str) -> String {
+    let mut html = String::from(r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Authorization Administration</title>
+<style>
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.25em 0.5em; text-align: left; vertical-align: top; }
+.error { color: #b00; }
+</style>
+</head>
+<body>
+<h1>Authorization Administration</h1>
+"#);
+    html.push_str(&format!("<p>Signed in as {}. Edits are checked before they are saved, and one that would leave no administrator able to edit the auth files is refused.</p>\n", html_escape(user)));
+
+    for file in files {
+        html.push_str(&format!("<section>\n<h2>{}</h2>\n", html_escape(file.url.as_deref().unwrap_or(&file.path))));
+        if file.url.is_none() {
+            html.push_str("<p>This file is outside the host root, so it can only be edited on disk.</p>\n");
+        }
+        let items = admin_items(&file.content);
+        for (item_type, heading, noun, columns) in SECTIONS {
+            let of_type: Vec<&AdminItem> = items.iter().filter(|item| item.item_type == *item_type).collect();
+            if of_type.is_empty() {
+                continue;
+            }
+            html.push_str(&format!("<h3>{}</h3>\n<table>\n<thead><tr>", heading));
+            for column in columns.iter() {
+                html.push_str(&format!("<th>{}</th>", column));
+            }
+            if file.url.is_some() {
+                html.push_str("<th></th>");
+            }
+            html.push_str("</tr></thead>\n<tbody>\n");
+            for item in &of_type {
+                html.push_str("<tr>");
+                for cell in item_cells(item) {
+                    html.push_str(&format!("<td>{}</td>", html_escape(&cell)));
+                }
+                if let Some(url) = &file.url {
+                    html.push_str(&format!(
+                        "<td><details><summary>Edit</summary>{}</details></td>",
+                        editor(url, Some(&item.selector), &item.html, &[("PUT", "Save"), ("DELETE", "Delete")]),
+                    ));
+                }
+                html.push_str("</tr>\n");
+            }
+            html.push_str("</tbody>\n</table>\n");
+            // New items start as a copy of the last one, next to it
+            if let (Some(url), Some(last)) = (&file.url, of_type.last()) {
+                html.push_str(&format!(
+                    "<details><summary>Add a {}</summary>{}</details>\n",
+                    noun,
+                    editor(url, Some(&last.container), &last.html, &[("POST", "Add")]),
+                ));
+            }
+        }
+        if let Some(url) = &file.url {
+            html.push_str(&format!(
+                "<details><summary>Edit the whole file</summary>{}</details>\n",
+                editor(url, None, &file.content, &[("PUT", "Save")]),
+            ));
+        }
+        html.push_str("</section>\n");
+    }
+
+    html.push_str(&format!("<script>{}</script>\n</body>\n</html>\n", PAGE_SCRIPT));
+    html
+}
@@ -20,6 +20,18 @@
 //! - Credentials define users and their roles
 //! - Authorization rules specify who can access what
 //!
+//! ## Several Auth Files
+//! `authfile` may list several files separated by commas, and an auth file
+//! may pull in others with `<link itemprop="include" href="wiki/auth.html">`,
+//! the href being relative to the including file. Rules from every file are
+//! merged into one list: the listed files in order, each file's own rules
+//! before the rules of the files it includes, and included files in the
+//! order they are named. That order decides ties under `first_match`.
+//! A user with credentials in several files has the roles from all of them.
+//! A file is only read once, so a file included again, directly or through
+//! a cycle, is skipped. If any file cannot be read, no rules are loaded and
+//! every request is denied.
+//!
 //! ## Username Placeholders
 //! Use `${username}` in selectors to create user-specific rules:
 //! - `li:has(meta[content="${username}"])` - matches elements with user's username
@@ -29,29 +41,211 @@
 //! - Usernames preserve special characters (@ . - _) for email addresses
 //! - Quotes and backslashes are escaped to prevent CSS injection
 //!
+//! ## Ownership Rules
+//! The `ownershipSelector` rule property is evaluated against each element
+//! matched by the request selector rather than against the whole document.
+//! The rule only applies if every one of those elements matches it or
+//! carries a match of its own, for example
+//! `meta[itemprop=author][content="${username}"]` lets users DELETE only the
+//! entries they wrote. A match counts as the element's own when it is a
+//! direct child, or when the element is an `itemscope` and no nested
+//! `itemscope` encloses the match, so a list is not owned by whoever wrote one
+//! of its items. A rule with an ownership selector but no `selector` applies
+//! to any request selector.
+//!
+//! ## Exact Selector Matches
+//! A request selector normally only has to select some of the elements the
+//! rule selector matches. With `requireExactMatch` set to `true` it must
+//! select exactly those elements, so a rule for `li[data-state="draft"]`
+//! allows a DELETE of all drafts but not of one draft picked out with a
+//! broader selector such as `li:first-child`, and the rule stops applying
+//! once the elements' state changes. Selectors that can't be checked
+//! against the document must then be written identically.
+//!
+//! ## Header Conditions
+//! A rule with `requiredHeader` properties only applies to requests carrying
+//! each of those headers. A value of `X-Webhook-Signature` requires the
+//! header with any value, and `X-Source: github` requires that value, so a
+//! rule can allow `POST` on `/webhooks/*` only when a signature is sent,
+//! leaving checking the signature to another plugin. Header names are
+//! matched in any case and values exactly. Checks made without a request,
+//! such as those of other plugins through the host's access services and
+//! the effective permissions, see no headers, so header-conditioned rules
+//! don't apply to them. A rule with a malformed `requiredHeader` is skipped.
+//!
+//! ## Method Specifications
+//! The `method` property accepts individual methods, comma-separated lists and:
+//! - `*` - every method, including methods not listed below
+//! - `read` - GET, HEAD, OPTIONS
+//! - `write` - PUT, POST, DELETE, PATCH
+//!
+//! ## Exclusions
+//! A rule can leave out users and roles it would otherwise cover, with
+//! `exclude` properties or `!`-prefixed `username` values. A rule for
+//! username `users` and `!suspended` applies to every member of the `users`
+//! role except those who also have the `suspended` role; a rule naming only
+//! exclusions applies to everyone else. Excluded users are removed before
+//! priorities are compared, so a lower-priority rule can still decide for them.
+//!
+//! ## Read Redaction
+//! A rule with a `redactSelector` does not allow or deny anything; it names
+//! elements to take out of the HTML responses the users it covers receive.
+//! A rule for `*` excluding `hr` on `/staff/*` with redactSelector `.salary`
+//! strips salaries from every GET of a staff page except for HR. Redaction
+//! rules match users, roles, exclusions, paths and methods as other rules
+//! do, default to read methods, and all that apply are used. Whole
+//! documents and selector responses are redacted alike, and lose the ETag
+//! and digest headers describing the unredacted document, as do HEAD
+//! responses. `redactMode` set to `obfuscate` keeps the elements, with
+//! `[redacted]` as their text and only their `id`, `class` and `itemprop`
+//! attributes, so the page keeps its layout; by default they are removed. `${username}` placeholders are
+//! filled in, with an empty username for anonymous users. A response that
+//! cannot be redacted, being encoded or not UTF-8, or because a
+//! `redactSelector` is invalid, is replaced by a 500.
+//!
 //! ## Rule Priority
 //! 1. Exact username match (highest)
 //! 2. :username (current authenticated user)
 //! 3. Role match
 //! 4. Wildcard (*) match (lowest)
 //!
+//! ## Default Actions per Path
+//! A request no rule matches is denied, unless a `PathPolicy` item in an auth
+//! file sets another default for its path. A policy has a `path` pattern, a
+//! `defaultAction` of `allow` or `deny` and optionally `method` values
+//! limiting it to some methods, so `/public/*` can default to allowing GET
+//! while `/admin/*` denies everything. Policies only decide requests that no
+//! rule matches; of those covering a request, the one with the longest path
+//! pattern applies, the first of them on a tie.
+//!
+//! ## Conflict Resolution
+//! When several rules match at the same priority, the `conflict` config value decides:
+//! - `first_match` (default) - the rule that appears first in the auth file wins
+//! - `deny_overrides` - any tied deny rule wins over tied allow rules
+//! - `allow_overrides` - any tied allow rule wins over tied deny rules
+//!
+//! ## Selector Evaluation
+//! Selector rules are checked against the target HTML file. Each file is read
+//! and parsed once per request, and each selector is evaluated once against
+//! that parsed document no matter how many rules use it. The raw file
+//! contents can also be kept across requests in a small LRU cache, keyed by
+//! path and invalidated when the file's modification time or size changes:
+//! - `selector_cache_size` - number of files to keep (default: 0, disabled)
+//!
+//! ## Denied Requests
+//! A denied request gets a 403 whose body explains the decision: an HTML page
+//! by default, or a JSON object when the client's Accept header prefers
+//! `application/json`. The JSON carries a `code` (`denied_by_rule`,
+//! `no_matching_rule`, `auth_config_unavailable` or `admin_role_required`), the deny rule that
+//! decided if there was one, the permission that was missing, and a link to
+//! the documentation. In verbose mode, or with `denied_rule_header` set, the
+//! deciding rule is also described in an `X-Denied-Rule` header.
+//! - `documentation_url` - link included in JSON denials
+//! - `denied_rule_header` - send `X-Denied-Rule` outside verbose mode (default: false)
+//!
+//! ## Rule Identifiers
+//! Rules may carry an `id` and a `description`. The id of the deciding rule
+//! appears in verbose logs, in `X-Denied-Rule`, in JSON denials and in the
+//! `authorization_rule` request metadata. Each decision is also logged as a
+//! record with `user`, `method`, `path` and `rule` fields, denials at info
+//! level and grants at debug level, as an audit trail.
+//!
+//! ## Rule Expiry
+//! A rule with `expires`, an RFC 3339 time or a date meaning midnight UTC at
+//! its start, stops applying once that time has passed, so temporary grants
+//! end without anyone having to remember to remove them. An `owner` names
+//! who answers for the rule in access reviews. Each rule that has expired
+//! or expires within `expiry_warning_days` is logged once, at info and warn
+//! level respectively, and expired rules are reported by the configuration
+//! check. A rule whose `expires` cannot be read is skipped. With
+//! `expiry_report_path` set, a `GET` of that path lists the expired and
+//! expiring rules as JSON, soonest first; `?days=` looks further ahead. The
+//! report reveals the rules, so the `GET` is authorized like any other request.
+//! - `expiry_warning_days` - days ahead a rule counts as expiring (default: 14)
+//! - `expiry_report_path` - path of the report (default: none, disabled)
+//!
+//! ## Effective Permissions
+//! `GET /auth/permissions?path=/page.html` returns, as JSON, the methods the
+//! current user may use on that resource and each selector named by the rules
+//! for it that they may write to, so front-ends can decide which edit
+//! controls to show. Adding `&selector=...` reports that one selector instead.
+//! - `permissions_path` - path of the endpoint (default: `/auth/permissions`, empty disables it)
+//!
+//! ## Rule Testing
+//! With `rule_test_path` set, a `POST` to that path with a JSON body such as
+//! `{"user": "alice", "roles": ["editors"], "path": "/page.html",
+//! "method": "PUT", "selector": "#notes"}` evaluates the rules for the
+//! described request without making it, and answers with the decision, the
+//! deciding rule and its priority, and a trace of every rule with the
+//! priority it matched at or the check it failed. `roles` replaces the roles
+//! the user has in the auth files; `user` and `selector` may be left out for
+//! an anonymous user and a whole-resource request, and `headers`, an object
+//! of header names and values, gives the request headers for header
+//! conditions. The endpoint is meant for
+//! development and is off by default. It reveals the rules, so the `POST`
+//! to it is itself authorized like any other request.
+//! - `rule_test_path` - path of the endpoint (default: none, disabled)
+//!
+//! ## Testing Auth Files
+//! With the `test-support` feature the crate exports the `testing` module,
+//! so sites can test their auth files in CI against this evaluation logic:
+//! an `AuthHarness` loads auth files, or writes ones built with `AuthFile`,
+//! and `assert_allowed!` and `assert_denied!` check how it decides requests
+//! built with `TestRequest`.
+//!
+//! ## Administration Page
+//! With `admin_path` set, for example to `/auth/admin`, a `GET` of that path
+//! by a user with the `admin_role` role returns a page listing the users,
+//! rules and path policies of every auth file, from which they can be
+//! edited. Others get a 403 with the code `admin_role_required`. The page
+//! edits a file with selector `PUT`, `POST` and `DELETE` requests for its
+//! items, or a `PUT` of the whole file, which are authorized like any other
+//! request, so the rules must let administrators write the auth files.
+//! Files outside the host root are shown but can't be edited this way.
+//! While the page is enabled, every write to an auth file is checked before
+//! it is made, whoever makes it: one leaving a file without valid microdata,
+//! with a selector that picks nothing, or that can't be predicted, such as a
+//! `PATCH`, is refused with a 422, and one after which no user with
+//! `admin_role` would be allowed to `PUT` each auth file under the host
+//! root is refused with a 409. The user making the edit counts as an
+//! administrator if roles from an authentication plugin give them the role.
+//! - `admin_path` - path of the page (default: none, disabled)
+//! - `admin_role` - role allowed to use it (default: `administrators`)
+//!
 //! ## Integration
 //! - Must be placed after authentication plugins (basic-auth, oauth2)
 //! - Works with selector-handler for CSS selector validation
 //! - Provides metadata for downstream plugins
 
-use rusty_beam_plugin_api::{Plugin, PluginRequest, PluginContext, PluginResponse, create_plugin};
+use rusty_beam_plugin_api::{capabilities, paths, transform_body, Capabilities, Plugin, PluginRequest, PluginContext, PluginResponse, ConfigIssue, create_plugin, log_debug, log_error, log_info, log_warn};
+use rusty_beam_plugin_api::validation::check_readable_file;
+use rusty_beam_plugin_api::services::WITHHOLD_ALL;
 use async_trait::async_trait;
-use hyper::{Body, Response, StatusCode, header::CONTENT_TYPE};
-use std::collections::HashMap;
+use hyper::{Body, Method, Response, StatusCode, header::{HeaderValue, CACHE_CONTROL, CONTENT_ENCODING, CONTENT_TYPE, VARY}};
+use serde::Deserialize;
+use serde_json::json;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use chrono::{DateTime, NaiveDate, SecondsFormat, TimeDelta, Utc};
 use microdata_extract::MicrodataExtractor;
-use dom_query::{Document, Selection};
-use regex::Regex;
+use dom_query::{Document, Matcher};
+use regex::{NoExpand, Regex};
+
+mod admin;
+#[cfg(any(test, feature = "test-support"))]
+pub mod testing;
+
+use admin::{AdminFile, Edit};
 
 // Schema URLs
 const SCHEMA_CREDENTIAL: &str = "https://rustybeam.net/schema/Credential";
 const SCHEMA_AUTHORIZATION_RULE: &str = "https://rustybeam.net/schema/AuthorizationRule";
+const SCHEMA_PATH_POLICY: &str = "https://rustybeam.net/schema/PathPolicy";
 
 // Default values
 const DEFAULT_ACTION: &str = "deny";
@@ -60,6 +254,16 @@ const DEFAULT_PLUGIN_NAME: &str = "authorization";
 // Special usernames
 const USERNAME_CURRENT: &str = ":username";
 const USERNAME_WILDCARD: &str = "*";
+const EXCLUDE_PREFIX: char = '!';
+
+// Method specifications
+const METHOD_WILDCARD: &str = "*";
+const METHOD_GROUP_READ: &str = "read";
+const METHOD_GROUP_WRITE: &str = "write";
+const READ_METHODS: &[&str] = &["GET", "HEAD", "OPTIONS"];
+const WRITE_METHODS: &[&str] = &["PUT", "POST", "DELETE", "PATCH"];
+/// Methods writing the file named by their Destination header
+const RELOCATE_METHODS: &[&str] = &["COPY", "MOVE"];
 
 // Rule priorities
 const PRIORITY_EXACT_USERNAME: usize = 3;
@@ -76,6 +280,9 @@ const PATH_PARAMETER_PREFIX: char = ':';
 const USERNAME_PLACEHOLDER_PATTERN: &str = r"\$\{\s*username\s*\}";
 const USERNAME_ANONYMOUS: &str = "*";
 
+// Selector cache defaults
+const DEFAULT_SELECTOR_CACHE_SIZE: usize = 0;
+
 // File extensions
 const HTML_EXTENSIONS: &[&str] = &[".html", ".htm"];
 
@@ -85,16 +292,174 @@ const ACCESS_DENIED_HTML: &str = r#"<!DOCTYPE html>
 <head><title>403 Forbidden</title></head>
 <body>
 <h1>403 Forbidden</h1>
-<p>User '{}' does not have permission to {} '{}'.</p>
+<p>User '{user}' does not have permission to {method} '{resource}'.</p>
 <p>Contact your administrator if you believe this is an error.</p>
 </body>
 </html>"#;
 
+// Denied request details
+const CONTENT_TYPE_HTML: &str = "text/html";
+const CONTENT_TYPE_JSON: &str = "application/json";
+const DENIED_RULE_HEADER: &str = "X-Denied-Rule";
+/// Request metadata naming the rule that decided, when it has an id
+const METADATA_AUTHORIZATION_RULE: &str = "authorization_rule";
+const DEFAULT_DOCUMENTATION_URL: &str = "https://rustybeam.net/plugins/authorization/#denied-requests";
+
+// Read redaction
+const REDACTED_TEXT: &str = "[redacted]";
+/// Attributes an obfuscated element keeps, as they name it rather than hold its content
+const OBFUSCATED_KEPT_ATTRIBUTES: &[&str] = &["id", "class", "itemprop"];
+const MSG_REDACTION_FAILED: &str = "The response could not be redacted";
+/// Headers describing the unredacted document, which a redacted response
+/// must not carry
+const UNREDACTED_HEADERS: &[&str] = &["etag", "repr-digest", "content-digest", "digest"];
+
+// Effective permissions endpoint
+const DEFAULT_PERMISSIONS_PATH: &str = "/auth/permissions";
+const PERMISSIONS_PATH_PARAM: &str = "path";
+const PERMISSIONS_SELECTOR_PARAM: &str = "selector";
+
+// Administration page
+const DEFAULT_ADMIN_ROLE: &str = "administrators";
+
+// Rule expiry
+const DEFAULT_EXPIRY_WARNING_DAYS: i64 = 14;
+const EXPIRY_REPORT_DAYS_PARAM: &str = "days";
+
 /// Plugin for resource authorization with role-based access control
 #[derive(Debug)]
 pub struct AuthorizationPlugin {
     name: String,
-    auth_file: Option<String>,
+    /// Auth files named by `authfile`, in order
+    auth_files: Vec<String>,
+    conflict_strategy: ConflictStrategy,
+    /// File contents reused across requests for selector checks
+    source_cache: Mutex<SourceCache>,
+    /// Link included in JSON denials
+    documentation_url: String,
+    /// Describe the deciding rule in a header even outside verbose mode
+    denied_rule_header: bool,
+    /// Path answering effective permissions queries; None disables them
+    permissions_path: Option<String>,
+    /// Path answering rule tests; None disables them
+    rule_test_path: Option<String>,
+    /// Path of the administration page, if it is enabled
+    admin_path: Option<String>,
+    /// Role a user needs to use the administration page
+    admin_role: String,
+    /// Path answering rule expiry reports; None disables them
+    expiry_report_path: Option<String>,
+    /// Days before it expires that a rule is reported and logged as expiring
+    expiry_warning_days: i64,
+    /// Rules already logged as expiring or expired, so each is logged once
+    expiry_logged: Mutex<HashSet<String>>,
+}
+
+/// LRU cache of HTML file contents used for selector checks
+#[derive(Debug)]
+struct SourceCache {
+    capacity: usize,
+    entries: HashMap<String, CachedSource>,
+    /// Paths from least to most recently used
+    order: VecDeque<String>,
+}
+
+/// File contents along with the metadata used to detect changes
+#[derive(Debug)]
+struct CachedSource {
+    modified: Option<SystemTime>,
+    len: u64,
+    content: Arc<String>,
+}
+
+impl SourceCache {
+    fn new(capacity: usize) -> Self {
+        Self { capacity, entries: HashMap::new(), order: VecDeque::new() }
+    }
+    
+    /// Return cached contents if the file is unchanged since it was cached
+    fn get(&mut self, path: &str, modified: Option<SystemTime>, len: u64) -> Option<Arc<String>> {
+        let entry = self.entries.get(path)?;
+        if entry.modified != modified || entry.len != len {
+            return None;
+        }
+        let content = Arc::clone(&entry.content);
+        self.touch(path);
+        Some(content)
+    }
+    
+    /// Store contents, evicting the least recently used file when full
+    fn insert(&mut self, path: &str, modified: Option<SystemTime>, len: u64, content: Arc<String>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.insert(path.to_string(), CachedSource { modified, len, content }).is_some() {
+            self.touch(path);
+            return;
+        }
+        self.order.push_back(path.to_string());
+        while self.order.len() > self.capacity {
+            if let Some(evicted) = self.order.pop_front() {
+                self.entries.remove(&evicted);
+            }
+        }
+    }
+    
+    /// Mark a path as most recently used
+    fn touch(&mut self, path: &str) {
+        if let Some(position) = self.order.iter().position(|p| p == path) {
+            if let Some(entry) = self.order.remove(position) {
+                self.order.push_back(entry);
+            }
+        }
+    }
+}
+
+/// Parsed documents and selector matches shared by every rule checked for one request
+/// 
+/// dom_query documents are not thread-safe, so this never outlives a single
+/// authorization check.
+#[derive(Default)]
+struct SelectorEvaluation {
+    /// Parsed document per file path, or None if the file can't be DOM-checked
+    documents: RefCell<HashMap<String, Option<Rc<Document>>>>,
+    /// Signatures of the elements each selector matches, per file path
+    matches: RefCell<HashMap<(String, String), ElementSignatures>>,
+}
+
+/// Serialized HTML of the elements matched by a selector
+type ElementSignatures = Rc<HashSet<String>>;
+
+/// How to choose between matching rules of equal priority
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConflictStrategy {
+    /// The rule listed first in the auth file wins
+    FirstMatch,
+    /// A deny rule wins over allow rules
+    DenyOverrides,
+    /// An allow rule wins over deny rules
+    AllowOverrides,
+}
+
+impl ConflictStrategy {
+    /// Parse a `conflict` config value
+    fn from_config(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().replace('-', "_").as_str() {
+            "first_match" => Some(ConflictStrategy::FirstMatch),
+            "deny_overrides" => Some(ConflictStrategy::DenyOverrides),
+            "allow_overrides" => Some(ConflictStrategy::AllowOverrides),
+            _ => None,
+        }
+    }
+    
+    /// Whether a tied rule with `challenger` action should replace one with `current`
+    fn overrides(&self, current: &Permission, challenger: &Permission) -> bool {
+        match self {
+            ConflictStrategy::FirstMatch => false,
+            ConflictStrategy::DenyOverrides => *current == Permission::Allow && *challenger == Permission::Deny,
+            ConflictStrategy::AllowOverrides => *current == Permission::Deny && *challenger == Permission::Allow,
+        }
+    }
 }
 
 /// Authorization rule defining access permissions
@@ -106,10 +471,215 @@ pub struct AuthorizationRule {
     pub path: String,
     /// Optional CSS selector for fine-grained element access
     pub selector: Option<String>,
+    /// Optional CSS selector every element matched by the request selector must match or contain
+    pub ownership_selector: Option<String>,
+    /// Whether the request selector must match exactly the rule selector's elements
+    pub require_exact_match: bool,
+    /// Headers the request must carry for the rule to apply
+    pub required_headers: Vec<RequiredHeader>,
+    /// Usernames and roles the rule never applies to
+    pub exclude: Vec<String>,
     /// HTTP methods this rule applies to
     pub methods: Vec<String>,
     /// Allow or deny action
     pub action: Permission,
+    /// Identifier naming the rule in logs, headers and denials
+    pub id: Option<String>,
+    /// What the rule is for, in the words of whoever wrote it
+    pub description: Option<String>,
+    /// When the rule stops applying, for temporary grants
+    pub expires: Option<DateTime<Utc>>,
+    /// Who answers for the rule in access reviews
+    pub owner: Option<String>,
+}
+
+/// A header a rule requires the request to carry
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequiredHeader {
+    /// Header name, matched in any case
+    pub name: String,
+    /// Value the header must have; any value will do when `None`
+    pub value: Option<String>,
+}
+
+impl RequiredHeader {
+    /// Parse `Name` or `Name: value`
+    fn parse(spec: &str) -> Option<Self> {
+        let (name, value) = match spec.split_once(':') {
+            Some((name, value)) => (name.trim(), Some(value.trim().to_string())),
+            None => (spec.trim(), None),
+        };
+        hyper::header::HeaderName::from_bytes(name.as_bytes()).ok()?;
+        Some(Self { name: name.to_string(), value })
+    }
+    
+    /// Whether a request's headers meet the requirement
+    fn is_met(&self, headers: &hyper::HeaderMap) -> bool {
+        headers.get_all(self.name.as_str()).iter().any(|value| match &self.value {
+            Some(expected) => value.to_str().is_ok_and(|value| value.trim() == expected),
+            None => true,
+        })
+    }
+}
+
+impl std::fmt::Display for RequiredHeader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.value {
+            Some(value) => write!(f, "{}: {}", self.name, value),
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// Default action for requests to a path that no rule decides
+#[derive(Debug, Clone)]
+pub struct PathPolicy {
+    /// Path pattern, as for rules
+    pub path: String,
+    /// HTTP methods the default applies to
+    pub methods: Vec<String>,
+    /// Action taken when no rule matches
+    pub action: Permission,
+    /// Identifier naming the policy in logs, headers and denials
+    pub id: Option<String>,
+}
+
+impl PathPolicy {
+    /// The policy as a rule for everyone, to report it where a rule decided
+    fn to_rule(&self) -> AuthorizationRule {
+        AuthorizationRule {
+            username: USERNAME_WILDCARD.to_string(),
+            path: self.path.clone(),
+            selector: None,
+            ownership_selector: None,
+            require_exact_match: false,
+            required_headers: vec![],
+            exclude: Vec::new(),
+            methods: self.methods.clone(),
+            action: self.action.clone(),
+            id: self.id.clone(),
+            description: Some(format!("Default action for {}", self.path)),
+            expires: None,
+            owner: None,
+        }
+    }
+}
+
+/// How redacted elements are left in a response
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RedactionMode {
+    /// The elements are taken out
+    Remove,
+    /// The elements stay, with their content and attributes replaced
+    Obfuscate,
+}
+
+/// Rule naming elements of HTML responses a user may not see
+///
+/// The rule's username, exclusions, path and methods decide whom and which
+/// responses it applies to, as they do for access rules.
+#[derive(Debug, Clone)]
+pub struct RedactionRule {
+    pub rule: AuthorizationRule,
+    /// CSS selector for the elements to redact
+    pub redact_selector: String,
+    pub mode: RedactionMode,
+}
+
+/// Users, rules and path policies merged from the auth files
+#[derive(Debug, Default)]
+struct LoadedAuthFiles {
+    users: Vec<User>,
+    rules: Vec<AuthorizationRule>,
+    policies: Vec<PathPolicy>,
+    redactions: Vec<RedactionRule>,
+    /// Canonical paths of the files read so far
+    files: Vec<PathBuf>,
+    /// Includes that were skipped, for configuration checks
+    problems: Vec<String>,
+}
+
+/// A request described to the rule testing endpoint, which is evaluated
+/// but never made
+#[derive(Debug, Deserialize)]
+struct RuleTest {
+    user: Option<String>,
+    /// Roles to evaluate with instead of the user's own
+    roles: Option<Vec<String>>,
+    path: String,
+    method: Option<String>,
+    selector: Option<String>,
+    /// Headers the request carries, for rules with header conditions
+    #[serde(default)]
+    headers: HashMap<String, String>,
+}
+
+/// Why a request was denied
+#[derive(Debug)]
+struct Denial {
+    code: DenialCode,
+    /// The deny rule that decided, if one did
+    rule: Option<Box<AuthorizationRule>>,
+}
+
+/// Machine-readable reason for a denial
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DenialCode {
+    /// The auth file could not be loaded
+    ConfigUnavailable,
+    /// No rule applies, so the default deny action was taken
+    NoMatchingRule,
+    /// The best matching rule denies access
+    DeniedByRule,
+    /// The administration page needs a role the user doesn't have
+    AdminRoleRequired,
+}
+
+impl DenialCode {
+    fn as_str(&self) -> &'static str {
+        match self {
+            DenialCode::ConfigUnavailable => "auth_config_unavailable",
+            DenialCode::NoMatchingRule => "no_matching_rule",
+            DenialCode::DeniedByRule => "denied_by_rule",
+            DenialCode::AdminRoleRequired => "admin_role_required",
+        }
+    }
+}
+
+/// The first check a rule failed against a request
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RuleMismatch {
+    /// The rule's expiry date has passed
+    Expired,
+    /// The rule is for other methods
+    Method,
+    /// The rule is for other paths
+    Path,
+    /// The request lacks a header the rule requires
+    Header,
+    /// Only one of the rule and the request has a selector
+    SelectorPresence,
+    /// The rule names the user in a selector, and the user is anonymous
+    AnonymousPlaceholder,
+    /// The request selector picks elements the rule does not cover
+    Selector,
+    /// The rule is for other users and roles, or excludes the user
+    User,
+}
+
+impl RuleMismatch {
+    fn as_str(&self) -> &'static str {
+        match self {
+            RuleMismatch::Expired => "expired",
+            RuleMismatch::Method => "method",
+            RuleMismatch::Path => "path",
+            RuleMismatch::Header => "header",
+            RuleMismatch::SelectorPresence => "selector_presence",
+            RuleMismatch::AnonymousPlaceholder => "anonymous_placeholder",
+            RuleMismatch::Selector => "selector",
+            RuleMismatch::User => "user",
+        }
+    }
 }
 
 /// Permission action for authorization rules
@@ -130,50 +700,276 @@ pub struct User {
     pub roles: Vec<String>,
 }
 
+impl AuthorizationRule {
+    /// Whether a user, or one of their roles, is excluded from the rule
+    fn excludes(&self, username: &str, user_roles: &[String]) -> bool {
+        self.exclude.iter().any(|excluded| excluded == username || user_roles.contains(excluded))
+    }
+    
+    /// Whether the rule's expiry date has passed
+    fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires.is_some_and(|expires| expires <= now)
+    }
+    
+    /// Whether the rule constrains which elements a request may select
+    fn has_selector(&self) -> bool {
+        self.selector.is_some() || self.ownership_selector.is_some()
+    }
+    
+    /// One-line description identifying the rule in a header
+    fn describe(&self) -> String {
+        let mut description = match &self.id {
+            Some(id) => format!("id={}; ", id),
+            None => String::new(),
+        };
+        description.push_str(&format!(
+            "username={}; path={}; methods={}",
+            self.username, self.path, self.methods.join(",")
+        ));
+        if let Some(selector) = &self.selector {
+            description.push_str(&format!("; selector={}", selector));
+        }
+        if let Some(ownership_selector) = &self.ownership_selector {
+            description.push_str(&format!("; ownershipSelector={}", ownership_selector));
+        }
+        if self.require_exact_match {
+            description.push_str("; requireExactMatch=true");
+        }
+        if !self.required_headers.is_empty() {
+            let headers: Vec<String> = self.required_headers.iter().map(RequiredHeader::to_string).collect();
+            description.push_str(&format!("; requiredHeader={}", headers.join(",")));
+        }
+        if !self.exclude.is_empty() {
+            description.push_str(&format!("; exclude={}", self.exclude.join(",")));
+        }
+        description.push_str(&format!("; action={}", self.action.as_str()));
+        if let Some(expires) = self.expires {
+            description.push_str(&format!("; expires={}", format_time(expires)));
+        }
+        description
+    }
+    
+    /// The rule's id, or its description when it has none
+    fn label(&self) -> String {
+        self.id.clone().unwrap_or_else(|| self.describe())
+    }
+    
+    /// The rule as it appears in JSON denials
+    fn to_json(&self) -> serde_json::Value {
+        json!({
+            "id": self.id,
+            "description": self.description,
+            "username": self.username,
+            "path": self.path,
+            "selector": self.selector,
+            "ownershipSelector": self.ownership_selector,
+            "requireExactMatch": self.require_exact_match,
+            "requiredHeaders": self.required_headers.iter().map(RequiredHeader::to_string).collect::<Vec<_>>(),
+            "exclude": self.exclude,
+            "methods": self.methods,
+            "action": self.action.as_str(),
+            "expires": self.expires.map(format_time),
+            "owner": self.owner,
+        })
+    }
+}
+
+impl Permission {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Permission::Allow => "allow",
+            Permission::Deny => "deny",
+        }
+    }
+}
+
 impl AuthorizationPlugin {
     pub fn new(config: HashMap<String, String>) -> Self {
         let name = config.get("name").cloned().unwrap_or_else(|| DEFAULT_PLUGIN_NAME.to_string());
-        let auth_file = config.get("authfile").cloned();
+        let auth_files = config.get("authfile")
+            .map(|files| files.split(',')
+                .map(|file| file.trim().to_string())
+                .filter(|file| !file.is_empty())
+                .collect())
+            .unwrap_or_default();
+        let conflict_strategy = match config.get("conflict") {
+            Some(value) => ConflictStrategy::from_config(value).unwrap_or_else(|| {
+                log_warn!(name, "Unknown conflict strategy, using first_match"; conflict = value);
+                ConflictStrategy::FirstMatch
+            }),
+            None => ConflictStrategy::FirstMatch,
+        };
         
-        Self { name, auth_file }
-    }
-    
-    /// Load authorization configuration from HTML file
-    fn load_auth_config(&self) -> Option<(Vec<User>, Vec<AuthorizationRule>)> {
-        let auth_file = self.auth_file.as_ref()?;
+        let selector_cache_size = config.get("selector_cache_size")
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_SELECTOR_CACHE_SIZE);
         
-        // Handle file:// URLs
-        let file_path = if auth_file.starts_with("file://") {
-            &auth_file[7..]
-        } else {
-            auth_file
+        let documentation_url = config.get("documentation_url")
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_DOCUMENTATION_URL.to_string());
+        let denied_rule_header = config.get("denied_rule_header")
+            .map(|v| v.trim().eq_ignore_ascii_case("true"))
+            .unwrap_or(false);
+        // An empty value turns the endpoint off
+        let permissions_path = match config.get("permissions_path") {
+            Some(path) if path.trim().is_empty() => None,
+            Some(path) => Some(path.trim().to_string()),
+            None => Some(DEFAULT_PERMISSIONS_PATH.to_string()),
         };
+        let rule_test_path = config.get("rule_test_path")
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty());
+        let admin_path = config.get("admin_path")
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty());
+        let admin_role = config.get("admin_role")
+            .map(|role| role.trim().to_string())
+            .filter(|role| !role.is_empty())
+            .unwrap_or_else(|| DEFAULT_ADMIN_ROLE.to_string());
+        let expiry_report_path = config.get("expiry_report_path")
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty());
+        let expiry_warning_days = config.get("expiry_warning_days")
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|days| *days >= 0)
+            .unwrap_or(DEFAULT_EXPIRY_WARNING_DAYS);
         
-        let content = fs::read_to_string(file_path).ok()?;
-        let extractor = MicrodataExtractor::new();
-        let items = extractor.extract(&content).ok()?;
+        Self {
+            name,
+            auth_files,
+            conflict_strategy,
+            source_cache: Mutex::new(SourceCache::new(selector_cache_size)),
+            documentation_url,
+            denied_rule_header,
+            permissions_path,
+            rule_test_path,
+            admin_path,
+            admin_role,
+            expiry_report_path,
+            expiry_warning_days,
+            expiry_logged: Mutex::new(HashSet::new()),
+        }
+    }
+    
+    /// Load authorization configuration from the auth files
+    fn load_auth_config(&self) -> Option<LoadedAuthFiles> {
+        let loaded = self.read_auth_files().ok()?;
+        self.log_rule_expiry(&loaded.rules);
+        Some(loaded)
+    }
+    
+    /// Log rules that have expired or expire within `expiry_warning_days`,
+    /// the first time they are seen in that state, for access reviews
+    fn log_rule_expiry(&self, rules: &[AuthorizationRule]) {
+        let now = Utc::now();
+        let warning = now + TimeDelta::days(self.expiry_warning_days);
+        let mut logged = self.expiry_logged.lock().unwrap_or_else(|e| e.into_inner());
+        for rule in rules {
+            let Some(expires) = rule.expires.filter(|expires| *expires <= warning) else { continue };
+            let expired = expires <= now;
+            if !logged.insert(format!("{}|{}", expired, rule.describe())) {
+                continue;
+            }
+            let expires = format_time(expires);
+            let owner = rule.owner.as_deref().unwrap_or("-");
+            if expired {
+                log_info!(self.name, "Authorization rule has expired and is ignored"; rule = rule.label(), expires = expires, owner = owner);
+            } else {
+                log_warn!(self.name, "Authorization rule expires soon"; rule = rule.label(), expires = expires, owner = owner);
+            }
+        }
+    }
+    
+    /// Read every configured auth file and the files they include
+    fn read_auth_files(&self) -> Result<LoadedAuthFiles, String> {
+        self.read_edited_auth_files(None)
+    }
+    
+    /// Read the auth files as they would be with one file's content replaced
+    fn read_edited_auth_files(&self, replacement: Option<(&Path, &str)>) -> Result<LoadedAuthFiles, String> {
+        let mut loaded = LoadedAuthFiles::default();
+        for auth_file in &self.auth_files {
+            let path = Path::new(auth_file.strip_prefix("file://").unwrap_or(auth_file));
+            self.read_auth_file(path, replacement, &mut Vec::new(), &mut loaded)?;
+        }
+        Ok(loaded)
+    }
+    
+    /// Read one auth file, then the files it includes
+    ///
+    /// `chain` holds the files that led to this one, to tell an include cycle
+    /// from a file that is simply included twice. `replacement` gives the
+    /// content to use for a file, by its canonical path, instead of reading it.
+    fn read_auth_file(
+        &self,
+        path: &Path,
+        replacement: Option<(&Path, &str)>,
+        chain: &mut Vec<PathBuf>,
+        loaded: &mut LoadedAuthFiles,
+    ) -> Result<(), String> {
+        let file = fs::canonicalize(path)
+            .map_err(|e| format!("auth file {} cannot be read: {}", path.display(), e))?;
+        if loaded.files.contains(&file) {
+            if chain.contains(&file) {
+                loaded.problems.push(format!("auth file {} includes itself, the include is skipped", file.display()));
+            }
+            return Ok(());
+        }
+        loaded.files.push(file.clone());
         
-        let mut users = Vec::new();
-        let mut authorization_rules = Vec::new();
+        let content = match replacement {
+            Some((replaced, content)) if replaced == file => content.to_string(),
+            _ => fs::read_to_string(&file)
+                .map_err(|e| format!("auth file {} cannot be read: {}", file.display(), e))?,
+        };
+        let items = MicrodataExtractor::new().extract(&content)
+            .map_err(|e| format!("auth file {} does not contain valid microdata: {}", file.display(), e))?;
         
-        // Process each microdata item
         for item in &items {
             match item.item_type() {
                 Some(SCHEMA_CREDENTIAL) => {
-                    if let Some(user) = self.parse_user_credential(item) {
-                        users.push(user);
+                    let Some(user) = self.parse_user_credential(item) else { continue };
+                    match loaded.users.iter_mut().find(|known| known.username == user.username) {
+                        Some(known) => {
+                            for role in user.roles {
+                                if !known.roles.contains(&role) {
+                                    known.roles.push(role);
+                                }
+                            }
+                        }
+                        None => loaded.users.push(user),
                     }
                 }
-                Some(SCHEMA_AUTHORIZATION_RULE) => {
-                    if let Some(rule) = self.parse_authorization_rule(item) {
-                        authorization_rules.push(rule);
+                Some(SCHEMA_AUTHORIZATION_RULE) if item.get_property("redactSelector").is_some() => {
+                    match self.parse_redaction_rule(item) {
+                        Ok(redaction) => loaded.redactions.push(redaction),
+                        Err(problem) => loaded.problems.push(format!("auth file {}: {}, the rule is skipped", file.display(), problem)),
                     }
                 }
+                Some(SCHEMA_AUTHORIZATION_RULE) => match self.parse_expiry(item).and(self.parse_required_headers(item)) {
+                    Ok(_) => {
+                        if let Some(rule) = self.parse_authorization_rule(item) {
+                            loaded.rules.push(rule);
+                        }
+                    }
+                    Err(problem) => loaded.problems.push(format!("auth file {}: {}, the rule is skipped", file.display(), problem)),
+                },
+                Some(SCHEMA_PATH_POLICY) => match self.parse_path_policy(item) {
+                    Ok(policy) => loaded.policies.push(policy),
+                    Err(problem) => loaded.problems.push(format!("auth file {}: {}, the policy is skipped", file.display(), problem)),
+                },
                 _ => {}
             }
         }
         
-        Some((users, authorization_rules))
+        let directory = file.parent().map(Path::to_path_buf).unwrap_or_default();
+        chain.push(file);
+        for include in auth_file_includes(&content) {
+            let include = include.strip_prefix("file://").unwrap_or(&include);
+            self.read_auth_file(&directory.join(include), replacement, chain, loaded)?;
+        }
+        chain.pop();
+        Ok(())
     }
     
     /// Parse user credential from microdata item
@@ -187,15 +983,40 @@ impl AuthorizationPlugin {
         Some(User { username, roles })
     }
     
-    /// Parse authorization rule from microdata item
-    fn parse_authorization_rule(&self, item: &microdata_extract::MicrodataItem) -> Option<AuthorizationRule> {
+    /// Parse the user or role a rule names and those it excludes
+    ///
+    /// A rule naming only exclusions applies to everyone else. The username
+    /// is empty if the rule names no one.
+    fn parse_rule_subject(&self, item: &microdata_extract::MicrodataItem) -> (String, Vec<String>) {
         // Support both "username" and "role" properties for backward compatibility
-        let username = item.get_property("username")
-            .or_else(|| item.get_property("role"))
-            .unwrap_or_default();
+        let mut subjects = item.get_property_values("username");
+        if subjects.is_empty() {
+            subjects = item.get_property_values("role");
+        }
         
+        // "!name" values exclude a user or role rather than naming the subject
+        let mut exclude: Vec<String> = item.get_property_values("exclude");
+        let mut username = String::new();
+        for subject in subjects {
+            let subject = subject.trim();
+            match subject.strip_prefix(EXCLUDE_PREFIX) {
+                Some(excluded) => exclude.push(excluded.trim().to_string()),
+                None if username.is_empty() => username = subject.to_string(),
+                None => {}
+            }
+        }
+        exclude.retain(|excluded| !excluded.trim().is_empty());
+        if username.is_empty() && !exclude.is_empty() {
+            username = USERNAME_WILDCARD.to_string();
+        }
+        (username, exclude)
+    }
+    
+    /// Parse authorization rule from microdata item
+    fn parse_authorization_rule(&self, item: &microdata_extract::MicrodataItem) -> Option<AuthorizationRule> {
+        let (username, exclude) = self.parse_rule_subject(item);
         let path = item.get_property("path").unwrap_or_default();
-        let methods = item.get_property_values("method");
+        let methods = self.expand_method_specs(&item.get_property_values("method"));
         
         // Validate required fields
         if username.is_empty() || path.is_empty() || methods.is_empty() {
@@ -204,6 +1025,11 @@ impl AuthorizationPlugin {
         
         let selector = item.get_property("selector")
             .filter(|s| !s.trim().is_empty());
+        let ownership_selector = item.get_property("ownershipSelector")
+            .filter(|s| !s.trim().is_empty());
+        let require_exact_match = item.get_property("requireExactMatch")
+            .is_some_and(|v| v.trim().eq_ignore_ascii_case("true"));
+        let required_headers = self.parse_required_headers(item).ok()?;
         
         let action_str = item.get_property("action")
             .unwrap_or_else(|| DEFAULT_ACTION.to_string());
@@ -213,16 +1039,192 @@ impl AuthorizationPlugin {
             _ => Permission::Deny,
         };
         
+        let id = item.get_property("id")
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty());
+        let description = item.get_property("description")
+            .map(|description| description.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|description| !description.is_empty());
+        let expires = self.parse_expiry(item).ok()?;
+        
         Some(AuthorizationRule {
             username,
             path,
             selector,
+            ownership_selector,
+            require_exact_match,
+            required_headers,
+            exclude,
             methods,
             action,
+            id,
+            description,
+            expires,
+            owner: self.parse_owner(item),
+        })
+    }
+    
+    /// Parse when a rule stops applying, as an RFC 3339 time or a date,
+    /// which means midnight UTC at its start
+    fn parse_expiry(&self, item: &microdata_extract::MicrodataItem) -> Result<Option<DateTime<Utc>>, String> {
+        let Some(expires) = item.get_property("expires").map(|expires| expires.trim().to_string()).filter(|expires| !expires.is_empty()) else {
+            return Ok(None);
+        };
+        parse_time(&expires).map(Some).ok_or_else(|| {
+            let rule = item.get_property("id").or_else(|| item.get_property("path")).unwrap_or_default();
+            format!("rule {} has an invalid expires date '{}'", rule.trim(), expires)
+        })
+    }
+    
+    /// Parse the headers a rule requires the request to carry
+    fn parse_required_headers(&self, item: &microdata_extract::MicrodataItem) -> Result<Vec<RequiredHeader>, String> {
+        item.get_property_values("requiredHeader").iter()
+            .filter(|spec| !spec.trim().is_empty())
+            .map(|spec| RequiredHeader::parse(spec).ok_or_else(|| {
+                let rule = item.get_property("id").or_else(|| item.get_property("path")).unwrap_or_default();
+                format!("rule {} has an invalid requiredHeader '{}'", rule.trim(), spec.trim())
+            }))
+            .collect()
+    }
+    
+    /// Parse who answers for a rule in access reviews
+    fn parse_owner(&self, item: &microdata_extract::MicrodataItem) -> Option<String> {
+        item.get_property("owner")
+            .map(|owner| owner.trim().to_string())
+            .filter(|owner| !owner.is_empty())
+    }
+    
+    /// Parse a redaction rule from an authorization rule item with a
+    /// `redactSelector`
+    ///
+    /// Without methods the rule applies to reads. A `redactMode` other than
+    /// `obfuscate` removes the elements.
+    fn parse_redaction_rule(&self, item: &microdata_extract::MicrodataItem) -> Result<RedactionRule, String> {
+        let redact_selector = item.get_property("redactSelector")
+            .map(|selector| selector.trim().to_string())
+            .filter(|selector| !selector.is_empty())
+            .ok_or("a redaction rule has an empty redactSelector")?;
+        let (username, exclude) = self.parse_rule_subject(item);
+        if username.is_empty() {
+            return Err(format!("redaction rule for {} names no username or role", redact_selector));
+        }
+        let path = item.get_property("path")
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty())
+            .ok_or_else(|| format!("redaction rule for {} has no path", redact_selector))?;
+        let mut methods = item.get_property_values("method");
+        if methods.is_empty() {
+            methods.push(METHOD_GROUP_READ.to_string());
+        }
+        let mode = match item.get_property("redactMode").map(|mode| mode.trim().to_lowercase()).as_deref() {
+            Some("obfuscate") => RedactionMode::Obfuscate,
+            _ => RedactionMode::Remove,
+        };
+        let id = item.get_property("id")
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty());
+        let description = item.get_property("description")
+            .map(|description| description.split_whitespace().collect::<Vec<_>>().join(" "))
+            .filter(|description| !description.is_empty());
+        let expires = self.parse_expiry(item)?;
+        let required_headers = self.parse_required_headers(item)?;
+        
+        Ok(RedactionRule {
+            rule: AuthorizationRule {
+                username,
+                path,
+                selector: None,
+                ownership_selector: None,
+                require_exact_match: false,
+                required_headers,
+                exclude,
+                methods: self.expand_method_specs(&methods),
+                action: Permission::Deny,
+                id,
+                description,
+                expires,
+                owner: self.parse_owner(item),
+            },
+            redact_selector,
+            mode,
         })
     }
     
+    /// Parse a path policy from microdata item
+    fn parse_path_policy(&self, item: &microdata_extract::MicrodataItem) -> Result<PathPolicy, String> {
+        let path = item.get_property("path")
+            .map(|path| path.trim().to_string())
+            .filter(|path| !path.is_empty())
+            .ok_or("a path policy has no path")?;
+        let action = match item.get_property("defaultAction").map(|action| action.trim().to_lowercase()).as_deref() {
+            Some("allow") => Permission::Allow,
+            Some("deny") => Permission::Deny,
+            Some(action) => return Err(format!("path policy for {} has unknown defaultAction {}", path, action)),
+            None => return Err(format!("path policy for {} has no defaultAction", path)),
+        };
+        // Without methods the default applies to every method
+        let mut methods = item.get_property_values("method");
+        if methods.is_empty() {
+            methods.push(METHOD_WILDCARD.to_string());
+        }
+        let id = item.get_property("id")
+            .map(|id| id.trim().to_string())
+            .filter(|id| !id.is_empty());
+        
+        Ok(PathPolicy { methods: self.expand_method_specs(&methods), path, action, id })
+    }
+    
+    /// The policy deciding `method` on `path` when no rule does: the one with
+    /// the longest path pattern among those that apply, the first of them on a tie
+    fn find_path_policy<'a>(&self, policies: &'a [PathPolicy], path: &str, method: &str) -> Option<&'a PathPolicy> {
+        let method = method.to_uppercase();
+        policies.iter()
+            .filter(|policy| policy.methods.iter().any(|m| m == METHOD_WILDCARD || *m == method))
+            .filter(|policy| self.path_matches(path, &policy.path))
+            .fold(None, |best: Option<&PathPolicy>, policy| match best {
+                Some(best) if best.path.len() >= policy.path.len() => Some(best),
+                _ => Some(policy),
+            })
+    }
+    
+    /// Expand method specifications into uppercase method names
+    ///
+    /// Groups are replaced by their member methods. The wildcard is expanded to
+    /// all known methods for OPTIONS discovery and also kept as a marker so that
+    /// methods outside the known list still match.
+    fn expand_method_specs(&self, specs: &[String]) -> Vec<String> {
+        let mut methods: Vec<String> = Vec::new();
+        let mut push = |method: &str| {
+            if !methods.iter().any(|m| m == method) {
+                methods.push(method.to_string());
+            }
+        };
+        
+        for spec in specs.iter().flat_map(|s| s.split(',')) {
+            let spec = spec.trim();
+            if spec.is_empty() {
+                continue;
+            }
+            
+            match spec.to_lowercase().as_str() {
+                METHOD_WILDCARD => {
+                    READ_METHODS.iter().chain(WRITE_METHODS).for_each(|m| push(m));
+                    push(METHOD_WILDCARD);
+                }
+                METHOD_GROUP_READ => READ_METHODS.iter().for_each(|m| push(m)),
+                METHOD_GROUP_WRITE => WRITE_METHODS.iter().for_each(|m| push(m)),
+                _ => push(&spec.to_uppercase()),
+            }
+        }
+        
+        methods
+    }
+    
     /// Extract CSS selector from Range header
+    ///
+    /// The selector handler's paging parameters, `;start=N` and `;count=N`,
+    /// only choose which matches a GET returns, so rules see the selector
+    /// without them.
     fn extract_selector_from_request(&self, request: &PluginRequest) -> Option<String> {
         let range_header = request.http_request.headers().get("range")?;
         let range_str = range_header.to_str().ok()?;
@@ -230,7 +1232,8 @@ impl AuthorizationPlugin {
         let selector_regex = Regex::new(r"selector=(.*)\s*$").ok()?;
         let captures = selector_regex.captures(range_str)?;
         captures.get(1).map(|m| {
-            urlencoding::decode(m.as_str()).unwrap_or_else(|_| m.as_str().into()).into_owned()
+            let selector = strip_paging_parameters(m.as_str());
+            urlencoding::decode(selector).unwrap_or_else(|_| selector.into()).into_owned()
         })
     }
     
@@ -323,7 +1326,9 @@ impl AuthorizationPlugin {
         &self,
         rule_selector: &str,
         request_selector: &str,
+        require_exact_match: bool,
         file_path: &str,
+        evaluation: &SelectorEvaluation,
         context: &PluginContext
     ) -> bool {
         // Wildcard selector matches anything
@@ -331,17 +1336,73 @@ impl AuthorizationPlugin {
             return true;
         }
         
-        // Validate file for selector checking
-        let html_content = match self.validate_file_for_selector_check(file_path, context) {
-            Ok(content) => content,
-            Err(_) => {
+        // Parse the file once per request
+        let document = match self.parsed_document(file_path, evaluation, context) {
+            Some(document) => document,
+            None => {
                 // Fallback to string comparison if file validation fails
                 return rule_selector == request_selector;
             }
         };
         
-        // Parse and compare selectors in DOM
-        self.compare_selectors_in_dom(rule_selector, request_selector, &html_content, context)
+        // Get elements matched by both selectors
+        let rule_elements = self.selector_matches(&document, rule_selector, file_path, evaluation);
+        let request_elements = self.selector_matches(&document, request_selector, file_path, evaluation);
+        
+        context.log_verbose(&format!(
+            "[Authorization] Rule selector '{}' matches {} elements",
+            rule_selector, rule_elements.len()
+        ));
+        context.log_verbose(&format!(
+            "[Authorization] Request selector '{}' matches {} elements",
+            request_selector, request_elements.len()
+        ));
+        
+        if require_exact_match {
+            return request_elements == rule_elements;
+        }
+        
+        // Check if request elements are a subset of rule elements
+        self.elements_are_subset(&request_elements, &rule_elements)
+    }
+    
+    /// Get the parsed document for a file, parsing it on first use in this request
+    fn parsed_document(
+        &self,
+        file_path: &str,
+        evaluation: &SelectorEvaluation,
+        context: &PluginContext
+    ) -> Option<Rc<Document>> {
+        if let Some(document) = evaluation.documents.borrow().get(file_path) {
+            return document.clone();
+        }
+        
+        let document = self.validate_file_for_selector_check(file_path, context)
+            .ok()
+            .map(|content| Rc::new(Document::from(content.as_str())));
+        evaluation.documents.borrow_mut().insert(file_path.to_string(), document.clone());
+        document
+    }
+    
+    /// Get the signatures of the elements a selector matches, evaluating it once per request
+    fn selector_matches(
+        &self,
+        document: &Document,
+        selector: &str,
+        file_path: &str,
+        evaluation: &SelectorEvaluation
+    ) -> ElementSignatures {
+        let key = (file_path.to_string(), selector.to_string());
+        if let Some(signatures) = evaluation.matches.borrow().get(&key) {
+            return Rc::clone(signatures);
+        }
+        
+        let signatures: ElementSignatures = Rc::new(document.select(selector)
+            .iter()
+            .map(|elem| elem.html().to_string())
+            .collect());
+        evaluation.matches.borrow_mut().insert(key, Rc::clone(&signatures));
+        signatures
     }
     
     /// Validate file exists and is suitable for selector checking
@@ -349,15 +1410,18 @@ impl AuthorizationPlugin {
         &self,
         file_path: &str,
         context: &PluginContext
-    ) -> Result<String, ()> {
+    ) -> Result<Arc<String>, ()> {
         // Check file exists
-        if !std::path::Path::new(file_path).exists() {
-            context.log_verbose(&format!(
-                "[Authorization] File not found for selector check: {}",
-                file_path
-            ));
-            return Err(());
-        }
+        let metadata = match fs::metadata(file_path) {
+            Ok(metadata) => metadata,
+            Err(_) => {
+                context.log_verbose(&format!(
+                    "[Authorization] File not found for selector check: {}",
+                    file_path
+                ));
+                return Err(());
+            }
+        };
         
         // Check if file is HTML
         if !self.is_html_file(file_path) {
@@ -367,21 +1431,35 @@ impl AuthorizationPlugin {
             return Err(());
         }
         
-        // Read file content
-        match std::fs::read_to_string(file_path) {
-            Ok(content) if !content.trim().is_empty() => Ok(content),
-            Ok(_) => {
-                context.log_verbose("[Authorization] Empty HTML file, skipping DOM parsing");
-                Err(())
-            }
-            Err(e) => {
-                context.log_verbose(&format!(
-                    "[Authorization] Failed to read file for selector check: {}",
-                    e
-                ));
-                Err(())
-            }
+        // Read file content, reusing a cached copy if the file is unchanged
+        let modified = metadata.modified().ok();
+        let cached = self.source_cache.lock().ok()
+            .and_then(|mut cache| cache.get(file_path, modified, metadata.len()));
+        let content = match cached {
+            Some(content) => content,
+            None => match fs::read_to_string(file_path) {
+                Ok(content) => {
+                    let content = Arc::new(content);
+                    if let Ok(mut cache) = self.source_cache.lock() {
+                        cache.insert(file_path, modified, metadata.len(), Arc::clone(&content));
+                    }
+                    content
+                }
+                Err(e) => {
+                    context.log_verbose(&format!(
+                        "[Authorization] Failed to read file for selector check: {}",
+                        e
+                    ));
+                    return Err(());
+                }
+            },
+        };
+        
+        if content.trim().is_empty() {
+            context.log_verbose("[Authorization] Empty HTML file, skipping DOM parsing");
+            return Err(());
         }
+        Ok(content)
     }
     
     /// Check if file has HTML extension
@@ -389,83 +1467,83 @@ impl AuthorizationPlugin {
         HTML_EXTENSIONS.iter().any(|ext| file_path.ends_with(ext))
     }
     
-    /// Compare selectors in parsed DOM
-    fn compare_selectors_in_dom(
-        &self,
-        rule_selector: &str,
-        request_selector: &str,
-        html_content: &str,
-        context: &PluginContext
-    ) -> bool {
-        let document = Document::from(html_content);
-        
-        // Get elements matched by both selectors
-        let rule_elements = document.select(rule_selector);
-        let request_elements = document.select(request_selector);
-        
-        context.log_verbose(&format!(
-            "[Authorization] Rule selector '{}' matches {} elements",
-            rule_selector, rule_elements.length()
-        ));
-        context.log_verbose(&format!(
-            "[Authorization] Request selector '{}' matches {} elements",
-            request_selector, request_elements.length()
-        ));
-        
-        // Check if request elements are a subset of rule elements
-        self.elements_are_subset(&request_elements, &rule_elements)
-    }
-    
     /// Check if a rule matches the request
+    #[allow(clippy::too_many_arguments)]
     fn rule_matches_request(
         &self,
         rule: &AuthorizationRule,
         username: &str,
         user_roles: &[String],
         request: &PluginRequest,
+        evaluation: &SelectorEvaluation,
         context: &PluginContext,
         check_method: Option<&str>
     ) -> Option<usize> {
+        self.match_rule(rule, username, user_roles, request, evaluation, context, check_method).ok()
+    }
+    
+    /// Match a rule against the request, returning its priority or the
+    /// first check it failed
+    #[allow(clippy::too_many_arguments)]
+    fn match_rule(
+        &self,
+        rule: &AuthorizationRule,
+        username: &str,
+        user_roles: &[String],
+        request: &PluginRequest,
+        evaluation: &SelectorEvaluation,
+        context: &PluginContext,
+        check_method: Option<&str>
+    ) -> Result<usize, RuleMismatch> {
+        // Expired rules no longer apply to anything
+        if rule.is_expired(Utc::now()) {
+            return Err(RuleMismatch::Expired);
+        }
+        
         // Check method match
         if !self.check_method_match(rule, check_method) {
-            return None;
+            return Err(RuleMismatch::Method);
         }
         
         // Check path match
         if !self.check_path_match(rule, request) {
-            return None;
+            return Err(RuleMismatch::Path);
+        }
+        
+        // Check header conditions
+        if !rule.required_headers.iter().all(|header| header.is_met(request.http_request.headers())) {
+            return Err(RuleMismatch::Header);
         }
         
         // Check selector compatibility
         if !self.check_selector_compatibility(rule, request) {
-            return None;
+            return Err(RuleMismatch::SelectorPresence);
         }
         
-        // Early check for username placeholder in selector with anonymous user
-        if let Some(selector) = &rule.selector {
-            if let Ok(regex) = Regex::new(USERNAME_PLACEHOLDER_PATTERN) {
+        // Early check for username placeholder in selectors with anonymous user
+        if let Ok(regex) = Regex::new(USERNAME_PLACEHOLDER_PATTERN) {
+            for selector in rule.selector.iter().chain(&rule.ownership_selector) {
                 if regex.is_match(selector) && (username == USERNAME_ANONYMOUS || username == USERNAME_WILDCARD) {
                     context.log_verbose(&format!(
                         "[Authorization] Skipping rule with username placeholder '{}' for anonymous user", 
                         selector
                     ));
-                    return None;
+                    return Err(RuleMismatch::AnonymousPlaceholder);
                 }
             }
         }
         
         // For OPTIONS requests or when selectors are present, validate selector match BEFORE calculating priority
         // This ensures rules with non-matching selectors are excluded from the applicable rules
-        if check_method.is_none() || (rule.selector.is_some() && self.extract_selector_from_request(request).is_some()) {
-            if !self.validate_selector_match(rule, request, context) {
-                return None;
-            }
+        if (check_method.is_none() || (rule.has_selector() && self.extract_selector_from_request(request).is_some()))
+            && !self.validate_selector_match(rule, request, evaluation, context)
+        {
+            return Err(RuleMismatch::Selector);
         }
         
         // Calculate priority based on user match
-        let priority = self.calculate_rule_priority(rule, username, user_roles)?;
-        
-        Some(priority)
+        self.calculate_rule_priority(rule, username, user_roles)
+            .ok_or(RuleMismatch::User)
     }
     
     /// Check if rule method matches request
@@ -473,7 +1551,7 @@ impl AuthorizationPlugin {
         match check_method {
             Some(method) => {
                 let method_upper = method.to_uppercase();
-                rule.methods.iter().any(|m| m.to_uppercase() == method_upper)
+                rule.methods.iter().any(|m| m == METHOD_WILDCARD || m.to_uppercase() == method_upper)
             }
             None => true
         }
@@ -487,7 +1565,7 @@ impl AuthorizationPlugin {
     /// Check selector compatibility between rule and request
     fn check_selector_compatibility(&self, rule: &AuthorizationRule, request: &PluginRequest) -> bool {
         let request_has_selector = self.extract_selector_from_request(request).is_some();
-        let rule_has_selector = rule.selector.is_some();
+        let rule_has_selector = rule.has_selector();
         
         // Both must have selectors or both must not have selectors
         request_has_selector == rule_has_selector
@@ -500,6 +1578,11 @@ impl AuthorizationPlugin {
         username: &str,
         user_roles: &[String]
     ) -> Option<usize> {
+        // Exclusions win over every way a rule can name a user
+        if rule.excludes(username, user_roles) {
+            return None;
+        }
+        
         if rule.username == username {
             Some(PRIORITY_EXACT_USERNAME)
         } else if rule.username == USERNAME_CURRENT {
@@ -557,51 +1640,132 @@ impl AuthorizationPlugin {
         &self,
         rule: &AuthorizationRule,
         request: &PluginRequest,
+        evaluation: &SelectorEvaluation,
         context: &PluginContext
     ) -> bool {
-        match (&rule.selector, self.extract_selector_from_request(request)) {
-            (Some(rule_selector), Some(request_selector)) => {
-                // Get authenticated user for placeholder replacement
-                let username = request.metadata.get("authenticated_user")
-                    .cloned()
-                    .unwrap_or_else(|| USERNAME_WILDCARD.to_string());
-                
-                // Replace ${username} placeholder in rule selector
-                let processed_rule_selector = match self.replace_username_placeholder(rule_selector, &username) {
-                    Some(selector) => selector,
-                    None => {
-                        // Rule contains username placeholder but user is anonymous - skip rule
-                        context.log_verbose(&format!(
-                            "[Authorization] Skipping rule with username placeholder '{}' for anonymous user", 
-                            rule_selector
-                        ));
-                        return false;
-                    }
-                };
-                
-                let file_path = self.construct_file_path(request, context);
-                let matches = self.check_selector_match(
-                    &processed_rule_selector,
-                    &request_selector,
-                    &file_path,
-                    context
-                );
-                
-                if !matches {
+        let request_selector = match self.extract_selector_from_request(request) {
+            Some(selector) if rule.has_selector() => selector,
+            _ => return true, // No selector validation needed
+        };
+        
+        // Get authenticated user for placeholder replacement
+        let username = request.metadata.get("authenticated_user")
+            .cloned()
+            .unwrap_or_else(|| USERNAME_WILDCARD.to_string());
+        // Without a file, selectors are compared as strings
+        let file_path = self.construct_file_path(request, context).unwrap_or_default();
+        
+        if let Some(rule_selector) = &rule.selector {
+            // Replace ${username} placeholder in rule selector
+            let processed_rule_selector = match self.replace_username_placeholder(rule_selector, &username) {
+                Some(selector) => selector,
+                None => {
+                    // Rule contains username placeholder but user is anonymous - skip rule
                     context.log_verbose(&format!(
-                        "[Authorization] Selector '{}' does not match rule selector '{}' (DOM-aware check)", 
-                        request_selector, processed_rule_selector
+                        "[Authorization] Skipping rule with username placeholder '{}' for anonymous user", 
+                        rule_selector
                     ));
+                    return false;
                 }
-                
-                matches
+            };
+            
+            let matches = self.check_selector_match(
+                &processed_rule_selector,
+                &request_selector,
+                rule.require_exact_match,
+                &file_path,
+                evaluation,
+                context
+            );
+            
+            if !matches {
+                context.log_verbose(&format!(
+                    "[Authorization] Selector '{}' does not match rule selector '{}' (DOM-aware check)", 
+                    request_selector, processed_rule_selector
+                ));
+                return false;
+            }
+        }
+        
+        if let Some(ownership_selector) = &rule.ownership_selector {
+            let processed_ownership_selector = match self.replace_username_placeholder(ownership_selector, &username) {
+                Some(selector) => selector,
+                None => {
+                    context.log_verbose(&format!(
+                        "[Authorization] Skipping rule with username placeholder '{}' for anonymous user", 
+                        ownership_selector
+                    ));
+                    return false;
+                }
+            };
+            
+            if !self.check_ownership(&processed_ownership_selector, &request_selector, &file_path, evaluation, context) {
+                context.log_verbose(&format!(
+                    "[Authorization] Elements selected by '{}' are not owned according to '{}'", 
+                    request_selector, processed_ownership_selector
+                ));
+                return false;
             }
-            _ => true // No selector validation needed
         }
+        
+        true
+    }
+    
+    /// Check that every element matched by the request selector carries its own ownership marker
+    ///
+    /// Ownership can only be established against the parsed document, so
+    /// requests selecting nothing and files that can't be DOM-checked fail.
+    /// A marker nested in another element's item does not make the selected
+    /// element owned, so a list holding one of the user's items is not theirs
+    /// (see `marks_owner`).
+    fn check_ownership(
+        &self,
+        ownership_selector: &str,
+        request_selector: &str,
+        file_path: &str,
+        evaluation: &SelectorEvaluation,
+        context: &PluginContext
+    ) -> bool {
+        let document = match self.parsed_document(file_path, evaluation, context) {
+            Some(document) => document,
+            None => return false,
+        };
+        
+        let elements = document.select(request_selector);
+        context.log_verbose(&format!(
+            "[Authorization] Checking ownership '{}' of {} elements",
+            ownership_selector, elements.length()
+        ));
+        
+        elements.exists() && elements.iter().all(|element| {
+            element.is(ownership_selector) || element.select(ownership_selector).nodes().iter()
+                .any(|marker| element.nodes().iter().any(|owner| Self::marks_owner(marker, owner)))
+        })
+    }
+    
+    /// Whether an ownership marker inside an element is the element's own
+    ///
+    /// The marker must be a direct child of the element or, when the element
+    /// is an `itemscope`, belong to its item: no `itemscope` may lie between
+    /// the two.
+    fn marks_owner(marker: &dom_query::NodeRef, owner: &dom_query::NodeRef) -> bool {
+        let mut direct = true;
+        let mut parent = marker.parent();
+        while let Some(node) = parent {
+            if node.id == owner.id {
+                return direct || owner.has_attr("itemscope");
+            }
+            if node.has_attr("itemscope") {
+                return false;
+            }
+            direct = false;
+            parent = node.parent();
+        }
+        false
     }
     
     /// Check if one set of elements is a subset of another
-    fn elements_are_subset(&self, subset: &Selection, superset: &Selection) -> bool {
+    fn elements_are_subset(&self, subset: &HashSet<String>, superset: &HashSet<String>) -> bool {
         // Empty subset is always valid
         if subset.is_empty() {
             return true;
@@ -612,24 +1776,8 @@ impl AuthorizationPlugin {
             return false;
         }
         
-        // Build set of superset element signatures
-        let superset_signatures = self.build_element_signature_set(superset);
-        
         // Check if all subset elements exist in superset
-        self.all_elements_in_set(subset, &superset_signatures)
-    }
-    
-    /// Build a set of element signatures for comparison
-    fn build_element_signature_set(&self, selection: &Selection) -> std::collections::HashSet<String> {
-        selection.iter()
-            .map(|elem| elem.html().to_string())
-            .collect()
-    }
-    
-    /// Check if all elements in selection exist in the signature set
-    fn all_elements_in_set(&self, selection: &Selection, signatures: &std::collections::HashSet<String>) -> bool {
-        selection.iter()
-            .all(|elem| signatures.contains(&elem.html().to_string()))
+        subset.is_subset(superset)
     }
     
     /// Get user's roles
@@ -653,7 +1801,7 @@ impl AuthorizationPlugin {
     
     /// Get all allowed methods for a user/path/selector combination
     fn get_allowed_methods(&self, username: &str, request: &PluginRequest, context: &PluginContext) -> Vec<String> {
-        let (users, rules) = match self.load_auth_config() {
+        let loaded = match self.load_auth_config() {
             Some(config) => config,
             None => {
                 context.log_verbose("[Authorization] Failed to load auth config for OPTIONS");
@@ -661,12 +1809,26 @@ impl AuthorizationPlugin {
             }
         };
         
-        let user_roles = self.get_user_roles(username, &users, &request.metadata);
+        self.allowed_methods_with_rules(username, &loaded.users, &loaded.rules, &loaded.policies, request, context)
+    }
+    
+    /// Get all allowed methods under an already loaded auth config
+    fn allowed_methods_with_rules(
+        &self,
+        username: &str,
+        users: &[User],
+        rules: &[AuthorizationRule],
+        policies: &[PathPolicy],
+        request: &PluginRequest,
+        context: &PluginContext
+    ) -> Vec<String> {
+        let user_roles = self.get_user_roles(username, users, &request.metadata);
         
         // Collect applicable rules with their priorities
+        let evaluation = SelectorEvaluation::default();
         let mut applicable_rules: Vec<(usize, &AuthorizationRule)> = rules.iter()
             .filter_map(|rule| {
-                self.rule_matches_request(rule, username, &user_roles, request, context, None)
+                self.rule_matches_request(rule, username, &user_roles, request, &evaluation, context, None)
                     .map(|priority| (priority, rule))
             })
             .collect();
@@ -675,7 +1837,20 @@ impl AuthorizationPlugin {
         applicable_rules.sort_by(|a, b| b.0.cmp(&a.0));
         
         // Process rules to determine allowed methods
-        let (allowed_methods, _) = self.process_rules_for_methods(&applicable_rules, context);
+        let (mut allowed_methods, denied_methods) = self.process_rules_for_methods(&applicable_rules, context);
+        
+        // Methods no rule decided take the path's default
+        for method in READ_METHODS.iter().chain(WRITE_METHODS) {
+            let method = method.to_string();
+            if allowed_methods.contains(&method) || denied_methods.contains(&method) {
+                continue;
+            }
+            if self.find_path_policy(policies, &request.path, &method)
+                .is_some_and(|policy| policy.action == Permission::Allow)
+            {
+                allowed_methods.insert(method);
+            }
+        }
         
         // Always include OPTIONS itself
         let mut result: Vec<String> = allowed_methods.into_iter().collect();
@@ -698,7 +1873,8 @@ impl AuthorizationPlugin {
     ) -> (std::collections::HashSet<String>, std::collections::HashSet<String>) {
         let mut allowed_methods = std::collections::HashSet::new();
         let mut denied_methods = std::collections::HashSet::new();
-        let mut methods_processed = std::collections::HashSet::new();
+        // Priority and action each method was decided with
+        let mut methods_processed: HashMap<String, (usize, Permission)> = HashMap::new();
         
         for (priority, rule) in applicable_rules {
             context.log_verbose(&format!(
@@ -706,15 +1882,23 @@ impl AuthorizationPlugin {
                 rule.username, rule.path, rule.methods, rule.action, priority
             ));
             
-            for method in &rule.methods {
+            // The wildcard marker is already expanded into the known methods
+            for method in rule.methods.iter().filter(|m| *m != METHOD_WILDCARD) {
                 let method_upper = method.to_uppercase();
                 
-                // Skip if we've already processed this method at a higher priority
-                if methods_processed.contains(&method_upper) {
-                    continue;
+                // Skip if we've already processed this method at a higher priority,
+                // or at the same priority unless the conflict strategy overrides it
+                if let Some((decided_priority, decided_action)) = methods_processed.get(&method_upper) {
+                    if decided_priority != priority || !self.conflict_strategy.overrides(decided_action, &rule.action) {
+                        continue;
+                    }
+                    context.log_verbose(&format!(
+                        "[Authorization] Tied rules for {} at priority {}, {:?} overrides {:?} ({:?})",
+                        method_upper, priority, rule.action, decided_action, self.conflict_strategy
+                    ));
                 }
                 
-                methods_processed.insert(method_upper.clone());
+                methods_processed.insert(method_upper.clone(), (*priority, rule.action.clone()));
                 
                 match rule.action {
                     Permission::Allow => {
@@ -733,18 +1917,20 @@ impl AuthorizationPlugin {
     }
     
     /// Check if user is authorized for the request
-    fn is_authorized(
+    /// 
+    /// Returns the rule that allowed the request, or explains the denial.
+    fn authorize(
         &self, 
         username: &str, 
         request: &PluginRequest, 
         method: &str, 
         context: &PluginContext
-    ) -> bool {
-        let (users, rules) = match self.load_auth_config() {
+    ) -> Result<AuthorizationRule, Denial> {
+        let LoadedAuthFiles { users, rules, policies, .. } = match self.load_auth_config() {
             Some(config) => config,
             None => {
                 context.log_verbose("[Authorization] Failed to load auth config, denying access");
-                return false;
+                return Err(Denial { code: DenialCode::ConfigUnavailable, rule: None });
             }
         };
         
@@ -763,9 +1949,12 @@ impl AuthorizationPlugin {
         match best_match {
             Some((_, rule)) => {
                 context.log_verbose(&format!(
-                    "[Authorization] Best match - User: {}, Path: {}, Selector: {:?}, Method: {}, Action: {:?}", 
-                    rule.username, rule.path, rule.selector, method, rule.action
+                    "[Authorization] Best match - Rule: {}, User: {}, Path: {}, Selector: {:?}, Method: {}, Action: {:?}", 
+                    rule.id.as_deref().unwrap_or("-"), rule.username, rule.path, rule.selector, method, rule.action
                 ));
+                if let Some(description) = &rule.description {
+                    context.log_verbose(&format!("[Authorization] Rule description: {}", description));
+                }
                 
                 let decision = rule.action == Permission::Allow;
                 context.log_verbose(&format!(
@@ -773,14 +1962,30 @@ impl AuthorizationPlugin {
                     username, request.path, method, if decision { "ALLOW" } else { "DENY" }
                 ));
                 
-                decision
+                if decision {
+                    Ok(rule.clone())
+                } else {
+                    Err(Denial { code: DenialCode::DeniedByRule, rule: Some(Box::new(rule.clone())) })
+                }
             }
             None => {
                 context.log_verbose(&format!(
                     "[Authorization] No matching rule found for user '{}' accessing '{}' with {}", 
                     username, request.path, method
                 ));
-                false
+                match self.find_path_policy(&policies, &request.path, method) {
+                    Some(policy) => {
+                        context.log_verbose(&format!(
+                            "[Authorization] Default action for '{}' with {} from path policy {}: {:?}",
+                            request.path, method, policy.path, policy.action
+                        ));
+                        match policy.action {
+                            Permission::Allow => Ok(policy.to_rule()),
+                            Permission::Deny => Err(Denial { code: DenialCode::NoMatchingRule, rule: Some(Box::new(policy.to_rule())) }),
+                        }
+                    }
+                    None => Err(Denial { code: DenialCode::NoMatchingRule, rule: None }),
+                }
             }
         }
     }
@@ -796,6 +2001,8 @@ impl AuthorizationPlugin {
         context: &PluginContext
     ) -> Option<(usize, &'a AuthorizationRule)> {
         let mut best_match: Option<(usize, &AuthorizationRule)> = None;
+        let mut tied_rules: Vec<&AuthorizationRule> = Vec::new();
+        let evaluation = SelectorEvaluation::default();
         
         for rule in rules {
             if let Some(priority) = self.rule_matches_request(
@@ -803,25 +2010,46 @@ impl AuthorizationPlugin {
                 username,
                 user_roles,
                 request,
+                &evaluation,
                 context,
                 Some(method)
             ) {
                 context.log_verbose(&format!(
-                    "[Authorization] Rule evaluated - User: {}, Path: {}, Selector: {:?}, Method: {}, Action: {:?}, Priority: {}", 
-                    rule.username, rule.path, rule.selector, method, rule.action, priority
+                    "[Authorization] Rule evaluated - Rule: {}, User: {}, Path: {}, Selector: {:?}, Method: {}, Action: {:?}, Priority: {}", 
+                    rule.id.as_deref().unwrap_or("-"), rule.username, rule.path, rule.selector, method, rule.action, priority
                 ));
                 
                 match best_match {
-                    None => best_match = Some((priority, rule)),
-                    Some((best_priority, _)) => {
-                        if priority > best_priority {
+                    Some((best_priority, best_rule)) if priority == best_priority => {
+                        tied_rules.push(rule);
+                        if self.conflict_strategy.overrides(&best_rule.action, &rule.action) {
                             best_match = Some((priority, rule));
                         }
                     }
+                    Some((best_priority, _)) if priority < best_priority => {}
+                    _ => {
+                        best_match = Some((priority, rule));
+                        tied_rules = vec![rule];
+                    }
                 }
             }
         }
         
+        if tied_rules.len() > 1 {
+            for rule in &tied_rules {
+                context.log_verbose(&format!(
+                    "[Authorization] Tied rule - Rule: {}, User: {}, Path: {}, Selector: {:?}, Action: {:?}",
+                    rule.id.as_deref().unwrap_or("-"), rule.username, rule.path, rule.selector, rule.action
+                ));
+            }
+            if let Some((_, rule)) = best_match {
+                context.log_verbose(&format!(
+                    "[Authorization] Resolved {} tied rules with {:?}: {:?}",
+                    tied_rules.len(), self.conflict_strategy, rule.action
+                ));
+            }
+        }
+        
         best_match
     }
     
@@ -835,37 +2063,72 @@ impl AuthorizationPlugin {
             .unwrap_or_else(|| ".".to_string())
     }
     
-    /// Normalize path to handle index files
-    fn normalize_path(&self, path: &str) -> String {
-        if path == "/" {
-            "/index.html".to_string()
-        } else if path.ends_with('/') {
-            format!("{}/index.html", path.trim_end_matches('/'))
-        } else {
-            path.to_string()
-        }
-    }
-    
-    /// Construct file path from request
-    fn construct_file_path(&self, request: &PluginRequest, context: &PluginContext) -> String {
+    /// Construct file path from request, or None if it has no file below the host root
+    fn construct_file_path(&self, request: &PluginRequest, context: &PluginContext) -> Option<String> {
         let host_root = self.get_host_root(request, context);
-        let normalized_path = self.normalize_path(&request.path);
-        
-        let file_path = format!("{}{}", host_root, normalized_path);
-        context.log_verbose(&format!("[Authorization] Constructed file path: {} (host_root: {}, path: {})", 
-            file_path, host_root, normalized_path));
-        file_path
+        match paths::request_file_path(request, context, &host_root) {
+            Ok(file_path) => {
+                context.log_verbose(&format!("[Authorization] Constructed file path: {} (host_root: {}, path: {})", 
+                    file_path.display(), host_root, request.path));
+                Some(file_path.to_string_lossy().into_owned())
+            }
+            Err(e) => {
+                context.log_verbose(&format!("[Authorization] No file for path {}: {}", request.path, e));
+                None
+            }
+        }
     }
     
-    /// Create access denied response
-    fn create_access_denied(&self, user: &str, resource: &str, method: &str) -> Response<Body> {
-        Response::builder()
+    /// Create access denied response, as JSON if the client prefers it
+    fn create_access_denied(
+        &self,
+        request: &PluginRequest,
+        user: &str,
+        method: &str,
+        denial: &Denial,
+        context: &PluginContext
+    ) -> Response<Body> {
+        let mut builder = Response::builder()
             .status(StatusCode::FORBIDDEN)
-            .header(CONTENT_TYPE, "text/html")
+            .header(VARY, "Accept");
+        
+        if context.verbose || self.denied_rule_header {
+            let description = match &denial.rule {
+                Some(rule) => rule.describe(),
+                None => format!("none ({})", denial.code.as_str()),
+            };
+            // Rules with characters a header can't carry are left out
+            if let Ok(value) = hyper::header::HeaderValue::from_str(&description) {
+                builder = builder.header(DENIED_RULE_HEADER, value);
+            }
+        }
+        
+        if request.prefers_json() {
+            let body = json!({
+                "error": "forbidden",
+                "code": denial.code.as_str(),
+                "message": format!("User '{}' does not have permission to {} '{}'", user, method, request.path),
+                "user": user,
+                "missing_permission": {
+                    "method": method,
+                    "path": request.path,
+                    "selector": self.extract_selector_from_request(request),
+                },
+                "rule": denial.rule.as_ref().map(|rule| rule.to_json()),
+                "documentation_url": self.documentation_url,
+            });
+            return builder
+                .header(CONTENT_TYPE, CONTENT_TYPE_JSON)
+                .body(Body::from(body.to_string()))
+                .unwrap();
+        }
+        
+        builder
+            .header(CONTENT_TYPE, CONTENT_TYPE_HTML)
             .body(Body::from(ACCESS_DENIED_HTML
-                .replace("{}", user)
-                .replace("{}", method)
-                .replace("{}", resource)))
+                .replace("{user}", &html_escape(user))
+                .replace("{method}", &html_escape(method))
+                .replace("{resource}", &html_escape(&request.path))))
             .unwrap()
     }
 }
@@ -875,14 +2138,199 @@ impl Plugin for AuthorizationPlugin {
     async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
         let method = request.http_request.method().as_str().to_string();
         
+        // Serve the administration page to administrators
+        if method == "GET" && self.admin_path.as_deref() == Some(request.path.as_str()) {
+            return Some(self.handle_admin_request(request, context).into());
+        }
+        
+        // Answer what the current user may do on a resource
+        if method == "GET" && self.permissions_path.as_deref() == Some(request.path.as_str()) {
+            return Some(self.handle_permissions_request(request, context).into());
+        }
+        
+        // Evaluate the rules for a described request, once the caller may
+        if method == "POST" && self.rule_test_path.as_deref() == Some(request.path.as_str()) {
+            if let Some(denied) = self.handle_authorization_check(request, &method, context) {
+                return Some(denied.into());
+            }
+            return Some(self.handle_rule_test_request(request, context).await.into());
+        }
+        
+        // List expired and expiring rules, once the caller may
+        if method == "GET" && self.expiry_report_path.as_deref() == Some(request.path.as_str()) {
+            if let Some(denied) = self.handle_authorization_check(request, &method, context) {
+                return Some(denied.into());
+            }
+            return Some(self.handle_expiry_report_request(request, context).into());
+        }
+        
         // Handle OPTIONS requests for method discovery
         if method == "OPTIONS" {
             return Some(self.handle_options_request(request, context).await.into());
         }
         
         // Handle authorization check for other methods
-        self.handle_authorization_check(request, &method, context)
-            .map(|response| response.into())
+        if let Some(denied) = self.handle_authorization_check(request, &method, context) {
+            return Some(denied.into());
+        }
+        
+        // Check what a write to an auth file would leave behind
+        if self.admin_path.is_some() && WRITE_METHODS.contains(&method.as_str()) {
+            return self.check_auth_file_edit(request, context).await.map(|refused| refused.into());
+        }
+        if self.admin_path.is_some() && RELOCATE_METHODS.contains(&method.as_str()) {
+            return self.check_auth_file_relocation(request, context).map(|refused| refused.into());
+        }
+        None
+    }
+    
+    async fn handle_response(&self, request: &PluginRequest, response: &mut Response<Body>, context: &PluginContext) {
+        let method = request.http_request.method();
+        if (method != Method::GET && method != Method::HEAD) || !response.status().is_success() || !is_html_response(response) {
+            return;
+        }
+        // A request whose rules could not be loaded was denied already
+        let Some(loaded) = self.load_auth_config() else { return };
+        if loaded.redactions.is_empty() {
+            return;
+        }
+        let user = request.metadata.get("authenticated_user")
+            .cloned()
+            .unwrap_or_else(|| USERNAME_WILDCARD.to_string());
+        let redactions = self.redactions_for(&user, &loaded, request, context);
+        if redactions.is_empty() {
+            return;
+        }
+        // The headers of a HEAD describe the document as it would be sent
+        // unredacted, and there is no body to redact them by
+        if method == Method::HEAD {
+            for name in UNREDACTED_HEADERS.iter().chain(&["content-length"]) {
+                response.headers_mut().remove(*name);
+            }
+            response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("private"));
+            return;
+        }
+        
+        // Content that cannot be redacted is withheld rather than sent whole
+        if response.headers().contains_key(CONTENT_ENCODING) {
+            log_error!(self.name, context, "Cannot redact an encoded response"; path = request.path);
+            *response = redaction_failed_response();
+            return;
+        }
+        let mut failure = None;
+        let redacted = transform_body(response, |bytes| {
+            match std::str::from_utf8(bytes).map_err(|e| e.to_string()).and_then(|html| redact_html(html, &redactions)) {
+                Ok(redacted) => redacted,
+                Err(e) => {
+                    failure = Some(e);
+                    None
+                }
+            }
+        }).await;
+        match (redacted, failure) {
+            (Ok(true), _) => {
+                // The body now depends on who asked for it
+                for name in UNREDACTED_HEADERS {
+                    response.headers_mut().remove(*name);
+                }
+                response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_static("private"));
+                context.log_verbose(&format!(
+                    "[Authorization] Redacted response to '{}' for user '{}'", request.path, user
+                ));
+            }
+            (Ok(false), None) => {}
+            (Ok(false), Some(e)) => {
+                log_error!(self.name, context, "Failed to redact response: {}", e; path = request.path);
+                *response = redaction_failed_response();
+            }
+            (Err(e), _) => {
+                log_error!(self.name, context, "Failed to read response body: {}", e; path = request.path);
+                *response = redaction_failed_response();
+            }
+        }
+    }
+    
+    fn check_access(&self, user: Option<&str>, method: &str, path: &str, context: &PluginContext) -> Option<bool> {
+        Some(self.check_access_to(user, method, path, None, context))
+    }
+    
+    fn check_selector_access(
+        &self,
+        user: Option<&str>,
+        method: &str,
+        path: &str,
+        selector: &str,
+        context: &PluginContext,
+    ) -> Option<bool> {
+        Some(self.check_access_to(user, method, path, Some(selector), context))
+    }
+    
+    fn withheld_selectors(&self, user: Option<&str>, path: &str, context: &PluginContext) -> Vec<String> {
+        let Some(loaded) = self.load_auth_config() else {
+            return vec![WITHHOLD_ALL.to_string()];
+        };
+        let Some(request) = stand_in_request(Method::GET.as_str(), path, None) else {
+            return vec![WITHHOLD_ALL.to_string()];
+        };
+        let username = user.unwrap_or(USERNAME_WILDCARD);
+        let mut selectors: Vec<String> = self.redactions_for(username, &loaded, &request, context)
+            .into_iter()
+            .map(|(selector, _)| selector)
+            .collect();
+        
+        // Elements of selector rules on the path that the user may not GET
+        let now = Utc::now();
+        for rule in loaded.rules.iter().filter(|rule| !rule.is_expired(now)
+            && self.check_method_match(rule, Some(Method::GET.as_str()))
+            && self.check_path_match(rule, &request))
+        {
+            let Some(selector) = rule.selector.as_deref()
+                .and_then(|selector| self.replace_username_placeholder(selector, username))
+            else {
+                continue;
+            };
+            if !selectors.contains(&selector) && !self.check_access_to(user, Method::GET.as_str(), path, Some(&selector), context) {
+                selectors.push(selector);
+            }
+        }
+        selectors
+    }
+    
+    fn validate_config(&self, _context: &PluginContext) -> Vec<ConfigIssue> {
+        if self.auth_files.is_empty() {
+            return vec![ConfigIssue::error("no authfile is configured, so every request is denied")];
+        }
+        let unreadable: Vec<ConfigIssue> = self.auth_files.iter()
+            .filter_map(|auth_file| check_readable_file("authfile", auth_file))
+            .collect();
+        if !unreadable.is_empty() {
+            return unreadable;
+        }
+        match self.read_auth_files() {
+            Err(e) => vec![ConfigIssue::error(format!("{}, so every request is denied", e))],
+            Ok(loaded) => {
+                let mut issues: Vec<ConfigIssue> = loaded.problems.into_iter().map(ConfigIssue::warning).collect();
+                let now = Utc::now();
+                let rules = loaded.rules.iter().chain(loaded.redactions.iter().map(|redaction| &redaction.rule));
+                for rule in rules.filter(|rule| rule.is_expired(now)) {
+                    issues.push(ConfigIssue::warning(format!(
+                        "authorization rule {} expired at {} and is ignored",
+                        rule.label(), rule.expires.map(format_time).unwrap_or_default(),
+                    )));
+                }
+                if loaded.rules.is_empty() && loaded.policies.is_empty() {
+                    issues.push(ConfigIssue::warning(format!(
+                        "authfile {} has no authorization rules, so every request is denied",
+                        self.auth_files.join(", "),
+                    )));
+                }
+                issues
+            }
+        }
+    }
+    
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::new(&[capabilities::AUTHORIZATION], &[capabilities::AUTHENTICATION])
     }
     
     fn name(&self) -> &str {
@@ -936,6 +2384,487 @@ impl AuthorizationPlugin {
             .unwrap()
     }
     
+    /// Answer an effective permissions query
+    /// 
+    /// `?path=/page.html` reports the methods the current user may use on the
+    /// whole resource and every rule selector on it they may write to.
+    /// `&selector=...` reports the methods for that one selector instead.
+    fn handle_permissions_request(&self, request: &PluginRequest, context: &PluginContext) -> Response<Body> {
+        let query: HashMap<String, String> = request.http_request.uri().query()
+            .map(|query| query.split('&')
+                .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+                .map(|(key, value)| (
+                    decode_query_component(key),
+                    decode_query_component(value),
+                ))
+                .collect())
+            .unwrap_or_default();
+        
+        let path = match query.get(PERMISSIONS_PATH_PARAM) {
+            Some(path) if path.starts_with('/') => path.clone(),
+            _ => return json_response(StatusCode::BAD_REQUEST, json!({
+                "error": "invalid_path",
+                "message": "The path parameter must be an absolute path",
+            })),
+        };
+        
+        let user = request.metadata.get("authenticated_user")
+            .cloned()
+            .unwrap_or_else(|| USERNAME_WILDCARD.to_string());
+        let LoadedAuthFiles { users, rules, policies, .. } = match self.load_auth_config() {
+            Some(config) => config,
+            None => {
+                context.log_verbose("[Authorization] Failed to load auth config for permissions query");
+                return json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({
+                    "error": DenialCode::ConfigUnavailable.as_str(),
+                }));
+            }
+        };
+        
+        let query_selector = query.get(PERMISSIONS_SELECTOR_PARAM).filter(|s| !s.is_empty());
+        let Some(target) = self.permissions_query_request(request, &path, query_selector.map(String::as_str)) else {
+            return json_response(StatusCode::BAD_REQUEST, json!({
+                "error": "invalid_path",
+                "message": "The path parameter is not a valid path",
+            }));
+        };
+        let methods = self.allowed_methods_with_rules(&user, &users, &rules, &policies, &target, context);
+        
+        // Selectors the user may write to, as named by the rules for this path
+        let mut selectors = Vec::new();
+        if query_selector.is_none() {
+            let user_roles = self.get_user_roles(&user, &users, &request.metadata);
+            let mut seen = HashSet::new();
+            let now = Utc::now();
+            let candidates = rules.iter()
+                .filter(|rule| !rule.is_expired(now))
+                .filter(|rule| self.path_matches(&path, &rule.path))
+                .filter(|rule| self.calculate_rule_priority(rule, &user, &user_roles).is_some())
+                .filter_map(|rule| rule.selector.as_deref())
+                .filter_map(|selector| self.replace_username_placeholder(selector, &user));
+            for selector in candidates {
+                if !seen.insert(selector.clone()) {
+                    continue;
+                }
+                let Some(target) = self.permissions_query_request(request, &path, Some(&selector)) else { continue };
+                let selector_methods = self.allowed_methods_with_rules(&user, &users, &rules, &policies, &target, context);
+                if selector_methods.iter().any(|method| WRITE_METHODS.contains(&method.as_str())) {
+                    selectors.push(json!({ "selector": selector, "methods": selector_methods }));
+                }
+            }
+        }
+        
+        context.log_verbose(&format!(
+            "[Authorization] Permissions for user '{}' on '{}': {:?}, {} writable selectors",
+            user, path, methods, selectors.len()
+        ));
+        
+        let mut body = json!({
+            "user": if user == USERNAME_WILDCARD { None } else { Some(&user) },
+            "path": path,
+            "methods": methods,
+        });
+        match query_selector {
+            Some(selector) => body["selector"] = json!(selector),
+            None => body["selectors"] = json!(selectors),
+        }
+        json_response(StatusCode::OK, body)
+    }
+    
+    /// A stand-in request for a permissions query, carrying the querying
+    /// request's metadata and extensions so roles from authentication
+    /// plugins apply
+    fn permissions_query_request(&self, request: &PluginRequest, path: &str, selector: Option<&str>) -> Option<PluginRequest> {
+        let mut target = stand_in_request("GET", path, selector)?;
+        target.metadata = request.metadata.clone();
+        target.extensions = request.extensions.clone();
+        Some(target)
+    }
+    
+    /// Answer a rule test with the evaluation of the request it describes
+    async fn handle_rule_test_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Response<Body> {
+        let test = match request.get_body().await {
+            Ok(body) => serde_json::from_slice::<RuleTest>(&body).map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        let test = match test {
+            Ok(test) => test,
+            Err(e) => return json_response(StatusCode::BAD_REQUEST, json!({
+                "error": "invalid_body",
+                "message": format!("Expected a JSON object with at least a path: {}", e),
+            })),
+        };
+        
+        let loaded = match self.load_auth_config() {
+            Some(config) => config,
+            None => {
+                context.log_verbose("[Authorization] Failed to load auth config for rule test");
+                return json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({
+                    "error": DenialCode::ConfigUnavailable.as_str(),
+                }));
+            }
+        };
+        
+        let tester = request.metadata.get("authenticated_user").map(String::as_str).unwrap_or(USERNAME_WILDCARD);
+        log_info!(self.name, context, "Rule test";
+            user = tester, tested_user = test.user.as_deref().unwrap_or(USERNAME_WILDCARD), path = test.path);
+        self.rule_test_report(&test, &loaded.users, &loaded.rules, &loaded.policies, context)
+    }
+    
+    /// Evaluate the rules for a rule test's request the way a real request
+    /// would be, tracing what each rule made of it
+    fn rule_test_report(
+        &self,
+        test: &RuleTest,
+        users: &[User],
+        rules: &[AuthorizationRule],
+        policies: &[PathPolicy],
+        context: &PluginContext,
+    ) -> Response<Body> {
+        let invalid_path = || json_response(StatusCode::BAD_REQUEST, json!({
+            "error": "invalid_path",
+            "message": "The path must be a valid absolute path",
+        }));
+        let Ok(path) = paths::normalize_request_path(&test.path) else {
+            return invalid_path();
+        };
+        let method = test.method.as_deref().unwrap_or("GET").trim().to_uppercase();
+        let selector = test.selector.as_deref().filter(|selector| !selector.is_empty());
+        let Some(mut target) = stand_in_request(&method, &path, selector) else {
+            return invalid_path();
+        };
+        for (name, value) in &test.headers {
+            let header = hyper::header::HeaderName::from_bytes(name.as_bytes()).ok()
+                .zip(HeaderValue::from_str(value).ok());
+            let Some((name, value)) = header else {
+                return json_response(StatusCode::BAD_REQUEST, json!({
+                    "error": "invalid_header",
+                    "message": format!("The header {} is not a valid HTTP header", name),
+                }));
+            };
+            target.http_request.headers_mut().append(name, value);
+        }
+        
+        let user = test.user.clone()
+            .filter(|user| !user.is_empty())
+            .unwrap_or_else(|| USERNAME_WILDCARD.to_string());
+        let user_roles = match &test.roles {
+            Some(roles) => roles.clone(),
+            None => self.get_user_roles(&user, users, &HashMap::new()),
+        };
+        
+        let evaluation = SelectorEvaluation::default();
+        let trace: Vec<serde_json::Value> = rules.iter().enumerate()
+            .map(|(index, rule)| {
+                let mut step = json!({ "index": index, "rule": rule.to_json() });
+                match self.match_rule(rule, &user, &user_roles, &target, &evaluation, context, Some(&method)) {
+                    Ok(priority) => step["priority"] = json!(priority),
+                    Err(mismatch) => step["skipped"] = json!(mismatch.as_str()),
+                }
+                step
+            })
+            .collect();
+        
+        let best_match = self.find_best_matching_rule(rules, &user, &user_roles, &target, &method, context);
+        // The path's default decides only when no rule does
+        let policy = match best_match {
+            Some(_) => None,
+            None => self.find_path_policy(policies, &path, &method),
+        };
+        let (decision, code) = match (best_match, policy) {
+            (Some((_, rule)), _) if rule.action == Permission::Allow => (Permission::Allow, None),
+            (Some(_), _) => (Permission::Deny, Some(DenialCode::DeniedByRule)),
+            (None, Some(policy)) if policy.action == Permission::Allow => (Permission::Allow, None),
+            (None, _) => (Permission::Deny, Some(DenialCode::NoMatchingRule)),
+        };
+        
+        json_response(StatusCode::OK, json!({
+            "user": if user == USERNAME_WILDCARD { None } else { Some(&user) },
+            "roles": user_roles,
+            "method": method,
+            "path": path,
+            "selector": selector,
+            "decision": decision.as_str(),
+            "code": code.map(|code| code.as_str()),
+            "rule": best_match.map(|(_, rule)| rule.to_json()),
+            "index": best_match.and_then(|(_, rule)| rules.iter().position(|candidate| std::ptr::eq(candidate, rule))),
+            "priority": best_match.map(|(priority, _)| priority),
+            "policy": policy.map(|policy| json!({
+                "id": policy.id,
+                "path": policy.path,
+                "methods": policy.methods,
+                "defaultAction": policy.action.as_str(),
+            })),
+            "trace": trace,
+        }))
+    }
+    
+    /// Answer an expiry report: the rules that have expired and those
+    /// expiring within `expiry_warning_days`, or the `days` given, soonest
+    /// first
+    fn handle_expiry_report_request(&self, request: &PluginRequest, context: &PluginContext) -> Response<Body> {
+        let days = match request.http_request.uri().query()
+            .and_then(|query| query.split('&').find_map(|pair| pair.strip_prefix(EXPIRY_REPORT_DAYS_PARAM)?.strip_prefix('=')))
+        {
+            Some(days) => match days.parse::<i64>() {
+                Ok(days) if (0..=36500).contains(&days) => days,
+                _ => return json_response(StatusCode::BAD_REQUEST, json!({
+                    "error": "invalid_days",
+                    "message": "The days parameter must be a number of days",
+                })),
+            },
+            None => self.expiry_warning_days,
+        };
+        let Some(loaded) = self.load_auth_config() else {
+            context.log_verbose("[Authorization] Failed to load auth config for expiry report");
+            return json_response(StatusCode::INTERNAL_SERVER_ERROR, json!({
+                "error": DenialCode::ConfigUnavailable.as_str(),
+            }));
+        };
+        
+        let now = Utc::now();
+        let warning = now + TimeDelta::days(days);
+        let mut rules: Vec<&AuthorizationRule> = loaded.rules.iter()
+            .chain(loaded.redactions.iter().map(|redaction| &redaction.rule))
+            .filter(|rule| rule.expires.is_some_and(|expires| expires <= warning))
+            .collect();
+        rules.sort_by_key(|rule| rule.expires);
+        let (expired, expiring): (Vec<&AuthorizationRule>, Vec<&AuthorizationRule>) = rules.into_iter()
+            .partition(|rule| rule.is_expired(now));
+        
+        context.log_verbose(&format!(
+            "[Authorization] Expiry report for {} days: {} expired, {} expiring",
+            days, expired.len(), expiring.len()
+        ));
+        json_response(StatusCode::OK, json!({
+            "now": format_time(now),
+            "days": days,
+            "expired": expired.iter().map(|rule| rule.to_json()).collect::<Vec<_>>(),
+            "expiring": expiring.iter().map(|rule| rule.to_json()).collect::<Vec<_>>(),
+        }))
+    }
+    
+    /// Decide an access check made outside a request, optionally for the
+    /// elements a selector picks
+    fn check_access_to(
+        &self,
+        user: Option<&str>,
+        method: &str,
+        path: &str,
+        selector: Option<&str>,
+        context: &PluginContext,
+    ) -> bool {
+        let user = user.unwrap_or(USERNAME_WILDCARD);
+        let Some(mut request) = stand_in_request(method, path, selector) else {
+            context.log_verbose(&format!("[Authorization] Denying access check for invalid path '{}'", path));
+            return false;
+        };
+        // Rule selectors fill in `${username}` from the request
+        if user != USERNAME_WILDCARD {
+            request.metadata.insert("authenticated_user".to_string(), user.to_string());
+        }
+        
+        let allowed = self.authorize(user, &request, method, context).is_ok();
+        context.log_verbose(&format!(
+            "[Authorization] Access check for user '{}' on '{}'{} with {}: {}",
+            user,
+            path,
+            selector.map(|selector| format!(" selector '{}'", selector)).unwrap_or_default(),
+            method,
+            if allowed { "ALLOW" } else { "DENY" }
+        ));
+        allowed
+    }
+    
+    /// Serve the administration page, to users with the admin role only
+    fn handle_admin_request(&self, request: &PluginRequest, context: &PluginContext) -> Response<Body> {
+        let user = request.metadata.get("authenticated_user")
+            .cloned()
+            .unwrap_or_else(|| USERNAME_WILDCARD.to_string());
+        let Some(loaded) = self.load_auth_config() else {
+            let denial = Denial { code: DenialCode::ConfigUnavailable, rule: None };
+            return self.create_access_denied(request, &user, "GET", &denial, context);
+        };
+        
+        let user_roles = self.get_user_roles(&user, &loaded.users, &request.metadata);
+        if user == USERNAME_WILDCARD || !user_roles.contains(&self.admin_role) {
+            log_info!(self.name, context, "Access denied";
+                user = user, method = "GET", path = request.path, rule = DenialCode::AdminRoleRequired.as_str());
+            let denial = Denial { code: DenialCode::AdminRoleRequired, rule: None };
+            return self.create_access_denied(request, &user, "GET", &denial, context);
+        }
+        
+        let host_root = fs::canonicalize(self.get_host_root(request, context)).ok();
+        let files: Vec<AdminFile> = loaded.files.iter()
+            .map(|file| AdminFile {
+                path: file.display().to_string(),
+                url: host_root.as_deref().and_then(|root| auth_file_url(root, file)),
+                content: fs::read_to_string(file).unwrap_or_default(),
+            })
+            .collect();
+        log_info!(self.name, context, "Administration page served"; user = user, files = files.len());
+        
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(CACHE_CONTROL, "no-store")
+            .body(Body::from(admin::render_page(&files, &user)))
+            .unwrap()
+    }
+    
+    /// Refuse an authorized write to an auth file that would leave it
+    /// without valid microdata, or leave no administrator able to edit the
+    /// auth files
+    async fn check_auth_file_edit(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<Response<Body>> {
+        let file = fs::canonicalize(self.construct_file_path(request, context)?).ok()?;
+        let loaded = self.load_auth_config()?;
+        if !loaded.files.contains(&file) {
+            return None;
+        }
+        
+        let path = request.path.clone();
+        let refuse = |message: String| {
+            log_info!(self.name, context, "Auth file edit refused: {}", message; path = path);
+            Some(json_response(StatusCode::UNPROCESSABLE_ENTITY, json!({
+                "error": "invalid_auth_file_edit",
+                "message": message,
+            })))
+        };
+        // Uploads and version restores write other content than the body
+        let is_upload = request.http_request.headers().get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("multipart/form-data"));
+        let edit = match Edit::of(request.http_request.method(), self.extract_selector_from_request(request)) {
+            Some(edit) if !is_upload && request.http_request.uri().query().is_none() => edit,
+            _ => return refuse(format!(
+                "a {} of an auth file can't be checked before it is made", request.http_request.method()
+            )),
+        };
+        let body = if edit.needs_body() {
+            match request.get_body_string().await {
+                Ok(body) => body,
+                Err(e) => return refuse(e),
+            }
+        } else {
+            String::new()
+        };
+        let current = match fs::read_to_string(&file) {
+            Ok(current) => current,
+            Err(e) => return refuse(format!("auth file {} cannot be read: {}", file.display(), e)),
+        };
+        let content = match edit.apply(&current, &body) {
+            Ok(content) => content,
+            Err(e) => return refuse(e),
+        };
+        let edited = match self.read_edited_auth_files(Some((&file, &content))) {
+            Ok(edited) => edited,
+            Err(e) => return refuse(e),
+        };
+        
+        if let Err(message) = self.check_administrators_remain(request, &edited, context) {
+            log_info!(self.name, context, "Auth file edit refused: {}", message; path = request.path);
+            return Some(json_response(StatusCode::CONFLICT, json!({
+                "error": "administrator_lockout",
+                "message": message,
+            })));
+        }
+        None
+    }
+    
+    /// Refuse a COPY or MOVE onto an auth file, or a MOVE of one, as neither
+    /// can be checked before it is made
+    fn check_auth_file_relocation(&self, request: &PluginRequest, context: &PluginContext) -> Option<Response<Body>> {
+        let loaded = self.load_auth_config()?;
+        let holds_auth_file = |path: &std::path::Path| loaded.files.iter().any(|file| file.starts_with(path));
+        
+        let method = request.http_request.method().as_str();
+        let source = self.construct_file_path(request, context).and_then(|source| fs::canonicalize(source).ok());
+        let moves_auth_file = method == "MOVE" && source.as_deref().is_some_and(holds_auth_file);
+        
+        // The file handler refuses destinations it cannot resolve
+        let destination = request.http_request.headers().get("Destination")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.trim().parse::<hyper::Uri>().ok())
+            .and_then(|uri| paths::normalize_request_path(uri.path()).ok())
+            .and_then(|path| paths::resolve(
+                std::path::Path::new(&self.get_host_root(request, context)),
+                &path,
+                paths::SymlinkPolicy::from_context(context),
+            ).ok());
+        let replaces_auth_file = destination.as_deref()
+            .map(|destination| fs::canonicalize(destination).unwrap_or_else(|_| destination.to_path_buf()))
+            .is_some_and(|destination| holds_auth_file(&destination));
+        
+        if !moves_auth_file && !replaces_auth_file {
+            return None;
+        }
+        let message = format!("a {} of or onto an auth file can't be checked before it is made", method);
+        log_info!(self.name, context, "Auth file edit refused: {}", message; path = request.path);
+        Some(json_response(StatusCode::UNPROCESSABLE_ENTITY, json!({
+            "error": "invalid_auth_file_edit",
+            "message": message,
+        })))
+    }
+    
+    /// Check that some user with the admin role would still be allowed to
+    /// replace each auth file under the host root with the edited rules
+    fn check_administrators_remain(
+        &self,
+        request: &PluginRequest,
+        edited: &LoadedAuthFiles,
+        context: &PluginContext,
+    ) -> Result<(), String> {
+        let Some(host_root) = fs::canonicalize(self.get_host_root(request, context)).ok() else { return Ok(()) };
+        let urls: Vec<String> = edited.files.iter()
+            .filter_map(|file| auth_file_url(&host_root, file))
+            .collect();
+        
+        // Credentials with the role, and the user making the edit if an
+        // authentication plugin gives it to them
+        let mut administrators: Vec<(String, Vec<String>)> = edited.users.iter()
+            .filter(|user| user.roles.contains(&self.admin_role))
+            .map(|user| (user.username.clone(), user.roles.clone()))
+            .collect();
+        if let Some(user) = request.metadata.get("authenticated_user") {
+            let roles = self.get_user_roles(user, &edited.users, &request.metadata);
+            if roles.contains(&self.admin_role) && !administrators.iter().any(|(name, _)| name == user) {
+                administrators.push((user.clone(), roles));
+            }
+        }
+        if administrators.is_empty() {
+            return Err(format!("the edit would leave no user with the {} role", self.admin_role));
+        }
+        
+        let keeps_access = administrators.iter().any(|(user, roles)| {
+            urls.iter().all(|url| self.rules_allow(edited, user, roles, "PUT", url, context))
+        });
+        if !keeps_access {
+            return Err(format!(
+                "the edit would leave no user with the {} role allowed to PUT {}",
+                self.admin_role, urls.join(", ")
+            ));
+        }
+        Ok(())
+    }
+    
+    /// Whether a set of rules and policies allows a user a whole-resource request
+    fn rules_allow(
+        &self,
+        loaded: &LoadedAuthFiles,
+        user: &str,
+        user_roles: &[String],
+        method: &str,
+        path: &str,
+        context: &PluginContext,
+    ) -> bool {
+        let Some(target) = stand_in_request(method, path, None) else { return false };
+        match self.find_best_matching_rule(&loaded.rules, user, user_roles, &target, method, context) {
+            Some((_, rule)) => rule.action == Permission::Allow,
+            None => self.find_path_policy(&loaded.policies, path, method)
+                .is_some_and(|policy| policy.action == Permission::Allow),
+        }
+    }
+    
     /// Handle authorization check for non-OPTIONS requests
     fn handle_authorization_check(
         &self,
@@ -967,12 +2896,27 @@ impl AuthorizationPlugin {
         }
         
         // Check if user is authorized
-        if !self.is_authorized(&user, request, method, context) {
-            return Some(self.create_access_denied(&user, &request.path, method));
-        }
+        let rule = match self.authorize(&user, request, method, context) {
+            Ok(rule) => rule,
+            Err(denial) => {
+                let rule = denial.rule.as_ref().map(|rule| rule.label())
+                    .unwrap_or_else(|| denial.code.as_str().to_string());
+                log_info!(self.name, context, "Access denied";
+                    user = user, method = method, path = request.path, rule = rule);
+                if let Some(id) = denial.rule.as_ref().and_then(|rule| rule.id.clone()) {
+                    request.metadata.insert(METADATA_AUTHORIZATION_RULE.to_string(), id);
+                }
+                return Some(self.create_access_denied(request, &user, method, &denial, context));
+            }
+        };
+        log_debug!(self.name, context, "Access granted";
+            user = user, method = method, path = request.path, rule = rule.label());
         
         // Set authorization metadata for downstream plugins
         self.set_authorization_metadata(request, &user);
+        if let Some(id) = rule.id {
+            request.metadata.insert(METADATA_AUTHORIZATION_RULE.to_string(), id);
+        }
         
         match &selector {
             Some(sel) => {
@@ -997,6 +2941,183 @@ impl AuthorizationPlugin {
         request.metadata.insert("authorized".to_string(), "true".to_string());
         request.metadata.insert("authorized_user".to_string(), user.to_string());
     }
+    
+    /// The selectors and modes of the redaction rules applying to a
+    /// response for a user, with `${username}` placeholders filled in
+    ///
+    /// Anonymous users fill placeholders with an empty username, so
+    /// selectors such as `.private:not([data-owner="${username}"])` redact
+    /// every private element for them.
+    fn redactions_for(
+        &self,
+        user: &str,
+        loaded: &LoadedAuthFiles,
+        request: &PluginRequest,
+        context: &PluginContext
+    ) -> Vec<(String, RedactionMode)> {
+        let method = request.http_request.method().as_str();
+        let user_roles = self.get_user_roles(user, &loaded.users, &request.metadata);
+        let username = if user == USERNAME_ANONYMOUS { String::new() } else { self.sanitize_username_for_css(user) };
+        let placeholder = Regex::new(USERNAME_PLACEHOLDER_PATTERN).expect("valid placeholder pattern");
+        
+        let now = Utc::now();
+        loaded.redactions.iter()
+            .filter(|redaction| !redaction.rule.is_expired(now)
+                && self.check_method_match(&redaction.rule, Some(method))
+                && self.check_path_match(&redaction.rule, request)
+                && self.calculate_rule_priority(&redaction.rule, user, &user_roles).is_some())
+            .map(|redaction| {
+                context.log_verbose(&format!(
+                    "[Authorization] Redaction rule {} applies to user '{}' on '{}': {} ({:?})",
+                    redaction.rule.id.as_deref().unwrap_or("-"), user, request.path, redaction.redact_selector, redaction.mode
+                ));
+                let selector = placeholder.replace_all(&redaction.redact_selector, NoExpand(&username)).to_string();
+                (selector, redaction.mode)
+            })
+            .collect()
+    }
+}
+
+/// Parse an RFC 3339 time, or a date as midnight UTC at its start
+fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc())
+}
+
+fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+/// Whether a response carries HTML
+fn is_html_response(response: &Response<Body>) -> bool {
+    response.headers().get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|content_type| content_type.to_lowercase().starts_with(CONTENT_TYPE_HTML))
+}
+
+/// Redact the elements picked by each selector from an HTML document, or
+/// from a fragment such as a selector response
+///
+/// Returns None if no element was picked, or an error naming a selector
+/// that cannot be parsed.
+fn redact_html(html: &str, redactions: &[(String, RedactionMode)]) -> Result<Option<String>, String> {
+    let start: String = html.trim_start().chars().take(9).collect::<String>().to_lowercase();
+    let is_document = start.starts_with("<!doctype") || start.starts_with("<html");
+    let document = if is_document { Document::from(html) } else { Document::fragment(html) };
+    
+    let mut redacted = false;
+    for (selector, mode) in redactions {
+        let matcher = Matcher::new(selector).map_err(|_| format!("invalid redactSelector '{}'", selector))?;
+        let selection = document.select_matcher(&matcher);
+        if selection.is_empty() {
+            continue;
+        }
+        match mode {
+            RedactionMode::Remove => selection.remove(),
+            RedactionMode::Obfuscate => {
+                selection.retain_attrs(OBFUSCATED_KEPT_ATTRIBUTES);
+                selection.set_text(REDACTED_TEXT);
+            }
+        }
+        redacted = true;
+    }
+    
+    if !redacted {
+        return Ok(None);
+    }
+    Ok(Some(if is_document {
+        document.html().to_string()
+    } else {
+        document.select("html").inner_html().to_string()
+    }))
+}
+
+/// The response sent in place of one that could not be redacted
+fn redaction_failed_response() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .header(CONTENT_TYPE, "text/plain")
+        .header(CACHE_CONTROL, "no-store")
+        .body(Body::from(MSG_REDACTION_FAILED))
+        .unwrap()
+}
+
+/// A JSON response that must not be cached, as it depends on the user
+fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, CONTENT_TYPE_JSON)
+        .header(CACHE_CONTROL, "no-store")
+        .body(Body::from(body.to_string()))
+        .unwrap()
+}
+
+/// A bare request standing in for one that is checked but not made, as
+/// rules are matched against the path, method and selector only
+fn stand_in_request(method: &str, path: &str, selector: Option<&str>) -> Option<PluginRequest> {
+    let mut builder = hyper::Request::builder().method(method).uri(path);
+    if let Some(selector) = selector {
+        builder = builder.header("range", format!("selector={}", urlencoding::encode(selector)));
+    }
+    let http_request = builder.body(Body::empty()).ok()?;
+    Some(PluginRequest::new(http_request, path.to_string()))
+}
+
+/// The path an auth file is served at, if it is under the host root
+fn auth_file_url(host_root: &Path, file: &Path) -> Option<String> {
+    let relative = file.strip_prefix(host_root).ok()?;
+    let parts: Option<Vec<&str>> = relative.components()
+        .map(|component| component.as_os_str().to_str())
+        .collect();
+    Some(format!("/{}", parts?.join("/")))
+}
+
+/// Files an auth file includes, in the order it names them
+fn auth_file_includes(content: &str) -> Vec<String> {
+    Document::from(content)
+        .select(r#"[itemprop~="include"][href]"#)
+        .iter()
+        .filter_map(|include| include.attr("href"))
+        .map(|href| href.trim().to_string())
+        .filter(|href| !href.is_empty())
+        .collect()
+}
+
+/// Decodes a percent-encoded query string component
+fn decode_query_component(component: &str) -> String {
+    let component = component.replace('+', " ");
+    urlencoding::decode(&component)
+        .map(|decoded| decoded.into_owned())
+        .unwrap_or(component)
+}
+
+/// A raw selector Range value without trailing `;start=N` and `;count=N`
+/// parameters, split off the way the selector handler does
+fn strip_paging_parameters(value: &str) -> &str {
+    let mut selector = value;
+    while let Some((rest, parameter)) = selector.rsplit_once(';') {
+        let is_paging = parameter.split_once('=').is_some_and(|(name, number)| {
+            matches!(name.trim(), "start" | "count") && number.trim().parse::<usize>().is_ok()
+        });
+        if !is_paging {
+            break;
+        }
+        selector = rest;
+    }
+    selector.trim_end()
+}
+
+/// Escapes text for inclusion in HTML
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
 }
 
 // Export the plugin creation function
@@ -1007,8 +3128,34 @@ mod tests {
     use super::*;
     use std::sync::Arc;
     use hyper::Request;
+    use hyper::header::{ACCEPT, ETAG};
     use tokio::sync::Mutex;
     
+    fn create_test_plugin_with_conflict(conflict: &str) -> AuthorizationPlugin {
+        let mut config = HashMap::new();
+        config.insert("authfile".to_string(), "file://tests/test-auth.html".to_string());
+        config.insert("conflict".to_string(), conflict.to_string());
+        AuthorizationPlugin::new(config)
+    }
+    
+    fn create_role_rule(role: &str, action: Permission) -> AuthorizationRule {
+        AuthorizationRule {
+            username: role.to_string(),
+            path: "/doc.html".to_string(),
+            selector: None,
+            ownership_selector: None,
+            require_exact_match: false,
+            required_headers: vec![],
+            exclude: vec![],
+            methods: vec!["GET".to_string()],
+            action,
+            id: None,
+            description: None,
+            expires: None,
+            owner: None,
+        }
+    }
+    
     fn create_test_plugin() -> AuthorizationPlugin {
         let mut config = HashMap::new();
         config.insert("name".to_string(), "test-auth".to_string());
@@ -1026,6 +3173,8 @@ mod tests {
             request_id: "test-request".to_string(),
             runtime_handle: None,
             verbose: false,
+            services: None,
+            deadline: None,
         }
     }
     
@@ -1047,7 +3196,9 @@ mod tests {
             path: path.to_string(),
             canonical_path: None,
             metadata: HashMap::new(),
+            extensions: Default::default(),
             body_cache: Arc::new(Mutex::new(None)),
+            spool: None,
         }
     }
     
@@ -1076,6 +3227,12 @@ mod tests {
         let req_complex = create_test_request("GET", "/", Some("#entries .entry:nth-child(1)"));
         let selector_complex = plugin.extract_selector_from_request(&req_complex);
         assert_eq!(selector_complex, Some("#entries .entry:nth-child(1)".to_string()));
+        
+        // Paging parameters are not part of the selector
+        let req_paged = create_test_request("GET", "/", Some("#entries .entry;start=20;count=10"));
+        assert_eq!(plugin.extract_selector_from_request(&req_paged), Some("#entries .entry".to_string()));
+        let req_other = create_test_request("GET", "/", Some("a[title='x;y=1']"));
+        assert_eq!(plugin.extract_selector_from_request(&req_other), Some("a[title='x;y=1']".to_string()));
     }
     
     #[test]
@@ -1105,26 +3262,72 @@ mod tests {
         assert!(!plugin.path_matches("/api/v2/users", "/api/v1/*"));
     }
     
+    #[test]
+    fn test_normalized_paths_match_rules() {
+        use rusty_beam_plugin_api::paths::{normalize_request_path, InvalidPath};
+        let plugin = create_test_plugin();
+        
+        // Encoded, dotted and doubled spellings of a protected path match its rule
+        for raw in ["/%61dmin/users.html", "/admin%2Fusers.html", "//admin/users.html", "/public/../admin/users.html", "/public/%2e%2E/admin/users.html"] {
+            let path = normalize_request_path(raw).unwrap();
+            assert!(plugin.path_matches(&path, "/admin/*"), "{} normalized to {}", raw, path);
+        }
+        
+        // Decomposed characters match rules written in composed form
+        let path = normalize_request_path("/caf%65%CC%81/menu.html").unwrap();
+        assert_eq!(path, "/caf\u{e9}/menu.html");
+        assert!(plugin.path_matches(&path, "/caf\u{e9}/*"));
+        
+        // Decoding happens once, so a double-encoded dot segment is a name
+        let path = normalize_request_path("/public/%252E%252E/admin/users.html").unwrap();
+        assert!(!plugin.path_matches(&path, "/admin/*"));
+        
+        // Dot segments cannot climb above the root
+        assert_eq!(normalize_request_path("/../../admin/").unwrap(), "/admin/");
+        
+        assert_eq!(normalize_request_path("/admin%00.html"), Err(InvalidPath::ControlCharacter));
+        assert_eq!(normalize_request_path("/admin%0a/users.html"), Err(InvalidPath::ControlCharacter));
+        assert_eq!(normalize_request_path("/%E9t%E9.html"), Err(InvalidPath::Encoding));
+        assert_eq!(normalize_request_path("/100%.html"), Err(InvalidPath::Encoding));
+    }
+    
     #[test]
     fn test_construct_file_path() {
+        let dir = std::env::temp_dir().join(format!("authz-file-path-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("dir")).unwrap();
+        let root = dir.canonicalize().unwrap();
+        
         let plugin = create_test_plugin();
         let mut context = create_test_context();
         
         // Test with host_root
-        context.host_config.insert("host_root".to_string(), "/var/www".to_string());
+        context.host_config.insert("host_root".to_string(), dir.to_string_lossy().to_string());
         let req = create_test_request("GET", "/test.html", None);
         let path = plugin.construct_file_path(&req, &context);
-        assert_eq!(path, "/var/www/test.html");
+        assert_eq!(path, Some(root.join("test.html").to_string_lossy().to_string()));
         
         // Test with root path
         let req_root = create_test_request("GET", "/", None);
         let path_root = plugin.construct_file_path(&req_root, &context);
-        assert_eq!(path_root, "/var/www/index.html");
+        assert_eq!(path_root, Some(root.join("index.html").to_string_lossy().to_string()));
         
         // Test with trailing slash
         let req_dir = create_test_request("GET", "/dir/", None);
         let path_dir = plugin.construct_file_path(&req_dir, &context);
-        assert_eq!(path_dir, "/var/www/dir/index.html");
+        assert_eq!(path_dir, Some(root.join("dir/index.html").to_string_lossy().to_string()));
+        
+        // Test with a path leaving the root
+        let mut req_escape = create_test_request("GET", "/", None);
+        req_escape.path = "/dir/../../secret.html".to_string();
+        assert_eq!(plugin.construct_file_path(&req_escape, &context), None);
+        
+        // The path resolved by the server takes precedence
+        let mut req_resolved = create_test_request("GET", "/test.html", None);
+        req_resolved.canonical_path = Some(root.join("resolved.html"));
+        let path_resolved = plugin.construct_file_path(&req_resolved, &context);
+        assert_eq!(path_resolved, Some(root.join("resolved.html").to_string_lossy().to_string()));
+        
+        std::fs::remove_dir_all(&dir).ok();
     }
     
     #[test]
@@ -1133,8 +3336,16 @@ mod tests {
             username: "testuser".to_string(),
             path: "/test/*".to_string(),
             selector: Some("#content".to_string()),
+            ownership_selector: None,
+            require_exact_match: false,
+            required_headers: vec![],
+            exclude: vec![],
             methods: vec!["GET".to_string(), "POST".to_string()],
             action: Permission::Allow,
+            id: None,
+            description: None,
+            expires: None,
+            owner: None,
         };
         
         assert_eq!(rule.username, "testuser");
@@ -1185,6 +3396,82 @@ mod tests {
         assert_eq!(unknown_roles.len(), 0);
     }
     
+    #[test]
+    fn test_rule_exclusions() {
+        let plugin = create_test_plugin();
+        let html = r#"<table>
+            <tr itemscope itemtype="https://rustybeam.net/schema/AuthorizationRule">
+                <td itemprop="username">users</td><td itemprop="username">!suspended</td>
+                <td itemprop="exclude">mallory</td>
+                <td itemprop="path">/wiki/*</td><td itemprop="method">read</td><td itemprop="action">allow</td>
+            </tr>
+            <tr itemscope itemtype="https://rustybeam.net/schema/AuthorizationRule">
+                <td itemprop="username">!guests</td>
+                <td itemprop="path">/*</td><td itemprop="method">GET</td><td itemprop="action">allow</td>
+            </tr>
+        </table>"#;
+        let items = MicrodataExtractor::new().extract(html).unwrap();
+        let rules: Vec<AuthorizationRule> = items.iter()
+            .filter_map(|item| plugin.parse_authorization_rule(item))
+            .collect();
+        assert_eq!(rules.len(), 2);
+        
+        let wiki = &rules[0];
+        assert_eq!(wiki.username, "users");
+        assert_eq!(wiki.exclude, vec!["mallory".to_string(), "suspended".to_string()]);
+        let users = vec!["users".to_string()];
+        let suspended = vec!["users".to_string(), "suspended".to_string()];
+        assert_eq!(plugin.calculate_rule_priority(wiki, "alice", &users), Some(PRIORITY_ROLE_MATCH));
+        assert_eq!(plugin.calculate_rule_priority(wiki, "bob", &suspended), None);
+        assert_eq!(plugin.calculate_rule_priority(wiki, "mallory", &users), None);
+        
+        // Naming only exclusions covers everyone else
+        let everyone = &rules[1];
+        assert_eq!(everyone.username, USERNAME_WILDCARD);
+        assert_eq!(plugin.calculate_rule_priority(everyone, "alice", &users), Some(PRIORITY_WILDCARD));
+        assert_eq!(plugin.calculate_rule_priority(everyone, "eve", &["guests".to_string()]), None);
+        assert!(everyone.describe().contains("exclude=guests"));
+    }
+
+    #[test]
+    fn test_rule_ids_and_descriptions() {
+        let plugin = create_test_plugin();
+        let html = r#"<table>
+            <tr itemscope itemtype="https://rustybeam.net/schema/AuthorizationRule">
+                <td itemprop="id">wiki-read</td>
+                <td itemprop="description">Members may read
+                    the wiki</td>
+                <td itemprop="username">users</td>
+                <td itemprop="path">/wiki/*</td><td itemprop="method">read</td><td itemprop="action">allow</td>
+            </tr>
+            <tr itemscope itemtype="https://rustybeam.net/schema/AuthorizationRule">
+                <td itemprop="id">deny-drafts</td>
+                <td itemprop="username">*</td>
+                <td itemprop="path">/drafts/*</td><td itemprop="method">*</td><td itemprop="action">deny</td>
+            </tr>
+            <tr itemscope itemtype="https://rustybeam.net/schema/AuthorizationRule">
+                <td itemprop="username">*</td>
+                <td itemprop="path">/*</td><td itemprop="method">GET</td><td itemprop="action">allow</td>
+            </tr>
+        </table>"#;
+        let items = MicrodataExtractor::new().extract(html).unwrap();
+        let rules: Vec<AuthorizationRule> = items.iter()
+            .filter_map(|item| plugin.parse_authorization_rule(item))
+            .collect();
+
+        assert_eq!(rules[0].id.as_deref(), Some("wiki-read"));
+        assert_eq!(rules[0].description.as_deref(), Some("Members may read the wiki"));
+        assert!(rules[0].describe().starts_with("id=wiki-read; username=users"));
+        assert_eq!(rules[0].to_json()["description"], "Members may read the wiki");
+        assert_eq!(rules[1].id.as_deref(), Some("deny-drafts"));
+        assert_eq!(rules[1].label(), "deny-drafts");
+
+        // Rules without an id are named by their description
+        assert_eq!(rules[2].id, None);
+        assert!(rules[2].to_json()["id"].is_null());
+        assert_eq!(rules[2].label(), rules[2].describe());
+    }
+
     #[test]
     fn test_get_user_roles_from_metadata() {
         let plugin = create_test_plugin();
@@ -1307,8 +3594,16 @@ mod tests {
             username: "user".to_string(),
             path: "/test/".to_string(),
             selector: Some("li.owned-by-user".to_string()),
+            ownership_selector: None,
+            require_exact_match: false,
+            required_headers: vec![],
+            exclude: vec![],
             methods: vec!["DELETE".to_string()],
             action: Permission::Allow,
+            id: None,
+            description: None,
+            expires: None,
+            owner: None,
         };
         
         // Create a request with a selector that doesn't match the rule selector
@@ -1320,6 +3615,7 @@ mod tests {
             "testuser",
             &vec!["user".to_string()],
             &request,
+            &SelectorEvaluation::default(),
             &context,
             None // OPTIONS request has no specific method check
         );
@@ -1339,8 +3635,16 @@ mod tests {
             username: "testuser".to_string(), // Exact match = high priority
             path: "/test/".to_string(),
             selector: Some("li.specific".to_string()),
+            ownership_selector: None,
+            require_exact_match: false,
+            required_headers: vec![],
+            exclude: vec![],
             methods: vec!["DELETE".to_string()],
             action: Permission::Allow,
+            id: None,
+            description: None,
+            expires: None,
+            owner: None,
         };
         
         // Request with non-matching selector
@@ -1353,10 +3657,907 @@ mod tests {
             "testuser",
             &vec![],
             &request,
+            &SelectorEvaluation::default(),
             &context,
             None
         );
         
         assert_eq!(result, None, "High-priority rule should still be excluded when selector doesn't match");
     }
-}
\ No newline at end of file
+    
+    #[test]
+    fn test_expand_method_groups() {
+        let plugin = create_test_plugin();
+        
+        let read = plugin.expand_method_specs(&["read".to_string()]);
+        assert_eq!(read, vec!["GET", "HEAD", "OPTIONS"]);
+        
+        let write = plugin.expand_method_specs(&["Write".to_string()]);
+        assert_eq!(write, vec!["PUT", "POST", "DELETE", "PATCH"]);
+        
+        // Groups, lists and individual methods combine without duplicates
+        let mixed = plugin.expand_method_specs(&["read, post".to_string(), "GET".to_string()]);
+        assert_eq!(mixed, vec!["GET", "HEAD", "OPTIONS", "POST"]);
+    }
+    
+    #[test]
+    fn test_method_wildcard_matches_any_method() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        
+        let rule = AuthorizationRule {
+            username: "*".to_string(),
+            path: "/".to_string(),
+            selector: None,
+            ownership_selector: None,
+            require_exact_match: false,
+            required_headers: vec![],
+            exclude: vec![],
+            methods: plugin.expand_method_specs(&["*".to_string()]),
+            action: Permission::Allow,
+            id: None,
+            description: None,
+            expires: None,
+            owner: None,
+        };
+        
+        assert!(plugin.check_method_match(&rule, Some("PATCH")));
+        assert!(plugin.check_method_match(&rule, Some("PROPFIND")));
+        
+        // OPTIONS discovery lists the known methods but not the marker itself
+        let (allowed, _) = plugin.process_rules_for_methods(&[(0, &rule)], &context);
+        assert!(allowed.contains("PATCH"));
+        assert!(allowed.contains("GET"));
+        assert!(!allowed.contains(METHOD_WILDCARD));
+    }
+    
+    #[test]
+    fn test_conflict_strategies_for_tied_rules() {
+        let context = create_test_context();
+        let request = create_test_request("GET", "/doc.html", None);
+        let roles = vec!["editors".to_string(), "viewers".to_string()];
+        let rules = vec![
+            create_role_rule("editors", Permission::Allow),
+            create_role_rule("viewers", Permission::Deny),
+        ];
+        
+        let decide = |conflict: &str| {
+            let plugin = create_test_plugin_with_conflict(conflict);
+            plugin.find_best_matching_rule(&rules, "alice", &roles, &request, "GET", &context)
+                .map(|(_, rule)| rule.action.clone())
+        };
+        
+        assert_eq!(decide("first_match"), Some(Permission::Allow));
+        assert_eq!(decide("deny_overrides"), Some(Permission::Deny));
+        assert_eq!(decide("allow_overrides"), Some(Permission::Allow));
+    }
+    
+    #[tokio::test]
+    async fn test_permissions_endpoint() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        
+        // The path must be absolute
+        let mut request = create_test_request("GET", "/auth/permissions?path=doc.html", None);
+        request.path = DEFAULT_PERMISSIONS_PATH.to_string();
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+        assert_eq!(response.headers()[CACHE_CONTROL], "no-store");
+        
+        // The test auth file does not exist, so a valid query cannot be answered
+        let request = create_test_request("GET", "/auth/permissions?path=%2Fdoc.html", None);
+        let response = plugin.handle_permissions_request(&request, &context);
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        
+        // Selector queries are evaluated against the selector rules
+        let users = vec![];
+        let rules = vec![AuthorizationRule {
+            username: "alice".to_string(),
+            path: "/doc.html".to_string(),
+            selector: Some("#notes".to_string()),
+            ownership_selector: None,
+            require_exact_match: false,
+            required_headers: vec![],
+            exclude: vec![],
+            methods: vec!["PUT".to_string()],
+            action: Permission::Allow,
+            id: None,
+            description: None,
+            expires: None,
+            owner: None,
+        }];
+        let target = plugin.permissions_query_request(&request, "/doc.html", Some("#notes")).unwrap();
+        let methods = plugin.allowed_methods_with_rules("alice", &users, &rules, &[], &target, &context);
+        assert!(methods.contains(&"PUT".to_string()));
+        let methods = plugin.allowed_methods_with_rules("bob", &users, &rules, &[], &target, &context);
+        assert!(!methods.contains(&"PUT".to_string()));
+        
+        let plugin = AuthorizationPlugin::new(HashMap::from([
+            ("permissions_path".to_string(), String::new()),
+        ]));
+        assert!(plugin.permissions_path.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_rule_test_report() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        assert!(plugin.rule_test_path.is_none());
+        
+        let rule = |username: &str, action: Permission| AuthorizationRule {
+            username: username.to_string(),
+            path: "/doc.html".to_string(),
+            selector: None,
+            ownership_selector: None,
+            require_exact_match: false,
+            required_headers: vec![],
+            exclude: vec![],
+            methods: vec!["PUT".to_string()],
+            action,
+            id: None,
+            description: None,
+            expires: None,
+            owner: None,
+        };
+        let rules = vec![rule("alice", Permission::Allow), rule("editors", Permission::Deny)];
+        let users = vec![User { username: "bob".to_string(), roles: vec!["editors".to_string()] }];
+        let report = |test: serde_json::Value| {
+            let test: RuleTest = serde_json::from_value(test).unwrap();
+            let response = plugin.rule_test_report(&test, &users, &rules, &[], &context);
+            let status = response.status();
+            async move {
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                (status, serde_json::from_slice::<serde_json::Value>(&body).unwrap())
+            }
+        };
+        
+        // The exact username outranks the role
+        let (status, body) = report(json!({
+            "user": "alice", "roles": ["editors"], "path": "/doc.html", "method": "put"
+        })).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["decision"], "allow");
+        assert_eq!(body["index"], 0);
+        assert_eq!(body["priority"], PRIORITY_EXACT_USERNAME);
+        assert_eq!(body["trace"][1]["priority"], PRIORITY_ROLE_MATCH);
+        
+        // Roles come from the auth files unless given
+        let (_, body) = report(json!({ "user": "bob", "path": "/doc.html", "method": "PUT" })).await;
+        assert_eq!(body["decision"], "deny");
+        assert_eq!(body["code"], "denied_by_rule");
+        assert_eq!(body["index"], 1);
+        assert_eq!(body["trace"][0]["skipped"], "user");
+        
+        // The path is normalized like a request path
+        let (_, body) = report(json!({ "user": "bob", "path": "/a/../doc.html" })).await;
+        assert_eq!(body["path"], "/doc.html");
+        assert_eq!(body["code"], "no_matching_rule");
+        assert_eq!(body["trace"][0]["skipped"], "method");
+        assert!(body["rule"].is_null());
+        
+        let (status, _) = report(json!({ "path": "doc.html" })).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+    
+    #[tokio::test]
+    async fn test_access_denied_body_follows_accept_header() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        let denial = Denial {
+            code: DenialCode::DeniedByRule,
+            rule: Some(Box::new(create_role_rule("viewers", Permission::Deny))),
+        };
+        
+        let mut request = create_test_request("GET", "/doc.html", None);
+        request.http_request.headers_mut().insert(ACCEPT, "application/json".parse().unwrap());
+        let response = plugin.create_access_denied(&request, "alice", "GET", &denial, &context);
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers()[CONTENT_TYPE], CONTENT_TYPE_JSON);
+        assert!(response.headers().get(DENIED_RULE_HEADER).is_none());
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["code"], "denied_by_rule");
+        assert_eq!(body["rule"]["username"], "viewers");
+        assert_eq!(body["missing_permission"]["path"], "/doc.html");
+        assert_eq!(body["documentation_url"], DEFAULT_DOCUMENTATION_URL);
+        
+        // Each placeholder gets its own value, escaped for HTML
+        let request = create_test_request("GET", "/doc.html", None);
+        let verbose_context = PluginContext { verbose: true, ..create_test_context() };
+        let response = plugin.create_access_denied(&request, "<bob>", "GET", &denial, &verbose_context);
+        assert_eq!(response.headers()[CONTENT_TYPE], CONTENT_TYPE_HTML);
+        assert!(response.headers()[DENIED_RULE_HEADER].to_str().unwrap().contains("username=viewers"));
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+        assert!(body.contains("User '&lt;bob&gt;' does not have permission to GET '/doc.html'"));
+    }
+    
+    #[test]
+    fn test_conflict_strategy_applies_to_options() {
+        let context = create_test_context();
+        let allow = create_role_rule("editors", Permission::Allow);
+        let deny = create_role_rule("viewers", Permission::Deny);
+        let applicable = vec![(PRIORITY_ROLE_MATCH, &allow), (PRIORITY_ROLE_MATCH, &deny)];
+        
+        let plugin = create_test_plugin_with_conflict("deny_overrides");
+        let (allowed, denied) = plugin.process_rules_for_methods(&applicable, &context);
+        assert!(!allowed.contains("GET"));
+        assert!(denied.contains("GET"));
+        
+        let plugin = create_test_plugin_with_conflict("first_match");
+        let (allowed, _) = plugin.process_rules_for_methods(&applicable, &context);
+        assert!(allowed.contains("GET"));
+    }
+    
+    #[test]
+    fn test_selector_document_parsed_once_per_request() {
+        let dir = std::env::temp_dir().join(format!("authz-selector-cache-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("doc.html"), "<ul><li class=\"a\">A</li><li class=\"b\">B</li></ul>").unwrap();
+        
+        let plugin = create_test_plugin();
+        let mut context = create_test_context();
+        context.host_config.insert("hostRoot".to_string(), dir.to_string_lossy().to_string());
+        let request = create_test_request("GET", "/doc.html", Some("li.a"));
+        let rule = |selector: &str| AuthorizationRule {
+            username: "*".to_string(),
+            path: "/doc.html".to_string(),
+            selector: Some(selector.to_string()),
+            ownership_selector: None,
+            require_exact_match: false,
+            required_headers: vec![],
+            exclude: vec![],
+            methods: vec!["GET".to_string()],
+            action: Permission::Allow,
+            id: None,
+            description: None,
+            expires: None,
+            owner: None,
+        };
+        
+        let evaluation = SelectorEvaluation::default();
+        for (selector, expected) in [("li", true), ("li.b", false), ("ul li", true)] {
+            let result = plugin.rule_matches_request(&rule(selector), "*", &[], &request, &evaluation, &context, Some("GET"));
+            assert_eq!(result.is_some(), expected, "rule selector '{}'", selector);
+        }
+        
+        // One document, and the request selector evaluated only once alongside each rule selector
+        assert_eq!(evaluation.documents.borrow().len(), 1);
+        assert_eq!(evaluation.matches.borrow().len(), 4);
+        
+        std::fs::remove_dir_all(&dir).ok();
+    }
+    
+    #[test]
+    fn test_ownership_selector_checks_selected_elements() {
+        let dir = std::env::temp_dir().join(format!("authz-ownership-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("doc.html"), concat!(
+            "<ul>",
+            "<li id=\"a\"><meta itemprop=\"author\" content=\"alice\">A</li>",
+            "<li id=\"b\"><meta itemprop=\"author\" content=\"bob\">B</li>",
+            "</ul>",
+        )).unwrap();
+        
+        let plugin = create_test_plugin();
+        let mut context = create_test_context();
+        context.host_config.insert("hostRoot".to_string(), dir.to_string_lossy().to_string());
+        let rule = AuthorizationRule {
+            username: ":username".to_string(),
+            path: "/doc.html".to_string(),
+            selector: None,
+            ownership_selector: Some("meta[itemprop=author][content=\"${username}\"]".to_string()),
+            require_exact_match: false,
+            required_headers: vec![],
+            exclude: vec![],
+            methods: vec!["DELETE".to_string()],
+            action: Permission::Allow,
+            id: None,
+            description: None,
+            expires: None,
+            owner: None,
+        };
+        let applies = |selector: Option<&str>, user: &str| {
+            let mut request = create_test_request("DELETE", "/doc.html", selector);
+            request.metadata.insert("authenticated_user".to_string(), user.to_string());
+            plugin.rule_matches_request(&rule, user, &[], &request, &SelectorEvaluation::default(), &context, Some("DELETE"))
+                .is_some()
+        };
+        
+        assert!(applies(Some("#a"), "alice"));
+        assert!(!applies(Some("#b"), "alice"));
+        // Every selected element must be owned, and something must be selected
+        assert!(!applies(Some("li"), "alice"));
+        assert!(!applies(Some("#missing"), "alice"));
+        // Ownership is about elements, so whole-document requests don't match
+        assert!(!applies(None, "alice"));
+        assert!(!applies(Some("#a"), "*"));
+        // A container does not belong to the owner of one of its items
+        assert!(!applies(Some("ul"), "alice"));
+        
+        std::fs::remove_dir_all(&dir).ok();
+    }
+    
+    #[test]
+    fn test_ownership_selector_ignores_markers_of_nested_items() {
+        let dir = std::env::temp_dir().join(format!("authz-ownership-nested-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("doc.html"), concat!(
+            "<section id=\"mine\" itemscope>",
+            "<div><meta itemprop=\"author\" content=\"alice\"></div>",
+            "<p>Mine</p>",
+            "</section>",
+            "<section id=\"shared\" itemscope>",
+            "<meta itemprop=\"author\" content=\"bob\">",
+            "<article id=\"alices\" itemscope><meta itemprop=\"author\" content=\"alice\">A</article>",
+            "<article id=\"bobs\" itemscope><meta itemprop=\"author\" content=\"bob\">B</article>",
+            "</section>",
+            "<ul id=\"list\">",
+            "<li><div itemscope><meta itemprop=\"author\" content=\"alice\"></div></li>",
+            "</ul>",
+        )).unwrap();
+        
+        let plugin = create_test_plugin();
+        let mut context = create_test_context();
+        context.host_config.insert("hostRoot".to_string(), dir.to_string_lossy().to_string());
+        let rule = AuthorizationRule {
+            username: ":username".to_string(),
+            path: "/doc.html".to_string(),
+            selector: None,
+            ownership_selector: Some("meta[itemprop=author][content=\"${username}\"]".to_string()),
+            require_exact_match: false,
+            required_headers: vec![],
+            exclude: vec![],
+            methods: vec!["DELETE".to_string()],
+            action: Permission::Allow,
+            id: None,
+            description: None,
+            expires: None,
+            owner: None,
+        };
+        let applies = |selector: &str, user: &str| {
+            let mut request = create_test_request("DELETE", "/doc.html", Some(selector));
+            request.metadata.insert("authenticated_user".to_string(), user.to_string());
+            plugin.rule_matches_request(&rule, user, &[], &request, &SelectorEvaluation::default(), &context, Some("DELETE"))
+                .is_some()
+        };
+        
+        // Markers anywhere in an item's own properties count
+        assert!(applies("#mine", "alice"));
+        // The container holding alice's and bob's items is bob's alone
+        assert!(!applies("#shared", "alice"));
+        assert!(applies("#shared", "bob"));
+        assert!(applies("#alices", "alice"));
+        assert!(!applies("#bobs", "alice"));
+        // Nor does a plain container belong to the owner of a nested item
+        assert!(!applies("#list", "alice"));
+        assert!(!applies("#list li", "alice"));
+        
+        std::fs::remove_dir_all(&dir).ok();
+    }
+    
+    #[test]
+    fn test_require_exact_match_rejects_partial_selections() {
+        let dir = std::env::temp_dir().join(format!("authz-exact-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("doc.html"), concat!(
+            "<ul>",
+            "<li id=\"a\" data-state=\"draft\">A</li>",
+            "<li id=\"b\" data-state=\"draft\">B</li>",
+            "<li id=\"c\" data-state=\"published\">C</li>",
+            "</ul>",
+        )).unwrap();
+        
+        let plugin = create_test_plugin();
+        let mut context = create_test_context();
+        context.host_config.insert("hostRoot".to_string(), dir.to_string_lossy().to_string());
+        let rule = |require_exact_match: bool| AuthorizationRule {
+            username: "*".to_string(),
+            path: "/doc.html".to_string(),
+            selector: Some("li[data-state=\"draft\"]".to_string()),
+            ownership_selector: None,
+            require_exact_match,
+            required_headers: vec![],
+            exclude: vec![],
+            methods: vec!["DELETE".to_string()],
+            action: Permission::Allow,
+            id: None,
+            description: None,
+            expires: None,
+            owner: None,
+        };
+        let applies = |rule: &AuthorizationRule, selector: &str| {
+            let request = create_test_request("DELETE", "/doc.html", Some(selector));
+            plugin.rule_matches_request(rule, "*", &[], &request, &SelectorEvaluation::default(), &context, Some("DELETE"))
+                .is_some()
+        };
+        
+        let subset_rule = rule(false);
+        assert!(applies(&subset_rule, "li:first-child"));
+        assert!(applies(&subset_rule, "li[data-state=\"draft\"]"));
+        assert!(!applies(&subset_rule, "li"));
+        
+        let exact_rule = rule(true);
+        assert!(!applies(&exact_rule, "li:first-child"));
+        assert!(applies(&exact_rule, "#a, #b"));
+        assert!(applies(&exact_rule, "li[data-state=\"draft\"]"));
+        assert!(!applies(&exact_rule, "li"));
+        
+        std::fs::remove_dir_all(&dir).ok();
+    }
+    
+    #[test]
+    fn test_source_cache_eviction_and_invalidation() {
+        let mut cache = SourceCache::new(2);
+        let modified = Some(SystemTime::UNIX_EPOCH);
+        cache.insert("a.html", modified, 1, Arc::new("a".to_string()));
+        cache.insert("b.html", modified, 1, Arc::new("b".to_string()));
+        
+        // Using "a" makes "b" the least recently used entry
+        assert!(cache.get("a.html", modified, 1).is_some());
+        cache.insert("c.html", modified, 1, Arc::new("c".to_string()));
+        assert!(cache.get("b.html", modified, 1).is_none());
+        assert!(cache.get("a.html", modified, 1).is_some());
+        
+        // A changed size or modification time misses
+        assert!(cache.get("c.html", modified, 2).is_none());
+        assert!(cache.get("c.html", None, 1).is_none());
+        
+        // A zero capacity cache stores nothing
+        let mut disabled = SourceCache::new(0);
+        disabled.insert("a.html", modified, 1, Arc::new("a".to_string()));
+        assert!(disabled.get("a.html", modified, 1).is_none());
+    }
+    
+    #[test]
+    fn test_auth_files_and_includes() {
+        let directory = std::env::temp_dir().join(format!("rusty-beam-authz-includes-{}", std::process::id()));
+        fs::create_dir_all(directory.join("wiki")).unwrap();
+        let rule = |role: &str, path: &str| format!(
+            r#"<div itemscope itemtype="{}"><span itemprop="role">{}</span><span itemprop="path">{}</span><span itemprop="method">GET</span><span itemprop="action">allow</span></div>"#,
+            SCHEMA_AUTHORIZATION_RULE, role, path,
+        );
+        let credential = |username: &str, role: &str| format!(
+            r#"<div itemscope itemtype="{}"><span itemprop="username">{}</span><span itemprop="role">{}</span></div>"#,
+            SCHEMA_CREDENTIAL, username, role,
+        );
+        fs::write(directory.join("main.html"), format!(
+            r#"<link itemprop="include" href="wiki/auth.html">{}{}"#,
+            credential("alice", "users"), rule("users", "/main/*"),
+        )).unwrap();
+        // The cycle back to main.html is skipped
+        fs::write(directory.join("wiki/auth.html"), format!(
+            r#"{}<link itemprop="include" href="../main.html">"#,
+            rule("editors", "/wiki/*"),
+        )).unwrap();
+        fs::write(directory.join("extra.html"), format!(
+            r#"{}<link itemprop="include" href="wiki/auth.html">"#,
+            credential("alice", "editors"),
+        )).unwrap();
+        
+        let auth_files = [directory.join("main.html"), directory.join("extra.html")]
+            .map(|file| format!("file://{}", file.display()))
+            .join(", ");
+        let plugin = AuthorizationPlugin::new(HashMap::from([("authfile".to_string(), auth_files)]));
+        let loaded = plugin.read_auth_files().unwrap();
+        
+        // A file's own rules come before those of the files it includes, and
+        // a file included twice is read once
+        let paths: Vec<&str> = loaded.rules.iter().map(|rule| rule.path.as_str()).collect();
+        assert_eq!(paths, vec!["/main/*", "/wiki/*"]);
+        assert_eq!(loaded.users.len(), 1);
+        assert_eq!(loaded.users[0].roles, vec!["users".to_string(), "editors".to_string()]);
+        assert_eq!(loaded.problems.len(), 1);
+        assert!(loaded.problems[0].contains("includes itself"));
+        
+        // A missing include leaves no rules at all
+        fs::write(directory.join("extra.html"), r#"<link itemprop="include" href="missing.html">"#).unwrap();
+        assert!(plugin.read_auth_files().is_err());
+        assert!(plugin.load_auth_config().is_none());
+        
+        fs::remove_dir_all(&directory).unwrap();
+    }
+    
+    #[test]
+    fn test_path_policies_decide_when_no_rule_matches() {
+        let directory = std::env::temp_dir().join(format!("rusty-beam-authz-policies-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let policy = |path: &str, method: &str, action: &str, id: &str| format!(
+            r#"<div itemscope itemtype="{}"><span itemprop="path">{}</span><span itemprop="method">{}</span><span itemprop="defaultAction">{}</span><span itemprop="id">{}</span></div>"#,
+            SCHEMA_PATH_POLICY, path, method, action, id,
+        );
+        fs::write(directory.join("auth.html"), format!(
+            r#"{}{}{}<div itemscope itemtype="{}"><span itemprop="username">*</span><span itemprop="path">/public/secret.html</span><span itemprop="method">GET</span><span itemprop="action">deny</span></div><div itemscope itemtype="{}"><span itemprop="path">/broken/*</span></div>"#,
+            policy("/public/*", "GET", "allow", "public-read"),
+            policy("/public/drafts/*", "*", "deny", "drafts"),
+            policy("/admin/*", "*", "deny", "admin"),
+            SCHEMA_AUTHORIZATION_RULE,
+            SCHEMA_PATH_POLICY,
+        )).unwrap();
+        let plugin = AuthorizationPlugin::new(HashMap::from([
+            ("authfile".to_string(), format!("file://{}", directory.join("auth.html").display())),
+        ]));
+        let context = create_test_context();
+        let authorize = |method: &str, path: &str| {
+            plugin.authorize(USERNAME_WILDCARD, &create_test_request(method, path, None), method, &context)
+        };
+        
+        // The default allows only the methods it names
+        assert_eq!(authorize("GET", "/public/page.html").unwrap().id.as_deref(), Some("public-read"));
+        let denial = authorize("PUT", "/public/page.html").unwrap_err();
+        assert_eq!(denial.code, DenialCode::NoMatchingRule);
+        assert!(denial.rule.is_none());
+        
+        // The most specific policy decides, and a matching rule overrides both
+        let denial = authorize("GET", "/public/drafts/next.html").unwrap_err();
+        assert_eq!(denial.code, DenialCode::NoMatchingRule);
+        assert_eq!(denial.rule.unwrap().id.as_deref(), Some("drafts"));
+        assert_eq!(authorize("GET", "/public/secret.html").unwrap_err().code, DenialCode::DeniedByRule);
+        assert!(authorize("GET", "/elsewhere.html").is_err());
+        
+        let request = create_test_request("OPTIONS", "/public/page.html", None);
+        assert_eq!(plugin.get_allowed_methods(USERNAME_WILDCARD, &request, &context), vec!["GET", "OPTIONS"]);
+        
+        let loaded = plugin.read_auth_files().unwrap();
+        assert_eq!(loaded.policies.len(), 3);
+        assert!(loaded.problems[0].contains("no defaultAction"));
+        
+        fs::remove_dir_all(&directory).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_expired_rules_are_ignored_and_reported() {
+        let directory = std::env::temp_dir().join(format!("rusty-beam-authz-expiry-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let now = Utc::now();
+        let rule = |id: &str, expires: String| format!(
+            r#"<div itemscope itemtype="{}"><span itemprop="id">{}</span><span itemprop="username">alice</span><span itemprop="path">/doc.html</span><span itemprop="method">GET</span><span itemprop="action">allow</span><span itemprop="expires">{}</span><span itemprop="owner">bob</span></div>"#,
+            SCHEMA_AUTHORIZATION_RULE, id, expires,
+        );
+        fs::write(directory.join("auth.html"), format!(
+            "<html><body>{}{}{}{}</body></html>",
+            rule("expired", format_time(now - TimeDelta::days(1))),
+            rule("expiring", format_time(now + TimeDelta::days(3))),
+            rule("later", (now + TimeDelta::days(60)).format("%Y-%m-%d").to_string()),
+            rule("typo", "next tuesday".to_string()),
+        )).unwrap();
+        let plugin = AuthorizationPlugin::new(HashMap::from([
+            ("authfile".to_string(), format!("file://{}", directory.join("auth.html").display())),
+            ("expiry_report_path".to_string(), "/auth/expiry".to_string()),
+        ]));
+        let context = create_test_context();
+        
+        // The expired rule no longer applies; the next one does
+        let request = create_test_request("GET", "/doc.html", None);
+        assert_eq!(plugin.authorize("alice", &request, "GET", &context).unwrap().id.as_deref(), Some("expiring"));
+        
+        // Rules with dates that can't be read are skipped and reported
+        let loaded = plugin.read_auth_files().unwrap();
+        assert_eq!(loaded.rules.len(), 3);
+        assert!(loaded.problems[0].contains("rule typo has an invalid expires date 'next tuesday'"));
+        let issues = plugin.validate_config(&context);
+        assert!(issues.iter().any(|issue| issue.message.contains("rule expired expired at")));
+        
+        let report = |query: &str| {
+            let request = create_test_request("GET", &format!("/auth/expiry{}", query), None);
+            let response = plugin.handle_expiry_report_request(&request, &context);
+            async move {
+                let status = response.status();
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+                (status, serde_json::from_slice::<serde_json::Value>(&body).unwrap())
+            }
+        };
+        let (status, body) = report("").await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["days"], DEFAULT_EXPIRY_WARNING_DAYS);
+        assert_eq!(body["expired"][0]["id"], "expired");
+        assert_eq!(body["expired"][0]["owner"], "bob");
+        assert_eq!(body["expiring"].as_array().unwrap().len(), 1);
+        assert_eq!(body["expiring"][0]["id"], "expiring");
+        let (_, body) = report("?days=90").await;
+        assert_eq!(body["expiring"][1]["id"], "later");
+        let (status, _) = report("?days=soon").await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        
+        fs::remove_dir_all(&directory).unwrap();
+    }
+    
+    #[tokio::test]
+    async fn test_redaction_rules_strip_elements_from_responses() {
+        let directory = std::env::temp_dir().join(format!("rusty-beam-authz-redaction-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let redaction = |subject: &str, selector: &str, mode: &str| format!(
+            r#"<div itemscope itemtype="{}"><span itemprop="username">{}</span><span itemprop="path">/staff/*</span><span itemprop="redactSelector">{}</span><span itemprop="redactMode">{}</span></div>"#,
+            SCHEMA_AUTHORIZATION_RULE, subject, selector, mode,
+        );
+        fs::write(directory.join("auth.html"), format!(
+            r#"<div itemscope itemtype="{}"><span itemprop="username">carol</span><span itemprop="role">hr</span></div>{}{}"#,
+            SCHEMA_CREDENTIAL,
+            redaction("!hr", ".salary", "remove"),
+            redaction("*", r#".note:not([data-author="${username}"])"#, "obfuscate"),
+        )).unwrap();
+        let plugin = AuthorizationPlugin::new(HashMap::from([
+            ("authfile".to_string(), format!("file://{}", directory.join("auth.html").display())),
+        ]));
+        let loaded = plugin.read_auth_files().unwrap();
+        assert_eq!(loaded.redactions.len(), 2);
+        assert!(loaded.rules.is_empty());
+        
+        let context = create_test_context();
+        let page = r#"<!DOCTYPE html><html><body><p class="salary">100</p><p class="note" data-author="bob" title="x">mine</p></body></html>"#;
+        let respond = |user: Option<&str>, method: &str, body: &'static str| {
+            let mut request = create_test_request(method, "/staff/bob.html", None);
+            if let Some(user) = user {
+                request.metadata.insert("authenticated_user".to_string(), user.to_string());
+            }
+            let response = Response::builder()
+                .header(CONTENT_TYPE, "text/html; charset=utf-8")
+                .header(ETAG, "\"abc\"")
+                .header("Repr-Digest", "sha-256=:abc=:")
+                .header("Digest", "SHA-256=abc=")
+                .header("Content-Length", body.len())
+                .body(Body::from(body))
+                .unwrap();
+            (request, response)
+        };
+        let body_of = |response: Response<Body>| async {
+            String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap()
+        };
+        
+        // HR sees salaries, and everyone sees their own notes
+        let (request, mut response) = respond(Some("carol"), "GET", page);
+        plugin.handle_response(&request, &mut response, &context).await;
+        let body = body_of(response).await;
+        assert!(body.contains("100"));
+        assert!(body.contains(r#"<p class="note">[redacted]</p>"#));
+        
+        let (request, mut response) = respond(Some("bob"), "GET", page);
+        plugin.handle_response(&request, &mut response, &context).await;
+        assert!(response.headers().get(ETAG).is_none());
+        assert!(response.headers().get("Repr-Digest").is_none());
+        assert!(response.headers().get("Digest").is_none());
+        assert_eq!(response.headers().get(CACHE_CONTROL).unwrap(), "private");
+        let body = body_of(response).await;
+        assert!(!body.contains("salary"));
+        assert!(body.contains("mine"));
+        
+        // Anonymous users see no one's notes, and fragments stay fragments
+        let (request, mut response) = respond(None, "GET", r#"<p class="salary">100</p><p class="note" data-author="bob">mine</p>"#);
+        plugin.handle_response(&request, &mut response, &context).await;
+        assert_eq!(body_of(response).await, r#"<p class="note">[redacted]</p>"#);
+        
+        // A HEAD loses the headers describing the unredacted document
+        let (request, mut response) = respond(Some("bob"), "HEAD", page);
+        plugin.handle_response(&request, &mut response, &context).await;
+        for name in ["ETag", "Repr-Digest", "Digest", "Content-Length"] {
+            assert!(response.headers().get(name).is_none(), "{}", name);
+        }
+        
+        // Writes are left alone
+        let (request, mut response) = respond(Some("bob"), "PUT", page);
+        plugin.handle_response(&request, &mut response, &context).await;
+        assert_eq!(body_of(response).await, page);
+        
+        // Content that can't be redacted is withheld
+        let (request, mut response) = respond(Some("bob"), "GET", page);
+        response.headers_mut().insert(CONTENT_ENCODING, HeaderValue::from_static("gzip"));
+        plugin.handle_response(&request, &mut response, &context).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(redact_html("<p></p>", &[("p[".to_string(), RedactionMode::Remove)]).unwrap_err(), "invalid redactSelector 'p['");
+        
+        fs::remove_dir_all(&directory).unwrap();
+    }
+    
+    #[test]
+    fn test_withheld_selectors() {
+        let directory = std::env::temp_dir().join(format!("rusty-beam-authz-withheld-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let rule = |subject: &str, selector: &str, action: &str| format!(
+            r#"<div itemscope itemtype="{}"><span itemprop="username">{}</span><span itemprop="path">/staff/*</span>{}<span itemprop="method">GET</span><span itemprop="action">{}</span></div>"#,
+            SCHEMA_AUTHORIZATION_RULE, subject,
+            if selector.is_empty() { String::new() } else { format!(r#"<span itemprop="selector">{}</span>"#, selector) },
+            action,
+        );
+        fs::write(directory.join("auth.html"), format!(
+            r#"<div itemscope itemtype="{credential}"><span itemprop="username">carol</span><span itemprop="role">hr</span></div>{}{}{}{}<div itemscope itemtype="{rule}"><span itemprop="username">!hr</span><span itemprop="path">/staff/*</span><span itemprop="redactSelector">.salary</span></div>"#,
+            rule("*", "", "allow"),
+            rule("*", "#public", "allow"),
+            rule("*", "#private", "deny"),
+            rule("*", r#".note[data-author="${username}"]"#, "allow"),
+            credential = SCHEMA_CREDENTIAL,
+            rule = SCHEMA_AUTHORIZATION_RULE,
+        )).unwrap();
+        let plugin = AuthorizationPlugin::new(HashMap::from([
+            ("authfile".to_string(), format!("file://{}", directory.join("auth.html").display())),
+        ]));
+        fs::create_dir_all(directory.join("staff")).unwrap();
+        fs::write(directory.join("staff/bob.html"), concat!(
+            r#"<p id="public">Hi</p><p id="private">Secret</p><p class="salary">100</p>"#,
+            r#"<p class="note" data-author="bob">Mine</p>"#,
+        )).unwrap();
+        let mut context = create_test_context();
+        context.host_config.insert("hostRoot".to_string(), directory.to_string_lossy().to_string());
+        
+        let withheld = plugin.withheld_selectors(Some("bob"), "/staff/bob.html", &context);
+        assert!(withheld.contains(&".salary".to_string()));
+        assert!(withheld.contains(&"#private".to_string()));
+        assert!(!withheld.contains(&"#public".to_string()));
+        assert!(!withheld.iter().any(|selector| selector.starts_with(".note")));
+        assert!(!plugin.withheld_selectors(Some("carol"), "/staff/bob.html", &context).contains(&".salary".to_string()));
+        assert!(plugin.withheld_selectors(Some("bob"), "/public.html", &context).is_empty());
+        
+        // Nothing can be said without the rules
+        fs::remove_dir_all(&directory).unwrap();
+        assert_eq!(plugin.withheld_selectors(Some("bob"), "/staff/bob.html", &context), vec![WITHHOLD_ALL.to_string()]);
+    }
+    
+    #[tokio::test]
+    async fn test_admin_page_and_auth_file_edits() {
+        let directory = std::env::temp_dir().join(format!("rusty-beam-authz-admin-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let credential = |id: &str, username: &str, role: &str| format!(
+            r#"<div id="{}" itemscope itemtype="{}"><span itemprop="username">{}</span><span itemprop="password">secret</span><span itemprop="role">{}</span></div>"#,
+            id, SCHEMA_CREDENTIAL, username, role,
+        );
+        let rule = |role: &str, action: &str| format!(
+            r#"<div id="rule" itemscope itemtype="{}"><span itemprop="role">{}</span><span itemprop="path">/*</span><span itemprop="method">*</span><span itemprop="action">{}</span></div>"#,
+            SCHEMA_AUTHORIZATION_RULE, role, action,
+        );
+        // Selector edits need a rule with a selector
+        let selector_rule = format!(
+            r#"<div itemscope itemtype="{}"><span itemprop="role">administrators</span><span itemprop="path">/*</span><span itemprop="selector">*</span><span itemprop="method">*</span><span itemprop="action">allow</span></div>"#,
+            SCHEMA_AUTHORIZATION_RULE,
+        );
+        fs::write(directory.join("auth.html"), format!(
+            "<html><body>{}{}{}{}</body></html>",
+            credential("admin", "alice", "administrators"), credential("bob", "bob", "users"), rule("administrators", "allow"), selector_rule,
+        )).unwrap();
+        let plugin = AuthorizationPlugin::new(HashMap::from([
+            ("authfile".to_string(), format!("file://{}", directory.join("auth.html").display())),
+            ("admin_path".to_string(), "/auth/admin".to_string()),
+        ]));
+        let mut context = create_test_context();
+        context.host_config.insert("host_root".to_string(), directory.display().to_string());
+        let send = |user: &str, method: &str, path: &str, selector: Option<&str>, body: String| {
+            let mut builder = Request::builder().method(method).uri(path);
+            if let Some(selector) = selector {
+                builder = builder.header("range", format!("selector={}", urlencoding::encode(selector)));
+            }
+            let mut request = PluginRequest::new(builder.body(Body::from(body)).unwrap(), path.to_string());
+            request.metadata.insert("authenticated_user".to_string(), user.to_string());
+            let plugin = &plugin;
+            let context = &context;
+            async move {
+                plugin.handle_request(&mut request, context).await
+                    .map(|response| response.response)
+            }
+        };
+        let status = |response: Option<Response<Body>>| response.map(|response| response.status());
+        
+        // Only administrators get the page, which leaves out passwords
+        assert_eq!(status(send("bob", "GET", "/auth/admin", None, String::new()).await), Some(StatusCode::FORBIDDEN));
+        let page = send("alice", "GET", "/auth/admin", None, String::new()).await.unwrap();
+        assert_eq!(page.status(), StatusCode::OK);
+        let page = String::from_utf8(hyper::body::to_bytes(page.into_body()).await.unwrap().to_vec()).unwrap();
+        assert!(page.contains("<td>bob</td><td>users</td>"));
+        assert!(!page.contains("<td>secret</td>"));
+        assert!(page.contains(r#"data-url="/auth.html" data-selector="html &gt; body:nth-child(2) &gt; div:nth-child(2)""#));
+        
+        // Edits keeping an administrator able to write the file pass
+        let promoted = credential("bob", "bob", "administrators");
+        assert!(send("alice", "PUT", "/auth.html", Some("#bob"), promoted).await.is_none());
+        
+        // Edits locking every administrator out are refused
+        assert_eq!(status(send("alice", "DELETE", "/auth.html", Some("#admin"), String::new()).await), Some(StatusCode::CONFLICT));
+        assert_eq!(status(send("alice", "PUT", "/auth.html", Some("#rule"), rule("administrators", "deny")).await), Some(StatusCode::CONFLICT));
+        assert_eq!(status(send("alice", "PUT", "/auth.html", None, String::new()).await), Some(StatusCode::CONFLICT));
+        
+        // As are edits whose outcome can't be worked out
+        assert_eq!(status(send("alice", "PUT", "/auth.html", Some("#missing"), String::new()).await), Some(StatusCode::UNPROCESSABLE_ENTITY));
+        assert_eq!(status(send("alice", "PATCH", "/auth.html", None, String::new()).await), Some(StatusCode::UNPROCESSABLE_ENTITY));
+        
+        // Copies and moves onto the file, and moves of it, are too
+        let relocate = |method: &str, path: &str, destination: &str| {
+            let mut request = PluginRequest::new(
+                Request::builder().method(method).uri(path).header("Destination", destination).body(Body::empty()).unwrap(),
+                path.to_string(),
+            );
+            request.metadata.insert("authenticated_user".to_string(), "alice".to_string());
+            let plugin = &plugin;
+            let context = &context;
+            async move {
+                plugin.handle_request(&mut request, context).await
+                    .map(|response| response.response.status())
+            }
+        };
+        fs::write(directory.join("page.html"), "<html><body></body></html>").unwrap();
+        assert_eq!(relocate("COPY", "/page.html", "/auth.html").await, Some(StatusCode::UNPROCESSABLE_ENTITY));
+        assert_eq!(relocate("MOVE", "/page.html", "http://localhost/./auth.html").await, Some(StatusCode::UNPROCESSABLE_ENTITY));
+        assert_eq!(relocate("COPY", "/page.html", "/%61uth.html").await, Some(StatusCode::UNPROCESSABLE_ENTITY));
+        assert_eq!(relocate("MOVE", "/auth.html", "/moved.html").await, Some(StatusCode::UNPROCESSABLE_ENTITY));
+        assert_eq!(relocate("COPY", "/auth.html", "/copy.html").await, None);
+        assert_eq!(relocate("MOVE", "/page.html", "/moved.html").await, None);
+        
+        // Other files are left alone
+        assert!(send("alice", "DELETE", "/page.html", None, String::new()).await.is_none());
+        
+        fs::remove_dir_all(&directory).unwrap();
+    }
+    
+    #[test]
+    fn test_testing_harness_decides_built_auth_files() {
+        let harness = testing::AuthHarness::new(&testing::AuthFile::new()
+            .user("alice", &["editors"])
+            .user("bob", &["users"])
+            .rule(testing::Rule::allow("*").methods("read").id("public-read"))
+            .rule(testing::Rule::allow("editors").path("/drafts/*").methods("write").id("edit-drafts"))
+            .rule(testing::Rule::deny("alice").path("/drafts/locked.html").methods("write").id("locked"))
+            .rule(testing::Rule::allow("users").path("/notes.html").methods("PUT").selector("#notes").id("notes"))
+            .policy(testing::Policy::allow("/public/*").methods("write")))
+            .with_document("/notes.html", r#"<html><body><div id="notes">Notes</div><div id="other"></div></body></html>"#);
+
+        assert_eq!(crate::assert_allowed!(harness, testing::TestRequest::get("/index.html")).rule_id(), Some("public-read"));
+        crate::assert_allowed!(harness, testing::TestRequest::put("/drafts/post.html").as_user("alice"), "editors write drafts");
+        let denied = crate::assert_denied!(harness, testing::TestRequest::put("/drafts/locked.html").as_user("alice"));
+        assert_eq!(denied.code, Some("denied_by_rule"));
+        assert_eq!(denied.rule_id(), Some("locked"));
+        crate::assert_denied!(harness, testing::TestRequest::put("/drafts/post.html").as_user("bob"));
+        assert_eq!(crate::assert_denied!(harness, testing::TestRequest::put("/drafts/post.html")).code, Some("no_matching_rule"));
+
+        // Roles from an authentication plugin count for users the files don't list
+        crate::assert_allowed!(harness, testing::TestRequest::put("/drafts/post.html").as_user("carol@example.com").with_roles(&["editors"]));
+
+        // Selector rules are checked against the documents
+        crate::assert_allowed!(harness, testing::TestRequest::put("/notes.html").as_user("bob").selector("#notes"));
+        crate::assert_denied!(harness, testing::TestRequest::put("/notes.html").as_user("bob").selector("#other"));
+
+        // Path policies decide what no rule does
+        crate::assert_allowed!(harness, testing::TestRequest::post("/public/guestbook.html"));
+        assert_eq!(crate::assert_denied!(harness, testing::TestRequest::get("index.html")).code, Some("invalid_path"));
+    }
+
+    #[test]
+    fn test_testing_harness_reads_existing_auth_files_and_config() {
+        let harness = testing::AuthHarness::new(&testing::AuthFile::new()
+            .rule(testing::Rule::allow("users").path("/doc.html").methods("GET").id("allow"))
+            .rule(testing::Rule::deny("users").path("/doc.html").methods("GET").id("deny")));
+        let request = testing::TestRequest::get("/doc.html").as_user("dave").with_roles(&["users"]);
+        crate::assert_allowed!(harness, request);
+        let harness = harness.with_config("conflict", "deny_overrides");
+        assert_eq!(crate::assert_denied!(harness, request).rule_id(), Some("deny"));
+
+        let auth_file = harness.host_root().join("auth.html");
+        let existing = testing::AuthHarness::from_file(&format!("file://{}", auth_file.display()));
+        crate::assert_allowed!(existing, request);
+    }
+
+    #[test]
+    fn test_testing_rules_can_expire() {
+        let harness = testing::AuthHarness::new(&testing::AuthFile::new()
+            .rule(testing::Rule::allow("*").methods("read").id("old-grant").expires("2001-01-01"))
+            .rule(testing::Rule::allow("*").methods("read").id("grant").expires("2999-01-01T00:00:00Z")));
+        assert_eq!(crate::assert_allowed!(harness, testing::TestRequest::get("/index.html")).rule_id(), Some("grant"));
+    }
+
+    #[test]
+    fn test_required_headers_condition_rules() {
+        let harness = testing::AuthHarness::new(&testing::AuthFile::new()
+            .rule(testing::Rule::allow("*").path("/webhooks/*").methods("POST").required_header("X-Webhook-Signature").id("signed"))
+            .rule(testing::Rule::allow("*").path("/hooks/*").methods("POST").required_header("X-Source: github").id("github")));
+        let webhook = testing::TestRequest::post("/webhooks/build");
+        crate::assert_denied!(harness, webhook);
+        assert_eq!(crate::assert_allowed!(harness, webhook.clone().header("x-webhook-signature", "sha256=abc")).rule_id(), Some("signed"));
+        crate::assert_allowed!(harness, testing::TestRequest::post("/hooks/push").header("X-Source", "github"));
+        crate::assert_denied!(harness, testing::TestRequest::post("/hooks/push").header("X-Source", "gitlab"));
+
+        assert_eq!(RequiredHeader::parse("X-Source: github"), Some(RequiredHeader { name: "X-Source".to_string(), value: Some("github".to_string()) }));
+        assert_eq!(RequiredHeader::parse("Bad Header"), None);
+    }
+
+    #[test]
+    #[should_panic(expected = "expected PUT /index.html anonymously to be allowed, but it was denied (no_matching_rule)")]
+    fn test_testing_failed_assertions_describe_the_decision() {
+        let harness = testing::AuthHarness::new(&testing::AuthFile::new());
+        crate::assert_allowed!(harness, testing::TestRequest::put("/index.html"));
+    }
+}
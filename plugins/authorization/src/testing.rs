@@ -0,0 +1,576 @@
+//! Support for testing auth files against the plugin's own rule evaluation
+//!
+//! Enabled with the `test-support` feature, so a site can check in CI that
+//! its auth files allow and deny what it means them to, with the same code
+//! that decides requests in the server:
+//!
+//! ```ignore
+//! use rusty_beam_authorization::testing::{AuthFile, AuthHarness, Rule, TestRequest};
+//! use rusty_beam_authorization::{assert_allowed, assert_denied};
+//!
+//! let harness = AuthHarness::from_file("site/auth/users.html").with_host_root("site");
+//! assert_allowed!(harness, TestRequest::get("/index.html"));
+//! assert_denied!(harness, TestRequest::put("/index.html").as_user("alice"));
+//!
+//! let harness = AuthHarness::new(&AuthFile::new()
+//!     .user("alice", &["editors"])
+//!     .rule(Rule::allow("editors").path("/drafts/*").methods("write").id("edit-drafts")));
+//! assert_allowed!(harness, TestRequest::put("/drafts/post.html").as_user("alice"));
+//! ```
+//!
+//! A request is decided as the plugin decides a request reaching it: by the
+//! best matching rule, then by the path policies, for the user the request
+//! names or the anonymous user. Roles given with `with_roles` stand in for
+//! those an authentication plugin such as OAuth2 assigns, and are only used
+//! for users the auth files don't list. Selector rules are checked against
+//! the documents under the host root, which `with_document` can write.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use rusty_beam_plugin_api::{paths, PluginContext};
+
+use super::{
+    html_escape, stand_in_request, AuthorizationPlugin, AuthorizationRule, Permission,
+    SCHEMA_AUTHORIZATION_RULE, SCHEMA_CREDENTIAL, SCHEMA_PATH_POLICY, USERNAME_WILDCARD,
+};
+
+/// Name of the auth file a harness writes for an `AuthFile`
+const AUTH_FILE_NAME: &str = "auth.html";
+
+/// Harnesses made so far, to give each its own directory
+static HARNESS_COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// An auth file built in code, written as the microdata the plugin reads
+#[derive(Debug, Clone, Default)]
+pub struct AuthFile {
+    items: Vec<String>,
+}
+
+impl AuthFile {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a user with roles
+    pub fn user(mut self, username: &str, roles: &[&str]) -> Self {
+        let mut html = property("username", username);
+        for role in roles {
+            html.push_str(&property("role", role));
+        }
+        self.items.push(item(SCHEMA_CREDENTIAL, &html));
+        self
+    }
+
+    /// Add a rule, after those added before it
+    pub fn rule(mut self, rule: Rule) -> Self {
+        self.items.push(rule.to_html());
+        self
+    }
+
+    /// Add a path policy
+    pub fn policy(mut self, policy: Policy) -> Self {
+        self.items.push(policy.to_html());
+        self
+    }
+
+    /// Include another auth file, relative to this one
+    pub fn include(mut self, href: &str) -> Self {
+        self.items.push(format!(r#"<link itemprop="include" href="{}">"#, html_escape(href)));
+        self
+    }
+
+    /// The auth file as an HTML document
+    pub fn to_html(&self) -> String {
+        format!("<!DOCTYPE html>\n<html>\n<body>\n{}\n</body>\n</html>\n", self.items.join("\n"))
+    }
+}
+
+/// An authorization rule for an `AuthFile`
+///
+/// A rule applies to every path and method until `path` and `methods`
+/// narrow it.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    subject: String,
+    action: Permission,
+    path: String,
+    methods: Vec<String>,
+    selector: Option<String>,
+    ownership_selector: Option<String>,
+    require_exact_match: bool,
+    required_headers: Vec<String>,
+    exclude: Vec<String>,
+    id: Option<String>,
+    expires: Option<String>,
+}
+
+impl Rule {
+    /// A rule allowing a user, a role, `:username` or `*`
+    pub fn allow(subject: &str) -> Self {
+        Self::new(subject, Permission::Allow)
+    }
+
+    /// A rule denying a user, a role, `:username` or `*`
+    pub fn deny(subject: &str) -> Self {
+        Self::new(subject, Permission::Deny)
+    }
+
+    fn new(subject: &str, action: Permission) -> Self {
+        Self {
+            subject: subject.to_string(),
+            action,
+            path: "/*".to_string(),
+            methods: Vec::new(),
+            selector: None,
+            ownership_selector: None,
+            require_exact_match: false,
+            required_headers: Vec::new(),
+            exclude: Vec::new(),
+            id: None,
+            expires: None,
+        }
+    }
+
+    /// Path pattern the rule covers, such as `/drafts/*`
+    pub fn path(mut self, path: &str) -> Self {
+        self.path = path.to_string();
+        self
+    }
+
+    /// Comma-separated methods or groups the rule covers, such as `read`
+    /// or `PUT, DELETE` (default: `*`)
+    pub fn methods(mut self, methods: &str) -> Self {
+        self.methods = methods.split(',')
+            .map(|method| method.trim().to_string())
+            .filter(|method| !method.is_empty())
+            .collect();
+        self
+    }
+
+    /// Selector the request's selector must pick elements of
+    pub fn selector(mut self, selector: &str) -> Self {
+        self.selector = Some(selector.to_string());
+        self
+    }
+
+    /// Selector every selected element must match or contain
+    pub fn ownership_selector(mut self, selector: &str) -> Self {
+        self.ownership_selector = Some(selector.to_string());
+        self
+    }
+
+    /// Require the request's selector to pick exactly the rule's elements
+    pub fn require_exact_match(mut self) -> Self {
+        self.require_exact_match = true;
+        self
+    }
+
+    /// Header the request must carry, as `Name` or `Name: value`
+    pub fn required_header(mut self, header: &str) -> Self {
+        self.required_headers.push(header.to_string());
+        self
+    }
+
+    /// Leave out a user or role the rule would otherwise cover
+    pub fn exclude(mut self, subject: &str) -> Self {
+        self.exclude.push(subject.to_string());
+        self
+    }
+
+    pub fn id(mut self, id: &str) -> Self {
+        self.id = Some(id.to_string());
+        self
+    }
+
+    /// When the rule stops applying, as an RFC 3339 time or a date
+    pub fn expires(mut self, expires: &str) -> Self {
+        self.expires = Some(expires.to_string());
+        self
+    }
+
+    fn to_html(&self) -> String {
+        let mut html = property("username", &self.subject);
+        html.push_str(&property("path", &self.path));
+        if self.methods.is_empty() {
+            html.push_str(&property("method", "*"));
+        }
+        for method in &self.methods {
+            html.push_str(&property("method", method));
+        }
+        if let Some(selector) = &self.selector {
+            html.push_str(&property("selector", selector));
+        }
+        if let Some(selector) = &self.ownership_selector {
+            html.push_str(&property("ownershipSelector", selector));
+        }
+        if self.require_exact_match {
+            html.push_str(&property("requireExactMatch", "true"));
+        }
+        for header in &self.required_headers {
+            html.push_str(&property("requiredHeader", header));
+        }
+        for excluded in &self.exclude {
+            html.push_str(&property("exclude", excluded));
+        }
+        html.push_str(&property("action", self.action.as_str()));
+        if let Some(id) = &self.id {
+            html.push_str(&property("id", id));
+        }
+        if let Some(expires) = &self.expires {
+            html.push_str(&property("expires", expires));
+        }
+        item(SCHEMA_AUTHORIZATION_RULE, &html)
+    }
+}
+
+/// A path policy for an `AuthFile`, deciding requests no rule matches
+#[derive(Debug, Clone)]
+pub struct Policy {
+    path: String,
+    action: Permission,
+    methods: Vec<String>,
+}
+
+impl Policy {
+    /// Allow what no rule decides below a path pattern
+    pub fn allow(path: &str) -> Self {
+        Self { path: path.to_string(), action: Permission::Allow, methods: Vec::new() }
+    }
+
+    /// Deny what no rule decides below a path pattern
+    pub fn deny(path: &str) -> Self {
+        Self { path: path.to_string(), action: Permission::Deny, methods: Vec::new() }
+    }
+
+    /// Comma-separated methods or groups the policy is limited to
+    pub fn methods(mut self, methods: &str) -> Self {
+        self.methods = methods.split(',')
+            .map(|method| method.trim().to_string())
+            .filter(|method| !method.is_empty())
+            .collect();
+        self
+    }
+
+    fn to_html(&self) -> String {
+        let mut html = property("path", &self.path);
+        for method in &self.methods {
+            html.push_str(&property("method", method));
+        }
+        html.push_str(&property("defaultAction", self.action.as_str()));
+        item(SCHEMA_PATH_POLICY, &html)
+    }
+}
+
+/// A request to decide, anonymous unless `as_user` names a user
+#[derive(Debug, Clone)]
+pub struct TestRequest {
+    method: String,
+    path: String,
+    selector: Option<String>,
+    user: Option<String>,
+    roles: Vec<String>,
+    headers: Vec<(String, String)>,
+}
+
+impl TestRequest {
+    pub fn new(method: &str, path: &str) -> Self {
+        Self {
+            method: method.trim().to_uppercase(),
+            path: path.to_string(),
+            selector: None,
+            user: None,
+            roles: Vec::new(),
+            headers: Vec::new(),
+        }
+    }
+
+    pub fn get(path: &str) -> Self {
+        Self::new("GET", path)
+    }
+
+    pub fn put(path: &str) -> Self {
+        Self::new("PUT", path)
+    }
+
+    pub fn post(path: &str) -> Self {
+        Self::new("POST", path)
+    }
+
+    pub fn patch(path: &str) -> Self {
+        Self::new("PATCH", path)
+    }
+
+    pub fn delete(path: &str) -> Self {
+        Self::new("DELETE", path)
+    }
+
+    /// Make the request as an authenticated user
+    pub fn as_user(mut self, username: &str) -> Self {
+        self.user = Some(username.to_string());
+        self
+    }
+
+    /// Roles an authentication plugin gave the user
+    pub fn with_roles(mut self, roles: &[&str]) -> Self {
+        self.roles = roles.iter().map(|role| role.to_string()).collect();
+        self
+    }
+
+    /// Make the request for the elements a selector picks
+    pub fn selector(mut self, selector: &str) -> Self {
+        self.selector = Some(selector.to_string());
+        self
+    }
+
+    /// Send a header with the request
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_string(), value.to_string()));
+        self
+    }
+}
+
+impl fmt::Display for TestRequest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.method, self.path)?;
+        if let Some(selector) = &self.selector {
+            write!(f, " selector '{}'", selector)?;
+        }
+        match &self.user {
+            Some(user) => write!(f, " as '{}'", user)?,
+            None => write!(f, " anonymously")?,
+        }
+        if !self.roles.is_empty() {
+            write!(f, " with roles {}", self.roles.join(", "))?;
+        }
+        for (name, value) in &self.headers {
+            write!(f, " with header {}: {}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// How the plugin decided a request
+#[derive(Debug, Clone)]
+pub struct Decision {
+    pub allowed: bool,
+    /// Why a request was denied, as in JSON denials, such as `denied_by_rule`
+    pub code: Option<&'static str>,
+    /// The rule that decided, or the path policy as a rule, if one did
+    pub rule: Option<AuthorizationRule>,
+}
+
+impl Decision {
+    /// Id of the rule that decided, if it has one
+    pub fn rule_id(&self) -> Option<&str> {
+        self.rule.as_ref().and_then(|rule| rule.id.as_deref())
+    }
+}
+
+impl fmt::Display for Decision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", if self.allowed { "allowed" } else { "denied" })?;
+        if let Some(code) = self.code {
+            write!(f, " ({})", code)?;
+        }
+        if let Some(rule) = &self.rule {
+            write!(f, " by {}", rule.label())?;
+        }
+        Ok(())
+    }
+}
+
+/// An authorization plugin set up with auth files, deciding test requests
+#[derive(Debug)]
+pub struct AuthHarness {
+    config: HashMap<String, String>,
+    plugin: AuthorizationPlugin,
+    host_root: PathBuf,
+    /// Directory the harness made and removes when dropped
+    scratch: Option<PathBuf>,
+}
+
+impl AuthHarness {
+    /// A harness for an auth file built in code, written with any documents
+    /// to a directory of its own that is also the host root
+    pub fn new(auth_file: &AuthFile) -> Self {
+        let directory = std::env::temp_dir().join(format!(
+            "rusty-beam-authz-harness-{}-{}",
+            std::process::id(),
+            HARNESS_COUNT.fetch_add(1, Ordering::Relaxed),
+        ));
+        fs::create_dir_all(&directory).expect("create the harness directory");
+        let auth_path = directory.join(AUTH_FILE_NAME);
+        fs::write(&auth_path, auth_file.to_html()).expect("write the auth file");
+
+        let mut harness = Self::from_file(&auth_path.to_string_lossy()).with_host_root(&directory.to_string_lossy());
+        harness.scratch = Some(directory);
+        harness
+    }
+
+    /// A harness for existing auth files, given as the plugin's `authfile`,
+    /// with the working directory as host root
+    pub fn from_file(authfile: &str) -> Self {
+        let config = HashMap::from([("authfile".to_string(), authfile.to_string())]);
+        Self {
+            plugin: AuthorizationPlugin::new(config.clone()),
+            config,
+            host_root: PathBuf::from("."),
+            scratch: None,
+        }
+    }
+
+    /// Set another plugin configuration value, such as `conflict`
+    pub fn with_config(mut self, key: &str, value: &str) -> Self {
+        self.config.insert(key.to_string(), value.to_string());
+        self.plugin = AuthorizationPlugin::new(self.config.clone());
+        self
+    }
+
+    /// Directory selector rules find documents in
+    pub fn with_host_root(mut self, host_root: &str) -> Self {
+        self.host_root = PathBuf::from(host_root.strip_prefix("file://").unwrap_or(host_root));
+        self
+    }
+
+    /// Write a document under the host root for selector rules to check
+    pub fn with_document(self, path: &str, html: &str) -> Self {
+        let file = self.host_root.join(path.trim_start_matches('/'));
+        if let Some(directory) = file.parent() {
+            fs::create_dir_all(directory).expect("create the document's directory");
+        }
+        fs::write(&file, html).expect("write the document");
+        self
+    }
+
+    /// The host root, where `with_document` writes
+    pub fn host_root(&self) -> &Path {
+        &self.host_root
+    }
+
+    /// Decide a request as the plugin would
+    ///
+    /// A request whose path can't be normalized is denied with the code
+    /// `invalid_path`.
+    pub fn decide(&self, request: &TestRequest) -> Decision {
+        let invalid = Decision { allowed: false, code: Some("invalid_path"), rule: None };
+        let Ok(path) = paths::normalize_request_path(&request.path) else { return invalid };
+        let Some(mut target) = stand_in_request(&request.method, &path, request.selector.as_deref()) else {
+            return invalid;
+        };
+        let user = request.user.clone().unwrap_or_else(|| USERNAME_WILDCARD.to_string());
+        if let Some(user) = &request.user {
+            target.metadata.insert("authenticated_user".to_string(), user.clone());
+        }
+        if !request.roles.is_empty() {
+            target.metadata.insert("authenticated_user_roles".to_string(), request.roles.join(","));
+        }
+        for (name, value) in &request.headers {
+            let header = hyper::header::HeaderName::from_bytes(name.as_bytes()).ok()
+                .zip(hyper::header::HeaderValue::from_str(value).ok());
+            let Some((name, value)) = header else {
+                return Decision { allowed: false, code: Some("invalid_header"), rule: None };
+            };
+            target.http_request.headers_mut().append(name, value);
+        }
+
+        match self.plugin.authorize(&user, &target, &request.method, &self.context()) {
+            Ok(rule) => Decision { allowed: true, code: None, rule: Some(rule) },
+            Err(denial) => Decision {
+                allowed: false,
+                code: Some(denial.code.as_str()),
+                rule: denial.rule.map(|rule| *rule),
+            },
+        }
+    }
+
+    /// Panic unless a request is allowed; see `assert_allowed!`
+    #[track_caller]
+    pub fn assert_allowed(&self, request: &TestRequest, message: Option<fmt::Arguments<'_>>) -> Decision {
+        self.assert_decision(request, true, message)
+    }
+
+    /// Panic unless a request is denied; see `assert_denied!`
+    #[track_caller]
+    pub fn assert_denied(&self, request: &TestRequest, message: Option<fmt::Arguments<'_>>) -> Decision {
+        self.assert_decision(request, false, message)
+    }
+
+    #[track_caller]
+    fn assert_decision(&self, request: &TestRequest, allowed: bool, message: Option<fmt::Arguments<'_>>) -> Decision {
+        let decision = self.decide(request);
+        if decision.allowed != allowed {
+            let expected = if allowed { "allowed" } else { "denied" };
+            match message {
+                Some(message) => panic!("expected {} to be {}, but it was {}: {}", request, expected, decision, message),
+                None => panic!("expected {} to be {}, but it was {}", request, expected, decision),
+            }
+        }
+        decision
+    }
+
+    fn context(&self) -> PluginContext {
+        PluginContext {
+            plugin_config: self.config.clone(),
+            server_config: HashMap::new(),
+            server_metadata: HashMap::new(),
+            host_config: HashMap::from([("hostRoot".to_string(), self.host_root.to_string_lossy().into_owned())]),
+            host_name: "localhost".to_string(),
+            request_id: "authorization-test".to_string(),
+            runtime_handle: None,
+            verbose: false,
+            services: None,
+            deadline: None,
+        }
+    }
+}
+
+impl Drop for AuthHarness {
+    fn drop(&mut self) {
+        if let Some(directory) = &self.scratch {
+            let _ = fs::remove_dir_all(directory);
+        }
+    }
+}
+
+/// Assert that a harness allows a request, returning the decision
+///
+/// ```ignore
+/// assert_allowed!(harness, TestRequest::get("/index.html"));
+/// assert_allowed!(harness, TestRequest::put("/drafts/a.html").as_user("alice"), "editors write drafts");
+/// ```
+#[macro_export]
+macro_rules! assert_allowed {
+    ($harness:expr, $request:expr $(,)?) => {
+        $harness.assert_allowed(&$request, None)
+    };
+    ($harness:expr, $request:expr, $($message:tt)+) => {
+        $harness.assert_allowed(&$request, Some(format_args!($($message)+)))
+    };
+}
+
+/// Assert that a harness denies a request, returning the decision
+///
+/// ```ignore
+/// assert_denied!(harness, TestRequest::delete("/index.html").as_user("bob"));
+/// ```
+#[macro_export]
+macro_rules! assert_denied {
+    ($harness:expr, $request:expr $(,)?) => {
+        $harness.assert_denied(&$request, None)
+    };
+    ($harness:expr, $request:expr, $($message:tt)+) => {
+        $harness.assert_denied(&$request, Some(format_args!($($message)+)))
+    };
+}
+
+fn item(item_type: &str, properties: &str) -> String {
+    format!(r#"<div itemscope itemtype="{}">{}</div>"#, item_type, properties)
+}
+
+fn property(name: &str, value: &str) -> String {
+    format!(r#"<span itemprop="{}">{}</span>"#, name, html_escape(value))
+}
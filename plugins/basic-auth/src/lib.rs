@@ -51,7 +51,8 @@
 //! - **Access Log Plugin**: Log authenticated usernames
 //! - **Rate Limit Plugin**: Apply per-user rate limits
 
-use rusty_beam_plugin_api::{Plugin, PluginRequest, PluginContext, PluginResponse, create_plugin};
+use rusty_beam_plugin_api::{capabilities, Capabilities, Plugin, PluginRequest, PluginContext, PluginResponse, ConfigIssue, create_plugin};
+use rusty_beam_plugin_api::validation::check_readable_file;
 use async_trait::async_trait;
 use hyper::{Body, Response, StatusCode, header::{AUTHORIZATION, WWW_AUTHENTICATE}};
 use std::collections::HashMap;
@@ -321,6 +322,26 @@ impl Plugin for BasicAuthPlugin {
         }
     }
     
+    fn validate_config(&self, _context: &PluginContext) -> Vec<ConfigIssue> {
+        // Without a readable file the built-in demonstration credentials are accepted
+        let Some(auth_file) = &self.auth_file else {
+            return vec![ConfigIssue::error("no authfile is configured, so the built-in demonstration credentials are accepted")];
+        };
+        match check_readable_file("authfile", auth_file) {
+            Some(issue) => vec![ConfigIssue::error(
+                format!("{}, so the built-in demonstration credentials are accepted", issue.message),
+            )],
+            None if self.load_credentials_from_file(auth_file).is_none_or(|credentials| credentials.is_empty()) => {
+                vec![ConfigIssue::warning(format!("authfile {} defines no credentials", auth_file))]
+            }
+            None => Vec::new(),
+        }
+    }
+    
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::new(&[capabilities::AUTHENTICATION], &[])
+    }
+    
     fn name(&self) -> &str {
         &self.name
     }
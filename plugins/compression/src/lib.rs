@@ -35,14 +35,21 @@
 //! - **SEO Benefits**: Google considers page speed in search rankings
 //! - **Cost Savings**: Reduced bandwidth usage lowers hosting costs
 //!
+//! ## Streamed Responses
+//! Responses with a `Content-Length` are compressed whole, within the size
+//! limits. Responses without one, such as streamed or chunked bodies, are
+//! compressed chunk by chunk as they are sent, whatever their size, and each
+//! chunk is flushed so clients receive data as soon as it is produced.
+//!
 //! ## Algorithm Selection Priority
 //! 1. **Brotli**: Best compression, preferred for modern browsers
 //! 2. **Gzip**: Excellent compatibility and performance
 //! 3. **Deflate**: Fallback for legacy client support
 
-use rusty_beam_plugin_api::{Plugin, PluginRequest, PluginContext, PluginResponse, create_plugin};
+use rusty_beam_plugin_api::{Plugin, PluginRequest, PluginContext, PluginResponse, create_plugin, map_body_stream, transform_body};
 use async_trait::async_trait;
-use hyper::{Body, Response, header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH}};
+use hyper::{Body, HeaderMap, Method, Response, StatusCode, header::{HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH}};
+use hyper::body::Bytes;
 use std::collections::HashMap;
 use std::io::Write;
 use flate2::{Compression, write::GzEncoder, write::DeflateEncoder};
@@ -188,19 +195,15 @@ impl CompressionPlugin {
     }
     
     /// Update response headers for compressed content
-    fn update_response_headers(&self, response: &mut Response<Body>, compressed_data: &[u8], encoding_name: &str) {
-        let headers = response.headers_mut();
-        
+    /// 
+    /// Content-Length is maintained by `transform_body` when the body is replaced.
+    fn update_response_headers(&self, headers: &mut HeaderMap, encoding_name: &str) {
+
         // Set Content-Encoding header
         if let Ok(encoding_value) = HeaderValue::from_str(encoding_name) {
             headers.insert(CONTENT_ENCODING, encoding_value);
         }
         
-        // Update Content-Length header
-        if let Ok(length_value) = HeaderValue::from_str(&compressed_data.len().to_string()) {
-            headers.insert(CONTENT_LENGTH, length_value);
-        }
-        
         // Add Vary header to indicate response varies by Accept-Encoding
         headers.insert("Vary", HeaderValue::from_static("Accept-Encoding"));
     }
@@ -215,41 +218,25 @@ impl CompressionPlugin {
         self.get_preferred_encoding(accept_encoding)
     }
     
-    /// Extract response body as bytes
-    async fn extract_response_body(&self, response: &mut Response<Body>) -> Option<hyper::body::Bytes> {
-        let body = std::mem::replace(response.body_mut(), Body::empty());
-        hyper::body::to_bytes(body).await.ok()
-    }
-    
-    /// Apply compression to response body and update headers
-    fn apply_compression(
+    /// Compress a response body, returning None if it should be left as-is
+    fn compress_body(
         &self,
-        response: &mut Response<Body>,
-        body_bytes: hyper::body::Bytes,
+        body_bytes: &[u8],
         algorithm: &CompressionAlgorithm,
         context: &PluginContext,
-    ) -> Result<(), hyper::body::Bytes> {
-        // Attempt compression
-        let compressed_data = self.compress_data(&body_bytes, algorithm)
-            .map_err(|_| body_bytes.clone())?;
-        
-        // Calculate compression statistics
-        let stats = CompressionStats::new(&body_bytes, &compressed_data, algorithm);
+    ) -> Option<Vec<u8>> {
+        if !self.is_within_size_limits(body_bytes.len()) {
+            return None;
+        }
         
-        // Update response
-        let encoding_name = Self::algorithm_to_encoding_name(algorithm);
-        self.update_response_headers(response, &compressed_data, encoding_name);
-        *response.body_mut() = Body::from(compressed_data);
+        // Attempt compression
+        let compressed_data = self.compress_data(body_bytes, algorithm).ok()?;
         
         // Log success
+        let stats = CompressionStats::new(body_bytes, &compressed_data, algorithm);
         self.log_compression_success(&stats, context);
         
-        Ok(())
-    }
-    
-    /// Restore original response body
-    fn restore_original_body(&self, response: &mut Response<Body>, body_bytes: hyper::body::Bytes) {
-        *response.body_mut() = Body::from(body_bytes);
+        Some(compressed_data)
     }
     
     /// Log successful compression
@@ -273,20 +260,20 @@ impl CompressionPlugin {
         false
     }
     
-    /// Check if response should be compressed
-    fn should_compress(&self, response: &Response<Body>, body_size: usize) -> bool {
-        // Check size constraints
-        if body_size < self.min_size || body_size > self.max_size {
-            return false;
-        }
-        
+    /// Check if the body size falls within the configured limits
+    fn is_within_size_limits(&self, body_size: usize) -> bool {
+        body_size >= self.min_size && body_size <= self.max_size
+    }
+    
+    /// Check if response headers allow compression
+    fn should_compress(&self, headers: &HeaderMap) -> bool {
         // Check if already compressed
-        if response.headers().contains_key(CONTENT_ENCODING) {
+        if headers.contains_key(CONTENT_ENCODING) {
             return false;
         }
         
         // Check content type
-        if let Some(content_type) = response.headers().get("content-type") {
+        if let Some(content_type) = headers.get("content-type") {
             if let Ok(content_type_str) = content_type.to_str() {
                 if !self.is_compressible_type(content_type_str) {
                     return false;
@@ -337,34 +324,110 @@ impl Plugin for CompressionPlugin {
             None => return, // No supported encoding or compression not needed
         };
         
-        // Extract and validate response body
-        let body_bytes = match self.extract_response_body(response).await {
-            Some(bytes) => bytes,
-            None => return, // Cannot process body
-        };
-        
-        // Check compression eligibility
-        if !self.should_compress(response, body_bytes.len()) {
-            self.restore_original_body(response, body_bytes);
+        // Check compression eligibility before buffering the body; bodies of
+        // unknown length are compressed as they stream instead
+        if !self.should_compress(response.headers()) || !response.headers().contains_key(CONTENT_LENGTH) {
             return;
         }
         
-        // Perform compression
-        match self.apply_compression(response, body_bytes, &preferred_encoding, context) {
-            Ok(_) => {}, // Compression successful
-            Err(original_body) => {
-                // Compression failed, restore original content
-                self.restore_original_body(response, original_body);
-            }
+        // Compress the body; it is restored unchanged if compression is skipped or fails
+        let compressed = transform_body(response, |body_bytes| {
+            self.compress_body(body_bytes, &preferred_encoding, context)
+        }).await;
+        
+        if let Ok(true) = compressed {
+            let encoding_name = Self::algorithm_to_encoding_name(&preferred_encoding);
+            self.update_response_headers(response.headers_mut(), encoding_name);
         }
     }
     
+    async fn handle_response_stream(
+        &self,
+        request: &PluginRequest,
+        head: &mut hyper::http::response::Parts,
+        body: Body,
+        context: &PluginContext,
+    ) -> Body {
+        let Some(preferred_encoding) = self.determine_compression_encoding(request) else {
+            return body;
+        };
+        let has_body = request.http_request.method() != Method::HEAD
+            && !head.status.is_informational()
+            && head.status != StatusCode::NO_CONTENT
+            && head.status != StatusCode::NOT_MODIFIED;
+        if !has_body || head.headers.contains_key(CONTENT_LENGTH) || !self.should_compress(&head.headers) {
+            return body;
+        }
+        
+        let encoding_name = Self::algorithm_to_encoding_name(&preferred_encoding);
+        self.update_response_headers(&mut head.headers, encoding_name);
+        context.log_verbose(&format!("[Compression] Compressing streamed body using {}", encoding_name));
+        
+        let mut encoder = StreamEncoder::new(&preferred_encoding, self.compression_level);
+        map_body_stream(body, move |chunk| encoder.encode(chunk))
+    }
+    
     
     fn name(&self) -> &str {
         &self.name
     }
 }
 
+/// An encoder compressing a body as it streams
+enum StreamEncoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Deflate(DeflateEncoder<Vec<u8>>),
+    /// None once the stream has been finished
+    Brotli(Option<Box<CompressorWriter<Vec<u8>>>>),
+}
+
+impl StreamEncoder {
+    fn new(algorithm: &CompressionAlgorithm, level: u32) -> Self {
+        match algorithm {
+            CompressionAlgorithm::Gzip => Self::Gzip(GzEncoder::new(Vec::new(), Compression::new(level))),
+            CompressionAlgorithm::Deflate => Self::Deflate(DeflateEncoder::new(Vec::new(), Compression::new(level))),
+            CompressionAlgorithm::Brotli => Self::Brotli(Some(Box::new(CompressorWriter::new(Vec::new(), 4096, level, 22)))),
+        }
+    }
+    
+    /// Compress a chunk and flush it, or finish the stream on None, returning
+    /// the compressed bytes produced so far
+    /// 
+    /// Writes into memory do not fail, so errors are not expected here.
+    fn encode(&mut self, chunk: Option<Bytes>) -> Bytes {
+        let output = match (self, chunk) {
+            (Self::Gzip(encoder), Some(chunk)) => {
+                let _ = encoder.write_all(&chunk);
+                let _ = encoder.flush();
+                std::mem::take(encoder.get_mut())
+            }
+            (Self::Gzip(encoder), None) => {
+                let _ = encoder.try_finish();
+                std::mem::take(encoder.get_mut())
+            }
+            (Self::Deflate(encoder), Some(chunk)) => {
+                let _ = encoder.write_all(&chunk);
+                let _ = encoder.flush();
+                std::mem::take(encoder.get_mut())
+            }
+            (Self::Deflate(encoder), None) => {
+                let _ = encoder.try_finish();
+                std::mem::take(encoder.get_mut())
+            }
+            (Self::Brotli(encoder), Some(chunk)) => match encoder {
+                Some(encoder) => {
+                    let _ = encoder.write_all(&chunk);
+                    let _ = encoder.flush();
+                    std::mem::take(encoder.get_mut())
+                }
+                None => Vec::new(),
+            },
+            (Self::Brotli(encoder), None) => encoder.take().map(|encoder| encoder.into_inner()).unwrap_or_default(),
+        };
+        Bytes::from(output)
+    }
+}
+
 /// Compression statistics for logging and monitoring
 struct CompressionStats {
     original_size: usize,
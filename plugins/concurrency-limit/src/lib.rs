@@ -0,0 +1,294 @@
+//! Concurrency Limit Plugin for Rusty Beam
+//!
+//! This plugin caps the number of requests that may be in flight at the same
+//! time, protecting expensive pipelines such as the JavaScript engine or
+//! selector writes from being overwhelmed. Where the rate-limit plugin counts
+//! requests over time, this plugin counts requests that have not finished yet.
+//!
+//! ## Features
+//! - **Semaphore Based**: Each key has a semaphore with `max_concurrent` permits
+//! - **Bounded Queue**: Requests wait for a permit up to `queue_depth` deep
+//! - **Multiple Key Strategies**: Limit per host, per path prefix, or per authenticated user
+//! - **Retry-After Support**: Rejected requests get a 503 telling clients when to retry
+//!
+//! ## Configuration
+//! - `max_concurrent`: Simultaneous requests allowed per key (default: 10)
+//! - `queue_depth`: Requests allowed to wait for a permit per key (default: 20)
+//! - `queue_timeout`: Seconds a queued request waits before being rejected (default: 30)
+//! - `key_strategy`: "host", "path", or "user" (default: "host")
+//! - `path_prefixes`: Comma-separated path prefixes; when set, only requests
+//!   under one of them are limited
+//! - `retry_after`: Seconds sent in `Retry-After` when rejecting (default: 1)
+//! - `hold_timeout`: Seconds after which a permit is reclaimed if its request
+//!   never reached the response phase (default: 300)
+//!
+//! ## Limiting Keys
+//! - **Host Strategy**: One limit for each virtual host
+//! - **Path Strategy**: One limit for each configured path prefix on each host,
+//!   or for each path when no prefixes are configured
+//! - **User Strategy**: One limit for each authenticated user; anonymous
+//!   requests share a single limit
+//!
+//! Several instances can be placed in the pipeline to combine limits, for
+//! example a per-host cap followed by a tighter cap on `/api/`.
+//!
+//! ## Request Lifecycle
+//! A permit is taken in the request phase and released in the response phase,
+//! so it covers every plugin between the two. Requests that are cancelled
+//! before reaching the response phase have their permits reclaimed after
+//! `hold_timeout`.
+
+use rusty_beam_plugin_api::{Plugin, PluginRequest, PluginContext, PluginResponse, create_plugin};
+use async_trait::async_trait;
+use hyper::{Body, Response, StatusCode};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+// Defaults
+const DEFAULT_PLUGIN_NAME: &str = "concurrency-limit";
+const DEFAULT_MAX_CONCURRENT: usize = 10;
+const DEFAULT_QUEUE_DEPTH: usize = 20;
+const DEFAULT_QUEUE_TIMEOUT_SECS: u64 = 30;
+const DEFAULT_RETRY_AFTER_SECS: u64 = 1;
+const DEFAULT_HOLD_TIMEOUT_SECS: u64 = 300;
+
+// Key strategies
+const STRATEGY_HOST: &str = "host";
+const STRATEGY_PATH: &str = "path";
+const STRATEGY_USER: &str = "user";
+const ANONYMOUS_USER: &str = "anonymous";
+
+// Metadata
+const METADATA_KEY: &str = "concurrency_limit_key";
+
+/// Concurrency state for one limiting key
+#[derive(Debug)]
+struct Limiter {
+    semaphore: Arc<Semaphore>,
+    waiting: AtomicUsize,
+}
+
+/// A permit held by a request until its response phase
+#[derive(Debug)]
+struct HeldPermit {
+    _permit: OwnedSemaphorePermit,
+    acquired: Instant,
+}
+
+/// Counts a request as queued for as long as it waits, even if it is cancelled
+struct QueueSlot<'a>(&'a AtomicUsize);
+
+impl Drop for QueueSlot<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Why a request could not be given a permit
+enum Rejection {
+    QueueFull,
+    TimedOut,
+}
+
+/// Plugin for limiting simultaneous in-flight requests
+#[derive(Debug)]
+pub struct ConcurrencyLimitPlugin {
+    name: String,
+    max_concurrent: usize,
+    queue_depth: usize,
+    queue_timeout: Duration,
+    key_strategy: String,
+    path_prefixes: Vec<String>,
+    retry_after: Duration,
+    hold_timeout: Duration,
+    limiters: Mutex<HashMap<String, Arc<Limiter>>>,
+    permits: Mutex<HashMap<String, HeldPermit>>,
+}
+
+impl ConcurrencyLimitPlugin {
+    pub fn new(config: HashMap<String, String>) -> Self {
+        let name = config.get("name").cloned().unwrap_or_else(|| DEFAULT_PLUGIN_NAME.to_string());
+
+        let max_concurrent = config.get("max_concurrent")
+            .and_then(|v| v.parse().ok())
+            .filter(|&max: &usize| max > 0)
+            .unwrap_or(DEFAULT_MAX_CONCURRENT);
+
+        let queue_depth = config.get("queue_depth")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(DEFAULT_QUEUE_DEPTH);
+
+        let queue_timeout = config.get("queue_timeout")
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(DEFAULT_QUEUE_TIMEOUT_SECS));
+
+        let key_strategy = config.get("key_strategy")
+            .cloned()
+            .unwrap_or_else(|| STRATEGY_HOST.to_string());
+
+        let path_prefixes = config.get("path_prefixes")
+            .map(|v| v.split(',')
+                .map(|prefix| prefix.trim().to_string())
+                .filter(|prefix| !prefix.is_empty())
+                .collect())
+            .unwrap_or_default();
+
+        let retry_after = config.get("retry_after")
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(DEFAULT_RETRY_AFTER_SECS));
+
+        let hold_timeout = config.get("hold_timeout")
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs)
+            .unwrap_or(Duration::from_secs(DEFAULT_HOLD_TIMEOUT_SECS));
+
+        Self {
+            name,
+            max_concurrent,
+            queue_depth,
+            queue_timeout,
+            key_strategy,
+            path_prefixes,
+            retry_after,
+            hold_timeout,
+            limiters: Mutex::new(HashMap::new()),
+            permits: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Find the longest configured prefix the request path falls under
+    fn matching_prefix(&self, path: &str) -> Option<&str> {
+        self.path_prefixes.iter()
+            .filter(|prefix| path.starts_with(prefix.as_str()))
+            .max_by_key(|prefix| prefix.len())
+            .map(|prefix| prefix.as_str())
+    }
+
+    /// Extract the limiting key, or None if the request is not limited
+    fn extract_key(&self, request: &PluginRequest, context: &PluginContext) -> Option<String> {
+        let prefix = self.matching_prefix(&request.path);
+        if !self.path_prefixes.is_empty() && prefix.is_none() {
+            return None;
+        }
+
+        let key = match self.key_strategy.as_str() {
+            STRATEGY_PATH => format!("{}{}", context.host_name, prefix.unwrap_or(&request.path)),
+            STRATEGY_USER => request.metadata.get("authenticated_user")
+                .cloned()
+                .unwrap_or_else(|| ANONYMOUS_USER.to_string()),
+            _ => context.host_name.clone(),
+        };
+        Some(key)
+    }
+
+    /// Get or create the limiter for a key
+    fn limiter(&self, key: &str) -> Arc<Limiter> {
+        let mut limiters = self.limiters.lock().unwrap();
+        limiters.entry(key.to_string())
+            .or_insert_with(|| Arc::new(Limiter {
+                semaphore: Arc::new(Semaphore::new(self.max_concurrent)),
+                waiting: AtomicUsize::new(0),
+            }))
+            .clone()
+    }
+
+    /// Take a permit, queueing for one if none is free
+    async fn acquire(&self, limiter: &Limiter, context: &PluginContext) -> Result<OwnedSemaphorePermit, Rejection> {
+        if let Ok(permit) = limiter.semaphore.clone().try_acquire_owned() {
+            return Ok(permit);
+        }
+
+        if limiter.waiting.fetch_add(1, Ordering::SeqCst) >= self.queue_depth {
+            limiter.waiting.fetch_sub(1, Ordering::SeqCst);
+            return Err(Rejection::QueueFull);
+        }
+        let _slot = QueueSlot(&limiter.waiting);
+
+        // Timers need the server's runtime, which this library's copy of tokio
+        // only knows about when it is entered
+        let acquire = {
+            let _runtime = context.runtime_handle.as_ref().map(|handle| handle.enter());
+            tokio::time::timeout(self.queue_timeout, limiter.semaphore.clone().acquire_owned())
+        };
+        match acquire.await {
+            Ok(Ok(permit)) => Ok(permit),
+            // The semaphore is never closed, so only the timeout can fail
+            _ => Err(Rejection::TimedOut),
+        }
+    }
+
+    /// Reclaim permits of requests that never reached the response phase and
+    /// forget limiters that are idle
+    fn cleanup(&self) {
+        let now = Instant::now();
+        self.permits.lock().unwrap()
+            .retain(|_, held| now.duration_since(held.acquired) < self.hold_timeout);
+
+        self.limiters.lock().unwrap().retain(|_, limiter| {
+            limiter.semaphore.available_permits() < self.max_concurrent
+                || limiter.waiting.load(Ordering::SeqCst) > 0
+        });
+    }
+
+    /// Create the response for a rejected request
+    fn create_rejection_response(&self, rejection: Rejection) -> Response<Body> {
+        let message = match rejection {
+            Rejection::QueueFull => "Too many concurrent requests. Please try again later.",
+            Rejection::TimedOut => "Timed out waiting for a free request slot. Please try again later.",
+        };
+        let body = serde_json::json!({
+            "error": "Concurrency limit exceeded",
+            "message": message,
+            "retry_after_seconds": self.retry_after.as_secs()
+        });
+
+        Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header("Content-Type", "application/json")
+            .header("Retry-After", self.retry_after.as_secs().to_string())
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+}
+
+#[async_trait]
+impl Plugin for ConcurrencyLimitPlugin {
+    async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
+        self.cleanup();
+
+        let key = self.extract_key(request, context)?;
+        let limiter = self.limiter(&key);
+
+        match self.acquire(&limiter, context).await {
+            Ok(permit) => {
+                self.permits.lock().unwrap().insert(
+                    context.request_id.clone(),
+                    HeldPermit { _permit: permit, acquired: Instant::now() },
+                );
+                request.metadata.insert(METADATA_KEY.to_string(), key);
+                None
+            }
+            Err(rejection) => {
+                context.log_verbose(&format!("[ConcurrencyLimit] Request rejected for key: {}", key));
+                Some(self.create_rejection_response(rejection).into())
+            }
+        }
+    }
+
+    async fn handle_response(&self, _request: &PluginRequest, _response: &mut Response<Body>, context: &PluginContext) {
+        // Dropping the permit frees the slot for the next request
+        self.permits.lock().unwrap().remove(&context.request_id);
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// Export the plugin creation function
+create_plugin!(ConcurrencyLimitPlugin);
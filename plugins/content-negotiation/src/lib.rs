@@ -0,0 +1,341 @@
+//! Content Negotiation Plugin for Rusty Beam
+//!
+//! This plugin lets clients request alternative representations of HTML
+//! documents through the `Accept` header. Content is stored as HTML only; the
+//! alternative views are produced on the fly in the response phase.
+//!
+//! ## Features
+//! - **Markdown Views**: Converts HTML responses to `text/markdown`
+//! - **JSON Views**: Extracts microdata from HTML responses as `application/json`
+//! - **Quality Values**: Honours `q` parameters and wildcards in the Accept header
+//! - **Cache Friendly**: Adds `Vary: Accept` to every negotiable response
+//!
+//! ## Configuration
+//! - `name`: Plugin instance name (default: "content-negotiation")
+//! - `formats`: Comma-separated list of alternative formats to offer,
+//!   from "markdown" and "json" (default: "markdown,json")
+//!
+//! ## Negotiation Rules
+//! - Only `200 OK` and `206 Partial Content` responses with an HTML
+//!   Content-Type are considered
+//! - HTML is preferred when it ties with another format, so `Accept: */*`
+//!   and browser defaults continue to receive HTML
+//! - Responses to HEAD requests are only given the `Vary` header
+//!
+//! ## JSON Format
+//! The JSON view follows the WHATWG microdata-to-JSON algorithm:
+//! ```json
+//! {"items":[{"type":["https://schema.org/Person"],"properties":{"name":["Alice"]}}]}
+//! ```
+//!
+//! ## Pipeline Integration
+//! Place this plugin after content handlers (file-handler, selector-handler)
+//! and before compression so the converted body is what gets compressed.
+
+use async_trait::async_trait;
+use hyper::{header, Body, Method, Response, StatusCode};
+use microdata_extract::{MicrodataExtractor, MicrodataItem};
+use rusty_beam_plugin_api::{create_plugin, log_warn, transform_body, Plugin, PluginContext, PluginRequest, PluginResponse};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+// Default configuration values
+const DEFAULT_PLUGIN_NAME: &str = "content-negotiation";
+const DEFAULT_FORMATS: &str = "markdown,json";
+
+// Media types
+const MEDIA_TYPE_HTML: &str = "text/html";
+const MEDIA_TYPE_MARKDOWN: &str = "text/markdown";
+const MEDIA_TYPE_JSON: &str = "application/json";
+
+// Response Content-Type values
+const CONTENT_TYPE_MARKDOWN: &str = "text/markdown; charset=utf-8";
+const CONTENT_TYPE_JSON: &str = "application/json";
+
+// Header values
+const VARY_ACCEPT: &str = "Accept";
+
+/// Representations this plugin can produce
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Representation {
+    Html,
+    Markdown,
+    Json,
+}
+
+impl Representation {
+    /// Media type used when matching against the Accept header
+    fn media_type(&self) -> &'static str {
+        match self {
+            Representation::Html => MEDIA_TYPE_HTML,
+            Representation::Markdown => MEDIA_TYPE_MARKDOWN,
+            Representation::Json => MEDIA_TYPE_JSON,
+        }
+    }
+
+    /// Parse a configured format name
+    fn from_format_name(name: &str) -> Option<Self> {
+        match name.trim().to_lowercase().as_str() {
+            "markdown" | "md" => Some(Representation::Markdown),
+            "json" => Some(Representation::Json),
+            _ => None,
+        }
+    }
+}
+
+/// Plugin that converts HTML responses based on the Accept header
+#[derive(Debug)]
+pub struct ContentNegotiationPlugin {
+    name: String,
+    /// Offered representations in server preference order, HTML first
+    representations: Vec<Representation>,
+}
+
+impl ContentNegotiationPlugin {
+    pub fn new(config: HashMap<String, String>) -> Self {
+        let name = config
+            .get("name")
+            .cloned()
+            .unwrap_or_else(|| DEFAULT_PLUGIN_NAME.to_string());
+
+        let mut representations = vec![Representation::Html];
+        let formats = config
+            .get("formats")
+            .map(|f| f.as_str())
+            .unwrap_or(DEFAULT_FORMATS);
+        for format in formats.split(',') {
+            match Representation::from_format_name(format) {
+                Some(representation) if !representations.contains(&representation) => {
+                    representations.push(representation);
+                }
+                Some(_) => {}
+                None => log_warn!(name, "Ignoring unknown format"; format = format.trim()),
+            }
+        }
+
+        Self {
+            name,
+            representations,
+        }
+    }
+
+    /// Check whether the response is an HTML document we may convert
+    fn is_negotiable(&self, response: &Response<Body>) -> bool {
+        let status_ok = matches!(response.status(), StatusCode::OK | StatusCode::PARTIAL_CONTENT);
+        let is_html = response
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|ct| ct.to_lowercase().starts_with(MEDIA_TYPE_HTML))
+            .unwrap_or(false);
+        status_ok && is_html
+    }
+
+    /// Parse an Accept header into (media range, quality) pairs
+    fn parse_accept(&self, accept: &str) -> Vec<(String, f32)> {
+        accept
+            .split(',')
+            .filter_map(|part| {
+                let mut params = part.split(';');
+                let media_range = params.next()?.trim().to_lowercase();
+                if media_range.is_empty() {
+                    return None;
+                }
+                let quality = params
+                    .filter_map(|p| p.trim().strip_prefix("q="))
+                    .filter_map(|q| q.trim().parse::<f32>().ok())
+                    .next()
+                    .unwrap_or(1.0);
+                Some((media_range, quality))
+            })
+            .collect()
+    }
+
+    /// Quality the client assigns to a media type, using the most specific matching range
+    fn quality_for(&self, media_type: &str, accepted: &[(String, f32)]) -> f32 {
+        let (type_part, _) = media_type.split_once('/').unwrap_or((media_type, ""));
+        let type_wildcard = format!("{}/*", type_part);
+
+        let mut best: Option<(u8, f32)> = None;
+        for (range, quality) in accepted {
+            let specificity = if range == media_type {
+                2
+            } else if *range == type_wildcard {
+                1
+            } else if range == "*/*" {
+                0
+            } else {
+                continue;
+            };
+            if best.map(|(s, _)| specificity > s).unwrap_or(true) {
+                best = Some((specificity, *quality));
+            }
+        }
+        best.map(|(_, q)| q).unwrap_or(0.0)
+    }
+
+    /// Choose the representation for a request, preferring HTML on ties
+    fn negotiate(&self, request: &PluginRequest) -> Representation {
+        let accept = match request
+            .http_request
+            .headers()
+            .get(header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+        {
+            Some(accept) if !accept.trim().is_empty() => accept,
+            _ => return Representation::Html,
+        };
+
+        let accepted = self.parse_accept(accept);
+        let mut chosen = Representation::Html;
+        let mut chosen_quality = self.quality_for(MEDIA_TYPE_HTML, &accepted);
+        for representation in self.representations.iter().skip(1) {
+            let quality = self.quality_for(representation.media_type(), &accepted);
+            if quality > chosen_quality {
+                chosen = *representation;
+                chosen_quality = quality;
+            }
+        }
+        chosen
+    }
+
+    /// Add Accept to the Vary header without clobbering existing values
+    fn add_vary_accept(&self, response: &mut Response<Body>) {
+        let existing = response
+            .headers()
+            .get(header::VARY)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.to_string());
+
+        let value = match existing {
+            Some(vary) if vary.split(',').any(|v| v.trim().eq_ignore_ascii_case(VARY_ACCEPT) || v.trim() == "*") => return,
+            Some(vary) if !vary.trim().is_empty() => format!("{}, {}", vary, VARY_ACCEPT),
+            _ => VARY_ACCEPT.to_string(),
+        };
+
+        if let Ok(header_value) = header::HeaderValue::from_str(&value) {
+            response.headers_mut().insert(header::VARY, header_value);
+        }
+    }
+
+    /// Convert HTML to Markdown
+    fn html_to_markdown(&self, html: &str) -> String {
+        html2md::parse_html(html)
+    }
+
+    /// Extract microdata from HTML as JSON
+    fn html_to_json(&self, html: &str) -> Result<String, String> {
+        let items = MicrodataExtractor::new()
+            .extract(html)
+            .map_err(|e| format!("Failed to extract microdata: {}", e))?;
+        let items: Vec<Value> = items.iter().map(|item| self.item_to_json(item)).collect();
+        serde_json::to_string(&json!({ "items": items }))
+            .map_err(|e| format!("Failed to serialize microdata: {}", e))
+    }
+
+    /// Convert a microdata item to its JSON representation
+    fn item_to_json(&self, item: &MicrodataItem) -> Value {
+        let mut object = Map::new();
+        if let Some(item_type) = item.item_type() {
+            let types: Vec<Value> = item_type
+                .split_whitespace()
+                .map(|t| Value::String(t.to_string()))
+                .collect();
+            object.insert("type".to_string(), Value::Array(types));
+        }
+        if let Some(item_id) = item.item_id() {
+            object.insert("id".to_string(), Value::String(item_id.to_string()));
+        }
+
+        let mut properties = Map::new();
+        for property in item.properties() {
+            let value = match property.as_item() {
+                Some(nested) => self.item_to_json(nested),
+                None => Value::String(property.value_as_string()),
+            };
+            if let Value::Array(values) = properties
+                .entry(property.name().to_string())
+                .or_insert_with(|| Value::Array(Vec::new()))
+            {
+                values.push(value);
+            }
+        }
+        object.insert("properties".to_string(), Value::Object(properties));
+
+        Value::Object(object)
+    }
+}
+
+#[async_trait]
+impl Plugin for ContentNegotiationPlugin {
+    async fn handle_request(&self, _request: &mut PluginRequest, _context: &PluginContext) -> Option<PluginResponse> {
+        // Negotiation happens in the response phase
+        None
+    }
+
+    async fn handle_response(&self, request: &PluginRequest, response: &mut Response<Body>, context: &PluginContext) {
+        if !self.is_negotiable(response) {
+            return;
+        }
+
+        // The representation depends on Accept, so caches must key on it
+        self.add_vary_accept(response);
+
+        let representation = self.negotiate(request);
+        if representation == Representation::Html || request.is_method(&Method::HEAD) {
+            return;
+        }
+
+        let mut content_type = CONTENT_TYPE_JSON;
+        let converted = transform_body(response, |body_bytes| {
+            let html = match std::str::from_utf8(body_bytes) {
+                Ok(html) => html,
+                Err(_) => {
+                    context.log_verbose("[ContentNegotiation] Response body is not valid UTF-8, leaving as HTML");
+                    return None;
+                }
+            };
+            match representation {
+                Representation::Markdown => {
+                    content_type = CONTENT_TYPE_MARKDOWN;
+                    Some(self.html_to_markdown(html))
+                }
+                Representation::Json => match self.html_to_json(html) {
+                    Ok(json) => Some(json),
+                    Err(e) => {
+                        context.log_error(&format!("[ContentNegotiation] {}", e));
+                        None
+                    }
+                },
+                Representation::Html => unreachable!("HTML responses are returned unchanged"),
+            }
+        })
+        .await;
+
+        match converted {
+            Ok(true) => {}
+            Ok(false) => return,
+            Err(e) => {
+                context.log_error(&format!("[ContentNegotiation] Failed to read response body: {}", e));
+                return;
+            }
+        }
+
+        context.log_verbose(&format!(
+            "[ContentNegotiation] Converted {} to {}",
+            request.path, content_type
+        ));
+
+        let headers = response.headers_mut();
+        headers.insert(header::CONTENT_TYPE, header::HeaderValue::from_static(content_type));
+        // Validators computed for the HTML body no longer apply
+        headers.remove(header::ETAG);
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// Export the plugin
+create_plugin!(ContentNegotiationPlugin);
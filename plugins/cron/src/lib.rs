@@ -0,0 +1,598 @@
+//! Cron Plugin for Rusty Beam
+//!
+//! This plugin runs tasks on schedules, or when documents change: requests
+//! run through the host's pipeline, such as regenerating a page or invoking
+//! a JavaScript engine script, and rotation of log files. Tasks are declared
+//! as microdata in an HTML file, like users and authorization rules, and
+//! their state can be published as microdata too.
+//!
+//! ## Features
+//! - **Cron Schedules**: Five-field crontab expressions in local time, and
+//!   shorthands such as `@daily`
+//! - **Change Triggers**: Tasks run after the selector-handler changed a
+//!   document whose path matches a pattern, to regenerate derived content
+//! - **Pipeline Requests**: Requests pass every plugin of the host, access
+//!   control included, exactly like requests from clients
+//! - **Log Rotation**: Log files are renamed with a timestamp suffix, keeping
+//!   a configured number of old files
+//! - **Status Page**: The schedule, last run and next run of every task as
+//!   microdata HTML
+//!
+//! ## Configuration
+//! - `tasks_file`: HTML file declaring the tasks (file:// URL or path, required)
+//! - `status_path`: Path serving the state of the tasks (default: none, disabled)
+//!
+//! ## Tasks
+//! Each task is an item of type `https://rustybeam.net/schema/ScheduledTask`:
+//! - `name`: Name of the task, shown on the status page and in logs (required)
+//! - `schedule`: When the task runs, e.g. `30 2 * * *` for 02:30 every day
+//! - `trigger`: Path pattern of documents whose changes run the task, where
+//!   `*` stands for any run of characters, e.g. `/data/*.html`; there can be
+//!   several. A task needs a `schedule`, a `trigger` or both
+//! - `action`: `request` (default) or `rotate-log`
+//! - `method`: Method of the request (default: GET)
+//! - `path`: Path of the request, which may include a query string
+//! - `header`: Header of the request as `Name: value`; there can be several,
+//!   for example an `Authorization` header for protected paths
+//! - `body`: Body of the request (default: empty)
+//! - `file`: Log file a `rotate-log` task rotates
+//! - `keep`: How many rotated files a `rotate-log` task keeps (default: 7)
+//!
+//! JavaScript engine scripts run by requesting a path routed to them.
+//!
+//! ## Execution
+//! Tasks run one at a time on a thread of the plugin, started once the
+//! pipeline is built and stopped when the plugin shuts down. A task whose
+//! time passes while another one runs is run as soon as that finishes. A
+//! request task succeeds when the pipeline answers with a 2xx status.
+//!
+//! Changes are announced by plugins editing documents, such as the
+//! selector-handler, through `PluginContext::notify_change`. A burst of
+//! changes runs a triggered task once, and changes made while the task runs,
+//! including those its own request makes, do not run it again.
+//!
+//! ## Status Page
+//! `GET` on the status path answers with one `ScheduledTask` item per task,
+//! carrying `lastRun`, `lastResult` and `nextRun` along with the declared
+//! properties. Protect the path with the authorization plugin like any other.
+
+mod schedule;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Local, SecondsFormat};
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use microdata_extract::{MicrodataExtractor, MicrodataItem};
+use rusty_beam_plugin_api::condition::glob_match;
+use rusty_beam_plugin_api::{create_plugin, log_error, log_info, log_warn, DocumentChange, HostServices, Plugin, PluginContext, PluginRequest, PluginResponse};
+use schedule::Schedule;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+// Default configuration values
+const DEFAULT_PLUGIN_NAME: &str = "cron";
+const DEFAULT_KEEP: usize = 7;
+
+// Configuration keys
+const CONFIG_KEY_NAME: &str = "name";
+const CONFIG_KEY_TASKS_FILE: &str = "tasks_file";
+const CONFIG_KEY_STATUS_PATH: &str = "status_path";
+
+// Microdata
+const SCHEMA_SCHEDULED_TASK: &str = "https://rustybeam.net/schema/ScheduledTask";
+
+// Actions
+const ACTION_REQUEST: &str = "request";
+const ACTION_ROTATE_LOG: &str = "rotate-log";
+
+/// Longest the scheduler sleeps before checking the clock again, so changes
+/// of the system time are noticed
+const MAX_SLEEP: Duration = Duration::from_secs(60);
+
+/// Format of the suffix of rotated log files, as the access log uses
+const ROTATED_SUFFIX_FORMAT: &str = "%Y%m%d_%H%M%S";
+
+// Content types
+const CONTENT_TYPE_HTML: &str = "text/html; charset=utf-8";
+
+/// What a task does when it is due
+#[derive(Debug, Clone)]
+enum Action {
+    Request {
+        method: Method,
+        path: String,
+        headers: Vec<(String, String)>,
+        body: String,
+    },
+    RotateLog {
+        file: PathBuf,
+        keep: usize,
+    },
+}
+
+/// A task declared in the tasks file
+#[derive(Debug, Clone)]
+struct Task {
+    name: String,
+    schedule_text: String,
+    schedule: Option<Schedule>,
+    /// Path patterns of documents whose changes run the task
+    triggers: Vec<String>,
+    action: Action,
+}
+
+impl Task {
+    /// Parse a task from a `ScheduledTask` item, describing what is wrong otherwise
+    fn from_item(item: &MicrodataItem) -> Result<Self, String> {
+        let name = item.get_property("name")
+            .map(|name| name.trim().to_string())
+            .filter(|name| !name.is_empty())
+            .ok_or("Scheduled task without a name")?;
+        let schedule_text = item.get_property("schedule")
+            .map(|schedule| schedule.trim().to_string())
+            .unwrap_or_default();
+        let schedule = match schedule_text.as_str() {
+            "" => None,
+            text => Some(text.parse().map_err(|e| format!("Task '{}': {}", name, e))?),
+        };
+        let triggers: Vec<String> = item.get_property_values("trigger").iter()
+            .map(|trigger| trigger.trim().to_string())
+            .filter(|trigger| !trigger.is_empty())
+            .collect();
+        if schedule.is_none() && triggers.is_empty() {
+            return Err(format!("Task '{}' has neither a schedule nor a trigger", name));
+        }
+
+        let action = match item.get_property("action").as_deref().map(str::trim).unwrap_or(ACTION_REQUEST) {
+            ACTION_REQUEST => {
+                let path = item.get_property("path")
+                    .map(|path| path.trim().to_string())
+                    .filter(|path| path.starts_with('/'))
+                    .ok_or_else(|| format!("Task '{}' needs a path starting with '/'", name))?;
+                let method = item.get_property("method").as_deref().unwrap_or("GET").trim().to_uppercase();
+                let method = Method::from_bytes(method.as_bytes())
+                    .map_err(|_| format!("Task '{}' has an invalid method '{}'", name, method))?;
+                let mut headers = Vec::new();
+                for header in item.get_property_values("header") {
+                    match header.split_once(':') {
+                        Some((key, value)) if !key.trim().is_empty() => {
+                            headers.push((key.trim().to_string(), value.trim().to_string()));
+                        }
+                        _ => return Err(format!("Task '{}' has an invalid header '{}'", name, header)),
+                    }
+                }
+                Action::Request {
+                    method,
+                    path,
+                    headers,
+                    body: item.get_property("body").unwrap_or_default(),
+                }
+            }
+            ACTION_ROTATE_LOG => {
+                let file = item.get_property("file")
+                    .map(|file| file.trim().to_string())
+                    .filter(|file| !file.is_empty())
+                    .ok_or_else(|| format!("Task '{}' has no file to rotate", name))?;
+                let keep = match item.get_property("keep") {
+                    Some(keep) => keep.trim().parse()
+                        .map_err(|_| format!("Task '{}' has an invalid keep '{}'", name, keep))?,
+                    None => DEFAULT_KEEP,
+                };
+                Action::RotateLog { file: file_path(&file), keep }
+            }
+            other => return Err(format!("Task '{}' has an unknown action '{}'", name, other)),
+        };
+
+        Ok(Self { name, schedule_text, schedule, triggers, action })
+    }
+
+    /// When the schedule next runs the task after a time
+    fn next_after(&self, time: DateTime<Local>) -> Option<DateTime<Local>> {
+        self.schedule.as_ref().and_then(|schedule| schedule.next_after(time))
+    }
+
+    /// Whether a change to the document at a path runs the task
+    fn is_triggered_by(&self, path: &str) -> bool {
+        self.triggers.iter().any(|trigger| glob_match(trigger, path))
+    }
+
+    fn action_name(&self) -> &'static str {
+        match self.action {
+            Action::Request { .. } => ACTION_REQUEST,
+            Action::RotateLog { .. } => ACTION_ROTATE_LOG,
+        }
+    }
+}
+
+/// What is known about the runs of a task
+#[derive(Debug, Clone, Default)]
+struct TaskStatus {
+    next_run: Option<DateTime<Local>>,
+    last_run: Option<DateTime<Local>>,
+    last_result: Option<String>,
+}
+
+/// What the scheduler thread is woken for
+enum Signal {
+    /// A document changed, so triggered tasks may be pending
+    Changed,
+    Stop,
+}
+
+/// Tasks and their state, shared with the scheduler thread
+struct Scheduler {
+    plugin_name: String,
+    tasks: Vec<Task>,
+    status: RwLock<Vec<TaskStatus>>,
+    /// Tasks a change has triggered since they last ran
+    triggered: Mutex<Vec<bool>>,
+    services: RwLock<Option<Arc<dyn HostServices>>>,
+}
+
+impl Scheduler {
+    /// Run due tasks until told to stop
+    fn run(&self, signals: mpsc::Receiver<Signal>) {
+        {
+            let now = Local::now();
+            let mut status = self.status.write().unwrap();
+            for (task, status) in self.tasks.iter().zip(status.iter_mut()) {
+                status.next_run = task.next_after(now);
+            }
+        }
+
+        loop {
+            let next_run = self.status.read().unwrap().iter().filter_map(|status| status.next_run).min();
+            let sleep = next_run
+                .and_then(|next_run| (next_run - Local::now()).to_std().ok())
+                .unwrap_or_default()
+                .min(MAX_SLEEP);
+            match signals.recv_timeout(sleep) {
+                Ok(Signal::Changed) | Err(RecvTimeoutError::Timeout) => {}
+                // Stopped, or the plugin was dropped
+                Ok(Signal::Stop) | Err(RecvTimeoutError::Disconnected) => return,
+            }
+
+            for (index, task) in self.tasks.iter().enumerate() {
+                let now = Local::now();
+                let scheduled = self.status.read().unwrap()[index].next_run.is_some_and(|next_run| next_run <= now);
+                let triggered = self.triggered.lock().unwrap()[index];
+                if !scheduled && !triggered {
+                    continue;
+                }
+                let result = self.run_task(task);
+                let finished = Local::now();
+                // Changes made while the task ran, its own included, are covered by this run
+                self.triggered.lock().unwrap()[index] = false;
+                let mut status = self.status.write().unwrap();
+                status[index] = TaskStatus {
+                    next_run: if scheduled { task.next_after(finished) } else { status[index].next_run },
+                    last_run: Some(now),
+                    last_result: Some(match &result {
+                        Ok(outcome) => outcome.clone(),
+                        Err(e) => format!("failed: {}", e),
+                    }),
+                };
+                drop(status);
+                match result {
+                    Ok(outcome) => log_info!(self.plugin_name, "Ran scheduled task"; task = task.name, result = outcome),
+                    Err(e) => log_error!(self.plugin_name, "Scheduled task failed: {}", e; task = task.name),
+                }
+            }
+        }
+    }
+
+    fn run_task(&self, task: &Task) -> Result<String, String> {
+        match &task.action {
+            Action::Request { method, path, headers, body } => self.run_request(method, path, headers, body),
+            Action::RotateLog { file, keep } => rotate_log(file, *keep),
+        }
+    }
+
+    /// Run a request through the pipeline, describing the response status
+    fn run_request(&self, method: &Method, path: &str, headers: &[(String, String)], body: &str) -> Result<String, String> {
+        let services = self.services.read().unwrap().clone()
+            .ok_or("the server provided no host services")?;
+
+        let mut builder = Request::builder().method(method.clone()).uri(path);
+        for (key, value) in headers {
+            builder = builder.header(key.as_str(), value.as_str());
+        }
+        let request = builder.body(Body::from(body.to_string())).map_err(|e| e.to_string())?;
+
+        let response = futures::executor::block_on(services.dispatch(request));
+        let status = response.status();
+        // Read the body so plugins streaming it see the response through
+        let _ = futures::executor::block_on(hyper::body::to_bytes(response.into_body()));
+        if status.is_success() {
+            Ok(status.to_string())
+        } else {
+            Err(status.to_string())
+        }
+    }
+}
+
+/// Rename a log file with a timestamp suffix and remove the oldest rotated
+/// files beyond `keep`
+fn rotate_log(file: &Path, keep: usize) -> Result<String, String> {
+    if !file.exists() {
+        return Ok("nothing to rotate".to_string());
+    }
+    let rotated = PathBuf::from(format!(
+        "{}.{}",
+        file.to_string_lossy(),
+        Local::now().format(ROTATED_SUFFIX_FORMAT)
+    ));
+    std::fs::rename(file, &rotated).map_err(|e| e.to_string())?;
+
+    // Timestamp suffixes sort in the order the files were rotated
+    let file_name = file.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    let prefix = format!("{}.", file_name);
+    let directory = file.parent().filter(|parent| !parent.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let mut rotated_files: Vec<PathBuf> = std::fs::read_dir(directory)
+        .map_err(|e| e.to_string())?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.file_name()
+            .map(|name| name.to_string_lossy())
+            .and_then(|name| name.strip_prefix(&prefix).map(is_rotation_suffix))
+            .unwrap_or(false))
+        .collect();
+    rotated_files.sort();
+    let removed = rotated_files.len().saturating_sub(keep);
+    for old in &rotated_files[..removed] {
+        std::fs::remove_file(old).map_err(|e| e.to_string())?;
+    }
+    Ok(format!("rotated to {}, removed {} old files", rotated.display(), removed))
+}
+
+/// Whether a file name suffix is a rotation timestamp such as `20240131_023000`
+fn is_rotation_suffix(suffix: &str) -> bool {
+    suffix.len() == 15 && suffix.char_indices().all(|(index, c)| if index == 8 { c == '_' } else { c.is_ascii_digit() })
+}
+
+fn file_path(value: &str) -> PathBuf {
+    PathBuf::from(value.strip_prefix("file://").unwrap_or(value))
+}
+
+/// Escape text for use in HTML content and attribute values
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Plugin running scheduled tasks
+pub struct CronPlugin {
+    name: String,
+    status_path: Option<String>,
+    scheduler: Arc<Scheduler>,
+    /// Signals the scheduler thread, which is running while this is set
+    worker: Mutex<Option<(Sender<Signal>, JoinHandle<()>)>>,
+}
+
+impl std::fmt::Debug for CronPlugin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CronPlugin")
+            .field("name", &self.name)
+            .field("status_path", &self.status_path)
+            .field("tasks", &self.scheduler.tasks)
+            .finish()
+    }
+}
+
+impl CronPlugin {
+    pub fn new(config: HashMap<String, String>) -> Self {
+        let name = config.get(CONFIG_KEY_NAME).cloned().unwrap_or_else(|| DEFAULT_PLUGIN_NAME.to_string());
+        let non_empty = |key: &str| config.get(key)
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+
+        let tasks = match non_empty(CONFIG_KEY_TASKS_FILE) {
+            Some(tasks_file) => Self::load_tasks(&name, &file_path(&tasks_file)),
+            None => {
+                log_error!(name, "No tasks_file configured, no tasks will run");
+                Vec::new()
+            }
+        };
+
+        Self {
+            status_path: non_empty(CONFIG_KEY_STATUS_PATH),
+            scheduler: Arc::new(Scheduler {
+                plugin_name: name.clone(),
+                status: RwLock::new(vec![TaskStatus::default(); tasks.len()]),
+                triggered: Mutex::new(vec![false; tasks.len()]),
+                tasks,
+                services: RwLock::new(None),
+            }),
+            worker: Mutex::new(None),
+            name,
+        }
+    }
+
+    /// Read the tasks declared in a tasks file, skipping invalid ones
+    fn load_tasks(name: &str, tasks_file: &Path) -> Vec<Task> {
+        let content = match std::fs::read_to_string(tasks_file) {
+            Ok(content) => content,
+            Err(e) => {
+                log_error!(name, "Failed to read tasks file: {}", e; file = tasks_file.display());
+                return Vec::new();
+            }
+        };
+        let items = match MicrodataExtractor::new().extract(&content) {
+            Ok(items) => items,
+            Err(e) => {
+                log_error!(name, "Failed to parse tasks file: {}", e; file = tasks_file.display());
+                return Vec::new();
+            }
+        };
+
+        items.iter()
+            .filter(|item| item.item_type() == Some(SCHEMA_SCHEDULED_TASK))
+            .filter_map(|item| match Task::from_item(item) {
+                Ok(task) => Some(task),
+                Err(e) => {
+                    log_warn!(name, "Ignoring scheduled task: {}", e; file = tasks_file.display());
+                    None
+                }
+            })
+            .collect()
+    }
+
+    /// Stop the scheduler thread, waiting for a running task to finish
+    fn stop(&self) {
+        let worker = self.worker.lock().unwrap().take();
+        if let Some((signals, handle)) = worker {
+            let _ = signals.send(Signal::Stop);
+            let _ = handle.join();
+        }
+    }
+
+    /// The tasks and their state as microdata
+    fn create_status_response(&self) -> Response<Body> {
+        let status = self.scheduler.status.read().unwrap();
+        let time = |time: Option<DateTime<Local>>| time
+            .map(|time| time.to_rfc3339_opts(SecondsFormat::Secs, false))
+            .unwrap_or_default();
+
+        let mut rows = String::new();
+        for (task, status) in self.scheduler.tasks.iter().zip(status.iter()) {
+            let target = match &task.action {
+                Action::Request { method, path, .. } => format!(
+                    "<span itemprop=\"method\">{}</span> <span itemprop=\"path\">{}</span>",
+                    method, escape_html(path)
+                ),
+                Action::RotateLog { file, .. } => format!(
+                    "<span itemprop=\"file\">{}</span>",
+                    escape_html(&file.to_string_lossy())
+                ),
+            };
+            let triggers: Vec<String> = task.triggers.iter()
+                .map(|trigger| format!("<span itemprop=\"trigger\">{}</span>", escape_html(trigger)))
+                .collect();
+            rows.push_str(&format!(
+                concat!(
+                    "<tr itemscope itemtype=\"{}\">",
+                    "<td itemprop=\"name\">{}</td>",
+                    "<td itemprop=\"schedule\">{}</td>",
+                    "<td>{}</td>",
+                    "<td itemprop=\"action\">{}</td>",
+                    "<td>{}</td>",
+                    "<td><time itemprop=\"lastRun\" datetime=\"{}\">{}</time></td>",
+                    "<td itemprop=\"lastResult\">{}</td>",
+                    "<td><time itemprop=\"nextRun\" datetime=\"{}\">{}</time></td>",
+                    "</tr>\n"
+                ),
+                SCHEMA_SCHEDULED_TASK,
+                escape_html(&task.name),
+                escape_html(&task.schedule_text),
+                triggers.join(" "),
+                task.action_name(),
+                target,
+                time(status.last_run), time(status.last_run),
+                escape_html(status.last_result.as_deref().unwrap_or("")),
+                time(status.next_run), time(status.next_run),
+            ));
+        }
+
+        let body = format!(
+            concat!(
+                "<!DOCTYPE html>\n<html>\n<head><title>Scheduled Tasks</title></head>\n<body>\n",
+                "<h1>Scheduled Tasks</h1>\n<table>\n",
+                "<thead><tr><th>Name</th><th>Schedule</th><th>Triggers</th><th>Action</th><th>Target</th>",
+                "<th>Last Run</th><th>Last Result</th><th>Next Run</th></tr></thead>\n",
+                "<tbody>\n{}</tbody>\n</table>\n</body>\n</html>\n"
+            ),
+            rows
+        );
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, CONTENT_TYPE_HTML)
+            .body(Body::from(body))
+            .unwrap()
+    }
+}
+
+impl Drop for CronPlugin {
+    fn drop(&mut self) {
+        // The thread runs code of this library, so it must end before the library is unloaded
+        self.stop();
+    }
+}
+
+#[async_trait]
+impl Plugin for CronPlugin {
+    async fn handle_request(&self, request: &mut PluginRequest, _context: &PluginContext) -> Option<PluginResponse> {
+        if self.status_path.as_deref() != Some(request.path.as_str()) {
+            return None;
+        }
+        match *request.http_request.method() {
+            Method::GET | Method::HEAD => Some(self.create_status_response().into()),
+            _ => Some(Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header("Allow", "GET, HEAD")
+                .body(Body::from("Method not allowed"))
+                .unwrap()
+                .into()),
+        }
+    }
+
+    fn attach_services(&self, services: Arc<dyn HostServices>) {
+        *self.scheduler.services.write().unwrap() = Some(services);
+    }
+
+    async fn on_startup(&self) {
+        if self.scheduler.tasks.is_empty() {
+            return;
+        }
+        let mut worker = self.worker.lock().unwrap();
+        if worker.is_some() {
+            return;
+        }
+        let (signals, received) = mpsc::channel();
+        let scheduler = Arc::clone(&self.scheduler);
+        match std::thread::Builder::new()
+            .name(format!("{}-scheduler", self.name))
+            .spawn(move || scheduler.run(received))
+        {
+            Ok(handle) => {
+                log_info!(self.name, "Scheduled {} tasks", self.scheduler.tasks.len());
+                *worker = Some((signals, handle));
+            }
+            Err(e) => log_error!(self.name, "Failed to start the scheduler: {}", e),
+        }
+    }
+
+    async fn on_shutdown(&self) {
+        self.stop();
+    }
+
+    fn on_change(&self, change: &DocumentChange, _context: &PluginContext) {
+        let mut any = false;
+        {
+            let mut triggered = self.scheduler.triggered.lock().unwrap();
+            for (task, triggered) in self.scheduler.tasks.iter().zip(triggered.iter_mut()) {
+                if task.is_triggered_by(&change.path) {
+                    *triggered = true;
+                    any = true;
+                }
+            }
+        }
+        if any {
+            if let Some((signals, _)) = self.worker.lock().unwrap().as_ref() {
+                let _ = signals.send(Signal::Changed);
+            }
+        }
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// Export the plugin creation function
+create_plugin!(CronPlugin);
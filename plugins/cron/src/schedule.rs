@@ -0,0 +1,152 @@
+//! Cron expressions
+//!
+//! A schedule has the five fields of crontab, `minute hour day-of-month month
+//! day-of-week`, each `*`, a number, a range `a-b`, a step `*/n` or `a-b/n`, or
+//! a comma-separated list of these. Sunday is day 0 or 7. As in crontab, when
+//! both day fields are restricted a day matching either one is due. The
+//! shorthands `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are
+//! accepted too.
+
+use chrono::{DateTime, Datelike, Duration, Local, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use std::str::FromStr;
+
+/// How many days ahead to look for the next run, enough for any schedule
+/// that can be due at all, such as the 29th of February
+const SEARCH_DAYS: i64 = 8 * 366;
+
+/// When a scheduled task is due, in local time
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    days_restricted: bool,
+    weekdays_restricted: bool,
+}
+
+impl Schedule {
+    /// The first time after `after` the schedule is due, at the start of a minute
+    pub fn next_after(&self, after: DateTime<Local>) -> Option<DateTime<Local>> {
+        let start = after.naive_local().with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let last_day = start.date() + Duration::days(SEARCH_DAYS);
+
+        let mut day = start.date();
+        while day <= last_day {
+            if self.is_due_on(day) {
+                let first_minute = if day == start.date() { start.hour() * 60 + start.minute() } else { 0 };
+                for minute_of_day in first_minute..24 * 60 {
+                    let (hour, minute) = (minute_of_day / 60, minute_of_day % 60);
+                    if !has(self.hours, hour) || !has(self.minutes, minute) {
+                        continue;
+                    }
+                    // Times skipped by a daylight saving change never come
+                    if let Some(time) = Self::local_time(day, hour, minute) {
+                        return Some(time);
+                    }
+                }
+            }
+            day = day.succ_opt()?;
+        }
+        None
+    }
+
+    fn is_due_on(&self, day: NaiveDate) -> bool {
+        if !has(self.months, day.month()) {
+            return false;
+        }
+        let day_matches = has(self.days, day.day());
+        let weekday_matches = has(self.weekdays, day.weekday().num_days_from_sunday());
+        match (self.days_restricted, self.weekdays_restricted) {
+            (true, true) => day_matches || weekday_matches,
+            (true, false) => day_matches,
+            (false, true) => weekday_matches,
+            (false, false) => true,
+        }
+    }
+
+    fn local_time(day: NaiveDate, hour: u32, minute: u32) -> Option<DateTime<Local>> {
+        let naive: NaiveDateTime = day.and_hms_opt(hour, minute, 0)?;
+        Local.from_local_datetime(&naive).earliest()
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let expression = match value.trim() {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            other => other,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(format!("Schedule '{}' does not have five fields", value.trim()));
+        };
+
+        // Sunday may be written as 7
+        let mut weekday_set = parse_field(weekdays, 0, 7)?;
+        if has(weekday_set, 7) {
+            weekday_set |= 1;
+        }
+
+        Ok(Self {
+            minutes: parse_field(minutes, 0, 59)?,
+            hours: parse_field(hours, 0, 23)?,
+            days: parse_field(days, 1, 31)?,
+            months: parse_field(months, 1, 12)?,
+            weekdays: weekday_set,
+            days_restricted: days != "*",
+            weekdays_restricted: weekdays != "*",
+        })
+    }
+}
+
+fn has(set: u64, value: u32) -> bool {
+    set & (1 << value) != 0
+}
+
+/// Parse one field into a bit set of the values it allows
+fn parse_field(field: &str, min: u32, max: u32) -> Result<u64, String> {
+    let mut set = 0;
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => match step.parse::<u32>() {
+                Ok(step) if step > 0 => (range, step),
+                _ => return Err(format!("Invalid step in '{}'", part)),
+            },
+            None => (part, 1),
+        };
+        let (first, last) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((first, last)) => (parse_value(first, min, max)?, parse_value(last, min, max)?),
+                // A single value with a step runs to the end of the field
+                None if step > 1 => (parse_value(range, min, max)?, max),
+                None => {
+                    let value = parse_value(range, min, max)?;
+                    (value, value)
+                }
+            },
+        };
+        if first > last {
+            return Err(format!("Invalid range '{}'", range));
+        }
+        for value in (first..=last).step_by(step as usize) {
+            set |= 1 << value;
+        }
+    }
+    Ok(set)
+}
+
+fn parse_value(value: &str, min: u32, max: u32) -> Result<u32, String> {
+    match value.parse::<u32>() {
+        Ok(number) if (min..=max).contains(&number) => Ok(number),
+        _ => Err(format!("'{}' is not a number from {} to {}", value, min, max)),
+    }
+}
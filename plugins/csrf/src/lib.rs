@@ -0,0 +1,388 @@
+//! CSRF Plugin for Rusty Beam
+//!
+//! This plugin protects state-changing requests against cross-site request
+//! forgery. It issues a token for each session, writes it into the HTML forms
+//! the server sends, and rejects state-changing requests that do not carry it.
+//!
+//! ## Features
+//! - **Per-Session Tokens**: Tokens are bound to the OAuth2 session, the
+//!   basic-auth user, or for anonymous visitors a session cookie of its own
+//! - **Form Rewriting**: Every same-origin POST form in an HTML response gets
+//!   a hidden input holding the token
+//! - **Script Access**: A `<meta name="csrf-token">` element carries the token
+//!   for pages that send requests with `fetch`
+//! - **API Exemptions**: Path prefixes such as `/api/` can be exempted
+//!
+//! ## Configuration
+//! - `secret_env`: Environment variable holding the secret tokens are derived
+//!   from (default: a random secret, so tokens change when the server restarts)
+//! - `header_name`: Request header carrying the token (default: "X-CSRF-Token")
+//! - `field_name`: Form field carrying the token (default: "csrf_token")
+//! - `protected_methods`: Comma-separated methods that need a token
+//!   (default: "POST,PUT,DELETE,PATCH")
+//! - `exempt_paths`: Comma-separated path prefixes that need no token
+//!   (default: none)
+//! - `session_cookie`: Cookie holding the OAuth2 session (default: "session_id")
+//! - `meta_tag`: Whether to add the token as a meta element (default: true)
+//! - `secure_cookie`: Whether the anonymous session cookie is marked Secure
+//!   (default: false)
+//!
+//! ## Tokens
+//! A token is an HMAC-SHA256 of the session it belongs to, so tokens need no
+//! server-side storage. The session is, in order of preference, the OAuth2
+//! session cookie, the `authenticated_user` set by an authentication plugin,
+//! or the plugin's own `csrf_session` cookie, which it sets on the first HTML
+//! response to a visitor without one. A request passes with the token in the
+//! configured header, or in the configured field of an
+//! `application/x-www-form-urlencoded` or `multipart/form-data` body.
+//!
+//! ## Pipeline Integration
+//! Place the plugin after the authentication plugins, so their users are
+//! known, and before the compression plugin, so it sees HTML it can rewrite.
+
+use async_trait::async_trait;
+use dom_query::Document;
+use hyper::header::{HeaderValue, CONTENT_ENCODING, CONTENT_TYPE, COOKIE, HOST, SET_COOKIE};
+use hyper::{Body, Method, Response, StatusCode};
+use rusty_beam_plugin_api::{create_plugin, log_debug, log_error, log_warn, transform_body, Plugin, PluginContext, PluginRequest, PluginResponse};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+// Default configuration values
+const DEFAULT_PLUGIN_NAME: &str = "csrf";
+const DEFAULT_HEADER_NAME: &str = "X-CSRF-Token";
+const DEFAULT_FIELD_NAME: &str = "csrf_token";
+const DEFAULT_PROTECTED_METHODS: &str = "POST,PUT,DELETE,PATCH";
+const DEFAULT_SESSION_COOKIE: &str = "session_id";
+
+// Configuration keys
+const CONFIG_KEY_NAME: &str = "name";
+const CONFIG_KEY_SECRET_ENV: &str = "secret_env";
+const CONFIG_KEY_HEADER_NAME: &str = "header_name";
+const CONFIG_KEY_FIELD_NAME: &str = "field_name";
+const CONFIG_KEY_PROTECTED_METHODS: &str = "protected_methods";
+const CONFIG_KEY_EXEMPT_PATHS: &str = "exempt_paths";
+const CONFIG_KEY_SESSION_COOKIE: &str = "session_cookie";
+const CONFIG_KEY_META_TAG: &str = "meta_tag";
+const CONFIG_KEY_SECURE_COOKIE: &str = "secure_cookie";
+
+// Session sources
+const ANONYMOUS_COOKIE_NAME: &str = "csrf_session";
+const AUTHENTICATED_USER_METADATA: &str = "authenticated_user";
+
+// Content types
+const CONTENT_TYPE_HTML: &str = "text/html";
+const CONTENT_TYPE_PLAIN: &str = "text/plain";
+const CONTENT_TYPE_FORM: &str = "application/x-www-form-urlencoded";
+const CONTENT_TYPE_MULTIPART: &str = "multipart/form-data";
+
+// HTML rewriting
+const FORM_SELECTOR: &str = "form";
+const HEAD_SELECTOR: &str = "head";
+const META_TAG_NAME: &str = "csrf-token";
+
+// HMAC-SHA256 parameters
+const HMAC_BLOCK_SIZE: usize = 64;
+const SECRET_SIZE: usize = 32;
+
+// Messages
+const MSG_TOKEN_REJECTED: &str = "CSRF token missing or invalid";
+
+/// Plugin issuing and checking CSRF tokens
+#[derive(Debug)]
+pub struct CsrfPlugin {
+    name: String,
+    secret: Vec<u8>,
+    header_name: String,
+    field_name: String,
+    protected_methods: Vec<Method>,
+    exempt_paths: Vec<String>,
+    session_cookie: String,
+    meta_tag: bool,
+    secure_cookie: bool,
+}
+
+impl CsrfPlugin {
+    pub fn new(config: HashMap<String, String>) -> Self {
+        let name = config.get(CONFIG_KEY_NAME).cloned().unwrap_or_else(|| DEFAULT_PLUGIN_NAME.to_string());
+
+        let secret = match config.get(CONFIG_KEY_SECRET_ENV) {
+            Some(var) => match std::env::var(var) {
+                Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+                _ => {
+                    log_warn!(name, "Secret environment variable is not set, using a random secret"; variable = var);
+                    rand::random::<[u8; SECRET_SIZE]>().to_vec()
+                }
+            },
+            None => rand::random::<[u8; SECRET_SIZE]>().to_vec(),
+        };
+
+        let protected_methods = Self::parse_list(&config, CONFIG_KEY_PROTECTED_METHODS, DEFAULT_PROTECTED_METHODS)
+            .into_iter()
+            .filter_map(|method| match Method::from_bytes(method.to_uppercase().as_bytes()) {
+                Ok(method) => Some(method),
+                Err(_) => {
+                    log_warn!(name, "Ignoring invalid method"; method = method);
+                    None
+                }
+            })
+            .collect();
+
+        Self {
+            name,
+            secret,
+            header_name: config.get(CONFIG_KEY_HEADER_NAME).cloned().unwrap_or_else(|| DEFAULT_HEADER_NAME.to_string()),
+            field_name: config.get(CONFIG_KEY_FIELD_NAME).cloned().unwrap_or_else(|| DEFAULT_FIELD_NAME.to_string()),
+            protected_methods,
+            exempt_paths: Self::parse_list(&config, CONFIG_KEY_EXEMPT_PATHS, ""),
+            session_cookie: config.get(CONFIG_KEY_SESSION_COOKIE).cloned().unwrap_or_else(|| DEFAULT_SESSION_COOKIE.to_string()),
+            meta_tag: config.get(CONFIG_KEY_META_TAG).and_then(|v| v.parse().ok()).unwrap_or(true),
+            secure_cookie: config.get(CONFIG_KEY_SECURE_COOKIE).and_then(|v| v.parse().ok()).unwrap_or(false),
+        }
+    }
+
+    /// Parse a comma-separated configuration value, skipping empty entries
+    fn parse_list(config: &HashMap<String, String>, key: &str, default: &str) -> Vec<String> {
+        config.get(key).map(String::as_str).unwrap_or(default)
+            .split(',')
+            .map(|entry| entry.trim().to_string())
+            .filter(|entry| !entry.is_empty())
+            .collect()
+    }
+
+    fn is_exempt(&self, path: &str) -> bool {
+        self.exempt_paths.iter().any(|prefix| path.starts_with(prefix.as_str()))
+    }
+
+    /// Value of a cookie sent with the request
+    fn cookie<'a>(request: &'a PluginRequest, name: &str) -> Option<&'a str> {
+        request.http_request.headers().get_all(COOKIE).iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|cookies| cookies.split(';'))
+            .filter_map(|cookie| cookie.trim().split_once('='))
+            .find(|(cookie_name, value)| *cookie_name == name && !value.is_empty())
+            .map(|(_, value)| value)
+    }
+
+    /// The session a token of this request is bound to, if there is one
+    fn session_key(&self, request: &PluginRequest) -> Option<String> {
+        if let Some(session_id) = Self::cookie(request, &self.session_cookie) {
+            return Some(format!("session:{}", session_id));
+        }
+        if let Some(user) = request.get_metadata(AUTHENTICATED_USER_METADATA) {
+            return Some(format!("user:{}", user));
+        }
+        Self::cookie(request, ANONYMOUS_COOKIE_NAME).map(|id| format!("anonymous:{}", id))
+    }
+
+    /// The token belonging to a session
+    fn token_for(&self, session_key: &str) -> String {
+        hex::encode(hmac_sha256(&self.secret, session_key.as_bytes()))
+    }
+
+    /// Whether the request carries the token of its session
+    async fn has_valid_token(&self, request: &mut PluginRequest) -> bool {
+        let Some(session_key) = self.session_key(request) else {
+            return false;
+        };
+        let expected = self.token_for(&session_key);
+
+        let header_token = request.http_request.headers()
+            .get(self.header_name.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+        let token = match header_token {
+            Some(token) => Some(token),
+            None => self.form_token(request).await,
+        };
+        token.is_some_and(|token| constant_time_eq(token.trim().as_bytes(), expected.as_bytes()))
+    }
+
+    /// The token submitted in the body of a form
+    async fn form_token(&self, request: &mut PluginRequest) -> Option<String> {
+        let content_type = request.http_request.headers()
+            .get(CONTENT_TYPE)?
+            .to_str()
+            .ok()?
+            .to_string();
+        let media_type = content_type.split(';').next().unwrap_or_default().trim().to_lowercase();
+
+        if media_type == CONTENT_TYPE_FORM {
+            let body = request.get_body().await.ok()?;
+            return url::form_urlencoded::parse(&body)
+                .find(|(name, _)| name == self.field_name.as_str())
+                .map(|(_, value)| value.into_owned());
+        }
+
+        if media_type == CONTENT_TYPE_MULTIPART {
+            let boundary = multer::parse_boundary(&content_type).ok()?;
+            // The body stays cached for the plugins handling the upload
+            let body = request.body().await.ok()?;
+            let mut multipart = multer::Multipart::new(body.stream(), boundary);
+            while let Ok(Some(field)) = multipart.next_field().await {
+                if field.name() == Some(self.field_name.as_str()) {
+                    return field.text().await.ok();
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Whether a form posts to this server with a method that needs a token
+    fn needs_token(form: &dom_query::Selection, host: Option<&str>) -> bool {
+        let method = form.attr("method").map(|m| m.trim().to_lowercase()).unwrap_or_default();
+        if method != "post" {
+            return false;
+        }
+        let Some(action) = form.attr("action") else {
+            return true;
+        };
+        // Never hand the token to another site
+        match url::Url::parse(action.trim()) {
+            Ok(url) => host.is_some_and(|host| {
+                let authority = match url.port() {
+                    Some(port) => format!("{}:{}", url.host_str().unwrap_or_default(), port),
+                    None => url.host_str().unwrap_or_default().to_string(),
+                };
+                authority.eq_ignore_ascii_case(host)
+            }),
+            Err(_) => !action.trim_start().starts_with("//"),
+        }
+    }
+
+    /// Add the token to the POST forms of a page, and as a meta element
+    fn rewrite_html(&self, html: &str, token: &str, host: Option<&str>) -> Option<String> {
+        let document = Document::from(html);
+        let mut rewritten = false;
+
+        for form in document.select(FORM_SELECTOR).iter() {
+            if Self::needs_token(&form, host) {
+                form.prepend_html(format!(
+                    r#"<input type="hidden" name="{}" value="{}">"#,
+                    html_escape(&self.field_name), token
+                ));
+                rewritten = true;
+            }
+        }
+
+        if self.meta_tag {
+            let head = document.select(HEAD_SELECTOR);
+            if head.length() > 0 {
+                head.append_html(format!(r#"<meta name="{}" content="{}">"#, META_TAG_NAME, token));
+                rewritten = true;
+            }
+        }
+
+        rewritten.then(|| document.html().to_string())
+    }
+
+    fn is_html_response(response: &Response<Body>) -> bool {
+        response.headers().get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|content_type| content_type.to_lowercase().starts_with(CONTENT_TYPE_HTML))
+    }
+
+    fn anonymous_cookie(&self, id: &str) -> String {
+        let secure = if self.secure_cookie { "; Secure" } else { "" };
+        format!("{}={}; Path=/; HttpOnly; SameSite=Lax{}", ANONYMOUS_COOKIE_NAME, id, secure)
+    }
+
+    fn create_rejection_response(&self) -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::FORBIDDEN)
+            .header(CONTENT_TYPE, CONTENT_TYPE_PLAIN)
+            .body(Body::from(MSG_TOKEN_REJECTED))
+            .unwrap()
+    }
+}
+
+/// HMAC-SHA256 as defined in RFC 2104
+fn hmac_sha256(key: &[u8], message: &[u8]) -> Vec<u8> {
+    let mut block = [0u8; HMAC_BLOCK_SIZE];
+    if key.len() > HMAC_BLOCK_SIZE {
+        block[..32].copy_from_slice(&Sha256::digest(key));
+    } else {
+        block[..key.len()].copy_from_slice(key);
+    }
+
+    let inner_pad: Vec<u8> = block.iter().map(|b| b ^ 0x36).collect();
+    let outer_pad: Vec<u8> = block.iter().map(|b| b ^ 0x5c).collect();
+    let inner = Sha256::new().chain_update(&inner_pad).chain_update(message).finalize();
+    Sha256::new().chain_update(&outer_pad).chain_update(inner).finalize().to_vec()
+}
+
+/// Compare two byte strings in time independent of where they differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn html_escape(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[async_trait]
+impl Plugin for CsrfPlugin {
+    async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
+        if !self.protected_methods.contains(request.http_request.method()) || self.is_exempt(&request.path) {
+            return None;
+        }
+
+        if self.has_valid_token(request).await {
+            return None;
+        }
+
+        log_debug!(self.name, context, "Rejected request without a valid token";
+            method = request.http_request.method(), path = request.path);
+        Some(self.create_rejection_response().into())
+    }
+
+    async fn handle_response(&self, request: &PluginRequest, response: &mut Response<Body>, context: &PluginContext) {
+        if request.http_request.method() == Method::HEAD
+            || !Self::is_html_response(response)
+            || response.headers().contains_key(CONTENT_ENCODING)
+        {
+            return;
+        }
+
+        // Visitors without any session get one of their own
+        let (session_key, new_session) = match self.session_key(request) {
+            Some(session_key) => (session_key, None),
+            None => {
+                let id = hex::encode(rand::random::<[u8; SECRET_SIZE]>());
+                (format!("anonymous:{}", id), Some(id))
+            }
+        };
+        let token = self.token_for(&session_key);
+        let host = request.http_request.headers()
+            .get(HOST)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string);
+
+        let rewritten = transform_body(response, |bytes| {
+            std::str::from_utf8(bytes).ok()
+                .and_then(|html| self.rewrite_html(html, &token, host.as_deref()))
+        }).await;
+        match rewritten {
+            Ok(true) => {
+                if let Some(id) = new_session {
+                    if let Ok(cookie) = HeaderValue::from_str(&self.anonymous_cookie(&id)) {
+                        response.headers_mut().append(SET_COOKIE, cookie);
+                    }
+                }
+            }
+            Ok(false) => {}
+            Err(e) => log_error!(self.name, context, "Failed to read response body: {}", e),
+        }
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// Export the plugin creation function
+create_plugin!(CsrfPlugin);
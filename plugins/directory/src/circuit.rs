@@ -0,0 +1,93 @@
+//! Circuit breaker for a directory's nested pipeline
+//!
+//! Counts the requests in a row the nested plugins failed. Once the count
+//! reaches the threshold the circuit opens and requests go straight to the
+//! fallback pipeline. After the reset time one request at a time tries the
+//! nested plugins again: one that succeeds closes the circuit, one that fails
+//! keeps it open for another reset time.
+
+use super::DEFAULT_PLUGIN_NAME;
+use rusty_beam_plugin_api::{log_info, log_warn};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+pub const CONFIG_KEY_FAILURE_THRESHOLD: &str = "failure_threshold";
+pub const CONFIG_KEY_CIRCUIT_RESET: &str = "circuit_reset_ms";
+
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_CIRCUIT_RESET_MS: u64 = 30_000;
+
+#[derive(Debug, Default)]
+struct State {
+    /// Failures since the nested plugins last succeeded
+    consecutive_failures: u32,
+    /// Until when requests skip the nested plugins, while the circuit is open
+    open_until: Option<Instant>,
+}
+
+/// Whether a directory's requests try its nested plugins
+#[derive(Debug)]
+pub struct Circuit {
+    /// Failures in a row that open the circuit; 0 never opens it
+    failure_threshold: u32,
+    /// How long the circuit stays open before the nested plugins are tried again
+    reset_after: Duration,
+    state: Mutex<State>,
+}
+
+impl Circuit {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        let failure_threshold = config.get(CONFIG_KEY_FAILURE_THRESHOLD)
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_FAILURE_THRESHOLD);
+        let reset_ms = config.get(CONFIG_KEY_CIRCUIT_RESET)
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_CIRCUIT_RESET_MS);
+        Self { failure_threshold, reset_after: Duration::from_millis(reset_ms), state: Mutex::new(State::default()) }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether a request should try the nested plugins
+    ///
+    /// Once the reset time is over, the request that asks first is let
+    /// through and the circuit stays open for the others until it is decided.
+    pub fn allows_request(&self) -> bool {
+        let mut state = self.state();
+        match state.open_until {
+            None => true,
+            Some(until) if Instant::now() < until => false,
+            Some(_) => {
+                state.open_until = Some(Instant::now() + self.reset_after);
+                true
+            }
+        }
+    }
+
+    /// Note a request the nested plugins handled, closing the circuit
+    pub fn succeeded(&self, directory: &str) {
+        let mut state = self.state();
+        if state.open_until.take().is_some() {
+            log_info!(DEFAULT_PLUGIN_NAME, "Nested plugins recovered, closing the circuit"; directory = directory);
+        }
+        state.consecutive_failures = 0;
+    }
+
+    /// Note a request the nested plugins failed, opening the circuit once
+    /// there have been enough in a row
+    pub fn failed(&self, directory: &str) {
+        let mut state = self.state();
+        state.consecutive_failures = state.consecutive_failures.saturating_add(1);
+        if self.failure_threshold == 0 || state.consecutive_failures < self.failure_threshold {
+            return;
+        }
+        if state.open_until.is_none() {
+            log_warn!(DEFAULT_PLUGIN_NAME, "Nested plugins failed {} requests in a row, opening the circuit", state.consecutive_failures;
+                directory = directory, reset_ms = self.reset_after.as_millis());
+        }
+        state.open_until = Some(Instant::now() + self.reset_after);
+    }
+}
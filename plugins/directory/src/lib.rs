@@ -21,43 +21,204 @@
 //!
 //! ## Configuration
 //! - `directory`: The path prefix to match (e.g., "/admin", "/api")
+//! - `host`: Comma-separated host names the request must be for, such as
+//!   `admin.example.com` or `*.example.com` (default: any host)
 //! - `nested_plugins`: JSON array of plugin configurations to execute
+//! - `config_href`: `file://` URL of an HTML file describing nested plugins
+//!   with microdata, run after any given in `nested_plugins`
+//! - `inherit`: Comma-separated keys of the directory's configuration passed
+//!   down to nested plugins, or "none" (default: every key)
+//! - `request_timeout_ms`: Milliseconds the nested plugins may take to answer
+//!   a request before it gets a 504 (default: only the server's limit)
+//! - `response_phase`: Which nested plugins see the response: `all`,
+//!   `responder_only` or `matched_only` (default: "all")
+//! - `fallback_plugins`: JSON array of plugin configurations run when the
+//!   nested plugins fail, as for `nested_plugins`
+//! - `fallback_config_href`: `file://` URL of an HTML file describing
+//!   fallback plugins, run after any given in `fallback_plugins`
+//! - `failure_threshold`: Failed requests in a row that open the circuit,
+//!   0 for never (default: 5)
+//! - `circuit_reset_ms`: Milliseconds the circuit stays open before the
+//!   nested plugins are tried again (default: 30000)
+//!
+//! ## Configuration Inheritance
+//! Any other property set on the directory plugin, such as `authfile` or
+//! `logfile`, is passed down to every nested plugin, so it need only be
+//! written once. A nested plugin's own value for a key always takes
+//! precedence over an inherited one. Nested directory plugins pass on what
+//! they inherited along with their own properties, so settings flow down
+//! through every level. The directory's own `directory`, `host`,
+//! `nested_plugins`, `config_href`, `inherit`, `request_timeout_ms`,
+//! `response_phase`, `isolation`, `name` and fallback settings are never
+//! inherited. Host settings such as `hostRoot`
+//! reach nested plugins through the request context, as for top-level plugins.
 //!
 //! ## Nested Plugin Configuration
 //! Each nested plugin requires:
 //! - `library`: Path to the plugin shared library (file:// URL)
 //! - `config`: Plugin-specific configuration as key-value pairs
 //! - `nested_plugins`: Additional nested plugins (recursive)
+//! - `condition`: Optional expression the request must satisfy for the plugin
+//!   to run, as for top-level plugins
+//!
+//! ## External Pipeline Files
+//! Rather than writing nested plugins as JSON, `config_href` may name an HTML
+//! file that describes them with the same microdata as the main
+//! configuration. Every top-level `https://rustybeam.net/schema/Plugin` item
+//! of the file becomes a nested plugin, in document order. Its `library` and
+//! `condition` properties are read as in the main configuration, nested
+//! `plugin` items become its own nested plugins, and every other property is
+//! passed to the plugin as configuration:
+//!
+//! ```html
+//! <table itemscope itemtype="https://rustybeam.net/schema/Plugin">
+//!     <tr><td>Library</td><td itemprop="library">file://./plugins/libbasic_auth.so</td></tr>
+//!     <tr><td>Users</td><td itemprop="authfile">file://./admin/users.html</td></tr>
+//! </table>
+//! ```
+//!
+//! The file is read once, when the directory plugin is created; paths are
+//! relative to the server's working directory.
 //!
 //! ## Path Matching Rules
 //! - Exact match: `/admin` matches `/admin`
 //! - Prefix match: `/admin` matches `/admin/users`, `/admin/settings`
 //! - Trailing slashes ignored: `/admin/` matches `/admin`
 //! - Case sensitive: `/Admin` does not match `/admin`
+//! - Wildcard segment: `/sites/*/static` matches `/sites/blog/static/app.css`;
+//!   `*` stands for exactly one non-empty segment
+//! - Parameter segment: `/tenants/:tenant/files` matches
+//!   `/tenants/acme/files/report.html` and captures `acme` as `tenant`
+//!
+//! ## Route Parameters
+//! Before the nested plugins run, each value a parameter segment captured is
+//! stored in the request metadata as `route_param_<name>`, so the example
+//! above sets `route_param_tenant` to `acme`. Nested plugins can read it to
+//! choose tenant-specific files or settings, and it stays in the metadata
+//! for the plugins after the directory and the access log. Nested
+//! directories add their own parameters to those of the directories around
+//! them. Authorization rules with paths such as `/tenants/:tenant/*` match
+//! the same requests.
+//!
+//! ## Host Matching
+//! With `host` set, a request must also be for one of the named hosts, as
+//! the server saw it in the `Host` header without the port. Host names are
+//! compared ignoring case, and `*` stands for any run of characters, so
+//! `*.example.com` matches every subdomain of `example.com` but not
+//! `example.com` itself. One host configuration can thus route
+//! `admin.example.com` and `www.example.com` to different nested pipelines,
+//! each a directory plugin for `/` with its own `host`.
 //!
 //! ## Execution Flow
 //! 1. **Request Phase**: If path matches, execute nested plugins sequentially
 //! 2. **First Response Wins**: Stop at first plugin that returns a response
-//! 3. **Response Phase**: All matching nested plugins process the response
+//! 3. **Response Phase**: Nested plugins process the response, as chosen by
+//!    `response_phase`
+//!
+//! ## Response Phase Scope
+//! By default every nested plugin gets `handle_response` whenever the request
+//! is for the directory, even if an earlier nested plugin answered and the
+//! later ones never saw the request, or the response came from outside the
+//! directory. `response_phase` narrows this:
+//! - `all`: every nested plugin, as above
+//! - `responder_only`: only the nested plugin that produced the response;
+//!   none if the response came from elsewhere
+//! - `matched_only`: the nested plugins the request reached, like layers of
+//!   an onion: those up to and including the one that answered, or all of
+//!   them if none did and the request went on past the directory. None if the
+//!   request never reached the directory.
+//!
+//! A nested plugin's `condition` is checked again in the response phase in
+//! every mode.
+//!
+//! ## Access Checks Outside Requests
+//! Plugins such as search ask the host whether a user may read a document
+//! and which of its elements are withheld from them. For paths in the
+//! directory the nested plugins answer these, as they answer requests. If a
+//! configured nested or fallback plugin failed to load, every element of
+//! the directory's documents is withheld, as that plugin can't be asked.
+//!
+//! ## Capabilities
+//! The directory declares the capabilities its nested and fallback plugins
+//! provide, and those they require that no nested plugin before them
+//! provides, so the server checks the plugins around it against them. An
+//! authorization plugin nested after an authentication plugin thus requires
+//! nothing of the plugins before the directory, and the directory provides
+//! both to the plugins after it.
+//!
+//! ## Request Metadata
+//! A request for the directory gets `matched_directory` metadata naming the
+//! directory pattern. When a nested plugin answers, `responding_plugin` names
+//! it and `matched_directory` the directory it belongs to, so the access log
+//! can tell which pipeline served a request. With nested directories the
+//! innermost one that answered is recorded.
+//!
+//! ## Request Deadlines
+//! With `request_timeout_ms`, requests to the directory get a deadline that
+//! many milliseconds after they reach it, or keep the server's if that is
+//! sooner. Nested plugins see it as `PluginContext::deadline`. A nested plugin
+//! still running when the deadline passes is abandoned and the request is
+//! answered with a 504 naming it, as the server does for top-level plugins.
+//!
+//! ## Fallback Pipeline
+//! With fallback plugins configured, a request the nested plugins fail is
+//! handed to them instead, for example to serve a static snapshot with the
+//! file-handler when a javascript-engine handler breaks. The nested plugins
+//! fail a request when the one that answers it answers with a 5xx status,
+//! which includes panics, or overruns `request_timeout_ms`; that timeout
+//! then only limits the nested plugins, and the fallback plugins have what
+//! is left of the server's. The fallback plugins run in order like the
+//! nested ones. If none of them answers, the nested plugins' failure is
+//! sent. Inherited configuration reaches them as it does nested plugins.
+//!
+//! Failures also feed a circuit breaker. After `failure_threshold` failed
+//! requests in a row the circuit opens: for `circuit_reset_ms` requests go
+//! straight to the fallback plugins, then one request tries the nested
+//! plugins again, closing the circuit if they handle it and keeping it open
+//! for another period if not. Opening and closing are logged. A request the
+//! fallback plugins served gets `directory_fallback` metadata giving the
+//! reason, `error`, `timeout` or `circuit_open`, and only they take part in
+//! its response phase. Without fallback plugins failures are sent as they
+//! are and there is no circuit.
+//!
+//! ## Process Isolation
+//! A nested plugin configured with `isolation` set to `process` is run by a
+//! `rusty-beam-plugin-host` process instead of being loaded into the server,
+//! so a crash in it fails only the request it was handling; the next request
+//! starts a new host. `pluginHost` names the host binary if it is not next to
+//! the server's. See `rusty_beam_plugin_api::isolation` for what crosses the
+//! process boundary.
 //!
 //! ## Security Considerations
 //! - **Library Loading**: Only loads libraries from file:// URLs
-//! - **Sandboxing**: Each nested plugin runs in the same process (no isolation)
+//! - **Sandboxing**: Nested plugins run in the server's process unless
+//!   configured with `isolation` set to `process` (see Process Isolation)
 //! - **Configuration Validation**: Validates plugin configurations at load time
 //! - **Error Handling**: Failed plugins don't crash the directory plugin
 //!
 //! ## Performance
 //! - **Lazy Loading**: Plugins loaded once at startup, not per request
-//! - **Fast Path Matching**: O(1) string comparison for path checking
+//! - **Fast Path Matching**: one comparison per segment of the directory pattern
 //! - **Memory Efficient**: Plugins shared via Arc for multiple references
 
 use async_trait::async_trait;
 use hyper::{Body, Response};
-use rusty_beam_plugin_api::{create_plugin, Plugin, PluginContext, PluginRequest, PluginResponse};
+use rusty_beam_plugin_api::{capabilities, create_plugin, log, log_error, log_warn, run_response_phase, Capabilities, Condition, ConditionalPlugin, ConfigIssue, DocumentChange, HostServices, Plugin, PluginContext, PluginFailures, PluginRequest, PluginResponse, SelectorError, SelectorOperation};
+use rusty_beam_plugin_api::condition::glob_match;
+use rusty_beam_plugin_api::deadline::{self, CONFIG_KEY_REQUEST_TIMEOUT};
+use rusty_beam_plugin_api::isolation::{self, IsolatedPlugin};
+use rusty_beam_plugin_api::services::WITHHOLD_ALL;
+use circuit::{Circuit, CONFIG_KEY_CIRCUIT_RESET, CONFIG_KEY_FAILURE_THRESHOLD};
 use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use serde::{Deserialize, Serialize};
 use libloading::{Library, Symbol};
+use microdata_extract::{MicrodataError, MicrodataExtractor, MicrodataItem};
+
+mod circuit;
 
 // Plugin identification
 const DEFAULT_PLUGIN_NAME: &str = "directory";
@@ -74,10 +235,91 @@ const DEFAULT_JSON_CONFIG: &str = "{}";
 // Test metadata tracking
 const METADATA_CALLED_SUFFIX: &str = "_called";
 const METADATA_TRUE_VALUE: &str = "true";
+/// Prefix of the metadata key recording which nested plugin answered
+const METADATA_RESPONDER_PREFIX: &str = "directory_responder_";
+/// Responder value when the request passed through every nested plugin
+const METADATA_NO_RESPONDER: &str = "none";
+/// Metadata key naming the directory that served a request, for access logs
+const METADATA_MATCHED_DIRECTORY: &str = "matched_directory";
+/// Metadata key naming the nested plugin that produced the response
+const METADATA_RESPONDING_PLUGIN: &str = "responding_plugin";
+/// Prefix of the metadata keys holding the values parameter segments captured
+const METADATA_ROUTE_PARAM_PREFIX: &str = "route_param_";
+/// Metadata key giving why the fallback plugins served a request
+const METADATA_FALLBACK: &str = "directory_fallback";
+/// Suffix of the responder key marking requests the fallback plugins served
+const METADATA_FALLBACK_SUFFIX: &str = "_fallback";
+
+// Reasons the fallback plugins serve a request
+const FALLBACK_REASON_ERROR: &str = "error";
+const FALLBACK_REASON_TIMEOUT: &str = "timeout";
+const FALLBACK_REASON_CIRCUIT_OPEN: &str = "circuit_open";
+
+// Directory pattern segments
+const PARAMETER_SEGMENT_PREFIX: char = ':';
+const WILDCARD_SEGMENT: &str = "*";
 
 // Configuration keys
 const CONFIG_KEY_DIRECTORY: &str = "directory";
+const CONFIG_KEY_HOST: &str = "host";
 const CONFIG_KEY_NESTED_PLUGINS: &str = "nested_plugins";
+const CONFIG_KEY_INHERIT: &str = "inherit";
+const CONFIG_KEY_NAME: &str = "name";
+const CONFIG_KEY_CONFIG_HREF: &str = "config_href";
+const CONFIG_KEY_RESPONSE_PHASE: &str = "response_phase";
+const CONFIG_KEY_FALLBACK_PLUGINS: &str = "fallback_plugins";
+const CONFIG_KEY_FALLBACK_CONFIG_HREF: &str = "fallback_config_href";
+const INHERIT_NONE: &str = "none";
+
+// Pipeline files
+const SCHEMA_PLUGIN: &str = "https://rustybeam.net/schema/Plugin";
+const PROPERTY_LIBRARY: &str = "library";
+const PROPERTY_CONDITION: &str = "condition";
+const PROPERTY_PLUGIN: &str = "plugin";
+
+/// Keys that configure the directory plugin itself and are never inherited
+const NON_INHERITED_KEYS: &[&str] = &[
+    CONFIG_KEY_DIRECTORY,
+    CONFIG_KEY_HOST,
+    CONFIG_KEY_NESTED_PLUGINS,
+    CONFIG_KEY_INHERIT,
+    CONFIG_KEY_NAME,
+    CONFIG_KEY_CONFIG_HREF,
+    CONFIG_KEY_REQUEST_TIMEOUT,
+    CONFIG_KEY_RESPONSE_PHASE,
+    CONFIG_KEY_FALLBACK_PLUGINS,
+    CONFIG_KEY_FALLBACK_CONFIG_HREF,
+    CONFIG_KEY_FAILURE_THRESHOLD,
+    CONFIG_KEY_CIRCUIT_RESET,
+    isolation::ISOLATION_KEY,
+    capabilities::CONFIG_KEY_PROVIDES,
+    capabilities::CONFIG_KEY_REQUIRES,
+];
+
+/// Tells directory plugins apart in request metadata
+static NEXT_INSTANCE: AtomicUsize = AtomicUsize::new(0);
+
+/// Which nested plugins take part in the response phase
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResponsePhase {
+    /// Every nested plugin
+    All,
+    /// Only the nested plugin that produced the response
+    ResponderOnly,
+    /// The nested plugins the request reached
+    MatchedOnly,
+}
+
+impl ResponsePhase {
+    fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "all" => Some(Self::All),
+            "responder_only" => Some(Self::ResponderOnly),
+            "matched_only" => Some(Self::MatchedOnly),
+            _ => None,
+        }
+    }
+}
 
 /// Configuration structure for nested plugins
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -87,20 +329,34 @@ pub struct PluginConfig {
     pub config: HashMap<String, String>,
     #[serde(default)]
     pub nested_plugins: Vec<PluginConfig>,
+    #[serde(default)]
+    pub condition: Option<String>,
 }
 
 /// Configuration structure for the directory plugin
 #[derive(Debug, Serialize, Deserialize)]
 pub struct DirectoryConfig {
     pub directory: String,
+    /// Host name patterns the request must match; empty matches any host
+    #[serde(default)]
+    pub hosts: Vec<String>,
     #[serde(default)]
     pub nested_plugins: Vec<PluginConfig>,
+    /// Plugins run when the nested plugins fail
+    #[serde(default)]
+    pub fallback_plugins: Vec<PluginConfig>,
+    /// Configuration passed down to every nested plugin
+    #[serde(default)]
+    pub inherited: HashMap<String, String>,
 }
 
 /// Wrapper to keep dynamic libraries alive
+///
+/// Fields are dropped in order, so the plugin goes before the library whose
+/// code its destructor runs.
 struct DynamicPluginWrapper {
-    _library: Library,
     plugin: Box<dyn Plugin>,
+    _library: Library,
 }
 
 impl std::fmt::Debug for DynamicPluginWrapper {
@@ -119,6 +375,65 @@ impl Plugin for DynamicPluginWrapper {
         self.plugin.handle_response(request, response, context).await;
     }
 
+    async fn handle_response_stream(&self, request: &PluginRequest, head: &mut hyper::http::response::Parts, body: Body, context: &PluginContext) -> Body {
+        self.plugin.handle_response_stream(request, head, body, context).await
+    }
+
+    async fn on_startup(&self) {
+        self.plugin.on_startup().await;
+    }
+
+    fn attach_services(&self, services: Arc<dyn HostServices>) {
+        self.plugin.attach_services(services);
+    }
+
+    async fn on_shutdown(&self) {
+        self.plugin.on_shutdown().await;
+    }
+
+    fn check_access(&self, user: Option<&str>, method: &str, path: &str, context: &PluginContext) -> Option<bool> {
+        self.plugin.check_access(user, method, path, context)
+    }
+
+    fn publish(&self, channel: &str, message: &str) -> usize {
+        self.plugin.publish(channel, message)
+    }
+
+    fn on_change(&self, change: &DocumentChange, context: &PluginContext) {
+        self.plugin.on_change(change, context)
+    }
+
+    fn check_selector_access(
+        &self,
+        user: Option<&str>,
+        method: &str,
+        path: &str,
+        selector: &str,
+        context: &PluginContext,
+    ) -> Option<bool> {
+        self.plugin.check_selector_access(user, method, path, selector, context)
+    }
+
+    fn apply_selector(&self, operation: &SelectorOperation, context: &PluginContext) -> Option<Result<String, SelectorError>> {
+        self.plugin.apply_selector(operation, context)
+    }
+
+    fn withheld_selectors(&self, user: Option<&str>, path: &str, context: &PluginContext) -> Vec<String> {
+        self.plugin.withheld_selectors(user, path, context)
+    }
+
+    fn validate_config(&self, context: &PluginContext) -> Vec<ConfigIssue> {
+        self.plugin.validate_config(context)
+    }
+
+    fn failures(&self) -> Option<PluginFailures> {
+        self.plugin.failures()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.plugin.capabilities()
+    }
+
     fn name(&self) -> &str {
         self.plugin.name()
     }
@@ -128,19 +443,120 @@ impl Plugin for DynamicPluginWrapper {
 #[derive(Debug)]
 pub struct DirectoryPlugin {
     directory: String,
+    /// Lowercase host name patterns; empty matches any host
+    hosts: Vec<String>,
     nested_plugins: Vec<Arc<dyn Plugin>>,
+    /// Time nested plugins have to answer a request
+    request_timeout: Option<Duration>,
+    /// Which nested plugins see the response
+    response_phase: ResponsePhase,
+    /// Metadata key recording which nested plugin answered a request
+    responder_key: String,
+    /// Plugins run when the nested plugins fail
+    fallback_plugins: Vec<Arc<dyn Plugin>>,
+    /// Whether requests try the nested plugins, when there are fallback plugins
+    circuit: Circuit,
+    /// Whether a configured nested or fallback plugin failed to load, so it
+    /// can't be asked what it withholds
+    incomplete: bool,
+}
+
+/// How a request went through a list of plugins
+enum ChainOutcome {
+    /// The plugin at the index answered
+    Responded(usize, PluginResponse),
+    /// The plugin at the index was still running at the deadline
+    TimedOut(usize),
+    /// No plugin answered
+    PassedThrough,
 }
 
 impl DirectoryPlugin {
     pub fn new(config: HashMap<String, String>) -> Self {
+        let request_timeout = Self::parse_request_timeout(&config);
+        let response_phase = Self::parse_response_phase(&config);
+        let circuit = Circuit::from_config(&config);
         let directory_config = Self::parse_directory_config(config);
         let directory = Self::process_directory_path(&directory_config.directory);
-        let nested_plugins = Self::load_nested_plugins(&directory_config.nested_plugins);
+        let nested_plugins = Self::load_nested_plugins(&directory_config.nested_plugins, &directory_config.inherited);
+        let fallback_plugins = Self::load_nested_plugins(&directory_config.fallback_plugins, &directory_config.inherited);
+        let incomplete = nested_plugins.len() < directory_config.nested_plugins.len()
+            || fallback_plugins.len() < directory_config.fallback_plugins.len();
 
         Self {
             directory,
+            hosts: directory_config.hosts,
             nested_plugins,
+            request_timeout,
+            response_phase,
+            responder_key: Self::new_responder_key(),
+            fallback_plugins,
+            circuit,
+            incomplete,
+        }
+    }
+    
+    /// Replace the plugins run when the nested plugins fail
+    pub fn with_fallback_plugins(mut self, fallback_plugins: Vec<Arc<dyn Plugin>>) -> Self {
+        self.fallback_plugins = fallback_plugins;
+        self
+    }
+    
+    /// The nested plugins followed by the fallback plugins
+    fn all_plugins(&self) -> impl Iterator<Item = &Arc<dyn Plugin>> {
+        self.nested_plugins.iter().chain(&self.fallback_plugins)
+    }
+    
+    /// Metadata key marking requests the fallback plugins served
+    fn fallback_key(&self) -> String {
+        format!("{}{}", self.responder_key, METADATA_FALLBACK_SUFFIX)
+    }
+    
+    /// Parse which nested plugins see the response
+    fn parse_response_phase(config: &HashMap<String, String>) -> ResponsePhase {
+        let Some(value) = config.get(CONFIG_KEY_RESPONSE_PHASE) else {
+            return ResponsePhase::All;
+        };
+        ResponsePhase::parse(value).unwrap_or_else(|| {
+            log_warn!(DEFAULT_PLUGIN_NAME, "Ignoring invalid response phase, using 'all'"; response_phase = value);
+            ResponsePhase::All
+        })
+    }
+    
+    /// A metadata key no other directory plugin uses
+    fn new_responder_key() -> String {
+        format!("{}{}", METADATA_RESPONDER_PREFIX, NEXT_INSTANCE.fetch_add(1, Ordering::Relaxed))
+    }
+    
+    /// Indexes of the plugins, of `count` nested or fallback plugins, that
+    /// take part in the response phase of a request
+    fn response_phase_plugins(&self, request: &PluginRequest, count: usize) -> Range<usize> {
+        let all = 0..count;
+        if self.response_phase == ResponsePhase::All {
+            return all;
+        }
+        // Without the key the request never reached the nested plugins
+        let responder = match request.get_metadata(&self.responder_key) {
+            None => return 0..0,
+            Some(METADATA_NO_RESPONDER) => None,
+            Some(index) => index.parse::<usize>().ok().filter(|index| all.contains(index)),
+        };
+        match (self.response_phase, responder) {
+            (ResponsePhase::ResponderOnly, Some(index)) => index..index + 1,
+            (ResponsePhase::ResponderOnly, None) => 0..0,
+            (_, Some(index)) => 0..index + 1,
+            (_, None) => all,
+        }
+    }
+    
+    /// Parse the time nested plugins have to answer a request
+    fn parse_request_timeout(config: &HashMap<String, String>) -> Option<Duration> {
+        let value = config.get(CONFIG_KEY_REQUEST_TIMEOUT)?;
+        let budget = deadline::parse_budget(value);
+        if budget.is_none() && value.trim() != "0" {
+            log_warn!(DEFAULT_PLUGIN_NAME, "Ignoring invalid request timeout"; request_timeout_ms = value);
         }
+        budget
     }
     
     /// Parse directory configuration from raw config map
@@ -153,7 +569,15 @@ impl DirectoryPlugin {
         // 2. Create a plugin registry pattern to avoid re-serialization
         // 3. Use a more efficient binary format instead of JSON
         
-        let nested_plugins = Self::parse_nested_plugins_config(&config);
+        let mut nested_plugins = Self::parse_nested_plugins_config(&config, CONFIG_KEY_NESTED_PLUGINS);
+        if let Some(href) = config.get(CONFIG_KEY_CONFIG_HREF) {
+            nested_plugins.extend(Self::load_pipeline_file(href));
+        }
+        let mut fallback_plugins = Self::parse_nested_plugins_config(&config, CONFIG_KEY_FALLBACK_PLUGINS);
+        if let Some(href) = config.get(CONFIG_KEY_FALLBACK_CONFIG_HREF) {
+            fallback_plugins.extend(Self::load_pipeline_file(href));
+        }
+        let inherited = Self::inherited_config(&config);
         let directory = config
             .get(CONFIG_KEY_DIRECTORY)
             .cloned()
@@ -161,17 +585,117 @@ impl DirectoryPlugin {
         
         DirectoryConfig {
             directory,
+            hosts: Self::parse_hosts(&config),
             nested_plugins,
+            fallback_plugins,
+            inherited,
+        }
+    }
+    
+    /// Parse the host name patterns requests must match
+    fn parse_hosts(config: &HashMap<String, String>) -> Vec<String> {
+        config.get(CONFIG_KEY_HOST)
+            .map(|hosts| hosts.split(',')
+                .map(|host| host.trim().to_lowercase())
+                .filter(|host| !host.is_empty())
+                .collect())
+            .unwrap_or_default()
+    }
+    
+    /// Select the keys of the directory's configuration that nested plugins inherit
+    fn inherited_config(config: &HashMap<String, String>) -> HashMap<String, String> {
+        let selected: Option<Vec<&str>> = config.get(CONFIG_KEY_INHERIT)
+            .map(|keys| keys.split(',').map(str::trim).filter(|key| !key.is_empty()).collect());
+        
+        config.iter()
+            .filter(|(key, _)| !NON_INHERITED_KEYS.contains(&key.as_str()))
+            .filter(|(key, _)| match &selected {
+                Some(keys) => keys.contains(&key.as_str()) && !keys.contains(&INHERIT_NONE),
+                None => true,
+            })
+            .map(|(key, value)| (key.clone(), value.clone()))
+            .collect()
+    }
+    
+    /// Build the configuration a nested plugin is created with
+    /// 
+    /// Inherited keys come first so the plugin's own values replace them, and
+    /// the plugin's own nested plugins are passed on for nested directories.
+    fn nested_plugin_config(config: &PluginConfig, inherited: &HashMap<String, String>) -> HashMap<String, String> {
+        let mut merged = inherited.clone();
+        merged.extend(config.config.iter().map(|(key, value)| (key.clone(), value.clone())));
+        
+        if !config.nested_plugins.is_empty()
+            && let Ok(nested_json) = serde_json::to_string(&config.nested_plugins)
+        {
+            merged.insert(CONFIG_KEY_NESTED_PLUGINS.to_string(), nested_json);
         }
+        merged
     }
     
-    /// Parse nested plugins configuration from JSON
-    fn parse_nested_plugins_config(config: &HashMap<String, String>) -> Vec<PluginConfig> {
-        config.get(CONFIG_KEY_NESTED_PLUGINS)
+    /// Parse the plugin configurations a key holds as JSON
+    fn parse_nested_plugins_config(config: &HashMap<String, String>, key: &str) -> Vec<PluginConfig> {
+        config.get(key)
             .and_then(|json| serde_json::from_str::<Vec<PluginConfig>>(json).ok())
             .unwrap_or_default()
     }
 
+    /// Read the nested plugins described by an external pipeline file
+    fn load_pipeline_file(href: &str) -> Vec<PluginConfig> {
+        let Some(path) = href.strip_prefix(FILE_URL_SCHEME) else {
+            log_error!(DEFAULT_PLUGIN_NAME, "Unsupported pipeline file URL scheme"; config_href = href);
+            return Vec::new();
+        };
+        let html = match std::fs::read_to_string(path) {
+            Ok(html) => html,
+            Err(e) => {
+                log_error!(DEFAULT_PLUGIN_NAME, "Failed to read pipeline file: {}", e; config_href = href);
+                return Vec::new();
+            }
+        };
+        match Self::parse_pipeline_html(&html) {
+            Ok(plugins) => {
+                if plugins.is_empty() {
+                    log_warn!(DEFAULT_PLUGIN_NAME, "Pipeline file describes no plugins"; config_href = href);
+                }
+                plugins
+            }
+            Err(e) => {
+                log_error!(DEFAULT_PLUGIN_NAME, "Failed to parse pipeline file: {}", e; config_href = href);
+                Vec::new()
+            }
+        }
+    }
+    
+    /// Parse the top-level Plugin items of a pipeline file
+    fn parse_pipeline_html(html: &str) -> Result<Vec<PluginConfig>, MicrodataError> {
+        let items = MicrodataExtractor::new().extract(html)?;
+        Ok(items.iter()
+            .filter(|item| item.item_type() == Some(SCHEMA_PLUGIN))
+            .filter_map(Self::plugin_config_from_item)
+            .collect())
+    }
+    
+    /// Build a nested plugin's configuration from its microdata item
+    fn plugin_config_from_item(item: &MicrodataItem) -> Option<PluginConfig> {
+        let Some(library) = item.get_property(PROPERTY_LIBRARY).filter(|library| !library.is_empty()) else {
+            log_warn!(DEFAULT_PLUGIN_NAME, "Skipping a Plugin item without a library");
+            return None;
+        };
+        let condition = item.get_property(PROPERTY_CONDITION)
+            .filter(|condition| !condition.trim().is_empty());
+        let nested_plugins = item.get_nested_items(PROPERTY_PLUGIN).into_iter()
+            .filter_map(Self::plugin_config_from_item)
+            .collect();
+        let config = item.properties().iter()
+            .filter(|property| ![PROPERTY_LIBRARY, PROPERTY_CONDITION, PROPERTY_PLUGIN].contains(&property.name()))
+            .map(|property| (property.name().to_string(), property.value_as_string()))
+            .filter(|(_, value)| !value.is_empty())
+            .collect();
+        
+        Some(PluginConfig { library, config, nested_plugins, condition })
+    }
+
     /// Process directory path to handle file:// URLs
     fn process_directory_path(directory: &str) -> String {
         if directory.starts_with(FILE_URL_SCHEME) {
@@ -186,48 +710,53 @@ impl DirectoryPlugin {
     }
 
     /// Load nested plugins from configuration with error tracking
-    fn load_nested_plugins(plugin_configs: &[PluginConfig]) -> Vec<Arc<dyn Plugin>> {
+    fn load_nested_plugins(plugin_configs: &[PluginConfig], inherited: &HashMap<String, String>) -> Vec<Arc<dyn Plugin>> {
         let mut plugins = Vec::new();
         let mut failed_count = 0;
         
         for (index, plugin_config) in plugin_configs.iter().enumerate() {
-            match Self::load_plugin_from_config(plugin_config) {
+            match Self::load_plugin_from_config(plugin_config, inherited) {
                 Some(plugin) => {
                     plugins.push(plugin);
                 }
                 None => {
-                    eprintln!(
-                        "[DirectoryPlugin] Failed to load nested plugin at index {}: {}",
-                        index, plugin_config.library
-                    );
+                    log_error!(DEFAULT_PLUGIN_NAME, "Failed to load nested plugin";
+                        index = index, library = plugin_config.library);
                     failed_count += 1;
                 }
             }
         }
         
         if failed_count > 0 {
-            eprintln!(
-                "[DirectoryPlugin] Loaded {}/{} nested plugins successfully",
-                plugins.len(),
-                plugin_configs.len()
-            );
+            log_warn!(DEFAULT_PLUGIN_NAME, "Loaded {}/{} nested plugins successfully", plugins.len(), plugin_configs.len());
         }
         
         plugins
     }
 
     /// Load a single plugin from configuration with validation
-    fn load_plugin_from_config(config: &PluginConfig) -> Option<Arc<dyn Plugin>> {
+    fn load_plugin_from_config(config: &PluginConfig, inherited: &HashMap<String, String>) -> Option<Arc<dyn Plugin>> {
         let library_path = &config.library;
         
         // Validate and extract file path
         let file_path = Self::validate_and_extract_library_path(library_path)?;
         
+        let condition = match config.condition.as_deref().map(Condition::parse).transpose() {
+            Ok(condition) => condition,
+            Err(e) => {
+                log_error!(DEFAULT_PLUGIN_NAME, "Invalid condition for plugin: {}", e; library = library_path);
+                return None;
+            }
+        };
+        
         // Load the plugin with error handling
-        match Self::load_dynamic_plugin(file_path, &config.config) {
-            Some(plugin) => Some(plugin),
+        match Self::load_dynamic_plugin(file_path, &Self::nested_plugin_config(config, inherited)) {
+            Some(plugin) => match condition {
+                Some(condition) => Some(Arc::new(ConditionalPlugin::new(condition, plugin))),
+                None => Some(plugin),
+            },
             None => {
-                eprintln!("[DirectoryPlugin] Failed to load plugin from: {}", library_path);
+                log_error!(DEFAULT_PLUGIN_NAME, "Failed to load plugin"; library = library_path);
                 None
             }
         }
@@ -237,7 +766,7 @@ impl DirectoryPlugin {
     fn validate_and_extract_library_path(library_path: &str) -> Option<&str> {
         // Only support file:// URLs for security
         if !library_path.starts_with(FILE_URL_SCHEME) {
-            eprintln!("[DirectoryPlugin] Unsupported library URL scheme: {}", library_path);
+            log_error!(DEFAULT_PLUGIN_NAME, "Unsupported library URL scheme"; library = library_path);
             return None;
         }
         
@@ -245,7 +774,7 @@ impl DirectoryPlugin {
         
         // Basic path validation
         if file_path.is_empty() || file_path.contains("../") {
-            eprintln!("[DirectoryPlugin] Invalid library path: {}", file_path);
+            log_error!(DEFAULT_PLUGIN_NAME, "Invalid library path"; path = file_path);
             return None;
         }
         
@@ -254,6 +783,15 @@ impl DirectoryPlugin {
 
     /// Load a plugin from a dynamic library
     fn load_dynamic_plugin(library_path: &str, config: &HashMap<String, String>) -> Option<Arc<dyn Plugin>> {
+        if isolation::is_isolated(config) {
+            return match IsolatedPlugin::spawn(library_path, config.clone()) {
+                Ok(plugin) => Some(Arc::new(plugin)),
+                Err(e) => {
+                    log_error!(DEFAULT_PLUGIN_NAME, "Failed to start plugin host: {}", e; library = library_path);
+                    None
+                }
+            };
+        }
         let library = Self::load_library_safely(library_path)?;
         let plugin = Self::create_plugin_from_library(library, config)?;
         Some(Arc::new(plugin))
@@ -263,7 +801,7 @@ impl DirectoryPlugin {
     fn load_library_safely(library_path: &str) -> Option<Library> {
         // Validate file exists and is readable
         if !std::path::Path::new(library_path).exists() {
-            eprintln!("[DirectoryPlugin] Library file not found: {}", library_path);
+            log_error!(DEFAULT_PLUGIN_NAME, "Library file not found"; library = library_path);
             return None;
         }
         
@@ -271,7 +809,7 @@ impl DirectoryPlugin {
             match Library::new(library_path) {
                 Ok(lib) => Some(lib),
                 Err(e) => {
-                    eprintln!("[DirectoryPlugin] Failed to load library {}: {}", library_path, e);
+                    log_error!(DEFAULT_PLUGIN_NAME, "Failed to load library: {}", e; library = library_path);
                     None
                 }
             }
@@ -284,6 +822,11 @@ impl DirectoryPlugin {
             // Get the plugin creation function
             let create_fn = Self::get_plugin_creation_function(&library)?;
             
+            // Hand on the logger this library received from the server
+            if let Ok(set_logger) = library.get::<unsafe extern "C" fn(*const std::ffi::c_void)>(log::SET_LOGGER_FUNCTION) {
+                set_logger(log::logger_into_raw());
+            }
+            
             // Prepare configuration for FFI
             let config_cstr = Self::prepare_config_for_ffi(config)?;
             
@@ -336,18 +879,127 @@ impl DirectoryPlugin {
 
         Self {
             directory,
+            hosts: Self::parse_hosts(&config),
             nested_plugins,
+            request_timeout: Self::parse_request_timeout(&config),
+            response_phase: Self::parse_response_phase(&config),
+            responder_key: Self::new_responder_key(),
+            fallback_plugins: Vec::new(),
+            circuit: Circuit::from_config(&config),
+            incomplete: false,
         }
     }
     
+    /// Check if a request for a path on the context's host belongs to this directory
+    fn matches(&self, path: &str, context: &PluginContext) -> bool {
+        self.matches_host(&context.host_name) && self.matches_directory(path)
+    }
+    
+    /// Check if a host name matches one of this directory's host patterns
+    fn matches_host(&self, host_name: &str) -> bool {
+        let host_name = host_name.to_lowercase();
+        self.hosts.is_empty() || self.hosts.iter().any(|pattern| glob_match(pattern, &host_name))
+    }
+    
     /// Check if a request path matches this directory's pattern
     fn matches_directory(&self, path: &str) -> bool {
-        let normalized_dir = self.normalize_path(&self.directory);
-        let normalized_path = self.normalize_path(path);
+        self.route_params(path).is_some()
+    }
+    
+    /// The names and values the pattern's parameter segments capture from a
+    /// request path, or None if the path is not in the directory
+    ///
+    /// The path matches if it starts with as many segments as the pattern
+    /// has and each of them matches the pattern's segment at its position.
+    fn route_params<'a>(&'a self, path: &'a str) -> Option<Vec<(&'a str, &'a str)>> {
+        let pattern_segments: Vec<&str> = self.normalize_path(&self.directory).split('/').collect();
+        let path_segments: Vec<&str> = self.normalize_path(path).split('/').collect();
+        if path_segments.len() < pattern_segments.len() {
+            return None;
+        }
         
-        // Check if path matches exactly or starts with directory followed by /
-        normalized_path == normalized_dir || 
-        path.starts_with(&format!("{}/", normalized_dir))
+        let mut params = Vec::new();
+        for (pattern_segment, path_segment) in pattern_segments.iter().zip(&path_segments) {
+            if let Some(name) = pattern_segment.strip_prefix(PARAMETER_SEGMENT_PREFIX) {
+                if path_segment.is_empty() {
+                    return None;
+                }
+                params.push((name, *path_segment));
+            } else if *pattern_segment == WILDCARD_SEGMENT {
+                if path_segment.is_empty() {
+                    return None;
+                }
+            } else if pattern_segment != path_segment {
+                return None;
+            }
+        }
+        Some(params)
+    }
+    
+    /// Record this directory and its nested plugin as the ones that served a
+    /// request, unless a nested directory already recorded its own
+    fn record_responder(&self, request: &mut PluginRequest, plugin: &dyn Plugin) {
+        if request.get_metadata(METADATA_RESPONDING_PLUGIN).is_some() {
+            return;
+        }
+        request.set_metadata(METADATA_MATCHED_DIRECTORY.to_string(), self.directory.clone());
+        request.set_metadata(METADATA_RESPONDING_PLUGIN.to_string(), plugin.name().to_string());
+    }
+    
+    /// Run a request through plugins in sequence until one returns a response
+    async fn run_chain(
+        &self,
+        plugins: &[Arc<dyn Plugin>],
+        request: &mut PluginRequest,
+        context: &PluginContext,
+    ) -> ChainOutcome {
+        for (index, plugin) in plugins.iter().enumerate() {
+            let outcome = deadline::within(context.deadline, plugin.handle_request(request, context)).await;
+            let Ok(outcome) = outcome else {
+                log_warn!(DEFAULT_PLUGIN_NAME, context, "Nested plugin exceeded the request deadline";
+                    plugin = plugin.name(), path = request.path);
+                return ChainOutcome::TimedOut(index);
+            };
+            match outcome {
+                Some(response) => {
+                    context.log_verbose(&format!(
+                        "[DirectoryPlugin] Nested plugin '{}' (index {}) handled request",
+                        plugin.name(), index
+                    ));
+                    return ChainOutcome::Responded(index, response);
+                }
+                None => {
+                    context.log_verbose(&format!(
+                        "[DirectoryPlugin] Nested plugin '{}' (index {}) passed through",
+                        plugin.name(), index
+                    ));
+                }
+            }
+        }
+        ChainOutcome::PassedThrough
+    }
+    
+    /// Answer a request as a run through plugins decided, recording which of
+    /// them answered
+    fn finish_request(
+        &self,
+        request: &mut PluginRequest,
+        plugins: &[Arc<dyn Plugin>],
+        outcome: ChainOutcome,
+        context: &PluginContext,
+    ) -> Option<PluginResponse> {
+        let (index, response) = match outcome {
+            ChainOutcome::Responded(index, response) => (index, response),
+            ChainOutcome::TimedOut(index) => (index, deadline::exceeded_response(plugins[index].name()).into()),
+            ChainOutcome::PassedThrough => {
+                context.log_verbose("[DirectoryPlugin] No nested plugin provided a response");
+                request.set_metadata(self.responder_key.clone(), METADATA_NO_RESPONDER.to_string());
+                return None;
+            }
+        };
+        request.set_metadata(self.responder_key.clone(), index.to_string());
+        self.record_responder(request, plugins[index].as_ref());
+        Some(response)
     }
     
     /// Normalize a path by removing trailing slashes
@@ -363,11 +1015,11 @@ impl Plugin for DirectoryPlugin {
         request: &mut PluginRequest,
         context: &PluginContext,
     ) -> Option<PluginResponse> {
-        // Check if the request path matches the configured directory
-        if !self.matches_directory(&request.path) {
+        // Check if the request host and path match the configured directory
+        if !self.matches(&request.path, context) {
             context.log_verbose(&format!(
-                "[DirectoryPlugin] Path '{}' does not match directory '{}'",
-                request.path, self.directory
+                "[DirectoryPlugin] Host '{}' and path '{}' do not match directory '{}'",
+                context.host_name, request.path, self.directory
             ));
             return None;
         }
@@ -376,28 +1028,70 @@ impl Plugin for DirectoryPlugin {
             "[DirectoryPlugin] Path '{}' matches directory '{}', executing {} nested plugins",
             request.path, self.directory, self.nested_plugins.len()
         ));
+        request.set_metadata(METADATA_MATCHED_DIRECTORY.to_string(), self.directory.clone());
+        let params: Vec<(String, String)> = self.route_params(&request.path)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(name, value)| (format!("{}{}", METADATA_ROUTE_PARAM_PREFIX, name), value.to_string()))
+            .collect();
+        for (key, value) in params {
+            request.set_metadata(key, value);
+        }
 
-        // Path matches, execute nested plugins in sequence until one returns a response
-        for (index, plugin) in self.nested_plugins.iter().enumerate() {
-            match plugin.handle_request(request, context).await {
-                Some(response) => {
-                    context.log_verbose(&format!(
-                        "[DirectoryPlugin] Nested plugin '{}' (index {}) handled request",
-                        plugin.name(), index
-                    ));
-                    return Some(response);
-                }
-                None => {
-                    context.log_verbose(&format!(
-                        "[DirectoryPlugin] Nested plugin '{}' (index {}) passed through",
-                        plugin.name(), index
-                    ));
-                }
+        // The directory's own budget can only shorten the request's, and
+        // only binds the nested plugins
+        let budgeted_context;
+        let nested_context = match self.request_timeout {
+            Some(budget) => {
+                budgeted_context = PluginContext {
+                    deadline: deadline::tighten(context.deadline, Some(budget)),
+                    ..context.clone()
+                };
+                &budgeted_context
             }
+            None => context,
+        };
+
+        if self.fallback_plugins.is_empty() {
+            let outcome = self.run_chain(&self.nested_plugins, request, nested_context).await;
+            return self.finish_request(request, &self.nested_plugins, outcome, context);
         }
 
-        context.log_verbose("[DirectoryPlugin] No nested plugin provided a response");
-        None
+        // With fallback plugins, a failure of the nested plugins is only sent
+        // when none of them answers instead
+        let (reason, failure) = if self.circuit.allows_request() {
+            let outcome = self.run_chain(&self.nested_plugins, request, nested_context).await;
+            let reason = match &outcome {
+                ChainOutcome::TimedOut(_) => FALLBACK_REASON_TIMEOUT,
+                ChainOutcome::Responded(_, response) if response.response.status().is_server_error() => FALLBACK_REASON_ERROR,
+                _ => {
+                    self.circuit.succeeded(&self.directory);
+                    return self.finish_request(request, &self.nested_plugins, outcome, context);
+                }
+            };
+            self.circuit.failed(&self.directory);
+            log_warn!(DEFAULT_PLUGIN_NAME, context, "Nested plugins failed, running the fallback plugins";
+                directory = self.directory, path = request.path, reason = reason);
+            (reason, Some(outcome))
+        } else {
+            context.log_verbose(&format!(
+                "[DirectoryPlugin] Circuit for '{}' is open, running the fallback plugins",
+                self.directory
+            ));
+            (FALLBACK_REASON_CIRCUIT_OPEN, None)
+        };
+
+        let outcome = self.run_chain(&self.fallback_plugins, request, context).await;
+        match (outcome, failure) {
+            (ChainOutcome::PassedThrough, Some(failure)) => {
+                self.finish_request(request, &self.nested_plugins, failure, context)
+            }
+            (outcome, _) => {
+                request.set_metadata(self.fallback_key(), reason.to_string());
+                request.set_metadata(METADATA_FALLBACK.to_string(), reason.to_string());
+                self.finish_request(request, &self.fallback_plugins, outcome, context)
+            }
+        }
     }
 
     async fn handle_response(
@@ -407,27 +1101,154 @@ impl Plugin for DirectoryPlugin {
         context: &PluginContext,
     ) {
         // Only call handle_response on nested plugins if the directory matches
-        if !self.matches_directory(&request.path) {
+        if !self.matches(&request.path, context) {
             context.log_verbose(&format!(
-                "[DirectoryPlugin] Skipping response phase - path '{}' does not match directory '{}'",
-                request.path, self.directory
+                "[DirectoryPlugin] Skipping response phase - host '{}' and path '{}' do not match directory '{}'",
+                context.host_name, request.path, self.directory
             ));
             return;
         }
         
+        // Requests the fallback plugins served are theirs to finish
+        let plugins = if request.get_metadata(&self.fallback_key()).is_some() {
+            &self.fallback_plugins
+        } else {
+            &self.nested_plugins
+        };
+        let indexes = self.response_phase_plugins(request, plugins.len());
         context.log_verbose(&format!(
-            "[DirectoryPlugin] Processing response phase for {} nested plugins",
-            self.nested_plugins.len()
+            "[DirectoryPlugin] Processing response phase for {} of {} nested plugins",
+            indexes.len(), plugins.len()
         ));
         
-        // Call handle_response on all nested plugins
-        for (index, plugin) in self.nested_plugins.iter().enumerate() {
+        for index in indexes {
+            let plugin = &plugins[index];
             context.log_verbose(&format!(
                 "[DirectoryPlugin] Calling handle_response on nested plugin '{}' (index {})",
                 plugin.name(), index
             ));
-            plugin.handle_response(request, response, context).await;
+            run_response_phase(plugin.as_ref(), request, response, context).await;
+        }
+    }
+
+    async fn on_startup(&self) {
+        for plugin in self.all_plugins() {
+            plugin.on_startup().await;
+        }
+    }
+
+    fn attach_services(&self, services: Arc<dyn HostServices>) {
+        for plugin in self.all_plugins() {
+            plugin.attach_services(Arc::clone(&services));
+        }
+    }
+
+    async fn on_shutdown(&self) {
+        for plugin in self.all_plugins() {
+            plugin.on_shutdown().await;
+        }
+    }
+
+    fn check_access(&self, user: Option<&str>, method: &str, path: &str, context: &PluginContext) -> Option<bool> {
+        // Nested plugins only govern paths inside the directory
+        if !self.matches(path, context) {
+            return None;
+        }
+        self.all_plugins()
+            .find_map(|plugin| plugin.check_access(user, method, path, context))
+    }
+
+    fn publish(&self, channel: &str, message: &str) -> usize {
+        self.all_plugins()
+            .map(|plugin| plugin.publish(channel, message))
+            .sum()
+    }
+    
+    fn on_change(&self, change: &DocumentChange, context: &PluginContext) {
+        for plugin in self.all_plugins() {
+            plugin.on_change(change, context);
+        }
+    }
+
+    fn check_selector_access(
+        &self,
+        user: Option<&str>,
+        method: &str,
+        path: &str,
+        selector: &str,
+        context: &PluginContext,
+    ) -> Option<bool> {
+        if !self.matches(path, context) {
+            return None;
+        }
+        self.all_plugins()
+            .find_map(|plugin| plugin.check_selector_access(user, method, path, selector, context))
+    }
+
+    fn apply_selector(&self, operation: &SelectorOperation, context: &PluginContext) -> Option<Result<String, SelectorError>> {
+        if !self.matches(&operation.path, context) {
+            return None;
+        }
+        self.all_plugins()
+            .find_map(|plugin| plugin.apply_selector(operation, context))
+    }
+
+    fn withheld_selectors(&self, user: Option<&str>, path: &str, context: &PluginContext) -> Vec<String> {
+        if !self.matches(path, context) {
+            return Vec::new();
+        }
+        if self.incomplete {
+            return vec![WITHHOLD_ALL.to_string()];
+        }
+        let mut selectors: Vec<String> = Vec::new();
+        for selector in self.all_plugins().flat_map(|plugin| plugin.withheld_selectors(user, path, context)) {
+            if !selectors.contains(&selector) {
+                selectors.push(selector);
+            }
         }
+        selectors
+    }
+
+    fn validate_config(&self, context: &PluginContext) -> Vec<ConfigIssue> {
+        self.all_plugins()
+            .flat_map(|plugin| plugin.validate_config(context).into_iter().map(move |issue| ConfigIssue {
+                message: format!("{} in {}: {}", plugin.name(), self.directory, issue.message),
+                ..issue
+            }))
+            .collect()
+    }
+
+    fn failures(&self) -> Option<PluginFailures> {
+        self.all_plugins()
+            .filter_map(|plugin| plugin.failures())
+            .reduce(PluginFailures::merge)
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        let mut capabilities = Capabilities::default();
+        // The fallback plugins run instead of the nested ones, not after them
+        for plugins in [&self.nested_plugins, &self.fallback_plugins] {
+            let mut provided: Vec<String> = Vec::new();
+            for plugin in plugins {
+                let own = plugin.capabilities();
+                for capability in own.requires {
+                    if !provided.contains(&capability) && !capabilities.requires.contains(&capability) {
+                        capabilities.requires.push(capability);
+                    }
+                }
+                for capability in own.provides {
+                    if !provided.contains(&capability) {
+                        provided.push(capability);
+                    }
+                }
+            }
+            for capability in provided {
+                if !capabilities.provides.contains(&capability) {
+                    capabilities.provides.push(capability);
+                }
+            }
+        }
+        capabilities
     }
 
     fn name(&self) -> &str {
@@ -442,10 +1263,12 @@ create_plugin!(DirectoryPlugin);
 mod tests {
     use super::*;
     use hyper::{Body, Method, Request, Response, StatusCode};
-    use rusty_beam_plugin_api::{PluginContext, PluginRequest, PluginResponse};
+    use rusty_beam_plugin_api::{PanicBreaker, PanicGuard, PluginContext, PluginRequest, PluginResponse};
     use std::collections::HashMap;
     use std::sync::Arc;
 
+    const RESPONSE_PHASE_HEADER: &str = "x-response-phase";
+
     // Mock plugin for testing
     #[derive(Debug)]
     struct MockPlugin {
@@ -491,12 +1314,11 @@ mod tests {
         async fn handle_response(
             &self,
             _request: &PluginRequest,
-            _response: &mut Response<Body>,
+            response: &mut Response<Body>,
             _context: &PluginContext,
         ) {
-            // For testing, we can't modify the request in handle_response
-            // In a real implementation, we would use logging or other mechanisms
-            // For now, we'll just check if the plugin was called in handle_request
+            // The request cannot be changed here, so note the call on the response
+            response.headers_mut().append(RESPONSE_PHASE_HEADER, self.name.parse().unwrap());
         }
 
         fn name(&self) -> &str {
@@ -504,6 +1326,74 @@ mod tests {
         }
     }
 
+    // Mock plugin that appends to bodies as they stream
+    #[derive(Debug)]
+    struct SuffixPlugin;
+
+    #[async_trait]
+    impl Plugin for SuffixPlugin {
+        async fn handle_response_stream(
+            &self,
+            _request: &PluginRequest,
+            head: &mut hyper::http::response::Parts,
+            body: Body,
+            _context: &PluginContext,
+        ) -> Body {
+            head.headers.remove(hyper::header::CONTENT_LENGTH);
+            rusty_beam_plugin_api::map_body_stream(body, |chunk| chunk.unwrap_or_else(|| "!".into()))
+        }
+
+        fn name(&self) -> &str {
+            "suffix"
+        }
+    }
+
+    // Mock plugin that never answers
+    #[derive(Debug)]
+    struct StalledPlugin;
+
+    #[async_trait]
+    impl Plugin for StalledPlugin {
+        async fn handle_request(
+            &self,
+            _request: &mut PluginRequest,
+            _context: &PluginContext,
+        ) -> Option<PluginResponse> {
+            std::future::pending().await
+        }
+
+        fn name(&self) -> &str {
+            "stalled"
+        }
+    }
+
+    // Mock plugin that always fails
+    #[derive(Debug)]
+    struct FailingPlugin;
+
+    #[async_trait]
+    impl Plugin for FailingPlugin {
+        async fn handle_request(
+            &self,
+            request: &mut PluginRequest,
+            _context: &PluginContext,
+        ) -> Option<PluginResponse> {
+            request.set_metadata(
+                format!("failing{}", METADATA_CALLED_SUFFIX),
+                METADATA_TRUE_VALUE.to_string(),
+            );
+            let response = Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .body(Body::from("broken"))
+                .unwrap();
+            Some(PluginResponse::from(response))
+        }
+
+        fn name(&self) -> &str {
+            "failing"
+        }
+    }
+
     fn create_test_request(path: &str) -> PluginRequest {
         let req = Request::builder()
             .method(Method::GET)
@@ -523,6 +1413,8 @@ mod tests {
             request_id: "test-request-id".to_string(),
             runtime_handle: Some(tokio::runtime::Handle::current()),
             verbose: false,
+            services: None,
+            deadline: None,
         }
     }
 
@@ -571,6 +1463,76 @@ mod tests {
         assert_eq!(request.get_metadata(&format!("test{}", METADATA_CALLED_SUFFIX)), None);
     }
 
+    #[tokio::test]
+    async fn test_directory_plugin_matches_normalized_spellings() {
+        let config = HashMap::from([("directory".to_string(), "/admin".to_string())]);
+        let mock_plugin = Arc::new(MockPlugin::new("test", true, "response"));
+        let directory_plugin = DirectoryPlugin::new_with_nested_plugins(config, vec![mock_plugin]);
+        let context = create_test_context();
+
+        // Spellings of paths below /admin the server brings into one form
+        for raw in ["/%61dmin/users", "//admin/users", "/public/../admin/users", "/./admin/%2E/users"] {
+            let path = rusty_beam_plugin_api::paths::normalize_request_path(raw).unwrap();
+            assert_eq!(path, "/admin/users", "{}", raw);
+            let mut request = create_test_request(&path);
+            assert!(directory_plugin.handle_request(&mut request, &context).await.is_some(), "{}", raw);
+        }
+
+        // Neighbours of the directory stay outside it
+        let path = rusty_beam_plugin_api::paths::normalize_request_path("/admin/../administrator").unwrap();
+        let mut request = create_test_request(&path);
+        assert!(directory_plugin.handle_request(&mut request, &context).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_directory_plugin_captures_route_parameters() {
+        let config = HashMap::from([("directory".to_string(), "/tenants/:tenant/*/files".to_string())]);
+        let mock_plugin = Arc::new(MockPlugin::new("test", true, "response"));
+        let directory_plugin = DirectoryPlugin::new_with_nested_plugins(config, vec![mock_plugin]);
+        let context = create_test_context();
+
+        let mut request = create_test_request("/tenants/acme/2024/files/report.html");
+        assert!(directory_plugin.handle_request(&mut request, &context).await.is_some());
+        assert_eq!(request.get_metadata("route_param_tenant"), Some("acme"));
+        assert!(directory_plugin.matches_directory("/tenants/acme/2024/files"));
+
+        // Every segment of the pattern must be there and match
+        for path in ["/tenants/acme/files", "/tenants//2024/files", "/tenants/acme/2024/other", "/tenants"] {
+            let mut request = create_test_request(path);
+            assert!(directory_plugin.handle_request(&mut request, &context).await.is_none(), "{}", path);
+            assert_eq!(request.get_metadata("route_param_tenant"), None, "{}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_directory_plugin_matches_host() {
+        let config = HashMap::from([
+            ("directory".to_string(), "/".to_string()),
+            ("host".to_string(), "admin.example.com, *.staging.example.com".to_string()),
+        ]);
+        let mock_plugin = Arc::new(MockPlugin::new("test", true, "response"));
+        let directory_plugin = DirectoryPlugin::new_with_nested_plugins(config, vec![mock_plugin]);
+        
+        for (host_name, matches) in [
+            ("admin.example.com", true),
+            ("Admin.Example.com", true),
+            ("www.staging.example.com", true),
+            ("staging.example.com", false),
+            ("www.example.com", false),
+            ("localhost", false),
+        ] {
+            let mut request = create_test_request("/index.html");
+            let context = PluginContext { host_name: host_name.to_string(), ..create_test_context() };
+            let response = directory_plugin.handle_request(&mut request, &context).await;
+            assert_eq!(response.is_some(), matches, "{}", host_name);
+        }
+        
+        // Without a host every host matches
+        let config = HashMap::from([("directory".to_string(), "/admin".to_string())]);
+        let directory_plugin = DirectoryPlugin::new_with_nested_plugins(config, vec![]);
+        assert!(directory_plugin.matches_host("www.example.com"));
+    }
+
     #[tokio::test]
     async fn test_directory_plugin_sequential_execution() {
         let config = HashMap::from([("directory".to_string(), "/admin".to_string())]);
@@ -594,6 +1556,238 @@ mod tests {
         assert_eq!(request.get_metadata(&format!("third{}", METADATA_CALLED_SUFFIX)), None); // Should not be called
     }
 
+    #[tokio::test]
+    async fn test_directory_plugin_records_serving_directory_and_plugin() {
+        let context = create_test_context();
+        let inner = Arc::new(DirectoryPlugin::new_with_nested_plugins(
+            HashMap::from([("directory".to_string(), "/admin/reports".to_string())]),
+            vec![Arc::new(MockPlugin::new("reports", true, "reports"))],
+        ));
+        let outer = DirectoryPlugin::new_with_nested_plugins(
+            HashMap::from([("directory".to_string(), "/admin".to_string())]),
+            vec![inner, Arc::new(MockPlugin::new("admin", true, "admin"))],
+        );
+
+        // The innermost directory that answered is recorded
+        let mut request = create_test_request("/admin/reports/2024");
+        assert!(outer.handle_request(&mut request, &context).await.is_some());
+        assert_eq!(request.get_metadata(METADATA_MATCHED_DIRECTORY), Some("/admin/reports"));
+        assert_eq!(request.get_metadata(METADATA_RESPONDING_PLUGIN), Some("reports"));
+
+        let mut request = create_test_request("/admin/users");
+        assert!(outer.handle_request(&mut request, &context).await.is_some());
+        assert_eq!(request.get_metadata(METADATA_MATCHED_DIRECTORY), Some("/admin"));
+        assert_eq!(request.get_metadata(METADATA_RESPONDING_PLUGIN), Some("admin"));
+
+        // Requests passing through keep the directory but name no plugin
+        let passing = DirectoryPlugin::new_with_nested_plugins(
+            HashMap::from([("directory".to_string(), "/admin".to_string())]),
+            vec![Arc::new(MockPlugin::new("first", false, ""))],
+        );
+        let mut request = create_test_request("/admin/users");
+        assert!(passing.handle_request(&mut request, &context).await.is_none());
+        assert_eq!(request.get_metadata(METADATA_MATCHED_DIRECTORY), Some("/admin"));
+        assert_eq!(request.get_metadata(METADATA_RESPONDING_PLUGIN), None);
+    }
+
+    #[tokio::test]
+    async fn test_directory_plugin_skips_plugins_whose_condition_fails() {
+        let config = HashMap::from([("directory".to_string(), "/admin".to_string())]);
+        let gated = |name: &str, condition: &str| -> Arc<dyn Plugin> {
+            Arc::new(ConditionalPlugin::new(
+                Condition::parse(condition).unwrap(),
+                Arc::new(MockPlugin::new(name, false, "")),
+            ))
+        };
+        let directory_plugin = DirectoryPlugin::new_with_nested_plugins(
+            config,
+            vec![
+                gated("role", "role == admin && method(GET, HEAD)"),
+                gated("posts", "method(POST)"),
+                gated("reports", "path('/admin/*/reports') || !has(role)"),
+            ]
+        );
+
+        let mut request = create_test_request("/admin/2024/reports");
+        request.set_metadata("role".to_string(), "admin".to_string());
+        let context = create_test_context();
+
+        let response = directory_plugin.handle_request(&mut request, &context).await;
+        
+        assert!(response.is_none());
+        assert_eq!(request.get_metadata(&format!("role{}", METADATA_CALLED_SUFFIX)), Some(METADATA_TRUE_VALUE));
+        assert_eq!(request.get_metadata(&format!("posts{}", METADATA_CALLED_SUFFIX)), None);
+        assert_eq!(request.get_metadata(&format!("reports{}", METADATA_CALLED_SUFFIX)), Some(METADATA_TRUE_VALUE));
+        
+        assert!(Condition::parse("role = admin").is_err());
+        assert!(Condition::parse("has(role").is_err());
+        assert!(Condition::parse("unknown(role)").is_err());
+    }
+
+    #[test]
+    fn test_nested_plugins_inherit_directory_config() {
+        let directory_config = |extra: &[(&str, &str)]| {
+            let mut config = HashMap::from([
+                ("directory".to_string(), "/admin".to_string()),
+                ("name".to_string(), "admin-area".to_string()),
+                ("authfile".to_string(), "file://./auth/admin.html".to_string()),
+                ("logfile".to_string(), "file://./logs/admin.log".to_string()),
+            ]);
+            config.extend(extra.iter().map(|(key, value)| (key.to_string(), value.to_string())));
+            DirectoryPlugin::inherited_config(&config)
+        };
+        let nested = PluginConfig {
+            library: "file://./plugins/librusty_beam_access_log.so".to_string(),
+            config: HashMap::from([("logfile".to_string(), "file://./logs/own.log".to_string())]),
+            nested_plugins: Vec::new(),
+            condition: None,
+        };
+        
+        let merged = DirectoryPlugin::nested_plugin_config(&nested, &directory_config(&[]));
+        assert_eq!(merged.get("authfile").map(String::as_str), Some("file://./auth/admin.html"));
+        assert_eq!(merged.get("logfile").map(String::as_str), Some("file://./logs/own.log"));
+        assert!(!merged.contains_key("directory"));
+        assert!(!merged.contains_key("name"));
+        
+        let selected = directory_config(&[("inherit", "authfile")]);
+        assert_eq!(selected.keys().collect::<Vec<_>>(), vec!["authfile"]);
+        assert!(directory_config(&[("inherit", "none")]).is_empty());
+    }
+    
+    #[test]
+    fn test_pipeline_file_describes_nested_plugins() {
+        let html = r#"<!DOCTYPE html>
+<html><body>
+    <table itemscope itemtype="https://rustybeam.net/schema/Plugin">
+        <tr><td itemprop="library">file://./plugins/librusty_beam_basic_auth.so</td></tr>
+        <tr><td itemprop="authfile">file://./admin/users.html</td></tr>
+        <tr><td itemprop="condition">method(GET, HEAD)</td></tr>
+    </table>
+    <div itemscope itemtype="https://rustybeam.net/schema/Plugin">
+        <span itemprop="library">file://./plugins/libdirectory.so</span>
+        <span itemprop="directory">/admin/reports</span>
+        <div itemprop="plugin" itemscope itemtype="https://rustybeam.net/schema/Plugin">
+            <span itemprop="library">file://./plugins/librusty_beam_file_handler.so</span>
+        </div>
+    </div>
+    <div itemscope itemtype="https://rustybeam.net/schema/Plugin">
+        <span itemprop="realm">No library</span>
+    </div>
+    <div itemscope itemtype="https://rustybeam.net/schema/Credential">
+        <span itemprop="library">file://./plugins/not-a-plugin.so</span>
+    </div>
+</body></html>"#;
+        
+        let plugins = DirectoryPlugin::parse_pipeline_html(html).unwrap();
+        assert_eq!(plugins.len(), 2);
+        
+        assert_eq!(plugins[0].library, "file://./plugins/librusty_beam_basic_auth.so");
+        assert_eq!(plugins[0].condition.as_deref(), Some("method(GET, HEAD)"));
+        assert_eq!(plugins[0].config, HashMap::from([
+            ("authfile".to_string(), "file://./admin/users.html".to_string()),
+        ]));
+        
+        assert_eq!(plugins[1].config.get("directory").map(String::as_str), Some("/admin/reports"));
+        assert!(!plugins[1].config.contains_key("plugin"));
+        assert_eq!(plugins[1].nested_plugins.len(), 1);
+        assert_eq!(plugins[1].nested_plugins[0].library, "file://./plugins/librusty_beam_file_handler.so");
+        assert!(plugins[1].nested_plugins[0].config.is_empty());
+    }
+    
+    #[tokio::test]
+    async fn test_nested_plugin_overrunning_request_timeout_gets_504() {
+        let config = HashMap::from([
+            ("directory".to_string(), "/admin".to_string()),
+            ("request_timeout_ms".to_string(), "20".to_string()),
+        ]);
+        let mock_plugin = Arc::new(MockPlugin::new("after", true, "response"));
+        let directory_plugin = DirectoryPlugin::new_with_nested_plugins(
+            config,
+            vec![Arc::new(StalledPlugin), mock_plugin]
+        );
+
+        let mut request = create_test_request("/admin");
+        let context = create_test_context();
+
+        let response = directory_plugin.handle_request(&mut request, &context).await.unwrap();
+        assert_eq!(response.response.status(), StatusCode::GATEWAY_TIMEOUT);
+        let body = hyper::body::to_bytes(response.response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("'stalled'"));
+        assert_eq!(request.get_metadata(&format!("after{}", METADATA_CALLED_SUFFIX)), None);
+    }
+
+    #[tokio::test]
+    async fn test_fallback_plugins_serve_failed_requests() {
+        let config = HashMap::from([
+            ("directory".to_string(), "/app".to_string()),
+            ("request_timeout_ms".to_string(), "20".to_string()),
+        ]);
+        let context = create_test_context();
+
+        for primary in [Arc::new(FailingPlugin) as Arc<dyn Plugin>, Arc::new(StalledPlugin)] {
+            let directory_plugin = DirectoryPlugin::new_with_nested_plugins(config.clone(), vec![primary])
+                .with_fallback_plugins(vec![Arc::new(MockPlugin::new("snapshot", true, "static copy"))]);
+            let mut request = create_test_request("/app/page");
+            let response = directory_plugin.handle_request(&mut request, &context).await.unwrap();
+            assert_eq!(response.response.status(), StatusCode::OK);
+            assert_eq!(request.get_metadata(METADATA_RESPONDING_PLUGIN), Some("snapshot"));
+
+            // Only the fallback plugins finish the response
+            let mut response = response.response;
+            directory_plugin.handle_response(&request, &mut response, &context).await;
+            let calls: Vec<_> = response.headers().get_all(RESPONSE_PHASE_HEADER).iter()
+                .map(|value| value.to_str().unwrap().to_string())
+                .collect();
+            assert_eq!(calls, vec!["snapshot"]);
+        }
+
+        // A failure is sent when the fallback plugins pass it by
+        let directory_plugin = DirectoryPlugin::new_with_nested_plugins(config, vec![Arc::new(FailingPlugin)])
+            .with_fallback_plugins(vec![Arc::new(MockPlugin::new("snapshot", false, ""))]);
+        let mut request = create_test_request("/app/page");
+        let response = directory_plugin.handle_request(&mut request, &context).await.unwrap();
+        assert_eq!(response.response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert_eq!(request.get_metadata(METADATA_RESPONDING_PLUGIN), Some("failing"));
+        assert_eq!(request.get_metadata(METADATA_FALLBACK), None);
+    }
+
+    #[tokio::test]
+    async fn test_circuit_opens_after_failures_in_a_row() {
+        let config = HashMap::from([
+            ("directory".to_string(), "/app".to_string()),
+            ("failure_threshold".to_string(), "2".to_string()),
+            ("circuit_reset_ms".to_string(), "50".to_string()),
+        ]);
+        let directory_plugin = DirectoryPlugin::new_with_nested_plugins(config, vec![Arc::new(FailingPlugin)])
+            .with_fallback_plugins(vec![Arc::new(MockPlugin::new("snapshot", true, "static copy"))]);
+        let context = create_test_context();
+        let failing_called = format!("failing{}", METADATA_CALLED_SUFFIX);
+
+        let mut reasons = Vec::new();
+        for _ in 0..3 {
+            let mut request = create_test_request("/app");
+            directory_plugin.handle_request(&mut request, &context).await.unwrap();
+            reasons.push((
+                request.get_metadata(&failing_called).is_some(),
+                request.get_metadata(METADATA_FALLBACK).unwrap().to_string(),
+            ));
+        }
+        assert_eq!(reasons, vec![
+            (true, "error".to_string()),
+            (true, "error".to_string()),
+            (false, "circuit_open".to_string()),
+        ]);
+
+        // After the reset one request tries the nested plugins again
+        tokio::time::sleep(Duration::from_millis(60)).await;
+        let mut request = create_test_request("/app");
+        directory_plugin.handle_request(&mut request, &context).await.unwrap();
+        assert!(request.get_metadata(&failing_called).is_some());
+        let mut request = create_test_request("/app");
+        directory_plugin.handle_request(&mut request, &context).await.unwrap();
+        assert!(request.get_metadata(&failing_called).is_none());
+    }
+
     #[tokio::test]
     async fn test_directory_plugin_no_nested_response() {
         let config = HashMap::from([("directory".to_string(), "/admin".to_string())]);
@@ -656,6 +1850,26 @@ mod tests {
         assert!(true);
     }
 
+    #[tokio::test]
+    async fn test_nested_plugins_transform_streamed_bodies() {
+        let config = HashMap::from([("directory".to_string(), "/admin".to_string())]);
+        let directory_plugin = DirectoryPlugin::new_with_nested_plugins(config, vec![Arc::new(SuffixPlugin)]);
+        let context = create_test_context();
+
+        let body_for = |path: &str| {
+            let request = create_test_request(path);
+            let mut response = Response::new(Body::from("test"));
+            let directory_plugin = &directory_plugin;
+            let context = &context;
+            async move {
+                directory_plugin.handle_response(&request, &mut response, context).await;
+                hyper::body::to_bytes(response.into_body()).await.unwrap()
+            }
+        };
+        assert_eq!(body_for("/admin/page").await, "test!");
+        assert_eq!(body_for("/public").await, "test");
+    }
+
     #[tokio::test]
     async fn test_directory_plugin_file_url_parsing() {
         let config = HashMap::from([("directory".to_string(), "file://./examples/localhost/admin".to_string())]);
@@ -670,4 +1884,156 @@ mod tests {
         assert!(response.is_some());
         assert_eq!(request.get_metadata(&format!("test{}", METADATA_CALLED_SUFFIX)), Some(METADATA_TRUE_VALUE));
     }
-}
\ No newline at end of file
+
+    /// Runs a request through a directory of plugins named "first" to
+    /// "third", of which `responder` answers, and returns which of them saw
+    /// the response
+    async fn response_phase_calls(response_phase: &str, responder: Option<&str>, reach_directory: bool) -> Vec<String> {
+        let config = HashMap::from([
+            ("directory".to_string(), "/admin".to_string()),
+            ("response_phase".to_string(), response_phase.to_string()),
+        ]);
+        let nested_plugins: Vec<Arc<dyn Plugin>> = ["first", "second", "third"].iter()
+            .map(|name| Arc::new(MockPlugin::new(name, responder == Some(*name), "response")) as Arc<dyn Plugin>)
+            .collect();
+        let directory_plugin = DirectoryPlugin::new_with_nested_plugins(config, nested_plugins);
+
+        let mut request = create_test_request("/admin");
+        let context = create_test_context();
+        if reach_directory {
+            directory_plugin.handle_request(&mut request, &context).await;
+        }
+        let mut response = Response::new(Body::empty());
+        directory_plugin.handle_response(&request, &mut response, &context).await;
+        response.headers().get_all(RESPONSE_PHASE_HEADER).iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_response_phase_scopes() {
+        let everyone = vec!["first", "second", "third"];
+        assert_eq!(response_phase_calls("all", Some("second"), true).await, everyone);
+        assert_eq!(response_phase_calls("all", None, false).await, everyone);
+        
+        assert_eq!(response_phase_calls("responder_only", Some("second"), true).await, vec!["second"]);
+        assert!(response_phase_calls("responder_only", None, true).await.is_empty());
+        assert!(response_phase_calls("responder_only", None, false).await.is_empty());
+        
+        assert_eq!(response_phase_calls("matched_only", Some("second"), true).await, vec!["first", "second"]);
+        assert_eq!(response_phase_calls("matched_only", None, true).await, everyone);
+        assert!(response_phase_calls("matched_only", None, false).await.is_empty());
+        
+        // An unknown scope keeps the default
+        assert_eq!(response_phase_calls("onion", Some("first"), true).await, everyone);
+    }
+
+    // Mock plugin that panics on every request
+    #[derive(Debug)]
+    struct PanickingPlugin;
+
+    #[async_trait]
+    impl Plugin for PanickingPlugin {
+        async fn handle_request(&self, _request: &mut PluginRequest, _context: &PluginContext) -> Option<PluginResponse> {
+            panic!("nested plugin failed");
+        }
+
+        fn name(&self) -> &str {
+            "panicking"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nested_plugins_report_their_panics() {
+        let guarded = |name: &str| -> Arc<dyn Plugin> {
+            Arc::new(PanicGuard::new(MockPlugin::new(name, false, ""), PanicBreaker::default()))
+        };
+        let config = HashMap::from([(CONFIG_KEY_DIRECTORY.to_string(), "/admin".to_string())]);
+        let directory_plugin = DirectoryPlugin::new_with_nested_plugins(config, vec![
+            guarded("first"),
+            Arc::new(PanicGuard::new(PanickingPlugin, PanicBreaker::default())),
+        ]);
+        assert_eq!(directory_plugin.failures().map(|failures| failures.panics), Some(0));
+
+        let response = directory_plugin.handle_request(&mut create_test_request("/admin"), &create_test_context()).await;
+        assert_eq!(response.unwrap().response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let failures = directory_plugin.failures().unwrap();
+        assert_eq!(failures.plugin, "panicking");
+        assert_eq!(failures.panics, 1);
+
+        // Plugins that report nothing leave nothing to report
+        let config = HashMap::from([(CONFIG_KEY_DIRECTORY.to_string(), "/admin".to_string())]);
+        let directory_plugin = DirectoryPlugin::new_with_nested_plugins(config, vec![Arc::new(MockPlugin::new("plain", false, ""))]);
+        assert_eq!(directory_plugin.failures(), None);
+    }
+
+    // Mock plugin declaring capabilities
+    #[derive(Debug)]
+    struct CapablePlugin(&'static [&'static str], &'static [&'static str]);
+
+    #[async_trait]
+    impl Plugin for CapablePlugin {
+        fn capabilities(&self) -> Capabilities {
+            Capabilities::new(self.0, self.1)
+        }
+
+        fn name(&self) -> &str {
+            "capable"
+        }
+    }
+
+    #[test]
+    fn test_directory_declares_nested_capabilities() {
+        let config = HashMap::from([(CONFIG_KEY_DIRECTORY.to_string(), "/admin".to_string())]);
+        let directory_plugin = DirectoryPlugin::new_with_nested_plugins(config, vec![
+            Arc::new(CapablePlugin(&["authentication"], &[])),
+            Arc::new(CapablePlugin(&["authorization"], &["authentication", "sessions"])),
+            Arc::new(CapablePlugin(&[], &["authorization", "sessions"])),
+        ]);
+        assert_eq!(directory_plugin.capabilities(), Capabilities::new(&["authentication", "authorization"], &["sessions"]));
+
+        // Fallback plugins can't rely on the nested plugins
+        let directory_plugin = directory_plugin.with_fallback_plugins(vec![
+            Arc::new(CapablePlugin(&[], &["authentication"])),
+        ]);
+        assert_eq!(directory_plugin.capabilities(), Capabilities::new(&["authentication", "authorization"], &["sessions", "authentication"]));
+    }
+
+    // Mock access-control plugin withholding an element from everyone
+    #[derive(Debug)]
+    struct WithholdingPlugin(&'static str);
+
+    #[async_trait]
+    impl Plugin for WithholdingPlugin {
+        fn withheld_selectors(&self, _user: Option<&str>, _path: &str, _context: &PluginContext) -> Vec<String> {
+            vec![self.0.to_string()]
+        }
+
+        fn name(&self) -> &str {
+            "withholding"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_nested_plugins_say_what_is_withheld_in_the_directory() {
+        let config = HashMap::from([(CONFIG_KEY_DIRECTORY.to_string(), "/staff".to_string())]);
+        let directory_plugin = DirectoryPlugin::new_with_nested_plugins(config, vec![
+            Arc::new(WithholdingPlugin(".salary")),
+            Arc::new(WithholdingPlugin(".salary")),
+            Arc::new(WithholdingPlugin(".address")),
+        ]);
+        let context = create_test_context();
+
+        assert_eq!(directory_plugin.withheld_selectors(Some("alice"), "/staff/bob.html", &context), [".salary", ".address"]);
+        assert!(directory_plugin.withheld_selectors(Some("alice"), "/about.html", &context).is_empty());
+
+        // A nested plugin that failed to load can't be asked, so everything is withheld
+        let config = HashMap::from([
+            (CONFIG_KEY_DIRECTORY.to_string(), "/staff".to_string()),
+            (CONFIG_KEY_NESTED_PLUGINS.to_string(), r#"[{"library": "file:///nonexistent/libauthorization.so"}]"#.to_string()),
+        ]);
+        let directory_plugin = DirectoryPlugin::new(config);
+        assert_eq!(directory_plugin.withheld_selectors(Some("alice"), "/staff/bob.html", &context), [WITHHOLD_ALL]);
+        assert!(directory_plugin.withheld_selectors(Some("alice"), "/about.html", &context).is_empty());
+    }
+}
@@ -0,0 +1,203 @@
+//! Events Plugin for Rusty Beam
+//!
+//! This plugin streams the changes the selector-handler makes to a document
+//! as Server-Sent Events, for clients that cannot use WebSockets, such as
+//! those behind proxies that refuse upgrades. It is the SSE counterpart of
+//! the websocket plugin and sends the same `StreamItem` microdata.
+//!
+//! ## Features
+//! - **URL Subscriptions**: A client subscribes to the document it requests
+//!   with `Accept: text/event-stream`
+//! - **Change Events**: Every successful PUT, POST, PATCH or DELETE with a
+//!   selector is sent to the subscribers of the document
+//! - **Keep-Alives**: A comment is written to silent streams so proxies keep
+//!   them open
+//! - **Disconnect Detection**: Subscribers are dropped once their client has
+//!   gone away
+//!
+//! ## Configuration
+//! - `keep_alive`: Seconds a stream may stay silent before a keep-alive
+//!   comment is written (default: 15, 0 disables)
+//! - `retry`: Milliseconds clients wait before reconnecting, sent when a
+//!   stream opens (default: the client's own delay)
+//!
+//! ## Events
+//! Each change is an event named `change` whose data is a `StreamItem` with
+//! `method`, `url`, `selector` and `content` properties, and
+//! `previousContent` when the selector-handler reports the replaced element.
+//! Event ids count up from 1 for each plugin instance. Events are not kept,
+//! so a reconnecting client reloads the document rather than replaying them.
+//!
+//! ```javascript
+//! const events = new EventSource('/page.html');
+//! events.addEventListener('change', (event) => console.log(event.data));
+//! ```
+//!
+//! ## Integration
+//! Place the plugin before the file-handler so event stream requests are
+//! answered, and after authorization so only readers of a document can
+//! subscribe to it. Change events are taken from the metadata of the
+//! selector-handler in the response phase.
+
+use async_trait::async_trait;
+use hyper::header::ACCEPT;
+use hyper::{Body, Method, Response};
+use rusty_beam_plugin_api::sse::{self, Event, EventSender, SendError, CONTENT_TYPE_EVENT_STREAM};
+use rusty_beam_plugin_api::{create_plugin, log_debug, Plugin, PluginContext, PluginRequest, PluginResponse};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+// Plugin configuration
+const DEFAULT_PLUGIN_NAME: &str = "events";
+const DEFAULT_KEEP_ALIVE_SECONDS: u64 = 15;
+const INDEX_FILE_NAME: &str = "index.html";
+
+// Events
+const CHANGE_EVENT: &str = "change";
+const STREAM_ITEM_SCHEMA: &str = "http://rustybeam.net/StreamItem";
+
+// HTTP methods whose selector changes are sent
+const CHANGE_METHODS: &[Method] = &[Method::PUT, Method::POST, Method::PATCH, Method::DELETE];
+
+/// A client's stream and the document it follows
+#[derive(Debug)]
+struct Subscriber {
+    url: String,
+    sender: EventSender,
+}
+
+/// Plugin streaming selector changes as Server-Sent Events
+#[derive(Debug)]
+pub struct EventsPlugin {
+    name: String,
+    keep_alive: Option<Duration>,
+    retry: Option<Duration>,
+    subscribers: Mutex<Vec<Subscriber>>,
+    next_event_id: AtomicU64,
+}
+
+impl EventsPlugin {
+    pub fn new(config: HashMap<String, String>) -> Self {
+        let name = config.get("name").cloned().unwrap_or_else(|| DEFAULT_PLUGIN_NAME.to_string());
+        let keep_alive = config.get("keep_alive")
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(DEFAULT_KEEP_ALIVE_SECONDS);
+        let retry = config.get("retry")
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_millis);
+
+        Self {
+            name,
+            keep_alive: (keep_alive > 0).then(|| Duration::from_secs(keep_alive)),
+            retry,
+            subscribers: Mutex::new(Vec::new()),
+            next_event_id: AtomicU64::new(1),
+        }
+    }
+
+    /// Normalizes URL paths so `/path/`, `/path` and `/path/index.html`
+    /// follow the same document, as the websocket plugin does
+    fn normalize_url(url: &str) -> String {
+        if url.ends_with('/') {
+            format!("{}{}", url, INDEX_FILE_NAME)
+        } else if !url.contains('.') {
+            format!("{}/{}", url, INDEX_FILE_NAME)
+        } else {
+            url.to_string()
+        }
+    }
+
+    /// Whether the client asks for an event stream
+    fn accepts_event_stream(request: &PluginRequest) -> bool {
+        request.http_request.headers().get_all(ACCEPT).iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .any(|media_type| media_type.split(';').next().unwrap_or_default().trim() == CONTENT_TYPE_EVENT_STREAM)
+    }
+
+    /// Open a stream following the requested document
+    fn subscribe(&self, request: &PluginRequest) -> Response<Body> {
+        let (sender, response) = sse::channel(self.keep_alive);
+        if let Some(retry) = self.retry {
+            let _ = sender.send(&Event::retry_only(retry));
+        }
+
+        let url = Self::normalize_url(&request.path);
+        log_debug!(self.name, "Stream opened"; url = url);
+        self.subscribers.lock().unwrap_or_else(|e| e.into_inner()).push(Subscriber { url, sender });
+        response
+    }
+
+    /// Send a change to every stream following its document
+    fn broadcast(&self, url: &str, event: &Event) {
+        let url = Self::normalize_url(url);
+        let mut subscribers = self.subscribers.lock().unwrap_or_else(|e| e.into_inner());
+        subscribers.retain(|subscriber| {
+            if subscriber.url != url {
+                return !subscriber.sender.is_closed();
+            }
+            match subscriber.sender.send(event) {
+                Ok(()) => true,
+                Err(SendError::Full) => {
+                    log_debug!(self.name, "Dropped an event for a client that is not reading"; url = url);
+                    true
+                }
+                Err(SendError::Disconnected) => {
+                    log_debug!(self.name, "Stream closed"; url = url);
+                    false
+                }
+            }
+        });
+    }
+
+    /// The change the selector-handler made for a request, as an event
+    fn change_event(&self, request: &PluginRequest, selector: &str) -> Option<Event> {
+        // The new content for PUT, POST and PATCH, the removed one for DELETE
+        let content = request.get_metadata("posted_content")
+            .or_else(|| request.get_metadata("selected_content"))?;
+        let previous_content = request.get_metadata("previous_content")
+            .map(|previous| format!("\n    <div itemprop=\"previousContent\">{}</div>", previous))
+            .unwrap_or_default();
+
+        let stream_item = format!(
+            r#"<div itemscope itemtype="{}">
+    <span itemprop="method">{}</span>
+    <span itemprop="url">{}</span>
+    <span itemprop="selector">{}</span>
+    <div itemprop="content">{}</div>{}
+</div>"#,
+            STREAM_ITEM_SCHEMA, request.http_request.method(), request.path, selector, content, previous_content
+        );
+        let id = self.next_event_id.fetch_add(1, Ordering::Relaxed);
+        Some(Event::new(stream_item).event(CHANGE_EVENT).id(id.to_string()))
+    }
+}
+
+#[async_trait]
+impl Plugin for EventsPlugin {
+    async fn handle_request(&self, request: &mut PluginRequest, _context: &PluginContext) -> Option<PluginResponse> {
+        if request.http_request.method() != Method::GET || !Self::accepts_event_stream(request) {
+            return None;
+        }
+        Some(self.subscribe(request).into())
+    }
+
+    async fn handle_response(&self, request: &PluginRequest, response: &mut Response<Body>, _context: &PluginContext) {
+        let Some(selector) = request.get_metadata("applied_selector") else { return };
+        if !response.status().is_success() || !CHANGE_METHODS.contains(request.http_request.method()) {
+            return;
+        }
+        if let Some(event) = self.change_event(request, selector) {
+            self.broadcast(&request.path, &event);
+        }
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// Export the plugin creation function
+create_plugin!(EventsPlugin);
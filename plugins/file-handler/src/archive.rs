@@ -0,0 +1,336 @@
+//! Zip archives of directories
+//!
+//! `GET /dir/?format=zip` answers with a zip archive of the files below a
+//! directory. The files are chosen before the response starts: hidden files
+//! and directories, files matching the `zipExclude` patterns and files the
+//! requesting user may not GET are left out, and symbolic links are never
+//! followed. The archive itself is written on a thread of its own while the
+//! client reads it, one deflated file at a time, and the thread stops as soon
+//! as the client goes away.
+//!
+//! The archive is a plain zip without zip64 extensions, so once it would
+//! exceed 65535 entries or 4 GiB the remaining files are left out.
+
+use chrono::{DateTime, Datelike, SecondsFormat, Timelike, Utc};
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use hyper::body::{Bytes, Sender};
+use rusty_beam_plugin_api::condition::glob_match;
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+// Configuration keys
+const CONFIG_KEY_ZIP_DOWNLOADS: &str = "zipDownloads";
+const CONFIG_KEY_ZIP_EXCLUDE: &str = "zipExclude";
+
+/// Files left out of archives unless `zipExclude` says otherwise: credential
+/// files and common secret and configuration formats
+const DEFAULT_ZIP_EXCLUDE: &str = "users.html, *.env, *.conf, *.key, *.pem";
+
+/// Name of the manifest entry; hidden files are never archived, so it
+/// cannot clash with a file of the directory
+pub const MANIFEST_NAME: &str = ".manifest.json";
+
+/// Bytes written to the client at a time
+const CHUNK_SIZE: usize = 64 * 1024;
+
+// Zip record signatures and fields
+const LOCAL_FILE_HEADER: u32 = 0x0403_4b50;
+const CENTRAL_DIRECTORY_HEADER: u32 = 0x0201_4b50;
+const END_OF_CENTRAL_DIRECTORY: u32 = 0x0605_4b50;
+const VERSION_NEEDED: u16 = 20;
+/// Made on Unix by a writer of version 2.0, so the file modes are kept
+const VERSION_MADE_BY: u16 = (3 << 8) | VERSION_NEEDED;
+const FLAG_UTF8_NAMES: u16 = 1 << 11;
+const METHOD_DEFLATE: u16 = 8;
+const UNIX_FILE_MODE: u32 = 0o100644;
+const MAX_ENTRIES: usize = u16::MAX as usize;
+
+/// Whether and what to archive
+#[derive(Debug)]
+pub struct ArchiveSettings {
+    pub enabled: bool,
+    /// Patterns matched against the names and relative paths of files to leave out
+    exclude: Vec<String>,
+}
+
+impl ArchiveSettings {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        let enabled = config.get(CONFIG_KEY_ZIP_DOWNLOADS)
+            .map(|v| !matches!(v.trim().to_lowercase().as_str(), "false" | "no" | "0"))
+            .unwrap_or(true);
+        let exclude = config.get(CONFIG_KEY_ZIP_EXCLUDE)
+            .map(String::as_str)
+            .unwrap_or(DEFAULT_ZIP_EXCLUDE);
+        Self { enabled, exclude: parse_patterns(exclude) }
+    }
+
+    /// Whether a file is left out by the exclusion patterns
+    pub fn excludes(&self, relative: &str) -> bool {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        self.exclude.iter().any(|pattern| glob_match(pattern, name) || glob_match(pattern, relative))
+    }
+}
+
+/// Splits a comma-separated list of glob patterns
+pub fn parse_patterns(patterns: &str) -> Vec<String> {
+    patterns.split(',')
+        .map(|pattern| pattern.trim().to_string())
+        .filter(|pattern| !pattern.is_empty())
+        .collect()
+}
+
+/// A file to archive
+#[derive(Debug)]
+pub struct ArchiveEntry {
+    /// Path inside the archive, relative to the archived directory
+    pub name: String,
+    pub path: PathBuf,
+}
+
+/// Lists the files below a directory in name order, without following
+/// symbolic links or entering hidden directories
+///
+/// `keep` decides each file by its path relative to the directory.
+pub fn collect_entries(directory: &Path, keep: &mut dyn FnMut(&str) -> bool) -> Vec<ArchiveEntry> {
+    let mut entries = Vec::new();
+    collect_into(directory, "", keep, &mut entries);
+    entries
+}
+
+fn collect_into(directory: &Path, prefix: &str, keep: &mut dyn FnMut(&str) -> bool, entries: &mut Vec<ArchiveEntry>) {
+    let Ok(read_dir) = fs::read_dir(directory) else { return };
+    let mut children: Vec<(String, PathBuf, fs::FileType)> = read_dir
+        .filter_map(|child| child.ok())
+        .filter_map(|child| {
+            let name = child.file_name().into_string().ok()?;
+            let file_type = child.file_type().ok()?;
+            Some((name, child.path(), file_type))
+        })
+        .filter(|(name, _, _)| !name.starts_with('.'))
+        .collect();
+    children.sort_by(|a, b| a.0.cmp(&b.0));
+
+    for (name, path, file_type) in children {
+        let relative = format!("{}{}", prefix, name);
+        if file_type.is_dir() {
+            collect_into(&path, &format!("{}/", relative), keep, entries);
+        } else if file_type.is_file() && keep(&relative) {
+            entries.push(ArchiveEntry { name: relative, path });
+        }
+    }
+}
+
+/// What the manifest records about an archived file
+#[derive(Debug)]
+struct ArchivedFile {
+    name: String,
+    size: u64,
+    modified: DateTime<Utc>,
+    crc32: u32,
+}
+
+/// Writes the archive of `entries` into a response body, ending early if
+/// the client goes away
+///
+/// Returns how many files were archived and why the archive ended early, if
+/// it did.
+pub fn write_archive(
+    entries: Vec<ArchiveEntry>,
+    body: Sender,
+    manifest_for: Option<String>,
+) -> (usize, Option<io::Error>) {
+    let mut zip = ZipWriter::new(BodyWriter::new(body));
+    let mut archived = Vec::new();
+    let mut problem = None;
+
+    for entry in entries {
+        let (data, modified) = match fs::read(&entry.path).and_then(|data| {
+            let modified = fs::metadata(&entry.path)?.modified().unwrap_or(SystemTime::UNIX_EPOCH);
+            Ok((data, modified))
+        }) {
+            Ok(file) => file,
+            // Removed or unreadable since it was listed
+            Err(_) => continue,
+        };
+        let modified = DateTime::<Utc>::from(modified);
+        match zip.add(&entry.name, modified, &data) {
+            Ok(Some(crc32)) => archived.push(ArchivedFile { name: entry.name, size: data.len() as u64, modified, crc32 }),
+            Ok(None) => {
+                problem = Some(io::Error::other("archive size limit reached"));
+                break;
+            }
+            Err(e) => return (archived.len(), Some(e)),
+        }
+    }
+
+    if let Some(directory) = manifest_for {
+        let manifest = serde_json::json!({
+            "directory": directory,
+            "created": Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            "files": archived.iter().map(|file| serde_json::json!({
+                "path": file.name,
+                "size": file.size,
+                "modified": file.modified.to_rfc3339_opts(SecondsFormat::Secs, true),
+                "crc32": format!("{:08x}", file.crc32),
+            })).collect::<Vec<_>>(),
+        });
+        let manifest = serde_json::to_vec_pretty(&manifest).unwrap_or_default();
+        if let Err(e) = zip.add(MANIFEST_NAME, Utc::now(), &manifest) {
+            return (archived.len(), Some(e));
+        }
+    }
+
+    match zip.finish() {
+        Ok(()) => (archived.len(), problem),
+        Err(e) => (archived.len(), Some(e)),
+    }
+}
+
+/// Writes zip entries one after another, keeping the central directory
+/// until the end
+struct ZipWriter<W: Write> {
+    out: W,
+    /// Bytes written so far, which is where the next entry starts
+    offset: u64,
+    central_directory: Vec<u8>,
+    entries: usize,
+}
+
+impl<W: Write> ZipWriter<W> {
+    fn new(out: W) -> Self {
+        Self { out, offset: 0, central_directory: Vec::new(), entries: 0 }
+    }
+
+    /// Adds a deflated file, returning its CRC-32, or None if it would take
+    /// the archive beyond what a zip without zip64 extensions can hold
+    fn add(&mut self, name: &str, modified: DateTime<Utc>, data: &[u8]) -> io::Result<Option<u32>> {
+        let crc32 = crc32fast::hash(data);
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(data)?;
+        let compressed = encoder.finish()?;
+
+        let name = name.as_bytes();
+        let header_length = 30 + name.len() as u64;
+        let central_length = 46 + name.len() as u64;
+        let end = self.offset + header_length + compressed.len() as u64;
+        let central_end = end + self.central_directory.len() as u64 + central_length + 22;
+        if self.entries >= MAX_ENTRIES || name.len() > u16::MAX as usize || central_end > u32::MAX as u64 {
+            return Ok(None);
+        }
+        let (time, date) = dos_date_time(modified);
+
+        let mut header = Vec::with_capacity(header_length as usize);
+        put_u32(&mut header, LOCAL_FILE_HEADER);
+        put_u16(&mut header, VERSION_NEEDED);
+        put_u16(&mut header, FLAG_UTF8_NAMES);
+        put_u16(&mut header, METHOD_DEFLATE);
+        put_u16(&mut header, time);
+        put_u16(&mut header, date);
+        put_u32(&mut header, crc32);
+        put_u32(&mut header, compressed.len() as u32);
+        put_u32(&mut header, data.len() as u32);
+        put_u16(&mut header, name.len() as u16);
+        put_u16(&mut header, 0);
+        header.extend_from_slice(name);
+        self.out.write_all(&header)?;
+        self.out.write_all(&compressed)?;
+
+        let central = &mut self.central_directory;
+        put_u32(central, CENTRAL_DIRECTORY_HEADER);
+        put_u16(central, VERSION_MADE_BY);
+        put_u16(central, VERSION_NEEDED);
+        put_u16(central, FLAG_UTF8_NAMES);
+        put_u16(central, METHOD_DEFLATE);
+        put_u16(central, time);
+        put_u16(central, date);
+        put_u32(central, crc32);
+        put_u32(central, compressed.len() as u32);
+        put_u32(central, data.len() as u32);
+        put_u16(central, name.len() as u16);
+        put_u16(central, 0); // extra field length
+        put_u16(central, 0); // comment length
+        put_u16(central, 0); // disk number
+        put_u16(central, 0); // internal attributes
+        put_u32(central, UNIX_FILE_MODE << 16);
+        put_u32(central, self.offset as u32);
+        central.extend_from_slice(name);
+
+        self.offset = end;
+        self.entries += 1;
+        Ok(Some(crc32))
+    }
+
+    /// Writes the central directory and its end record
+    fn finish(mut self) -> io::Result<()> {
+        let mut end = Vec::with_capacity(22);
+        put_u32(&mut end, END_OF_CENTRAL_DIRECTORY);
+        put_u16(&mut end, 0); // this disk
+        put_u16(&mut end, 0); // disk with the central directory
+        put_u16(&mut end, self.entries as u16);
+        put_u16(&mut end, self.entries as u16);
+        put_u32(&mut end, self.central_directory.len() as u32);
+        put_u32(&mut end, self.offset as u32);
+        put_u16(&mut end, 0); // comment length
+        self.out.write_all(&self.central_directory)?;
+        self.out.write_all(&end)?;
+        self.out.flush()
+    }
+}
+
+fn put_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+fn put_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// MS-DOS time and date fields, which start in 1980 and count seconds in twos
+fn dos_date_time(time: DateTime<Utc>) -> (u16, u16) {
+    if time.year() < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let dos_time = (time.hour() << 11) | (time.minute() << 5) | (time.second() / 2);
+    let dos_date = ((time.year().min(2107) as u32 - 1980) << 9) | (time.month() << 5) | time.day();
+    (dos_time as u16, dos_date as u16)
+}
+
+/// Buffers writes into chunks sent down a response body
+struct BodyWriter {
+    body: Sender,
+    buffer: Vec<u8>,
+}
+
+impl BodyWriter {
+    fn new(body: Sender) -> Self {
+        Self { body, buffer: Vec::with_capacity(CHUNK_SIZE) }
+    }
+
+    fn send_buffer(&mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+        let chunk = Bytes::from(std::mem::replace(&mut self.buffer, Vec::with_capacity(CHUNK_SIZE)));
+        // Waits until the client has taken the previous chunk
+        futures::executor::block_on(self.body.send_data(chunk))
+            .map_err(|_| io::Error::new(io::ErrorKind::BrokenPipe, "client disconnected"))
+    }
+}
+
+impl Write for BodyWriter {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(data);
+        if self.buffer.len() >= CHUNK_SIZE {
+            self.send_buffer()?;
+        }
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.send_buffer()
+    }
+}
@@ -0,0 +1,128 @@
+//! Paths the file handler refuses
+//!
+//! Some files below a host root must never be served: scripts meant for
+//! another server such as `.php`, secrets such as `.env`, repositories such as
+//! `.git/`, and other hidden files. Three lists name them:
+//! - `denyExtensions`: comma-separated file extensions, with or without their
+//!   dot and in any case (`php, .env`)
+//! - `denyPaths`: comma-separated patterns where `*` stands for any run of
+//!   characters. A pattern starting with `/` is matched against the whole
+//!   request path; any other pattern against the end of the path from any
+//!   segment on, so `secrets.json` denies that file in every directory. A
+//!   pattern ending in `/` denies a directory and everything below it, so
+//!   `.git/` denies every repository.
+//! - `hiddenFiles`: `serve` (the default) or `deny`, which denies every path
+//!   with a segment starting with `.`, except `.well-known` (RFC 8615)
+//!
+//! A request for a denied path is answered with `404 Not Found` whatever its
+//! method, before anything is read or written, so a client cannot tell a
+//! denied file from a missing one.
+//!
+//! The lists are host settings, which the file handler's own configuration
+//! replaces list by list. A directory plugin passes its configuration on to
+//! its nested plugins, so a directory block can set them for the paths below
+//! it.
+
+use rusty_beam_plugin_api::condition::glob_match;
+use rusty_beam_plugin_api::{log_error, PluginContext};
+use std::collections::HashMap;
+
+pub const CONFIG_KEY_DENY_EXTENSIONS: &str = "denyExtensions";
+pub const CONFIG_KEY_DENY_PATHS: &str = "denyPaths";
+pub const CONFIG_KEY_HIDDEN_FILES: &str = "hiddenFiles";
+
+/// Directory of well-known URIs, served even when hidden files are denied
+const WELL_KNOWN: &str = ".well-known";
+
+/// Whether hidden files and directories are served
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HiddenFiles {
+    #[default]
+    Serve,
+    Deny,
+}
+
+impl std::str::FromStr for HiddenFiles {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "serve" => Ok(HiddenFiles::Serve),
+            "deny" => Ok(HiddenFiles::Deny),
+            other => Err(format!("Invalid {} '{}', expected serve or deny", CONFIG_KEY_HIDDEN_FILES, other)),
+        }
+    }
+}
+
+/// The deny lists a configuration sets; `None` for those it leaves unset
+#[derive(Debug, Default)]
+pub struct DenyList {
+    /// Lowercase extensions without their dot
+    extensions: Option<Vec<String>>,
+    patterns: Option<Vec<String>>,
+    hidden_files: Option<HiddenFiles>,
+}
+
+impl DenyList {
+    /// Read the lists a plugin's configuration sets
+    pub fn from_config(plugin_name: &str, config: &HashMap<String, String>) -> Self {
+        if let Some(Err(e)) = config.get(CONFIG_KEY_HIDDEN_FILES).map(|v| v.parse::<HiddenFiles>()) {
+            log_error!(plugin_name, "{}, using the host's setting", e);
+        }
+        Self::parse(config)
+    }
+
+    /// Read the lists of a configuration, leaving out an invalid `hiddenFiles`
+    fn parse(config: &HashMap<String, String>) -> Self {
+        let extensions = config.get(CONFIG_KEY_DENY_EXTENSIONS).map(|extensions| extensions.split(',')
+            .map(|extension| extension.trim().trim_start_matches('.').to_lowercase())
+            .filter(|extension| !extension.is_empty())
+            .collect());
+        let patterns = config.get(CONFIG_KEY_DENY_PATHS).map(|patterns| patterns.split(',')
+            .map(|pattern| pattern.trim().to_string())
+            .filter(|pattern| !pattern.is_empty())
+            .collect());
+        let hidden_files = config.get(CONFIG_KEY_HIDDEN_FILES).and_then(|v| v.parse().ok());
+        Self { extensions, patterns, hidden_files }
+    }
+
+    /// Whether a request path is denied by these lists, or by the host's
+    /// where these are unset
+    pub fn denies(&self, context: &PluginContext, request_path: &str) -> bool {
+        let host = Self::parse(&context.host_config);
+        let extensions = self.extensions.as_ref().or(host.extensions.as_ref());
+        let patterns = self.patterns.as_ref().or(host.patterns.as_ref());
+        let hidden_files = self.hidden_files.or(host.hidden_files).unwrap_or_default();
+
+        let segments: Vec<&str> = request_path.split('/').filter(|segment| !segment.is_empty()).collect();
+        if hidden_files == HiddenFiles::Deny
+            && segments.iter().any(|segment| segment.starts_with('.') && *segment != WELL_KNOWN)
+        {
+            return true;
+        }
+        // A path ending in `/` names the directory's index file, whose extension is allowed
+        let file_name = segments.last().filter(|_| !request_path.ends_with('/'));
+        if let (Some(extensions), Some((_, extension))) = (extensions, file_name.and_then(|name| name.rsplit_once('.'))) {
+            if extensions.iter().any(|denied| denied.eq_ignore_ascii_case(extension)) {
+                return true;
+            }
+        }
+        patterns.is_some_and(|patterns| patterns.iter().any(|pattern| pattern_matches(pattern, request_path)))
+    }
+}
+
+/// Whether a `denyPaths` pattern matches a request path
+fn pattern_matches(pattern: &str, request_path: &str) -> bool {
+    let (pattern, directory) = match pattern.strip_suffix('/') {
+        Some(directory) => (directory, true),
+        None => (pattern, false),
+    };
+    let matches = |text: &str| {
+        glob_match(pattern, text)
+            || (directory && glob_match(&format!("{}/*", pattern), text))
+    };
+    if pattern.starts_with('/') {
+        return matches(request_path);
+    }
+    request_path.match_indices('/').any(|(index, _)| matches(&request_path[index + 1..]))
+}
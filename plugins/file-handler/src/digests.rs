@@ -0,0 +1,252 @@
+//! SHA-256 content digests and addressing files by them
+//!
+//! With `contentDigests` enabled, GET and HEAD responses for files carry the
+//! SHA-256 digest of the file as `Repr-Digest` (RFC 9530) and as the older
+//! `Digest` header (RFC 3230). A PUT carrying `Repr-Digest`, `Content-Digest`
+//! or `Digest` with a SHA-256 value is only written if the body matches it;
+//! digests in other algorithms are ignored.
+//!
+//! Files can also be fetched by digest below `blobsPath`, as
+//! `GET /.well-known/blobs/<hex sha-256>`, so pages can reference assets by
+//! content whatever they are named. A lookup searches the host root the way
+//! directory archives do: hidden files and directories are skipped and
+//! symbolic links are never followed. Digests are remembered per file along
+//! with its size and modification time, so each file is only hashed again
+//! after it changes, and a lookup first tries the files last seen with the
+//! digest. The host root is only searched when none of them has it any more,
+//! and at most once every ten seconds, so a file placed in the root other
+//! than through the server may take that long to be found.
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use ring::digest::{Context, SHA256, SHA256_OUTPUT_LEN};
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+// Configuration keys
+const CONFIG_KEY_CONTENT_DIGESTS: &str = "contentDigests";
+const CONFIG_KEY_BLOBS_PATH: &str = "blobsPath";
+
+// Defaults
+const DEFAULT_BLOBS_PATH: &str = "/.well-known/blobs/";
+
+/// Files whose digests are remembered before the cache starts over
+const MAX_CACHED_DIGESTS: usize = 10_000;
+
+/// Least time between two searches of a root for digests no known file has
+const SEARCH_INTERVAL: Duration = Duration::from_secs(10);
+
+// Header names and algorithm labels
+pub const HEADER_REPR_DIGEST: &str = "Repr-Digest";
+pub const HEADER_CONTENT_DIGEST: &str = "Content-Digest";
+pub const HEADER_DIGEST: &str = "Digest";
+const ALGORITHM_SHA256: &str = "sha-256";
+
+/// A SHA-256 digest
+pub type Sha256 = [u8; SHA256_OUTPUT_LEN];
+
+/// A file's digest, valid while its size and modification time are unchanged
+#[derive(Debug)]
+struct CachedDigest {
+    len: u64,
+    modified: Option<SystemTime>,
+    digest: Sha256,
+}
+
+/// Whether digests are sent and verified, and where files are served by digest
+#[derive(Debug)]
+pub struct DigestSettings {
+    pub enabled: bool,
+    /// URL path prefix of files addressed by digest, ending in `/`
+    blobs_path: Option<String>,
+    cache: Mutex<HashMap<PathBuf, CachedDigest>>,
+    /// When each root was last searched for a blob
+    searched: Mutex<HashMap<PathBuf, Instant>>,
+}
+
+impl DigestSettings {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        let enabled = config.get(CONFIG_KEY_CONTENT_DIGESTS)
+            .map(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "yes" | "1"))
+            .unwrap_or(false);
+        let blobs_path = config.get(CONFIG_KEY_BLOBS_PATH)
+            .map(|path| path.trim().to_string())
+            .unwrap_or_else(|| DEFAULT_BLOBS_PATH.to_string());
+        let blobs_path = (enabled && !blobs_path.is_empty()).then(|| {
+            let path = if blobs_path.starts_with('/') { blobs_path } else { format!("/{}", blobs_path) };
+            if path.ends_with('/') { path } else { format!("{}/", path) }
+        });
+        Self {
+            enabled,
+            blobs_path,
+            cache: Mutex::new(HashMap::new()),
+            searched: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether a request path lies below the blobs path
+    pub fn is_blob_path(&self, request_path: &str) -> bool {
+        self.blobs_path.as_ref().is_some_and(|prefix| request_path.starts_with(prefix.as_str()))
+    }
+
+    /// The digest a blob path names, or None if it is not a hex SHA-256
+    pub fn blob_digest(&self, request_path: &str) -> Option<Sha256> {
+        let hex = request_path.strip_prefix(self.blobs_path.as_deref()?)?;
+        parse_hex(hex)
+    }
+
+    /// The digest of a file, hashing it unless it is unchanged since last time
+    pub fn file_digest(&self, path: &Path, metadata: &fs::Metadata) -> io::Result<Sha256> {
+        let modified = metadata.modified().ok();
+        if let Some(cached) = self.cache.lock().unwrap().get(path) {
+            if cached.len == metadata.len() && cached.modified == modified {
+                return Ok(cached.digest);
+            }
+        }
+        let digest = hash_reader(fs::File::open(path)?)?;
+        self.remember(path, metadata, digest);
+        Ok(digest)
+    }
+
+    /// The files last seen with a digest, which may have changed since
+    pub fn known_paths(&self, digest: &Sha256) -> Vec<PathBuf> {
+        self.cache.lock().unwrap().iter()
+            .filter(|(_, cached)| cached.digest == *digest)
+            .map(|(path, _)| path.clone())
+            .collect()
+    }
+
+    /// Whether a root may be searched for a digest no known file has,
+    /// noting the search if so
+    pub fn begin_search(&self, root: &Path) -> bool {
+        let mut searched = self.searched.lock().unwrap();
+        let now = Instant::now();
+        if searched.get(root).is_some_and(|last| now.duration_since(*last) < SEARCH_INTERVAL) {
+            return false;
+        }
+        searched.insert(root.to_path_buf(), now);
+        true
+    }
+
+    /// Notes the digest of a file whose contents were read or written
+    pub fn remember(&self, path: &Path, metadata: &fs::Metadata, digest: Sha256) {
+        let mut cache = self.cache.lock().unwrap();
+        if cache.len() >= MAX_CACHED_DIGESTS && !cache.contains_key(path) {
+            cache.clear();
+        }
+        cache.insert(path.to_path_buf(), CachedDigest {
+            len: metadata.len(),
+            modified: metadata.modified().ok(),
+            digest,
+        });
+    }
+}
+
+/// Hashes a byte slice
+pub fn hash_bytes(contents: &[u8]) -> Sha256 {
+    finish(ring::digest::digest(&SHA256, contents))
+}
+
+/// Hashes everything a reader yields
+pub fn hash_reader(mut reader: impl Read) -> io::Result<Sha256> {
+    let mut writer = DigestWriter::new();
+    io::copy(&mut reader, &mut writer)?;
+    Ok(writer.finish())
+}
+
+fn finish(digest: ring::digest::Digest) -> Sha256 {
+    let mut output = [0u8; SHA256_OUTPUT_LEN];
+    output.copy_from_slice(digest.as_ref());
+    output
+}
+
+/// A writer hashing what is written to it, for bodies that may be spooled to disk
+pub struct DigestWriter(Context);
+
+impl DigestWriter {
+    pub fn new() -> Self {
+        Self(Context::new(&SHA256))
+    }
+
+    pub fn finish(self) -> Sha256 {
+        finish(self.0.finish())
+    }
+}
+
+impl Default for DigestWriter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Write for DigestWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.update(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The `Repr-Digest` value of a digest, a structured field dictionary
+pub fn repr_digest_value(digest: &Sha256) -> String {
+    format!("{}=:{}:", ALGORITHM_SHA256, STANDARD.encode(digest))
+}
+
+/// The `Digest` value of a digest, in the RFC 3230 syntax
+pub fn legacy_digest_value(digest: &Sha256) -> String {
+    format!("SHA-256={}", STANDARD.encode(digest))
+}
+
+/// Lowercase hex of a digest, as used in blob paths
+pub fn hex(digest: &Sha256) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn parse_hex(hex: &str) -> Option<Sha256> {
+    if hex.len() != SHA256_OUTPUT_LEN * 2 || !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    let mut digest = [0u8; SHA256_OUTPUT_LEN];
+    for (index, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).ok()?;
+    }
+    Some(digest)
+}
+
+/// The SHA-256 digests a request states for its body
+///
+/// `Repr-Digest` and `Content-Digest` hold `sha-256=:<base64>:` members and
+/// `Digest` holds `SHA-256=<base64>`. Returns an error naming the header if a
+/// SHA-256 value cannot be decoded.
+pub fn requested_digests(headers: &hyper::HeaderMap) -> Result<Vec<Sha256>, String> {
+    let mut digests = Vec::new();
+    for name in [HEADER_REPR_DIGEST, HEADER_CONTENT_DIGEST, HEADER_DIGEST] {
+        for value in headers.get_all(name) {
+            let value = value.to_str().map_err(|_| format!("{} is not valid text", name))?;
+            for member in value.split(',') {
+                let Some((algorithm, encoded)) = member.split_once('=') else { continue };
+                if !algorithm.trim().eq_ignore_ascii_case(ALGORITHM_SHA256) {
+                    continue;
+                }
+                // Structured fields wrap byte sequences in colons, RFC 3230 does not
+                let encoded = encoded.trim();
+                let encoded = encoded.strip_prefix(':')
+                    .and_then(|encoded| encoded.strip_suffix(':'))
+                    .unwrap_or(encoded);
+                let digest = STANDARD.decode(encoded)
+                    .ok()
+                    .and_then(|bytes| Sha256::try_from(bytes.as_slice()).ok())
+                    .ok_or_else(|| format!("{} has an invalid sha-256 value", name))?;
+                digests.push(digest);
+            }
+        }
+    }
+    Ok(digests)
+}
@@ -4,26 +4,188 @@
 //!
 //! ## HTTP Methods Supported
 //! - **GET**: Serve files and directories (with index.html fallback)
-//! - **HEAD**: Return file metadata and headers without body content
+//! - **HEAD**: Return the headers GET would, including Content-Length,
+//!   without reading the file
 //! - **PUT**: Create or update files (follows REST semantics)
-//! - **POST**: Append content to existing files
+//! - **POST**: Append content to existing files, or upload files from a
+//!   `multipart/form-data` form into the request directory
+//! - **PATCH**: Overwrite or extend a byte range of an existing file
 //! - **DELETE**: Remove files from the filesystem
-//! - **OPTIONS**: Return allowed methods and capabilities
+//! - **COPY**, **MOVE**: Copy or rename a file to the path in the
+//!   `Destination` header, as in WebDAV
+//! - **OPTIONS**: Return the methods the caller may use and capabilities
 //!
 //! ## Features
-//! - Content-type detection based on file extensions
+//! - Content-type detection from file extensions, with per-host overrides
+//!   and content sniffing for unrecognised files
 //! - Directory traversal protection (canonicalization)
 //! - Automatic index.html serving for directories
 //! - Proper HTTP status codes (201 Created, 200 OK, etc.)
 //! - Host-specific document root support
 //! - RFC 7231 compliant HTTP semantics
+//! - File reads, writes and metadata lookups run on the server's blocking
+//!   thread pool, so slow disks do not stall other requests
+//!
+//! ## Configuration
+//! - `name`: Plugin instance name (default: "file-handler")
+//! - `root_dir`: Document root used when the host has no `hostRoot`
+//! - `uploadMaxFileSize`: Maximum size in bytes of each uploaded file (default: 10485760)
+//! - `uploadMaxTotalSize`: Maximum size in bytes of a whole upload body (default: 52428800)
+//! - `uploadMaxFiles`: Maximum number of files in one upload (default: 20)
+//! - `uploadAllowedTypes`: Comma-separated content types accepted for uploads,
+//!   supporting wildcards like `image/*` (default: all types)
+//! - `versioning`: Keep the previous content of overwritten and deleted files
+//!   (default: false)
+//! - `versionsDirectory`: Directory under the host root holding the history
+//!   (default: ".versions")
+//! - `versionsMaxCount`: Versions kept per file (default: 10)
+//! - `versionsMaxBytes`: Total bytes of history kept per file, 0 for no limit
+//!   (default: 0)
+//! - `zipDownloads`: Answer `?format=zip` on directories (default: true)
+//! - `zipExclude`: Comma-separated patterns of files left out of archives
+//!   (default: "users.html, *.env, *.conf, *.key, *.pem")
+//! - `contentDigests`: Send SHA-256 digests of files and verify digests sent
+//!   with PUT (default: false)
+//! - `blobsPath`: Path below which files are served by digest when
+//!   `contentDigests` is enabled, empty to disable (default: "/.well-known/blobs/")
+//! - `precompressed`: Serve `.br` and `.gz` variants of files to clients that
+//!   accept them (default: true)
+//! - `denyExtensions`, `denyPaths`, `hiddenFiles`: Paths never served,
+//!   replacing the host's lists of the same names (see Denied Paths)
+//!
+//! ## Method Discovery
+//! The `Allow` header of an OPTIONS response lists only the methods the
+//! caller may use on the path. Each method is checked through the host's
+//! access services, so the authorization plugin decides them just as it would
+//! decide the requests themselves; without an access-control plugin every
+//! method is listed.
+//!
+//! ## Content Types
+//! The Content-Type of a served file is chosen from, in order: the host's
+//! `mimeType` overrides (passed to plugins as `mimeType.<extension>` host
+//! config entries), the comprehensive extension table from `mime_guess`, and
+//! finally the file's leading bytes for inert binary formats such as PNG,
+//! WOFF2 and WebAssembly. Text types are labelled UTF-8. Anything still
+//! unknown, including text, is served as `application/octet-stream`, so no
+//! file is sniffed into a type browsers run scripts in. Responses carry
+//! `X-Content-Type-Options: nosniff` so browsers trust the declared type.
+//!
+//! ## Form Uploads
+//! POSTing a `multipart/form-data` body to a directory stores every file field
+//! in that directory. Filenames are reduced to their final component and
+//! restricted to safe characters, and existing files are never overwritten: a
+//! numeric suffix is added instead. The response is `201 Created` with a
+//! listing of the created resources, as JSON when the client accepts
+//! `application/json` and as HTML otherwise.
+//!
+//! ## Byte-Range Updates
+//! PATCH writes the request body at the offset given by a
+//! `Content-Range: bytes <start>-<end>/<length|*>` header, without re-uploading
+//! the rest of the file. The body must be exactly `end - start + 1` bytes and
+//! `start` may not be beyond the end of the file, so starting at the current
+//! length appends. A numeric complete length must equal the file's size after
+//! the write; otherwise `416 Range Not Satisfiable` is returned. Unlike POST, this append only succeeds if nothing else
+//! grew the file first.
+//!
+//! PATCH also requires an `If-Match` header carrying the file's current ETag
+//! (as returned by GET and HEAD) or `*`. A missing header is rejected with
+//! `428 Precondition Required` and a stale ETag with `412 Precondition Failed`.
+//! A successful update returns `204 No Content` with the new ETag.
+//!
+//! ## Copying and Moving
+//! COPY and MOVE take the target from a `Destination` header holding an
+//! absolute path or a URL on the same host, which is resolved below the host
+//! root just like the request path. An existing target is replaced unless the
+//! request carries `Overwrite: F`, which fails with `412 Precondition Failed`
+//! instead. The response is `201 Created` with a `Location` header for a new
+//! file and `204 No Content` when one was replaced. MOVE renames the file, so
+//! readers never see a half-written target. Only files can be copied or
+//! moved.
+//!
+//! Access-control plugins decide the request itself on the source path. The
+//! target is also checked through the host's access services as a PUT, and
+//! for MOVE the source as a DELETE, so a user cannot write where a PUT would
+//! be refused.
+//!
+//! ## Versioning
+//! With `versioning` enabled, PUT, PATCH, DELETE and MOVE, and COPY and MOVE
+//! onto an existing file, first copy the file's current content into its
+//! history (see the `versions` module). The history
+//! is reached through query parameters on the file's own URL, which keep
+//! working after the file is deleted:
+//! - `GET /page.html?version=list`: JSON listing of the stored versions, newest first
+//! - `GET /page.html?version=3`: content of version 3
+//! - `POST /page.html?restore=3`: replace the file with version 3, saving the
+//!   current content as a new version first
+//!
+//! When a file's history exceeds `versionsMaxCount` versions or
+//! `versionsMaxBytes` bytes, the oldest versions are removed; the newest is
+//! always kept. The history directory itself is never served.
+//!
+//! ## Directory Archives
+//! `GET /dir/?format=zip` streams a zip archive of the files below a
+//! directory (see the `archive` module). Each file is checked through the
+//! host's access services as a GET by the requesting user, so the archive
+//! only holds files the user could download one by one. HTML files with
+//! elements withheld from the user, such as those the authorization plugin
+//! redacts, are left out too, as the archive cannot redact them. Hidden
+//! files and files matching `zipExclude` are left out. Query parameters refine it:
+//! - `include=*.html,docs/*`: only archive files matching one of the patterns
+//! - `manifest=true`: add a `.manifest.json` entry listing the archived files
+//!   with their sizes, modification times and CRC-32 checksums
+//!
+//! ## Content Digests
+//! With `contentDigests` enabled, GET and HEAD responses for files carry the
+//! file's SHA-256 digest as `Repr-Digest: sha-256=:<base64>:` and as the older
+//! `Digest: SHA-256=<base64>` (see the `digests` module). A PUT stating a
+//! SHA-256 digest in `Repr-Digest`, `Content-Digest` or `Digest` is rejected
+//! with `422 Unprocessable Entity` if the body does not match, and the file
+//! is left untouched; a successful PUT returns the stored file's digest.
+//!
+//! Files can also be fetched by digest: `GET /.well-known/blobs/<hex sha-256>`
+//! serves a file below the host root with that content, if the requesting
+//! user may GET the file at its own path without any of its elements being
+//! withheld. The response names that path in
+//! `Content-Location` and may be cached indefinitely, since the content of
+//! the address never changes. Other methods are not allowed below the blobs
+//! path.
+//!
+//! ## Precompressed Variants
+//! When `styles.css.br` or `styles.css.gz` sits next to `styles.css`, GET and
+//! HEAD of `styles.css` send the variant the client's `Accept-Encoding`
+//! accepts, Brotli first, with `Content-Encoding` set and the file's own
+//! `Content-Type`, and fall back to the file otherwise. Variants older than
+//! the file are ignored. Responses for such files carry
+//! `Vary: Accept-Encoding`, and each variant has an ETag of its own (see the
+//! `precompressed` module).
+//!
+//! ## Denied Paths
+//! Files with a denied extension, paths matching a denied pattern and, with
+//! `hiddenFiles` set to `deny`, hidden files are answered with
+//! `404 Not Found` for every method, as if they did not exist (see the `deny`
+//! module). They cannot be the `Destination` of a COPY or MOVE either, uploads
+//! with such names are refused, and archives and blobs leave them out.
 
-use rusty_beam_plugin_api::{Plugin, PluginRequest, PluginContext, PluginResponse, create_plugin};
+use rusty_beam_plugin_api::{condition, fs, paths, CachedBody, Plugin, PluginRequest, PluginContext, PluginResponse};
 use async_trait::async_trait;
 use hyper::{Body, Response, StatusCode, Method};
+use hyper::body::HttpBody;
+use hyper::http::response::Builder;
 use std::collections::HashMap;
 use std::path::{Path, PathBuf};
-use std::fs;
+use std::sync::Arc;
+use serde_json::json;
+
+mod archive;
+mod deny;
+mod digests;
+mod precompressed;
+mod versions;
+use archive::ArchiveSettings;
+use deny::DenyList;
+use digests::DigestSettings;
+use precompressed::{PrecompressedSettings, Variant};
+use versions::VersionStore;
 
 // Default values
 const DEFAULT_PLUGIN_NAME: &str = "file-handler";
@@ -32,15 +194,31 @@ const INDEX_FILE_NAME: &str = "index.html";
 
 // Content-Type mappings
 const CONTENT_TYPE_HTML: &str = "text/html; charset=utf-8";
-const CONTENT_TYPE_CSS: &str = "text/css; charset=utf-8";
-const CONTENT_TYPE_JAVASCRIPT: &str = "application/javascript; charset=utf-8";
+const MEDIA_TYPE_HTML: &str = "text/html";
 const CONTENT_TYPE_JSON: &str = "application/json; charset=utf-8";
-const CONTENT_TYPE_TEXT: &str = "text/plain; charset=utf-8";
-const CONTENT_TYPE_PNG: &str = "image/png";
-const CONTENT_TYPE_JPEG: &str = "image/jpeg";
-const CONTENT_TYPE_GIF: &str = "image/gif";
 const CONTENT_TYPE_OCTET_STREAM: &str = "application/octet-stream";
 const CONTENT_TYPE_PLAIN: &str = "text/plain";
+const CHARSET_UTF8_SUFFIX: &str = "; charset=utf-8";
+const HOST_CONFIG_MIME_TYPE_PREFIX: &str = "mimeType.";
+const NOSNIFF: &str = "nosniff";
+
+// Content sniffing, long enough for every signature
+const SNIFF_LENGTH: usize = 16;
+/// Leading byte signatures of common formats, checked in order
+const MAGIC_SIGNATURES: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "image/png"),
+    (b"\xff\xd8\xff", "image/jpeg"),
+    (b"GIF87a", "image/gif"),
+    (b"GIF89a", "image/gif"),
+    (b"\0asm", "application/wasm"),
+    (b"wOF2", "font/woff2"),
+    (b"wOFF", "font/woff"),
+    (b"PK\x03\x04", "application/zip"),
+    (b"\x1f\x8b", "application/gzip"),
+    (b"OggS", "audio/ogg"),
+    (b"ID3", "audio/mpeg"),
+    (b"\x1aE\xdf\xa3", "video/webm"),
+];
 
 // HTTP response messages
 const MSG_FILE_NOT_FOUND: &str = "File not found";
@@ -51,21 +229,124 @@ const MSG_CONTENT_APPENDED: &str = "Content appended successfully";
 const MSG_FAILED_READ_BODY: &str = "Failed to read request body";
 const MSG_FAILED_WRITE_FILE: &str = "Failed to write file";
 const MSG_FAILED_APPEND_FILE: &str = "Failed to append to file";
+const MSG_INVALID_MULTIPART: &str = "Invalid multipart/form-data body";
+const MSG_NO_FILES_UPLOADED: &str = "No files were uploaded";
+const MSG_INVALID_FILENAME: &str = "Invalid filename";
+const MSG_FILE_TOO_LARGE: &str = "Uploaded file exceeds the maximum size";
+const MSG_UPLOAD_TOO_LARGE: &str = "Upload exceeds the maximum total size";
+const MSG_TOO_MANY_FILES: &str = "Upload contains too many files";
+const MSG_UNSUPPORTED_UPLOAD_TYPE: &str = "Uploaded file type is not allowed";
+const MSG_UPLOAD_TARGET_NOT_DIRECTORY: &str = "Upload target is not a directory";
+const MSG_PRECONDITION_REQUIRED: &str = "PATCH requires an If-Match header";
+const MSG_PRECONDITION_FAILED: &str = "File has been modified";
+const MSG_INVALID_CONTENT_RANGE: &str = "PATCH requires a Content-Range header of the form 'bytes <start>-<end>/<length|*>'";
+const MSG_RANGE_LENGTH_MISMATCH: &str = "Request body length does not match Content-Range";
+const MSG_RANGE_NOT_SATISFIABLE: &str = "Content-Range does not fit the current file length";
+const MSG_FAILED_PATCH_FILE: &str = "Failed to update file";
+const MSG_PATCH_TARGET_IS_DIRECTORY: &str = "Cannot PATCH a directory";
+const MSG_FAILED_SAVE_VERSION: &str = "Failed to save previous version";
+const MSG_FAILED_READ_VERSIONS: &str = "Failed to read version history";
+const MSG_INVALID_VERSION: &str = "Version must be 'list' or a version number";
+const MSG_VERSION_NOT_FOUND: &str = "Version not found";
+const MSG_FILE_RESTORED: &str = "File restored from version";
+const MSG_DESTINATION_REQUIRED: &str = "COPY and MOVE require a Destination header";
+const MSG_INVALID_DESTINATION: &str = "Destination must be an absolute path or a URL on this host";
+const MSG_DESTINATION_OTHER_HOST: &str = "Destination is on another host";
+const MSG_SAME_DESTINATION: &str = "Destination is the source file";
+const MSG_DESTINATION_EXISTS: &str = "Destination exists and Overwrite is F";
+const MSG_SOURCE_NOT_FILE: &str = "Only files can be copied or moved";
+const MSG_FAILED_COPY_FILE: &str = "Failed to copy file";
+const MSG_FAILED_MOVE_FILE: &str = "Failed to move file";
+const MSG_ARCHIVE_NOT_DIRECTORY: &str = "Only directories can be downloaded as archives";
+const MSG_UNSUPPORTED_FORMAT: &str = "Unsupported format";
+const MSG_DIGEST_MISMATCH: &str = "Request body does not match its digest";
+const MSG_INVALID_BLOB_ADDRESS: &str = "Blobs are addressed by the hex SHA-256 of their content";
+const MSG_BLOB_READ_ONLY: &str = "Blobs can only be read";
 
 // HTTP methods for OPTIONS response
-const ALLOWED_METHODS: &str = "GET, PUT, DELETE, OPTIONS, POST, HEAD";
+const ALLOWED_METHODS: &[&str] = &["GET", "PUT", "DELETE", "OPTIONS", "POST", "HEAD", "PATCH", "COPY", "MOVE"];
 const ACCEPT_RANGES: &str = "selector";
 
 // Configuration keys
 const CONFIG_KEY_HOST_ROOT: &str = "hostRoot";
 const CONFIG_KEY_NAME: &str = "name";
 const CONFIG_KEY_ROOT_DIR: &str = "root_dir";
+const CONFIG_KEY_UPLOAD_MAX_FILE_SIZE: &str = "uploadMaxFileSize";
+const CONFIG_KEY_UPLOAD_MAX_TOTAL_SIZE: &str = "uploadMaxTotalSize";
+const CONFIG_KEY_UPLOAD_MAX_FILES: &str = "uploadMaxFiles";
+const CONFIG_KEY_UPLOAD_ALLOWED_TYPES: &str = "uploadAllowedTypes";
+
+// Upload defaults
+const DEFAULT_UPLOAD_MAX_FILE_SIZE: u64 = 10 * 1024 * 1024;
+const DEFAULT_UPLOAD_MAX_TOTAL_SIZE: u64 = 50 * 1024 * 1024;
+const DEFAULT_UPLOAD_MAX_FILES: usize = 20;
+const MULTIPART_FORM_DATA: &str = "multipart/form-data";
+const MEDIA_TYPE_JSON: &str = "application/json";
+
+// Byte-range updates
+const BYTES_RANGE_UNIT: &str = "bytes";
+const IF_MATCH_ANY: &str = "*";
+
+// Versioning query parameters
+const QUERY_VERSION: &str = "version";
+const QUERY_RESTORE: &str = "restore";
+const VERSION_LIST: &str = "list";
+const QUERY_FORMAT: &str = "format";
+const QUERY_INCLUDE: &str = "include";
+const QUERY_MANIFEST: &str = "manifest";
+const FORMAT_ZIP: &str = "zip";
+const CONTENT_TYPE_ZIP: &str = "application/zip";
+
+// COPY and MOVE
+const METHOD_COPY: &str = "COPY";
+const METHOD_MOVE: &str = "MOVE";
+const HEADER_DESTINATION: &str = "Destination";
+const HEADER_OVERWRITE: &str = "Overwrite";
+
+// Content digests
+const BLOB_CACHE_CONTROL: &str = "max-age=31536000, immutable";
+const BLOB_METHODS: &str = "GET, HEAD";
 
 /// File Handler Plugin for serving and manipulating files via HTTP
 #[derive(Debug)]
 pub struct FileHandlerPlugin {
     name: String,
     root_dir: String,
+    upload_max_file_size: u64,
+    /// Largest multipart body accepted, in bytes
+    upload_max_total_size: u64,
+    upload_max_files: usize,
+    /// Content types accepted for uploads; empty means any type
+    upload_allowed_types: Vec<String>,
+    /// History of overwritten and deleted files, if versioning is enabled
+    versions: Option<VersionStore>,
+    /// Which directories may be downloaded as zip archives, and with what
+    archives: ArchiveSettings,
+    /// Whether file digests are sent and verified, and the digests seen so far
+    digests: Arc<DigestSettings>,
+    /// Whether precompressed variants of files are served
+    precompressed: PrecompressedSettings,
+    /// Paths never served, in place of the host's lists
+    deny: DenyList,
+    /// Held from checking a PATCH's If-Match to writing the range, so
+    /// concurrent PATCHes each check the content they change
+    patch_lock: tokio::sync::Mutex<()>,
+}
+
+/// A parsed `Content-Range: bytes <start>-<end>/<length>` header
+#[derive(Debug, PartialEq)]
+struct ByteRange {
+    start: u64,
+    end: u64,
+    /// Complete length of the file after the update, if the client stated it
+    complete_length: Option<u64>,
+}
+
+/// A file received in a multipart upload, validated but not yet written
+struct UploadedFile {
+    file_name: String,
+    content_type: String,
+    data: hyper::body::Bytes,
 }
 
 impl FileHandlerPlugin {
@@ -77,74 +358,113 @@ impl FileHandlerPlugin {
             .cloned()
             .unwrap_or_else(|| DEFAULT_ROOT_DIR.to_string());
         
-        Self { name, root_dir }
+        let upload_max_file_size = config.get(CONFIG_KEY_UPLOAD_MAX_FILE_SIZE)
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_UPLOAD_MAX_FILE_SIZE);
+        let upload_max_total_size = config.get(CONFIG_KEY_UPLOAD_MAX_TOTAL_SIZE)
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_UPLOAD_MAX_TOTAL_SIZE);
+        let upload_max_files = config.get(CONFIG_KEY_UPLOAD_MAX_FILES)
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_UPLOAD_MAX_FILES);
+        let upload_allowed_types = config.get(CONFIG_KEY_UPLOAD_ALLOWED_TYPES)
+            .map(|types| types.split(',')
+                .map(|t| t.trim().to_lowercase())
+                .filter(|t| !t.is_empty())
+                .collect())
+            .unwrap_or_default();
+        
+        let versions = VersionStore::from_config(&config);
+        let archives = ArchiveSettings::from_config(&config);
+        let digests = Arc::new(DigestSettings::from_config(&config));
+        let precompressed = PrecompressedSettings::from_config(&config);
+        let deny = DenyList::from_config(&name, &config);
+        
+        Self {
+            name,
+            root_dir,
+            upload_max_file_size,
+            upload_max_total_size,
+            upload_max_files,
+            upload_allowed_types,
+            versions,
+            archives,
+            digests,
+            precompressed,
+            deny,
+            patch_lock: tokio::sync::Mutex::new(()),
+        }
     }
     
-    /// Determines the appropriate Content-Type header based on file extension
-    fn get_content_type(path: &Path) -> &'static str {
-        match path.extension().and_then(|ext| ext.to_str()) {
-            Some("html") => CONTENT_TYPE_HTML,
-            Some("css") => CONTENT_TYPE_CSS,
-            Some("js") | Some("mjs") => CONTENT_TYPE_JAVASCRIPT,
-            Some("json") => CONTENT_TYPE_JSON,
-            Some("txt") => CONTENT_TYPE_TEXT,
-            Some("png") => CONTENT_TYPE_PNG,
-            Some("jpg") | Some("jpeg") => CONTENT_TYPE_JPEG,
-            Some("gif") => CONTENT_TYPE_GIF,
-            _ => CONTENT_TYPE_OCTET_STREAM,
+    /// Determines the Content-Type header for a file
+    ///
+    /// `contents` are the file's leading bytes if already read; otherwise the
+    /// file is read only when its extension is not recognised.
+    fn get_content_type(context: &PluginContext, path: &Path, contents: Option<&[u8]>) -> String {
+        if let Some(content_type) = Self::content_type_from_extension(context, path) {
+            return content_type;
         }
+        
+        let sniffed = match contents {
+            Some(contents) => Self::sniff_content_type(contents),
+            None => Self::read_prefix(path).and_then(|prefix| Self::sniff_content_type(&prefix)),
+        };
+        sniffed.unwrap_or_else(|| CONTENT_TYPE_OCTET_STREAM.to_string())
     }
     
-    /// Builds a file path from the root directory and request path
-    /// Automatically appends index.html for directory paths
-    fn build_file_path(&self, context: &PluginContext, request_path: &str) -> String {
-        // Use host-specific root if available, otherwise fall back to plugin config
-        let root_dir = context.host_config.get(CONFIG_KEY_HOST_ROOT)
-            .unwrap_or(&self.root_dir);
-        let mut file_path = format!("{}{}", root_dir, request_path);
+    /// Looks up a file's extension in the host overrides, then the mime_guess table
+    fn content_type_from_extension(context: &PluginContext, path: &Path) -> Option<String> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
         
-        // If path ends with '/', append 'index.html'
-        if file_path.ends_with('/') {
-            file_path.push_str(INDEX_FILE_NAME);
+        if let Some(content_type) = context.host_config.get(&format!("{}{}", HOST_CONFIG_MIME_TYPE_PREFIX, extension)) {
+            return Some(content_type.clone());
         }
         
-        file_path
+        mime_guess::from_ext(&extension).first().map(|mime| Self::with_charset(mime.essence_str()))
     }
     
-    /// Validates that the resolved path is within the allowed root directory
-    /// Returns Ok(canonical_path) if valid, Err(response) if access should be denied
-    fn validate_path_security(
-        &self, 
-        context: &PluginContext, 
-        path: &Path
-    ) -> Result<PathBuf, Response<Body>> {
-        let root_dir = context.host_config.get(CONFIG_KEY_HOST_ROOT)
-            .unwrap_or(&self.root_dir);
-            
-        match path.canonicalize() {
-            Ok(canonical) => {
-                let root_canonical = Path::new(root_dir)
-                    .canonicalize()
-                    .unwrap_or_else(|_| Path::new(DEFAULT_ROOT_DIR).to_path_buf());
-                    
-                if canonical.starts_with(&root_canonical) {
-                    Ok(canonical)
-                } else {
-                    Err(Response::builder()
-                        .status(StatusCode::FORBIDDEN)
-                        .body(Body::from(MSG_ACCESS_DENIED))
-                        .unwrap())
-                }
-            }
-            Err(_) => {
-                Err(Response::builder()
-                    .status(StatusCode::FORBIDDEN)
-                    .body(Body::from(MSG_ACCESS_DENIED))
-                    .unwrap())
-            }
+    /// Recognises a file from its leading bytes
+    ///
+    /// Only inert binary formats are recognised. Text is never sniffed, as a
+    /// file uploaded without an extension must not be served as HTML, SVG or
+    /// any other type a browser would run scripts in.
+    fn sniff_content_type(contents: &[u8]) -> Option<String> {
+        MAGIC_SIGNATURES.iter()
+            .find(|(magic, _)| contents.starts_with(magic))
+            .map(|(_, content_type)| content_type.to_string())
+    }
+    
+    /// Reads up to `SNIFF_LENGTH` bytes from the start of a file
+    fn read_prefix(path: &Path) -> Option<Vec<u8>> {
+        use std::io::Read;
+        let file = std::fs::File::open(path).ok()?;
+        let mut prefix = Vec::with_capacity(SNIFF_LENGTH);
+        file.take(SNIFF_LENGTH as u64).read_to_end(&mut prefix).ok()?;
+        Some(prefix)
+    }
+    
+    /// Labels textual content types as UTF-8
+    fn with_charset(content_type: &str) -> String {
+        let is_text = content_type.starts_with("text/")
+            || matches!(content_type, "application/javascript" | "application/json" | "application/xml");
+        if is_text {
+            format!("{}{}", content_type, CHARSET_UTF8_SUFFIX)
+        } else {
+            content_type.to_string()
         }
     }
     
+    /// Resolves the file a request addresses below the host root
+    /// Returns Err(response) if the path leads outside the root
+    fn resolve_file_path(
+        &self,
+        request: &PluginRequest,
+        context: &PluginContext
+    ) -> Result<PathBuf, Response<Body>> {
+        paths::request_file_path(request, context, &self.root_dir)
+            .map_err(|_| self.create_error_response(StatusCode::FORBIDDEN, MSG_ACCESS_DENIED))
+    }
+    
     /// Creates a standardized error response
     fn create_error_response(&self, status: StatusCode, message: &str) -> Response<Body> {
         Response::builder()
@@ -156,70 +476,441 @@ impl FileHandlerPlugin {
     
     /// Handles GET requests to serve files and directories
     async fn handle_get(&self, request: &PluginRequest, context: &PluginContext) -> Option<Response<Body>> {
-        let file_path = self.build_file_path(context, &request.path);
-        let path = Path::new(&file_path);
+        let path = match self.resolve_file_path(request, context) {
+            Ok(path) => path,
+            Err(error_response) => return Some(error_response),
+        };
         
-        // Validate path security
-        if let Err(error_response) = self.validate_path_security(context, path) {
-            return Some(error_response);
+        if let Some(version) = self.versions.as_ref().and(Self::query_param(request, QUERY_VERSION)) {
+            return Some(self.serve_version(context, &path, &version).await);
+        }
+        
+        if let Some(format) = self.archive_format(request) {
+            return Some(self.serve_archive(request, context, &path, &format, true).await);
         }
         
         // Try to serve the requested file
-        match self.serve_file(path) {
+        match self.serve_file(request, context, &path).await {
             Ok(response) => Some(response),
-            Err(_) => self.try_serve_directory_index(path),
+            Err(_) => self.try_serve_directory_index(context, &path).await,
         }
     }
     
-    /// Attempts to serve a file directly
-    fn serve_file(&self, path: &Path) -> Result<Response<Body>, std::io::Error> {
-        let contents = fs::read(path)?;
-        let content_type = Self::get_content_type(path);
+    /// Attempts to serve a file directly, or a precompressed variant of it
+    async fn serve_file(&self, request: &PluginRequest, context: &PluginContext, path: &Path) -> Result<Response<Body>, std::io::Error> {
+        let file = path.to_path_buf();
+        let accept_encoding = self.accept_encoding(request);
+        let (contents, metadata, variants) = fs::blocking(context, move || {
+            let metadata = std::fs::metadata(&file)?;
+            let variants = match accept_encoding {
+                Some(accept_encoding) if metadata.is_file() => precompressed::find(&file, &metadata, accept_encoding.as_deref()),
+                _ => Default::default(),
+            };
+            let contents = match &variants.chosen {
+                Some(variant) => std::fs::read(&variant.path)?,
+                None => std::fs::read(&file)?,
+            };
+            Ok((contents, metadata, variants))
+        }).await?;
         
-        Ok(Response::builder()
+        let builder = match &variants.chosen {
+            Some(variant) => {
+                context.log_verbose(&format!("[FileHandler] Serving {} variant {}", variant.coding, variant.path.display()));
+                let builder = Self::variant_response(context, path, variant);
+                self.with_digest(builder, &variant.path, &variant.metadata, Some(&contents))
+            }
+            None => {
+                let builder = Self::file_response(context, path, &metadata, Some(&contents));
+                self.with_digest(builder, path, &metadata, Some(&contents))
+            }
+        };
+        let builder = if variants.exist { builder.header("Vary", "Accept-Encoding") } else { builder };
+        Ok(builder.body(Body::from(contents)).unwrap())
+    }
+    
+    /// The request's Accept-Encoding header, empty if it has none, when
+    /// precompressed variants are served; None when they are not
+    fn accept_encoding(&self, request: &PluginRequest) -> Option<Option<String>> {
+        self.precompressed.enabled.then(|| request.http_request.headers()
+            .get(hyper::header::ACCEPT_ENCODING)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string))
+    }
+    
+    /// Adds a file's digest headers to a response, if digests are enabled
+    ///
+    /// `contents` are the file's bytes if already read; otherwise the digest
+    /// is looked up or computed from the file.
+    fn with_digest(&self, builder: Builder, path: &Path, metadata: &std::fs::Metadata, contents: Option<&[u8]>) -> Builder {
+        if !self.digests.enabled {
+            return builder;
+        }
+        let digest = match contents {
+            Some(contents) => {
+                let digest = digests::hash_bytes(contents);
+                self.digests.remember(path, metadata, digest);
+                Ok(digest)
+            }
+            None => self.digests.file_digest(path, metadata),
+        };
+        match digest {
+            Ok(digest) => builder
+                .header(digests::HEADER_REPR_DIGEST, digests::repr_digest_value(&digest))
+                .header(digests::HEADER_DIGEST, digests::legacy_digest_value(&digest)),
+            Err(_) => builder,
+        }
+    }
+    
+    /// Starts the response GET and HEAD send for a file
+    ///
+    /// `contents` are the file's bytes if already read; otherwise the length
+    /// comes from the metadata.
+    fn file_response(context: &PluginContext, path: &Path, metadata: &std::fs::Metadata, contents: Option<&[u8]>) -> Builder {
+        let length = contents.map(|contents| contents.len() as u64).unwrap_or(metadata.len());
+        Response::builder()
             .status(StatusCode::OK)
-            .header("Content-Type", content_type)
-            .body(Body::from(contents))
-            .unwrap())
+            .header("Content-Type", Self::get_content_type(context, path, contents))
+            .header("X-Content-Type-Options", NOSNIFF)
+            .header("Content-Length", length)
+            .header("ETag", Self::file_etag(metadata))
+    }
+    
+    /// Starts the response GET and HEAD send for a precompressed variant of
+    /// a file, typed as the file itself
+    fn variant_response(context: &PluginContext, path: &Path, variant: &Variant) -> Builder {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", Self::get_content_type(context, path, None))
+            .header("Content-Encoding", variant.coding)
+            .header("X-Content-Type-Options", NOSNIFF)
+            .header("Content-Length", variant.metadata.len())
+            .header("ETag", precompressed::etag(&Self::file_etag(&variant.metadata), variant.coding))
+    }
+    
+    /// Starts the response GET and HEAD send for a directory's index file
+    fn directory_index_response(length: u64) -> Builder {
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", CONTENT_TYPE_HTML)
+            .header("X-Content-Type-Options", NOSNIFF)
+            .header("Content-Length", length)
+    }
+    
+    /// Turns a GET response into the matching HEAD response
+    fn without_body(response: Response<Body>) -> Response<Body> {
+        let (mut parts, body) = response.into_parts();
+        if let Some(length) = body.size_hint().exact() {
+            parts.headers.entry(hyper::header::CONTENT_LENGTH).or_insert(length.into());
+        }
+        Response::from_parts(parts, Body::empty())
+    }
+    
+    /// Builds a weak-collision ETag from a file's size and modification time
+    fn file_etag(metadata: &std::fs::Metadata) -> String {
+        let modified = metadata.modified()
+            .ok()
+            .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0);
+        format!("\"{:x}-{:x}\"", metadata.len(), modified)
     }
     
     /// Attempts to serve index.html from a directory, or returns 404
-    fn try_serve_directory_index(&self, path: &Path) -> Option<Response<Body>> {
-        if path.is_dir() {
-            let index_path = path.join(INDEX_FILE_NAME);
-            match fs::read(&index_path) {
-                Ok(contents) => {
-                    Some(Response::builder()
+    async fn try_serve_directory_index(&self, context: &PluginContext, path: &Path) -> Option<Response<Body>> {
+        let directory = path.to_path_buf();
+        let index_path = path.join(INDEX_FILE_NAME);
+        let index_file = index_path.clone();
+        let index = fs::blocking(context, move || {
+            if !directory.is_dir() {
+                return Ok(None);
+            }
+            Ok(Some((std::fs::read(&index_file)?, std::fs::metadata(&index_file)?)))
+        }).await;
+        match index {
+            Ok(Some((contents, metadata))) => {
+                let builder = Self::directory_index_response(contents.len() as u64);
+                Some(self.with_digest(builder, &index_path, &metadata, Some(&contents))
+                    .body(Body::from(contents))
+                    .unwrap())
+            }
+            _ => Some(self.create_error_response(StatusCode::NOT_FOUND, MSG_FILE_NOT_FOUND))
+        }
+    }
+    
+    /// Returns the decoded value of a query string parameter
+    fn query_param(request: &PluginRequest, name: &str) -> Option<String> {
+        let query = request.http_request.uri().query()?;
+        url::form_urlencoded::parse(query.as_bytes())
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    }
+    
+    /// The archive format a GET or HEAD asks for, if archives are enabled
+    fn archive_format(&self, request: &PluginRequest) -> Option<String> {
+        if !self.archives.enabled {
+            return None;
+        }
+        Self::query_param(request, QUERY_FORMAT)
+    }
+    
+    /// Answers with a zip archive of the files below a directory that the
+    /// requesting user may read, written on the blocking pool if `with_body`
+    async fn serve_archive(
+        &self,
+        request: &PluginRequest,
+        context: &PluginContext,
+        path: &Path,
+        format: &str,
+        with_body: bool,
+    ) -> Response<Body> {
+        if !format.eq_ignore_ascii_case(FORMAT_ZIP) {
+            return self.create_error_response(StatusCode::BAD_REQUEST, MSG_UNSUPPORTED_FORMAT);
+        }
+        // A path ending in `/` resolves to the directory's index file
+        let path = match path.parent() {
+            Some(parent) if request.path.ends_with('/') => parent,
+            _ => path,
+        };
+        match fs::metadata(context, path).await {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => return self.create_error_response(StatusCode::BAD_REQUEST, MSG_ARCHIVE_NOT_DIRECTORY),
+            Err(_) => return self.create_error_response(StatusCode::NOT_FOUND, MSG_FILE_NOT_FOUND),
+        }
+        
+        let directory = if request.path.ends_with('/') {
+            request.path.clone()
+        } else {
+            format!("{}/", request.path)
+        };
+        let include = Self::query_param(request, QUERY_INCLUDE)
+            .map(|patterns| archive::parse_patterns(&patterns))
+            .unwrap_or_default();
+        let user = request.get_metadata("authenticated_user");
+        // Walk the directory off the runtime, then decide each file here
+        let root = path.to_path_buf();
+        let candidates = fs::blocking(context, move || Ok(archive::collect_entries(&root, &mut |_| true)))
+            .await
+            .unwrap_or_default();
+        let mut denied = 0;
+        let entries: Vec<_> = candidates.into_iter().filter(|entry| {
+            let relative = entry.name.as_str();
+            if self.archives.excludes(relative)
+                || !(include.is_empty() || include.iter().any(|pattern| condition::glob_match(pattern, relative)))
+            {
+                return false;
+            }
+            let url_path = format!("{}{}", directory, relative);
+            if self.versions.as_ref().is_some_and(|versions| versions.is_history_path(&url_path))
+                || self.deny.denies(context, &url_path)
+            {
+                return false;
+            }
+            let allowed = Self::readable_whole(context, user, &url_path);
+            if !allowed {
+                denied += 1;
+            }
+            allowed
+        }).collect();
+        context.log_verbose(&format!(
+            "[FileHandler] Archiving {} file(s) of {}, {} denied", entries.len(), directory, denied
+        ));
+        
+        let archive_name = path.file_name()
+            .and_then(|name| name.to_str())
+            .filter(|_| directory != "/")
+            .unwrap_or(context.host_name.as_str());
+        let archive_name: String = archive_name.chars()
+            .map(|c| if c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.') { c } else { '_' })
+            .collect();
+        let builder = Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", CONTENT_TYPE_ZIP)
+            .header("Content-Disposition", format!("attachment; filename=\"{}.zip\"", archive_name))
+            .header("X-Content-Type-Options", NOSNIFF)
+            // The entries depend on who asks
+            .header("Cache-Control", "private, no-store");
+        if !with_body {
+            return builder.body(Body::empty()).unwrap();
+        }
+        
+        let manifest = Self::query_param(request, QUERY_MANIFEST)
+            .is_some_and(|value| matches!(value.to_lowercase().as_str(), "true" | "yes" | "1"))
+            .then(|| directory.clone());
+        let (sender, body) = Body::channel();
+        let thread_context = context.clone();
+        let work = move || {
+            let (archived, problem) = archive::write_archive(entries, sender, manifest);
+            match problem {
+                Some(e) => thread_context.log_verbose(&format!(
+                    "[FileHandler] Archive of {} ended after {} file(s): {}", directory, archived, e
+                )),
+                None => thread_context.log_verbose(&format!(
+                    "[FileHandler] Archived {} file(s) of {}", archived, directory
+                )),
+            }
+        };
+        // Without a runtime handle the archive still needs a thread of its own
+        let spawned = match &context.runtime_handle {
+            Some(handle) => {
+                handle.spawn_blocking(work);
+                Ok(())
+            }
+            None => std::thread::Builder::new()
+                .name("file-handler-archive".to_string())
+                .spawn(work)
+                .map(|_| ()),
+        };
+        match spawned {
+            Ok(()) => builder.body(body).unwrap(),
+            Err(e) => self.create_error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("Failed to start archive: {}", e),
+            ),
+        }
+    }
+    
+    /// Resolves where the history of a file is kept: the canonical host root
+    /// and the file's path relative to it, or None if the path is outside the root
+    ///
+    /// `path` is a resolved request path, so deleted files still resolve.
+    fn version_location(&self, context: &PluginContext, path: &Path) -> Option<(PathBuf, PathBuf)> {
+        let root_dir = context.host_config.get(CONFIG_KEY_HOST_ROOT)
+            .unwrap_or(&self.root_dir);
+        let root = Path::new(root_dir).canonicalize().ok()?;
+        let relative = path.strip_prefix(&root).ok()?.to_path_buf();
+        Some((root, relative))
+    }
+    
+    /// Copies a file's current content into its history before it changes,
+    /// if versioning is enabled
+    ///
+    /// Returns the error response to send if the content could not be saved.
+    async fn save_version(&self, context: &PluginContext, path: &Path) -> Option<Response<Body>> {
+        let versions = self.versions.clone()?;
+        let Some((root, relative)) = self.version_location(context, path) else {
+            return Some(self.create_error_response(StatusCode::FORBIDDEN, MSG_ACCESS_DENIED));
+        };
+        let (saved, current) = (relative.clone(), path.to_path_buf());
+        match fs::blocking(context, move || versions.save(&root, &saved, &current)).await {
+            Ok(Some(number)) => {
+                context.log_verbose(&format!("[FileHandler] Saved version {} of {}", number, relative.display()));
+                None
+            }
+            Ok(None) => None,
+            Err(e) => Some(self.create_error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("{}: {}", MSG_FAILED_SAVE_VERSION, e)
+            )),
+        }
+    }
+    
+    /// Serves the version listing of a file, or the content of one version
+    async fn serve_version(&self, context: &PluginContext, path: &Path, version: &str) -> Response<Body> {
+        let Some(versions) = self.versions.clone() else {
+            return self.create_error_response(StatusCode::NOT_FOUND, MSG_VERSION_NOT_FOUND);
+        };
+        let Some((root, relative)) = self.version_location(context, path) else {
+            return self.create_error_response(StatusCode::FORBIDDEN, MSG_ACCESS_DENIED);
+        };
+        
+        if version == VERSION_LIST {
+            let listed = relative.clone();
+            return match fs::blocking(context, move || versions.list(&root, &listed)).await {
+                Ok(list) => {
+                    let body = json!({
+                        "path": format!("/{}", relative.to_string_lossy()),
+                        "versions": list.iter().map(|version| version.to_json()).collect::<Vec<_>>(),
+                    });
+                    Response::builder()
                         .status(StatusCode::OK)
-                        .header("Content-Type", CONTENT_TYPE_HTML)
-                        .body(Body::from(contents))
-                        .unwrap())
+                        .header("Content-Type", CONTENT_TYPE_JSON)
+                        .body(Body::from(body.to_string()))
+                        .unwrap()
                 }
-                Err(_) => Some(self.create_error_response(StatusCode::NOT_FOUND, MSG_FILE_NOT_FOUND))
+                Err(e) => self.create_error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("{}: {}", MSG_FAILED_READ_VERSIONS, e)
+                ),
+            };
+        }
+        
+        let Ok(number) = version.parse::<u64>() else {
+            return self.create_error_response(StatusCode::BAD_REQUEST, MSG_INVALID_VERSION);
+        };
+        match fs::blocking(context, move || versions.read(&root, &relative, number)).await {
+            Ok(Some(contents)) => Response::builder()
+                .status(StatusCode::OK)
+                .header("Content-Type", Self::get_content_type(context, path, Some(&contents)))
+                .header("X-Content-Type-Options", NOSNIFF)
+                .body(Body::from(contents))
+                .unwrap(),
+            Ok(None) => self.create_error_response(StatusCode::NOT_FOUND, MSG_VERSION_NOT_FOUND),
+            Err(e) => self.create_error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("{}: {}", MSG_FAILED_READ_VERSIONS, e)
+            ),
+        }
+    }
+    
+    /// Replaces a file with one of its versions, saving the current content first
+    async fn restore_version(&self, context: &PluginContext, path: &Path, version: &str) -> Response<Body> {
+        let Some(versions) = self.versions.clone() else {
+            return self.create_error_response(StatusCode::NOT_FOUND, MSG_VERSION_NOT_FOUND);
+        };
+        let Ok(number) = version.parse::<u64>() else {
+            return self.create_error_response(StatusCode::BAD_REQUEST, MSG_INVALID_VERSION);
+        };
+        let Some((root, relative)) = self.version_location(context, path) else {
+            return self.create_error_response(StatusCode::FORBIDDEN, MSG_ACCESS_DENIED);
+        };
+        
+        // Read the version before saving the current content, which may prune it
+        let read = relative.clone();
+        let contents = match fs::blocking(context, move || versions.read(&root, &read, number)).await {
+            Ok(Some(contents)) => contents,
+            Ok(None) => return self.create_error_response(StatusCode::NOT_FOUND, MSG_VERSION_NOT_FOUND),
+            Err(e) => return self.create_error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("{}: {}", MSG_FAILED_READ_VERSIONS, e)
+            ),
+        };
+        
+        let file_existed = fs::exists(context, path).await;
+        if let Some(error_response) = self.save_version(context, path).await {
+            return error_response;
+        }
+        match self.write_file_safely(context, path, contents).await {
+            Ok(metadata) => {
+                context.log_verbose(&format!("[FileHandler] Restored {} to version {}", relative.display(), number));
+                let status = if file_existed { StatusCode::OK } else { StatusCode::CREATED };
+                Response::builder()
+                    .status(status)
+                    .header("Content-Type", CONTENT_TYPE_PLAIN)
+                    .header("ETag", Self::file_etag(&metadata))
+                    .body(Body::from(format!("{} {}", MSG_FILE_RESTORED, number)))
+                    .unwrap()
             }
-        } else {
-            Some(self.create_error_response(StatusCode::NOT_FOUND, MSG_FILE_NOT_FOUND))
+            Err(e) => self.create_error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("{}: {}", MSG_FAILED_WRITE_FILE, e)
+            ),
         }
     }
     
     /// Handles PUT requests to create or update files
     async fn handle_put(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<Response<Body>> {
-        let file_path = self.build_file_path(context, &request.path);
-        let path = Path::new(&file_path);
+        let path = match self.resolve_file_path(request, context) {
+            Ok(path) => path,
+            Err(error_response) => return Some(error_response),
+        };
+        let path = path.as_path();
         
         // Check if file exists before writing to determine correct status code
-        let file_existed = path.exists();
+        let file_existed = fs::exists(context, path).await;
         
-        // Validate parent directory security (for file creation)
-        if let Some(parent) = path.parent() {
-            if let Err(error_response) = self.validate_path_security(context, parent) {
-                return Some(error_response);
-            }
-        }
-        
-        // Get request body
-        let body_bytes = match request.get_body().await {
-            Ok(bytes) => bytes,
+        // Get request body, which may have been spooled to disk
+        let body = match request.body().await {
+            Ok(body) => body,
             Err(_) => {
                 return Some(self.create_error_response(
                     StatusCode::BAD_REQUEST, 
@@ -228,14 +919,31 @@ impl FileHandlerPlugin {
             }
         };
         
+        // Check the body against any digest the client stated before changing anything
+        let digest = match self.verify_body_digest(request, context, &body) {
+            Ok(digest) => digest,
+            Err(error_response) => return Some(error_response),
+        };
+        
+        if let Some(error_response) = self.save_version(context, path).await {
+            return Some(error_response);
+        }
+        
         // Write the file
-        match self.write_file_safely(path, &body_bytes) {
+        match self.write_body_safely(context, path, &body).await {
             Ok(_) => {
                 // RFC 7231: 201 for new resources, 200 for updates
                 let status = if file_existed { StatusCode::OK } else { StatusCode::CREATED };
-                Some(Response::builder()
+                let mut builder = Response::builder()
                     .status(status)
-                    .header("Content-Type", CONTENT_TYPE_PLAIN)
+                    .header("Content-Type", CONTENT_TYPE_PLAIN);
+                if let Some(digest) = digest {
+                    if let Ok(metadata) = fs::metadata(context, path).await {
+                        self.digests.remember(path, &metadata, digest);
+                    }
+                    builder = builder.header(digests::HEADER_REPR_DIGEST, digests::repr_digest_value(&digest));
+                }
+                Some(builder
                     .body(Body::from(MSG_FILE_UPLOADED))
                     .unwrap())
             }
@@ -248,31 +956,86 @@ impl FileHandlerPlugin {
         }
     }
     
-    /// Safely writes file content, creating parent directories as needed
-    fn write_file_safely(&self, path: &Path, content: &[u8]) -> Result<(), std::io::Error> {
-        // Create directory if it doesn't exist
-        if let Some(parent) = path.parent() {
-            fs::create_dir_all(parent)?;
+    /// Hashes a PUT body if digests are enabled, and checks it against the
+    /// SHA-256 digests the request states
+    ///
+    /// Returns the body's digest, or the error response to send if it does not
+    /// match or a stated digest is malformed.
+    #[allow(clippy::result_large_err)]
+    fn verify_body_digest(
+        &self,
+        request: &PluginRequest,
+        context: &PluginContext,
+        body: &CachedBody,
+    ) -> Result<Option<digests::Sha256>, Response<Body>> {
+        if !self.digests.enabled {
+            return Ok(None);
         }
-        
-        fs::write(path, content)
+        let stated = digests::requested_digests(request.http_request.headers())
+            .map_err(|e| self.create_error_response(StatusCode::BAD_REQUEST, &e))?;
+        let mut writer = digests::DigestWriter::new();
+        body.copy_to(&mut writer)
+            .map_err(|_| self.create_error_response(StatusCode::BAD_REQUEST, MSG_FAILED_READ_BODY))?;
+        let digest = writer.finish();
+        if stated.iter().any(|expected| *expected != digest) {
+            context.log_verbose(&format!("[FileHandler] Rejected PUT to {}: body does not match its digest", request.path));
+            return Err(self.create_error_response(StatusCode::UNPROCESSABLE_ENTITY, MSG_DIGEST_MISMATCH));
+        }
+        Ok(Some(digest))
+    }
+    
+    /// Safely writes file content, creating parent directories as needed,
+    /// and returns the metadata of the written file
+    async fn write_file_safely(&self, context: &PluginContext, path: &Path, content: Vec<u8>) -> Result<std::fs::Metadata, std::io::Error> {
+        let path = path.to_path_buf();
+        fs::blocking(context, move || {
+            // Create directory if it doesn't exist
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            
+            std::fs::write(&path, content)?;
+            std::fs::metadata(&path)
+        }).await
+    }
+    
+    /// Safely writes a request body, streaming a spooled body from disk
+    async fn write_body_safely(&self, context: &PluginContext, path: &Path, body: &CachedBody) -> Result<(), std::io::Error> {
+        let path = path.to_path_buf();
+        let body = body.clone();
+        fs::blocking(context, move || {
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            
+            let mut file = std::fs::File::create(&path)?;
+            body.copy_to(&mut file)?;
+            Ok(())
+        }).await
     }
     
     /// Handles POST requests to append content to files
     async fn handle_post(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<Response<Body>> {
-        let file_path = self.build_file_path(context, &request.path);
-        let path = Path::new(&file_path);
+        if let Some(version) = self.versions.as_ref().and(Self::query_param(request, QUERY_RESTORE)) {
+            return Some(match self.resolve_file_path(request, context) {
+                Ok(path) => self.restore_version(context, &path, &version).await,
+                Err(error_response) => error_response,
+            });
+        }
         
-        // Validate path security
-        if let Some(parent) = path.parent() {
-            if let Err(error_response) = self.validate_path_security(context, parent) {
-                return Some(error_response);
-            }
+        if let Some(boundary) = self.multipart_boundary(request) {
+            return Some(self.handle_multipart_upload(request, context, boundary).await);
         }
         
+        let path = match self.resolve_file_path(request, context) {
+            Ok(path) => path,
+            Err(error_response) => return Some(error_response),
+        };
+        let path = path.as_path();
+        
         // Get request body
-        let body_bytes = match request.get_body().await {
-            Ok(bytes) => bytes,
+        let body = match request.body().await {
+            Ok(body) => body,
             Err(_) => {
                 return Some(self.create_error_response(
                     StatusCode::BAD_REQUEST,
@@ -282,7 +1045,7 @@ impl FileHandlerPlugin {
         };
         
         // Append content to the file (create if it doesn't exist)
-        match self.append_to_file(path, &body_bytes) {
+        match self.append_to_file(context, path, &body).await {
             Ok(_) => {
                 Some(Response::builder()
                     .status(StatusCode::OK)
@@ -300,60 +1063,580 @@ impl FileHandlerPlugin {
     }
     
     /// Appends content to a file, creating it if it doesn't exist
-    fn append_to_file(&self, path: &Path, content: &[u8]) -> Result<(), std::io::Error> {
-        use std::io::Write;
+    async fn append_to_file(&self, context: &PluginContext, path: &Path, body: &CachedBody) -> Result<(), std::io::Error> {
+        let path = path.to_path_buf();
+        let body = body.clone();
+        fs::blocking(context, move || {
+            let mut file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)?;
+            
+            body.copy_to(&mut file).map(|_| ())
+        }).await
+    }
+    
+    /// Returns the boundary if the request body is multipart/form-data
+    fn multipart_boundary(&self, request: &PluginRequest) -> Option<String> {
+        let content_type = request.http_request.headers()
+            .get(hyper::header::CONTENT_TYPE)?
+            .to_str()
+            .ok()?;
+        if !content_type.trim_start().to_lowercase().starts_with(MULTIPART_FORM_DATA) {
+            return None;
+        }
+        multer::parse_boundary(content_type).ok()
+    }
+    
+    /// Handles multipart/form-data POSTs by storing each file in the request directory
+    async fn handle_multipart_upload(
+        &self,
+        request: &mut PluginRequest,
+        context: &PluginContext,
+        boundary: String
+    ) -> Response<Body> {
+        let directory = match self.resolve_file_path(request, context) {
+            // A path ending in '/' resolves to the directory's index file
+            Ok(path) if request.path.ends_with('/') => match path.parent() {
+                Some(parent) => parent.to_path_buf(),
+                None => return self.create_error_response(StatusCode::FORBIDDEN, MSG_ACCESS_DENIED),
+            },
+            Ok(path) => path,
+            Err(error_response) => return error_response,
+        };
+        if !fs::metadata(context, &directory).await.is_ok_and(|metadata| metadata.is_dir()) {
+            return self.create_error_response(StatusCode::CONFLICT, MSG_UPLOAD_TARGET_NOT_DIRECTORY);
+        }
         
-        let mut file = std::fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(path)?;
+        let body = match request.body().await {
+            Ok(body) => body,
+            Err(_) => return self.create_error_response(StatusCode::BAD_REQUEST, MSG_FAILED_READ_BODY),
+        };
+        
+        // Validate every part before writing anything so a rejected upload leaves no files behind
+        let files = match self.parse_multipart_files(body.stream(), boundary).await {
+            Ok(files) if files.is_empty() => {
+                return self.create_error_response(StatusCode::BAD_REQUEST, MSG_NO_FILES_UPLOADED);
+            }
+            Ok(files) => files,
+            Err(error_response) => return error_response,
+        };
+        
+        let url_dir = if request.path.ends_with('/') {
+            request.path.clone()
+        } else {
+            format!("{}/", request.path)
+        };
+        if files.iter().any(|file| self.deny.denies(context, &format!("{}{}", url_dir, file.file_name))) {
+            return self.create_error_response(StatusCode::BAD_REQUEST, MSG_INVALID_FILENAME);
+        }
+        
+        let mut created = Vec::new();
+        for file in files {
+            let (upload_directory, file_name, data) = (directory.clone(), file.file_name.clone(), file.data.clone());
+            let stored = fs::blocking(context, move || {
+                Self::create_upload_file(&upload_directory, &file_name, &data)
+            }).await;
+            let target = match stored {
+                Ok(target) => target,
+                Err(e) => return self.create_error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("{}: {}", MSG_FAILED_WRITE_FILE, e)
+                ),
+            };
             
-        file.write_all(content)
+            let stored_name = target.file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or(file.file_name);
+            context.log_verbose(&format!("[FileHandler] Uploaded {} ({} bytes)", target.display(), file.data.len()));
+            created.push(json!({
+                "name": stored_name,
+                "path": format!("{}{}", url_dir, stored_name),
+                "size": file.data.len(),
+                "contentType": file.content_type,
+            }));
+        }
+        
+        self.create_upload_listing(request, &created)
+    }
+    
+    /// Reads the file parts of a multipart body, enforcing the size, count and type limits
+    async fn parse_multipart_files(
+        &self,
+        body: Body,
+        boundary: String
+    ) -> Result<Vec<UploadedFile>, Response<Body>> {
+        let constraints = multer::Constraints::new()
+            .size_limit(multer::SizeLimit::new()
+                .per_field(self.upload_max_file_size)
+                .whole_stream(self.upload_max_total_size));
+        let mut multipart = multer::Multipart::with_constraints(body, boundary, constraints);
+        
+        let mut files = Vec::new();
+        loop {
+            let field = match multipart.next_field().await {
+                Ok(Some(field)) => field,
+                Ok(None) => break,
+                Err(e) => return Err(self.multipart_error_response(e)),
+            };
+            
+            // Plain form fields carry no file to store
+            let Some(raw_name) = field.file_name().map(|n| n.to_string()) else { continue };
+            if raw_name.is_empty() {
+                continue; // File input submitted without a selected file
+            }
+            if files.len() == self.upload_max_files {
+                return Err(self.create_error_response(StatusCode::PAYLOAD_TOO_LARGE, MSG_TOO_MANY_FILES));
+            }
+            let file_name = self.sanitize_file_name(&raw_name)
+                .ok_or_else(|| self.create_error_response(StatusCode::BAD_REQUEST, MSG_INVALID_FILENAME))?;
+            
+            let content_type = field.content_type()
+                .map(|mime| mime.essence_str().to_lowercase())
+                .unwrap_or_else(|| CONTENT_TYPE_OCTET_STREAM.to_string());
+            if !self.is_upload_type_allowed(&content_type) {
+                return Err(self.create_error_response(
+                    StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                    &format!("{}: {}", MSG_UNSUPPORTED_UPLOAD_TYPE, content_type)
+                ));
+            }
+            
+            let data = field.bytes().await.map_err(|e| self.multipart_error_response(e))?;
+            files.push(UploadedFile { file_name, content_type, data });
+        }
+        
+        Ok(files)
+    }
+    
+    /// Maps a multipart parsing error to an HTTP response
+    fn multipart_error_response(&self, error: multer::Error) -> Response<Body> {
+        match error {
+            multer::Error::FieldSizeExceeded { .. } => {
+                self.create_error_response(StatusCode::PAYLOAD_TOO_LARGE, MSG_FILE_TOO_LARGE)
+            }
+            multer::Error::StreamSizeExceeded { .. } => {
+                self.create_error_response(StatusCode::PAYLOAD_TOO_LARGE, MSG_UPLOAD_TOO_LARGE)
+            }
+            // Reading a field reports the limit through the stream it reads
+            multer::Error::StreamReadFailed(e) if matches!(
+                e.downcast_ref::<multer::Error>(),
+                Some(multer::Error::StreamSizeExceeded { .. })
+            ) => {
+                self.create_error_response(StatusCode::PAYLOAD_TOO_LARGE, MSG_UPLOAD_TOO_LARGE)
+            }
+            e => self.create_error_response(StatusCode::BAD_REQUEST, &format!("{}: {}", MSG_INVALID_MULTIPART, e)),
+        }
+    }
+    
+    /// Reduces a client-supplied filename to a safe final path component
+    fn sanitize_file_name(&self, raw_name: &str) -> Option<String> {
+        // Browsers on some platforms send full paths; keep only the last component
+        let base = raw_name.rsplit(['/', '\\']).next().unwrap_or(raw_name);
+        let sanitized: String = base.chars()
+            .map(|c| if c.is_alphanumeric() || matches!(c, '.' | '-' | '_') { c } else { '_' })
+            .collect();
+        // Leading dots would create hidden files or traverse upwards
+        let sanitized = sanitized.trim_start_matches('.');
+        
+        if sanitized.is_empty() {
+            None
+        } else {
+            Some(sanitized.to_string())
+        }
+    }
+    
+    /// Checks an upload's content type against the configured allow list
+    fn is_upload_type_allowed(&self, content_type: &str) -> bool {
+        if self.upload_allowed_types.is_empty() {
+            return true;
+        }
+        self.upload_allowed_types.iter().any(|allowed| {
+            match allowed.strip_suffix("/*") {
+                Some(prefix) => content_type.split('/').next() == Some(prefix),
+                None => allowed == "*/*" || allowed == content_type,
+            }
+        })
+    }
+    
+    /// Writes an upload to a new file in the directory, numbering the name
+    /// until it clobbers no existing file
+    ///
+    /// Each name is claimed by creating the file exclusively, so concurrent
+    /// uploads of the same name never write to the same file.
+    fn create_upload_file(directory: &Path, file_name: &str, data: &[u8]) -> Result<PathBuf, std::io::Error> {
+        use std::io::Write;
+        let (stem, extension) = match file_name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem, format!(".{}", ext)),
+            _ => (file_name, String::new()),
+        };
+        let candidates = std::iter::once(directory.join(file_name))
+            .chain((1u64..).map(|n| directory.join(format!("{}-{}{}", stem, n, extension))));
+        for candidate in candidates {
+            let mut file = match std::fs::OpenOptions::new().write(true).create_new(true).open(&candidate) {
+                Ok(file) => file,
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            };
+            if let Err(e) = file.write_all(data) {
+                drop(file);
+                let _ = std::fs::remove_file(&candidate);
+                return Err(e);
+            }
+            return Ok(candidate);
+        }
+        unreachable!("an unused filename always exists")
     }
     
-    /// Handles HEAD requests to return file metadata without body
+    /// Builds the 201 response listing uploaded resources as JSON or HTML
+    fn create_upload_listing(&self, request: &PluginRequest, created: &[serde_json::Value]) -> Response<Body> {
+        let wants_json = request.http_request.headers()
+            .get(hyper::header::ACCEPT)
+            .and_then(|v| v.to_str().ok())
+            .map(|accept| accept.to_lowercase().contains(MEDIA_TYPE_JSON))
+            .unwrap_or(false);
+        
+        let mut builder = Response::builder().status(StatusCode::CREATED);
+        if let Some(path) = created.first().and_then(|file| file["path"].as_str()) {
+            builder = builder.header("Location", path);
+        }
+        
+        if wants_json {
+            builder
+                .header("Content-Type", CONTENT_TYPE_JSON)
+                .body(Body::from(json!({ "files": created }).to_string()))
+                .unwrap()
+        } else {
+            let items: String = created.iter()
+                .map(|file| {
+                    let path = html_escape(file["path"].as_str().unwrap_or_default());
+                    let name = html_escape(file["name"].as_str().unwrap_or_default());
+                    format!("<li><a href=\"{}\">{}</a> ({} bytes)</li>\n", path, name, file["size"])
+                })
+                .collect();
+            builder
+                .header("Content-Type", CONTENT_TYPE_HTML)
+                .body(Body::from(format!(
+                    "<!DOCTYPE html>\n<html>\n<head><title>Files Uploaded</title></head>\n<body>\n<h1>Files Uploaded</h1>\n<ul>\n{}</ul>\n</body>\n</html>\n",
+                    items
+                )))
+                .unwrap()
+        }
+    }
+    
+    /// Handles HEAD requests with the headers GET would send, without reading the file
     async fn handle_head(&self, request: &PluginRequest, context: &PluginContext) -> Option<Response<Body>> {
-        let file_path = self.build_file_path(context, &request.path);
-        let path = Path::new(&file_path);
+        let path = match self.resolve_file_path(request, context) {
+            Ok(path) => path,
+            Err(error_response) => return Some(Self::without_body(error_response)),
+        };
+        let path = path.as_path();
         
-        // Validate path security
-        if let Err(error_response) = self.validate_path_security(context, path) {
-            return Some(error_response);
+        // Versions are only read on demand, so answer from the GET response
+        if let Some(version) = self.versions.as_ref().and(Self::query_param(request, QUERY_VERSION)) {
+            return Some(Self::without_body(self.serve_version(context, path, &version).await));
         }
         
-        // HEAD should return same headers as GET but without body
-        match fs::metadata(path) {
-            Ok(metadata) => {
-                let content_type = Self::get_content_type(path);
-                
-                Some(Response::builder()
-                    .status(StatusCode::OK)
-                    .header("Content-Type", content_type)
-                    .header("Content-Length", metadata.len().to_string())
-                    .body(Body::empty())
-                    .unwrap())
+        if let Some(format) = self.archive_format(request) {
+            return Some(self.serve_archive(request, context, path, &format, false).await);
+        }
+        
+        // Mirror handle_get: the file itself, else the directory's index
+        let builder = match fs::metadata(context, path).await {
+            Ok(metadata) if metadata.is_file() => {
+                let variants = match self.accept_encoding(request) {
+                    Some(accept_encoding) => {
+                        let file = path.to_path_buf();
+                        let file_metadata = metadata.clone();
+                        fs::blocking(context, move || {
+                            Ok(precompressed::find(&file, &file_metadata, accept_encoding.as_deref()))
+                        }).await.unwrap_or_default()
+                    }
+                    None => Default::default(),
+                };
+                let builder = match &variants.chosen {
+                    Some(variant) => self.with_digest(Self::variant_response(context, path, variant), &variant.path, &variant.metadata, None),
+                    None => self.with_digest(Self::file_response(context, path, &metadata, None), path, &metadata, None),
+                };
+                if variants.exist { builder.header("Vary", "Accept-Encoding") } else { builder }
+            }
+            Ok(metadata) if metadata.is_dir() => match fs::metadata(context, path.join(INDEX_FILE_NAME)).await {
+                Ok(index) if index.is_file() => {
+                    self.with_digest(Self::directory_index_response(index.len()), &path.join(INDEX_FILE_NAME), &index, None)
+                }
+                _ => return Some(Self::without_body(self.create_error_response(StatusCode::NOT_FOUND, MSG_FILE_NOT_FOUND))),
+            },
+            _ => return Some(Self::without_body(self.create_error_response(StatusCode::NOT_FOUND, MSG_FILE_NOT_FOUND))),
+        };
+        Some(builder.body(Body::empty()).unwrap())
+    }
+    
+    /// Whether a user may GET the file at a URL path by another path, as an
+    /// archive entry or a blob
+    ///
+    /// The response to such a GET is not redacted for the file's own path,
+    /// so HTML files with elements withheld from the user are refused.
+    fn readable_whole(context: &PluginContext, user: Option<&str>, url_path: &str) -> bool {
+        let Some(services) = context.services.as_ref() else {
+            return true;
+        };
+        if !services.is_allowed(user, "GET", url_path, context) {
+            return false;
+        }
+        let is_html = Self::content_type_from_extension(context, Path::new(url_path))
+            .is_some_and(|content_type| content_type.starts_with(MEDIA_TYPE_HTML));
+        !is_html || services.withheld_selectors(user, url_path, context).is_empty()
+    }
+    
+    /// Serves the file below the host root with the digest a blob path names,
+    /// if the requesting user may GET it at its own path
+    async fn serve_blob(&self, request: &PluginRequest, context: &PluginContext) -> Response<Body> {
+        if !matches!(*request.http_request.method(), Method::GET | Method::HEAD) {
+            let mut response = self.create_error_response(StatusCode::METHOD_NOT_ALLOWED, MSG_BLOB_READ_ONLY);
+            response.headers_mut().insert("Allow", hyper::header::HeaderValue::from_static(BLOB_METHODS));
+            return response;
+        }
+        let Some(wanted) = self.digests.blob_digest(&request.path) else {
+            return self.create_error_response(StatusCode::NOT_FOUND, MSG_INVALID_BLOB_ADDRESS);
+        };
+        let root_dir = PathBuf::from(context.host_config.get(CONFIG_KEY_HOST_ROOT).unwrap_or(&self.root_dir));
+        let Ok(root) = fs::blocking(context, move || root_dir.canonicalize()).await else {
+            return self.create_error_response(StatusCode::NOT_FOUND, MSG_FILE_NOT_FOUND);
+        };
+        let user = request.get_metadata("authenticated_user");
+        
+        // The files last seen with the digest, then those the root holds now
+        let (digests, known_root) = (Arc::clone(&self.digests), root.clone());
+        let known = fs::blocking(context, move || Ok(digests.known_paths(&wanted).into_iter()
+            .filter(|path| path.starts_with(&known_root) && Self::has_digest(&digests, path, &wanted))
+            .collect()
+        )).await.unwrap_or_default();
+        let mut found = self.first_readable_blob(&root, known, context, user);
+        if found.is_none() && self.digests.begin_search(&root) {
+            let (digests, search_root) = (Arc::clone(&self.digests), root.clone());
+            let matching = fs::blocking(context, move || Ok(archive::collect_entries(&search_root, &mut |relative| {
+                Self::has_digest(&digests, &search_root.join(relative), &wanted)
+            }).into_iter().map(|entry| entry.path).collect())).await.unwrap_or_default();
+            found = self.first_readable_blob(&root, matching, context, user);
+        }
+        let Some((path, url_path)) = found else {
+            context.log_verbose(&format!("[FileHandler] No readable file has digest {}", digests::hex(&wanted)));
+            return self.create_error_response(StatusCode::NOT_FOUND, MSG_FILE_NOT_FOUND);
+        };
+        
+        let read_path = path.clone();
+        let read = fs::blocking(context, move || Ok((std::fs::read(&read_path)?, std::fs::metadata(&read_path)?))).await;
+        let response = match read {
+            // The file may have changed since it was hashed
+            Ok((contents, metadata)) if digests::hash_bytes(&contents) == wanted => {
+                let builder = Self::file_response(context, &path, &metadata, Some(&contents));
+                self.with_digest(builder, &path, &metadata, Some(&contents))
+                    .header("Content-Location", Self::encode_path(&url_path))
+                    .header("Cache-Control", BLOB_CACHE_CONTROL)
+                    .body(Body::from(contents))
+                    .unwrap()
+            }
+            _ => self.create_error_response(StatusCode::NOT_FOUND, MSG_FILE_NOT_FOUND),
+        };
+        if request.http_request.method() == Method::HEAD {
+            Self::without_body(response)
+        } else {
+            response
+        }
+    }
+    
+    /// Whether a file's content has a digest, hashing it only if it changed
+    /// since it was last hashed
+    fn has_digest(digests: &DigestSettings, path: &Path, wanted: &digests::Sha256) -> bool {
+        std::fs::metadata(path)
+            .and_then(|metadata| digests.file_digest(path, &metadata))
+            .is_ok_and(|digest| digest == *wanted)
+    }
+    
+    /// The first of some files below a canonical root that may be served as
+    /// a blob to the user, with its URL path
+    ///
+    /// Files in hidden directories, in the version history or on the deny
+    /// list are never served as blobs.
+    fn first_readable_blob(
+        &self,
+        root: &Path,
+        mut paths: Vec<PathBuf>,
+        context: &PluginContext,
+        user: Option<&str>,
+    ) -> Option<(PathBuf, String)> {
+        paths.sort();
+        paths.into_iter().find_map(|path| {
+            let relative = path.strip_prefix(root).ok()?;
+            let segments: Vec<&str> = relative.iter().map(|segment| segment.to_str()).collect::<Option<_>>()?;
+            if segments.iter().any(|segment| segment.starts_with('.')) {
+                return None;
             }
+            let url_path = format!("/{}", segments.join("/"));
+            let readable = !self.versions.as_ref().is_some_and(|versions| versions.is_history_path(&url_path))
+                && !self.deny.denies(context, &url_path)
+                && Self::readable_whole(context, user, &url_path);
+            readable.then_some((path, url_path))
+        })
+    }
+    
+    /// Methods the caller may use on the request path, for the Allow header
+    fn allowed_methods(&self, request: &PluginRequest, context: &PluginContext) -> String {
+        let user = request.get_metadata("authenticated_user");
+        let allowed: Vec<&str> = ALLOWED_METHODS.iter()
+            .copied()
+            .filter(|method| *method == "OPTIONS" || context.services.as_ref()
+                .is_none_or(|services| services.is_allowed(user, method, &request.path, context)))
+            .collect();
+        allowed.join(", ")
+    }
+    
+    /// Handles PATCH requests to write a byte range of an existing file
+    async fn handle_patch(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<Response<Body>> {
+        // The file must already exist
+        let path = match self.resolve_file_path(request, context) {
+            Ok(path) => path,
+            Err(error_response) => return Some(error_response),
+        };
+        let path = path.as_path();
+        let _patch_guard = self.patch_lock.lock().await;
+        let metadata = match fs::metadata(context, path).await {
+            Ok(metadata) => metadata,
+            Err(_) => return Some(self.create_error_response(StatusCode::NOT_FOUND, MSG_FILE_NOT_FOUND)),
+        };
+        if metadata.is_dir() {
+            return Some(self.create_error_response(StatusCode::CONFLICT, MSG_PATCH_TARGET_IS_DIRECTORY));
+        }
+        
+        // Require the client to prove it has seen the current version
+        let if_match = match request.http_request.headers().get("If-Match").and_then(|v| v.to_str().ok()) {
+            Some(if_match) => if_match.to_string(),
+            None => return Some(self.create_error_response(
+                StatusCode::PRECONDITION_REQUIRED,
+                MSG_PRECONDITION_REQUIRED
+            )),
+        };
+        if !Self::if_match_satisfied(&if_match, &Self::file_etag(&metadata)) {
+            return Some(self.create_error_response(StatusCode::PRECONDITION_FAILED, MSG_PRECONDITION_FAILED));
+        }
+        
+        let range = match request.http_request.headers()
+            .get("Content-Range")
+            .and_then(|v| v.to_str().ok())
+            .and_then(Self::parse_content_range)
+        {
+            Some(range) => range,
+            None => return Some(self.create_error_response(StatusCode::BAD_REQUEST, MSG_INVALID_CONTENT_RANGE)),
+        };
+        
+        // Writes may overlap or extend the file but must not leave a hole, and
+        // a stated complete length must match the size after the write
+        let resulting_length = metadata.len().max(range.end + 1);
+        let length_mismatch = range.complete_length.is_some_and(|length| length != resulting_length);
+        if range.start > metadata.len() || length_mismatch {
+            return Some(Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header("Content-Type", CONTENT_TYPE_PLAIN)
+                .header("Content-Range", format!("{} */{}", BYTES_RANGE_UNIT, metadata.len()))
+                .body(Body::from(MSG_RANGE_NOT_SATISFIABLE))
+                .unwrap());
+        }
+        
+        // Get request body
+        let body_bytes = match request.get_body().await {
+            Ok(bytes) => bytes,
             Err(_) => {
+                return Some(self.create_error_response(
+                    StatusCode::BAD_REQUEST,
+                    MSG_FAILED_READ_BODY
+                ));
+            }
+        };
+        if body_bytes.len() as u64 != range.end - range.start + 1 {
+            return Some(self.create_error_response(StatusCode::BAD_REQUEST, MSG_RANGE_LENGTH_MISMATCH));
+        }
+        
+        if let Some(error_response) = self.save_version(context, path).await {
+            return Some(error_response);
+        }
+        
+        let write_result = self.write_file_range(context, path, range.start, body_bytes).await;
+        match write_result {
+            Ok(updated) => {
+                context.log_verbose(&format!(
+                    "[FileHandler] Patched bytes {}-{} of {} (now {} bytes)",
+                    range.start, range.end, request.path, updated.len()
+                ));
                 Some(Response::builder()
-                    .status(StatusCode::NOT_FOUND)
-                    .header("Content-Type", CONTENT_TYPE_PLAIN)
+                    .status(StatusCode::NO_CONTENT)
+                    .header("ETag", Self::file_etag(&updated))
                     .body(Body::empty())
                     .unwrap())
             }
+            Err(e) => {
+                Some(self.create_error_response(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    &format!("{}: {}", MSG_FAILED_PATCH_FILE, e)
+                ))
+            }
         }
     }
     
+    /// Checks an If-Match header value against the current ETag
+    fn if_match_satisfied(if_match: &str, current_etag: &str) -> bool {
+        if_match.split(',')
+            .map(|tag| tag.trim())
+            .any(|tag| tag == IF_MATCH_ANY || tag == current_etag)
+    }
+    
+    /// Parses `bytes <start>-<end>/<length|*>`, rejecting inconsistent ranges
+    fn parse_content_range(value: &str) -> Option<ByteRange> {
+        let spec = value.trim().strip_prefix(BYTES_RANGE_UNIT)?.trim_start();
+        let (range, complete_length) = spec.split_once('/')?;
+        let (start, end) = range.split_once('-')?;
+        let start: u64 = start.trim().parse().ok()?;
+        let end: u64 = end.trim().parse().ok()?;
+        let complete_length = match complete_length.trim() {
+            "*" => None,
+            length => Some(length.parse::<u64>().ok()?),
+        };
+        
+        // The range's length, end - start + 1, must be representable
+        if end < start || end.checked_add(1).is_none() || complete_length.is_some_and(|length| end >= length) {
+            return None;
+        }
+        Some(ByteRange { start, end, complete_length })
+    }
+    
+    /// Writes content at an offset of an existing file without truncating it,
+    /// and returns the metadata of the updated file
+    async fn write_file_range(&self, context: &PluginContext, path: &Path, offset: u64, content: hyper::body::Bytes) -> Result<std::fs::Metadata, std::io::Error> {
+        use std::io::{Seek, SeekFrom, Write};
+        
+        let path = path.to_path_buf();
+        fs::blocking(context, move || {
+            let mut file = std::fs::OpenOptions::new()
+                .write(true)
+                .open(&path)?;
+            file.seek(SeekFrom::Start(offset))?;
+            file.write_all(&content)?;
+            file.flush()?;
+            file.metadata()
+        }).await
+    }
+    
     /// Handles DELETE requests to remove files
     async fn handle_delete(&self, request: &PluginRequest, context: &PluginContext) -> Option<Response<Body>> {
-        let file_path = self.build_file_path(context, &request.path);
-        let path = Path::new(&file_path);
+        let path = match self.resolve_file_path(request, context) {
+            Ok(path) => path,
+            Err(error_response) => return Some(error_response),
+        };
+        let path = path.as_path();
         
-        // Validate path security
-        if let Err(error_response) = self.validate_path_security(context, path) {
+        if let Some(error_response) = self.save_version(context, path).await {
             return Some(error_response);
         }
         
-        match fs::remove_file(path) {
+        match fs::remove_file(context, path).await {
             Ok(_) => {
                 Some(Response::builder()
                     .status(StatusCode::NO_CONTENT)
@@ -368,26 +1651,207 @@ impl FileHandlerPlugin {
             }
         }
     }
+    
+    /// Handles COPY and MOVE requests to duplicate or rename a file
+    async fn handle_copy_or_move(&self, request: &PluginRequest, context: &PluginContext, is_move: bool) -> Option<Response<Body>> {
+        let source = match self.resolve_file_path(request, context) {
+            Ok(path) => path,
+            Err(error_response) => return Some(error_response),
+        };
+        let (destination_path, destination) = match self.resolve_destination(request, context) {
+            Ok(destination) => destination,
+            Err(error_response) => return Some(error_response),
+        };
+        if destination == source {
+            return Some(self.create_error_response(StatusCode::FORBIDDEN, MSG_SAME_DESTINATION));
+        }
+        
+        // The request itself was authorized on the source path only
+        let user = request.get_metadata("authenticated_user");
+        if let Some(services) = &context.services {
+            let source_method = if is_move { "DELETE" } else { "GET" };
+            if !services.is_allowed(user, "PUT", &destination_path, context)
+                || !services.is_allowed(user, source_method, &request.path, context) {
+                return Some(self.create_error_response(StatusCode::FORBIDDEN, MSG_ACCESS_DENIED));
+            }
+        }
+        
+        match fs::metadata(context, &source).await {
+            Ok(metadata) if metadata.is_file() => {}
+            Ok(_) => return Some(self.create_error_response(StatusCode::FORBIDDEN, MSG_SOURCE_NOT_FILE)),
+            Err(_) => return Some(self.create_error_response(StatusCode::NOT_FOUND, MSG_FILE_NOT_FOUND)),
+        }
+        
+        let destination_existed = match fs::metadata(context, &destination).await {
+            Ok(metadata) if metadata.is_file() => true,
+            Ok(_) => return Some(self.create_error_response(StatusCode::FORBIDDEN, MSG_ACCESS_DENIED)),
+            Err(_) => false,
+        };
+        if destination_existed && !Self::overwrite_allowed(request) {
+            return Some(self.create_error_response(StatusCode::PRECONDITION_FAILED, MSG_DESTINATION_EXISTS));
+        }
+        
+        if destination_existed {
+            if let Some(error_response) = self.save_version(context, &destination).await {
+                return Some(error_response);
+            }
+        }
+        if is_move {
+            if let Some(error_response) = self.save_version(context, &source).await {
+                return Some(error_response);
+            }
+        }
+        
+        let (from, to) = (source.clone(), destination.clone());
+        let result = if is_move {
+            fs::blocking(context, move || Self::move_file(&from, &to)).await
+        } else {
+            fs::blocking(context, move || Self::copy_file(&from, &to)).await
+        };
+        if let Err(e) = result {
+            let message = if is_move { MSG_FAILED_MOVE_FILE } else { MSG_FAILED_COPY_FILE };
+            return Some(self.create_error_response(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                &format!("{}: {}", message, e)
+            ));
+        }
+        
+        context.log_verbose(&format!(
+            "[FileHandler] {} {} to {}",
+            if is_move { "Moved" } else { "Copied" }, request.path, destination_path
+        ));
+        
+        // RFC 4918: 201 when the destination was created, 204 when it was replaced
+        Some(if destination_existed {
+            Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .body(Body::empty())
+                .unwrap()
+        } else {
+            Response::builder()
+                .status(StatusCode::CREATED)
+                .header("Location", Self::encode_path(&destination_path))
+                .body(Body::empty())
+                .unwrap()
+        })
+    }
+    
+    /// Resolves the Destination header of a COPY or MOVE request to the
+    /// decoded request path and the file it addresses below the host root
+    #[allow(clippy::result_large_err)]
+    fn resolve_destination(&self, request: &PluginRequest, context: &PluginContext) -> Result<(String, PathBuf), Response<Body>> {
+        let headers = request.http_request.headers();
+        let value = headers.get(HEADER_DESTINATION)
+            .ok_or_else(|| self.create_error_response(StatusCode::BAD_REQUEST, MSG_DESTINATION_REQUIRED))?;
+        let uri: hyper::Uri = value.to_str().ok()
+            .and_then(|value| value.trim().parse().ok())
+            .ok_or_else(|| self.create_error_response(StatusCode::BAD_REQUEST, MSG_INVALID_DESTINATION))?;
+        
+        // A URL must name the host the request was sent to; ports are not
+        // compared, as hosts are matched by name
+        if let Some(authority) = uri.authority() {
+            let host = headers.get(hyper::header::HOST)
+                .and_then(|host| host.to_str().ok())
+                .map(|host| host.split(':').next().unwrap_or_default());
+            if host.is_some_and(|host| !host.eq_ignore_ascii_case(authority.host())) {
+                return Err(self.create_error_response(StatusCode::BAD_GATEWAY, MSG_DESTINATION_OTHER_HOST));
+            }
+        }
+        
+        // Normalized as the server normalizes request paths, so rules see the same spelling
+        let destination_path = paths::normalize_request_path(uri.path())
+            .ok()
+            .ok_or_else(|| self.create_error_response(StatusCode::BAD_REQUEST, MSG_INVALID_DESTINATION))?;
+        
+        // History is only changed through the version query parameters
+        if self.versions.as_ref().is_some_and(|versions| versions.is_history_path(&destination_path)) {
+            return Err(self.create_error_response(StatusCode::FORBIDDEN, MSG_ACCESS_DENIED));
+        }
+        if self.deny.denies(context, &destination_path) {
+            return Err(self.create_error_response(StatusCode::NOT_FOUND, MSG_FILE_NOT_FOUND));
+        }
+        
+        let root = context.host_config.get(CONFIG_KEY_HOST_ROOT).unwrap_or(&self.root_dir);
+        let destination = paths::resolve(Path::new(root), &destination_path, paths::SymlinkPolicy::from_context(context))
+            .map_err(|_| self.create_error_response(StatusCode::FORBIDDEN, MSG_ACCESS_DENIED))?;
+        Ok((destination_path, destination))
+    }
+    
+    /// Whether a COPY or MOVE may replace an existing file; `Overwrite: T` is the default
+    fn overwrite_allowed(request: &PluginRequest) -> bool {
+        request.http_request.headers()
+            .get(HEADER_OVERWRITE)
+            .and_then(|v| v.to_str().ok())
+            .is_none_or(|overwrite| !overwrite.trim().eq_ignore_ascii_case("F"))
+    }
+    
+    /// Percent-encodes a decoded request path for a Location header
+    fn encode_path(path: &str) -> String {
+        path.split('/')
+            .map(|segment| urlencoding::encode(segment).into_owned())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+    
+    /// Copies a file, creating the destination's parent directories as needed
+    fn copy_file(source: &Path, destination: &Path) -> Result<(), std::io::Error> {
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        
+        std::fs::copy(source, destination).map(|_| ())
+    }
+    
+    /// Renames a file, copying it when the destination is on another filesystem
+    fn move_file(source: &Path, destination: &Path) -> Result<(), std::io::Error> {
+        if let Some(parent) = destination.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        
+        if std::fs::rename(source, destination).is_ok() {
+            return Ok(());
+        }
+        std::fs::copy(source, destination)?;
+        std::fs::remove_file(source)
+    }
 }
 
 #[async_trait]
 impl Plugin for FileHandlerPlugin {
     async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
+        // History is only reachable through the version query parameters
+        if self.versions.as_ref().is_some_and(|versions| versions.is_history_path(&request.path)) {
+            return Some(self.create_error_response(StatusCode::NOT_FOUND, MSG_FILE_NOT_FOUND).into());
+        }
+        
+        // Denied paths look missing, whatever the method
+        if self.deny.denies(context, &request.path) {
+            context.log_verbose(&format!("[FileHandler] Denied path {}", request.path));
+            return Some(self.create_error_response(StatusCode::NOT_FOUND, MSG_FILE_NOT_FOUND).into());
+        }
+        
+        if self.digests.is_blob_path(&request.path) {
+            return Some(self.serve_blob(request, context).await.into());
+        }
+        
         match *request.http_request.method() {
             Method::GET => self.handle_get(request, context).await.map(|r| r.into()),
             Method::HEAD => self.handle_head(request, context).await.map(|r| r.into()),
             Method::PUT => self.handle_put(request, context).await.map(|r| r.into()),
             Method::POST => self.handle_post(request, context).await.map(|r| r.into()),
             Method::DELETE => self.handle_delete(request, context).await.map(|r| r.into()),
+            Method::PATCH => self.handle_patch(request, context).await.map(|r| r.into()),
             Method::OPTIONS => {
                 Some(Response::builder()
                     .status(StatusCode::OK)
-                    .header("Allow", ALLOWED_METHODS)
+                    .header("Allow", self.allowed_methods(request, context))
                     .header("Accept-Ranges", ACCEPT_RANGES)
                     .body(Body::empty())
                     .unwrap()
                     .into())
             }
+            ref method if method.as_str() == METHOD_COPY => self.handle_copy_or_move(request, context, false).await.map(|r| r.into()),
+            ref method if method.as_str() == METHOD_MOVE => self.handle_copy_or_move(request, context, true).await.map(|r| r.into()),
             _ => {
                 Some(self.create_error_response(
                     StatusCode::METHOD_NOT_ALLOWED,
@@ -402,5 +1866,391 @@ impl Plugin for FileHandlerPlugin {
     }
 }
 
-// Export the plugin creation function
-create_plugin!(FileHandlerPlugin);
\ No newline at end of file
+/// Escapes text for inclusion in HTML
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Export the plugin creation function; tests link the directory plugin,
+// which exports the same symbols
+#[cfg(not(test))]
+rusty_beam_plugin_api::create_plugin!(FileHandlerPlugin);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusty_beam_plugin_api::PipelineServices;
+    use std::sync::Arc;
+
+    /// An access-control stand-in that lets everyone GET everything but
+    /// withholds salaries on staff pages from everyone except carol
+    #[derive(Debug)]
+    struct Redactor;
+
+    #[async_trait]
+    impl Plugin for Redactor {
+        fn withheld_selectors(&self, user: Option<&str>, path: &str, _context: &PluginContext) -> Vec<String> {
+            if path.starts_with("/staff/") && user != Some("carol") {
+                vec![".salary".to_string()]
+            } else {
+                Vec::new()
+            }
+        }
+
+        fn name(&self) -> &str {
+            "redactor"
+        }
+    }
+
+    fn create_test_root(name: &str) -> std::path::PathBuf {
+        let root = std::env::temp_dir().join(format!("rusty-beam-file-handler-{}-{}", name, std::process::id()));
+        std::fs::create_dir_all(root.join("staff")).unwrap();
+        std::fs::write(root.join("staff/bob.html"), r#"<p>Bob</p><p class="salary">100</p>"#).unwrap();
+        std::fs::write(root.join("staff/photo.png"), b"\x89PNG\r\n\x1a\nbob").unwrap();
+        root
+    }
+
+    fn create_test_context(root: &Path) -> PluginContext {
+        PluginContext {
+            plugin_config: HashMap::new(),
+            server_config: HashMap::new(),
+            server_metadata: HashMap::new(),
+            host_config: HashMap::from([(CONFIG_KEY_HOST_ROOT.to_string(), root.to_string_lossy().to_string())]),
+            host_name: "test-host".to_string(),
+            request_id: "test-request".to_string(),
+            runtime_handle: None,
+            verbose: false,
+            services: Some(Arc::new(PipelineServices::new(vec![Arc::new(Redactor)]))),
+            deadline: None,
+        }
+    }
+
+    fn create_test_request(method: &str, uri: &str, user: &str) -> PluginRequest {
+        let http_request = hyper::Request::builder().method(method).uri(uri).body(Body::empty()).unwrap();
+        let path = uri.split('?').next().unwrap_or(uri).to_string();
+        let mut request = PluginRequest::new(http_request, path);
+        request.metadata.insert("authenticated_user".to_string(), user.to_string());
+        request
+    }
+
+    async fn respond(plugin: &FileHandlerPlugin, request: &mut PluginRequest, context: &PluginContext) -> (StatusCode, Vec<u8>) {
+        let response = plugin.handle_request(request, context).await.unwrap().response;
+        let status = response.status();
+        (status, hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec())
+    }
+
+    #[tokio::test]
+    async fn test_blobs_of_redacted_files_are_refused() {
+        let root = create_test_root("blobs");
+        let context = create_test_context(&root);
+        let plugin = FileHandlerPlugin::new(HashMap::from([("contentDigests".to_string(), "true".to_string())]));
+        let blob = |contents: &[u8]| format!("/.well-known/blobs/{}", digests::hex(&digests::hash_bytes(contents)));
+        let page = blob(br#"<p>Bob</p><p class="salary">100</p>"#);
+
+        let (status, _) = respond(&plugin, &mut create_test_request("GET", &page, "bob"), &context).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, body) = respond(&plugin, &mut create_test_request("GET", &page, "carol"), &context).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(String::from_utf8(body).unwrap().contains("salary"));
+        // Files that are not redacted are served to everyone
+        let (status, _) = respond(&plugin, &mut create_test_request("GET", &blob(b"\x89PNG\r\n\x1a\nbob"), "bob"), &context).await;
+        assert_eq!(status, StatusCode::OK);
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[tokio::test]
+    async fn test_blobs_are_looked_up_by_known_digests() {
+        let root = create_test_root("blob-index");
+        let context = create_test_context(&root);
+        let plugin = FileHandlerPlugin::new(HashMap::from([("contentDigests".to_string(), "true".to_string())]));
+        let blob = |contents: &[u8]| format!("/.well-known/blobs/{}", digests::hex(&digests::hash_bytes(contents)));
+
+        // The first lookup searches the root, and remembers every digest it saw
+        let (status, _) = respond(&plugin, &mut create_test_request("GET", &blob(b"missing"), "carol"), &context).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+        let (status, _) = respond(&plugin, &mut create_test_request("GET", &blob(b"\x89PNG\r\n\x1a\nbob"), "carol"), &context).await;
+        assert_eq!(status, StatusCode::OK);
+
+        // A changed file no longer has its old digest
+        std::fs::write(root.join("staff/photo.png"), b"\x89PNG\r\n\x1a\nnew").unwrap();
+        let (status, _) = respond(&plugin, &mut create_test_request("GET", &blob(b"\x89PNG\r\n\x1a\nbob"), "carol"), &context).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // Files served through the plugin are known by their digest at once
+        let (status, _) = respond(&plugin, &mut create_test_request("GET", "/staff/photo.png", "carol"), &context).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, body) = respond(&plugin, &mut create_test_request("GET", &blob(b"\x89PNG\r\n\x1a\nnew"), "carol"), &context).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, b"\x89PNG\r\n\x1a\nnew");
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[tokio::test]
+    async fn test_archives_leave_out_redacted_files() {
+        let root = create_test_root("archives");
+        let context = create_test_context(&root);
+        let plugin = FileHandlerPlugin::new(HashMap::new());
+
+        let (status, body) = respond(&plugin, &mut create_test_request("GET", "/staff/?format=zip", "bob"), &context).await;
+        assert_eq!(status, StatusCode::OK);
+        let listing = String::from_utf8_lossy(&body);
+        assert!(listing.contains("photo.png"));
+        assert!(!listing.contains("bob.html"));
+        assert!(!listing.contains("salary"));
+
+        let (_, body) = respond(&plugin, &mut create_test_request("GET", "/staff/?format=zip", "carol"), &context).await;
+        assert!(String::from_utf8_lossy(&body).contains("bob.html"));
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[tokio::test]
+    async fn test_archives_leave_out_files_redacted_in_a_directory() {
+        let root = create_test_root("nested-archives");
+        let staff = directory::DirectoryPlugin::new_with_nested_plugins(
+            HashMap::from([("directory".to_string(), "/staff".to_string())]),
+            vec![Arc::new(Redactor)],
+        );
+        let context = PluginContext {
+            services: Some(Arc::new(PipelineServices::new(vec![Arc::new(staff)]))),
+            ..create_test_context(&root)
+        };
+        let plugin = FileHandlerPlugin::new(HashMap::new());
+
+        let (status, body) = respond(&plugin, &mut create_test_request("GET", "/staff/?format=zip", "bob"), &context).await;
+        assert_eq!(status, StatusCode::OK);
+        let listing = String::from_utf8_lossy(&body);
+        assert!(listing.contains("photo.png"));
+        assert!(!listing.contains("bob.html"));
+        assert!(!listing.contains("salary"));
+
+        let (_, body) = respond(&plugin, &mut create_test_request("GET", "/staff/?format=zip", "carol"), &context).await;
+        assert!(String::from_utf8_lossy(&body).contains("bob.html"));
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    fn create_upload_request(files: &[(&str, &str)]) -> PluginRequest {
+        let mut body = String::new();
+        for (name, content) in files {
+            body.push_str(&format!(
+                "--BOUNDARY\r\nContent-Disposition: form-data; name=\"file\"; filename=\"{}\"\r\nContent-Type: text/plain\r\n\r\n{}\r\n",
+                name, content,
+            ));
+        }
+        body.push_str("--BOUNDARY--\r\n");
+        let http_request = hyper::Request::builder()
+            .method("POST")
+            .uri("/uploads/")
+            .header("Content-Type", "multipart/form-data; boundary=BOUNDARY")
+            .header("Accept", "application/json")
+            .body(Body::from(body))
+            .unwrap();
+        PluginRequest::new(http_request, "/uploads/".to_string())
+    }
+
+    #[tokio::test]
+    async fn test_upload_limits() {
+        let root = create_test_root("upload-limits");
+        std::fs::create_dir_all(root.join("uploads")).unwrap();
+        let context = create_test_context(&root);
+        let plugin = FileHandlerPlugin::new(HashMap::from([
+            (CONFIG_KEY_UPLOAD_MAX_FILES.to_string(), "2".to_string()),
+            (CONFIG_KEY_UPLOAD_MAX_TOTAL_SIZE.to_string(), "1024".to_string()),
+        ]));
+
+        let (status, _) = respond(&plugin, &mut create_upload_request(&[("a.txt", "a"), ("b.txt", "b"), ("c.txt", "c")]), &context).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        let large = "x".repeat(600);
+        let (status, _) = respond(&plugin, &mut create_upload_request(&[("a.txt", &large), ("b.txt", &large)]), &context).await;
+        assert_eq!(status, StatusCode::PAYLOAD_TOO_LARGE);
+        // Rejected uploads leave nothing behind
+        assert_eq!(std::fs::read_dir(root.join("uploads")).unwrap().count(), 0);
+
+        let (status, _) = respond(&plugin, &mut create_upload_request(&[("a.txt", "a"), ("b.txt", "b")]), &context).await;
+        assert_eq!(status, StatusCode::CREATED);
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_uploads_of_a_name_keep_every_file() {
+        let root = create_test_root("upload-names");
+        std::fs::create_dir_all(root.join("uploads")).unwrap();
+        let context = create_test_context(&root);
+        let plugin = FileHandlerPlugin::new(HashMap::new());
+
+        let mut first = create_upload_request(&[("notes.txt", "first")]);
+        let mut second = create_upload_request(&[("notes.txt", "second")]);
+        let mut third = create_upload_request(&[("notes.txt", "third")]);
+        let ((first, _), (second, _), (third, _)) = tokio::join!(
+            respond(&plugin, &mut first, &context),
+            respond(&plugin, &mut second, &context),
+            respond(&plugin, &mut third, &context),
+        );
+        assert_eq!([first, second, third], [StatusCode::CREATED; 3]);
+
+        let mut contents: Vec<String> = ["notes.txt", "notes-1.txt", "notes-2.txt"].iter()
+            .map(|name| std::fs::read_to_string(root.join("uploads").join(name)).unwrap())
+            .collect();
+        contents.sort();
+        assert_eq!(contents, ["first", "second", "third"]);
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    fn create_patch_request(etag: &str, content_range: &str, body: &'static str) -> PluginRequest {
+        let http_request = hyper::Request::builder()
+            .method("PATCH")
+            .uri("/notes.txt")
+            .header("If-Match", etag)
+            .header("Content-Range", content_range)
+            .body(Body::from(body))
+            .unwrap();
+        PluginRequest::new(http_request, "/notes.txt".to_string())
+    }
+
+    #[test]
+    fn test_content_ranges_too_long_to_count_are_refused() {
+        assert!(FileHandlerPlugin::parse_content_range(&format!("bytes 0-{}/*", u64::MAX)).is_none());
+        assert!(FileHandlerPlugin::parse_content_range(&format!("bytes {}-{}/*", u64::MAX, u64::MAX)).is_none());
+        assert!(FileHandlerPlugin::parse_content_range(&format!("bytes 0-{}/*", u64::MAX - 1)).is_some());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_patches_check_what_they_change() {
+        let root = create_test_root("patches");
+        std::fs::write(root.join("notes.txt"), "hello").unwrap();
+        let context = create_test_context(&root);
+        let plugin = FileHandlerPlugin::new(HashMap::new());
+        let etag = FileHandlerPlugin::file_etag(&std::fs::metadata(root.join("notes.txt")).unwrap());
+
+        let (overflowing, _) = respond(&plugin, &mut create_patch_request(&etag, &format!("bytes 0-{}/*", u64::MAX), ""), &context).await;
+        assert_eq!(overflowing, StatusCode::BAD_REQUEST);
+
+        // Both were sent having seen the same version, so only one applies
+        let mut first = create_patch_request(&etag, "bytes 5-10/11", " world");
+        let mut second = create_patch_request(&etag, "bytes 5-10/11", " there");
+        let ((first, _), (second, _)) = tokio::join!(
+            respond(&plugin, &mut first, &context),
+            respond(&plugin, &mut second, &context),
+        );
+        let mut statuses = [first, second];
+        statuses.sort();
+        assert_eq!(statuses, [StatusCode::NO_CONTENT, StatusCode::PRECONDITION_FAILED]);
+        let content = std::fs::read_to_string(root.join("notes.txt")).unwrap();
+        assert!(content == "hello world" || content == "hello there");
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+    fn create_put_request(uri: &str, body: &'static str) -> PluginRequest {
+        let http_request = hyper::Request::builder().method("PUT").uri(uri).body(Body::from(body)).unwrap();
+        PluginRequest::new(http_request, uri.to_string())
+    }
+
+    #[tokio::test]
+    async fn test_overwritten_and_deleted_files_keep_their_versions() {
+        let root = create_test_root("versions");
+        let context = create_test_context(&root);
+        let plugin = FileHandlerPlugin::new(HashMap::from([
+            ("versioning".to_string(), "true".to_string()),
+            ("versionsMaxCount".to_string(), "2".to_string()),
+        ]));
+
+        for content in ["one", "two", "three", "four"] {
+            let (status, _) = respond(&plugin, &mut create_put_request("/page.html", content), &context).await;
+            assert!(status.is_success());
+        }
+        // Only the newest two of the three saved versions are kept
+        let (status, listing) = respond(&plugin, &mut create_test_request("GET", "/page.html?version=list", "alice"), &context).await;
+        assert_eq!(status, StatusCode::OK);
+        let listing: serde_json::Value = serde_json::from_slice(&listing).unwrap();
+        assert_eq!(listing["path"], "/page.html");
+        let numbers: Vec<u64> = listing["versions"].as_array().unwrap().iter()
+            .map(|version| version["version"].as_u64().unwrap())
+            .collect();
+        assert_eq!(numbers, [3, 2]);
+        assert_eq!(
+            respond(&plugin, &mut create_test_request("GET", "/page.html?version=2", "alice"), &context).await,
+            (StatusCode::OK, b"two".to_vec())
+        );
+        let (status, _) = respond(&plugin, &mut create_test_request("GET", "/page.html?version=1", "alice"), &context).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        // The history outlives the file and brings it back
+        let (status, _) = respond(&plugin, &mut create_test_request("DELETE", "/page.html", "alice"), &context).await;
+        assert!(status.is_success());
+        assert!(!root.join("page.html").exists());
+        assert_eq!(
+            respond(&plugin, &mut create_test_request("GET", "/page.html?version=4", "alice"), &context).await,
+            (StatusCode::OK, b"four".to_vec())
+        );
+        let (status, _) = respond(&plugin, &mut create_test_request("POST", "/page.html?restore=3", "alice"), &context).await;
+        assert!(status.is_success());
+        assert_eq!(std::fs::read_to_string(root.join("page.html")).unwrap(), "three");
+
+        // The history directory itself is never served
+        let (status, _) = respond(&plugin, &mut create_test_request("GET", "/.versions/page.html/4", "alice"), &context).await;
+        assert!(status.is_client_error());
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+
+    #[tokio::test]
+    async fn test_precompressed_variants_are_served_to_clients_that_accept_them() {
+        let root = create_test_root("precompressed");
+        let context = create_test_context(&root);
+        let plugin = FileHandlerPlugin::new(HashMap::new());
+        std::fs::write(root.join("styles.css"), "body {}").unwrap();
+        std::fs::write(root.join("styles.css.br"), "brotli").unwrap();
+        std::fs::write(root.join("styles.css.gz"), "gzip").unwrap();
+
+        let get = |accept_encoding: Option<&str>| {
+            let mut builder = hyper::Request::builder().method("GET").uri("/styles.css");
+            if let Some(accept_encoding) = accept_encoding {
+                builder = builder.header(hyper::header::ACCEPT_ENCODING, accept_encoding);
+            }
+            PluginRequest::new(builder.body(Body::empty()).unwrap(), "/styles.css".to_string())
+        };
+        let serve = |mut request: PluginRequest| {
+            let plugin = &plugin;
+            let context = &context;
+            async move {
+                let response = plugin.handle_request(&mut request, context).await.unwrap().response;
+                let encoding = response.headers().get("Content-Encoding").map(|value| value.to_str().unwrap().to_string());
+                let vary = response.headers().get("Vary").map(|value| value.to_str().unwrap().to_string());
+                let body = hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec();
+                (encoding, vary, String::from_utf8(body).unwrap())
+            }
+        };
+        let vary = Some("Accept-Encoding".to_string());
+
+        // Brotli is preferred, gzip is next, and q=0 refuses a coding
+        assert_eq!(serve(get(Some("gzip, br"))).await, (Some("br".to_string()), vary.clone(), "brotli".to_string()));
+        assert_eq!(serve(get(Some("gzip, br;q=0"))).await, (Some("gzip".to_string()), vary.clone(), "gzip".to_string()));
+        // Clients that accept neither get the file itself, still varying
+        assert_eq!(serve(get(Some("identity"))).await, (None, vary.clone(), "body {}".to_string()));
+        assert_eq!(serve(get(None)).await, (None, vary.clone(), "body {}".to_string()));
+
+        // A file without variants doesn't vary
+        std::fs::write(root.join("plain.css"), "p {}").unwrap();
+        let request = PluginRequest::new(
+            hyper::Request::builder().uri("/plain.css").header(hyper::header::ACCEPT_ENCODING, "br").body(Body::empty()).unwrap(),
+            "/plain.css".to_string(),
+        );
+        assert_eq!(serve(request).await, (None, None, "p {}".to_string()));
+
+        // Turned off, variants are ignored
+        let plugin = FileHandlerPlugin::new(HashMap::from([("precompressed".to_string(), "false".to_string())]));
+        let mut request = get(Some("br"));
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert!(response.headers().get("Content-Encoding").is_none());
+        assert!(response.headers().get("Vary").is_none());
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+}
@@ -0,0 +1,133 @@
+//! Precompressed variants of static files
+//!
+//! A file such as `styles.css` may have `styles.css.br` and `styles.css.gz`
+//! next to it, made when the site is built. A GET or HEAD of the file is then
+//! answered with the variant the client's `Accept-Encoding` accepts, Brotli
+//! before gzip, and the matching `Content-Encoding`, so nothing has to be
+//! compressed per request; the compression plugin leaves responses that are
+//! already encoded alone. A variant modified before the file itself is stale
+//! and ignored. Responses for a file with variants carry
+//! `Vary: Accept-Encoding` whichever representation they hold, so caches keep
+//! the representations apart, and a variant's ETag is its own with the coding
+//! appended, so it never matches the ETag of another representation.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const CONFIG_KEY_PRECOMPRESSED: &str = "precompressed";
+
+/// Content codings with the extension of their variants, in order of preference
+const ENCODINGS: &[(&str, &str)] = &[("br", "br"), ("gzip", "gz")];
+/// Older name of gzip that clients may still send
+const CODING_X_GZIP: &str = "x-gzip";
+const CODING_ANY: &str = "*";
+
+/// Whether precompressed variants are looked for
+#[derive(Debug)]
+pub struct PrecompressedSettings {
+    pub enabled: bool,
+}
+
+impl PrecompressedSettings {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        let enabled = config.get(CONFIG_KEY_PRECOMPRESSED)
+            .map(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "yes" | "1"))
+            .unwrap_or(true);
+        Self { enabled }
+    }
+}
+
+/// A precompressed variant of a file
+#[derive(Debug)]
+pub struct Variant {
+    pub path: PathBuf,
+    /// Content coding of the variant, for `Content-Encoding`
+    pub coding: &'static str,
+    pub metadata: fs::Metadata,
+}
+
+/// The variants of a file found for a request
+#[derive(Debug, Default)]
+pub struct Variants {
+    /// Whether the file has any current variant, so responses vary on
+    /// `Accept-Encoding`
+    pub exist: bool,
+    /// The variant to send, if the client accepts one
+    pub chosen: Option<Variant>,
+}
+
+/// Looks for current variants of a file, choosing the one to send a client
+/// with an `Accept-Encoding` header, if it sent one
+pub fn find(path: &Path, file: &fs::Metadata, accept_encoding: Option<&str>) -> Variants {
+    let mut variants = Variants::default();
+    let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+        return variants;
+    };
+    for (coding, extension) in ENCODINGS {
+        let variant_path = path.with_file_name(format!("{}.{}", file_name, extension));
+        let Ok(metadata) = fs::metadata(&variant_path) else { continue };
+        let stale = match (metadata.modified(), file.modified()) {
+            (Ok(variant_modified), Ok(file_modified)) => variant_modified < file_modified,
+            _ => false,
+        };
+        if !metadata.is_file() || stale {
+            continue;
+        }
+        variants.exist = true;
+        if variants.chosen.is_none() && accept_encoding.is_some_and(|accept| accepts(accept, coding)) {
+            variants.chosen = Some(Variant { path: variant_path, coding, metadata });
+        }
+    }
+    variants
+}
+
+/// Whether an `Accept-Encoding` header accepts a content coding, by name or
+/// through `*`, with a non-zero quality
+pub fn accepts(accept_encoding: &str, coding: &str) -> bool {
+    let mut any = false;
+    for range in accept_encoding.split(',') {
+        let mut parts = range.split(';');
+        let name = parts.next().unwrap_or_default().trim().to_ascii_lowercase();
+        let quality = parts
+            .find_map(|param| param.trim().strip_prefix("q=").and_then(|q| q.trim().parse::<f32>().ok()))
+            .unwrap_or(1.0);
+        if name == coding || (coding == "gzip" && name == CODING_X_GZIP) {
+            return quality > 0.0;
+        }
+        if name == CODING_ANY {
+            any = quality > 0.0;
+        }
+    }
+    any
+}
+
+/// The ETag of a variant: the ETag of its file with the coding appended
+pub fn etag(file_etag: &str, coding: &str) -> String {
+    format!("{}-{}\"", file_etag.trim_end_matches('"'), coding)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_accepts() {
+        assert!(accepts("gzip, br", "br"));
+        assert!(accepts("GZIP", "gzip"));
+        assert!(accepts("x-gzip", "gzip"));
+        assert!(accepts("*", "br"));
+        assert!(accepts("br;q=0.5", "br"));
+        assert!(!accepts("br;q=0", "br"));
+        assert!(!accepts("*, br;q=0", "br"));
+        assert!(!accepts("*;q=0", "gzip"));
+        assert!(!accepts("deflate", "gzip"));
+        assert!(!accepts("", "br"));
+    }
+
+    #[test]
+    fn test_variant_etags_differ_from_the_file_etag() {
+        assert_eq!(etag("\"abc\"", "br"), "\"abc-br\"");
+        assert_ne!(etag("\"abc\"", "br"), etag("\"abc\"", "gzip"));
+    }
+}
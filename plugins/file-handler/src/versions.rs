@@ -0,0 +1,171 @@
+//! Version history for files overwritten or deleted through the file handler
+//!
+//! Before a file is replaced, patched or deleted its current content is
+//! copied into a history directory under the host root, mirroring the file's
+//! path: the history of `/docs/page.html` lives in
+//! `<root>/.versions/docs/page.html/`. Each version is a file named after its
+//! number, which increases per file, and its modification time records when
+//! it was saved. The oldest versions are pruned once a file's history exceeds
+//! its count or byte quota.
+
+use chrono::{DateTime, SecondsFormat, Utc};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+// Configuration keys
+const CONFIG_KEY_VERSIONING: &str = "versioning";
+const CONFIG_KEY_VERSIONS_DIRECTORY: &str = "versionsDirectory";
+const CONFIG_KEY_VERSIONS_MAX_COUNT: &str = "versionsMaxCount";
+const CONFIG_KEY_VERSIONS_MAX_BYTES: &str = "versionsMaxBytes";
+
+// Defaults
+const DEFAULT_VERSIONS_DIRECTORY: &str = ".versions";
+const DEFAULT_VERSIONS_MAX_COUNT: usize = 10;
+
+/// A stored version of a file
+#[derive(Debug)]
+pub struct Version {
+    pub number: u64,
+    pub saved_at: DateTime<Utc>,
+    pub size: u64,
+    pub path: PathBuf,
+}
+
+impl Version {
+    /// Describes the version for a history listing
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "version": self.number,
+            "saved": self.saved_at.to_rfc3339_opts(SecondsFormat::Millis, true),
+            "size": self.size,
+        })
+    }
+}
+
+/// Where and how much history is kept
+#[derive(Debug, Clone)]
+pub struct VersionStore {
+    directory: String,
+    max_count: usize,
+    /// Total size allowed for one file's history; zero means unlimited
+    max_bytes: u64,
+}
+
+impl VersionStore {
+    /// Reads the versioning settings, or None if versioning is disabled
+    pub fn from_config(config: &HashMap<String, String>) -> Option<Self> {
+        let enabled = config.get(CONFIG_KEY_VERSIONING)
+            .map(|v| matches!(v.trim().to_lowercase().as_str(), "true" | "yes" | "1"))
+            .unwrap_or(false);
+        if !enabled {
+            return None;
+        }
+
+        let directory = config.get(CONFIG_KEY_VERSIONS_DIRECTORY)
+            .map(|v| v.trim().trim_matches('/').to_string())
+            .filter(|v| !v.is_empty() && !v.contains('/') && v != "." && v != "..")
+            .unwrap_or_else(|| DEFAULT_VERSIONS_DIRECTORY.to_string());
+        let max_count = config.get(CONFIG_KEY_VERSIONS_MAX_COUNT)
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|&count: &usize| count > 0)
+            .unwrap_or(DEFAULT_VERSIONS_MAX_COUNT);
+        let max_bytes = config.get(CONFIG_KEY_VERSIONS_MAX_BYTES)
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(0);
+
+        Some(Self { directory, max_count, max_bytes })
+    }
+
+    /// Whether a request path points into the history directory itself
+    pub fn is_history_path(&self, request_path: &str) -> bool {
+        request_path.trim_start_matches('/').split('/').next() == Some(self.directory.as_str())
+    }
+
+    /// Directory holding the versions of a file, given its path relative to the root
+    fn history_directory(&self, root: &Path, relative: &Path) -> PathBuf {
+        root.join(&self.directory).join(relative)
+    }
+
+    /// Copies the current content of a file into its history
+    ///
+    /// Returns the new version number, or None if there was no file to save.
+    pub fn save(&self, root: &Path, relative: &Path, current: &Path) -> io::Result<Option<u64>> {
+        if !current.is_file() {
+            return Ok(None);
+        }
+        let history = self.history_directory(root, relative);
+        fs::create_dir_all(&history)?;
+
+        let mut number = self.list(root, relative)?.first().map(|latest| latest.number + 1).unwrap_or(1);
+        // Claim the number atomically, so concurrent saves never share one
+        let mut target = loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(history.join(number.to_string()))
+            {
+                Ok(file) => break file,
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => number += 1,
+                Err(e) => return Err(e),
+            }
+        };
+        io::copy(&mut fs::File::open(current)?, &mut target)?;
+
+        self.prune(root, relative)?;
+        Ok(Some(number))
+    }
+
+    /// Lists the versions of a file, newest first
+    pub fn list(&self, root: &Path, relative: &Path) -> io::Result<Vec<Version>> {
+        let entries = match fs::read_dir(self.history_directory(root, relative)) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        let mut versions: Vec<Version> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let number = entry.file_name().to_str()?.parse().ok()?;
+                let metadata = entry.metadata().ok().filter(|metadata| metadata.is_file())?;
+                Some(Version {
+                    number,
+                    saved_at: metadata.modified().ok()?.into(),
+                    size: metadata.len(),
+                    path: entry.path(),
+                })
+            })
+            .collect();
+        versions.sort_by_key(|version| std::cmp::Reverse(version.number));
+        Ok(versions)
+    }
+
+    /// Finds one version of a file
+    pub fn get(&self, root: &Path, relative: &Path, number: u64) -> io::Result<Option<Version>> {
+        Ok(self.list(root, relative)?.into_iter().find(|version| version.number == number))
+    }
+
+    /// Reads the content of one version of a file
+    pub fn read(&self, root: &Path, relative: &Path, number: u64) -> io::Result<Option<Vec<u8>>> {
+        match self.get(root, relative, number)? {
+            Some(version) => fs::read(&version.path).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Removes the oldest versions beyond the quotas, always keeping the newest
+    fn prune(&self, root: &Path, relative: &Path) -> io::Result<()> {
+        let versions = self.list(root, relative)?;
+        let mut total = 0;
+        for (index, version) in versions.iter().enumerate() {
+            total += version.size;
+            let over_quota = index >= self.max_count || (self.max_bytes > 0 && total > self.max_bytes);
+            if index > 0 && over_quota {
+                fs::remove_file(&version.path)?;
+            }
+        }
+        Ok(())
+    }
+}
@@ -10,6 +10,10 @@
 //! - **Health Checks**: Comprehensive system health assessment
 //! - **Disk Space Monitoring**: Configurable disk space thresholds
 //! - **Document Root Validation**: Ensures critical paths are accessible
+//! - **Server Load**: Reports open connections and requests in flight, and
+//!   is degraded while the server is at its limits
+//! - **Plugin Failures**: Reports plugins that panicked, and is degraded
+//!   while one is disabled
 //! - **Detailed Diagnostics**: Optional verbose health information
 //!
 //! ## Standard Endpoints
@@ -25,6 +29,18 @@
 //! - `check_disk_space`: Enable disk space monitoring (default: true)
 //! - `min_disk_space_mb`: Minimum required disk space in MB (default: 100)
 //!
+//! ## Server Load
+//! Readiness reports the connections the server holds open and the requests
+//! it is processing against its `maxConnections` and `maxInFlightRequests`
+//! limits. At either limit the server is degraded, as it pauses accepting
+//! connections or turns requests away with a 503. Detailed responses carry
+//! the counts as a `load` object.
+//!
+//! ## Plugin Failures
+//! Readiness names every plugin of the host that has panicked, with how
+//! often. While a plugin is disabled after `panicLimit` panics in a row the
+//! server is degraded, as requests reaching that plugin get a 503.
+//!
 //! ## Health Status Levels
 //! - **Healthy**: All systems operational (HTTP 200)
 //! - **Degraded**: Partial functionality, still serving (HTTP 200)
@@ -34,7 +50,7 @@
 //! Compatible with Kubernetes probes, load balancer health checks,
 //! and monitoring systems like Prometheus, Consul, and AWS ALB.
 
-use rusty_beam_plugin_api::{Plugin, PluginRequest, PluginContext, PluginResponse, create_plugin};
+use rusty_beam_plugin_api::{Plugin, PluginRequest, PluginContext, PluginResponse, ServerLoad, create_plugin};
 use async_trait::async_trait;
 use hyper::{Body, Response, StatusCode, Method};
 use std::collections::HashMap;
@@ -135,6 +151,28 @@ impl HealthCheckPlugin {
             }
         }
         
+        if let Some(load) = context.server_load() {
+            let limit = |max: Option<usize>| max.map_or_else(|| "unlimited".to_string(), |max| max.to_string());
+            messages.push(format!(
+                "Server load: {} of {} connections, {} of {} requests in flight",
+                load.connections, limit(load.max_connections),
+                load.in_flight_requests, limit(load.max_in_flight_requests),
+            ));
+            if load.is_saturated() {
+                messages.push("Server is at its connection or request limit".to_string());
+                status = HealthStatus::Degraded;
+            }
+        }
+        
+        for failures in context.plugin_failures().iter().filter(|failures| failures.panics > 0) {
+            if failures.disabled {
+                messages.push(format!("Plugin {} is disabled after {} panics in a row", failures.plugin, failures.consecutive_panics));
+                status = HealthStatus::Degraded;
+            } else {
+                messages.push(format!("Plugin {} has panicked {} times", failures.plugin, failures.panics));
+            }
+        }
+        
         // Check disk space if enabled
         if self.check_disk_space {
             match self.check_disk_space_internal() {
@@ -200,10 +238,10 @@ impl HealthCheckPlugin {
     }
     
     /// Create health check response with proper error handling
-    fn create_health_response(&self, status: HealthStatus, messages: Vec<String>) -> Response<Body> {
+    fn create_health_response(&self, status: HealthStatus, messages: Vec<String>, load: Option<ServerLoad>) -> Response<Body> {
         let status_code = Self::health_status_to_http_code(&status);
         let status_text = Self::health_status_to_text(&status);
-        let response_body = self.create_response_body(status_text, messages);
+        let response_body = self.create_response_body(status_text, messages, load);
         
         Response::builder()
             .status(status_code)
@@ -239,9 +277,9 @@ impl HealthCheckPlugin {
     }
     
     /// Create JSON response body with optional detailed information
-    fn create_response_body(&self, status_text: &str, messages: Vec<String>) -> String {
+    fn create_response_body(&self, status_text: &str, messages: Vec<String>, load: Option<ServerLoad>) -> String {
         let response_json = if self.detailed_checks {
-            self.create_detailed_response_body(status_text, messages)
+            self.create_detailed_response_body(status_text, messages, load)
         } else {
             self.create_simple_response_body(status_text)
         };
@@ -250,14 +288,24 @@ impl HealthCheckPlugin {
     }
     
     /// Create detailed response body with checks and timestamp
-    fn create_detailed_response_body(&self, status_text: &str, messages: Vec<String>) -> serde_json::Value {
+    fn create_detailed_response_body(&self, status_text: &str, messages: Vec<String>, load: Option<ServerLoad>) -> serde_json::Value {
         let timestamp = self.get_current_timestamp();
         
-        serde_json::json!({
+        let mut body = serde_json::json!({
             "status": status_text,
             "checks": messages,
             "timestamp": timestamp
-        })
+        });
+        if let Some(load) = load {
+            body["load"] = serde_json::json!({
+                "connections": load.connections,
+                "max_connections": load.max_connections,
+                "in_flight_requests": load.in_flight_requests,
+                "max_in_flight_requests": load.max_in_flight_requests,
+                "rejected_requests": load.rejected_requests,
+            });
+        }
+        body
     }
     
     /// Create simple response body with just status
@@ -312,7 +360,7 @@ impl Plugin for HealthCheckPlugin {
             _ => return None,
         };
         
-        Some(self.create_health_response(status, messages).into())
+        Some(self.create_health_response(status, messages, context.server_load()).into())
     }
     
     async fn handle_response(&self, _request: &PluginRequest, _response: &mut Response<Body>, _context: &PluginContext) {
@@ -26,7 +26,7 @@ use dom_query::Document;
 use http::{Method, StatusCode};
 use hyper::{Body, Response};
 use rusty_beam_plugin_api::{
-    create_plugin, Plugin, PluginContext, PluginRequest, PluginResponse,
+    create_plugin, transform_body, Plugin, PluginContext, PluginRequest, PluginResponse,
 };
 use std::collections::HashMap;
 
@@ -37,7 +37,6 @@ const DEFAULT_INDENT: &str = "  ";
 // Content-Type detection
 const CONTENT_TYPE_HEADER: &str = "content-type";
 const CONTENT_TYPE_HTML: &str = "text/html";
-const CONTENT_LENGTH_HEADER: &str = "content-length";
 
 // HTML parsing constants
 const DOCTYPE_PREFIX: &str = "<!DOCTYPE";
@@ -314,61 +313,46 @@ impl Plugin for HtmlPrettifierPlugin {
             return;
         }
 
-        // Extract the body
-        let body = std::mem::replace(response.body_mut(), Body::empty());
-        
-        // Convert body to bytes
-        let body_bytes = match hyper::body::to_bytes(body).await {
-            Ok(bytes) => bytes,
-            Err(e) => {
-                context.log_verbose(&format!(
-                    "html-prettifier: Failed to read response body: {}",
-                    e
-                ));
-                return;
-            }
-        };
+        // Prettify the body in place; the original is restored on failure
+        let result = transform_body(response, |body_bytes| {
+            let html_str = match std::str::from_utf8(body_bytes) {
+                Ok(s) => s,
+                Err(e) => {
+                    context.log_verbose(&format!(
+                        "html-prettifier: Response body is not valid UTF-8: {}",
+                        e
+                    ));
+                    return None;
+                }
+            };
 
-        // Convert to string
-        let html_str = match String::from_utf8(body_bytes.to_vec()) {
-            Ok(s) => s,
-            Err(e) => {
-                context.log_verbose(&format!(
-                    "html-prettifier: Response body is not valid UTF-8: {}",
-                    e
-                ));
-                *response.body_mut() = Body::from(body_bytes);
-                return;
+            match self.prettify_html(html_str) {
+                Ok(prettified) => {
+                    context.log_verbose(&format!(
+                        "html-prettifier: Prettified HTML response for {} {} ({}B -> {}B)",
+                        request.http_request.method(),
+                        request.http_request.uri().path(),
+                        html_str.len(),
+                        prettified.len()
+                    ));
+                    Some(prettified)
+                }
+                Err(e) => {
+                    context.log_verbose(&format!(
+                        "html-prettifier: Failed to prettify HTML: {}",
+                        e
+                    ));
+                    None
+                }
             }
-        };
+        })
+        .await;
 
-        // Prettify the HTML
-        match self.prettify_html(&html_str) {
-            Ok(prettified) => {
-                context.log_verbose(&format!(
-                    "html-prettifier: Prettified HTML response for {} {} ({}B -> {}B)",
-                    request.http_request.method(),
-                    request.http_request.uri().path(),
-                    html_str.len(),
-                    prettified.len()
-                ));
-                
-                // Update content-length header
-                response.headers_mut().insert(
-                    CONTENT_LENGTH_HEADER,
-                    prettified.len().to_string().parse().unwrap(),
-                );
-                
-                *response.body_mut() = Body::from(prettified);
-            }
-            Err(e) => {
-                context.log_verbose(&format!(
-                    "html-prettifier: Failed to prettify HTML: {}",
-                    e
-                ));
-                // Restore original body
-                *response.body_mut() = Body::from(body_bytes);
-            }
+        if let Err(e) = result {
+            context.log_verbose(&format!(
+                "html-prettifier: Failed to read response body: {}",
+                e
+            ));
         }
     }
 
@@ -0,0 +1,349 @@
+//! Durable key-value storage for scripts
+//!
+//! Each script gets a namespace of its own, named after its path below the
+//! scripts directory, so scripts cannot read or overwrite each other's data.
+//! A namespace is kept as one JSON file in the store directory and held in
+//! memory once read. The store directory must be configured, so the store is
+//! never kept under a host root where it could be served. Every change
+//! rewrites the file through a temporary file of its own, flushed to disk
+//! before a rename, so a crash leaves either the old or the new content, and
+//! changes to one namespace are made one at a time, so a compare-and-swap
+//! never races another write.
+
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Bytes of keys and JSON values a namespace may hold
+const DEFAULT_MAX_BYTES: usize = 1024 * 1024;
+/// Keys a namespace may hold
+const DEFAULT_MAX_KEYS: usize = 10_000;
+const NAMESPACE_EXTENSION: &str = ".json";
+const TEMPORARY_EXTENSION: &str = ".tmp";
+
+/// Distinguishes the temporary files of writes made by this process
+static TEMPORARY_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+const CONFIG_KEY_DIRECTORY: &str = "javascript_engine_kv_dir";
+const CONFIG_KEY_MAX_BYTES: &str = "javascript_engine_kv_max_bytes";
+const CONFIG_KEY_MAX_KEYS: &str = "javascript_engine_kv_max_keys";
+
+/// A failed store operation, with the HTTP status that best describes it
+#[derive(Debug)]
+pub(crate) struct KvError {
+    pub(crate) status: u16,
+    pub(crate) message: String,
+}
+
+impl KvError {
+    fn new(status: u16, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+}
+
+/// The namespaces of every script of a host
+#[derive(Debug)]
+pub(crate) struct KvStore {
+    /// Directory holding the namespace files, or None if the store is not configured
+    directory: Option<PathBuf>,
+    max_bytes: usize,
+    max_keys: usize,
+    /// Namespaces read so far, by file
+    namespaces: Mutex<HashMap<PathBuf, BTreeMap<String, Value>>>,
+}
+
+impl KvStore {
+    pub(crate) fn from_config(config: &HashMap<String, String>) -> Self {
+        let limit = |key: &str, default: usize| config
+            .get(key)
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(default);
+        Self {
+            directory: config
+                .get(CONFIG_KEY_DIRECTORY)
+                .map(|directory| PathBuf::from(directory.strip_prefix("file://").unwrap_or(directory))),
+            max_bytes: limit(CONFIG_KEY_MAX_BYTES, DEFAULT_MAX_BYTES),
+            max_keys: limit(CONFIG_KEY_MAX_KEYS, DEFAULT_MAX_KEYS),
+            namespaces: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The file holding a script's namespace, or None if no store directory
+    /// is configured
+    pub(crate) fn namespace_file(&self, script: &str) -> Option<PathBuf> {
+        let directory = self.directory.as_ref()?;
+        Some(directory.join(format!("{}{}", encode_namespace(script), NAMESPACE_EXTENSION)))
+    }
+
+    pub(crate) fn get(&self, file: &Path, key: &str) -> Result<Option<Value>, KvError> {
+        self.with_namespace(file, |entries| Ok((entries.get(key).cloned(), false)))
+    }
+
+    pub(crate) fn set(&self, file: &Path, key: &str, value: Value) -> Result<(), KvError> {
+        self.with_namespace(file, |entries| {
+            entries.insert(key.to_string(), value);
+            Ok(((), true))
+        })
+    }
+
+    /// Removes a key, returning whether it was there
+    pub(crate) fn delete(&self, file: &Path, key: &str) -> Result<bool, KvError> {
+        self.with_namespace(file, |entries| {
+            let existed = entries.remove(key).is_some();
+            Ok((existed, existed))
+        })
+    }
+
+    /// The keys starting with a prefix, in order
+    pub(crate) fn list(&self, file: &Path, prefix: &str) -> Result<Vec<String>, KvError> {
+        self.with_namespace(file, |entries| {
+            let keys = entries.keys()
+                .filter(|key| key.starts_with(prefix))
+                .cloned()
+                .collect();
+            Ok((keys, false))
+        })
+    }
+
+    /// Sets a key to `value`, or removes it for None, only if it holds
+    /// `expected`, where None means the key must be absent
+    ///
+    /// Returns whether the swap was made.
+    pub(crate) fn compare_and_swap(
+        &self,
+        file: &Path,
+        key: &str,
+        expected: Option<&Value>,
+        value: Option<Value>,
+    ) -> Result<bool, KvError> {
+        self.with_namespace(file, |entries| {
+            if entries.get(key) != expected {
+                return Ok((false, false));
+            }
+            match value {
+                Some(value) => entries.insert(key.to_string(), value),
+                None => entries.remove(key),
+            };
+            Ok((true, true))
+        })
+    }
+
+    /// Runs an operation on a namespace, which reports its result and whether
+    /// it changed the entries, saving a change if it stays within the quotas
+    fn with_namespace<T>(
+        &self,
+        file: &Path,
+        operation: impl FnOnce(&mut BTreeMap<String, Value>) -> Result<(T, bool), KvError>,
+    ) -> Result<T, KvError> {
+        let mut namespaces = self.namespaces.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+        if !namespaces.contains_key(file) {
+            let entries = read_namespace(file)?;
+            namespaces.insert(file.to_path_buf(), entries);
+        }
+        let entries = namespaces.get_mut(file).expect("namespace was just read");
+
+        let mut changed_entries = entries.clone();
+        let (result, changed) = operation(&mut changed_entries)?;
+        if !changed {
+            return Ok(result);
+        }
+
+        if changed_entries.len() > self.max_keys {
+            return Err(KvError::new(507, format!("the store is limited to {} keys", self.max_keys)));
+        }
+        let bytes: usize = changed_entries.iter()
+            .map(|(key, value)| key.len() + value.to_string().len())
+            .sum();
+        if bytes > self.max_bytes {
+            return Err(KvError::new(507, format!("the store is limited to {} bytes", self.max_bytes)));
+        }
+
+        write_namespace(file, &changed_entries)?;
+        *entries = changed_entries;
+        Ok(result)
+    }
+}
+
+/// Reads a namespace file, which may not exist yet
+fn read_namespace(file: &Path) -> Result<BTreeMap<String, Value>, KvError> {
+    match std::fs::read_to_string(file) {
+        Ok(content) => serde_json::from_str(&content)
+            .map_err(|e| KvError::new(500, format!("the store {} is corrupt: {}", file.display(), e))),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(KvError::new(500, format!("the store {} cannot be read: {}", file.display(), e))),
+    }
+}
+
+/// Replaces a namespace file with new entries in one step
+///
+/// The entries go to a temporary file no other writer uses, which is flushed
+/// to disk before it is renamed over the namespace file, and removed if any
+/// step fails.
+fn write_namespace(file: &Path, entries: &BTreeMap<String, Value>) -> Result<(), KvError> {
+    let failed = |e: std::io::Error| KvError::new(500, format!("the store {} cannot be written: {}", file.display(), e));
+    if let Some(directory) = file.parent() {
+        std::fs::create_dir_all(directory).map_err(failed)?;
+    }
+    let content = serde_json::to_string(entries)
+        .map_err(|e| KvError::new(500, format!("the store cannot be serialized: {}", e)))?;
+    let temporary = file.with_file_name(format!(
+        "{}.{}-{}{}",
+        file.file_name().and_then(|name| name.to_str()).unwrap_or_default(),
+        std::process::id(),
+        TEMPORARY_COUNTER.fetch_add(1, Ordering::Relaxed),
+        TEMPORARY_EXTENSION
+    ));
+
+    let written = std::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&temporary)
+        .and_then(|mut temporary| {
+            temporary.write_all(content.as_bytes())?;
+            temporary.sync_all()
+        })
+        .and_then(|_| std::fs::rename(&temporary, file));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&temporary);
+        return Err(failed(e));
+    }
+    // Make the rename itself durable
+    if let Some(directory) = file.parent() {
+        if let Ok(directory) = std::fs::File::open(directory) {
+            let _ = directory.sync_all();
+        }
+    }
+    Ok(())
+}
+
+/// A file name for a script's namespace, with every character other than
+/// letters, digits, `.`, `-` and `_` percent-encoded so distinct scripts get
+/// distinct files
+fn encode_namespace(script: &str) -> String {
+    let mut encoded = String::with_capacity(script.len());
+    for byte in script.trim_start_matches('/').bytes() {
+        if byte.is_ascii_alphanumeric() || matches!(byte, b'.' | b'-' | b'_') {
+            encoded.push(byte as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn create_test_store(name: &str, max_bytes: usize, max_keys: usize) -> (KvStore, PathBuf) {
+        let directory = std::env::temp_dir().join(format!("kv-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        let config = HashMap::from([
+            (CONFIG_KEY_DIRECTORY.to_string(), directory.to_string_lossy().to_string()),
+            (CONFIG_KEY_MAX_BYTES.to_string(), max_bytes.to_string()),
+            (CONFIG_KEY_MAX_KEYS.to_string(), max_keys.to_string()),
+        ]);
+        (KvStore::from_config(&config), directory)
+    }
+
+    #[test]
+    fn test_store_needs_a_configured_directory() {
+        let store = KvStore::from_config(&HashMap::new());
+        assert!(store.namespace_file("/counter.js").is_none());
+    }
+
+    #[test]
+    fn test_namespaces_are_kept_apart() {
+        let (store, directory) = create_test_store("namespaces", DEFAULT_MAX_BYTES, DEFAULT_MAX_KEYS);
+        let counter = store.namespace_file("/counter.js").unwrap();
+        let nested = store.namespace_file("/counter/js").unwrap();
+        assert_ne!(counter, nested);
+        assert_eq!(counter.parent(), Some(directory.as_path()));
+
+        store.set(&counter, "visits", json!(1)).unwrap();
+        assert_eq!(store.get(&nested, "visits").unwrap(), None);
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[test]
+    fn test_get_set_delete_and_list() {
+        let (store, directory) = create_test_store("operations", DEFAULT_MAX_BYTES, DEFAULT_MAX_KEYS);
+        let file = store.namespace_file("/script.js").unwrap();
+
+        assert_eq!(store.get(&file, "user:alice").unwrap(), None);
+        store.set(&file, "user:alice", json!({"visits": 1})).unwrap();
+        store.set(&file, "user:bob", json!([1, 2])).unwrap();
+        store.set(&file, "total", json!(2)).unwrap();
+        assert_eq!(store.get(&file, "user:alice").unwrap(), Some(json!({"visits": 1})));
+        assert_eq!(store.list(&file, "user:").unwrap(), vec!["user:alice", "user:bob"]);
+        assert_eq!(store.list(&file, "").unwrap(), vec!["total", "user:alice", "user:bob"]);
+
+        assert!(store.delete(&file, "user:bob").unwrap());
+        assert!(!store.delete(&file, "user:bob").unwrap());
+        assert_eq!(store.list(&file, "user:").unwrap(), vec!["user:alice"]);
+
+        // A fresh store reads what the first one wrote
+        let reopened = KvStore::from_config(&HashMap::from([
+            (CONFIG_KEY_DIRECTORY.to_string(), directory.to_string_lossy().to_string()),
+        ]));
+        assert_eq!(reopened.get(&file, "total").unwrap(), Some(json!(2)));
+        assert_eq!(reopened.get(&file, "user:bob").unwrap(), None);
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[test]
+    fn test_writes_leave_no_temporary_files() {
+        let (store, directory) = create_test_store("temporary", DEFAULT_MAX_BYTES, DEFAULT_MAX_KEYS);
+        let file = store.namespace_file("/script.js").unwrap();
+        store.set(&file, "a", json!(1)).unwrap();
+        store.set(&file, "b", json!(2)).unwrap();
+
+        let names: Vec<_> = std::fs::read_dir(&directory).unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        assert_eq!(names, vec!["script.js.json"]);
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[test]
+    fn test_quotas() {
+        let (store, directory) = create_test_store("quotas", 20, 2);
+        let file = store.namespace_file("/script.js").unwrap();
+
+        store.set(&file, "a", json!(1)).unwrap();
+        store.set(&file, "b", json!(2)).unwrap();
+        let error = store.set(&file, "c", json!(3)).unwrap_err();
+        assert_eq!(error.status, 507);
+        // Replacing a key does not add one
+        store.set(&file, "b", json!(3)).unwrap();
+
+        let error = store.set(&file, "b", json!("a value far too long")).unwrap_err();
+        assert_eq!(error.status, 507);
+        // A refused change leaves the namespace as it was
+        assert_eq!(store.get(&file, "b").unwrap(), Some(json!(3)));
+        assert_eq!(store.get(&file, "c").unwrap(), None);
+        let _ = std::fs::remove_dir_all(directory);
+    }
+
+    #[test]
+    fn test_compare_and_swap() {
+        let (store, directory) = create_test_store("swap", DEFAULT_MAX_BYTES, DEFAULT_MAX_KEYS);
+        let file = store.namespace_file("/script.js").unwrap();
+
+        // An absent key is expected as None
+        assert!(store.compare_and_swap(&file, "visits", None, Some(json!(1))).unwrap());
+        assert!(!store.compare_and_swap(&file, "visits", None, Some(json!(5))).unwrap());
+        assert!(!store.compare_and_swap(&file, "visits", Some(&json!(0)), Some(json!(5))).unwrap());
+        assert!(store.compare_and_swap(&file, "visits", Some(&json!(1)), Some(json!(2))).unwrap());
+        assert_eq!(store.get(&file, "visits").unwrap(), Some(json!(2)));
+
+        // A swap to None removes the key
+        assert!(store.compare_and_swap(&file, "visits", Some(&json!(2)), None).unwrap());
+        assert_eq!(store.get(&file, "visits").unwrap(), None);
+        let _ = std::fs::remove_dir_all(directory);
+    }
+}
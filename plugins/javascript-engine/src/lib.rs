@@ -7,31 +7,159 @@
 //! - Server-side JavaScript execution using V8
 //! - ES6 module syntax transformation
 //! - Request/response object bridging
-//! - Script caching for performance
-//! - Route-based script mapping
+//! - Script caching, invalidated when a script's modification time changes
+//! - Route-based script mapping, from config or a per-host routes manifest
 //! - JavaScript console API (console.log, console.error)
+//! - Document API for reading and changing HTML documents by selector
 //! - Asynchronous JavaScript support
+//!
+//! ## Configuration
+//! - `javascript_engine_scripts_dir`: Directory containing `.mjs` scripts (default: "./scripts")
+//! - `javascript_engine_routes`: Routes manifest file (default: `routes.json` in the scripts directory)
+//! - `javascript_engine_route_<pattern>`: Legacy route mapping with `/` encoded as `_`
+//! - `javascript_engine_workers`: Number of threads running scripts (default: one per CPU)
+//!
+//! ## Routes Manifest
+//! The manifest maps path patterns to scripts and is re-read whenever it changes,
+//! so routes can be edited without restarting the server. Routes are matched in
+//! file order and take precedence over config route mappings. Patterns ending in
+//! `*` match by prefix. JSON manifests look like:
+//! ```json
+//! {"routes": [{"pattern": "/api/*", "script": "api.mjs"}]}
+//! ```
+//! Manifests with an `.html` extension are read as microdata instead, using
+//! `https://rustybeam.net/schema/JavaScriptRoute` items with `pattern` and
+//! `script` properties.
+//!
+//! ## Document API
+//! Scripts read and change documents of the host through a global `document`
+//! object, with the same selector semantics as HTTP clients get from the
+//! selector handler:
+//! - `document.get(path, selector)` returns the HTML of the matching elements
+//! - `document.replace(path, selector, html)` replaces them and returns the result
+//! - `document.append(path, selector, html)` appends to them and returns the result
+//! - `document.remove(path, selector)` removes them
+//!
+//! Operations act on behalf of the authenticated user of the request, so the
+//! authorization rules for the equivalent `GET`, `PUT`, `POST` and `DELETE`
+//! requests apply. A failed operation throws an `Error` whose `status` is the
+//! HTTP status the equivalent request would have received.
+//!
+//! ## Key-Value Storage
+//! Scripts keep small durable state, such as counters or session data,
+//! through a global `kv` object rather than writing files:
+//! - `kv.get(key)` returns the value stored under a key, or `undefined`
+//! - `kv.set(key, value)` stores any JSON-serializable value
+//! - `kv.delete(key)` removes a key and returns whether it was there
+//! - `kv.list(prefix)` returns the keys starting with `prefix`, in order
+//! - `kv.compareAndSwap(key, expected, value)` stores `value` only if the key
+//!   holds `expected`, and returns whether it did; an `expected` of
+//!   `undefined` means the key must be absent, and a `value` of `undefined`
+//!   removes the key
+//!
+//! Each script has a namespace of its own, stored as a JSON file in
+//! `javascript_engine_kv_dir`. Without that directory `kv` throws an `Error`
+//! with `status` 501, since a store under the host root could be served. A
+//! change that would take a namespace over its quota throws an `Error` with
+//! `status` 507.
+//! - `javascript_engine_kv_dir`: Directory of the store, outside any host root (required for `kv`)
+//! - `javascript_engine_kv_max_bytes`: Bytes of keys and JSON values per script (default: 1048576)
+//! - `javascript_engine_kv_max_keys`: Keys per script (default: 10000)
+//!
+//! ## Worker Threads
+//! Scripts run on a pool of worker threads rather than the server's runtime,
+//! so a long script holds up no other request. Requests wait for a free worker
+//! when every worker is busy. A script whose client disconnects is skipped if
+//! it has not started yet, and terminated otherwise.
+//!
+//! ## Request Deadlines
+//! Scripts run without yielding to the server, so the request deadline can
+//! only stop them from inside. A script still running when the deadline
+//! passes is terminated and the request is answered with a 504.
 
 use async_trait::async_trait;
 use hyper::{Body, Response, StatusCode};
 use once_cell::sync::OnceCell;
+use rusty_beam_plugin_api::deadline::{self, Elapsed};
 use rusty_beam_plugin_api::{
-    create_plugin, Plugin, PluginContext, PluginRequest, PluginResponse,
+    create_plugin, Plugin, PluginContext, PluginRequest, PluginResponse, SelectorError,
+    SelectorOperation,
 };
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
 use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 use tokio::sync::RwLock;
+use kv::KvStore;
+use workers::{Cancellation, WorkerPool};
+
+mod kv;
+mod workers;
 
 static V8_INITIALIZED: OnceCell<()> = OnceCell::new();
 
+const SCHEMA_JAVASCRIPT_ROUTE: &str = "https://rustybeam.net/schema/JavaScriptRoute";
+const DEFAULT_ROUTES_MANIFEST: &str = "routes.json";
+
 #[derive(Debug)]
 pub struct JavaScriptEnginePlugin {
     name: String,
     scripts_dir: Arc<RwLock<PathBuf>>,
     route_mappings: Arc<RwLock<HashMap<String, String>>>,
-    script_cache: Arc<RwLock<HashMap<String, String>>>,
+    /// Explicitly configured routes manifest; otherwise `routes.json` in the scripts dir
+    routes_manifest: Option<PathBuf>,
+    manifest_routes: Arc<RwLock<ManifestRoutes>>,
+    script_cache: Arc<RwLock<HashMap<String, CachedScript>>>,
+    /// Threads running scripts, started with the first script
+    workers: OnceCell<WorkerPool>,
+    worker_count: usize,
+    /// Durable storage behind the `kv` object of scripts
+    kv: Arc<KvStore>,
+}
+
+/// Script source along with the modification time it was read at
+#[derive(Debug, Clone)]
+struct CachedScript {
+    content: String,
+    modified: Option<SystemTime>,
+}
+
+/// Routes loaded from the manifest, in file order
+#[derive(Debug, Default)]
+struct ManifestRoutes {
+    routes: Vec<ManifestRoute>,
+    modified: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct ManifestRoute {
+    pattern: String,
+    script: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct RoutesManifest {
+    routes: Vec<ManifestRoute>,
+}
+
+/// A script to run on a worker, with everything it needs from its request
+struct ScriptRun {
+    script: String,
+    request_json: String,
+    host: Arc<ScriptHost>,
+    timeout: Option<Duration>,
+}
+
+/// What the document and key-value APIs need from the request a script
+/// runs for, kept in an isolate slot so host functions can reach it
+struct ScriptHost {
+    context: PluginContext,
+    user: Option<String>,
+    kv: Arc<KvStore>,
+    /// File of the script's key-value namespace, if the store has a place
+    kv_file: Option<PathBuf>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -62,7 +190,18 @@ impl JavaScriptEnginePlugin {
             .map(|s| PathBuf::from(s))
             .unwrap_or_else(|| PathBuf::from("./scripts"));
         
-        let plugin = Self::new_with_dir("javascript-engine".to_string(), scripts_dir);
+        let mut plugin = Self::new_with_dir("javascript-engine".to_string(), scripts_dir);
+        plugin.routes_manifest = config
+            .get("javascript_engine_routes")
+            .map(|s| PathBuf::from(s.strip_prefix("file://").unwrap_or(s)));
+        if let Some(workers) = config
+            .get("javascript_engine_workers")
+            .and_then(|s| s.trim().parse::<usize>().ok())
+            .filter(|workers| *workers > 0)
+        {
+            plugin.worker_count = workers;
+        }
+        plugin.kv = Arc::new(KvStore::from_config(&config));
         
         // Load route mappings from config
         // Format: javascript_engine_route_/api/*=api.js
@@ -90,49 +229,166 @@ impl JavaScriptEnginePlugin {
             name,
             scripts_dir: Arc::new(RwLock::new(scripts_dir)),
             route_mappings: Arc::new(RwLock::new(HashMap::new())),
+            routes_manifest: None,
+            manifest_routes: Arc::new(RwLock::new(ManifestRoutes::default())),
             script_cache: Arc::new(RwLock::new(HashMap::new())),
+            workers: OnceCell::new(),
+            worker_count: std::thread::available_parallelism()
+                .map(|count| count.get())
+                .unwrap_or(4),
+            kv: Arc::new(KvStore::from_config(&HashMap::new())),
         }
     }
 
+    /// Loads a script, re-reading it if the file changed since it was cached
     async fn load_script(&self, script_path: &str) -> Result<String, anyhow::Error> {
-        let cache = self.script_cache.read().await;
-        if let Some(cached) = cache.get(script_path) {
-            return Ok(cached.clone());
+        let full_path = self.scripts_dir.read().await.join(script_path);
+        let modified = tokio::fs::metadata(&full_path).await?.modified().ok();
+
+        if let Some(cached) = self.script_cache.read().await.get(script_path) {
+            if cached.modified == modified {
+                return Ok(cached.content.clone());
+            }
         }
-        drop(cache);
 
-        let scripts_dir = self.scripts_dir.read().await;
-        let full_path = scripts_dir.join(script_path);
         let content = tokio::fs::read_to_string(&full_path).await?;
 
         let mut cache = self.script_cache.write().await;
-        cache.insert(script_path.to_string(), content.clone());
+        cache.insert(
+            script_path.to_string(),
+            CachedScript { content: content.clone(), modified },
+        );
         Ok(content)
     }
 
-    /// Executes JavaScript code with the provided request context
+    /// Path of the routes manifest for this host
+    async fn routes_manifest_path(&self) -> PathBuf {
+        match &self.routes_manifest {
+            Some(path) => path.clone(),
+            None => self.scripts_dir.read().await.join(DEFAULT_ROUTES_MANIFEST),
+        }
+    }
+
+    /// Re-reads the routes manifest if its modification time changed
+    async fn refresh_manifest_routes(&self, context: &PluginContext) {
+        let manifest_path = self.routes_manifest_path().await;
+        let modified = tokio::fs::metadata(&manifest_path)
+            .await
+            .ok()
+            .and_then(|m| m.modified().ok());
+
+        if self.manifest_routes.read().await.modified == modified {
+            return;
+        }
+
+        let mut manifest_routes = self.manifest_routes.write().await;
+        if manifest_routes.modified == modified {
+            return; // Another request reloaded it first
+        }
+
+        // A missing manifest simply means there are no manifest routes
+        let routes = match modified {
+            Some(_) => match self.parse_routes_manifest(&manifest_path).await {
+                Ok(routes) => routes,
+                Err(e) => {
+                    // Keep the previous routes until the manifest is fixed
+                    context.log_error(&format!(
+                        "[JavaScript] Failed to load routes manifest {:?}: {}",
+                        manifest_path, e
+                    ));
+                    manifest_routes.modified = modified;
+                    return;
+                }
+            },
+            None => Vec::new(),
+        };
+
+        context.log_verbose(&format!(
+            "[JavaScript] Loaded {} route(s) from {:?}",
+            routes.len(),
+            manifest_path
+        ));
+        manifest_routes.routes = routes;
+        manifest_routes.modified = modified;
+    }
+
+    /// Parses a JSON or microdata HTML routes manifest
+    async fn parse_routes_manifest(&self, path: &Path) -> Result<Vec<ManifestRoute>, anyhow::Error> {
+        let content = tokio::fs::read_to_string(path).await?;
+        let is_html = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.eq_ignore_ascii_case("html") || ext.eq_ignore_ascii_case("htm"))
+            .unwrap_or(false);
+
+        if !is_html {
+            let manifest: RoutesManifest = serde_json::from_str(&content)?;
+            return Ok(manifest.routes);
+        }
+
+        let items = microdata_extract::MicrodataExtractor::new()
+            .extract(&content)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        Ok(items
+            .iter()
+            .filter(|item| item.item_type() == Some(SCHEMA_JAVASCRIPT_ROUTE))
+            .filter_map(|item| {
+                Some(ManifestRoute {
+                    pattern: item.get_property("pattern")?.trim().to_string(),
+                    script: item.get_property("script")?.trim().to_string(),
+                })
+            })
+            .collect())
+    }
+
+    /// Executes JavaScript code with the provided request context on a worker thread
     async fn execute_javascript(
         &self,
+        script_file: &str,
         script_content: &str,
         request: &PluginRequest,
+        plugin_context: &PluginContext,
     ) -> Result<Option<JsResponse>, anyhow::Error> {
         let js_request = self.create_js_request(request).await;
-        let request_json = serde_json::to_string(&js_request)?;
+        let run = ScriptRun {
+            script: script_content.to_string(),
+            request_json: serde_json::to_string(&js_request)?,
+            host: Arc::new(ScriptHost {
+                context: plugin_context.clone(),
+                user: request.get_metadata("authenticated_user").map(|user| user.to_string()),
+                kv: Arc::clone(&self.kv),
+                kv_file: self.kv.namespace_file(script_file),
+            }),
+            timeout: plugin_context.remaining_time(),
+        };
+
+        let workers = self.workers.get_or_try_init(|| {
+            plugin_context.log_verbose(&format!("[JavaScript] Starting {} worker thread(s)", self.worker_count));
+            WorkerPool::new(self.worker_count)
+        })?;
+        workers.run(move |cancellation| Self::run_script(run, cancellation)).await?
+    }
 
+    /// Runs a script in an isolate of its own, on the calling thread
+    fn run_script(run: ScriptRun, cancellation: &Cancellation) -> Result<Option<JsResponse>, anyhow::Error> {
         // Set up V8 execution context
         let isolate = &mut v8::Isolate::new(Default::default());
+        cancellation.watch(isolate.thread_safe_handle());
+        let _watchdog = run.timeout
+            .map(|remaining| Watchdog::arm(isolate.thread_safe_handle(), remaining));
+        isolate.set_slot(run.host);
         let handle_scope = &mut v8::HandleScope::new(isolate);
         let context = v8::Context::new(handle_scope, Default::default());
         let scope = &mut v8::ContextScope::new(handle_scope, context);
 
         // Set up global JavaScript functions (console, setTimeout, etc.)
-        self.setup_global_functions(scope)?;
+        Self::setup_global_functions(scope)?;
         
         // Set up the request object in global scope
         let global = context.global(scope);
         let request_key = v8::String::new(scope, "request").unwrap();
-        let request_str = v8::String::new(scope, &request_json).unwrap();
-        let json_obj = self.get_json_object(scope);
+        let request_str = v8::String::new(scope, &run.request_json).unwrap();
+        let json_obj = Self::get_json_object(scope);
         let parse_key = v8::String::new(scope, "parse").unwrap();
         let parse_fn = json_obj.get(scope, parse_key.into()).unwrap();
         let parse_fn = v8::Local::<v8::Function>::try_from(parse_fn).unwrap();
@@ -141,7 +397,7 @@ impl JavaScriptEnginePlugin {
         global.set(scope, request_key.into(), request_value);
         
         // Transform and prepare script for execution
-        let wrapper_script = self.prepare_script_for_execution(script_content);
+        let wrapper_script = Self::prepare_script_for_execution(&run.script);
         
         // Compile the JavaScript code
         let code = v8::String::new(scope, &wrapper_script).unwrap();
@@ -151,23 +407,28 @@ impl JavaScriptEnginePlugin {
         };
 
         // Execute the script and handle result
-        match script.run(scope) {
+        let result = match script.run(scope) {
             Some(value) => {
                 if value.is_promise() {
-                    self.resolve_promise(scope, value)
+                    Self::resolve_promise(scope, value)
                 } else {
-                    self.process_js_result(scope, value)
+                    Self::process_js_result(scope, value)
                 }
             }
+            None if scope.is_execution_terminating() => Err(Elapsed.into()),
             None => Err(anyhow::anyhow!("JavaScript execution failed")),
+        };
+        if cancellation.is_cancelled() {
+            return Err(anyhow::anyhow!("Client disconnected"));
         }
+        result
     }
     
     
     /// Transforms ES6 module syntax and wraps script for execution
-    fn prepare_script_for_execution(&self, script_content: &str) -> String {
+    fn prepare_script_for_execution(script_content: &str) -> String {
         // Transform ES6 export syntax to work in our context
-        let transformed_script = self.transform_es6_exports(script_content);
+        let transformed_script = Self::transform_es6_exports(script_content);
         
         // Wrap in async function to handle both sync and async handlers
         format!(
@@ -192,7 +453,7 @@ impl JavaScriptEnginePlugin {
     }
     
     /// Transforms ES6 export statements to variable assignments
-    fn transform_es6_exports(&self, script_content: &str) -> String {
+    fn transform_es6_exports(script_content: &str) -> String {
         script_content
             .replace("export default function", "const __handler = function")
             .replace("export default async function", "const __handler = async function")
@@ -202,33 +463,33 @@ impl JavaScriptEnginePlugin {
     
     /// Resolves a JavaScript promise and processes the result
     fn resolve_promise(
-        &self,
         scope: &mut v8::ContextScope<v8::HandleScope>,
         value: v8::Local<v8::Value>,
     ) -> Result<Option<JsResponse>, anyhow::Error> {
         let promise = v8::Local::<v8::Promise>::try_from(value).unwrap();
         
-        // Poll microtasks until promise resolves
-        while promise.state() == v8::PromiseState::Pending {
-            scope.perform_microtask_checkpoint();
+        // There is no event loop, so whatever can settle the promise is
+        // queued as microtasks, and one checkpoint runs them all
+        scope.perform_microtask_checkpoint();
+        if scope.is_execution_terminating() {
+            return Err(Elapsed.into());
         }
 
         match promise.state() {
             v8::PromiseState::Fulfilled => {
                 let result = promise.result(scope);
-                self.process_js_result(scope, result)
+                Self::process_js_result(scope, result)
             }
             v8::PromiseState::Rejected => {
                 let exception = promise.result(scope);
-                let error_msg = self.get_error_message(scope, exception);
+                let error_msg = Self::get_error_message(scope, exception);
                 Err(anyhow::anyhow!("JavaScript error: {}", error_msg))
             }
-            _ => Ok(None),
+            v8::PromiseState::Pending => Err(anyhow::anyhow!("JavaScript handler never settled its promise")),
         }
     }
 
     fn process_js_result<'s>(
-        &self,
         scope: &mut v8::HandleScope<'s>,
         value: v8::Local<'s, v8::Value>,
     ) -> Result<Option<JsResponse>, anyhow::Error> {
@@ -248,7 +509,6 @@ impl JavaScriptEnginePlugin {
     }
 
     fn setup_global_functions<'s>(
-        &self,
         scope: &mut v8::HandleScope<'s>,
     ) -> Result<(), anyhow::Error> {
         let context = scope.get_current_context();
@@ -275,6 +535,63 @@ impl JavaScriptEnginePlugin {
         let set_timeout_fn = v8::Function::new(scope, Self::set_timeout).unwrap();
         global.set(scope, set_timeout_key.into(), set_timeout_fn.into());
 
+        // Setup document object
+        let document_key = v8::String::new(scope, "document").unwrap();
+        let document_obj = v8::Object::new(scope);
+
+        // document.get
+        let get_key = v8::String::new(scope, "get").unwrap();
+        let get_fn = v8::Function::new(scope, Self::document_get).unwrap();
+        document_obj.set(scope, get_key.into(), get_fn.into());
+
+        // document.replace
+        let replace_key = v8::String::new(scope, "replace").unwrap();
+        let replace_fn = v8::Function::new(scope, Self::document_replace).unwrap();
+        document_obj.set(scope, replace_key.into(), replace_fn.into());
+
+        // document.append
+        let append_key = v8::String::new(scope, "append").unwrap();
+        let append_fn = v8::Function::new(scope, Self::document_append).unwrap();
+        document_obj.set(scope, append_key.into(), append_fn.into());
+
+        // document.remove
+        let remove_key = v8::String::new(scope, "remove").unwrap();
+        let remove_fn = v8::Function::new(scope, Self::document_remove).unwrap();
+        document_obj.set(scope, remove_key.into(), remove_fn.into());
+
+        global.set(scope, document_key.into(), document_obj.into());
+
+        // Setup kv object
+        let kv_key = v8::String::new(scope, "kv").unwrap();
+        let kv_obj = v8::Object::new(scope);
+
+        // kv.get
+        let get_key = v8::String::new(scope, "get").unwrap();
+        let get_fn = v8::Function::new(scope, Self::kv_get).unwrap();
+        kv_obj.set(scope, get_key.into(), get_fn.into());
+
+        // kv.set
+        let set_key = v8::String::new(scope, "set").unwrap();
+        let set_fn = v8::Function::new(scope, Self::kv_set).unwrap();
+        kv_obj.set(scope, set_key.into(), set_fn.into());
+
+        // kv.delete
+        let delete_key = v8::String::new(scope, "delete").unwrap();
+        let delete_fn = v8::Function::new(scope, Self::kv_delete).unwrap();
+        kv_obj.set(scope, delete_key.into(), delete_fn.into());
+
+        // kv.list
+        let list_key = v8::String::new(scope, "list").unwrap();
+        let list_fn = v8::Function::new(scope, Self::kv_list).unwrap();
+        kv_obj.set(scope, list_key.into(), list_fn.into());
+
+        // kv.compareAndSwap
+        let cas_key = v8::String::new(scope, "compareAndSwap").unwrap();
+        let cas_fn = v8::Function::new(scope, Self::kv_compare_and_swap).unwrap();
+        kv_obj.set(scope, cas_key.into(), cas_fn.into());
+
+        global.set(scope, kv_key.into(), kv_obj.into());
+
         Ok(())
     }
 
@@ -328,6 +645,269 @@ impl JavaScriptEnginePlugin {
         // For now, JavaScript console.error output is collected but not displayed
     }
 
+    /// JavaScript document.get(path, selector) implementation
+    fn document_get(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        rv: v8::ReturnValue,
+    ) {
+        Self::document_operation(scope, &args, rv, "get", "GET");
+    }
+
+    /// JavaScript document.replace(path, selector, html) implementation
+    fn document_replace(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        rv: v8::ReturnValue,
+    ) {
+        Self::document_operation(scope, &args, rv, "replace", "PUT");
+    }
+
+    /// JavaScript document.append(path, selector, html) implementation
+    fn document_append(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        rv: v8::ReturnValue,
+    ) {
+        Self::document_operation(scope, &args, rv, "append", "POST");
+    }
+
+    /// JavaScript document.remove(path, selector) implementation
+    fn document_remove(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        rv: v8::ReturnValue,
+    ) {
+        Self::document_operation(scope, &args, rv, "remove", "DELETE");
+    }
+
+    /// Carries out a document API call as the selector request `method`
+    /// through the host services of the script's request
+    fn document_operation(
+        scope: &mut v8::HandleScope,
+        args: &v8::FunctionCallbackArguments,
+        mut rv: v8::ReturnValue,
+        function: &str,
+        method: &str,
+    ) {
+        let Some(host) = scope.get_slot::<Arc<ScriptHost>>().cloned() else {
+            return;
+        };
+
+        // PUT and POST carry the HTML to write as a third argument
+        let with_content = matches!(method, "PUT" | "POST");
+        let arg_count = if with_content { 3 } else { 2 };
+        let mut values = Vec::with_capacity(arg_count);
+        for i in 0..arg_count {
+            let arg = args.get(i as i32);
+            let value = if arg.is_null_or_undefined() {
+                None
+            } else {
+                arg.to_string(scope).map(|value| value.to_rust_string_lossy(scope))
+            };
+            match value {
+                Some(value) => values.push(value),
+                None => {
+                    let expected = if with_content { "path, selector and html" } else { "path and selector" };
+                    let message = format!("document.{} expects {}", function, expected);
+                    let message = v8::String::new(scope, &message).unwrap();
+                    let exception = v8::Exception::type_error(scope, message);
+                    scope.throw_exception(exception);
+                    return;
+                }
+            }
+        }
+
+        if !values[0].starts_with('/') {
+            Self::throw_selector_error(
+                scope,
+                &SelectorError::new(400, format!("document.{} expects an absolute path", function)),
+            );
+            return;
+        }
+
+        let mut operation = SelectorOperation::new(method, &values[0], &values[1]);
+        if with_content {
+            operation = operation.with_content(values[2].clone());
+        }
+        let result = match &host.context.services {
+            Some(services) => services.apply_selector(host.user.as_deref(), &operation, &host.context),
+            None => Err(SelectorError::new(501, "Host services are not available")),
+        };
+
+        match result {
+            Ok(html) => {
+                let value = v8::String::new(scope, &html).unwrap();
+                rv.set(value.into());
+            }
+            Err(error) => Self::throw_selector_error(scope, &error),
+        }
+    }
+
+    /// Throws an `Error` carrying the HTTP status of a failed selector operation
+    fn throw_selector_error(scope: &mut v8::HandleScope, error: &SelectorError) {
+        Self::throw_status_error(scope, error.status, &error.message);
+    }
+
+    /// Throws an `Error` whose `status` is an HTTP status describing the failure
+    fn throw_status_error(scope: &mut v8::HandleScope, status: u16, message: &str) {
+        let message = v8::String::new(scope, message).unwrap();
+        let exception = v8::Exception::error(scope, message);
+        if let Some(object) = exception.to_object(scope) {
+            let status_key = v8::String::new(scope, "status").unwrap();
+            let status = v8::Integer::new(scope, status as i32);
+            object.set(scope, status_key.into(), status.into());
+        }
+        scope.throw_exception(exception);
+    }
+
+    /// Throws a `TypeError` for a host function called with the wrong arguments
+    fn throw_type_error(scope: &mut v8::HandleScope, message: &str) {
+        let message = v8::String::new(scope, message).unwrap();
+        let exception = v8::Exception::type_error(scope, message);
+        scope.throw_exception(exception);
+    }
+
+    /// The store and namespace file of the running script, throwing if the
+    /// store has nowhere to keep it
+    fn kv_namespace(scope: &mut v8::HandleScope) -> Option<(Arc<KvStore>, PathBuf)> {
+        let host = scope.get_slot::<Arc<ScriptHost>>().cloned()?;
+        match &host.kv_file {
+            Some(file) => Some((Arc::clone(&host.kv), file.clone())),
+            None => {
+                Self::throw_status_error(scope, 501, "kv needs javascript_engine_kv_dir");
+                None
+            }
+        }
+    }
+
+    /// The key a kv function was called with, throwing if it is missing
+    fn kv_key(scope: &mut v8::HandleScope, args: &v8::FunctionCallbackArguments, function: &str) -> Option<String> {
+        let key = args.get(0);
+        if !key.is_string() {
+            Self::throw_type_error(scope, &format!("kv.{} expects a string key", function));
+            return None;
+        }
+        Some(key.to_rust_string_lossy(scope))
+    }
+
+    /// Converts a JavaScript value to JSON, or None if it has no JSON form
+    fn value_to_json(scope: &mut v8::HandleScope, value: v8::Local<v8::Value>) -> Option<serde_json::Value> {
+        if value.is_undefined() {
+            return None;
+        }
+        let json = v8::json::stringify(scope, value)?;
+        serde_json::from_str(&json.to_rust_string_lossy(scope)).ok()
+    }
+
+    /// Converts JSON to a JavaScript value
+    fn json_to_value<'s>(scope: &mut v8::HandleScope<'s>, json: &serde_json::Value) -> Option<v8::Local<'s, v8::Value>> {
+        let json = v8::String::new(scope, &json.to_string())?;
+        v8::json::parse(scope, json)
+    }
+
+    /// JavaScript kv.get(key) implementation
+    fn kv_get(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut rv: v8::ReturnValue,
+    ) {
+        let Some((kv, file)) = Self::kv_namespace(scope) else { return };
+        let Some(key) = Self::kv_key(scope, &args, "get") else { return };
+        match kv.get(&file, &key) {
+            Ok(Some(json)) => {
+                if let Some(value) = Self::json_to_value(scope, &json) {
+                    rv.set(value);
+                }
+            }
+            Ok(None) => {}
+            Err(error) => Self::throw_status_error(scope, error.status, &error.message),
+        }
+    }
+
+    /// JavaScript kv.set(key, value) implementation
+    fn kv_set(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        _rv: v8::ReturnValue,
+    ) {
+        let Some((kv, file)) = Self::kv_namespace(scope) else { return };
+        let Some(key) = Self::kv_key(scope, &args, "set") else { return };
+        let Some(json) = Self::value_to_json(scope, args.get(1)) else {
+            Self::throw_type_error(scope, "kv.set expects a JSON-serializable value");
+            return;
+        };
+        if let Err(error) = kv.set(&file, &key, json) {
+            Self::throw_status_error(scope, error.status, &error.message);
+        }
+    }
+
+    /// JavaScript kv.delete(key) implementation
+    fn kv_delete(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut rv: v8::ReturnValue,
+    ) {
+        let Some((kv, file)) = Self::kv_namespace(scope) else { return };
+        let Some(key) = Self::kv_key(scope, &args, "delete") else { return };
+        match kv.delete(&file, &key) {
+            Ok(existed) => rv.set(v8::Boolean::new(scope, existed).into()),
+            Err(error) => Self::throw_status_error(scope, error.status, &error.message),
+        }
+    }
+
+    /// JavaScript kv.list(prefix) implementation
+    fn kv_list(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut rv: v8::ReturnValue,
+    ) {
+        let Some((kv, file)) = Self::kv_namespace(scope) else { return };
+        let prefix = args.get(0);
+        let prefix = if prefix.is_null_or_undefined() { String::new() } else { prefix.to_rust_string_lossy(scope) };
+        match kv.list(&file, &prefix) {
+            Ok(keys) => {
+                let keys: Vec<v8::Local<v8::Value>> = keys.iter()
+                    .map(|key| v8::String::new(scope, key).unwrap().into())
+                    .collect();
+                rv.set(v8::Array::new_with_elements(scope, &keys).into());
+            }
+            Err(error) => Self::throw_status_error(scope, error.status, &error.message),
+        }
+    }
+
+    /// JavaScript kv.compareAndSwap(key, expected, value) implementation
+    fn kv_compare_and_swap(
+        scope: &mut v8::HandleScope,
+        args: v8::FunctionCallbackArguments,
+        mut rv: v8::ReturnValue,
+    ) {
+        let Some((kv, file)) = Self::kv_namespace(scope) else { return };
+        let Some(key) = Self::kv_key(scope, &args, "compareAndSwap") else { return };
+        // undefined stands for an absent key, on either side
+        let mut operands = Vec::with_capacity(2);
+        for index in 1..=2 {
+            let value = args.get(index);
+            if value.is_undefined() {
+                operands.push(None);
+                continue;
+            }
+            match Self::value_to_json(scope, value) {
+                Some(json) => operands.push(Some(json)),
+                None => {
+                    Self::throw_type_error(scope, "kv.compareAndSwap expects JSON-serializable values");
+                    return;
+                }
+            }
+        }
+        let value = operands.pop().flatten();
+        let expected = operands.pop().flatten();
+        match kv.compare_and_swap(&file, &key, expected.as_ref(), value) {
+            Ok(swapped) => rv.set(v8::Boolean::new(scope, swapped).into()),
+            Err(error) => Self::throw_status_error(scope, error.status, &error.message),
+        }
+    }
+
     fn set_timeout(
         scope: &mut v8::HandleScope,
         args: v8::FunctionCallbackArguments,
@@ -344,7 +924,7 @@ impl JavaScriptEnginePlugin {
         }
     }
 
-    fn get_json_object<'s>(&self, scope: &mut v8::HandleScope<'s>) -> v8::Local<'s, v8::Object> {
+    fn get_json_object<'s>(scope: &mut v8::HandleScope<'s>) -> v8::Local<'s, v8::Object> {
         let context = scope.get_current_context();
         let global = context.global(scope);
         let json_key = v8::String::new(scope, "JSON").unwrap();
@@ -353,7 +933,6 @@ impl JavaScriptEnginePlugin {
     }
 
     fn get_error_message<'s>(
-        &self,
         scope: &mut v8::HandleScope<'s>,
         exception: v8::Local<'s, v8::Value>,
     ) -> String {
@@ -375,23 +954,37 @@ impl JavaScriptEnginePlugin {
 
         JsRequest {
             method: request.http_request.method().to_string(),
-            path: request.http_request.uri().path().to_string(),
+            path: request.path.clone(),
             headers,
             body,
         }
     }
 
+    /// Checks a route pattern against a path, treating a trailing `*` as a prefix match
+    fn route_matches(pattern: &str, path: &str) -> bool {
+        match pattern.strip_suffix('*') {
+            Some(prefix) => path.starts_with(prefix),
+            None => path == pattern,
+        }
+    }
+
     async fn find_script_for_path(&self, path: &str) -> Option<String> {
+        // Manifest routes come first, in the order they were written
+        let manifest_routes = self.manifest_routes.read().await;
+        if let Some(route) = manifest_routes
+            .routes
+            .iter()
+            .find(|route| Self::route_matches(&route.pattern, path))
+        {
+            return Some(route.script.clone());
+        }
+        drop(manifest_routes);
+
         let mappings = self.route_mappings.read().await;
         
         // Check for exact match or prefix match
         for (pattern, script) in mappings.iter() {
-            if pattern.ends_with("*") {
-                let prefix = &pattern[..pattern.len() - 1];
-                if path.starts_with(prefix) {
-                    return Some(script.clone());
-                }
-            } else if path == pattern {
+            if Self::route_matches(pattern, path) {
                 return Some(script.clone());
             }
         }
@@ -421,6 +1014,24 @@ impl JavaScriptEnginePlugin {
     }
 }
 
+/// Terminates a script still running when the request deadline passes
+struct Watchdog {
+    // Dropping the sender disarms the watchdog
+    _disarm: mpsc::Sender<()>,
+}
+
+impl Watchdog {
+    fn arm(isolate: v8::IsolateHandle, timeout: Duration) -> Self {
+        let (disarm, disarmed) = mpsc::channel();
+        std::thread::spawn(move || {
+            if disarmed.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout) {
+                isolate.terminate_execution();
+            }
+        });
+        Self { _disarm: disarm }
+    }
+}
+
 #[async_trait]
 impl Plugin for JavaScriptEnginePlugin {
     async fn handle_request(
@@ -438,17 +1049,16 @@ impl Plugin for JavaScriptEnginePlugin {
             return None;
         }
 
-        let path = request.http_request.uri().path();
-        
-        // Scripts directory is configured at plugin initialization time
-        
-        // Route mappings would need to be loaded from configuration at initialization
+        // Pick up edits to the routes manifest without a restart
+        self.refresh_manifest_routes(context).await;
+
+        let path = request.path.as_str();
 
         // Find and execute appropriate script
         if let Some(script_file) = self.find_script_for_path(path).await {
             match self.load_script(&script_file).await {
                 Ok(script_content) => {
-                    match self.execute_javascript(&script_content, request).await {
+                    match self.execute_javascript(&script_file, &script_content, request, context).await {
                         Ok(Some(js_response)) => {
                             let mut response = Response::builder()
                                 .status(StatusCode::from_u16(js_response.status).unwrap_or(StatusCode::OK));
@@ -467,6 +1077,10 @@ impl Plugin for JavaScriptEnginePlugin {
                             }
                         }
                         Ok(None) => None,
+                        Err(e) if e.is::<Elapsed>() => {
+                            context.log_verbose(&format!("[JavaScript] Terminated '{}' at the request deadline", script_file));
+                            Some(deadline::exceeded_response(&self.name).into())
+                        }
                         Err(e) => {
                             context.log_verbose(&format!("[JavaScript] Execution error: {}", e));
                             let response = Response::builder()
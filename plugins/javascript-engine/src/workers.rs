@@ -0,0 +1,135 @@
+//! Worker threads running scripts
+//!
+//! V8 runs a script to completion without yielding, so scripts run on a pool
+//! of threads of their own instead of the server's runtime. The request's task
+//! hands the script to the pool and awaits its result over a channel, leaving
+//! the runtime free to serve other requests meanwhile.
+//!
+//! The server drops the task of a request whose client went away. A script
+//! no worker picked up yet is then skipped, and a running one is terminated
+//! through the isolate it registered with its `Cancellation`.
+
+use futures::channel::oneshot;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed number of threads taking jobs from a shared queue
+#[derive(Debug)]
+pub struct WorkerPool {
+    jobs: Mutex<mpsc::Sender<Job>>,
+    size: usize,
+}
+
+impl WorkerPool {
+    /// Starts `size` worker threads, which stop once the pool is dropped
+    pub fn new(size: usize) -> std::io::Result<Self> {
+        let (jobs, queue) = mpsc::channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        for index in 0..size {
+            let queue = Arc::clone(&queue);
+            thread::Builder::new()
+                .name(format!("javascript-worker-{}", index))
+                .spawn(move || loop {
+                    let job = match queue.lock() {
+                        Ok(queue) => queue.recv(),
+                        Err(_) => return,
+                    };
+                    match job {
+                        // A panicking script fails its own request, not the worker
+                        Ok(job) => {
+                            let _ = panic::catch_unwind(AssertUnwindSafe(job));
+                        }
+                        Err(_) => return,
+                    }
+                })?;
+        }
+        Ok(Self { jobs: Mutex::new(jobs), size })
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Runs `task` on a worker and waits for its result
+    ///
+    /// Dropping the returned future cancels the task.
+    pub async fn run<T, F>(&self, task: F) -> Result<T, anyhow::Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&Cancellation) -> T + Send + 'static,
+    {
+        let (reply, result) = oneshot::channel();
+        let state = Arc::new(CancelState::default());
+        let cancellation = Cancellation(Arc::clone(&state));
+        let job: Job = Box::new(move || {
+            if cancellation.is_cancelled() {
+                return;
+            }
+            let value = task(&cancellation);
+            cancellation.release();
+            let _ = reply.send(value);
+        });
+
+        self.jobs
+            .lock()
+            .map_err(|_| anyhow::anyhow!("JavaScript workers are unavailable"))?
+            .send(job)
+            .map_err(|_| anyhow::anyhow!("JavaScript workers have stopped"))?;
+
+        let _cancel_on_drop = CancelOnDrop(state);
+        result
+            .await
+            .map_err(|_| anyhow::anyhow!("JavaScript worker failed while running the script"))
+    }
+}
+
+#[derive(Default)]
+struct CancelState {
+    cancelled: AtomicBool,
+    /// Isolate of the running script, terminated on cancellation
+    isolate: Mutex<Option<v8::IsolateHandle>>,
+}
+
+/// The worker's side of a task's cancellation
+pub struct Cancellation(Arc<CancelState>);
+
+impl Cancellation {
+    pub fn is_cancelled(&self) -> bool {
+        self.0.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Registers the isolate to terminate if the task is cancelled while it runs
+    pub fn watch(&self, isolate: v8::IsolateHandle) {
+        if let Ok(mut watched) = self.0.isolate.lock() {
+            // Cancelled before the isolate was registered
+            if self.is_cancelled() {
+                isolate.terminate_execution();
+            }
+            *watched = Some(isolate);
+        }
+    }
+
+    fn release(&self) {
+        if let Ok(mut watched) = self.0.isolate.lock() {
+            *watched = None;
+        }
+    }
+}
+
+/// Cancels a task when the future awaiting it is dropped
+struct CancelOnDrop(Arc<CancelState>);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Ok(watched) = self.0.isolate.lock() {
+            self.0.cancelled.store(true, Ordering::SeqCst);
+            if let Some(isolate) = watched.as_ref() {
+                isolate.terminate_execution();
+            }
+        }
+    }
+}
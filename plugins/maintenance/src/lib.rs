@@ -0,0 +1,286 @@
+//! Maintenance Plugin for Rusty Beam
+//!
+//! This plugin puts a host into maintenance mode: while it is on, every
+//! request gets a 503 Service Unavailable with a maintenance page and a
+//! `Retry-After` header, except for allowlisted paths such as health checks
+//! and for allowlisted users, who keep using the site as normal.
+//!
+//! ## Features
+//! - **Three Switches**: A config flag, a sentinel file whose existence turns
+//!   maintenance on, and an admin API for allowlisted users
+//! - **Path Allowlist**: Path prefixes that stay reachable, such as health
+//!   checks and admin pages
+//! - **User Allowlist**: Users and roles that bypass maintenance mode, so the
+//!   site can be checked before it is opened again
+//! - **Custom Page**: An HTML file to serve instead of the built-in page
+//!
+//! ## Configuration
+//! - `enabled`: Whether maintenance mode is on (default: false)
+//! - `sentinel_file`: Maintenance mode is on while this file exists
+//!   (default: none)
+//! - `admin_path`: Path of the admin API (default: none, disabled)
+//! - `allow_paths`: Comma-separated path prefixes served as normal
+//!   (default: "/health,/ready,/live")
+//! - `allow_users`: Comma-separated users served as normal (default: none)
+//! - `allow_roles`: Comma-separated roles served as normal (default: none)
+//! - `retry_after`: Value of the `Retry-After` header, in seconds or as an
+//!   HTTP date (default: "300")
+//! - `page_file`: HTML file served as the maintenance page (default: a
+//!   built-in page)
+//! - `message`: Message shown on the built-in page
+//!
+//! ## Admin API
+//! Allowlisted users can `GET` the admin path for the current state, `PUT` or
+//! `POST` `on` or `off` to it to switch maintenance mode, and `DELETE` it to
+//! hand control back to the config flag and sentinel file. A switch made
+//! through the API lasts until the server restarts or reloads its
+//! configuration; use the sentinel file for a switch that must persist.
+//!
+//! ## Pipeline Integration
+//! Place the plugin after the authentication plugins, so allowlisted users
+//! are recognised, and before the handlers serving content. Users come from
+//! the `authenticated_user` metadata and roles from `authenticated_user_roles`.
+
+use async_trait::async_trait;
+use hyper::header::{CACHE_CONTROL, CONTENT_TYPE, RETRY_AFTER};
+use hyper::{Body, Method, Response, StatusCode};
+use rusty_beam_plugin_api::{create_plugin, log_debug, log_error, log_info, Plugin, PluginContext, PluginRequest, PluginResponse};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::RwLock;
+
+// Default configuration values
+const DEFAULT_PLUGIN_NAME: &str = "maintenance";
+const DEFAULT_ALLOW_PATHS: &str = "/health,/ready,/live";
+const DEFAULT_RETRY_AFTER: &str = "300";
+const DEFAULT_MESSAGE: &str = "This site is down for maintenance. Please try again shortly.";
+
+// Configuration keys
+const CONFIG_KEY_NAME: &str = "name";
+const CONFIG_KEY_ENABLED: &str = "enabled";
+const CONFIG_KEY_SENTINEL_FILE: &str = "sentinel_file";
+const CONFIG_KEY_ADMIN_PATH: &str = "admin_path";
+const CONFIG_KEY_ALLOW_PATHS: &str = "allow_paths";
+const CONFIG_KEY_ALLOW_USERS: &str = "allow_users";
+const CONFIG_KEY_ALLOW_ROLES: &str = "allow_roles";
+const CONFIG_KEY_RETRY_AFTER: &str = "retry_after";
+const CONFIG_KEY_PAGE_FILE: &str = "page_file";
+const CONFIG_KEY_MESSAGE: &str = "message";
+
+// Request metadata set by authentication plugins
+const AUTHENTICATED_USER_METADATA: &str = "authenticated_user";
+const AUTHENTICATED_ROLES_METADATA: &str = "authenticated_user_roles";
+
+// Content types
+const CONTENT_TYPE_HTML: &str = "text/html; charset=utf-8";
+const CONTENT_TYPE_JSON: &str = "application/json";
+
+// Messages
+const MSG_ADMIN_FORBIDDEN: &str = "Only allowlisted users may switch maintenance mode";
+const MSG_ADMIN_BAD_STATE: &str = "Send 'on' or 'off' to switch maintenance mode";
+
+/// What decided whether maintenance mode is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Source {
+    Api,
+    Config,
+    SentinelFile,
+}
+
+impl Source {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Source::Api => "api",
+            Source::Config => "config",
+            Source::SentinelFile => "sentinel_file",
+        }
+    }
+}
+
+/// Plugin answering with a maintenance page while maintenance mode is on
+#[derive(Debug)]
+pub struct MaintenancePlugin {
+    name: String,
+    enabled: bool,
+    sentinel_file: Option<PathBuf>,
+    admin_path: Option<String>,
+    allow_paths: Vec<String>,
+    allow_users: Vec<String>,
+    allow_roles: Vec<String>,
+    retry_after: String,
+    page_file: Option<PathBuf>,
+    message: String,
+    /// State set through the admin API, overriding the config and sentinel file
+    api_state: RwLock<Option<bool>>,
+}
+
+impl MaintenancePlugin {
+    pub fn new(config: HashMap<String, String>) -> Self {
+        let name = config.get(CONFIG_KEY_NAME).cloned().unwrap_or_else(|| DEFAULT_PLUGIN_NAME.to_string());
+        let non_empty = |key: &str| config.get(key)
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+
+        Self {
+            name,
+            enabled: config.get(CONFIG_KEY_ENABLED).and_then(|v| v.trim().parse().ok()).unwrap_or(false),
+            sentinel_file: non_empty(CONFIG_KEY_SENTINEL_FILE).map(|path| Self::file_path(&path)),
+            admin_path: non_empty(CONFIG_KEY_ADMIN_PATH),
+            allow_paths: Self::parse_list(&config, CONFIG_KEY_ALLOW_PATHS, DEFAULT_ALLOW_PATHS),
+            allow_users: Self::parse_list(&config, CONFIG_KEY_ALLOW_USERS, ""),
+            allow_roles: Self::parse_list(&config, CONFIG_KEY_ALLOW_ROLES, ""),
+            retry_after: non_empty(CONFIG_KEY_RETRY_AFTER).unwrap_or_else(|| DEFAULT_RETRY_AFTER.to_string()),
+            page_file: non_empty(CONFIG_KEY_PAGE_FILE).map(|path| Self::file_path(&path)),
+            message: non_empty(CONFIG_KEY_MESSAGE).unwrap_or_else(|| DEFAULT_MESSAGE.to_string()),
+            api_state: RwLock::new(None),
+        }
+    }
+
+    /// Parse a comma-separated configuration value, skipping empty entries
+    fn parse_list(config: &HashMap<String, String>, key: &str, default: &str) -> Vec<String> {
+        config.get(key).map(String::as_str).unwrap_or(default)
+            .split(',')
+            .map(|entry| entry.trim().to_string())
+            .filter(|entry| !entry.is_empty())
+            .collect()
+    }
+
+    /// A configured file, given as a path or a file:// URL
+    fn file_path(value: &str) -> PathBuf {
+        PathBuf::from(value.strip_prefix("file://").unwrap_or(value))
+    }
+
+    /// Whether maintenance mode is on, and what decided it
+    fn state(&self) -> (bool, Source) {
+        if let Some(on) = *self.api_state.read().unwrap() {
+            return (on, Source::Api);
+        }
+        if self.enabled {
+            return (true, Source::Config);
+        }
+        match &self.sentinel_file {
+            Some(path) => (path.exists(), Source::SentinelFile),
+            None => (false, Source::Config),
+        }
+    }
+
+    fn is_allowed_path(&self, path: &str) -> bool {
+        self.admin_path.as_deref() == Some(path)
+            || self.allow_paths.iter().any(|prefix| path.starts_with(prefix.as_str()))
+    }
+
+    /// Whether the user behind the request may bypass maintenance mode
+    fn is_allowed_user(&self, request: &PluginRequest) -> bool {
+        let Some(user) = request.get_metadata(AUTHENTICATED_USER_METADATA) else {
+            return false;
+        };
+        if self.allow_users.iter().any(|allowed| allowed == user) {
+            return true;
+        }
+        request.get_metadata(AUTHENTICATED_ROLES_METADATA)
+            .map(|roles| roles.split(',').map(str::trim).any(|role| self.allow_roles.iter().any(|allowed| allowed == role)))
+            .unwrap_or(false)
+    }
+
+    /// Answer a request to the admin API
+    async fn handle_admin_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Response<Body> {
+        if !self.is_allowed_user(request) {
+            return Self::json_response(StatusCode::FORBIDDEN, serde_json::json!({ "error": MSG_ADMIN_FORBIDDEN }));
+        }
+
+        let method = request.http_request.method().clone();
+        if method == Method::PUT || method == Method::POST {
+            let body = request.get_body().await.unwrap_or_default();
+            let on = match String::from_utf8_lossy(&body).trim().to_lowercase().as_str() {
+                "on" | "true" => true,
+                "off" | "false" => false,
+                _ => return Self::json_response(StatusCode::BAD_REQUEST, serde_json::json!({ "error": MSG_ADMIN_BAD_STATE })),
+            };
+            *self.api_state.write().unwrap() = Some(on);
+            log_info!(self.name, context, "Maintenance mode switched {}", if on { "on" } else { "off" };
+                user = request.get_metadata(AUTHENTICATED_USER_METADATA).unwrap_or_default(),
+                host = context.host_name);
+        } else if method == Method::DELETE {
+            *self.api_state.write().unwrap() = None;
+            log_info!(self.name, context, "Maintenance mode handed back to the configuration"; host = context.host_name);
+        } else if method != Method::GET && method != Method::HEAD {
+            return Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header("Allow", "GET, HEAD, PUT, POST, DELETE")
+                .body(Body::empty())
+                .unwrap();
+        }
+
+        let (on, source) = self.state();
+        Self::json_response(StatusCode::OK, serde_json::json!({
+            "maintenance": on,
+            "source": source.as_str(),
+        }))
+    }
+
+    fn json_response(status: StatusCode, body: serde_json::Value) -> Response<Body> {
+        Response::builder()
+            .status(status)
+            .header(CONTENT_TYPE, CONTENT_TYPE_JSON)
+            .header(CACHE_CONTROL, "no-store")
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+
+    /// The maintenance page, read from `page_file` on every request so it can
+    /// be edited while the site is down
+    fn maintenance_page(&self, context: &PluginContext) -> String {
+        if let Some(path) = &self.page_file {
+            match std::fs::read_to_string(path) {
+                Ok(page) => return page,
+                Err(e) => log_error!(self.name, context, "Failed to read page file: {}", e; file = path.display()),
+            }
+        }
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<title>Down for Maintenance</title>\n</head>\n<body>\n<h1>Down for Maintenance</h1>\n<p>{}</p>\n</body>\n</html>\n",
+            html_escape(&self.message)
+        )
+    }
+
+    fn create_maintenance_response(&self, context: &PluginContext) -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .header(CONTENT_TYPE, CONTENT_TYPE_HTML)
+            .header(RETRY_AFTER, self.retry_after.as_str())
+            .header(CACHE_CONTROL, "no-store")
+            .body(Body::from(self.maintenance_page(context)))
+            .unwrap()
+    }
+}
+
+fn html_escape(value: &str) -> String {
+    value.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[async_trait]
+impl Plugin for MaintenancePlugin {
+    async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
+        if self.admin_path.as_deref() == Some(request.path.as_str()) {
+            return Some(self.handle_admin_request(request, context).await.into());
+        }
+
+        let (on, _) = self.state();
+        if !on || self.is_allowed_path(&request.path) || self.is_allowed_user(request) {
+            return None;
+        }
+
+        log_debug!(self.name, context, "Answered with the maintenance page";
+            method = request.http_request.method(), path = request.path);
+        Some(self.create_maintenance_response(context).into())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// Export the plugin creation function
+create_plugin!(MaintenancePlugin);
@@ -0,0 +1,449 @@
+//! OAuth2 device authorization grant (RFC 8628) for CLI clients
+//!
+//! A client without a browser starts a flow with `POST {devicePath}`. The
+//! plugin asks the provider for a device code and answers with the user code
+//! to enter at the provider's verification page, and a poll URL of the form
+//! `{devicePath}/{flow id}`. Each `GET` of the poll URL asks the provider
+//! whether the user has approved the sign-in, at most once per interval the
+//! provider sets, and answers 202 until they have. Once approved, the user
+//! gets the same domain and verification checks and identity linking as a
+//! browser login, and the poll answers with a long-lived API token, which
+//! the client presents as `Authorization: Bearer` from then on.
+//!
+//! The flow id is as unguessable as a session id, since whoever polls with
+//! it first receives the token. A flow is forgotten once it has issued its
+//! token, failed, or expired.
+//!
+//! Each start asks the provider for a code, so a client may start only a few
+//! flows a minute, answered 429 beyond that, and at most
+//! `MAX_PENDING_DEVICE_FLOWS` wait at once, answered 503 beyond that. Clients
+//! are told apart by `X-Forwarded-For` when `trustForwardedHeaders` is set;
+//! otherwise every client shares one limit.
+
+use super::*;
+use std::time::Instant;
+
+const GITHUB_DEVICE_CODE_URL: &str = "https://github.com/login/device/code";
+const GOOGLE_DEVICE_CODE_URL: &str = "https://oauth2.googleapis.com/device/code";
+const DEVICE_CODE_GRANT_TYPE: &str = "urn:ietf:params:oauth:grant-type:device_code";
+/// Poll interval when the provider sets none, as RFC 8628 specifies
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_secs(5);
+/// What a `slow_down` answer adds to the poll interval
+const SLOW_DOWN_INCREMENT: Duration = Duration::from_secs(5);
+const CONTENT_TYPE_TEXT: &str = "text/plain; charset=utf-8";
+/// Device flows one client may start per `DEVICE_START_WINDOW`
+const DEVICE_STARTS_PER_CLIENT: usize = 10;
+const DEVICE_START_WINDOW: Duration = Duration::from_secs(60);
+/// Device flows that may wait for their users at once
+const MAX_PENDING_DEVICE_FLOWS: usize = 1000;
+/// Seconds a client is asked to wait when too many flows are pending
+const PENDING_FLOWS_RETRY_SECONDS: u64 = 60;
+/// The client of requests without a trusted `X-Forwarded-For`
+const UNKNOWN_CLIENT: &str = "unknown";
+
+/// A device flow waiting for its user to approve the sign-in
+#[derive(Debug)]
+pub(crate) struct DeviceFlow {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    expires_at: Instant,
+    interval: Duration,
+    /// The provider is not asked again before this
+    next_poll: Instant,
+}
+
+/// The provider's answer to a device authorization request
+#[derive(Debug, Deserialize)]
+struct DeviceCodeResponse {
+    device_code: String,
+    user_code: String,
+    /// Google calls this `verification_url`
+    #[serde(alias = "verification_url")]
+    verification_uri: String,
+    expires_in: u64,
+    #[serde(default)]
+    interval: Option<u64>,
+}
+
+/// The provider's answer to polling a device flow
+enum DeviceGrant {
+    Authorized(String),
+    Pending,
+    SlowDown,
+    /// The provider will never authorize the flow, with its error code
+    Refused(String),
+}
+
+impl OAuth2Plugin {
+    /// The id of the device flow a request polls, if it is for a poll URL
+    pub(crate) fn device_flow_id(&self, request: &PluginRequest) -> Option<String> {
+        request.path.strip_prefix(self.device_path.as_str())?
+            .strip_prefix('/')
+            .filter(|flow_id| is_session_id(flow_id))
+            .map(str::to_string)
+    }
+
+    /// Starts a device flow, answering with the code for the user to enter
+    pub(crate) async fn handle_device_start(&self, request: &PluginRequest, context: &PluginContext) -> Response<Body> {
+        context.log_verbose(&format!("[OAuth2-{}] Starting device flow", self.provider));
+
+        if let Err(e) = self.check_credentials() {
+            return text_response(StatusCode::INTERNAL_SERVER_ERROR, e);
+        }
+        let now = Instant::now();
+        if let Err(wait) = self.record_device_start(request, now) {
+            let mut response = text_response(StatusCode::TOO_MANY_REQUESTS, "Too many device flows started; try again later\n".to_string());
+            response.headers_mut().insert(RETRY_AFTER, wait.as_secs().max(1).into());
+            return response;
+        }
+        {
+            let mut flows = self.device_flows.lock().unwrap_or_else(|e| e.into_inner());
+            flows.retain(|_, flow| flow.expires_at > now);
+            if flows.len() >= MAX_PENDING_DEVICE_FLOWS {
+                log_warn!(self.name, "Refusing device flow, {} are pending", flows.len(); provider = self.provider);
+                let mut response = text_response(StatusCode::SERVICE_UNAVAILABLE, "Too many device flows are pending; try again later\n".to_string());
+                response.headers_mut().insert(RETRY_AFTER, PENDING_FLOWS_RETRY_SECONDS.into());
+                return response;
+            }
+        }
+        let device_code = match self.request_device_code() {
+            Ok(device_code) => device_code,
+            Err(e) => {
+                log_error!(self.name, "Device authorization request failed: {}", e; provider = self.provider);
+                return text_response(StatusCode::BAD_GATEWAY, format!("Failed to start device flow: {}", e));
+            }
+        };
+
+        let flow_id = new_session_id();
+        let now = Instant::now();
+        let interval = device_code.interval.map(Duration::from_secs).unwrap_or(DEFAULT_POLL_INTERVAL);
+        let poll_url = self.external_url(request, &format!("{}/{}", self.device_path, flow_id));
+        let answer = serde_json::json!({
+            "user_code": device_code.user_code,
+            "verification_uri": device_code.verification_uri,
+            "expires_in": device_code.expires_in,
+            "interval": interval.as_secs(),
+            "poll_url": poll_url,
+        });
+        let text = format!(
+            "To sign in, visit {} and enter the code {}\n\nThen fetch your API token from {}\nIt answers 202 until you have approved the sign-in.\n",
+            device_code.verification_uri, device_code.user_code, poll_url
+        );
+
+        {
+            let mut flows = self.device_flows.lock().unwrap_or_else(|e| e.into_inner());
+            flows.retain(|_, flow| flow.expires_at > now);
+            flows.insert(flow_id, DeviceFlow {
+                device_code: device_code.device_code,
+                user_code: device_code.user_code,
+                verification_uri: device_code.verification_uri,
+                expires_at: now + Duration::from_secs(device_code.expires_in),
+                interval,
+                next_poll: now + interval,
+            });
+        }
+
+        if request.prefers_json() {
+            return json_response(StatusCode::OK, answer);
+        }
+        text_response(StatusCode::OK, text)
+    }
+
+    /// Records that the request's client starts a device flow, or returns how
+    /// long until it may start another
+    fn record_device_start(&self, request: &PluginRequest, now: Instant) -> Result<(), Duration> {
+        let client = self.forwarded_header(request, X_FORWARDED_FOR).unwrap_or(UNKNOWN_CLIENT);
+        let mut starts = self.device_starts.lock().unwrap_or_else(|e| e.into_inner());
+        starts.retain(|_, times| {
+            times.retain(|time| now.duration_since(*time) < DEVICE_START_WINDOW);
+            !times.is_empty()
+        });
+        let times = starts.entry(client.to_string()).or_default();
+        if times.len() >= DEVICE_STARTS_PER_CLIENT {
+            return Err(DEVICE_START_WINDOW.saturating_sub(now.duration_since(times[0])));
+        }
+        times.push(now);
+        Ok(())
+    }
+
+    /// Polls a device flow, answering with an API token once the user approves
+    ///
+    /// Flows this instance did not start are passed through, as another
+    /// instance may share the device path.
+    pub(crate) async fn handle_device_poll(&self, request: &PluginRequest, flow_id: &str, context: &PluginContext) -> Option<Response<Body>> {
+        let now = Instant::now();
+        let (device_code, pending) = {
+            let mut flows = self.device_flows.lock().unwrap_or_else(|e| e.into_inner());
+            let flow = flows.get_mut(flow_id)?;
+            if flow.expires_at <= now {
+                flows.remove(flow_id);
+                return Some(self.device_flow_expired(request));
+            }
+            let pending = pending_response(flow);
+            if now < flow.next_poll {
+                return Some(pending);
+            }
+            flow.next_poll = now + flow.interval;
+            (flow.device_code.clone(), pending)
+        };
+
+        let grant = match self.poll_device_grant(&device_code) {
+            Ok(grant) => grant,
+            Err(e) => {
+                // The flow stays open, so the client can retry
+                log_error!(self.name, "Device flow poll failed: {}", e; provider = self.provider);
+                return Some(text_response(StatusCode::BAD_GATEWAY, format!("Failed to poll device flow: {}", e)));
+            }
+        };
+        let access_token = match grant {
+            DeviceGrant::Authorized(access_token) => access_token,
+            DeviceGrant::Pending => return Some(pending),
+            DeviceGrant::SlowDown => {
+                if let Some(flow) = self.device_flows.lock().unwrap_or_else(|e| e.into_inner()).get_mut(flow_id) {
+                    flow.interval += SLOW_DOWN_INCREMENT;
+                    flow.next_poll = Instant::now() + flow.interval;
+                }
+                return Some(pending);
+            }
+            DeviceGrant::Refused(error) => {
+                self.device_flows.lock().unwrap_or_else(|e| e.into_inner()).remove(flow_id);
+                context.log_verbose(&format!("[OAuth2-{}] Device flow refused: {}", self.provider, error));
+                return Some(match error.as_str() {
+                    "expired_token" => self.device_flow_expired(request),
+                    "access_denied" => text_response(StatusCode::FORBIDDEN, "The sign-in was denied\n".to_string()),
+                    _ => text_response(StatusCode::BAD_GATEWAY, format!("The provider refused the device flow: {}\n", error)),
+                });
+            }
+        };
+
+        // The flow is done whether or not the user may sign in
+        self.device_flows.lock().unwrap_or_else(|e| e.into_inner()).remove(flow_id);
+        let mut user = match self.retrieve_user_information(&access_token, context).await {
+            Ok(user) => user,
+            Err(response) => return Some(response),
+        };
+        if let Err(response) = self.admit(&mut user, context).await {
+            return Some(response);
+        }
+
+        let (token, api_token) = self.issue_api_token(user).await;
+        context.log_verbose(&format!("[OAuth2-{}] Issued API token for {} through the device flow", self.provider, api_token.user.email));
+        if request.prefers_json() {
+            let expires_in = api_token.expires_at
+                .and_then(|expires_at| expires_at.duration_since(SystemTime::now()).ok())
+                .map(|remaining| remaining.as_secs());
+            return Some(json_response(StatusCode::OK, serde_json::json!({
+                "access_token": token,
+                "token_type": "Bearer",
+                "expires_in": expires_in,
+                "user": user_json(&api_token.user),
+            })));
+        }
+        Some(text_response(StatusCode::OK, format!("{}\n", token)))
+    }
+
+    fn device_flow_expired(&self, request: &PluginRequest) -> Response<Body> {
+        text_response(StatusCode::GONE, format!(
+            "The device code has expired; start again with POST {}\n",
+            self.external_url(request, &self.device_path)
+        ))
+    }
+
+    fn device_code_url(&self) -> &str {
+        match self.provider.as_str() {
+            GITHUB_PROVIDER => GITHUB_DEVICE_CODE_URL,
+            _ => GOOGLE_DEVICE_CODE_URL,
+        }
+    }
+
+    fn device_scope(&self) -> String {
+        match self.provider.as_str() {
+            GITHUB_PROVIDER => GITHUB_SCOPE.to_string(),
+            _ => format!("{} {}", GOOGLE_EMAIL_SCOPE, GOOGLE_PROFILE_SCOPE),
+        }
+    }
+
+    /// Asks the provider for a device code and the user code that goes with it
+    fn request_device_code(&self) -> Result<DeviceCodeResponse, String> {
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("client_id", &self.client_id)
+            .append_pair("scope", &self.device_scope())
+            .finish();
+        let url = self.device_code_url().to_string();
+
+        tokio::task::block_in_place(move || {
+            ureq::post(&url)
+                .set("Content-Type", "application/x-www-form-urlencoded")
+                .set("Accept", "application/json")
+                .send_string(&body)
+                .map_err(|e| format!("HTTP request failed: {}", e))
+        })?
+        .into_json()
+        .map_err(|e| format!("Failed to parse device code response: {}", e))
+    }
+
+    /// Asks the provider whether the user has approved a device flow
+    fn poll_device_grant(&self, device_code: &str) -> Result<DeviceGrant, String> {
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("grant_type", DEVICE_CODE_GRANT_TYPE)
+            .append_pair("device_code", device_code)
+            .append_pair("client_id", &self.client_id)
+            .append_pair("client_secret", &self.client_secret)
+            .finish();
+        let token_url = self.token_url.clone();
+
+        let response = tokio::task::block_in_place(move || {
+            let result = ureq::post(&token_url)
+                .set("Content-Type", "application/x-www-form-urlencoded")
+                .set("Accept", "application/json")
+                .send_string(&body);
+            // Google answers a pending flow with an error status, GitHub with a 200
+            match result {
+                Ok(response) | Err(ureq::Error::Status(_, response)) => Ok(response),
+                Err(e) => Err(format!("HTTP request failed: {}", e)),
+            }
+        })?;
+        let answer: serde_json::Value = response.into_json()
+            .map_err(|e| format!("Failed to parse token response: {}", e))?;
+
+        if let Some(access_token) = answer.get("access_token").and_then(|v| v.as_str()) {
+            return Ok(DeviceGrant::Authorized(access_token.to_string()));
+        }
+        match answer.get("error").and_then(|v| v.as_str()) {
+            Some("authorization_pending") => Ok(DeviceGrant::Pending),
+            Some("slow_down") => Ok(DeviceGrant::SlowDown),
+            Some(error) => Ok(DeviceGrant::Refused(error.to_string())),
+            None => Err("No access token in response".to_string()),
+        }
+    }
+}
+
+/// The 202 a client polling a flow gets until the user approves it
+fn pending_response(flow: &DeviceFlow) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .header(CONTENT_TYPE, CONTENT_TYPE_TEXT)
+        .header(CACHE_CONTROL, "no-store")
+        .header(RETRY_AFTER, flow.interval.as_secs().to_string())
+        .body(Body::from(format!(
+            "Waiting for the sign-in to be approved at {} with the code {}\n",
+            flow.verification_uri, flow.user_code
+        )))
+        .unwrap()
+}
+
+fn text_response(status: StatusCode, text: String) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, CONTENT_TYPE_TEXT)
+        .header(CACHE_CONTROL, "no-store")
+        .body(Body::from(text))
+        .unwrap()
+}
+
+pub(crate) fn json_response(status: StatusCode, json: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, CONTENT_TYPE_JSON)
+        .header(CACHE_CONTROL, "no-store")
+        .body(Body::from(json.to_string()))
+        .unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{create_nested_test_plugin, create_test_context, create_test_plugin, create_test_request};
+
+    fn add_flow(plugin: &OAuth2Plugin, expires_in: Duration) -> String {
+        let flow_id = new_session_id();
+        let now = Instant::now();
+        plugin.device_flows.lock().unwrap().insert(flow_id.clone(), DeviceFlow {
+            device_code: "device-code".to_string(),
+            user_code: "WDJB-MJHT".to_string(),
+            verification_uri: "https://www.google.com/device".to_string(),
+            expires_at: now + expires_in,
+            interval: DEFAULT_POLL_INTERVAL,
+            next_poll: now + DEFAULT_POLL_INTERVAL,
+        });
+        flow_id
+    }
+
+    #[tokio::test]
+    async fn test_device_poll_before_interval_is_pending() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        let flow_id = add_flow(&plugin, Duration::from_secs(600));
+
+        let mut request = create_test_request("GET", &format!("/auth/device/{}", flow_id), vec![]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::ACCEPTED);
+        assert_eq!(response.headers().get(RETRY_AFTER).unwrap(), "5");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("WDJB-MJHT"));
+
+        // Flows of other instances pass through
+        let mut request = create_test_request("GET", &format!("/auth/device/{}", new_session_id()), vec![]);
+        assert!(plugin.handle_request(&mut request, &context).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_expired_device_flow_is_gone() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        let flow_id = add_flow(&plugin, Duration::ZERO);
+
+        let mut request = create_test_request("GET", &format!("/auth/device/{}", flow_id), vec![]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::GONE);
+        assert!(plugin.device_flows.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_device_start_without_credentials() {
+        let mut config = HashMap::new();
+        config.insert("clientIdEnv".to_string(), "TEST_UNSET_DEVICE_CLIENT_ID".to_string());
+        config.insert("clientSecretEnv".to_string(), "TEST_UNSET_DEVICE_CLIENT_SECRET".to_string());
+        let plugin = OAuth2Plugin::new(config);
+        let context = create_test_context();
+
+        let mut request = create_test_request("POST", "/auth/device", vec![]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(plugin.device_flows.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_device_starts_are_limited_per_client() {
+        let plugin = create_nested_test_plugin(vec![("trustForwardedHeaders", "true")]);
+        let context = create_test_context();
+        let now = Instant::now();
+        plugin.device_starts.lock().unwrap()
+            .insert("192.0.2.1".to_string(), vec![now; DEVICE_STARTS_PER_CLIENT]);
+
+        let mut request = create_test_request("POST", "/auth/device", vec![("X-Forwarded-For", "192.0.2.1")]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(response.headers().contains_key(RETRY_AFTER));
+        assert!(plugin.device_flows.lock().unwrap().is_empty());
+
+        // Other clients have limits of their own
+        let request = create_test_request("POST", "/auth/device", vec![("X-Forwarded-For", "192.0.2.2")]);
+        assert!(plugin.record_device_start(&request, now).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_pending_device_flows_are_capped() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        for _ in 0..MAX_PENDING_DEVICE_FLOWS {
+            add_flow(&plugin, Duration::from_secs(600));
+        }
+
+        let mut request = create_test_request("POST", "/auth/device", vec![]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(plugin.device_flows.lock().unwrap().len(), MAX_PENDING_DEVICE_FLOWS);
+    }
+}
@@ -1,6 +1,12 @@
-use rusty_beam_plugin_api::{Plugin, PluginRequest, PluginContext, PluginResponse, create_plugin};
+mod device;
+mod redirects;
+mod revalidation;
+mod sealed;
+mod sessions;
+
+use rusty_beam_plugin_api::{capabilities, Capabilities, Plugin, PluginRequest, PluginContext, PluginResponse, create_plugin, log_error, log_info, log_warn};
 use async_trait::async_trait;
-use hyper::{Body, Response, StatusCode, Method, header::{CONTENT_TYPE, LOCATION, SET_COOKIE, COOKIE}};
+use hyper::{Body, Response, StatusCode, Method, header::{ACCEPT, AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE, HOST, LOCATION, RETRY_AFTER, SET_COOKIE, COOKIE, VARY}};
 use std::collections::HashMap;
 use oauth2::{
     AuthUrl, ClientId, ClientSecret, CsrfToken,
@@ -9,16 +15,24 @@ use oauth2::{
 };
 use serde::{Deserialize, Serialize};
 use cookie::{Cookie, SameSite, time};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, OnceLock};
 use tokio::sync::RwLock;
-use uuid::Uuid;
 use std::env;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+use device::DeviceFlow;
+use redirects::RedirectPolicy;
+use revalidation::{ProviderGrant, RevalidationSettings};
+use sealed::SessionSealer;
+use sessions::{SessionTable, SESSIONS_PATH, REVOKE_SESSIONS_PATH};
 
 // Constants
 const DEFAULT_PLUGIN_NAME: &str = "oauth2";
 const DEFAULT_PROVIDER: &str = "google";
 const GITHUB_PROVIDER: &str = "github";
 const GOOGLE_PROVIDER: &str = "google";
+const SERVER_SESSION_MODE: &str = "server";
+const COOKIE_SESSION_MODE: &str = "cookie";
 
 // OAuth2 Provider URLs
 const GITHUB_AUTH_URL: &str = "https://github.com/login/oauth/authorize";
@@ -36,7 +50,7 @@ const GOOGLE_EMAIL_SCOPE: &str = "email";
 const GOOGLE_PROFILE_SCOPE: &str = "profile";
 
 // Cookie names
-const SESSION_COOKIE_NAME: &str = "session_id";
+const DEFAULT_SESSION_COOKIE_NAME: &str = "session_id";
 const STATE_COOKIE_NAME: &str = "oauth2_state";
 const RETURN_TO_COOKIE_NAME: &str = "oauth2_return_to";
 
@@ -44,7 +58,29 @@ const RETURN_TO_COOKIE_NAME: &str = "oauth2_return_to";
 const AUTH_PATH_PREFIX: &str = "/auth/";
 const LOGOUT_PATH: &str = "/auth/logout";
 const USER_INFO_PATH: &str = "/auth/user";
+const STATUS_PATH: &str = "/auth/status";
+const DEFAULT_DEVICE_PATH: &str = "/auth/device";
 const DEFAULT_REDIRECT_PATH: &str = "/";
+const DEFAULT_COOKIE_PATH: &str = "/";
+
+// Session ids are 128 random bits written as lowercase hex
+const SESSION_ID_LENGTH: usize = 32;
+const API_TOKEN_PREFIX: &str = "rbt_";
+const API_TOKEN_HEX_LENGTH: usize = 64;
+const DEFAULT_API_TOKEN_DAYS: u64 = 90;
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const DEFAULT_SESSION_DAYS: u64 = 7;
+
+// Proxy headers, honoured when `trustForwardedHeaders` is set
+const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+const X_FORWARDED_HOST: &str = "x-forwarded-host";
+const X_FORWARDED_PREFIX: &str = "x-forwarded-prefix";
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+const DEFAULT_SCHEME: &str = "http";
+
+// Fetch metadata sent by browsers; only navigations are sent to sign in
+const SEC_FETCH_MODE: &str = "sec-fetch-mode";
+const FETCH_MODE_NAVIGATE: &str = "navigate";
 
 // HTTP User-Agent
 const USER_AGENT: &str = "Rusty-Beam-OAuth2";
@@ -52,12 +88,14 @@ const USER_AGENT: &str = "Rusty-Beam-OAuth2";
 // Error messages
 const ERROR_MISSING_CLIENT_ID_ENV: &str = "Error: 'clientIdEnv' configuration parameter is required for OAuth2Plugin";
 const ERROR_MISSING_CLIENT_SECRET_ENV: &str = "Error: 'clientSecretEnv' configuration parameter is required for OAuth2Plugin";
-const ERROR_MISSING_REDIRECT_URI_ENV: &str = "Error: 'redirectUriEnv' configuration parameter is required for OAuth2Plugin";
 const ERROR_OAUTH_NOT_CONFIGURED: &str = "OAuth2 not configured";
 const ERROR_OAUTH_FAILED: &str = "OAuth2 authentication failed";
 const ERROR_INVALID_STATE: &str = "Invalid state parameter";
 const ERROR_MISSING_CODE: &str = "Missing authorization code";
 const ERROR_USER_INFO_FAILED: &str = "Failed to fetch user information";
+const ERROR_SESSION_FAILED: &str = "Failed to create session";
+const ERROR_DOMAIN_NOT_ALLOWED: &str = "Sign-in with this email address is not permitted on this site.";
+const ERROR_EMAIL_NOT_VERIFIED: &str = "Your email address has not been verified by your sign-in provider. Please verify it and try again.";
 
 // Content types
 const CONTENT_TYPE_JSON: &str = "application/json";
@@ -66,22 +104,113 @@ const CONTENT_TYPE_HTML: &str = "text/html";
 // Role assignment
 const DEFAULT_USER_ROLE: &str = "user";
 
+/// Session storage keyed by session id and indexed by identity
+type SessionStore = Arc<RwLock<SessionTable>>;
+
+/// Linked identities keyed by provider account (`provider:user_id`), valued by identity email
+type IdentityStore = Arc<RwLock<HashMap<String, String>>>;
+
+/// API tokens issued through the device flow, keyed by token
+type ApiTokenStore = Arc<RwLock<HashMap<String, ApiToken>>>;
+
+/// Session stores shared by all OAuth2 instances in the process, keyed by registry name
+static SESSION_REGISTRIES: OnceLock<Mutex<HashMap<String, SessionStore>>> = OnceLock::new();
+
+/// Identity links shared by all OAuth2 instances in the process, keyed by registry name
+static IDENTITY_REGISTRIES: OnceLock<Mutex<HashMap<String, IdentityStore>>> = OnceLock::new();
+
+/// API tokens shared by all OAuth2 instances in the process, keyed by registry name
+static API_TOKEN_REGISTRIES: OnceLock<Mutex<HashMap<String, ApiTokenStore>>> = OnceLock::new();
+
+/// Returns the session store for a registry, creating it on first use
+fn shared_session_store(registry: &str) -> SessionStore {
+    let registries = SESSION_REGISTRIES.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut registries = registries.lock().unwrap_or_else(|e| e.into_inner());
+    registries.entry(registry.to_string())
+        .or_insert_with(|| Arc::new(RwLock::new(SessionTable::default())))
+        .clone()
+}
+
+/// Returns the identity links for a registry, creating them on first use
+fn shared_identity_store(registry: &str) -> IdentityStore {
+    let registries = IDENTITY_REGISTRIES.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut registries = registries.lock().unwrap_or_else(|e| e.into_inner());
+    registries.entry(registry.to_string())
+        .or_insert_with(|| Arc::new(RwLock::new(HashMap::new())))
+        .clone()
+}
+
+/// Returns the API tokens for a registry, creating them on first use
+fn shared_api_token_store(registry: &str) -> ApiTokenStore {
+    let registries = API_TOKEN_REGISTRIES.get_or_init(|| Mutex::new(HashMap::new()));
+    let mut registries = registries.lock().unwrap_or_else(|e| e.into_inner());
+    registries.entry(registry.to_string())
+        .or_insert_with(|| Arc::new(RwLock::new(HashMap::new())))
+        .clone()
+}
+
 /// OAuth2 Authentication Plugin
 #[derive(Debug)]
 pub struct OAuth2Plugin {
     name: String,
     client_id: String,
     client_secret: String,
+    /// Absolute URL, path, or empty to build it from the request
     redirect_uri: String,
     client_id_env: String,
     client_secret_env: String,
     redirect_uri_env: String,
     login_path: String,
+    /// Path the provider's callback is answered on
+    callback_path: String,
+    /// Whether X-Forwarded-Proto/Host/Prefix describe the URL clients see
+    trust_forwarded_headers: bool,
     provider: String,
     auth_url: String,
     token_url: String,
     user_info_url: String,
-    sessions: Arc<RwLock<HashMap<String, SessionData>>>,
+    session_file: Option<PathBuf>,
+    /// Shared with every instance configured with the same session registry,
+    /// or this instance's own without one
+    sessions: SessionStore,
+    /// Provider accounts linked to a single identity, shared like `sessions`
+    identities: IdentityStore,
+    /// Lowercase email domains permitted to sign in; empty allows any domain
+    allowed_domains: Vec<String>,
+    require_verified_email: bool,
+    /// Where users may be sent after signing in or out
+    redirect_policy: RedirectPolicy,
+    session_cookie: SessionCookie,
+    /// Seals sessions into the session cookie in the `cookie` session mode;
+    /// `None` keeps them in `sessions`
+    sealer: Option<SessionSealer>,
+    /// Path CLI clients start the device flow on; flows are polled below it
+    device_path: String,
+    /// Device flows waiting for the user, keyed by the id their client polls with
+    device_flows: Mutex<HashMap<String, DeviceFlow>>,
+    /// When each client recently started device flows, to limit how many it starts
+    device_starts: Mutex<HashMap<String, Vec<std::time::Instant>>>,
+    /// Shared like `sessions`, so tokens identify users to every instance
+    api_tokens: ApiTokenStore,
+    api_token_file: Option<PathBuf>,
+    /// How long issued API tokens are valid; `None` if they never expire
+    api_token_lifetime: Option<Duration>,
+    /// Turn 401 and 403 responses to anonymous page requests into a redirect
+    /// to the login path
+    login_redirect: bool,
+    /// How sessions are checked with the provider again; `None` if they aren't
+    revalidation: Option<RevalidationSettings>,
+}
+
+/// Name and attributes of the session cookie
+#[derive(Debug, Clone)]
+struct SessionCookie {
+    name: String,
+    domain: Option<String>,
+    path: String,
+    same_site: SameSite,
+    /// Mark the cookie Secure even when the client's URL is plain http
+    always_secure: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -91,17 +220,60 @@ struct SessionData {
     picture: Option<String>,
     provider: String,  // Add provider identification
     created_at: std::time::SystemTime,
+    /// Account id assigned by the provider, stable across email changes
+    #[serde(default)]
+    provider_user_id: Option<String>,
+    #[serde(default)]
+    email_verified: bool,
+    /// Every provider account (`provider:user_id`) linked to this identity
+    #[serde(default)]
+    linked_accounts: Vec<String>,
+    /// User-Agent of the client that signed in
+    #[serde(default)]
+    user_agent: Option<String>,
+    /// When the session was last used, to the nearest minute
+    #[serde(default)]
+    last_seen: Option<SystemTime>,
+    /// The provider's tokens, kept to check the session with it again
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    grant: Option<ProviderGrant>,
+}
+
+impl SessionData {
+    /// Key identifying the provider account behind this session
+    fn account_key(&self) -> Option<String> {
+        self.provider_user_id.as_ref().map(|id| format!("{}:{}", self.provider, id))
+    }
+}
+
+/// A long-lived token CLI clients present as `Authorization: Bearer`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ApiToken {
+    user: SessionData,
+    /// When the token stops being accepted; `None` if it never expires
+    expires_at: Option<SystemTime>,
+}
+
+impl ApiToken {
+    fn is_expired(&self) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= SystemTime::now())
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct GoogleUserInfo {
+    #[serde(default)]
+    id: Option<String>,
     email: String,
+    #[serde(default)]
+    verified_email: bool,
     name: String,
     picture: Option<String>,
 }
 
 #[derive(Debug, Deserialize)]
 struct GitHubUserInfo {
+    id: u64,
     email: Option<String>,
     name: Option<String>,
     login: String,
@@ -124,18 +296,35 @@ impl OAuth2Plugin {
             .expect(ERROR_MISSING_CLIENT_ID_ENV);
         let client_secret_env = config.get("clientSecretEnv").cloned()
             .expect(ERROR_MISSING_CLIENT_SECRET_ENV);
-        let redirect_uri_env = config.get("redirectUriEnv").cloned()
-            .expect(ERROR_MISSING_REDIRECT_URI_ENV);
+        // Without a redirect URI it is built from each request
+        let redirect_uri_env = config.get("redirectUriEnv").cloned().unwrap_or_default();
         
         // Read values from environment variables for security
         let client_id = env::var(&client_id_env).unwrap_or_default();
         let client_secret = env::var(&client_secret_env).unwrap_or_default();
-        let redirect_uri = env::var(&redirect_uri_env).unwrap_or_default();
+        let redirect_uri = if redirect_uri_env.is_empty() {
+            String::new()
+        } else {
+            env::var(&redirect_uri_env).unwrap_or_default()
+        };
         
         // Get login path from config with default
         let login_path = config.get("loginPath").cloned()
             .unwrap_or_else(|| format!("/auth/{}/login", name));
         
+        // The callback path defaults to the path of the redirect URI
+        let callback_path = config.get("callbackPath").cloned()
+            .or_else(|| match url::Url::parse(&redirect_uri) {
+                Ok(url) => Some(url.path().to_string()),
+                Err(_) if redirect_uri.starts_with('/') => Some(redirect_uri.split('?').next().unwrap_or_default().to_string()),
+                Err(_) => None,
+            })
+            .unwrap_or_else(|| format!("/auth/{}/callback", name));
+        
+        let trust_forwarded_headers = config.get("trustForwardedHeaders")
+            .map(|v| v.to_lowercase() == "true")
+            .unwrap_or(false);
+        
         // Get provider from config with default based on name
         let provider = config.get("provider").cloned()
             .unwrap_or_else(|| {
@@ -160,6 +349,109 @@ impl OAuth2Plugin {
             ),
         };
         
+        // Optional file used to persist sessions across restarts
+        let session_file = config.get("sessionFile")
+            .map(|path| PathBuf::from(path.strip_prefix("file://").unwrap_or(path)));
+        
+        // Instances sharing a registry see each other's sessions, so identity
+        // and logout are consistent across providers. Without one an instance
+        // keeps its own, so hosts never recognise each other's sessions.
+        let session_registry = config.get("sessionRegistry").filter(|registry| !registry.is_empty());
+        
+        // Restrict sign-in to particular email domains
+        let allowed_domains = config.get("allowedDomains")
+            .map(|domains| domains.split(',')
+                .map(|domain| domain.trim().trim_start_matches('@').to_lowercase())
+                .filter(|domain| !domain.is_empty())
+                .collect())
+            .unwrap_or_default();
+        
+        let require_verified_email = config.get("requireVerifiedEmail")
+            .map(|v| v.to_lowercase() == "true")
+            .unwrap_or(false);
+        
+        let (redirect_policy, invalid_redirects) = RedirectPolicy::parse(
+            config.get("allowedRedirects").map(String::as_str).unwrap_or_default()
+        );
+        for entry in invalid_redirects {
+            log_error!(name, "Ignoring invalid allowedRedirects entry '{}'", entry);
+        }
+        
+        let same_site = match config.get("cookieSameSite").map(|v| v.to_lowercase()).as_deref() {
+            None | Some("lax") => SameSite::Lax,
+            Some("strict") => SameSite::Strict,
+            Some("none") => SameSite::None,
+            Some(other) => {
+                log_error!(name, "Unknown cookieSameSite value '{}', using Lax", other);
+                SameSite::Lax
+            }
+        };
+        let session_cookie = SessionCookie {
+            name: config.get("cookieName").cloned()
+                .unwrap_or_else(|| DEFAULT_SESSION_COOKIE_NAME.to_string()),
+            domain: config.get("cookieDomain").cloned().filter(|domain| !domain.is_empty()),
+            path: config.get("cookiePath").cloned()
+                .unwrap_or_else(|| DEFAULT_COOKIE_PATH.to_string()),
+            same_site,
+            // Browsers drop SameSite=None cookies that are not Secure
+            always_secure: same_site == SameSite::None || config.get("cookieSecure")
+                .map(|v| v.to_lowercase() == "true")
+                .unwrap_or(false),
+        };
+        
+        // Sessions are kept on the server unless sealed into the cookie
+        let sealer = match config.get("sessionMode").map(|v| v.to_lowercase()).as_deref() {
+            None | Some(SERVER_SESSION_MODE) => None,
+            Some(COOKIE_SESSION_MODE) => {
+                let session_days = match config.get("sessionDays").map(|days| days.trim().parse::<u64>()) {
+                    None => DEFAULT_SESSION_DAYS,
+                    Some(Ok(days)) if days > 0 => days,
+                    Some(_) => {
+                        log_error!(name, "Invalid sessionDays value, using {}", DEFAULT_SESSION_DAYS);
+                        DEFAULT_SESSION_DAYS
+                    }
+                };
+                let keys_env = config.get("sessionKeysEnv").cloned().unwrap_or_default();
+                let keys = env::var(&keys_env).unwrap_or_default();
+                match SessionSealer::from_keys(&keys, Duration::from_secs(session_days * SECONDS_PER_DAY)) {
+                    Ok(sealer) => Some(sealer),
+                    Err(e) => {
+                        log_error!(name, "Cannot seal sessions into cookies ({}), keeping them on the server", e; env = keys_env);
+                        None
+                    }
+                }
+            }
+            Some(other) => {
+                log_error!(name, "Unknown sessionMode value '{}', keeping sessions on the server", other);
+                None
+            }
+        };
+        
+        let device_path = config.get("devicePath").cloned()
+            .unwrap_or_else(|| DEFAULT_DEVICE_PATH.to_string());
+        let api_token_file = config.get("apiTokenFile")
+            .map(|path| PathBuf::from(path.strip_prefix("file://").unwrap_or(path)));
+        // 0 issues tokens that never expire
+        let api_token_days = match config.get("apiTokenDays").map(|days| days.trim().parse::<u64>()) {
+            None => DEFAULT_API_TOKEN_DAYS,
+            Some(Ok(days)) => days,
+            Some(Err(_)) => {
+                log_error!(name, "Invalid apiTokenDays value, using {}", DEFAULT_API_TOKEN_DAYS);
+                DEFAULT_API_TOKEN_DAYS
+            }
+        };
+        let api_token_lifetime = (api_token_days > 0)
+            .then(|| Duration::from_secs(api_token_days * SECONDS_PER_DAY));
+        
+        let login_redirect = config.get("loginRedirect")
+            .map(|v| v.to_lowercase() == "true")
+            .unwrap_or(false);
+        
+        let revalidation = RevalidationSettings::from_config(&config, &name);
+        if revalidation.is_some() && sealer.is_some() {
+            log_error!(name, "Sessions sealed into cookies cannot be revalidated");
+        }
+        
         Self {
             name,
             client_id,
@@ -169,15 +461,33 @@ impl OAuth2Plugin {
             client_secret_env,
             redirect_uri_env,
             login_path,
+            callback_path,
+            trust_forwarded_headers,
             provider,
             auth_url,
             token_url,
             user_info_url,
-            sessions: Arc::new(RwLock::new(HashMap::new())),
+            session_file,
+            sessions: session_registry.map_or_else(Default::default, |registry| shared_session_store(registry)),
+            identities: session_registry.map_or_else(Default::default, |registry| shared_identity_store(registry)),
+            allowed_domains,
+            require_verified_email,
+            redirect_policy,
+            session_cookie,
+            sealer,
+            device_path,
+            device_flows: Mutex::new(HashMap::new()),
+            device_starts: Mutex::new(HashMap::new()),
+            api_tokens: session_registry.map_or_else(Default::default, |registry| shared_api_token_store(registry)),
+            api_token_file,
+            api_token_lifetime,
+            login_redirect,
+            revalidation,
         }
     }
     
-    fn create_oauth_client(&self) -> Result<BasicClient, String> {
+    /// Fails unless the client credentials were found in the environment
+    fn check_credentials(&self) -> Result<(), String> {
         let mut missing = Vec::new();
         if self.client_id.is_empty() {
             missing.push(format!("client_id (env var: {})", self.client_id_env));
@@ -185,13 +495,14 @@ impl OAuth2Plugin {
         if self.client_secret.is_empty() {
             missing.push(format!("client_secret (env var: {})", self.client_secret_env));
         }
-        if self.redirect_uri.is_empty() {
-            missing.push(format!("redirect_uri (env var: {})", self.redirect_uri_env));
-        }
-        
         if !missing.is_empty() {
             return Err(format!("OAuth2 configuration error: Missing {}. Please set the corresponding environment variables.", missing.join(", ")));
         }
+        Ok(())
+    }
+    
+    fn create_oauth_client(&self, redirect_uri: &str) -> Result<BasicClient, String> {
+        self.check_credentials()?;
         
         let auth_url = AuthUrl::new(self.auth_url.clone())
             .map_err(|e| format!("Invalid auth URL: {}", e))?;
@@ -206,8 +517,8 @@ impl OAuth2Plugin {
             Some(token_url),
         )
         .set_redirect_uri(
-            RedirectUrl::new(self.redirect_uri.clone())
-                .map_err(|e| format!("Invalid redirect URI: {}", e))?
+            RedirectUrl::new(redirect_uri.to_string())
+                .map_err(|e| format!("Invalid redirect URI ({}): {}", self.redirect_uri_env, e))?
         ))
     }
 }
@@ -215,37 +526,55 @@ impl OAuth2Plugin {
 #[async_trait]
 impl Plugin for OAuth2Plugin {
     async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
-        // Check if user is authenticated via session for ALL requests
-        if let Some(session_id) = self.get_session_id_from_request(request) {
-            let sessions = self.sessions.read().await;
-            if let Some(session_data) = sessions.get(&session_id) {
-                // Only set authenticated_user metadata if this session belongs to our provider
-                if session_data.provider == self.provider {
-                    request.metadata.insert("authenticated_user".to_string(), session_data.email.clone());
-                    // Automatically assign "user" role to any authenticated user
-                    request.metadata.insert("authenticated_user_roles".to_string(), DEFAULT_USER_ROLE.to_string());
-                    context.log_verbose(&format!("[OAuth2-{}] User {} authenticated via session with role: user", self.provider, session_data.email));
-                } else {
-                    context.log_verbose(&format!("[OAuth2-{}] Session belongs to different provider: {}", self.provider, session_data.provider));
-                }
+        // A session the provider no longer vouches for ends before it is used
+        self.revalidate_request_session(request, context).await;
+        
+        // Check if user is authenticated via session or API token for ALL requests
+        if let Some((session_data, credential)) = self.authenticate(request).await {
+            // Sessions and tokens from any provider in the registry identify the user
+            request.metadata.insert("authenticated_user".to_string(), session_data.email.clone());
+            request.metadata.insert("authenticated_user_provider".to_string(), session_data.provider.clone());
+            if let Some(provider_user_id) = &session_data.provider_user_id {
+                request.metadata.insert("authenticated_user_provider_id".to_string(), provider_user_id.clone());
+            }
+            // Automatically assign "user" role to any authenticated user
+            request.metadata.insert("authenticated_user_roles".to_string(), DEFAULT_USER_ROLE.to_string());
+            context.log_verbose(&format!("[OAuth2-{}] User {} authenticated via {} {} with role: user", self.provider, session_data.email, session_data.provider, credential));
+            if credential == "session" {
+                self.record_activity(request).await;
             }
         }
         
-        // Only handle specific auth endpoints
-        if !request.path.starts_with(AUTH_PATH_PREFIX) {
+        // Only handle specific auth endpoints, which may lie outside /auth/
+        // when the plugin is nested under a directory
+        let is_callback = self.is_callback_path(request);
+        let device_flow_id = self.device_flow_id(request);
+        if !request.path.starts_with(AUTH_PATH_PREFIX) && request.path != self.login_path && !is_callback
+            && request.path != self.device_path && device_flow_id.is_none() {
             return None;
         }
         
-        let callback_path = self.get_callback_path();
+        if let (&Method::GET, Some(flow_id)) = (request.http_request.method(), &device_flow_id) {
+            // Flows of other instances sharing the device path pass through to them
+            return self.handle_device_poll(request, flow_id, context).await.map(Into::into);
+        }
         
         match request.http_request.method() {
             &Method::GET if request.path == self.login_path => Some(self.handle_login(request, context).await.into()),
-            &Method::GET if request.path == callback_path => Some(self.handle_callback(request, context).await.into()),
+            &Method::GET if is_callback => Some(self.handle_callback(request, context).await.into()),
+            &Method::POST if request.path == self.device_path => Some(self.handle_device_start(request, context).await.into()),
             &Method::POST if request.path == LOGOUT_PATH => {
-                // Only handle logout if we have a session for this user
-                if let Some(session_id) = self.get_session_id_from_request(request) {
-                    if self.sessions.read().await.contains_key(&session_id) {
-                        context.log_verbose(&format!("[OAuth2-{}] Handling logout for session {}", self.provider, session_id));
+                // Clients holding an API token log out by revoking it
+                if self.revoke_api_token(request).await {
+                    context.log_verbose(&format!("[OAuth2-{}] Revoked API token", self.provider));
+                    Some(Response::builder()
+                        .status(StatusCode::NO_CONTENT)
+                        .body(Body::empty())
+                        .unwrap()
+                        .into())
+                } else if self.get_cookie_value(request, &self.session_cookie.name).is_some() {
+                    if let Some(session_data) = self.find_request_session(request).await {
+                        context.log_verbose(&format!("[OAuth2-{}] Handling logout for {}", self.provider, session_data.email));
                         Some(self.handle_logout(request, context).await.into())
                     } else {
                         context.log_verbose(&format!("[OAuth2-{}] No session found for logout, passing through", self.provider));
@@ -258,72 +587,332 @@ impl Plugin for OAuth2Plugin {
                 }
             },
             &Method::GET if request.path == USER_INFO_PATH => {
-                // Only respond if we have a valid session for this request
-                if let Some(session_id) = self.get_session_id_from_request(request) {
-                    if let Some(session_data) = self.sessions.read().await.get(&session_id) {
-                        if session_data.provider == self.provider {
-                            // We have a valid session - return user info
-                            context.log_verbose(&format!("[OAuth2-{}] Returning user info for {}", self.provider, session_data.email));
-                            Some(self.handle_user_info(session_data).await.into())
-                        } else {
-                            // Session belongs to different provider
-                            context.log_verbose(&format!("[OAuth2-{}] Session belongs to different provider: {}", self.provider, session_data.provider));
-                            None
-                        }
-                    } else {
-                        // No session with this ID in our storage
-                        context.log_verbose(&format!("[OAuth2-{}] No session found for id: {}", self.provider, session_id));
-                        None
-                    }
+                // Only respond if we have a valid session or API token for this request
+                if let Some((session_data, credential)) = self.authenticate(request).await {
+                    // Return user info whichever provider created the session or token
+                    context.log_verbose(&format!("[OAuth2-{}] Returning user info for {} ({} {})", self.provider, session_data.email, session_data.provider, credential));
+                    Some(self.handle_user_info(request, &session_data).await.into())
                 } else {
-                    // No session cookie at all
-                    context.log_verbose(&format!("[OAuth2-{}] No session cookie in request", self.provider));
+                    context.log_verbose(&format!("[OAuth2-{}] No session or API token in request", self.provider));
                     None
                 }
             },
+            &Method::GET if request.path == STATUS_PATH => Some(self.handle_status(request, context).await.into()),
+            &Method::GET if request.path == SESSIONS_PATH => {
+                let (user, _) = self.authenticate(request).await?;
+                Some(self.handle_list_sessions(request, &user, context).await.into())
+            },
+            &Method::POST if request.path == REVOKE_SESSIONS_PATH => {
+                let (user, _) = self.authenticate(request).await?;
+                Some(self.handle_revoke_sessions(request, &user, context).await.into())
+            },
             _ => None,
         }
     }
     
+    async fn handle_response(&self, request: &PluginRequest, response: &mut Response<Body>, context: &PluginContext) {
+        if !self.wants_login_redirect(request, response.status()) {
+            return;
+        }
+        let login_url = self.login_redirect_url(request);
+        context.log_verbose(&format!("[OAuth2-{}] {} for anonymous page request {}, redirecting to {}", self.provider, response.status(), request.path, login_url));
+        *response = Response::builder()
+            .status(StatusCode::FOUND)
+            .header(LOCATION, login_url)
+            .header(CACHE_CONTROL, "no-store")
+            .header(VARY, ACCEPT)
+            .body(Body::empty())
+            .unwrap();
+    }
+    
+    async fn on_startup(&self) {
+        self.load_sessions().await;
+        self.load_api_tokens().await;
+    }
+    
+    async fn on_shutdown(&self) {
+        self.save_sessions().await;
+        self.save_api_tokens().await;
+    }
+    
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::new(&[capabilities::AUTHENTICATION], &[])
+    }
+    
     fn name(&self) -> &str {
         &self.name
     }
 }
 
 impl OAuth2Plugin {
-    fn get_callback_path(&self) -> String {
-        // Extract path from redirect URI
-        if let Ok(url) = url::Url::parse(&self.redirect_uri) {
-            url.path().to_string()
+    /// Restores sessions previously written by `save_sessions`
+    async fn load_sessions(&self) {
+        let Some(path) = &self.session_file else { return };
+        // Files written by earlier versions may be readable by anyone, yet hold provider tokens
+        if let Err(e) = restrict_to_owner(path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log_warn!(self.name, "Failed to restrict session file permissions: {}", e; provider = self.provider, file = path.display());
+            }
+        }
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                log_error!(self.name, "Failed to read session file: {}", e; provider = self.provider, file = path.display());
+                return;
+            }
+        };
+        match serde_json::from_str::<HashMap<String, SessionData>>(&content) {
+            Ok(stored) => {
+                let mut sessions = self.sessions.write().await;
+                let mut identities = self.identities.write().await;
+                // Sessions from versions with another id format cannot be presented any more
+                for (id, data) in stored.into_iter().filter(|(id, data)| data.provider == self.provider && is_session_id(id)) {
+                    for account in &data.linked_accounts {
+                        identities.entry(account.clone()).or_insert_with(|| data.email.clone());
+                    }
+                    sessions.insert(id, data);
+                }
+            }
+            Err(e) => log_error!(self.name, "Failed to parse session file: {}", e; provider = self.provider, file = path.display()),
+        }
+    }
+    
+    /// Writes this provider's sessions to the configured session file
+    async fn save_sessions(&self) {
+        let Some(path) = &self.session_file else { return };
+        // The store may be shared with other providers, so only persist our own sessions
+        let sessions: HashMap<String, SessionData> = self.sessions.read().await.iter()
+            .filter(|(_, data)| data.provider == self.provider)
+            .map(|(id, data)| (id.clone(), data.clone()))
+            .collect();
+        let json = match serde_json::to_string(&sessions) {
+            Ok(json) => json,
+            Err(e) => {
+                log_error!(self.name, "Failed to serialize sessions: {}", e; provider = self.provider);
+                return;
+            }
+        };
+        if let Err(e) = write_private_file(path, &json) {
+            log_error!(self.name, "Failed to write session file: {}", e; provider = self.provider, file = path.display());
+        }
+    }
+    
+    /// Restores API tokens previously written by `save_api_tokens`
+    async fn load_api_tokens(&self) {
+        let Some(path) = &self.api_token_file else { return };
+        if let Err(e) = restrict_to_owner(path) {
+            if e.kind() != std::io::ErrorKind::NotFound {
+                log_warn!(self.name, "Failed to restrict API token file permissions: {}", e; provider = self.provider, file = path.display());
+            }
+        }
+        let content = match std::fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return,
+            Err(e) => {
+                log_error!(self.name, "Failed to read API token file: {}", e; provider = self.provider, file = path.display());
+                return;
+            }
+        };
+        match serde_json::from_str::<HashMap<String, ApiToken>>(&content) {
+            Ok(stored) => {
+                let mut api_tokens = self.api_tokens.write().await;
+                for (token, api_token) in stored.into_iter()
+                    .filter(|(token, api_token)| api_token.user.provider == self.provider && is_api_token(token) && !api_token.is_expired())
+                {
+                    api_tokens.insert(token, api_token);
+                }
+            }
+            Err(e) => log_error!(self.name, "Failed to parse API token file: {}", e; provider = self.provider, file = path.display()),
+        }
+    }
+    
+    /// Writes this provider's unexpired API tokens to the configured token file
+    ///
+    /// Called whenever a token is issued or revoked as well as on shutdown,
+    /// since tokens are meant to outlive the process.
+    async fn save_api_tokens(&self) {
+        let Some(path) = &self.api_token_file else { return };
+        let api_tokens: HashMap<String, ApiToken> = self.api_tokens.read().await.iter()
+            .filter(|(_, api_token)| api_token.user.provider == self.provider && !api_token.is_expired())
+            .map(|(token, api_token)| (token.clone(), api_token.clone()))
+            .collect();
+        let json = match serde_json::to_string(&api_tokens) {
+            Ok(json) => json,
+            Err(e) => {
+                log_error!(self.name, "Failed to serialize API tokens: {}", e; provider = self.provider);
+                return;
+            }
+        };
+        // The file holds bearer tokens, so only the server's user may read it
+        if let Err(e) = write_private_file(path, &json) {
+            log_error!(self.name, "Failed to write API token file: {}", e; provider = self.provider, file = path.display());
+        }
+    }
+    
+    /// The user behind the request's session cookie or API token, and which
+    /// of the two identified them
+    async fn authenticate(&self, request: &PluginRequest) -> Option<(SessionData, &'static str)> {
+        if let Some(session_data) = self.find_request_session(request).await {
+            return Some((session_data, "session"));
+        }
+        let token = bearer_token(request)?;
+        let api_tokens = self.api_tokens.read().await;
+        find_api_token(&api_tokens, token).map(|(_, api_token)| (api_token.user.clone(), "API token"))
+    }
+    
+    /// Issues an API token for a user, valid for the configured lifetime
+    async fn issue_api_token(&self, user: SessionData) -> (String, ApiToken) {
+        let token = new_api_token();
+        let api_token = ApiToken {
+            user,
+            expires_at: self.api_token_lifetime.map(|lifetime| SystemTime::now() + lifetime),
+        };
+        {
+            let mut api_tokens = self.api_tokens.write().await;
+            api_tokens.retain(|_, api_token| !api_token.is_expired());
+            api_tokens.insert(token.clone(), api_token.clone());
+        }
+        self.save_api_tokens().await;
+        (token, api_token)
+    }
+    
+    /// Revokes the API token the request presents, returning whether it was valid
+    async fn revoke_api_token(&self, request: &PluginRequest) -> bool {
+        let Some(token) = bearer_token(request) else { return false };
+        let revoked = {
+            let mut api_tokens = self.api_tokens.write().await;
+            let stored = find_api_token(&api_tokens, token).map(|(stored, _)| stored.clone());
+            stored.and_then(|stored| api_tokens.remove(&stored)).is_some()
+        };
+        if revoked {
+            self.save_api_tokens().await;
+        }
+        revoked
+    }
+    
+    /// Whether the request is for the callback path, either as the server
+    /// sees it or as clients see it through a proxy's path prefix
+    fn is_callback_path(&self, request: &PluginRequest) -> bool {
+        request.path == self.callback_path || self.external_path(request, &request.path) == self.callback_path
+    }
+    
+    /// A trusted forwarded header of the request
+    fn forwarded_header<'a>(&self, request: &'a PluginRequest, name: &str) -> Option<&'a str> {
+        if !self.trust_forwarded_headers {
+            return None;
+        }
+        request.http_request.headers().get(name)
+            .and_then(|value| value.to_str().ok())
+            // Proxies in a chain append their values; the first is the client's
+            .and_then(|value| value.split(',').next())
+            .map(str::trim)
+            .filter(|value| !value.is_empty())
+    }
+    
+    /// Scheme of the URL the client requested
+    fn request_scheme<'a>(&self, request: &'a PluginRequest) -> &'a str {
+        self.forwarded_header(request, X_FORWARDED_PROTO)
+            .or_else(|| request.http_request.uri().scheme_str())
+            .unwrap_or(DEFAULT_SCHEME)
+    }
+    
+    /// Path of the client's URL for a path of this server, adding the
+    /// prefix a proxy strips before forwarding
+    fn external_path(&self, request: &PluginRequest, path: &str) -> String {
+        match self.forwarded_header(request, X_FORWARDED_PREFIX) {
+            Some(prefix) => format!("{}{}", prefix.trim_end_matches('/'), path),
+            None => path.to_string(),
+        }
+    }
+    
+    /// Redirect URI for this request
+    ///
+    /// A configured absolute URI is used as is. Otherwise it is built from the
+    /// origin the client used, and the configured path or the callback path.
+    fn redirect_uri_for(&self, request: &PluginRequest) -> String {
+        if url::Url::parse(&self.redirect_uri).is_ok() {
+            return self.redirect_uri.clone();
+        }
+        if self.redirect_uri.starts_with('/') {
+            self.external_url(request, &self.redirect_uri)
         } else {
-            // Fallback if URI parsing fails
-            format!("/auth/{}/callback", self.name)
+            self.external_url(request, &self.callback_path)
         }
     }
     
-    fn get_session_id_from_request(&self, request: &PluginRequest) -> Option<String> {
-        // Parse cookies from request
-        request.http_request.headers()
-            .get(COOKIE)
+    /// Absolute URL clients use for a path of this server, built from the
+    /// origin of the request
+    fn external_url(&self, request: &PluginRequest, path: &str) -> String {
+        let host = self.forwarded_header(request, X_FORWARDED_HOST)
+            .or_else(|| request.http_request.headers().get(HOST).and_then(|value| value.to_str().ok()))
+            .or_else(|| request.http_request.uri().authority().map(|authority| authority.as_str()))
+            .unwrap_or_default();
+        format!("{}://{}{}", self.request_scheme(request), host, self.external_path(request, path))
+    }
+    
+    /// Where to send the user after signing in or out: the requested target
+    /// if the redirect policy permits it, or the site's root
+    fn return_to(&self, request: &PluginRequest, target: Option<String>, context: &PluginContext) -> String {
+        match target {
+            Some(target) if self.redirect_policy.permits(&target) => target,
+            Some(target) => {
+                context.log_verbose(&format!("[OAuth2-{}] Ignoring return_to {}, not an allowed redirect", self.provider, target));
+                self.external_path(request, DEFAULT_REDIRECT_PATH)
+            }
+            None => self.external_path(request, DEFAULT_REDIRECT_PATH),
+        }
+    }
+    
+    /// Whether a response with a status should become a redirect to sign in:
+    /// it refuses an anonymous browser navigation to an HTML page, rather
+    /// than a script or API client that handles the status itself
+    fn wants_login_redirect(&self, request: &PluginRequest, status: StatusCode) -> bool {
+        let headers = request.http_request.headers();
+        let is_navigation = headers.get(SEC_FETCH_MODE)
             .and_then(|value| value.to_str().ok())
-            .and_then(|cookies| {
-                cookies.split(';')
-                    .filter_map(|cookie| {
-                        let parts: Vec<&str> = cookie.trim().splitn(2, '=').collect();
-                        if parts.len() == 2 && parts[0] == SESSION_COOKIE_NAME {
-                            Some(parts[1].to_string())
-                        } else {
-                            None
-                        }
-                    })
-                    .next()
-            })
+            .is_none_or(|mode| mode.eq_ignore_ascii_case(FETCH_MODE_NAVIGATE));
+        self.login_redirect
+            && matches!(status, StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)
+            && matches!(*request.http_request.method(), Method::GET | Method::HEAD)
+            && !request.metadata.contains_key("authenticated_user")
+            && headers.get(AUTHORIZATION).is_none()
+            && request.path != self.login_path
+            && is_navigation
+            && prefers_html(request)
+    }
+    
+    /// The login URL, asking to come back to the URL of the request
+    fn login_redirect_url(&self, request: &PluginRequest) -> String {
+        let target = request.http_request.uri().path_and_query()
+            .map(|path_and_query| path_and_query.as_str())
+            .unwrap_or(&request.path);
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("return_to", &self.external_path(request, target))
+            .finish();
+        format!("{}?{}", self.external_path(request, &self.login_path), query)
+    }
+    
+    /// The session the request's cookie identifies or, in the `cookie`
+    /// session mode, holds
+    async fn find_request_session(&self, request: &PluginRequest) -> Option<SessionData> {
+        let value = self.get_cookie_value(request, &self.session_cookie.name)?;
+        match &self.sealer {
+            Some(sealer) => sealer.open(&value, &self.session_cookie.name),
+            None => find_session(&*self.sessions.read().await, &value).cloned(),
+        }
+    }
+    
+    /// The session id the request presents, if it has the form of one this server issues
+    fn get_session_id_from_request(&self, request: &PluginRequest) -> Option<String> {
+        self.get_cookie_value(request, &self.session_cookie.name)
+            .filter(|session_id| is_session_id(session_id))
     }
     
     async fn handle_login(&self, request: &PluginRequest, context: &PluginContext) -> Response<Body> {
         context.log_verbose("[OAuth2] Handling login request");
         
-        let client = match self.create_oauth_client() {
+        let client = match self.create_oauth_client(&self.redirect_uri_for(request)) {
             Ok(client) => client,
             Err(e) => {
                 return Response::builder()
@@ -345,6 +934,13 @@ impl OAuth2Plugin {
                 auth_builder = auth_builder
                     .add_scope(Scope::new(GOOGLE_EMAIL_SCOPE.to_string()))
                     .add_scope(Scope::new(GOOGLE_PROFILE_SCOPE.to_string()));
+                // Google access tokens last an hour, so checking sessions
+                // later needs a refresh token, which it only issues on consent
+                if self.revalidation.is_some() {
+                    auth_builder = auth_builder
+                        .add_extra_param("access_type", "offline")
+                        .add_extra_param("prompt", "consent");
+                }
             }
         }
         
@@ -367,6 +963,7 @@ impl OAuth2Plugin {
             .and_then(|q| url::form_urlencoded::parse(q.as_bytes())
                 .find(|(k, _)| k == "return_to")
                 .map(|(_, v)| v.to_string()))
+            .filter(|return_to| self.redirect_policy.permits(return_to))
         {
             context.log_verbose(&format!("[OAuth2-{}] Login: Setting return_to cookie to {}", self.provider, return_to));
             let return_cookie = Cookie::build(RETURN_TO_COOKIE_NAME, return_to)
@@ -376,8 +973,8 @@ impl OAuth2Plugin {
                 .finish();
             headers.push((SET_COOKIE, return_cookie.to_string()));
         } else {
-            context.log_verbose(&format!("[OAuth2-{}] Login: No return_to specified, clearing cookie", self.provider));
-            // Clear any existing return_to cookie if no return_to is specified
+            context.log_verbose(&format!("[OAuth2-{}] Login: No allowed return_to specified, clearing cookie", self.provider));
+            // Clear any existing return_to cookie if no allowed return_to is specified
             let clear_cookie = Cookie::build(RETURN_TO_COOKIE_NAME, "")
                 .http_only(true)
                 .same_site(SameSite::Lax)
@@ -444,23 +1041,23 @@ impl OAuth2Plugin {
     }
     
     /// Create and validate OAuth client
-    fn create_and_validate_oauth_client(&self, context: &PluginContext) -> Result<BasicClient, Response<Body>> {
-        match self.create_oauth_client() {
+    fn create_and_validate_oauth_client(&self, redirect_uri: &str, context: &PluginContext) -> Result<BasicClient, Box<Response<Body>>> {
+        match self.create_oauth_client(redirect_uri) {
             Ok(client) => Ok(client),
             Err(e) => {
                 context.log_verbose(&format!("[OAuth2] Failed to create client: {}", e));
-                Err(Response::builder()
+                Err(Box::new(Response::builder()
                     .status(StatusCode::INTERNAL_SERVER_ERROR)
                     .body(Body::from(format!("OAuth2 configuration error: {}", e)))
-                    .unwrap())
+                    .unwrap()))
             }
         }
     }
     
-    /// Exchange authorization code for access token
-    async fn exchange_authorization_code(&self, code: &str, context: &PluginContext) -> Result<String, Response<Body>> {
-        match self.exchange_code_for_token(code, context).await {
-            Ok(token) => Ok(token),
+    /// Exchange authorization code for the provider's tokens
+    async fn exchange_authorization_code(&self, code: &str, redirect_uri: &str, context: &PluginContext) -> Result<ProviderGrant, Response<Body>> {
+        match self.exchange_code_for_token(code, redirect_uri, context).await {
+            Ok(grant) => Ok(grant),
             Err(e) => {
                 context.log_verbose(&format!("[OAuth2-{}] Token exchange failed: {}", self.provider, e));
                 Err(Response::builder()
@@ -485,25 +1082,128 @@ impl OAuth2Plugin {
         }
     }
     
-    /// Create user session
-    async fn create_user_session(&self, session_data: SessionData, context: &PluginContext) -> String {
-        let session_id = Uuid::new_v4().to_string();
+    /// Rejects users outside the allowed domains or without a verified email
+    fn check_login_permitted(&self, session_data: &SessionData, context: &PluginContext) -> Result<(), Box<Response<Body>>> {
+        if self.require_verified_email && !session_data.email_verified {
+            context.log_verbose(&format!("[OAuth2-{}] Rejected unverified email: {}", self.provider, session_data.email));
+            return Err(Box::new(login_forbidden_response(ERROR_EMAIL_NOT_VERIFIED)));
+        }
+        
+        if !self.is_domain_allowed(&session_data.email) {
+            context.log_verbose(&format!("[OAuth2-{}] Rejected email outside allowed domains: {}", self.provider, session_data.email));
+            return Err(Box::new(login_forbidden_response(ERROR_DOMAIN_NOT_ALLOWED)));
+        }
+        
+        Ok(())
+    }
+    
+    /// Whether an email is in one of the allowed domains, if any are set
+    fn is_domain_allowed(&self, email: &str) -> bool {
+        if self.allowed_domains.is_empty() {
+            return true;
+        }
+        let domain = email.rsplit_once('@')
+            .map(|(_, domain)| domain.to_lowercase())
+            .unwrap_or_default();
+        self.allowed_domains.contains(&domain)
+    }
+    
+    /// Links a signing-in account to its identity if it may sign in
+    ///
+    /// The restrictions apply to the provider's email and again to the
+    /// identity the account is linked to, which keeps its email when the
+    /// provider's changes.
+    async fn admit(&self, session_data: &mut SessionData, context: &PluginContext) -> Result<(), Response<Body>> {
+        self.check_login_permitted(session_data, context).map_err(|response| *response)?;
+        self.link_identity(session_data, context).await;
+        self.check_login_permitted(session_data, context).map_err(|response| *response)
+    }
+    
+    /// Links the provider account to an identity, reusing one seen before
+    ///
+    /// An account already linked keeps its identity even if its email changes;
+    /// otherwise it joins the identity with the same email, provided the
+    /// provider has verified that email.
+    async fn link_identity(&self, session_data: &mut SessionData, context: &PluginContext) {
+        let Some(account) = session_data.account_key() else { return };
+        let mut identities = self.identities.write().await;
+        
+        let identity = match identities.get(&account) {
+            Some(identity) => identity.clone(),
+            None if session_data.email_verified => {
+                let identity = identities.values()
+                    .find(|identity| identity.eq_ignore_ascii_case(&session_data.email))
+                    .cloned()
+                    .unwrap_or_else(|| session_data.email.clone());
+                identities.insert(account.clone(), identity.clone());
+                identity
+            }
+            // Unverified emails must not be able to claim someone else's identity
+            None => {
+                session_data.linked_accounts = vec![account];
+                return;
+            }
+        };
+        
+        if identity != session_data.email {
+            context.log_verbose(&format!("[OAuth2-{}] Linked {} to identity {}", self.provider, account, identity));
+        }
+        
+        let mut linked_accounts: Vec<String> = identities.iter()
+            .filter(|(_, linked)| **linked == identity)
+            .map(|(account, _)| account.clone())
+            .collect();
+        linked_accounts.sort();
+        session_data.email = identity;
+        session_data.linked_accounts = linked_accounts;
+    }
+    
+    /// Create user session, returning the value of its cookie
+    ///
+    /// Any session the client already presents is ended, so an id planted
+    /// before login never becomes authenticated. In the `cookie` session
+    /// mode the session is sealed into the value instead of being stored.
+    async fn create_user_session(&self, request: &PluginRequest, session_data: SessionData, context: &PluginContext) -> Result<String, Response<Body>> {
+        if let Some(sealer) = &self.sealer {
+            let email = session_data.email.clone();
+            let sealed = sealer.seal(session_data, &self.session_cookie.name).map_err(|e| {
+                log_error!(self.name, "{}", e; provider = self.provider);
+                session_error_response()
+            })?;
+            if sealed.len() + self.session_cookie.name.len() > sealed::MAX_COOKIE_LENGTH {
+                log_error!(self.name, "Sealed session for {} is too long for a cookie", email; provider = self.provider, length = sealed.len());
+                return Err(session_error_response());
+            }
+            context.log_verbose(&format!("[OAuth2] Sealed session for user: {}", email));
+            return Ok(sealed);
+        }
+        
+        let session_id = new_session_id();
         context.log_verbose(&format!("[OAuth2] Created session for user: {}", session_data.email));
         
-        // Store session
-        self.sessions.write().await.insert(session_id.clone(), session_data);
-        session_id
+        let session_data = SessionData {
+            user_agent: sessions::session_user_agent(request),
+            last_seen: Some(SystemTime::now()),
+            ..session_data
+        };
+        let mut sessions = self.sessions.write().await;
+        if let Some(previous_id) = self.get_session_id_from_request(request) {
+            sessions.remove(&previous_id);
+        }
+        sessions.insert(session_id.clone(), session_data);
+        Ok(session_id)
     }
     
     /// Build callback response with session cookie and cleanup
     fn build_callback_response(&self, request: &PluginRequest, context: &PluginContext, session_id: String) -> Response<Body> {
         // Create session cookie
-        let session_cookie = self.create_session_cookie(SESSION_COOKIE_NAME, &session_id, request);
+        let session_cookie = self.create_session_cookie(&session_id, request);
         
         // Get return_to URL
         let return_to = self.get_cookie_value(request, RETURN_TO_COOKIE_NAME);
         context.log_verbose(&format!("[OAuth2-{}] Callback: return_to cookie value = {:?}", self.provider, return_to));
-        let return_to = return_to.unwrap_or_else(|| DEFAULT_REDIRECT_PATH.to_string());
+        // The cookie is checked again, as it may not have been set by the login
+        let return_to = self.return_to(request, return_to, context);
         context.log_verbose(&format!("[OAuth2-{}] Callback: redirecting to {}", self.provider, return_to));
         
         Response::builder()
@@ -531,27 +1231,42 @@ impl OAuth2Plugin {
         }
         
         // 3. Create OAuth client
-        let _client = match self.create_and_validate_oauth_client(context) {
+        let redirect_uri = self.redirect_uri_for(request);
+        let _client = match self.create_and_validate_oauth_client(&redirect_uri, context) {
             Ok(client) => client,
-            Err(response) => return response,
+            Err(response) => return *response,
         };
         
         // 4. Exchange code for token
-        let access_token = match self.exchange_authorization_code(code.secret(), context).await {
-            Ok(token) => token,
+        let grant = match self.exchange_authorization_code(code.secret(), &redirect_uri, context).await {
+            Ok(grant) => grant,
             Err(response) => return response,
         };
         
         // 5. Get user information
-        let session_data = match self.retrieve_user_information(&access_token, context).await {
+        let mut session_data = match self.retrieve_user_information(&grant.access_token, context).await {
             Ok(data) => data,
             Err(response) => return response,
         };
         
-        // 6. Create session
-        let session_id = self.create_user_session(session_data, context).await;
+        // 6. Link this provider account to a single identity, enforcing
+        // domain and verification restrictions
+        if let Err(response) = self.admit(&mut session_data, context).await {
+            return response;
+        }
+        
+        // Only stored sessions are checked with the provider again
+        if self.revalidation.is_some() && self.sealer.is_none() {
+            session_data.grant = Some(grant);
+        }
+        
+        // 7. Create session
+        let session_id = match self.create_user_session(request, session_data, context).await {
+            Ok(session_id) => session_id,
+            Err(response) => return response,
+        };
         
-        // 7. Build response
+        // 8. Build response
         self.build_callback_response(request, context, session_id)
     }
     
@@ -567,18 +1282,28 @@ impl OAuth2Plugin {
         let return_to = request.http_request.uri().query()
             .and_then(|q| url::form_urlencoded::parse(q.as_bytes())
                 .find(|(k, _)| k == "return_to")
-                .map(|(_, v)| v.to_string()))
-            .unwrap_or_else(|| "/".to_string());
+                .map(|(_, v)| v.to_string()));
+        let return_to = self.return_to(request, return_to, context);
         
         Response::builder()
             .status(StatusCode::FOUND)
             .header(LOCATION, return_to)
-            .header(SET_COOKIE, "session_id=; Max-Age=0")
+            .header(SET_COOKIE, self.create_expired_session_cookie().to_string())
             .body(Body::empty())
             .unwrap()
     }
     
-    async fn handle_user_info(&self, session_data: &SessionData) -> Response<Body> {
+    async fn handle_user_info(&self, request: &PluginRequest, session_data: &SessionData) -> Response<Body> {
+        if request.prefers_json() {
+            return Response::builder()
+                .status(StatusCode::OK)
+                .header(CONTENT_TYPE, CONTENT_TYPE_JSON)
+                .header(CACHE_CONTROL, "no-store")
+                .header(VARY, "Accept")
+                .body(Body::from(user_json(session_data).to_string()))
+                .unwrap();
+        }
+        
         // Return HTML with microdata about the authenticated user
         let html = format!(r#"<!DOCTYPE html>
 <html>
@@ -589,7 +1314,7 @@ impl OAuth2Plugin {
 <body>
     <div itemscope itemtype="https://schema.org/Person">
         <span itemprop="email">{}</span>
-        <span itemprop="name">{}</span>{}
+        <span itemprop="name">{}</span>{}{}
     </div>
 </body>
 </html>"#,
@@ -599,16 +1324,49 @@ impl OAuth2Plugin {
                 format!("\n        <link itemprop=\"image\" href=\"{}\">", html_escape(picture))
             } else {
                 String::new()
-            }
+            },
+            session_data.linked_accounts.iter()
+                .map(|account| format!("\n        <meta itemprop=\"identifier\" content=\"{}\">", html_escape(account)))
+                .collect::<String>()
         );
         
         Response::builder()
             .status(StatusCode::OK)
             .header(CONTENT_TYPE, "text/html; charset=utf-8")
+            .header(CACHE_CONTROL, "no-store")
+            .header(VARY, "Accept")
             .body(Body::from(html))
             .unwrap()
     }
     
+    /// Reports as JSON whether the request has a session, and for whom
+    ///
+    /// Unlike /auth/user this always answers, so frontends can decide whether
+    /// to offer the login link without handling a 404.
+    async fn handle_status(&self, request: &PluginRequest, context: &PluginContext) -> Response<Body> {
+        let session_data = self.find_request_session(request).await;
+        
+        let status = match &session_data {
+            Some(session_data) => serde_json::json!({
+                "authenticated": true,
+                "user": user_json(session_data),
+            }),
+            None => serde_json::json!({
+                "authenticated": false,
+                "provider": self.provider,
+                "login_url": self.external_path(request, &self.login_path),
+            }),
+        };
+        context.log_verbose(&format!("[OAuth2-{}] Status requested, authenticated: {}", self.provider, session_data.is_some()));
+        
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, CONTENT_TYPE_JSON)
+            .header(CACHE_CONTROL, "no-store")
+            .body(Body::from(status.to_string()))
+            .unwrap()
+    }
+    
     /// Creates a secure HTTP-only cookie with standard settings
     fn create_secure_cookie<'a>(&self, name: &'a str, value: &'a str) -> Cookie<'a> {
         Cookie::build(name, value)
@@ -619,13 +1377,32 @@ impl OAuth2Plugin {
     }
     
     /// Creates a secure session cookie with HTTPS detection
-    fn create_session_cookie<'a>(&self, name: &'a str, value: &'a str, request: &PluginRequest) -> Cookie<'a> {
-        Cookie::build(name, value)
+    fn create_session_cookie<'a>(&'a self, value: &'a str, request: &PluginRequest) -> Cookie<'a> {
+        let cookie = &self.session_cookie;
+        let mut builder = Cookie::build(cookie.name.as_str(), value)
             .http_only(true)
-            .same_site(SameSite::Lax)
-            .secure(request.http_request.uri().scheme_str() == Some("https"))
-            .path("/")
-            .finish()
+            .same_site(cookie.same_site)
+            .secure(cookie.always_secure || self.request_scheme(request) == "https")
+            .path(cookie.path.as_str());
+        if let Some(domain) = &cookie.domain {
+            builder = builder.domain(domain.as_str());
+        }
+        builder.finish()
+    }
+    
+    /// Creates a session cookie that clears the one set at login
+    fn create_expired_session_cookie(&self) -> Cookie<'_> {
+        let cookie = &self.session_cookie;
+        let mut builder = Cookie::build(cookie.name.as_str(), "")
+            .http_only(true)
+            .same_site(cookie.same_site)
+            .secure(cookie.always_secure)
+            .path(cookie.path.as_str())
+            .max_age(time::Duration::seconds(0));
+        if let Some(domain) = &cookie.domain {
+            builder = builder.domain(domain.as_str());
+        }
+        builder.finish()
     }
     
     /// Creates a cookie that immediately expires (for clearing)
@@ -657,14 +1434,14 @@ impl OAuth2Plugin {
             })
     }
     
-    async fn exchange_code_for_token(&self, code: &str, context: &PluginContext) -> Result<String, String> {
+    async fn exchange_code_for_token(&self, code: &str, redirect_uri: &str, context: &PluginContext) -> Result<ProviderGrant, String> {
         context.log_verbose(&format!("[OAuth2] Exchanging code for token with {}", self.provider));
         
         // Create form data
         let params = [
             ("grant_type", "authorization_code"),
             ("code", code),
-            ("redirect_uri", &self.redirect_uri),
+            ("redirect_uri", redirect_uri),
             ("client_id", &self.client_id),
             ("client_secret", &self.client_secret),
         ];
@@ -693,10 +1470,13 @@ impl OAuth2Plugin {
         let token_response: serde_json::Value = response.into_json()
             .map_err(|e| format!("Failed to parse token response: {}", e))?;
         
-        token_response.get("access_token")
+        let access_token = token_response.get("access_token")
+            .and_then(|v| v.as_str())
+            .ok_or_else(|| "No access token in response".to_string())?;
+        let refresh_token = token_response.get("refresh_token")
             .and_then(|v| v.as_str())
-            .map(|s| s.to_string())
-            .ok_or_else(|| "No access token in response".to_string())
+            .map(|s| s.to_string());
+        Ok(ProviderGrant::new(access_token.to_string(), refresh_token))
     }
     
     /// Fetches user information from OAuth2 provider
@@ -734,9 +1514,15 @@ impl OAuth2Plugin {
             .map_err(|e| format!("Failed to parse user info: {}", e))?;
         
         // Get email (GitHub might not include it in user endpoint)
-        let email = match user_info.email {
-            Some(email) => email,
-            None => self.fetch_github_primary_email(access_token, context).await?,
+        let (email, email_verified) = match user_info.email {
+            // The public profile email carries no verification flag of its own
+            Some(email) => {
+                let verified = self.fetch_github_emails(access_token, context).await
+                    .map(|emails| emails.iter().any(|e| e.verified && e.email.eq_ignore_ascii_case(&email)))
+                    .unwrap_or(false);
+                (email, verified)
+            }
+            None => (self.fetch_github_primary_email(access_token, context).await?, true),
         };
         
         Ok(SessionData {
@@ -745,16 +1531,27 @@ impl OAuth2Plugin {
             picture: user_info.avatar_url,
             provider: self.provider.clone(),
             created_at: std::time::SystemTime::now(),
+            provider_user_id: Some(user_info.id.to_string()),
+            email_verified,
+            linked_accounts: Vec::new(),
+            user_agent: None,
+            last_seen: None,
+            grant: None,
         })
     }
     
-    /// Fetches primary verified email from GitHub emails endpoint
-    async fn fetch_github_primary_email(&self, access_token: &str, context: &PluginContext) -> Result<String, String> {
+    /// Fetches the user's email addresses from the GitHub emails endpoint
+    async fn fetch_github_emails(&self, access_token: &str, context: &PluginContext) -> Result<Vec<GitHubEmail>, String> {
         context.log_verbose("[OAuth2] Fetching email from GitHub emails endpoint");
         
         let emails_response = self.make_oauth_request(GITHUB_EMAIL_URL, access_token, true)?;
-        let emails: Vec<GitHubEmail> = emails_response.into_json()
-            .map_err(|e| format!("Failed to parse emails: {}", e))?;
+        emails_response.into_json()
+            .map_err(|e| format!("Failed to parse emails: {}", e))
+    }
+    
+    /// Fetches primary verified email from GitHub emails endpoint
+    async fn fetch_github_primary_email(&self, access_token: &str, context: &PluginContext) -> Result<String, String> {
+        let emails = self.fetch_github_emails(access_token, context).await?;
         
         emails.iter()
             .find(|e| e.primary && e.verified)
@@ -775,35 +1572,183 @@ impl OAuth2Plugin {
             picture: user_info.picture,
             provider: self.provider.clone(),
             created_at: std::time::SystemTime::now(),
+            provider_user_id: user_info.id,
+            email_verified: user_info.verified_email,
+            linked_accounts: Vec::new(),
+            user_agent: None,
+            last_seen: None,
+            grant: None,
         })
     }
 }
 
-// Export the plugin creation function
-// Helper function to escape HTML
-fn html_escape(s: &str) -> String {
-    s.chars()
-        .map(|c| match c {
-            '&' => "&amp;".to_string(),
-            '<' => "&lt;".to_string(),
-            '>' => "&gt;".to_string(),
-            '"' => "&quot;".to_string(),
-            '\'' => "&#39;".to_string(),
-            _ => c.to_string(),
-        })
-        .collect()
+/// Builds the page shown when a user is not permitted to sign in
+fn session_error_response() -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::INTERNAL_SERVER_ERROR)
+        .body(Body::from(ERROR_SESSION_FAILED))
+        .unwrap()
 }
 
-create_plugin!(OAuth2Plugin);
-
+fn login_forbidden_response(message: &str) -> Response<Body> {
+    let html = format!(r#"<!DOCTYPE html>
+<html>
+<head>
+    <title>Sign-in Not Permitted</title>
+    <meta charset="UTF-8">
+</head>
+<body>
+    <h1>Sign-in Not Permitted</h1>
+    <p>{}</p>
+    <p><a href="/">Return to the home page</a></p>
+</body>
+</html>"#, html_escape(message));
+    
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .header(CONTENT_TYPE, "text/html; charset=utf-8")
+        .body(Body::from(html))
+        .unwrap()
+}
+
+/// Describes the user behind a session for JSON responses
+fn user_json(session_data: &SessionData) -> serde_json::Value {
+    serde_json::json!({
+        "email": session_data.email,
+        "name": session_data.name,
+        "picture": session_data.picture,
+        "provider": session_data.provider,
+        "linked_accounts": session_data.linked_accounts,
+        "roles": [DEFAULT_USER_ROLE],
+        // Sessions last until logout
+        "expires_at": null,
+    })
+}
+
+/// Whether the request's Accept header names HTML and ranks it above JSON,
+/// as browsers loading a page do
+fn prefers_html(request: &PluginRequest) -> bool {
+    let html = request.accept_quality(CONTENT_TYPE_HTML);
+    html > 0.0 && html >= request.accept_quality(CONTENT_TYPE_JSON)
+}
+
+/// A new session id: 128 random bits from the thread's CSPRNG, as hex
+fn new_session_id() -> String {
+    format!("{:032x}", rand::random::<u128>())
+}
+
+/// Whether a presented id has the form of those `new_session_id` returns
+fn is_session_id(id: &str) -> bool {
+    id.len() == SESSION_ID_LENGTH && id.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Looks up a session by a presented id
+///
+/// Ids are 128 random bits hashed with the table's randomly keyed SipHash,
+/// so how long a lookup takes tells nothing about the ids it holds.
+fn find_session<'a>(sessions: &'a SessionTable, session_id: &str) -> Option<&'a SessionData> {
+    if !is_session_id(session_id) {
+        return None;
+    }
+    sessions.get(session_id)
+}
+
+/// A new API token: 256 random bits from the thread's CSPRNG, as prefixed hex
+fn new_api_token() -> String {
+    format!("{}{:032x}{:032x}", API_TOKEN_PREFIX, rand::random::<u128>(), rand::random::<u128>())
+}
+
+/// Whether a presented token has the form of those `new_api_token` returns
+fn is_api_token(token: &str) -> bool {
+    token.strip_prefix(API_TOKEN_PREFIX).is_some_and(|hex| {
+        hex.len() == API_TOKEN_HEX_LENGTH && hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    })
+}
+
+/// The token of an `Authorization: Bearer` header
+fn bearer_token(request: &PluginRequest) -> Option<&str> {
+    let value = request.http_request.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.trim().split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+/// Looks up an unexpired API token, as `find_session` looks up sessions
+fn find_api_token<'a>(api_tokens: &'a HashMap<String, ApiToken>, token: &str) -> Option<(&'a String, &'a ApiToken)> {
+    if !is_api_token(token) {
+        return None;
+    }
+    api_tokens.get_key_value(token)
+        .filter(|(_, api_token)| !api_token.is_expired())
+}
+
+/// Replaces a file with contents only its owner may read, writing them to a
+/// temporary file beside it first so readers never see a partial file
+fn write_private_file(path: &std::path::Path, contents: &str) -> std::io::Result<()> {
+    use std::io::Write;
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(format!(".{:016x}.tmp", rand::random::<u64>()));
+    let temporary = PathBuf::from(temporary);
+
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let written = options.open(&temporary).and_then(|mut file| {
+        file.write_all(contents.as_bytes())?;
+        file.sync_all()
+    });
+    let result = written.and_then(|_| std::fs::rename(&temporary, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temporary);
+    }
+    result
+}
+
+/// Makes an existing file readable by its owner only
+fn restrict_to_owner(path: &std::path::Path) -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mut permissions = std::fs::metadata(path)?.permissions();
+        if permissions.mode() & 0o077 != 0 {
+            permissions.set_mode(permissions.mode() & 0o700);
+            std::fs::set_permissions(path, permissions)?;
+        }
+    }
+    #[cfg(not(unix))]
+    std::fs::metadata(path)?;
+    Ok(())
+}
+
+// Export the plugin creation function
+// Helper function to escape HTML
+fn html_escape(s: &str) -> String {
+    s.chars()
+        .map(|c| match c {
+            '&' => "&amp;".to_string(),
+            '<' => "&lt;".to_string(),
+            '>' => "&gt;".to_string(),
+            '"' => "&quot;".to_string(),
+            '\'' => "&#39;".to_string(),
+            _ => c.to_string(),
+        })
+        .collect()
+}
+
+create_plugin!(OAuth2Plugin);
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use hyper::Request;
+    use uuid::Uuid;
     use std::sync::Arc;
     use tokio::sync::Mutex;
     
-    fn create_test_plugin() -> OAuth2Plugin {
+    pub(crate) fn create_test_plugin() -> OAuth2Plugin {
         // Set test environment variables
         env::set_var("TEST_CLIENT_ID", "test_client_id");
         env::set_var("TEST_CLIENT_SECRET", "test_client_secret");
@@ -814,10 +1759,12 @@ mod tests {
         config.insert("clientIdEnv".to_string(), "TEST_CLIENT_ID".to_string());
         config.insert("clientSecretEnv".to_string(), "TEST_CLIENT_SECRET".to_string());
         config.insert("redirectUriEnv".to_string(), "TEST_REDIRECT_URI".to_string());
+        // Isolate each test from sessions created by the others
+        config.insert("sessionRegistry".to_string(), Uuid::new_v4().to_string());
         OAuth2Plugin::new(config)
     }
     
-    fn create_test_context() -> PluginContext {
+    pub(crate) fn create_test_context() -> PluginContext {
         PluginContext {
             plugin_config: HashMap::new(),
             server_config: HashMap::new(),
@@ -827,10 +1774,12 @@ mod tests {
             request_id: "test-request".to_string(),
             runtime_handle: None,
             verbose: false,
+            services: None,
+            deadline: None,
         }
     }
     
-    fn create_test_request(method: &str, uri: &str, headers: Vec<(&str, &str)>) -> PluginRequest {
+    pub(crate) fn create_test_request(method: &str, uri: &str, headers: Vec<(&str, &str)>) -> PluginRequest {
         let mut builder = Request::builder()
             .method(method)
             .uri(uri);
@@ -851,7 +1800,9 @@ mod tests {
             path,
             canonical_path: None,
             metadata: HashMap::new(),
+            extensions: Default::default(),
             body_cache: Arc::new(Mutex::new(None)),
+            spool: None,
         }
     }
     
@@ -880,6 +1831,72 @@ mod tests {
         assert!(set_cookie.contains("SameSite=Lax"));
     }
     
+    /// Plugin without a configured redirect URI, as nested under a directory
+    pub(crate) fn create_nested_test_plugin(extra: Vec<(&str, &str)>) -> OAuth2Plugin {
+        env::set_var("TEST_CLIENT_ID", "test_client_id");
+        env::set_var("TEST_CLIENT_SECRET", "test_client_secret");
+        
+        let mut config = HashMap::new();
+        config.insert("name".to_string(), "google".to_string());
+        config.insert("clientIdEnv".to_string(), "TEST_CLIENT_ID".to_string());
+        config.insert("clientSecretEnv".to_string(), "TEST_CLIENT_SECRET".to_string());
+        config.insert("sessionRegistry".to_string(), Uuid::new_v4().to_string());
+        for (key, value) in extra {
+            config.insert(key.to_string(), value.to_string());
+        }
+        OAuth2Plugin::new(config)
+    }
+    
+    #[tokio::test]
+    async fn test_callback_path_under_directory() {
+        let plugin = create_nested_test_plugin(vec![
+            ("loginPath", "/admin/login"),
+            ("callbackPath", "/admin/callback"),
+        ]);
+        let context = create_test_context();
+        
+        let mut request = create_test_request("GET", "/admin/login", vec![("host", "example.com")]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.contains("redirect_uri=http%3A%2F%2Fexample.com%2Fadmin%2Fcallback"));
+        
+        // The callback is answered outside /auth/
+        let mut request = create_test_request("GET", "/admin/callback", vec![("host", "example.com")]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    }
+    
+    #[tokio::test]
+    async fn test_forwarded_headers_build_redirect_uri() {
+        let forwarded = vec![
+            ("host", "internal:3000"),
+            ("x-forwarded-proto", "https"),
+            ("x-forwarded-host", "public.example"),
+            ("x-forwarded-prefix", "/app"),
+        ];
+        let context = create_test_context();
+        
+        let plugin = create_nested_test_plugin(vec![("trustForwardedHeaders", "true")]);
+        let mut request = create_test_request("GET", "/auth/google/login", forwarded.clone());
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.contains("redirect_uri=https%3A%2F%2Fpublic.example%2Fapp%2Fauth%2Fgoogle%2Fcallback"));
+        
+        let mut request = create_test_request("GET", "/auth/status", forwarded.clone());
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status["login_url"], "/app/auth/google/login");
+        
+        // Forwarded headers are ignored unless trusted
+        let plugin = create_nested_test_plugin(vec![]);
+        let mut request = create_test_request("GET", "/auth/google/login", forwarded);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        let location = response.headers().get(LOCATION).unwrap().to_str().unwrap();
+        assert!(location.contains("redirect_uri=http%3A%2F%2Finternal%3A3000%2Fauth%2Fgoogle%2Fcallback"));
+    }
+    
     #[tokio::test]
     async fn test_callback_missing_code() {
         let plugin = create_test_plugin();
@@ -924,20 +1941,26 @@ mod tests {
         let context = create_test_context();
         
         // Add a test session to the plugin
-        let session_id = "test_session";
+        let session_id = new_session_id();
         let session_data = SessionData {
             email: "test@example.com".to_string(),
             name: "Test User".to_string(),
             picture: Some("https://example.com/picture.jpg".to_string()),
             provider: plugin.provider.clone(),
             created_at: std::time::SystemTime::now(),
+            provider_user_id: None,
+            email_verified: true,
+            linked_accounts: Vec::new(),
+            user_agent: None,
+            last_seen: None,
+            grant: None,
         };
-        plugin.sessions.write().await.insert(session_id.to_string(), session_data);
+        plugin.sessions.write().await.insert(session_id.clone(), session_data);
         
         let mut request = create_test_request(
             "POST",
             "/auth/logout",
-            vec![("cookie", &format!("{}={}", SESSION_COOKIE_NAME, session_id))]
+            vec![("cookie", &format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, session_id))]
         );
         
         let response = plugin.handle_request(&mut request, &context).await.unwrap();
@@ -947,7 +1970,93 @@ mod tests {
         assert_eq!(response.headers().get(LOCATION).unwrap(), DEFAULT_REDIRECT_PATH);
         
         let set_cookie = response.headers().get(SET_COOKIE).unwrap().to_str().unwrap();
-        assert!(set_cookie.contains(&format!("{}=; Max-Age=0", SESSION_COOKIE_NAME)));
+        assert!(set_cookie.starts_with(&format!("{}=;", DEFAULT_SESSION_COOKIE_NAME)));
+        assert!(set_cookie.contains("Path=/"));
+        assert!(set_cookie.contains("Max-Age=0"));
+        assert!(!plugin.sessions.read().await.contains_key(&session_id));
+    }
+    
+    #[tokio::test]
+    async fn test_return_to_outside_allowed_redirects_is_ignored() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        let return_to_cookie = |response: &Response<Body>| response.headers().get_all(SET_COOKIE).iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .find(|value| value.starts_with(RETURN_TO_COOKIE_NAME))
+            .unwrap();
+        
+        // Logins only remember allowed targets
+        let mut request = create_test_request("GET", "/auth/google/login?return_to=%2Fdashboard", vec![]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert!(return_to_cookie(&response).starts_with(&format!("{}=/dashboard;", RETURN_TO_COOKIE_NAME)));
+        
+        let mut request = create_test_request("GET", "/auth/google/login?return_to=https%3A%2F%2Fevil.example%2F", vec![]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert!(return_to_cookie(&response).contains("Max-Age=0"));
+        
+        // Logouts fall back to the site's root
+        let mut request = create_test_request("POST", "/auth/logout?return_to=%2F%2Fevil.example", vec![]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.headers().get(LOCATION).unwrap(), DEFAULT_REDIRECT_PATH);
+        
+        // Listed sites are allowed
+        let mut config = HashMap::new();
+        config.insert("clientIdEnv".to_string(), "TEST_CLIENT_ID".to_string());
+        config.insert("clientSecretEnv".to_string(), "TEST_CLIENT_SECRET".to_string());
+        config.insert("sessionRegistry".to_string(), Uuid::new_v4().to_string());
+        config.insert("allowedRedirects".to_string(), "https://app.example.com/".to_string());
+        let plugin = OAuth2Plugin::new(config);
+        let mut request = create_test_request("POST", "/auth/logout?return_to=https%3A%2F%2Fapp.example.com%2Fhome", vec![]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.headers().get(LOCATION).unwrap(), "https://app.example.com/home");
+    }
+    
+    #[tokio::test]
+    async fn test_login_redirect_for_anonymous_page_requests() {
+        let context = create_test_context();
+        let forbidden = || Response::builder().status(StatusCode::FORBIDDEN).body(Body::empty()).unwrap();
+        let page = vec![("Accept", "text/html,application/xhtml+xml,*/*;q=0.8"), ("Sec-Fetch-Mode", "navigate")];
+        
+        // Off unless configured
+        let plugin = create_test_plugin();
+        let request = create_test_request("GET", "/admin/index.html?tab=users", page.clone());
+        let mut response = forbidden();
+        plugin.handle_response(&request, &mut response, &context).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        
+        let plugin = create_nested_test_plugin(vec![("loginRedirect", "true")]);
+        let mut response = forbidden();
+        plugin.handle_response(&request, &mut response, &context).await;
+        assert_eq!(response.status(), StatusCode::FOUND);
+        assert_eq!(
+            response.headers().get(LOCATION).unwrap(),
+            "/auth/google/login?return_to=%2Fadmin%2Findex.html%3Ftab%3Dusers"
+        );
+        
+        // Scripts, API clients and signed-in users get the status itself
+        let untouched = [
+            create_test_request("GET", "/admin/index.html", vec![("Accept", "application/json")]),
+            create_test_request("GET", "/admin/index.html", vec![("Accept", "*/*")]),
+            create_test_request("GET", "/admin/index.html", vec![("Accept", "text/html"), ("Sec-Fetch-Mode", "cors")]),
+            create_test_request("GET", "/admin/index.html", vec![("Accept", "text/html"), ("Authorization", "Bearer rbt_x")]),
+            create_test_request("PUT", "/admin/index.html", page.clone()),
+        ];
+        for request in untouched {
+            let mut response = forbidden();
+            plugin.handle_response(&request, &mut response, &context).await;
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{} {:?}", request.http_request.method(), request.http_request.headers());
+        }
+        let mut request = create_test_request("GET", "/admin/index.html", page.clone());
+        request.metadata.insert("authenticated_user".to_string(), "alice@example.com".to_string());
+        let mut response = forbidden();
+        plugin.handle_response(&request, &mut response, &context).await;
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        
+        // Other statuses are left alone
+        let request = create_test_request("GET", "/missing.html", page);
+        let mut response = Response::builder().status(StatusCode::NOT_FOUND).body(Body::empty()).unwrap();
+        plugin.handle_response(&request, &mut response, &context).await;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
     }
     
     #[tokio::test]
@@ -967,21 +2076,27 @@ mod tests {
         let context = create_test_context();
         
         // Add a test session
-        let session_id = "test_session_id";
+        let session_id = new_session_id();
         let session_data = SessionData {
             email: "test@example.com".to_string(),
             name: "Test User".to_string(),
             picture: Some("https://example.com/picture.jpg".to_string()),
             provider: plugin.provider.clone(),
             created_at: std::time::SystemTime::now(),
+            provider_user_id: None,
+            email_verified: true,
+            linked_accounts: Vec::new(),
+            user_agent: None,
+            last_seen: None,
+            grant: None,
         };
-        plugin.sessions.write().await.insert(session_id.to_string(), session_data);
+        plugin.sessions.write().await.insert(session_id.clone(), session_data);
         
         // Test /auth/user with valid session
         let mut request = create_test_request(
             "GET",
             "/auth/user",
-            vec![("cookie", &format!("{}={}", SESSION_COOKIE_NAME, session_id))]
+            vec![("cookie", &format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, session_id))]
         );
         
         let response = plugin.handle_request(&mut request, &context).await.unwrap();
@@ -1000,27 +2115,88 @@ mod tests {
         assert!(body_str.contains(r#"<link itemprop="image" href="https://example.com/picture.jpg">"#));
     }
     
+    #[tokio::test]
+    async fn test_user_info_as_json() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        let session_id = new_session_id();
+        plugin.sessions.write().await.insert(session_id.clone(), create_user("google", "7", "erin@example.com", true));
+        let cookie = format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, session_id);
+        
+        let mut request = create_test_request("GET", "/auth/user", vec![
+            ("cookie", &cookie),
+            ("accept", "text/html;q=0.5, application/json"),
+        ]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), CONTENT_TYPE_JSON);
+        
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let user: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(user["email"], "erin@example.com");
+        assert_eq!(user["provider"], "google");
+        assert_eq!(user["roles"], serde_json::json!(["user"]));
+        assert!(user["expires_at"].is_null());
+        
+        // Browsers asking for HTML still get microdata
+        let mut request = create_test_request("GET", "/auth/user", vec![
+            ("cookie", &cookie),
+            ("accept", "text/html,application/xhtml+xml,*/*;q=0.8"),
+        ]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "text/html; charset=utf-8");
+    }
+    
+    #[tokio::test]
+    async fn test_status_endpoint() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        
+        let mut request = create_test_request("GET", "/auth/status", vec![]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status["authenticated"], false);
+        assert_eq!(status["login_url"], "/auth/google/login");
+        
+        let session_id = new_session_id();
+        plugin.sessions.write().await.insert(session_id.clone(), create_user("google", "8", "fay@example.com", true));
+        let cookie = format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, session_id);
+        let mut request = create_test_request("GET", "/auth/status", vec![("cookie", &cookie)]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status["authenticated"], true);
+        assert_eq!(status["user"]["email"], "fay@example.com");
+    }
+    
     #[tokio::test]
     async fn test_session_authentication_metadata() {
         let plugin = create_test_plugin();
         let context = create_test_context();
         
         // Add a test session
-        let session_id = "test_session_id";
+        let session_id = new_session_id();
         let session_data = SessionData {
             email: "test@example.com".to_string(),
             name: "Test User".to_string(),
             picture: None,
             provider: plugin.provider.clone(),
             created_at: std::time::SystemTime::now(),
+            provider_user_id: None,
+            email_verified: true,
+            linked_accounts: Vec::new(),
+            user_agent: None,
+            last_seen: None,
+            grant: None,
         };
-        plugin.sessions.write().await.insert(session_id.to_string(), session_data);
+        plugin.sessions.write().await.insert(session_id.clone(), session_data);
         
         // Test non-auth path with session - should set metadata
         let mut request = create_test_request(
             "GET",
             "/some/path",
-            vec![("cookie", &format!("session_id={}", session_id))]
+            vec![("cookie", &format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, session_id))]
         );
         
         let response = plugin.handle_request(&mut request, &context).await;
@@ -1028,4 +2204,559 @@ mod tests {
         assert_eq!(request.metadata.get("authenticated_user").unwrap(), "test@example.com");
         assert_eq!(request.metadata.get("authenticated_user_roles").unwrap(), "user");
     }
-}
\ No newline at end of file
+    
+    #[tokio::test]
+    async fn test_sessions_persist_across_restart() {
+        let session_file = env::temp_dir().join(format!("oauth2-sessions-{}.json", Uuid::new_v4()));
+        
+        let mut plugin = create_test_plugin();
+        plugin.session_file = Some(session_file.clone());
+        let session_data = SessionData {
+            email: "test@example.com".to_string(),
+            name: "Test User".to_string(),
+            picture: None,
+            provider: plugin.provider.clone(),
+            created_at: std::time::SystemTime::now(),
+            provider_user_id: None,
+            email_verified: true,
+            linked_accounts: Vec::new(),
+            user_agent: None,
+            last_seen: None,
+            grant: None,
+        };
+        let session_id = new_session_id();
+        plugin.sessions.write().await.insert(session_id.clone(), session_data);
+        plugin.on_shutdown().await;
+        
+        let mut restarted = create_test_plugin();
+        restarted.session_file = Some(session_file.clone());
+        restarted.on_startup().await;
+        
+        let sessions = restarted.sessions.read().await;
+        assert_eq!(sessions.get(&session_id).unwrap().email, "test@example.com");
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&session_file).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        
+        let _ = std::fs::remove_file(session_file);
+    }
+    
+    #[tokio::test]
+    async fn test_sessions_survive_a_configuration_reload() {
+        let session_file = env::temp_dir().join(format!("oauth2-sessions-{}.json", Uuid::new_v4()));
+        let context = create_test_context();
+        let mut running = create_test_plugin();
+        running.session_file = Some(session_file.clone());
+        running.on_startup().await;
+        
+        // A session created after the last save
+        let session_id = new_session_id();
+        running.sessions.write().await.insert(session_id.clone(), create_user("google", "1", "alice@example.com", true));
+        
+        // The server's reload order: the running instance persists its state
+        // before the new instance restores it
+        let mut reloaded = create_test_plugin();
+        reloaded.session_file = Some(session_file.clone());
+        running.on_shutdown().await;
+        reloaded.on_startup().await;
+        
+        let mut request = create_test_request(
+            "GET",
+            "/some/path",
+            vec![("cookie", &format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, session_id))]
+        );
+        assert!(reloaded.handle_request(&mut request, &context).await.is_none());
+        assert_eq!(request.metadata.get("authenticated_user").unwrap(), "alice@example.com");
+        
+        let _ = std::fs::remove_file(session_file);
+    }
+    
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_session_file_holding_grants_is_private() {
+        use std::os::unix::fs::PermissionsExt;
+        let session_file = env::temp_dir().join(format!("oauth2-sessions-{}.json", Uuid::new_v4()));
+        // A file written by an earlier version, readable by anyone
+        std::fs::write(&session_file, "{}").unwrap();
+        std::fs::set_permissions(&session_file, std::fs::Permissions::from_mode(0o644)).unwrap();
+        
+        let mut plugin = create_test_plugin();
+        plugin.session_file = Some(session_file.clone());
+        plugin.on_startup().await;
+        assert_eq!(std::fs::metadata(&session_file).unwrap().permissions().mode() & 0o777, 0o600);
+        
+        let session_data = SessionData {
+            email: "test@example.com".to_string(),
+            name: "Test User".to_string(),
+            picture: None,
+            provider: plugin.provider.clone(),
+            created_at: std::time::SystemTime::now(),
+            provider_user_id: None,
+            email_verified: true,
+            linked_accounts: Vec::new(),
+            user_agent: None,
+            last_seen: None,
+            grant: Some(ProviderGrant::new("access".to_string(), Some("refresh".to_string()))),
+        };
+        let session_id = new_session_id();
+        plugin.sessions.write().await.insert(session_id.clone(), session_data);
+        plugin.on_shutdown().await;
+        assert_eq!(std::fs::metadata(&session_file).unwrap().permissions().mode() & 0o777, 0o600);
+        
+        let mut restarted = create_test_plugin();
+        restarted.session_file = Some(session_file.clone());
+        restarted.on_startup().await;
+        let sessions = restarted.sessions.read().await;
+        let grant = sessions.get(&session_id).unwrap().grant.as_ref().unwrap();
+        assert_eq!(grant.refresh_token.as_deref(), Some("refresh"));
+        
+        let _ = std::fs::remove_file(session_file);
+    }
+    
+    #[tokio::test]
+    async fn test_shared_session_registry_across_providers() {
+        env::set_var("TEST_CLIENT_ID", "test_client_id");
+        env::set_var("TEST_CLIENT_SECRET", "test_client_secret");
+        env::set_var("TEST_REDIRECT_URI", "http://localhost:3000/auth/github/callback");
+        
+        let registry = Uuid::new_v4().to_string();
+        let create_provider = |name: &str| {
+            let mut config = HashMap::new();
+            config.insert("name".to_string(), name.to_string());
+            config.insert("clientIdEnv".to_string(), "TEST_CLIENT_ID".to_string());
+            config.insert("clientSecretEnv".to_string(), "TEST_CLIENT_SECRET".to_string());
+            config.insert("redirectUriEnv".to_string(), "TEST_REDIRECT_URI".to_string());
+            config.insert("sessionRegistry".to_string(), registry.clone());
+            OAuth2Plugin::new(config)
+        };
+        let google = create_provider("google");
+        let github = create_provider("github");
+        let context = create_test_context();
+        
+        // A session created by GitHub...
+        let session_data = SessionData {
+            email: "octocat@example.com".to_string(),
+            name: "Octocat".to_string(),
+            picture: None,
+            provider: github.provider.clone(),
+            created_at: std::time::SystemTime::now(),
+            provider_user_id: None,
+            email_verified: true,
+            linked_accounts: Vec::new(),
+            user_agent: None,
+            last_seen: None,
+            grant: None,
+        };
+        let session_id = new_session_id();
+        github.sessions.write().await.insert(session_id.clone(), session_data);
+        let cookie = format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, session_id);
+        
+        // ...authenticates requests seen by the Google instance
+        let mut request = create_test_request("GET", "/some/path", vec![("cookie", &cookie)]);
+        assert!(google.handle_request(&mut request, &context).await.is_none());
+        assert_eq!(request.metadata.get("authenticated_user").unwrap(), "octocat@example.com");
+        
+        // ...is reported by the Google instance's /auth/user
+        let mut request = create_test_request("GET", "/auth/user", vec![("cookie", &cookie)]);
+        let response = google.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::OK);
+        
+        // ...and logging out through Google clears it for GitHub too
+        let mut request = create_test_request("POST", "/auth/logout", vec![("cookie", &cookie)]);
+        assert!(google.handle_request(&mut request, &context).await.is_some());
+        assert!(!github.sessions.read().await.contains_key("shared"));
+    }
+    
+    #[tokio::test]
+    async fn test_instances_without_registry_keep_their_own_sessions() {
+        env::set_var("TEST_CLIENT_ID", "test_client_id");
+        env::set_var("TEST_CLIENT_SECRET", "test_client_secret");
+        env::set_var("TEST_REDIRECT_URI", "http://localhost:3000/auth/google/callback");
+        
+        let create_host_instance = || {
+            let mut config = HashMap::new();
+            config.insert("clientIdEnv".to_string(), "TEST_CLIENT_ID".to_string());
+            config.insert("clientSecretEnv".to_string(), "TEST_CLIENT_SECRET".to_string());
+            config.insert("redirectUriEnv".to_string(), "TEST_REDIRECT_URI".to_string());
+            OAuth2Plugin::new(config)
+        };
+        let first_host = create_host_instance();
+        let second_host = create_host_instance();
+        let context = create_test_context();
+        
+        let session_id = new_session_id();
+        first_host.sessions.write().await.insert(session_id.clone(), create_user(&first_host.provider, "1", "alice@example.com", true));
+        let cookie = format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, session_id);
+        
+        let mut request = create_test_request("GET", "/some/path", vec![("cookie", &cookie)]);
+        assert!(first_host.handle_request(&mut request, &context).await.is_none());
+        assert_eq!(request.metadata.get("authenticated_user").unwrap(), "alice@example.com");
+        
+        let mut request = create_test_request("GET", "/some/path", vec![("cookie", &cookie)]);
+        assert!(second_host.handle_request(&mut request, &context).await.is_none());
+        assert!(!request.metadata.contains_key("authenticated_user"));
+    }
+    
+    pub(crate) fn create_user(provider: &str, provider_user_id: &str, email: &str, email_verified: bool) -> SessionData {
+        SessionData {
+            email: email.to_string(),
+            name: "Test User".to_string(),
+            picture: None,
+            provider: provider.to_string(),
+            created_at: std::time::SystemTime::now(),
+            provider_user_id: Some(provider_user_id.to_string()),
+            email_verified,
+            linked_accounts: Vec::new(),
+            user_agent: None,
+            last_seen: None,
+            grant: None,
+        }
+    }
+    
+    #[tokio::test]
+    async fn test_allowed_domains_restrict_login() {
+        let mut plugin = create_test_plugin();
+        plugin.allowed_domains = vec!["example.com".to_string()];
+        let context = create_test_context();
+        
+        let allowed = create_user("google", "1", "alice@Example.COM", true);
+        assert!(plugin.check_login_permitted(&allowed, &context).is_ok());
+        
+        let denied = create_user("google", "2", "mallory@example.com.evil.org", true);
+        let response = *plugin.check_login_permitted(&denied, &context).unwrap_err();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        assert_eq!(response.headers().get(CONTENT_TYPE).unwrap(), "text/html; charset=utf-8");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8(body.to_vec()).unwrap().contains("Sign-in Not Permitted"));
+    }
+    
+    #[tokio::test]
+    async fn test_require_verified_email() {
+        let mut plugin = create_test_plugin();
+        let context = create_test_context();
+        let unverified = create_user("github", "1", "bob@example.com", false);
+        
+        assert!(plugin.check_login_permitted(&unverified, &context).is_ok());
+        
+        plugin.require_verified_email = true;
+        let response = plugin.check_login_permitted(&unverified, &context).unwrap_err();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+    
+    #[tokio::test]
+    async fn test_linked_identity_must_be_permitted() {
+        let mut plugin = create_test_plugin();
+        plugin.allowed_domains = vec!["example.com".to_string()];
+        let context = create_test_context();
+        
+        // The account was linked to an identity before the domains were restricted
+        plugin.identities.write().await.insert("google:g-1".to_string(), "dave@elsewhere.org".to_string());
+        let mut dave = create_user("google", "g-1", "dave@example.com", true);
+        let response = plugin.admit(&mut dave, &context).await.unwrap_err();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        
+        let mut erin = create_user("google", "g-2", "erin@example.com", true);
+        assert!(plugin.admit(&mut erin, &context).await.is_ok());
+        assert_eq!(erin.email, "erin@example.com");
+    }
+    
+    #[tokio::test]
+    async fn test_accounts_linked_by_verified_email() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        
+        let mut google = create_user("google", "g-1", "carol@example.com", true);
+        plugin.link_identity(&mut google, &context).await;
+        assert_eq!(google.linked_accounts, vec!["google:g-1"]);
+        
+        // The same verified email from another provider joins the identity
+        let mut github = create_user("github", "42", "Carol@example.com", true);
+        plugin.link_identity(&mut github, &context).await;
+        assert_eq!(github.email, "carol@example.com");
+        assert_eq!(github.linked_accounts, vec!["github:42", "google:g-1"]);
+        
+        // A linked account keeps its identity after changing email
+        let mut renamed = create_user("github", "42", "carol@new.example.org", true);
+        plugin.link_identity(&mut renamed, &context).await;
+        assert_eq!(renamed.email, "carol@example.com");
+        
+        // An unverified email cannot claim the identity
+        let mut impostor = create_user("github", "99", "carol@example.com", false);
+        plugin.link_identity(&mut impostor, &context).await;
+        assert_eq!(impostor.linked_accounts, vec!["github:99"]);
+        assert!(!plugin.identities.read().await.contains_key("github:99"));
+    }
+    
+    #[tokio::test]
+    async fn test_provider_user_id_in_metadata_and_user_info() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        
+        let mut session_data = create_user("github", "42", "dave@example.com", true);
+        plugin.link_identity(&mut session_data, &context).await;
+        let session_id = new_session_id();
+        plugin.sessions.write().await.insert(session_id.clone(), session_data);
+        let cookie = format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, session_id);
+        
+        let mut request = create_test_request("GET", "/some/path", vec![("cookie", &cookie)]);
+        assert!(plugin.handle_request(&mut request, &context).await.is_none());
+        assert_eq!(request.metadata.get("authenticated_user_provider").unwrap(), "github");
+        assert_eq!(request.metadata.get("authenticated_user_provider_id").unwrap(), "42");
+        
+        let mut request = create_test_request("GET", "/auth/user", vec![("cookie", &cookie)]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(String::from_utf8(body.to_vec()).unwrap().contains(r#"<meta itemprop="identifier" content="github:42">"#));
+    }
+    
+    #[tokio::test]
+    async fn test_session_ids_not_issued_by_server_rejected() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        
+        // A client-chosen id is ignored even if a session is stored under it
+        plugin.sessions.write().await.insert("chosen-by-client".to_string(), create_user("google", "1", "mallory@example.com", true));
+        let mut request = create_test_request("GET", "/some/path", vec![("cookie", "session_id=chosen-by-client")]);
+        assert!(plugin.handle_request(&mut request, &context).await.is_none());
+        assert!(!request.metadata.contains_key("authenticated_user"));
+        
+        let session_id = new_session_id();
+        assert!(is_session_id(&session_id));
+        assert_ne!(session_id, new_session_id());
+        assert!(!is_session_id(&session_id.to_uppercase()));
+        assert!(!is_session_id(&session_id[1..]));
+    }
+    
+    #[tokio::test]
+    async fn test_login_regenerates_session_id() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        
+        // A session id planted in the browser before login ends at login
+        let planted = new_session_id();
+        plugin.sessions.write().await.insert(planted.clone(), create_user("google", "2", "victim@example.com", true));
+        let cookie = format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, planted);
+        let request = create_test_request("GET", "/auth/google/callback", vec![("cookie", &cookie)]);
+        
+        let session_id = plugin.create_user_session(&request, create_user("google", "2", "victim@example.com", true), &context).await.unwrap();
+        assert_ne!(session_id, planted);
+        let sessions = plugin.sessions.read().await;
+        assert!(sessions.contains_key(&session_id));
+        assert!(!sessions.contains_key(&planted));
+    }
+    
+    #[tokio::test]
+    async fn test_session_cookie_attributes() {
+        let request = create_test_request("GET", "/auth/google/callback", vec![]);
+        
+        let plugin = create_test_plugin();
+        let cookie = plugin.create_session_cookie("abc", &request).to_string();
+        assert_eq!(cookie, "session_id=abc; HttpOnly; SameSite=Lax; Path=/");
+        
+        env::set_var("TEST_CLIENT_ID", "test_client_id");
+        env::set_var("TEST_CLIENT_SECRET", "test_client_secret");
+        let mut config = HashMap::new();
+        config.insert("clientIdEnv".to_string(), "TEST_CLIENT_ID".to_string());
+        config.insert("clientSecretEnv".to_string(), "TEST_CLIENT_SECRET".to_string());
+        config.insert("sessionRegistry".to_string(), Uuid::new_v4().to_string());
+        config.insert("cookieName".to_string(), "app_session".to_string());
+        config.insert("cookieDomain".to_string(), "example.com".to_string());
+        config.insert("cookiePath".to_string(), "/app".to_string());
+        config.insert("cookieSameSite".to_string(), "Strict".to_string());
+        config.insert("cookieSecure".to_string(), "true".to_string());
+        let plugin = OAuth2Plugin::new(config);
+        
+        let cookie = plugin.create_session_cookie("abc", &request);
+        assert_eq!(cookie.name(), "app_session");
+        assert_eq!(cookie.domain(), Some("example.com"));
+        assert_eq!(cookie.path(), Some("/app"));
+        assert_eq!(cookie.same_site(), Some(SameSite::Strict));
+        assert_eq!(cookie.secure(), Some(true));
+        
+        // The logout cookie must match to replace it
+        let expired = plugin.create_expired_session_cookie();
+        assert_eq!((expired.name(), expired.domain(), expired.path()), ("app_session", Some("example.com"), Some("/app")));
+        
+        // SameSite=None cookies are always Secure
+        let mut config = HashMap::new();
+        config.insert("clientIdEnv".to_string(), "TEST_CLIENT_ID".to_string());
+        config.insert("clientSecretEnv".to_string(), "TEST_CLIENT_SECRET".to_string());
+        config.insert("sessionRegistry".to_string(), Uuid::new_v4().to_string());
+        config.insert("cookieSameSite".to_string(), "None".to_string());
+        let plugin = OAuth2Plugin::new(config);
+        assert_eq!(plugin.create_session_cookie("abc", &request).secure(), Some(true));
+    }
+    
+    #[tokio::test]
+    async fn test_api_token_authenticates_bearer_requests() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        let (token, _) = plugin.issue_api_token(create_user("google", "42", "cli@example.com", true)).await;
+        assert!(is_api_token(&token));
+        
+        let mut request = create_test_request("GET", "/api/data", vec![("authorization", &format!("Bearer {}", token))]);
+        assert!(plugin.handle_request(&mut request, &context).await.is_none());
+        assert_eq!(request.metadata.get("authenticated_user").unwrap(), "cli@example.com");
+        assert_eq!(request.metadata.get("authenticated_user_provider_id").unwrap(), "42");
+        
+        // The user endpoint answers for token holders too
+        let mut request = create_test_request("GET", "/auth/user", vec![
+            ("authorization", &format!("bearer {}", token)),
+            ("accept", "application/json"),
+        ]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::OK);
+        
+        // Tokens the server did not issue, and expired ones, are ignored
+        let mut request = create_test_request("GET", "/api/data", vec![("authorization", &format!("Bearer {}", new_api_token()))]);
+        plugin.handle_request(&mut request, &context).await;
+        assert!(!request.metadata.contains_key("authenticated_user"));
+        
+        plugin.api_tokens.write().await.get_mut(&token).unwrap().expires_at = Some(SystemTime::now());
+        let mut request = create_test_request("GET", "/api/data", vec![("authorization", &format!("Bearer {}", token))]);
+        plugin.handle_request(&mut request, &context).await;
+        assert!(!request.metadata.contains_key("authenticated_user"));
+    }
+    
+    #[tokio::test]
+    async fn test_logout_revokes_api_token() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        let (token, _) = plugin.issue_api_token(create_user("google", "42", "cli@example.com", true)).await;
+        
+        let mut request = create_test_request("POST", "/auth/logout", vec![("authorization", &format!("Bearer {}", token))]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        assert!(plugin.api_tokens.read().await.is_empty());
+        
+        let mut request = create_test_request("GET", "/api/data", vec![("authorization", &format!("Bearer {}", token))]);
+        plugin.handle_request(&mut request, &context).await;
+        assert!(!request.metadata.contains_key("authenticated_user"));
+    }
+    
+    #[tokio::test]
+    async fn test_api_tokens_persist_and_expire() {
+        let token_file = env::temp_dir().join(format!("oauth2-tokens-{}.json", Uuid::new_v4()));
+        let create_plugin = |days: &str| {
+            let mut config = HashMap::new();
+            config.insert("clientIdEnv".to_string(), "TEST_CLIENT_ID".to_string());
+            config.insert("clientSecretEnv".to_string(), "TEST_CLIENT_SECRET".to_string());
+            config.insert("provider".to_string(), "google".to_string());
+            config.insert("sessionRegistry".to_string(), Uuid::new_v4().to_string());
+            config.insert("apiTokenFile".to_string(), token_file.display().to_string());
+            config.insert("apiTokenDays".to_string(), days.to_string());
+            OAuth2Plugin::new(config)
+        };
+        
+        let plugin = create_plugin("30");
+        let (token, api_token) = plugin.issue_api_token(create_user("google", "42", "cli@example.com", true)).await;
+        let lifetime = api_token.expires_at.unwrap().duration_since(SystemTime::now()).unwrap();
+        assert!(lifetime > Duration::from_secs(29 * SECONDS_PER_DAY));
+        
+        // Issued tokens are written straight away, not only on shutdown
+        let restarted = create_plugin("0");
+        restarted.on_startup().await;
+        assert!(restarted.api_tokens.read().await.contains_key(&token));
+        
+        let (_, api_token) = restarted.issue_api_token(create_user("google", "43", "other@example.com", true)).await;
+        assert!(api_token.expires_at.is_none());
+        std::fs::remove_file(&token_file).unwrap();
+    }
+    
+    fn create_cookie_session_plugin(keys: &str) -> OAuth2Plugin {
+        // Each test uses its own variable, since tests run in parallel
+        let keys_env = format!("TEST_SESSION_KEYS_{}", Uuid::new_v4().simple());
+        env::set_var(&keys_env, keys);
+        let mut config = HashMap::new();
+        config.insert("clientIdEnv".to_string(), "TEST_CLIENT_ID".to_string());
+        config.insert("clientSecretEnv".to_string(), "TEST_CLIENT_SECRET".to_string());
+        config.insert("provider".to_string(), "google".to_string());
+        config.insert("sessionRegistry".to_string(), Uuid::new_v4().to_string());
+        config.insert("sessionMode".to_string(), "cookie".to_string());
+        config.insert("sessionKeysEnv".to_string(), keys_env);
+        OAuth2Plugin::new(config)
+    }
+    
+    const OLD_SESSION_KEY: &str = "AAECAwQFBgcICQoLDA0ODxAREhMUFRYXGBkaGxwdHh8=";
+    const NEW_SESSION_KEY: &str = "ICEiIyQlJicoKSorLC0uLzAxMjM0NTY3ODk6Ozw9Pj8=";
+    
+    #[tokio::test]
+    async fn test_cookie_sessions_are_stateless() {
+        let plugin = create_cookie_session_plugin(NEW_SESSION_KEY);
+        assert!(create_test_plugin().sealer.is_none());
+        let context = create_test_context();
+        let login = create_test_request("GET", "/auth/google/callback", vec![]);
+        let sealed = plugin.create_user_session(&login, create_user("google", "7", "roaming@example.com", true), &context).await.unwrap();
+        assert!(plugin.sessions.read().await.is_empty());
+        
+        // Another server holding the same key accepts the cookie
+        let other_server = create_cookie_session_plugin(NEW_SESSION_KEY);
+        let cookie = format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, sealed);
+        let mut request = create_test_request("GET", "/index.html", vec![("cookie", &cookie)]);
+        assert!(other_server.handle_request(&mut request, &context).await.is_none());
+        assert_eq!(request.metadata.get("authenticated_user"), Some(&"roaming@example.com".to_string()));
+        assert_eq!(request.metadata.get("authenticated_user_provider_id"), Some(&"7".to_string()));
+        
+        let mut request = create_test_request("GET", "/auth/status", vec![("cookie", &cookie)]);
+        let response = other_server.handle_request(&mut request, &context).await.unwrap().response;
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let status: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(status["authenticated"], true);
+        
+        // Any change to the cookie is detected
+        let mut tampered = sealed.into_bytes();
+        let middle = tampered.len() / 2;
+        tampered[middle] = if tampered[middle] == b'A' { b'B' } else { b'A' };
+        let cookie = format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, String::from_utf8(tampered).unwrap());
+        let mut request = create_test_request("GET", "/index.html", vec![("cookie", &cookie)]);
+        other_server.handle_request(&mut request, &context).await;
+        assert!(!request.metadata.contains_key("authenticated_user"));
+        
+        // Session ids are not sessions in this mode
+        let cookie = format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, new_session_id());
+        let request = create_test_request("GET", "/index.html", vec![("cookie", &cookie)]);
+        assert!(plugin.authenticate(&request).await.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_cookie_session_key_rotation() {
+        let context = create_test_context();
+        let login = create_test_request("GET", "/auth/google/callback", vec![]);
+        let before = create_cookie_session_plugin(OLD_SESSION_KEY);
+        let sealed = before.create_user_session(&login, create_user("google", "8", "rotated@example.com", true), &context).await.unwrap();
+        let cookie = format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, sealed);
+        let request = create_test_request("GET", "/index.html", vec![("cookie", &cookie)]);
+        
+        // Cookies sealed with the old key open while it is still listed
+        let rotating = create_cookie_session_plugin(&format!("{}, {}", NEW_SESSION_KEY, OLD_SESSION_KEY));
+        assert_eq!(rotating.authenticate(&request).await.unwrap().0.email, "rotated@example.com");
+        
+        // ... and new cookies are sealed with the new key
+        let resealed = rotating.create_user_session(&login, create_user("google", "8", "rotated@example.com", true), &context).await.unwrap();
+        let new_cookie = format!("{}={}", DEFAULT_SESSION_COOKIE_NAME, resealed);
+        let new_request = create_test_request("GET", "/index.html", vec![("cookie", &new_cookie)]);
+        let after = create_cookie_session_plugin(NEW_SESSION_KEY);
+        assert!(after.authenticate(&new_request).await.is_some());
+        assert!(after.authenticate(&request).await.is_none());
+    }
+    
+    #[tokio::test]
+    async fn test_cookie_sessions_expire() {
+        let sealer = SessionSealer::from_keys(NEW_SESSION_KEY, Duration::ZERO).unwrap();
+        let sealed = sealer.seal(create_user("google", "9", "brief@example.com", true), DEFAULT_SESSION_COOKIE_NAME).unwrap();
+        assert!(sealer.open(&sealed, DEFAULT_SESSION_COOKIE_NAME).is_none());
+        
+        // Sealed cookies only open under the name they were set with
+        let sealer = SessionSealer::from_keys(NEW_SESSION_KEY, Duration::from_secs(60)).unwrap();
+        let sealed = sealer.seal(create_user("google", "9", "brief@example.com", true), DEFAULT_SESSION_COOKIE_NAME).unwrap();
+        assert!(sealer.open(&sealed, DEFAULT_SESSION_COOKIE_NAME).is_some());
+        assert!(sealer.open(&sealed, "other_cookie").is_none());
+        
+        // Without a usable key sessions stay on the server
+        assert!(SessionSealer::from_keys("c2hvcnQ=", Duration::from_secs(60)).is_err());
+        assert!(create_cookie_session_plugin("").sealer.is_none());
+    }
+}
@@ -0,0 +1,132 @@
+//! Where users may be sent after signing in or out
+//!
+//! The `return_to` of the login and logout endpoints comes from the client,
+//! so it is checked before the plugin redirects to it. Relative paths on
+//! this site are always allowed; `//host` and `/\host`, which browsers read
+//! as another site, are not. Absolute URLs are only followed if
+//! `allowedRedirects` lists them, as a comma-separated list of
+//! - URL prefixes such as `https://app.example.com/dashboard`, matching URLs
+//!   with the same scheme, host and port whose path is below the prefix's
+//! - host names such as `app.example.com`, or `*.example.com` for its
+//!   subdomains, matching http and https URLs on that host
+//!
+//! Any other target is replaced with the site's root.
+
+use url::Url;
+
+/// A target `allowedRedirects` permits
+#[derive(Debug, Clone, PartialEq)]
+enum AllowedTarget {
+    /// URLs on the prefix's origin, below its path
+    Prefix(Url),
+    /// URLs on a host
+    Host(String),
+    /// URLs on subdomains of a domain, stored with its leading dot
+    Subdomains(String),
+}
+
+/// The targets users may be redirected to
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RedirectPolicy {
+    allowed: Vec<AllowedTarget>,
+}
+
+impl RedirectPolicy {
+    /// Parse `allowedRedirects`, returning the entries that could not be understood
+    pub(crate) fn parse(value: &str) -> (Self, Vec<String>) {
+        let mut allowed = Vec::new();
+        let mut invalid = Vec::new();
+        for entry in value.split(',').map(str::trim).filter(|entry| !entry.is_empty()) {
+            let target = if entry.contains("://") {
+                Url::parse(entry).ok()
+                    .filter(|url| is_web_url(url) && url.host_str().is_some())
+                    .map(AllowedTarget::Prefix)
+            } else if let Some(domain) = entry.strip_prefix("*.") {
+                is_host_name(domain).then(|| AllowedTarget::Subdomains(format!(".{}", domain.to_lowercase())))
+            } else {
+                is_host_name(entry).then(|| AllowedTarget::Host(entry.to_lowercase()))
+            };
+            match target {
+                Some(target) => allowed.push(target),
+                None => invalid.push(entry.to_string()),
+            }
+        }
+        (Self { allowed }, invalid)
+    }
+
+    /// Whether users may be redirected to a target
+    pub(crate) fn permits(&self, target: &str) -> bool {
+        if target.chars().any(|c| c.is_control() || c == '\\') {
+            return false;
+        }
+        if target.starts_with('/') {
+            return !target.starts_with("//");
+        }
+        let Ok(url) = Url::parse(target) else { return false };
+        if !is_web_url(&url) || !url.username().is_empty() || url.password().is_some() {
+            return false;
+        }
+        let Some(host) = url.host_str() else { return false };
+        self.allowed.iter().any(|allowed| match allowed {
+            AllowedTarget::Prefix(prefix) => {
+                url.origin() == prefix.origin() && is_below(url.path(), prefix.path())
+            }
+            AllowedTarget::Host(allowed) => host == allowed,
+            AllowedTarget::Subdomains(domain) => host.ends_with(domain.as_str()),
+        })
+    }
+}
+
+fn is_web_url(url: &Url) -> bool {
+    url.scheme() == "http" || url.scheme() == "https"
+}
+
+fn is_host_name(value: &str) -> bool {
+    !value.is_empty() && value.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '.')
+}
+
+/// Whether a path is a prefix path or below it, a whole segment at a time
+fn is_below(path: &str, prefix: &str) -> bool {
+    let prefix = prefix.trim_end_matches('/');
+    path.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_policy_allows_only_relative_paths() {
+        let policy = RedirectPolicy::default();
+        assert!(policy.permits("/"));
+        assert!(policy.permits("/dashboard?tab=1#top"));
+        assert!(!policy.permits("//evil.example"));
+        assert!(!policy.permits("/\\evil.example"));
+        assert!(!policy.permits("/\tpath"));
+        assert!(!policy.permits("https://evil.example/"));
+        assert!(!policy.permits("javascript:alert(1)"));
+        assert!(!policy.permits("dashboard"));
+    }
+
+    #[test]
+    fn test_allowed_redirects_permit_listed_targets() {
+        let (policy, invalid) = RedirectPolicy::parse(
+            "https://app.example.com/dashboard, docs.example.com, *.example.org, bad host, ftp://files.example.com",
+        );
+        assert_eq!(invalid, vec!["bad host", "ftp://files.example.com"]);
+
+        assert!(policy.permits("https://app.example.com/dashboard"));
+        assert!(policy.permits("https://app.example.com/dashboard/settings"));
+        assert!(!policy.permits("https://app.example.com/dashboards"));
+        assert!(!policy.permits("http://app.example.com/dashboard"));
+        assert!(!policy.permits("https://app.example.com.evil.example/dashboard"));
+
+        assert!(policy.permits("http://docs.example.com/guide"));
+        assert!(policy.permits("https://DOCS.example.com/"));
+        assert!(!policy.permits("https://user@docs.example.com/"));
+
+        assert!(policy.permits("https://www.example.org/"));
+        assert!(!policy.permits("https://example.org/"));
+        assert!(!policy.permits("https://evilexample.org/"));
+    }
+}
@@ -0,0 +1,366 @@
+//! Checking sessions with their provider again while they last
+//!
+//! With `revalidateMinutes` set, a server-side session keeps the provider's
+//! access token, and its refresh token if the provider issued one. When the
+//! session is used after that many minutes have passed since the provider
+//! last confirmed it, the token is presented to the provider again: to its
+//! token introspection endpoint (RFC 7662) if `introspectionUrl` is set,
+//! otherwise to its userinfo endpoint. A token the provider rejects is
+//! refreshed once, since access tokens may simply have expired. The session
+//! ends if the provider no longer honours the grant, if the userinfo names
+//! another account, or if the account's email is outside `allowedDomains`.
+//! If the provider cannot be reached the session is kept and checked again
+//! after the next interval.
+//!
+//! Only the instance for the session's provider checks it, and a session is
+//! claimed before the provider is asked, so concurrent requests ask once.
+//! Sessions sealed into cookies, sessions created before revalidation was
+//! turned on and API tokens are not checked.
+
+use super::*;
+
+const CONFIG_KEY_REVALIDATE_MINUTES: &str = "revalidateMinutes";
+const CONFIG_KEY_INTROSPECTION_URL: &str = "introspectionUrl";
+const SECONDS_PER_MINUTE: u64 = 60;
+
+// Why a session was ended
+const REASON_GRANT_REVOKED: &str = "the provider no longer honours its token";
+const REASON_ACCOUNT_CHANGED: &str = "the provider names another account";
+const REASON_DOMAIN_NOT_ALLOWED: &str = "the account's email is outside the allowed domains";
+
+/// How often sessions are checked with the provider
+#[derive(Debug, Clone)]
+pub(crate) struct RevalidationSettings {
+    interval: Duration,
+    /// Token introspection endpoint, used instead of the userinfo endpoint
+    introspection_url: Option<String>,
+}
+
+impl RevalidationSettings {
+    /// The settings in a plugin's configuration, or `None` if sessions are
+    /// not checked again
+    pub(crate) fn from_config(config: &HashMap<String, String>, name: &str) -> Option<Self> {
+        let minutes = match config.get(CONFIG_KEY_REVALIDATE_MINUTES).map(|minutes| minutes.trim().parse::<u64>()) {
+            None | Some(Ok(0)) => return None,
+            Some(Ok(minutes)) => minutes,
+            Some(Err(_)) => {
+                log_error!(name, "Invalid revalidateMinutes value, not revalidating sessions");
+                return None;
+            }
+        };
+        Some(Self {
+            interval: Duration::from_secs(minutes * SECONDS_PER_MINUTE),
+            introspection_url: config.get(CONFIG_KEY_INTROSPECTION_URL).cloned().filter(|url| !url.is_empty()),
+        })
+    }
+}
+
+/// The provider's tokens behind a session
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct ProviderGrant {
+    pub(crate) access_token: String,
+    #[serde(default)]
+    pub(crate) refresh_token: Option<String>,
+    /// When the provider last confirmed the grant
+    pub(crate) checked_at: SystemTime,
+}
+
+impl ProviderGrant {
+    pub(crate) fn new(access_token: String, refresh_token: Option<String>) -> Self {
+        Self { access_token, refresh_token, checked_at: SystemTime::now() }
+    }
+
+    fn is_due(&self, interval: Duration, now: SystemTime) -> bool {
+        now.duration_since(self.checked_at).is_ok_and(|since| since >= interval)
+    }
+}
+
+/// The account the provider says a token belongs to
+#[derive(Debug, Default)]
+struct ProviderAccount {
+    id: Option<String>,
+    email: Option<String>,
+}
+
+/// What the provider said about a session's grant
+#[derive(Debug)]
+enum Verdict {
+    /// The grant stands, with the tokens to keep using
+    Active(ProviderGrant),
+    /// The session must end, for the reason given
+    Revoked(&'static str),
+    /// The provider could not be asked
+    Unknown(String),
+}
+
+impl OAuth2Plugin {
+    /// Checks the request's session with its provider if it is due, ending it
+    /// if the provider no longer vouches for it
+    pub(crate) async fn revalidate_request_session(&self, request: &PluginRequest, context: &PluginContext) {
+        let Some(settings) = &self.revalidation else { return };
+        if self.sealer.is_some() {
+            return;
+        }
+        let Some(session_id) = self.get_session_id_from_request(request) else { return };
+        let now = SystemTime::now();
+        let due = |session_data: &SessionData| session_data.provider == self.provider
+            && session_data.grant.as_ref().is_some_and(|grant| grant.is_due(settings.interval, now));
+        if !find_session(&*self.sessions.read().await, &session_id).is_some_and(due) {
+            return;
+        }
+
+        // Claim the check, so requests arriving meanwhile don't repeat it
+        let (session_data, grant) = {
+            let mut sessions = self.sessions.write().await;
+            if !find_session(&sessions, &session_id).is_some_and(due) {
+                return;
+            }
+            let Some(session_data) = sessions.get_mut(&session_id) else { return };
+            let Some(grant) = session_data.grant.as_mut() else { return };
+            let claimed = grant.clone();
+            grant.checked_at = now;
+            (session_data.clone(), claimed)
+        };
+
+        match self.check_grant(settings, &session_data, grant) {
+            Verdict::Active(grant) => {
+                context.log_verbose(&format!("[OAuth2-{}] Provider confirmed the session of {}", self.provider, session_data.email));
+                if let Some(session_data) = self.sessions.write().await.get_mut(&session_id) {
+                    session_data.grant = Some(ProviderGrant { checked_at: now, ..grant });
+                }
+            }
+            Verdict::Revoked(reason) => {
+                log_info!(self.name, "Ending the session of {}: {}", session_data.email, reason; provider = self.provider);
+                self.sessions.write().await.remove(&session_id);
+            }
+            Verdict::Unknown(e) => {
+                log_warn!(self.name, "Could not check the session of {} with the provider: {}", session_data.email, e; provider = self.provider);
+            }
+        }
+    }
+
+    /// Asks the provider whether a session's grant still stands, refreshing
+    /// its access token once if the provider rejects it
+    fn check_grant(&self, settings: &RevalidationSettings, session_data: &SessionData, mut grant: ProviderGrant) -> Verdict {
+        let mut refreshed = false;
+        let account = loop {
+            match self.current_account(settings, &grant.access_token) {
+                Ok(Some(account)) => break account,
+                Ok(None) if refreshed => return Verdict::Revoked(REASON_GRANT_REVOKED),
+                Ok(None) => match self.refresh_grant(&grant) {
+                    Ok(Some(new_grant)) => {
+                        grant = new_grant;
+                        refreshed = true;
+                    }
+                    Ok(None) => return Verdict::Revoked(REASON_GRANT_REVOKED),
+                    Err(e) => return Verdict::Unknown(e),
+                },
+                Err(e) => return Verdict::Unknown(e),
+            }
+        };
+
+        if account.id.is_some() && session_data.provider_user_id.is_some() && account.id != session_data.provider_user_id {
+            return Verdict::Revoked(REASON_ACCOUNT_CHANGED);
+        }
+        if account.email.is_some_and(|email| !self.is_domain_allowed(&email)) {
+            return Verdict::Revoked(REASON_DOMAIN_NOT_ALLOWED);
+        }
+        Verdict::Active(grant)
+    }
+
+    /// The account an access token belongs to, or `None` if the provider
+    /// rejects the token
+    fn current_account(&self, settings: &RevalidationSettings, access_token: &str) -> Result<Option<ProviderAccount>, String> {
+        if let Some(introspection_url) = &settings.introspection_url {
+            let body = url::form_urlencoded::Serializer::new(String::new())
+                .append_pair("token", access_token)
+                .append_pair("token_type_hint", "access_token")
+                .append_pair("client_id", &self.client_id)
+                .append_pair("client_secret", &self.client_secret)
+                .finish();
+            let introspection_url = introspection_url.clone();
+            let response = tokio::task::block_in_place(move || {
+                ureq::post(&introspection_url)
+                    .set("Content-Type", "application/x-www-form-urlencoded")
+                    .set("Accept", "application/json")
+                    .send_string(&body)
+                    .map_err(|e| format!("Introspection failed: {}", e))
+            })?;
+            let answer: serde_json::Value = response.into_json()
+                .map_err(|e| format!("Failed to parse introspection response: {}", e))?;
+            if answer.get("active").and_then(|active| active.as_bool()) != Some(true) {
+                return Ok(None);
+            }
+            // Introspection subjects need not be the ids userinfo gives
+            let email = ["email", "username"].iter()
+                .filter_map(|claim| answer.get(*claim).and_then(|value| value.as_str()))
+                .find(|value| value.contains('@'))
+                .map(str::to_string);
+            return Ok(Some(ProviderAccount { id: None, email }));
+        }
+
+        let user_info_url = self.user_info_url.clone();
+        let access_token = access_token.to_string();
+        let response = tokio::task::block_in_place(move || {
+            let result = ureq::get(&user_info_url)
+                .set("Authorization", &format!("Bearer {}", access_token))
+                .set("Accept", "application/json")
+                .set("User-Agent", USER_AGENT)
+                .call();
+            match result {
+                Ok(response) => Ok(Some(response)),
+                Err(ureq::Error::Status(401 | 403, _)) => Ok(None),
+                Err(e) => Err(format!("HTTP request failed: {}", e)),
+            }
+        })?;
+        let Some(response) = response else { return Ok(None) };
+        let user_info: serde_json::Value = response.into_json()
+            .map_err(|e| format!("Failed to parse user info: {}", e))?;
+        // Google gives string ids, GitHub numbers
+        let id = user_info.get("id").and_then(|id| match id {
+            serde_json::Value::String(id) => Some(id.clone()),
+            serde_json::Value::Number(id) => Some(id.to_string()),
+            _ => None,
+        });
+        let email = user_info.get("email").and_then(|email| email.as_str()).map(str::to_string);
+        Ok(Some(ProviderAccount { id, email }))
+    }
+
+    /// Trades a grant's refresh token for a new access token, or `None` if
+    /// the provider refuses to, as it does once the grant is revoked
+    fn refresh_grant(&self, grant: &ProviderGrant) -> Result<Option<ProviderGrant>, String> {
+        let Some(refresh_token) = &grant.refresh_token else { return Ok(None) };
+        let body = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("grant_type", "refresh_token")
+            .append_pair("refresh_token", refresh_token)
+            .append_pair("client_id", &self.client_id)
+            .append_pair("client_secret", &self.client_secret)
+            .finish();
+        let token_url = self.token_url.clone();
+        let response = tokio::task::block_in_place(move || {
+            let result = ureq::post(&token_url)
+                .set("Content-Type", "application/x-www-form-urlencoded")
+                .set("Accept", "application/json")
+                .send_string(&body);
+            match result {
+                Ok(response) => Ok(Some(response)),
+                Err(ureq::Error::Status(400 | 401, _)) => Ok(None),
+                Err(e) => Err(format!("Token refresh failed: {}", e)),
+            }
+        })?;
+        let Some(response) = response else { return Ok(None) };
+        let answer: serde_json::Value = response.into_json()
+            .map_err(|e| format!("Failed to parse token response: {}", e))?;
+        // GitHub refuses with a 200
+        let Some(access_token) = answer.get("access_token").and_then(|v| v.as_str()) else { return Ok(None) };
+        let refresh_token = answer.get("refresh_token").and_then(|v| v.as_str())
+            .map(str::to_string)
+            .or_else(|| grant.refresh_token.clone());
+        Ok(Some(ProviderGrant::new(access_token.to_string(), refresh_token)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{create_test_context, create_test_plugin, create_test_request, create_user};
+    use std::io::{BufRead, BufReader, Read, Write};
+    use std::net::TcpListener;
+
+    /// Serves a provider's userinfo and token endpoints on a local port
+    ///
+    /// Userinfo knows the access tokens `good` and `refreshed` for ann at
+    /// example.com and `moved` for her at another domain; the token endpoint
+    /// only refreshes with the refresh token `renew`.
+    fn start_provider() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(stream) = stream else { continue };
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let (mut authorization, mut length) = (String::new(), 0);
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    let Some((name, value)) = line.trim_end().split_once(": ") else { break };
+                    match name.to_lowercase().as_str() {
+                        "authorization" => authorization = value.to_string(),
+                        "content-length" => length = value.parse().unwrap(),
+                        _ => {}
+                    }
+                }
+                let mut body = vec![0; length];
+                reader.read_exact(&mut body).unwrap();
+                let body = String::from_utf8(body).unwrap();
+
+                let (status, json) = if request_line.starts_with("POST /token") {
+                    if body.contains("refresh_token=renew") {
+                        ("200 OK", r#"{"access_token": "refreshed"}"#)
+                    } else {
+                        ("400 Bad Request", r#"{"error": "invalid_grant"}"#)
+                    }
+                } else {
+                    match authorization.as_str() {
+                        "Bearer good" | "Bearer refreshed" => ("200 OK", r#"{"id": "1", "email": "ann@example.com"}"#),
+                        "Bearer moved" => ("200 OK", r#"{"id": "1", "email": "ann@elsewhere.com"}"#),
+                        _ => ("401 Unauthorized", r#"{"error": "invalid_token"}"#),
+                    }
+                };
+                let _ = write!(reader.get_mut(), "HTTP/1.1 {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status, json.len(), json);
+            }
+        });
+        format!("http://{}", address)
+    }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_sessions_are_revalidated_with_the_provider() {
+        let provider = start_provider();
+        let mut plugin = create_test_plugin();
+        plugin.user_info_url = format!("{}/user", provider);
+        plugin.token_url = format!("{}/token", provider);
+        plugin.allowed_domains = vec!["example.com".to_string()];
+        plugin.revalidation = RevalidationSettings::from_config(
+            &HashMap::from([(CONFIG_KEY_REVALIDATE_MINUTES.to_string(), "10".to_string())]),
+            "google",
+        );
+        let context = create_test_context();
+
+        let long_ago = SystemTime::now() - Duration::from_secs(3600);
+        let sessions = [
+            ("good", None, long_ago, true),
+            ("moved", None, long_ago, false),
+            ("expired", Some("renew"), long_ago, true),
+            ("expired", None, long_ago, false),
+            // Not due, so the provider isn't asked
+            ("expired", None, SystemTime::now(), true),
+        ];
+        for (access_token, refresh_token, checked_at, kept) in sessions {
+            let session_id = new_session_id();
+            let mut user = create_user("google", "1", "ann@example.com", true);
+            user.grant = Some(ProviderGrant {
+                access_token: access_token.to_string(),
+                refresh_token: refresh_token.map(str::to_string),
+                checked_at,
+            });
+            plugin.sessions.write().await.insert(session_id.clone(), user);
+
+            let cookie = format!("session_id={}", session_id);
+            let mut request = create_test_request("GET", "/page", vec![("cookie", &cookie)]);
+            plugin.handle_request(&mut request, &context).await;
+            assert_eq!(request.metadata.contains_key("authenticated_user"), kept, "{} {:?}", access_token, refresh_token);
+
+            let sessions = plugin.sessions.read().await;
+            assert_eq!(sessions.contains_key(&session_id), kept);
+            if let Some(grant) = sessions.get(&session_id).and_then(|session_data| session_data.grant.as_ref()) {
+                assert!(!grant.is_due(Duration::from_secs(600), SystemTime::now()));
+                if refresh_token.is_some() {
+                    assert_eq!(grant.access_token, "refreshed");
+                }
+            }
+        }
+    }
+}
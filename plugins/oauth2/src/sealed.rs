@@ -0,0 +1,96 @@
+//! Sessions sealed into the session cookie
+//!
+//! In the `cookie` session mode nothing about a session is kept on the
+//! server. The session data and an expiry time are serialized, encrypted
+//! with AES-256-GCM and written to the session cookie, so every server
+//! holding the keys can read it back without sharing a session store.
+//!
+//! Cookies are sealed with the first configured key and opened with any of
+//! them. A key is rotated by putting the new one first and dropping the old
+//! one once the cookies it sealed have expired. Since a sealed session is
+//! not stored, logging out only clears the client's cookie; a copy of the
+//! cookie taken before stays valid until it expires.
+
+use super::*;
+use base64::engine::general_purpose::{STANDARD, URL_SAFE_NO_PAD};
+use base64::Engine;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+
+/// Prefix of sealed cookies, naming the format so it can change later
+const SEALED_PREFIX: &str = "v1.";
+/// Browsers ignore cookies longer than this, attributes included
+pub(crate) const MAX_COOKIE_LENGTH: usize = 4096;
+const KEY_LENGTH: usize = 32;
+
+/// What a sealed cookie holds
+#[derive(Serialize, Deserialize)]
+struct SealedSession {
+    session: SessionData,
+    expires_at: SystemTime,
+}
+
+/// Keys sealing sessions into cookies and opening them again
+#[derive(Debug)]
+pub(crate) struct SessionSealer {
+    /// The first key seals, all of them open
+    keys: Vec<LessSafeKey>,
+    lifetime: Duration,
+}
+
+impl SessionSealer {
+    /// Builds a sealer from comma-separated base64 keys of 32 bytes each
+    pub(crate) fn from_keys(keys: &str, lifetime: Duration) -> Result<Self, String> {
+        let keys = keys.split(',')
+            .map(str::trim)
+            .filter(|key| !key.is_empty())
+            .enumerate()
+            .map(|(index, key)| {
+                let bytes = STANDARD.decode(key)
+                    .map_err(|e| format!("session key {} is not valid base64: {}", index + 1, e))?;
+                if bytes.len() != KEY_LENGTH {
+                    return Err(format!("session key {} is {} bytes long, not {}", index + 1, bytes.len(), KEY_LENGTH));
+                }
+                UnboundKey::new(&AES_256_GCM, &bytes)
+                    .map(LessSafeKey::new)
+                    .map_err(|_| format!("session key {} was rejected", index + 1))
+            })
+            .collect::<Result<Vec<_>, String>>()?;
+        if keys.is_empty() {
+            return Err("no session keys are configured".to_string());
+        }
+        Ok(Self { keys, lifetime })
+    }
+
+    /// Seals a session into a cookie value, bound to the cookie's name
+    pub(crate) fn seal(&self, session: SessionData, cookie_name: &str) -> Result<String, String> {
+        let sealed = SealedSession { session, expires_at: SystemTime::now() + self.lifetime };
+        let mut in_out = serde_json::to_vec(&sealed)
+            .map_err(|e| format!("Failed to serialize session: {}", e))?;
+        let nonce: [u8; NONCE_LEN] = rand::random();
+        self.keys[0]
+            .seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(cookie_name.as_bytes()), &mut in_out)
+            .map_err(|_| "Failed to encrypt session".to_string())?;
+        let mut token = nonce.to_vec();
+        token.extend_from_slice(&in_out);
+        Ok(format!("{}{}", SEALED_PREFIX, URL_SAFE_NO_PAD.encode(token)))
+    }
+
+    /// The session sealed in a cookie value, unless it was tampered with,
+    /// sealed with a key no longer configured, or has expired
+    pub(crate) fn open(&self, value: &str, cookie_name: &str) -> Option<SessionData> {
+        let token = URL_SAFE_NO_PAD.decode(value.strip_prefix(SEALED_PREFIX)?).ok()?;
+        if token.len() <= NONCE_LEN {
+            return None;
+        }
+        let (nonce, ciphertext) = token.split_at(NONCE_LEN);
+        let plaintext = self.keys.iter().find_map(|key| {
+            let nonce = Nonce::try_assume_unique_for_key(nonce).ok()?;
+            let mut in_out = ciphertext.to_vec();
+            key.open_in_place(nonce, Aad::from(cookie_name.as_bytes()), &mut in_out).ok()?;
+            in_out.truncate(in_out.len() - AES_256_GCM.tag_len());
+            Some(in_out)
+        })?;
+        let sealed: SealedSession = serde_json::from_slice(&plaintext).ok()?;
+        (sealed.expires_at > SystemTime::now()).then_some(sealed.session)
+    }
+}
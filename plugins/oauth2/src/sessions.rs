@@ -0,0 +1,291 @@
+//! Server-side sessions, and letting users list and revoke their own
+//!
+//! Sessions are kept by id and indexed by the email of the identity they
+//! belong to, so all of a user's sessions, from every provider sharing the
+//! session registry, can be found without scanning the store. Each session
+//! records the User-Agent it was created with and when it was last used,
+//! refreshed at most once per `ACTIVITY_RESOLUTION` so requests rarely need
+//! the store's write lock.
+//!
+//! A signed-in user can `GET /auth/sessions` for a JSON list of their
+//! sessions and `POST /auth/sessions/revoke` with `id=<id>` to end one of them
+//! or `all=true` to end every one, this session included. Listed ids are
+//! derived from the session ids, which stay secret. Sessions sealed into
+//! cookies are not stored, so they cannot be listed or revoked.
+
+use super::*;
+use crate::device::json_response;
+use std::collections::HashSet;
+
+pub(crate) const SESSIONS_PATH: &str = "/auth/sessions";
+pub(crate) const REVOKE_SESSIONS_PATH: &str = "/auth/sessions/revoke";
+
+/// How stale a session's last activity may get before it is updated
+const ACTIVITY_RESOLUTION: Duration = Duration::from_secs(60);
+/// Longest User-Agent recorded for a session
+const MAX_USER_AGENT_LENGTH: usize = 256;
+/// Bytes of a session id's SHA-256 used to name it in listings
+const SESSION_HANDLE_BYTES: usize = 16;
+
+const ERROR_SEALED_SESSIONS: &str = "Sessions sealed into cookies cannot be listed or revoked";
+const ERROR_REVOKE_TARGET: &str = "Name a session with 'id' or revoke every session with 'all=true'";
+const ERROR_UNKNOWN_SESSION: &str = "No such session";
+
+/// Sessions by id, indexed by the email of their identity
+#[derive(Debug, Default)]
+pub(crate) struct SessionTable {
+    sessions: HashMap<String, SessionData>,
+    by_email: HashMap<String, HashSet<String>>,
+}
+
+impl SessionTable {
+    pub(crate) fn insert(&mut self, session_id: String, session_data: SessionData) -> Option<SessionData> {
+        let previous = self.remove(&session_id);
+        self.by_email.entry(session_data.email.clone()).or_default().insert(session_id.clone());
+        self.sessions.insert(session_id, session_data);
+        previous
+    }
+
+    pub(crate) fn remove(&mut self, session_id: &str) -> Option<SessionData> {
+        let session_data = self.sessions.remove(session_id)?;
+        if let Some(ids) = self.by_email.get_mut(&session_data.email) {
+            ids.remove(session_id);
+            if ids.is_empty() {
+                self.by_email.remove(&session_data.email);
+            }
+        }
+        Some(session_data)
+    }
+
+    pub(crate) fn get(&self, session_id: &str) -> Option<&SessionData> {
+        self.sessions.get(session_id)
+    }
+
+    pub(crate) fn get_mut(&mut self, session_id: &str) -> Option<&mut SessionData> {
+        self.sessions.get_mut(session_id)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn contains_key(&self, session_id: &str) -> bool {
+        self.sessions.contains_key(session_id)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn is_empty(&self) -> bool {
+        self.sessions.is_empty()
+    }
+
+    pub(crate) fn iter(&self) -> impl Iterator<Item = (&String, &SessionData)> {
+        self.sessions.iter()
+    }
+
+    /// Ids of every session of an identity
+    pub(crate) fn ids_for(&self, email: &str) -> Vec<String> {
+        self.by_email.get(email)
+            .map(|ids| ids.iter().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Notes that a session was just used
+    fn touch(&mut self, session_id: &str, now: SystemTime) {
+        if let Some(session_data) = self.sessions.get_mut(session_id) {
+            session_data.last_seen = Some(now);
+        }
+    }
+}
+
+impl OAuth2Plugin {
+    /// Records that the request used its server-side session, unless that
+    /// was noted recently
+    pub(crate) async fn record_activity(&self, request: &PluginRequest) {
+        if self.sealer.is_some() {
+            return;
+        }
+        let Some(session_id) = self.get_session_id_from_request(request) else { return };
+        let now = SystemTime::now();
+        let stale = find_session(&*self.sessions.read().await, &session_id)
+            .is_some_and(|session_data| session_data.last_seen
+                .and_then(|last_seen| now.duration_since(last_seen).ok())
+                .is_none_or(|since| since >= ACTIVITY_RESOLUTION));
+        if stale {
+            self.sessions.write().await.touch(&session_id, now);
+        }
+    }
+
+    /// Lists the sessions of the signed-in user as JSON
+    pub(crate) async fn handle_list_sessions(&self, request: &PluginRequest, user: &SessionData, context: &PluginContext) -> Response<Body> {
+        if self.sealer.is_some() {
+            return json_response(StatusCode::NOT_IMPLEMENTED, serde_json::json!({ "error": ERROR_SEALED_SESSIONS }));
+        }
+        let current = self.get_session_id_from_request(request);
+        let sessions = self.sessions.read().await;
+        let mut listed: Vec<(SystemTime, serde_json::Value)> = sessions.ids_for(&user.email).iter()
+            .filter_map(|session_id| {
+                let session_data = sessions.get(session_id)?;
+                Some((session_data.created_at, serde_json::json!({
+                    "id": session_handle(session_id),
+                    "provider": session_data.provider,
+                    "created_at": unix_seconds(session_data.created_at),
+                    "last_seen": session_data.last_seen.map(unix_seconds),
+                    "user_agent": session_data.user_agent,
+                    "current": current.as_deref() == Some(session_id.as_str()),
+                })))
+            })
+            .collect();
+        // Newest first
+        listed.sort_by_key(|(created_at, _)| std::cmp::Reverse(*created_at));
+        context.log_verbose(&format!("[OAuth2-{}] Listing {} session(s) of {}", self.provider, listed.len(), user.email));
+        json_response(StatusCode::OK, serde_json::json!({
+            "sessions": listed.into_iter().map(|(_, session)| session).collect::<Vec<_>>(),
+        }))
+    }
+
+    /// Ends one or all of the signed-in user's sessions
+    ///
+    /// The session to end is named by `id` and `all=true` ends every one, in
+    /// a form or JSON body. Ending the request's own session also clears its
+    /// cookie.
+    pub(crate) async fn handle_revoke_sessions(&self, request: &mut PluginRequest, user: &SessionData, context: &PluginContext) -> Response<Body> {
+        if self.sealer.is_some() {
+            return json_response(StatusCode::NOT_IMPLEMENTED, serde_json::json!({ "error": ERROR_SEALED_SESSIONS }));
+        }
+        let (id, all) = revoke_target(request).await;
+        if !all && id.is_none() {
+            return json_response(StatusCode::BAD_REQUEST, serde_json::json!({ "error": ERROR_REVOKE_TARGET }));
+        }
+        let current = self.get_session_id_from_request(request);
+
+        let revoked: Vec<String> = {
+            let mut sessions = self.sessions.write().await;
+            let targets: Vec<String> = sessions.ids_for(&user.email).into_iter()
+                .filter(|session_id| all || id.as_deref() == Some(session_handle(session_id).as_str()))
+                .collect();
+            if targets.is_empty() && !all {
+                return json_response(StatusCode::NOT_FOUND, serde_json::json!({ "error": ERROR_UNKNOWN_SESSION }));
+            }
+            for session_id in &targets {
+                sessions.remove(session_id);
+            }
+            targets
+        };
+        context.log_verbose(&format!("[OAuth2-{}] Revoked {} session(s) of {}", self.provider, revoked.len(), user.email));
+
+        let mut response = json_response(StatusCode::OK, serde_json::json!({ "revoked": revoked.len() }));
+        if current.is_some_and(|current| revoked.contains(&current)) {
+            if let Ok(cookie) = self.create_expired_session_cookie().to_string().parse() {
+                response.headers_mut().insert(SET_COOKIE, cookie);
+            }
+        }
+        response
+    }
+}
+
+/// The session a revoke request names, and whether it asks for all of them
+async fn revoke_target(request: &mut PluginRequest) -> (Option<String>, bool) {
+    let is_json = request.http_request.headers().get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.trim().to_lowercase().starts_with(CONTENT_TYPE_JSON));
+    let body = request.body().await
+        .ok()
+        .and_then(|body| body.to_bytes().ok())
+        .unwrap_or_default();
+    if is_json {
+        let json: serde_json::Value = serde_json::from_slice(&body).unwrap_or_default();
+        let id = json.get("id").and_then(|id| id.as_str()).map(str::to_string);
+        let all = json.get("all").is_some_and(|all| all.as_bool() == Some(true) || all.as_str() == Some("true"));
+        return (id, all);
+    }
+    let params: HashMap<String, String> = url::form_urlencoded::parse(&body).into_owned().collect();
+    let all = params.get("all").is_some_and(|all| matches!(all.as_str(), "true" | "1"));
+    (params.get("id").cloned().filter(|id| !id.is_empty()), all)
+}
+
+/// The User-Agent a session is created with, shortened to a sane length
+pub(crate) fn session_user_agent(request: &PluginRequest) -> Option<String> {
+    let user_agent = request.http_request.headers().get(hyper::header::USER_AGENT)?.to_str().ok()?;
+    Some(user_agent.chars().take(MAX_USER_AGENT_LENGTH).collect())
+}
+
+/// Names a session in listings without revealing its id
+fn session_handle(session_id: &str) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, session_id.as_bytes());
+    digest.as_ref()[..SESSION_HANDLE_BYTES].iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Seconds since the Unix epoch
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(std::time::UNIX_EPOCH).map(|since| since.as_secs()).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tests::{create_test_context, create_test_plugin, create_test_request, create_user};
+
+    async fn body_json(response: Response<Body>) -> serde_json::Value {
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_sessions_are_listed_and_revoked_per_user() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+        let (mine, other, theirs) = (new_session_id(), new_session_id(), new_session_id());
+        {
+            let mut sessions = plugin.sessions.write().await;
+            sessions.insert(mine.clone(), create_user("google", "1", "ann@example.com", true));
+            sessions.insert(other.clone(), create_user("github", "2", "ann@example.com", true));
+            sessions.insert(theirs.clone(), create_user("google", "3", "bob@example.com", true));
+        }
+        let cookie = format!("session_id={}", mine);
+
+        let mut request = create_test_request("GET", SESSIONS_PATH, vec![("cookie", &cookie), ("user-agent", "test-agent")]);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::OK);
+        let listed = body_json(response).await;
+        let listed = listed["sessions"].as_array().unwrap();
+        assert_eq!(listed.len(), 2);
+        assert!(listed.iter().all(|session| session["id"] != mine.as_str() && session["id"] != other.as_str()));
+        let current: Vec<_> = listed.iter().filter(|session| session["current"] == true).collect();
+        assert_eq!(current.len(), 1);
+        assert!(current[0]["last_seen"].is_u64());
+
+        // Another user's session cannot be named
+        let mut request = create_test_request("POST", REVOKE_SESSIONS_PATH, vec![("cookie", &cookie)]);
+        *request.http_request.body_mut() = Body::from(format!("id={}", session_handle(&theirs)));
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        let mut request = create_test_request("POST", REVOKE_SESSIONS_PATH, vec![("cookie", &cookie)]);
+        *request.http_request.body_mut() = Body::from(format!("id={}", session_handle(&other)));
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(SET_COOKIE).is_none());
+        assert!(!plugin.sessions.read().await.contains_key(&other));
+
+        let mut request = create_test_request("POST", REVOKE_SESSIONS_PATH, vec![("cookie", &cookie), ("content-type", "application/json")]);
+        *request.http_request.body_mut() = Body::from(r#"{"all": true}"#);
+        let response = plugin.handle_request(&mut request, &context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(SET_COOKIE).unwrap().to_str().unwrap().contains("Max-Age=0"));
+        assert_eq!(body_json(response).await["revoked"], 1);
+
+        let sessions = plugin.sessions.read().await;
+        assert!(!sessions.contains_key(&mine));
+        assert!(sessions.contains_key(&theirs));
+        assert!(sessions.ids_for("ann@example.com").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_session_listing_needs_a_session() {
+        let plugin = create_test_plugin();
+        let context = create_test_context();
+
+        let mut request = create_test_request("GET", SESSIONS_PATH, vec![]);
+        assert!(plugin.handle_request(&mut request, &context).await.is_none());
+
+        let mut request = create_test_request("POST", REVOKE_SESSIONS_PATH, vec![]);
+        assert!(plugin.handle_request(&mut request, &context).await.is_none());
+    }
+}
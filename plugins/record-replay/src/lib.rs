@@ -0,0 +1,445 @@
+//! Record/Replay Plugin for Rusty Beam
+//!
+//! This plugin captures complete request/response exchanges to disk so that
+//! problems reported by users can be reproduced, and so that recorded traffic
+//! can be replayed through the pipeline as a regression test after changing
+//! plugins or configuration.
+//!
+//! ## Features
+//! - **Full Capture**: Method, URL, headers and bodies of requests and responses
+//! - **Pipeline Metadata**: Records the metadata set by other plugins (such as
+//!   `authenticated_user`) as it stood when the response was produced
+//! - **Two Formats**: A HAR 1.2 document, or newline-delimited JSON with one
+//!   HAR entry per line
+//! - **Binary Safe**: Bodies that are not UTF-8 are stored base64-encoded
+//! - **Replay**: Recordings are fed back through the pipeline with
+//!   `rusty-beam --replay <recording> <config-file>`
+//!
+//! ## Configuration
+//! - `record_file`: Path of the recording (required)
+//! - `format`: "ndjson" or "har" (default: "har" for `.har` files, otherwise "ndjson")
+//! - `enabled`: Record exchanges (default: true)
+//! - `path_prefix`: Only record requests whose path starts with this prefix (default: all)
+//! - `max_body_size`: Bodies larger than this many bytes are truncated in the
+//!   recording (default: 1048576)
+//! - `redact_headers`: Comma-separated headers whose values are replaced with
+//!   `REDACTED`, e.g. "authorization,cookie" (default: none)
+//! - `max_entries`: Most recent exchanges a HAR document keeps (default: 1000)
+//! - `flush_every`: Exchanges between rewrites of a HAR document, which is
+//!   also written at shutdown (default: 20)
+//!
+//! ## Pipeline Placement
+//! Place this plugin first in the pipeline. It buffers the request body during
+//! the request phase, so it must run before any plugin that consumes the body,
+//! and it records the final response after every other plugin has modified it.
+//!
+//! ## Replay
+//! Requests the replay runner marks with `replayed` metadata are never
+//! recorded, so a recording can be replayed against a configuration that still includes
+//! this plugin. Streaming responses (`text/event-stream`) and protocol
+//! upgrades are not recorded, since their bodies never end.
+
+use rusty_beam_plugin_api::{fs, Plugin, PluginRequest, PluginContext, PluginResponse, create_plugin, log_error, log_warn, transform_body};
+use async_trait::async_trait;
+use base64::Engine;
+use hyper::{Body, Response, StatusCode, header::{HeaderMap, CONTENT_TYPE}};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+// Default values
+const DEFAULT_PLUGIN_NAME: &str = "record-replay";
+const DEFAULT_MAX_BODY_SIZE: usize = 1024 * 1024;
+const DEFAULT_MAX_ENTRIES: usize = 1000;
+const DEFAULT_FLUSH_EVERY: usize = 20;
+const HAR_EXTENSION: &str = "har";
+const HAR_VERSION: &str = "1.2";
+const CREATOR_NAME: &str = "rusty-beam";
+
+// Request markers
+const REPLAYED_METADATA_KEY: &str = "replayed";
+const STARTED_METADATA_KEY: &str = "record_replay_started_ms";
+
+// Recorded values
+const REDACTED_VALUE: &str = "REDACTED";
+const BASE64_ENCODING: &str = "base64";
+const EVENT_STREAM_CONTENT_TYPE: &str = "text/event-stream";
+
+/// File format of a recording
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RecordFormat {
+    /// One HAR entry per line
+    Ndjson,
+    /// A complete HAR document
+    Har,
+}
+
+/// Entries of a HAR document and how many are not yet on disk
+#[derive(Debug, Default)]
+struct HarLog {
+    entries: VecDeque<Value>,
+    unflushed: usize,
+}
+
+/// Record/Replay Plugin for capturing exchanges to disk
+#[derive(Debug)]
+pub struct RecordReplayPlugin {
+    name: String,
+    record_file: Option<PathBuf>,
+    format: RecordFormat,
+    enabled: bool,
+    path_prefix: Option<String>,
+    max_body_size: usize,
+    /// Lowercase names of headers whose values are not recorded
+    redact_headers: Vec<String>,
+    max_entries: usize,
+    flush_every: usize,
+    /// Entries of the HAR document, rewritten in full every `flush_every` exchanges
+    har_log: Mutex<HarLog>,
+    /// Held while writing the HAR document, so documents reach the disk in order
+    har_write_lock: tokio::sync::Mutex<()>,
+}
+
+impl RecordReplayPlugin {
+    pub fn new(config: HashMap<String, String>) -> Self {
+        let name = config.get("name").cloned().unwrap_or_else(|| DEFAULT_PLUGIN_NAME.to_string());
+        let record_file = config.get("record_file")
+            .map(|path| PathBuf::from(path.strip_prefix("file://").unwrap_or(path)));
+        if record_file.is_none() {
+            log_warn!(name, "No record_file configured, nothing will be recorded");
+        }
+
+        let format = match config.get("format").map(|f| f.to_lowercase()) {
+            Some(format) if format == "har" => RecordFormat::Har,
+            Some(format) if format == "ndjson" => RecordFormat::Ndjson,
+            _ => match record_file.as_ref().and_then(|path| path.extension()) {
+                Some(extension) if extension == HAR_EXTENSION => RecordFormat::Har,
+                _ => RecordFormat::Ndjson,
+            },
+        };
+
+        let enabled = config.get("enabled")
+            .map(|v| v.to_lowercase() == "true")
+            .unwrap_or(true);
+        let path_prefix = config.get("path_prefix").cloned();
+        let max_body_size = config.get("max_body_size")
+            .and_then(|v| v.trim().parse().ok())
+            .unwrap_or(DEFAULT_MAX_BODY_SIZE);
+        let redact_headers = config.get("redact_headers")
+            .map(|headers| headers.split(',')
+                .map(|h| h.trim().to_lowercase())
+                .filter(|h| !h.is_empty())
+                .collect())
+            .unwrap_or_default();
+        let max_entries = config.get("max_entries")
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|&max| max > 0)
+            .unwrap_or(DEFAULT_MAX_ENTRIES);
+        let flush_every = config.get("flush_every")
+            .and_then(|v| v.trim().parse().ok())
+            .filter(|&every| every > 0)
+            .unwrap_or(DEFAULT_FLUSH_EVERY);
+
+        Self {
+            name,
+            record_file,
+            format,
+            enabled,
+            path_prefix,
+            max_body_size,
+            redact_headers,
+            max_entries,
+            flush_every,
+            har_log: Mutex::new(HarLog::default()),
+            har_write_lock: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Whether this request should be captured
+    fn should_record(&self, request: &PluginRequest) -> bool {
+        self.enabled
+            && self.record_file.is_some()
+            && !request.has_metadata(REPLAYED_METADATA_KEY)
+            && self.path_prefix.as_ref().is_none_or(|prefix| request.path.starts_with(prefix.as_str()))
+    }
+
+    /// Whether the response body can be buffered for recording
+    fn is_recordable_response(response: &Response<Body>) -> bool {
+        let is_event_stream = response.headers().get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .map(|v| v.starts_with(EVENT_STREAM_CONTENT_TYPE))
+            .unwrap_or(false);
+        response.status() != StatusCode::SWITCHING_PROTOCOLS && !is_event_stream
+    }
+
+    /// Converts headers to HAR name/value pairs, redacting configured headers
+    fn header_list(&self, headers: &HeaderMap) -> Vec<Value> {
+        headers.iter()
+            .map(|(name, value)| {
+                let value = if self.redact_headers.iter().any(|h| h == name.as_str()) {
+                    REDACTED_VALUE.to_string()
+                } else {
+                    String::from_utf8_lossy(value.as_bytes()).into_owned()
+                };
+                json!({ "name": name.as_str(), "value": value })
+            })
+            .collect()
+    }
+
+    /// Builds the HAR `text`/`encoding` fields for a body
+    fn body_fields(&self, body: &[u8]) -> Map<String, Value> {
+        let truncated = body.len() > self.max_body_size;
+        let body = &body[..body.len().min(self.max_body_size)];
+
+        let mut fields = Map::new();
+        match std::str::from_utf8(body) {
+            Ok(text) => {
+                fields.insert("text".to_string(), json!(text));
+            }
+            Err(_) => {
+                fields.insert("text".to_string(), json!(base64::engine::general_purpose::STANDARD.encode(body)));
+                fields.insert("encoding".to_string(), json!(BASE64_ENCODING));
+            }
+        }
+        if truncated {
+            fields.insert("_truncated".to_string(), json!(true));
+        }
+        fields
+    }
+
+    /// Builds a HAR entry describing one exchange
+    fn build_entry(
+        &self,
+        request: &PluginRequest,
+        request_body: &[u8],
+        response: &Response<Body>,
+        response_body: &[u8],
+        started_ms: i64,
+        context: &PluginContext,
+    ) -> Value {
+        let http_request = &request.http_request;
+        let uri = http_request.uri();
+        let url = format!(
+            "{}://{}{}",
+            uri.scheme_str().unwrap_or("http"),
+            context.host_name,
+            uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/"),
+        );
+        let query_string: Vec<Value> = uri.query()
+            .map(|query| url::form_urlencoded::parse(query.as_bytes())
+                .map(|(name, value)| json!({ "name": name, "value": value }))
+                .collect())
+            .unwrap_or_default();
+        let content_type = |headers: &HeaderMap| headers.get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("")
+            .to_string();
+
+        let mut har_request = json!({
+            "method": http_request.method().as_str(),
+            "url": url,
+            "httpVersion": format!("{:?}", http_request.version()),
+            "cookies": [],
+            "headers": self.header_list(http_request.headers()),
+            "queryString": query_string,
+            "headersSize": -1,
+            "bodySize": request_body.len(),
+        });
+        if !request_body.is_empty() {
+            let mut post_data = self.body_fields(request_body);
+            post_data.insert("mimeType".to_string(), json!(content_type(http_request.headers())));
+            har_request["postData"] = Value::Object(post_data);
+        }
+
+        let mut content = self.body_fields(response_body);
+        content.insert("size".to_string(), json!(response_body.len()));
+        content.insert("mimeType".to_string(), json!(content_type(response.headers())));
+
+        let started = chrono::DateTime::from_timestamp_millis(started_ms).unwrap_or_else(chrono::Utc::now);
+        let elapsed_ms = (chrono::Utc::now().timestamp_millis() - started_ms).max(0);
+        // Sorted so that recordings diff cleanly
+        let metadata: BTreeMap<&String, &String> = request.metadata.iter()
+            .filter(|(key, _)| key.as_str() != STARTED_METADATA_KEY)
+            .collect();
+
+        json!({
+            "startedDateTime": started.to_rfc3339(),
+            "time": elapsed_ms,
+            "request": har_request,
+            "response": {
+                "status": response.status().as_u16(),
+                "statusText": response.status().canonical_reason().unwrap_or(""),
+                "httpVersion": format!("{:?}", response.version()),
+                "cookies": [],
+                "headers": self.header_list(response.headers()),
+                "content": content,
+                "redirectURL": response.headers().get(hyper::header::LOCATION)
+                    .and_then(|v| v.to_str().ok())
+                    .unwrap_or(""),
+                "headersSize": -1,
+                "bodySize": response_body.len(),
+            },
+            "cache": {},
+            "timings": { "send": 0, "wait": elapsed_ms, "receive": 0 },
+            "_host": context.host_name,
+            "_metadata": metadata,
+        })
+    }
+
+    /// Appends an entry to the recording
+    async fn write_entry(&self, path: &Path, entry: Value, context: &PluginContext) -> std::io::Result<()> {
+        let path = path.to_path_buf();
+        match self.format {
+            RecordFormat::Ndjson => {
+                let line = format!("{}\n", entry);
+                fs::blocking(context, move || {
+                    if let Some(parent) = path.parent() {
+                        std::fs::create_dir_all(parent)?;
+                    }
+                    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+                    file.write_all(line.as_bytes())
+                }).await
+            }
+            RecordFormat::Har => {
+                {
+                    let mut log = self.har_log.lock().unwrap_or_else(|e| e.into_inner());
+                    log.entries.push_back(entry);
+                    while log.entries.len() > self.max_entries {
+                        log.entries.pop_front();
+                    }
+                    log.unflushed += 1;
+                }
+                let _writing = self.har_write_lock.lock().await;
+                let Some(json) = self.har_document(false)? else { return Ok(()) };
+                fs::blocking(context, move || write_har(&path, &json)).await
+            }
+        }
+    }
+
+    /// Serializes the HAR document once `flush_every` entries are unwritten,
+    /// or whenever any are if `force` is set
+    fn har_document(&self, force: bool) -> std::io::Result<Option<String>> {
+        let mut log = self.har_log.lock().unwrap_or_else(|e| e.into_inner());
+        if log.unflushed == 0 || (!force && log.unflushed < self.flush_every) {
+            return Ok(None);
+        }
+        log.unflushed = 0;
+        let document = json!({
+            "log": {
+                "version": HAR_VERSION,
+                "creator": { "name": CREATOR_NAME, "version": env!("CARGO_PKG_VERSION") },
+                "entries": log.entries,
+            }
+        });
+        serde_json::to_string_pretty(&document)
+            .map(Some)
+            .map_err(std::io::Error::other)
+    }
+}
+
+/// Replaces the HAR document on disk
+fn write_har(path: &Path, json: &str) -> std::io::Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, json)
+}
+
+#[async_trait]
+impl Plugin for RecordReplayPlugin {
+    async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
+        if !self.should_record(request) {
+            return None;
+        }
+
+        // Buffer the body now so it is still available when the response is recorded
+        if let Err(e) = request.get_body().await {
+            context.log_error(&format!("[RecordReplay] {}", e));
+        }
+        request.set_metadata(STARTED_METADATA_KEY.to_string(), chrono::Utc::now().timestamp_millis().to_string());
+        None
+    }
+
+    async fn handle_response(&self, request: &PluginRequest, response: &mut Response<Body>, context: &PluginContext) {
+        let Some(record_file) = &self.record_file else { return };
+        if !self.should_record(request) {
+            return;
+        }
+        // Only exchanges seen during the request phase have a buffered body
+        let Some(started_ms) = request.get_metadata(STARTED_METADATA_KEY).and_then(|v| v.parse().ok()) else {
+            return;
+        };
+        if !Self::is_recordable_response(response) {
+            context.log_verbose(&format!("[RecordReplay] Not recording streaming response for {}", request.path));
+            return;
+        }
+
+        let request_body = match request.body_cache.lock().await.as_ref().map(|body| body.to_bytes()) {
+            Some(Ok(bytes)) => bytes,
+            Some(Err(e)) => {
+                context.log_error(&format!("[RecordReplay] Failed to read spooled request body: {}", e));
+                return;
+            }
+            None => hyper::body::Bytes::new(),
+        };
+        let mut response_body = hyper::body::Bytes::new();
+        if let Err(e) = transform_body(response, |bytes| {
+            response_body = bytes.clone();
+            None::<hyper::body::Bytes>
+        }).await {
+            context.log_error(&format!("[RecordReplay] Failed to read response body: {}", e));
+            return;
+        }
+
+        let entry = self.build_entry(request, &request_body, response, &response_body, started_ms, context);
+        match self.write_entry(record_file, entry, context).await {
+            Ok(()) => context.log_verbose(&format!("[RecordReplay] Recorded {} {}", request.http_request.method(), request.path)),
+            Err(e) => context.log_error(&format!("[RecordReplay] Failed to write {:?}: {}", record_file, e)),
+        }
+    }
+
+    async fn on_startup(&self) {
+        // Keep the entries of an existing HAR document rather than overwriting them
+        let Some(record_file) = &self.record_file else { return };
+        if self.format != RecordFormat::Har {
+            return;
+        }
+        let Ok(content) = std::fs::read_to_string(record_file) else { return };
+        match serde_json::from_str::<Value>(&content) {
+            Ok(document) => {
+                if let Some(existing) = document["log"]["entries"].as_array() {
+                    let mut log = self.har_log.lock().unwrap_or_else(|e| e.into_inner());
+                    let skipped = existing.len().saturating_sub(self.max_entries);
+                    log.entries = existing[skipped..].iter().cloned().collect();
+                }
+            }
+            Err(e) => log_warn!(self.name, "Ignoring unreadable recording: {}", e; file = record_file.display()),
+        }
+    }
+
+    async fn on_shutdown(&self) {
+        // Write the entries recorded since the last flush
+        let Some(record_file) = &self.record_file else { return };
+        if self.format != RecordFormat::Har {
+            return;
+        }
+        let _writing = self.har_write_lock.lock().await;
+        let written = match self.har_document(true) {
+            Ok(Some(json)) => write_har(record_file, &json),
+            Ok(None) => Ok(()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            log_error!(self.name, "Failed to write recording: {}", e; file = record_file.display());
+        }
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+create_plugin!(RecordReplayPlugin);
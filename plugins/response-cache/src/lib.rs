@@ -0,0 +1,519 @@
+//! Response Cache Plugin for Rusty Beam
+//!
+//! This plugin keeps complete responses to GET requests in memory and answers
+//! later requests for the same resource from them, so read-heavy sites do not
+//! read files and evaluate selectors again for every request.
+//!
+//! ## Features
+//! - **Full Responses**: Status, headers and body are kept per path and
+//!   query, with a variant for each value of the request headers the
+//!   response names in `Vary`
+//! - **Cache-Control**: Responses marked `no-store`, `no-cache` or `private`
+//!   are not kept, and `s-maxage` or `max-age` sets how long a response
+//!   stays fresh
+//! - **Validators**: Conditional GETs are answered with `304 Not Modified`
+//!   using the `ETag` or `Last-Modified` of the kept response
+//! - **Invalidation**: A successful PUT, POST, PATCH, DELETE, COPY or MOVE,
+//!   or a change announced by another plugin, drops the responses for the
+//!   path it changed
+//! - **Stale While Revalidate**: For configured paths, a response that is no
+//!   longer fresh is still served while a replacement is fetched in the
+//!   background
+//!
+//! ## Configuration
+//! - `ttl`: Seconds a response without `max-age` stays fresh (default: 60)
+//! - `max_entries`: Number of responses kept (default: 1000)
+//! - `max_entry_size`: Largest body kept, in bytes (default: 1048576)
+//! - `stale_while_revalidate`: Seconds a response may still be served after
+//!   it stopped being fresh (default: 0, disabled)
+//! - `stale_paths`: Comma-separated path patterns, where `*` stands for any
+//!   run of characters, of the paths stale responses are served for
+//!   (default: `*`)
+//!
+//! ## What Is Kept
+//! A response is kept when it answers a GET without a `Range` header, comes
+//! from a plugin after this one, has a status of 200, 203, 204, 300, 301,
+//! 404 or 410 and a body of known length within `max_entry_size`. Responses
+//! setting cookies or varying on `*` are never kept, nor are responses to
+//! requests with an `Authorization` or `Cookie` header, or from an
+//! authenticated user, unless marked `public` or given an `s-maxage`.
+//! Clients skip kept responses with `Cache-Control: no-cache`, which still
+//! stores the new response, or `no-store`, which does not.
+//! HEAD requests are answered from the responses kept for GET.
+//!
+//! Every response the cache took part in carries an `X-Cache` header of
+//! `HIT`, `STALE` or `MISS`, and responses from the cache an `Age` header.
+//!
+//! ## Pipeline Placement
+//! Place the plugin after authentication and authorization, so every request
+//! is checked before it is answered from the cache, and before the plugins
+//! producing responses, such as the selector-handler and file-handler.
+//! Files changed other than through the pipeline are only noticed once their
+//! responses stop being fresh.
+
+mod store;
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use hyper::header::{
+    HeaderMap, HeaderName, HeaderValue, AGE, AUTHORIZATION, CACHE_CONTROL, CONTENT_LENGTH, COOKIE, ETAG,
+    IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED, RANGE, SET_COOKIE, VARY,
+};
+use hyper::body::HttpBody;
+use hyper::{Body, Method, Request, Response, StatusCode};
+use rusty_beam_plugin_api::condition::glob_match;
+use rusty_beam_plugin_api::{create_plugin, log_warn, paths, transform_body, DocumentChange, Plugin, PluginContext, PluginRequest, PluginResponse};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use store::{CachedResponse, ResponseStore};
+
+// Default configuration values
+const DEFAULT_PLUGIN_NAME: &str = "response-cache";
+const DEFAULT_TTL_SECONDS: u64 = 60;
+const DEFAULT_MAX_ENTRIES: usize = 1000;
+const DEFAULT_MAX_ENTRY_SIZE: u64 = 1024 * 1024;
+const DEFAULT_STALE_PATHS: &str = "*";
+
+/// Response header telling whether the response came from the cache
+const CACHE_STATUS_HEADER: &str = "X-Cache";
+const CACHE_STATUS_HIT: &str = "HIT";
+const CACHE_STATUS_STALE: &str = "STALE";
+const CACHE_STATUS_MISS: &str = "MISS";
+
+/// Request metadata recording what the cache did with a request
+const METADATA_CACHE_STATUS: &str = "response_cache";
+
+/// Request metadata naming the user a request was authenticated as
+const METADATA_AUTHENTICATED_USER: &str = "authenticated_user";
+
+/// Request header of the requests fetching replacements for stale responses,
+/// which skip the cache
+const REVALIDATION_HEADER: &str = "X-Cache-Revalidation";
+
+/// Statuses whose responses may be kept without explicit freshness
+const CACHEABLE_STATUSES: &[u16] = &[200, 203, 204, 300, 301, 404, 410];
+
+/// Methods whose successful responses drop the responses kept for their path
+const CHANGE_METHODS: &[&str] = &["PUT", "POST", "PATCH", "DELETE", "COPY", "MOVE"];
+
+/// Headers of a kept response repeated in a 304 answer
+const NOT_MODIFIED_HEADERS: &[HeaderName] = &[CACHE_CONTROL, ETAG, LAST_MODIFIED, VARY];
+
+/// Plugin caching responses to GET requests
+#[derive(Debug)]
+pub struct ResponseCachePlugin {
+    name: String,
+    ttl: Duration,
+    max_entry_size: u64,
+    stale_while_revalidate: Duration,
+    stale_paths: Vec<String>,
+    store: Arc<Mutex<ResponseStore>>,
+}
+
+impl ResponseCachePlugin {
+    pub fn new(config: HashMap<String, String>) -> Self {
+        let name = config.get("name").cloned().unwrap_or_else(|| DEFAULT_PLUGIN_NAME.to_string());
+        let number = |key: &str, default: u64| config.get(key)
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(default);
+        let stale_paths = config.get("stale_paths").map(String::as_str).unwrap_or(DEFAULT_STALE_PATHS)
+            .split(',')
+            .map(|pattern| pattern.trim().to_string())
+            .filter(|pattern| !pattern.is_empty())
+            .collect();
+
+        Self {
+            ttl: Duration::from_secs(number("ttl", DEFAULT_TTL_SECONDS)),
+            max_entry_size: number("max_entry_size", DEFAULT_MAX_ENTRY_SIZE),
+            stale_while_revalidate: Duration::from_secs(number("stale_while_revalidate", 0)),
+            stale_paths,
+            store: Arc::new(Mutex::new(ResponseStore::new(number("max_entries", DEFAULT_MAX_ENTRIES as u64) as usize))),
+            name,
+        }
+    }
+
+    fn store(&self) -> std::sync::MutexGuard<'_, ResponseStore> {
+        self.store.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether a response that is no longer fresh may still be served for a path
+    fn may_serve_stale(&self, response: &CachedResponse, path: &str) -> bool {
+        response.age() < response.fresh_for + self.stale_while_revalidate
+            && self.stale_paths.iter().any(|pattern| glob_match(pattern, path))
+    }
+
+    /// How long a response stays fresh, or None if it must not be kept
+    fn freshness(&self, request: &PluginRequest, response: &Response<Body>) -> Option<Duration> {
+        if !CACHEABLE_STATUSES.contains(&response.status().as_u16())
+            || response.headers().contains_key(SET_COOKIE)
+            || vary_names(response.headers()).iter().any(|name| name == "*")
+        {
+            return None;
+        }
+        let directives = cache_control(response.headers());
+        if ["no-store", "no-cache", "private"].iter().any(|directive| directives.contains_key(*directive)) {
+            return None;
+        }
+        // A shared cache only keeps responses to authorized requests when told it may
+        let personal = request.http_request.headers().contains_key(AUTHORIZATION)
+            || request.http_request.headers().contains_key(COOKIE)
+            || request.get_metadata(METADATA_AUTHENTICATED_USER).is_some();
+        if personal
+            && !directives.contains_key("public")
+            && !directives.contains_key("s-maxage")
+        {
+            return None;
+        }
+        let seconds = |directive: &str| directives.get(directive)
+            .and_then(|value| value.as_deref())
+            .and_then(|value| value.parse().ok())
+            .map(Duration::from_secs);
+        let fresh_for = seconds("s-maxage").or_else(|| seconds("max-age")).unwrap_or(self.ttl);
+        (!fresh_for.is_zero()).then_some(fresh_for)
+    }
+
+    /// Keep a response to a GET, leaving its body as it was
+    async fn keep(&self, key: String, request: &PluginRequest, response: &mut Response<Body>, context: &PluginContext) {
+        let Some(fresh_for) = self.freshness(request, response) else {
+            return;
+        };
+        let length = response.body().size_hint().exact().or_else(|| {
+            response.headers().get(CONTENT_LENGTH)
+                .and_then(|length| length.to_str().ok())
+                .and_then(|length| length.parse().ok())
+        });
+        if length.is_none_or(|length| length > self.max_entry_size) {
+            return;
+        }
+
+        let mut body = None;
+        if transform_body(response, |bytes| {
+            body = Some(bytes.clone());
+            None::<Bytes>
+        }).await.is_err() {
+            return;
+        }
+        let Some(body) = body else { return };
+
+        let vary = vary_names(response.headers()).into_iter()
+            .filter_map(|name| HeaderName::from_bytes(name.as_bytes()).ok())
+            .map(|name| {
+                let value = request.http_request.headers().get(&name).cloned();
+                (name, value)
+            })
+            .collect();
+        let mut headers = response.headers().clone();
+        headers.remove(CACHE_STATUS_HEADER);
+
+        let mut store = self.store();
+        store.insert(&key, CachedResponse {
+            status: response.status(),
+            headers,
+            body,
+            vary,
+            stored: std::time::Instant::now(),
+            fresh_for,
+            revalidating: false,
+        });
+        context.log_verbose(&format!(
+            "[ResponseCache] Stored {} for {}s ({} responses kept)",
+            key, fresh_for.as_secs(), store.len(),
+        ));
+    }
+
+    /// Drop the responses kept for a path, and for the directory it is the index of
+    fn invalidate(&self, path: &str, context: &PluginContext) {
+        let mut store = self.store();
+        let mut removed = store.remove_path(path);
+        if let Some(directory) = path.strip_suffix("index.html").filter(|directory| directory.ends_with('/')) {
+            removed += store.remove_path(directory);
+        } else if path.ends_with('/') {
+            removed += store.remove_path(&format!("{}index.html", path));
+        }
+        if removed > 0 {
+            context.log_verbose(&format!("[ResponseCache] Dropped {} responses for {}", removed, path));
+        }
+    }
+
+    /// Fetch a replacement for a stale response through the pipeline, on a
+    /// thread of its own so the client does not wait for it
+    fn revalidate(&self, key: String, request: &PluginRequest, context: &PluginContext) {
+        let Some(services) = context.services.clone() else {
+            return;
+        };
+        if !self.store().begin_revalidation(&key, request.http_request.headers()) {
+            return;
+        }
+
+        let mut builder = Request::builder().method(Method::GET).uri(request.http_request.uri().clone());
+        for (name, value) in request.http_request.headers() {
+            // The replacement must be a full response
+            if name != IF_NONE_MATCH && name != IF_MODIFIED_SINCE {
+                builder = builder.header(name, value);
+            }
+        }
+        let revalidation = match builder.header(REVALIDATION_HEADER, "1").body(Body::empty()) {
+            Ok(revalidation) => revalidation,
+            Err(_) => {
+                self.store().end_revalidation(&key);
+                return;
+            }
+        };
+
+        let store = Arc::clone(&self.store);
+        let thread_key = key.clone();
+        let spawned = std::thread::Builder::new()
+            .name(format!("{}-revalidate", self.name))
+            .spawn(move || {
+                let response = futures::executor::block_on(services.dispatch(revalidation));
+                let _ = futures::executor::block_on(hyper::body::to_bytes(response.into_body()));
+                store.lock().unwrap_or_else(|e| e.into_inner()).end_revalidation(&thread_key);
+            });
+        if let Err(e) = spawned {
+            log_warn!(self.name, "Failed to start revalidation: {}", e; path = key);
+            self.store().end_revalidation(&key);
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for ResponseCachePlugin {
+    async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
+        let method = request.http_request.method();
+        if (method != Method::GET && method != Method::HEAD) || request.http_request.headers().contains_key(RANGE) {
+            return None;
+        }
+        let directives = cache_control(request.http_request.headers());
+        if directives.contains_key("no-store") {
+            return None;
+        }
+        let key = cache_key(request);
+        let bypass = request.http_request.headers().contains_key(REVALIDATION_HEADER)
+            || directives.contains_key("no-cache")
+            || directives.get("max-age").is_some_and(|age| age.as_deref() == Some("0"));
+        let cached = if bypass { None } else { self.store().get(&key, request.http_request.headers()) };
+
+        let status = match cached {
+            Some(cached) if cached.is_fresh() => Some((cached, CACHE_STATUS_HIT)),
+            Some(cached) if self.may_serve_stale(&cached, &request.path) => {
+                self.revalidate(key.clone(), request, context);
+                Some((cached, CACHE_STATUS_STALE))
+            }
+            _ => None,
+        };
+        let Some((cached, status)) = status else {
+            request.set_metadata(METADATA_CACHE_STATUS.to_string(), CACHE_STATUS_MISS.to_string());
+            return None;
+        };
+        request.set_metadata(METADATA_CACHE_STATUS.to_string(), status.to_string());
+        context.log_verbose(&format!("[ResponseCache] {} {}", status, key));
+        Some(cached_response(&cached, request, status).into())
+    }
+
+    async fn handle_response(&self, request: &PluginRequest, response: &mut Response<Body>, context: &PluginContext) {
+        let method = request.http_request.method().as_str();
+        if CHANGE_METHODS.contains(&method) {
+            if !response.status().is_client_error() && !response.status().is_server_error() {
+                self.invalidate(&request.path, context);
+                if let Some(destination) = destination_path(request) {
+                    self.invalidate(&destination, context);
+                }
+            }
+            return;
+        }
+
+        // Only requests the cache passed on have responses to keep
+        if request.get_metadata(METADATA_CACHE_STATUS) != Some(CACHE_STATUS_MISS) {
+            return;
+        }
+        response.headers_mut().insert(CACHE_STATUS_HEADER, HeaderValue::from_static(CACHE_STATUS_MISS));
+        if method == Method::GET.as_str() {
+            self.keep(cache_key(request), request, response, context).await;
+        }
+    }
+
+    fn on_change(&self, change: &DocumentChange, context: &PluginContext) {
+        self.invalidate(&change.path, context);
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Path and query a response is kept under
+fn cache_key(request: &PluginRequest) -> String {
+    match request.http_request.uri().query() {
+        Some(query) => format!("{}?{}", request.path, query),
+        None => request.path.clone(),
+    }
+}
+
+/// The normalized path of a COPY or MOVE destination
+fn destination_path(request: &PluginRequest) -> Option<String> {
+    let destination = request.http_request.headers().get("Destination")?.to_str().ok()?;
+    let uri: hyper::Uri = destination.parse().ok()?;
+    paths::normalize_request_path(uri.path()).ok()
+}
+
+/// Answer a request from a kept response
+fn cached_response(cached: &CachedResponse, request: &PluginRequest, status: &'static str) -> Response<Body> {
+    let mut response = if cached.status == StatusCode::OK && not_modified(cached, request.http_request.headers()) {
+        let mut response = Response::new(Body::empty());
+        *response.status_mut() = StatusCode::NOT_MODIFIED;
+        for name in NOT_MODIFIED_HEADERS {
+            for value in cached.headers.get_all(name) {
+                response.headers_mut().append(name, value.clone());
+            }
+        }
+        response
+    } else {
+        let body = if request.http_request.method() == Method::HEAD {
+            Body::empty()
+        } else {
+            Body::from(cached.body.clone())
+        };
+        let mut response = Response::new(body);
+        *response.status_mut() = cached.status;
+        *response.headers_mut() = cached.headers.clone();
+        response
+    };
+    response.headers_mut().insert(AGE, HeaderValue::from(cached.age().as_secs()));
+    response.headers_mut().insert(CACHE_STATUS_HEADER, HeaderValue::from_static(status));
+    response
+}
+
+/// Whether a conditional request's validators match a kept response
+fn not_modified(cached: &CachedResponse, headers: &HeaderMap) -> bool {
+    if let Some(if_none_match) = headers.get(IF_NONE_MATCH).and_then(|value| value.to_str().ok()) {
+        let Some(etag) = cached.headers.get(ETAG).and_then(|value| value.to_str().ok()) else {
+            return false;
+        };
+        // GETs compare entity tags weakly
+        let weak = |tag: &str| tag.trim().trim_start_matches("W/").to_string();
+        return if_none_match.split(',').any(|tag| tag.trim() == "*" || weak(tag) == weak(etag));
+    }
+    let since = headers.get(IF_MODIFIED_SINCE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok());
+    let modified = cached.headers.get(LAST_MODIFIED)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| httpdate::parse_http_date(value).ok());
+    matches!((since, modified), (Some(since), Some(modified)) if modified <= since)
+}
+
+/// Cache-Control directives, by lowercase name, with their unquoted values
+fn cache_control(headers: &HeaderMap) -> HashMap<String, Option<String>> {
+    headers.get_all(CACHE_CONTROL).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|directive| {
+            let (name, value) = match directive.split_once('=') {
+                Some((name, value)) => (name, Some(value.trim().trim_matches('"').to_string())),
+                None => (directive, None),
+            };
+            let name = name.trim().to_lowercase();
+            (!name.is_empty()).then_some((name, value))
+        })
+        .collect()
+}
+
+/// Lowercase names of the request headers a response varies on
+fn vary_names(headers: &HeaderMap) -> Vec<String> {
+    headers.get_all(VARY).iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_lowercase())
+        .filter(|name| !name.is_empty())
+        .collect()
+}
+
+// Export the plugin creation function
+create_plugin!(ResponseCachePlugin);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_context() -> PluginContext {
+        PluginContext {
+            plugin_config: HashMap::new(),
+            server_config: HashMap::new(),
+            server_metadata: HashMap::new(),
+            host_config: HashMap::new(),
+            host_name: "test-host".to_string(),
+            request_id: "test-request".to_string(),
+            runtime_handle: None,
+            verbose: false,
+            services: None,
+            deadline: None,
+        }
+    }
+
+    fn create_test_request(user: Option<&str>, cookie: Option<&str>) -> PluginRequest {
+        let mut builder = Request::builder().method(Method::GET).uri("/account.html");
+        if let Some(cookie) = cookie {
+            builder = builder.header(COOKIE, cookie);
+        }
+        let mut request = PluginRequest::new(builder.body(Body::empty()).unwrap(), "/account.html".to_string());
+        if let Some(user) = user {
+            request.set_metadata(METADATA_AUTHENTICATED_USER.to_string(), user.to_string());
+        }
+        request
+    }
+
+    /// Pass a request through the cache, answering it with `body` when the
+    /// cache does not, and return the body the client got
+    async fn fetch(plugin: &ResponseCachePlugin, mut request: PluginRequest, body: &str, cache_control: Option<&str>) -> String {
+        let context = create_test_context();
+        let response = match plugin.handle_request(&mut request, &context).await {
+            Some(cached) => cached.response,
+            None => {
+                let mut response = Response::new(Body::from(body.to_string()));
+                if let Some(cache_control) = cache_control {
+                    response.headers_mut().insert(CACHE_CONTROL, HeaderValue::from_str(cache_control).unwrap());
+                }
+                plugin.handle_response(&request, &mut response, &context).await;
+                response
+            }
+        };
+        String::from_utf8(hyper::body::to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_responses_to_users_are_not_shared() {
+        let plugin = ResponseCachePlugin::new(HashMap::new());
+
+        let alice = fetch(&plugin, create_test_request(Some("alice"), None), "alice's account", None).await;
+        let bob = fetch(&plugin, create_test_request(Some("bob"), None), "bob's account", None).await;
+        assert_eq!(alice, "alice's account");
+        assert_eq!(bob, "bob's account");
+
+        let alice = fetch(&plugin, create_test_request(None, Some("session=alice")), "alice's account", None).await;
+        let bob = fetch(&plugin, create_test_request(None, Some("session=bob")), "bob's account", None).await;
+        assert_eq!(alice, "alice's account");
+        assert_eq!(bob, "bob's account");
+        assert_eq!(plugin.store().len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_public_responses_to_users_are_shared() {
+        let plugin = ResponseCachePlugin::new(HashMap::new());
+
+        let alice = fetch(&plugin, create_test_request(Some("alice"), None), "everyone's page", Some("public")).await;
+        let bob = fetch(&plugin, create_test_request(Some("bob"), None), "bob's page", None).await;
+        assert_eq!(alice, "everyone's page");
+        assert_eq!(bob, "everyone's page");
+    }
+
+    #[tokio::test]
+    async fn test_anonymous_responses_are_shared() {
+        let plugin = ResponseCachePlugin::new(HashMap::new());
+
+        let first = fetch(&plugin, create_test_request(None, None), "first", None).await;
+        let second = fetch(&plugin, create_test_request(None, None), "second", None).await;
+        assert_eq!(first, "first");
+        assert_eq!(second, "first");
+    }
+}
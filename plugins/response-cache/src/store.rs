@@ -0,0 +1,144 @@
+//! Response store
+//!
+//! Responses are kept in memory under the path and query of their request.
+//! A key holds one variant of the response per combination of values of the
+//! request headers its `Vary` header names. Once more than the configured
+//! number of responses are held, the keys used least recently are dropped
+//! with all their variants.
+
+use bytes::Bytes;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue};
+use hyper::StatusCode;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+/// A response as it was sent, along with what is needed to reuse it
+#[derive(Debug, Clone)]
+pub struct CachedResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: Bytes,
+    /// Request headers the response varies on, with the values they had
+    pub vary: Vec<(HeaderName, Option<HeaderValue>)>,
+    pub stored: Instant,
+    /// How long after being stored the response may be used without asking
+    /// the plugins after the cache again
+    pub fresh_for: Duration,
+    /// Whether a request fetching a replacement is under way
+    pub revalidating: bool,
+}
+
+impl CachedResponse {
+    pub fn age(&self) -> Duration {
+        self.stored.elapsed()
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        self.age() < self.fresh_for
+    }
+
+    /// Whether the response was made for a request with these headers
+    fn matches(&self, headers: &HeaderMap) -> bool {
+        self.vary.iter().all(|(name, value)| headers.get(name) == value.as_ref())
+    }
+}
+
+/// Responses kept by the cache, evicted least recently used first
+#[derive(Debug)]
+pub struct ResponseStore {
+    capacity: usize,
+    entries: HashMap<String, Vec<CachedResponse>>,
+    /// Keys from least to most recently used
+    order: VecDeque<String>,
+    len: usize,
+}
+
+impl ResponseStore {
+    pub fn new(capacity: usize) -> Self {
+        Self { capacity, entries: HashMap::new(), order: VecDeque::new(), len: 0 }
+    }
+
+    /// The variant of a response made for a request with these headers
+    pub fn get(&mut self, key: &str, headers: &HeaderMap) -> Option<CachedResponse> {
+        let response = self.entries.get(key)?.iter().find(|response| response.matches(headers))?.clone();
+        self.touch(key);
+        Some(response)
+    }
+
+    /// Store a response, replacing the variant made for the same header values
+    pub fn insert(&mut self, key: &str, response: CachedResponse) {
+        if self.capacity == 0 {
+            return;
+        }
+        match self.entries.get_mut(key) {
+            Some(variants) => {
+                let before = variants.len();
+                variants.retain(|variant| variant.vary != response.vary);
+                self.len -= before - variants.len();
+                variants.push(response);
+                self.touch(key);
+            }
+            None => {
+                self.entries.insert(key.to_string(), vec![response]);
+                self.order.push_back(key.to_string());
+            }
+        }
+        self.len += 1;
+        while self.len > self.capacity {
+            let Some(evicted) = self.order.pop_front() else { break };
+            if let Some(variants) = self.entries.remove(&evicted) {
+                self.len -= variants.len();
+            }
+        }
+    }
+
+    /// Drop every response for a path, whatever its query, returning how many there were
+    pub fn remove_path(&mut self, path: &str) -> usize {
+        let query_prefix = format!("{}?", path);
+        let keys: Vec<String> = self.entries.keys()
+            .filter(|key| *key == path || key.starts_with(&query_prefix))
+            .cloned()
+            .collect();
+        let mut removed = 0;
+        for key in keys {
+            if let Some(variants) = self.entries.remove(&key) {
+                removed += variants.len();
+            }
+            self.order.retain(|used| *used != key);
+        }
+        self.len -= removed;
+        removed
+    }
+
+    /// Mark the variant for these headers as being revalidated, returning
+    /// false if it already was or is gone
+    pub fn begin_revalidation(&mut self, key: &str, headers: &HeaderMap) -> bool {
+        let Some(response) = self.entries.get_mut(key)
+            .and_then(|variants| variants.iter_mut().find(|response| response.matches(headers)))
+        else {
+            return false;
+        };
+        !std::mem::replace(&mut response.revalidating, true)
+    }
+
+    /// Let the variants of a key be revalidated again, for when a
+    /// revalidation ended without storing a replacement
+    pub fn end_revalidation(&mut self, key: &str) {
+        for response in self.entries.get_mut(key).into_iter().flatten() {
+            response.revalidating = false;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Mark a key as most recently used
+    fn touch(&mut self, key: &str) {
+        if let Some(position) = self.order.iter().position(|used| used == key) {
+            if let Some(used) = self.order.remove(position) {
+                self.order.push_back(used);
+            }
+        }
+    }
+}
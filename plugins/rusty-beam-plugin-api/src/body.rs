@@ -0,0 +1,338 @@
+//! Request bodies held in memory or spooled to disk
+//!
+//! `PluginRequest` reads a request body once and caches it for every plugin
+//! of the pipeline. Small bodies are kept in memory; a body larger than the
+//! server's spool threshold is written to a temporary file as it arrives, so
+//! uploads of several gigabytes never have to fit in memory. The temporary
+//! file is removed once the last handle to the body is dropped.
+
+use bytes::{Bytes, BytesMut};
+use futures_core::Stream;
+use hyper::body::HttpBody;
+use hyper::Body;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+/// Size of the chunks a spooled body is read back in
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// Prefix of the temporary files bodies are spooled to
+const SPOOL_FILE_PREFIX: &str = "rusty-beam-body";
+
+/// Counter keeping the names of concurrently spooled bodies apart
+static NEXT_SPOOL_FILE: AtomicU64 = AtomicU64::new(0);
+
+/// When and where request bodies are spooled to disk
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SpoolConfig {
+    /// Bodies larger than this many bytes are written to a temporary file
+    pub threshold: u64,
+    /// Directory the temporary files are created in
+    pub directory: PathBuf,
+}
+
+impl SpoolConfig {
+    pub fn new(threshold: u64, directory: impl Into<PathBuf>) -> Self {
+        Self { threshold, directory: directory.into() }
+    }
+}
+
+/// A request body that has been read from the connection
+///
+/// Cloning is cheap: a spooled body shares its temporary file between clones.
+#[derive(Debug, Clone)]
+pub enum CachedBody {
+    /// The body is held in memory
+    Memory(Bytes),
+    /// The body was spooled to a temporary file
+    File(Arc<SpooledBody>),
+}
+
+impl CachedBody {
+    /// Length of the body in bytes
+    pub fn len(&self) -> u64 {
+        match self {
+            CachedBody::Memory(bytes) => bytes.len() as u64,
+            CachedBody::File(spooled) => spooled.len,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether the body lives in a temporary file rather than in memory
+    pub fn is_spooled(&self) -> bool {
+        matches!(self, CachedBody::File(_))
+    }
+
+    /// Path of the temporary file holding a spooled body
+    pub fn path(&self) -> Option<&Path> {
+        match self {
+            CachedBody::Memory(_) => None,
+            CachedBody::File(spooled) => Some(&spooled.path),
+        }
+    }
+
+    /// The whole body in memory, reading a spooled body back from disk
+    pub fn to_bytes(&self) -> io::Result<Bytes> {
+        match self {
+            CachedBody::Memory(bytes) => Ok(bytes.clone()),
+            CachedBody::File(spooled) => std::fs::read(&spooled.path).map(Bytes::from),
+        }
+    }
+
+    /// The body as a stream, reading a spooled body from disk in chunks
+    pub fn stream(&self) -> Body {
+        match self {
+            CachedBody::Memory(bytes) => Body::from(bytes.clone()),
+            CachedBody::File(spooled) => match File::open(&spooled.path) {
+                Ok(file) => Body::wrap_stream(SpoolStream { file, _spooled: spooled.clone() }),
+                Err(e) => Body::wrap_stream(FailedStream(Some(e))),
+            },
+        }
+    }
+
+    /// Copy the body into `writer` without holding a spooled body in memory,
+    /// returning the number of bytes written
+    pub fn copy_to<W: Write + ?Sized>(&self, writer: &mut W) -> io::Result<u64> {
+        match self {
+            CachedBody::Memory(bytes) => {
+                writer.write_all(bytes)?;
+                Ok(bytes.len() as u64)
+            }
+            CachedBody::File(spooled) => io::copy(&mut File::open(&spooled.path)?, writer),
+        }
+    }
+
+    /// Read `body` to the end, spooling it to disk once it grows past the
+    /// threshold of `spool`; without a spool configuration it stays in memory
+    pub async fn read(mut body: Body, spool: Option<&SpoolConfig>) -> Result<CachedBody, String> {
+        let Some(spool) = spool.filter(|spool| spool.threshold > 0) else {
+            return hyper::body::to_bytes(body).await
+                .map(CachedBody::Memory)
+                .map_err(|e| format!("Failed to read request body: {}", e));
+        };
+
+        let mut buffer = BytesMut::new();
+        let mut spooled: Option<(File, SpooledBody)> = None;
+        // A body announced as too large goes straight to disk
+        if HttpBody::size_hint(&body).lower() > spool.threshold {
+            spooled = Some(SpooledBody::create(&spool.directory).map_err(spool_error)?);
+        }
+
+        while let Some(chunk) = body.data().await {
+            let chunk = chunk.map_err(|e| format!("Failed to read request body: {}", e))?;
+            match spooled.as_mut() {
+                Some((file, spooled)) => {
+                    file.write_all(&chunk).map_err(spool_error)?;
+                    spooled.len += chunk.len() as u64;
+                }
+                None => {
+                    buffer.extend_from_slice(&chunk);
+                    if buffer.len() as u64 > spool.threshold {
+                        let (mut file, mut created) = SpooledBody::create(&spool.directory).map_err(spool_error)?;
+                        file.write_all(&buffer).map_err(spool_error)?;
+                        created.len = buffer.len() as u64;
+                        buffer = BytesMut::new();
+                        spooled = Some((file, created));
+                    }
+                }
+            }
+        }
+
+        match spooled {
+            Some((mut file, spooled)) => {
+                file.flush().map_err(spool_error)?;
+                Ok(CachedBody::File(Arc::new(spooled)))
+            }
+            None => Ok(CachedBody::Memory(buffer.freeze())),
+        }
+    }
+}
+
+impl Default for CachedBody {
+    fn default() -> Self {
+        CachedBody::Memory(Bytes::new())
+    }
+}
+
+impl From<Bytes> for CachedBody {
+    fn from(bytes: Bytes) -> Self {
+        CachedBody::Memory(bytes)
+    }
+}
+
+fn spool_error(e: io::Error) -> String {
+    format!("Failed to spool request body to disk: {}", e)
+}
+
+/// A temporary file holding a request body, removed when dropped
+#[derive(Debug)]
+pub struct SpooledBody {
+    path: PathBuf,
+    len: u64,
+}
+
+impl SpooledBody {
+    /// Create an empty spool file in `directory`
+    fn create(directory: &Path) -> io::Result<(File, SpooledBody)> {
+        loop {
+            let path = directory.join(format!(
+                "{}-{}-{}",
+                SPOOL_FILE_PREFIX,
+                std::process::id(),
+                NEXT_SPOOL_FILE.fetch_add(1, Ordering::Relaxed)
+            ));
+            match OpenOptions::new().write(true).create_new(true).open(&path) {
+                Ok(file) => return Ok((file, SpooledBody { path, len: 0 })),
+                // Left behind by an earlier process with the same id
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for SpooledBody {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+/// Chunks of a spooled body, keeping the file alive while it is streamed
+struct SpoolStream {
+    file: File,
+    _spooled: Arc<SpooledBody>,
+}
+
+impl Stream for SpoolStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let mut chunk = vec![0; READ_CHUNK_SIZE];
+        match self.file.read(&mut chunk) {
+            Ok(0) => Poll::Ready(None),
+            Ok(read) => {
+                chunk.truncate(read);
+                Poll::Ready(Some(Ok(Bytes::from(chunk))))
+            }
+            Err(e) => Poll::Ready(Some(Err(e))),
+        }
+    }
+}
+
+/// A stream reporting that a spooled body could not be opened
+struct FailedStream(Option<io::Error>);
+
+impl Stream for FailedStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Poll::Ready(self.0.take().map(Err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spool_directory(name: &str) -> PathBuf {
+        let directory = std::env::temp_dir().join(format!("rusty-beam-spool-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        std::fs::create_dir_all(&directory).unwrap();
+        directory
+    }
+
+    /// A body arriving in chunks, without a length announced up front
+    fn chunked_body(chunks: &[&'static str]) -> Body {
+        let (mut sender, body) = Body::channel();
+        let chunks: Vec<_> = chunks.iter().map(|chunk| Bytes::from_static(chunk.as_bytes())).collect();
+        tokio::spawn(async move {
+            for chunk in chunks {
+                sender.send_data(chunk).await.unwrap();
+            }
+        });
+        body
+    }
+
+    #[tokio::test]
+    async fn test_small_bodies_stay_in_memory() {
+        let directory = spool_directory("small");
+        let spool = SpoolConfig::new(16, &directory);
+
+        let body = CachedBody::read(chunked_body(&["0123456789", "abcdef"]), Some(&spool)).await.unwrap();
+        assert!(!body.is_spooled());
+        assert_eq!(body.to_bytes().unwrap(), "0123456789abcdef");
+        assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 0);
+
+        // Without a spool configuration bodies are never spooled
+        let body = CachedBody::read(Body::from("x".repeat(64)), None).await.unwrap();
+        assert!(!body.is_spooled());
+        assert_eq!(body.len(), 64);
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[tokio::test]
+    async fn test_large_bodies_are_spooled_until_dropped() {
+        let directory = spool_directory("large");
+        let spool = SpoolConfig::new(16, &directory);
+
+        // Spooled once it grows past the threshold
+        let body = CachedBody::read(chunked_body(&["0123456789", "abcdefghij", "KLMNOPQRST"]), Some(&spool)).await.unwrap();
+        assert!(body.is_spooled());
+        assert_eq!(body.len(), 30);
+        let path = body.path().unwrap().to_path_buf();
+        assert!(path.starts_with(&directory));
+        assert_eq!(body.to_bytes().unwrap(), "0123456789abcdefghijKLMNOPQRST");
+        assert_eq!(hyper::body::to_bytes(body.stream()).await.unwrap(), "0123456789abcdefghijKLMNOPQRST");
+        let mut copied = Vec::new();
+        assert_eq!(body.copy_to(&mut copied).unwrap(), 30);
+        assert_eq!(copied, b"0123456789abcdefghijKLMNOPQRST");
+
+        // The file outlives clones and streams, but not the last of them
+        let clone = body.clone();
+        let stream = body.stream();
+        drop(body);
+        drop(clone);
+        assert!(path.exists());
+        drop(stream);
+        assert!(!path.exists());
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[tokio::test]
+    async fn test_bodies_announced_as_large_go_straight_to_disk() {
+        let directory = spool_directory("announced");
+        let spool = SpoolConfig::new(16, &directory);
+
+        let body = CachedBody::read(Body::from("x".repeat(17)), Some(&spool)).await.unwrap();
+        assert!(body.is_spooled());
+        assert_eq!(body.len(), 17);
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+
+    #[tokio::test]
+    async fn test_requests_read_their_body_once() {
+        let directory = spool_directory("request");
+        let request = hyper::Request::post("/upload").body(Body::from("y".repeat(32))).unwrap();
+        let mut request = crate::PluginRequest::new(request, "/upload".to_string())
+            .with_spool(SpoolConfig::new(16, &directory));
+
+        let first = request.body().await.unwrap();
+        let second = request.body().await.unwrap();
+        assert!(first.is_spooled());
+        assert_eq!(first.path(), second.path());
+        assert_eq!(request.get_body().await.unwrap().len(), 32);
+
+        let _ = std::fs::remove_dir_all(&directory);
+    }
+}
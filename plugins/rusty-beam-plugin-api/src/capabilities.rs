@@ -0,0 +1,61 @@
+//! Capabilities plugins provide to and require from each other
+//!
+//! Some plugins only work after others in the pipeline: an authorization
+//! plugin decides on the user an authentication plugin found, so it must run
+//! after one. Plugins declare this through `Plugin::capabilities`, and the
+//! server checks every pipeline when it builds it: a capability no plugin
+//! before the one requiring it provides is an error, and one only a later
+//! plugin provides is a warning, as the pipeline is probably misordered.
+//!
+//! A plugin's `provides` and `requires` configuration values, comma-separated
+//! capability names, replace what it declares itself. Capability names are
+//! free-form; the ones below are those the bundled plugins use.
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Configuration key replacing the capabilities a plugin provides
+pub const CONFIG_KEY_PROVIDES: &str = "provides";
+/// Configuration key replacing the capabilities a plugin requires
+pub const CONFIG_KEY_REQUIRES: &str = "requires";
+
+/// Sets the `authenticated_user` metadata of requests
+pub const AUTHENTICATION: &str = "authentication";
+/// Refuses requests the user may not make
+pub const AUTHORIZATION: &str = "authorization";
+
+/// What a plugin provides to the plugins after it and requires from those
+/// before it
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub provides: Vec<String>,
+    pub requires: Vec<String>,
+}
+
+impl Capabilities {
+    pub fn new(provides: &[&str], requires: &[&str]) -> Self {
+        Self {
+            provides: provides.iter().map(|c| c.to_string()).collect(),
+            requires: requires.iter().map(|c| c.to_string()).collect(),
+        }
+    }
+
+    /// These capabilities with the ones a plugin configuration declares in
+    /// their place
+    pub fn configured(mut self, config: &HashMap<String, String>) -> Self {
+        if let Some(provides) = config.get(CONFIG_KEY_PROVIDES) {
+            self.provides = parse_list(provides);
+        }
+        if let Some(requires) = config.get(CONFIG_KEY_REQUIRES) {
+            self.requires = parse_list(requires);
+        }
+        self
+    }
+}
+
+fn parse_list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(|capability| capability.trim().to_lowercase())
+        .filter(|capability| !capability.is_empty())
+        .collect()
+}
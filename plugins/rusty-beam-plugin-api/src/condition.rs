@@ -0,0 +1,417 @@
+//! Conditions deciding whether a plugin runs for a request
+//!
+//! A plugin entry in the configuration may carry a `condition` expression.
+//! The pipeline evaluates it against the request before calling the plugin's
+//! `handle_request` and again before `handle_response`, skipping the plugin in
+//! a phase where it is false. Since metadata set by earlier plugins is visible,
+//! a condition such as `authorized == "true"` lets one plugin gate another.
+//!
+//! ## Syntax
+//! - `has(key)`: the metadata key is set
+//! - `key == value`, `key != value`: compares a metadata value; a missing key
+//!   equals no value
+//! - `path(pattern)`: the request path matches the pattern, where `*` stands
+//!   for any run of characters
+//! - `method(GET, POST)`: the request method is one of those listed
+//! - `a && b`, `a || b`, `!a` and parentheses combine tests, with `&&`
+//!   binding tighter than `||`
+//!
+//! Values may be quoted with single or double quotes, or written bare when
+//! they contain no spaces, quotes, commas, parentheses or operators.
+//!
+//! ## Example
+//! ```text
+//! path("/api/*") && method(GET, HEAD) && !has(cache_bypass)
+//! ```
+
+use crate::{Capabilities, ConfigIssue, DocumentChange, HostServices, Plugin, PluginContext, PluginFailures, PluginRequest, PluginResponse, SelectorError, SelectorOperation};
+use async_trait::async_trait;
+use hyper::{Body, Response};
+use std::sync::Arc;
+
+/// A parsed condition expression
+#[derive(Debug, Clone)]
+pub struct Condition {
+    source: String,
+    expression: Expression,
+}
+
+impl Condition {
+    /// Parse a condition expression
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, position: 0 };
+        let expression = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {} in condition '{}'", token.describe(), source));
+        }
+        Ok(Self {
+            source: source.to_string(),
+            expression,
+        })
+    }
+
+    /// The expression as written in the configuration
+    pub fn as_str(&self) -> &str {
+        &self.source
+    }
+
+    /// Whether the request currently satisfies the condition
+    pub fn matches(&self, request: &PluginRequest) -> bool {
+        self.expression.evaluate(request)
+    }
+}
+
+/// Node of a condition expression
+#[derive(Debug, Clone)]
+enum Expression {
+    And(Box<Expression>, Box<Expression>),
+    Or(Box<Expression>, Box<Expression>),
+    Not(Box<Expression>),
+    Has(String),
+    Equals { key: String, value: String },
+    Path(String),
+    Method(Vec<String>),
+}
+
+impl Expression {
+    fn evaluate(&self, request: &PluginRequest) -> bool {
+        match self {
+            Expression::And(left, right) => left.evaluate(request) && right.evaluate(request),
+            Expression::Or(left, right) => left.evaluate(request) || right.evaluate(request),
+            Expression::Not(inner) => !inner.evaluate(request),
+            Expression::Has(key) => request.metadata.contains_key(key),
+            Expression::Equals { key, value } => request.get_metadata(key) == Some(value.as_str()),
+            Expression::Path(pattern) => glob_match(pattern, &request.path),
+            Expression::Method(methods) => {
+                let method = request.http_request.method().as_str();
+                methods.iter().any(|candidate| candidate.eq_ignore_ascii_case(method))
+            }
+        }
+    }
+}
+
+/// Match text against a pattern where `*` stands for any run of characters
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    // split always yields at least one part
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let middle: Vec<&str> = parts.collect();
+    let Some((last, middle)) = middle.split_last() else {
+        // No wildcard, so the pattern must match exactly
+        return rest.is_empty();
+    };
+    for part in middle {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    OpenParen,
+    CloseParen,
+    Comma,
+    Not,
+    Equal,
+    NotEqual,
+    And,
+    Or,
+}
+
+impl Token {
+    fn describe(&self) -> String {
+        match self {
+            Token::Word(word) => format!("'{}'", word),
+            Token::Quoted(value) => format!("\"{}\"", value),
+            Token::OpenParen => "'('".to_string(),
+            Token::CloseParen => "')'".to_string(),
+            Token::Comma => "','".to_string(),
+            Token::Not => "'!'".to_string(),
+            Token::Equal => "'=='".to_string(),
+            Token::NotEqual => "'!='".to_string(),
+            Token::And => "'&&'".to_string(),
+            Token::Or => "'||'".to_string(),
+        }
+    }
+}
+
+/// Characters that end a bare word
+fn is_delimiter(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | ',' | '!' | '=' | '&' | '|' | '"' | '\'')
+}
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::OpenParen,
+            ')' => Token::CloseParen,
+            ',' => Token::Comma,
+            '!' if chars.next_if_eq(&'=').is_some() => Token::NotEqual,
+            '!' => Token::Not,
+            '=' if chars.next_if_eq(&'=').is_some() => Token::Equal,
+            '&' if chars.next_if_eq(&'&').is_some() => Token::And,
+            '|' if chars.next_if_eq(&'|').is_some() => Token::Or,
+            '"' | '\'' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some(other) => value.push(other),
+                        None => return Err(format!("unterminated string in condition '{}'", source)),
+                    }
+                }
+                Token::Quoted(value)
+            }
+            '=' | '&' | '|' => {
+                return Err(format!("expected '{0}{0}' in condition '{1}'", c, source));
+            }
+            _ => {
+                let mut word = c.to_string();
+                while let Some(next) = chars.next_if(|next| !is_delimiter(*next)) {
+                    word.push(next);
+                }
+                Token::Word(word)
+            }
+        };
+        tokens.push(token);
+    }
+
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn accept(&mut self, token: &Token) -> bool {
+        if self.peek() == Some(token) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &Token) -> Result<(), String> {
+        match self.next() {
+            Some(found) if found == *token => Ok(()),
+            Some(found) => Err(format!("expected {} but found {}", token.describe(), found.describe())),
+            None => Err(format!("expected {} at end of condition", token.describe())),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expression, String> {
+        let mut expression = self.parse_and()?;
+        while self.accept(&Token::Or) {
+            expression = Expression::Or(Box::new(expression), Box::new(self.parse_and()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_and(&mut self) -> Result<Expression, String> {
+        let mut expression = self.parse_unary()?;
+        while self.accept(&Token::And) {
+            expression = Expression::And(Box::new(expression), Box::new(self.parse_unary()?));
+        }
+        Ok(expression)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expression, String> {
+        if self.accept(&Token::Not) {
+            return Ok(Expression::Not(Box::new(self.parse_unary()?)));
+        }
+        if self.accept(&Token::OpenParen) {
+            let expression = self.parse_or()?;
+            self.expect(&Token::CloseParen)?;
+            return Ok(expression);
+        }
+        self.parse_test()
+    }
+
+    fn parse_test(&mut self) -> Result<Expression, String> {
+        let name = match self.next() {
+            Some(Token::Word(word)) => word,
+            Some(token) => return Err(format!("expected a test but found {}", token.describe())),
+            None => return Err("expected a test at end of condition".to_string()),
+        };
+
+        if self.accept(&Token::OpenParen) {
+            let arguments = self.parse_arguments()?;
+            return match (name.as_str(), arguments.as_slice()) {
+                ("has", [key]) => Ok(Expression::Has(key.clone())),
+                ("path", [pattern]) => Ok(Expression::Path(pattern.clone())),
+                ("method", methods) if !methods.is_empty() => Ok(Expression::Method(arguments)),
+                ("has" | "path", _) => Err(format!("{}() takes exactly one argument", name)),
+                ("method", _) => Err("method() needs at least one method".to_string()),
+                _ => Err(format!("unknown test '{}()'", name)),
+            };
+        }
+
+        let negate = match self.peek() {
+            Some(Token::Equal) => false,
+            Some(Token::NotEqual) => true,
+            _ => return Err(format!("expected '==' or '!=' after '{}'", name)),
+        };
+        self.position += 1;
+        let value = self.parse_value()?;
+        let equals = Expression::Equals { key: name, value };
+        Ok(if negate { Expression::Not(Box::new(equals)) } else { equals })
+    }
+
+    fn parse_arguments(&mut self) -> Result<Vec<String>, String> {
+        let mut arguments = Vec::new();
+        if self.accept(&Token::CloseParen) {
+            return Ok(arguments);
+        }
+        loop {
+            arguments.push(self.parse_value()?);
+            if self.accept(&Token::CloseParen) {
+                return Ok(arguments);
+            }
+            if self.peek().is_none() {
+                return Err(format!("expected {} at end of condition", Token::CloseParen.describe()));
+            }
+            self.expect(&Token::Comma)?;
+        }
+    }
+
+    fn parse_value(&mut self) -> Result<String, String> {
+        match self.next() {
+            Some(Token::Word(value)) | Some(Token::Quoted(value)) => Ok(value),
+            Some(token) => Err(format!("expected a value but found {}", token.describe())),
+            None => Err("expected a value at end of condition".to_string()),
+        }
+    }
+}
+
+/// Runs a plugin only for requests satisfying a condition
+#[derive(Debug)]
+pub struct ConditionalPlugin {
+    condition: Condition,
+    plugin: Arc<dyn Plugin>,
+}
+
+impl ConditionalPlugin {
+    pub fn new(condition: Condition, plugin: Arc<dyn Plugin>) -> Self {
+        Self { condition, plugin }
+    }
+}
+
+#[async_trait]
+impl Plugin for ConditionalPlugin {
+    async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
+        if !self.condition.matches(request) {
+            context.log_verbose(&format!(
+                "Skipping plugin {}: condition '{}' is false",
+                self.plugin.name(),
+                self.condition.as_str()
+            ));
+            return None;
+        }
+        self.plugin.handle_request(request, context).await
+    }
+
+    async fn handle_response(&self, request: &PluginRequest, response: &mut Response<Body>, context: &PluginContext) {
+        if self.condition.matches(request) {
+            self.plugin.handle_response(request, response, context).await
+        }
+    }
+
+    async fn handle_response_stream(
+        &self,
+        request: &PluginRequest,
+        head: &mut hyper::http::response::Parts,
+        body: Body,
+        context: &PluginContext,
+    ) -> Body {
+        if !self.condition.matches(request) {
+            return body;
+        }
+        self.plugin.handle_response_stream(request, head, body, context).await
+    }
+
+    async fn on_startup(&self) {
+        self.plugin.on_startup().await
+    }
+
+    fn attach_services(&self, services: Arc<dyn HostServices>) {
+        self.plugin.attach_services(services)
+    }
+
+    // Conditions describe requests, so calls outside one always go through
+    fn check_access(&self, user: Option<&str>, method: &str, path: &str, context: &PluginContext) -> Option<bool> {
+        self.plugin.check_access(user, method, path, context)
+    }
+
+    fn publish(&self, channel: &str, message: &str) -> usize {
+        self.plugin.publish(channel, message)
+    }
+
+    fn on_change(&self, change: &DocumentChange, context: &PluginContext) {
+        self.plugin.on_change(change, context)
+    }
+
+    fn check_selector_access(
+        &self,
+        user: Option<&str>,
+        method: &str,
+        path: &str,
+        selector: &str,
+        context: &PluginContext,
+    ) -> Option<bool> {
+        self.plugin.check_selector_access(user, method, path, selector, context)
+    }
+
+    fn apply_selector(&self, operation: &SelectorOperation, context: &PluginContext) -> Option<Result<String, SelectorError>> {
+        self.plugin.apply_selector(operation, context)
+    }
+
+    fn withheld_selectors(&self, user: Option<&str>, path: &str, context: &PluginContext) -> Vec<String> {
+        self.plugin.withheld_selectors(user, path, context)
+    }
+
+    async fn on_shutdown(&self) {
+        self.plugin.on_shutdown().await
+    }
+
+    fn validate_config(&self, context: &PluginContext) -> Vec<ConfigIssue> {
+        self.plugin.validate_config(context)
+    }
+
+    fn failures(&self) -> Option<PluginFailures> {
+        self.plugin.failures()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.plugin.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.plugin.name()
+    }
+}
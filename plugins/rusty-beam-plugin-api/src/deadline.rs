@@ -0,0 +1,180 @@
+//! Request deadlines
+//!
+//! The server can give every request a time budget, `request_timeout_ms`,
+//! and directory plugins can give the requests they handle a shorter one.
+//! `PluginContext::deadline` is when the tightest budget runs out. A plugin
+//! still running its `handle_request` then is abandoned and the request is
+//! answered with a 504 naming it. Plugins with timeouts of their own, such as
+//! those calling other servers or running scripts, should keep them within
+//! `PluginContext::remaining_time` so they fail in their own way first.
+//!
+//! A future can only be abandoned at an await point, so a plugin that blocks
+//! its thread overruns the deadline until it returns.
+//!
+//! Each plugin library has its own copy of tokio, whose timers only work in
+//! a runtime of that copy, so `within` sleeps on a timer thread of this
+//! module instead and works in any runtime.
+
+use hyper::{header::CONTENT_TYPE, Body, Response, StatusCode};
+use std::fmt;
+use std::future::Future;
+use std::pin::{pin, Pin};
+use std::sync::{Arc, Condvar, Mutex, OnceLock, Weak};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Configuration key of a request's time budget in milliseconds
+pub const CONFIG_KEY_REQUEST_TIMEOUT: &str = "request_timeout_ms";
+
+/// A deadline passed before the future it bounded completed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Elapsed;
+
+impl fmt::Display for Elapsed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("request deadline exceeded")
+    }
+}
+
+impl std::error::Error for Elapsed {}
+
+/// Parse a budget in milliseconds, where 0 means none
+pub fn parse_budget(value: &str) -> Option<Duration> {
+    value.trim().parse::<u64>().ok()
+        .filter(|ms| *ms > 0)
+        .map(Duration::from_millis)
+}
+
+/// The earlier of an existing deadline and the end of a budget starting now
+pub fn tighten(deadline: Option<Instant>, budget: Option<Duration>) -> Option<Instant> {
+    let budget_end = budget.map(|budget| Instant::now() + budget);
+    match (deadline, budget_end) {
+        (Some(deadline), Some(budget_end)) => Some(deadline.min(budget_end)),
+        (deadline, budget_end) => deadline.or(budget_end),
+    }
+}
+
+/// Run a future until it completes or the deadline passes
+///
+/// The future is polled once even if the deadline has already passed, so
+/// work that finishes without waiting is never abandoned.
+pub async fn within<F: Future>(deadline: Option<Instant>, future: F) -> Result<F::Output, Elapsed> {
+    let Some(deadline) = deadline else {
+        return Ok(future.await);
+    };
+
+    let mut future = pin!(future);
+    let mut sleep = Sleep::until(deadline);
+    std::future::poll_fn(|cx| {
+        if let Poll::Ready(output) = future.as_mut().poll(cx) {
+            return Poll::Ready(Ok(output));
+        }
+        Pin::new(&mut sleep).poll(cx).map(|()| Err(Elapsed))
+    }).await
+}
+
+/// The 504 a request gets when a plugin overruns its deadline
+pub fn exceeded_response(plugin_name: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::GATEWAY_TIMEOUT)
+        .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+        .body(Body::from(format!("Plugin '{}' exceeded the request deadline", plugin_name)))
+        .unwrap_or_else(|_| Response::new(Body::empty()))
+}
+
+/// Shared state of a sleep and the timer thread that ends it
+#[derive(Default)]
+struct SleepState {
+    fired: bool,
+    waker: Option<Waker>,
+}
+
+/// Future completing at an instant, woken by the timer thread
+struct Sleep {
+    deadline: Instant,
+    state: Option<Arc<Mutex<SleepState>>>,
+}
+
+impl Sleep {
+    fn until(deadline: Instant) -> Self {
+        Self { deadline, state: None }
+    }
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if Instant::now() >= self.deadline {
+            return Poll::Ready(());
+        }
+        let deadline = self.deadline;
+        let state = self.state.get_or_insert_with(|| {
+            let state = Arc::new(Mutex::new(SleepState::default()));
+            register_timer(deadline, Arc::downgrade(&state));
+            state
+        });
+        let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+        if state.fired {
+            return Poll::Ready(());
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+/// Pending sleeps, which the timer thread wakes when they are due
+#[derive(Default)]
+struct Timers {
+    sleeps: Vec<(Instant, Weak<Mutex<SleepState>>)>,
+    running: bool,
+}
+
+static TIMERS: OnceLock<(Mutex<Timers>, Condvar)> = OnceLock::new();
+
+fn register_timer(deadline: Instant, state: Weak<Mutex<SleepState>>) {
+    let timers = TIMERS.get_or_init(|| (Mutex::new(Timers::default()), Condvar::new()));
+    let mut pending = timers.0.lock().unwrap_or_else(|e| e.into_inner());
+    // Sleeps whose future was dropped need no waking
+    pending.sleeps.retain(|(_, state)| state.strong_count() > 0);
+    pending.sleeps.push((deadline, state));
+    if !pending.running {
+        pending.running = true;
+        thread::Builder::new()
+            .name("request-deadline".to_string())
+            .spawn(move || fire_timers(timers))
+            .expect("failed to spawn the request deadline thread");
+    }
+    timers.1.notify_one();
+}
+
+/// Body of the timer thread, which parks while no sleep is pending
+fn fire_timers(timers: &(Mutex<Timers>, Condvar)) {
+    let (lock, condvar) = timers;
+    let mut pending = lock.lock().unwrap_or_else(|e| e.into_inner());
+    loop {
+        let now = Instant::now();
+        pending.sleeps.retain(|(deadline, state)| {
+            if *deadline > now {
+                return state.strong_count() > 0;
+            }
+            if let Some(state) = state.upgrade() {
+                let mut state = state.lock().unwrap_or_else(|e| e.into_inner());
+                state.fired = true;
+                if let Some(waker) = state.waker.take() {
+                    waker.wake();
+                }
+            }
+            false
+        });
+
+        let next = pending.sleeps.iter().map(|(deadline, _)| *deadline).min();
+        pending = match next {
+            Some(next) => condvar.wait_timeout(pending, next.saturating_duration_since(now))
+                .unwrap_or_else(|e| e.into_inner())
+                .0,
+            None => condvar.wait(pending).unwrap_or_else(|e| e.into_inner()),
+        };
+    }
+}
@@ -0,0 +1,99 @@
+//! Typed values shared between the plugins handling a request
+//!
+//! `PluginRequest::metadata` only holds strings, so structured values have to
+//! be written out and parsed again by every plugin reading them. Extensions
+//! hold values of any type instead, one per type, so a plugin can leave a
+//! parsed principal, a parsed document or a byte counter for the plugins
+//! after it. The string metadata stays the way to pass values that plugins
+//! agree on by name only.
+//!
+//! A value is found by the `TypeId` of its type. Plugins are built as
+//! separate libraries, so they can only share values of types they all get
+//! from one crate, such as this one, and only when built by the same
+//! compiler. A plugin's own types are private to it.
+//!
+//! Values are kept behind `Arc`, so cloning the map is cheap and a plugin can
+//! hold on to a value beyond the request.
+//!
+//! ```rust
+//! use rusty_beam_plugin_api::Extensions;
+//!
+//! struct BytesRead(u64);
+//!
+//! let mut extensions = Extensions::new();
+//! extensions.insert(BytesRead(512));
+//! assert_eq!(extensions.get::<BytesRead>().map(|read| read.0), Some(512));
+//! ```
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// A map holding at most one value of each type
+#[derive(Clone, Default)]
+pub struct Extensions {
+    values: HashMap<TypeId, Arc<dyn Any + Send + Sync>>,
+}
+
+impl Extensions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Store a value, returning the value of its type stored before
+    pub fn insert<T: Send + Sync + 'static>(&mut self, value: T) -> Option<Arc<T>> {
+        self.insert_arc(Arc::new(value))
+    }
+
+    /// Store a value that is already shared
+    pub fn insert_arc<T: Send + Sync + 'static>(&mut self, value: Arc<T>) -> Option<Arc<T>> {
+        self.values
+            .insert(TypeId::of::<T>(), value)
+            .and_then(|previous| previous.downcast().ok())
+    }
+
+    /// The value of a type
+    pub fn get<T: Send + Sync + 'static>(&self) -> Option<&T> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| value.downcast_ref())
+    }
+
+    /// The value of a type, to keep beyond the request
+    pub fn get_arc<T: Send + Sync + 'static>(&self) -> Option<Arc<T>> {
+        self.values
+            .get(&TypeId::of::<T>())
+            .and_then(|value| Arc::clone(value).downcast().ok())
+    }
+
+    /// Whether there is a value of a type
+    pub fn contains<T: Send + Sync + 'static>(&self) -> bool {
+        self.values.contains_key(&TypeId::of::<T>())
+    }
+
+    /// Take out the value of a type
+    pub fn remove<T: Send + Sync + 'static>(&mut self) -> Option<Arc<T>> {
+        self.values
+            .remove(&TypeId::of::<T>())
+            .and_then(|value| value.downcast().ok())
+    }
+
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.values.clear();
+    }
+}
+
+impl fmt::Debug for Extensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Extensions").field("len", &self.values.len()).finish()
+    }
+}
@@ -0,0 +1,75 @@
+//! File I/O off the server's async threads
+//!
+//! `std::fs` blocks the thread it runs on, and a plugin blocking one of the
+//! server's async worker threads stalls every request queued on it. The
+//! functions here run file operations on the blocking thread pool of the
+//! server's runtime, taken from `PluginContext::runtime_handle`, and wait
+//! for them without blocking.
+//!
+//! `tokio::fs` cannot be used for this from a plugin: each plugin library has
+//! its own copy of tokio, which finds no runtime of its own on the server's
+//! threads. Without a runtime handle, as in some tests, operations run on the
+//! calling thread.
+
+use crate::PluginContext;
+use std::fs::Metadata;
+use std::io;
+use std::path::Path;
+
+/// Run blocking work, such as a series of file operations, on the server's
+/// blocking thread pool
+///
+/// Work cut short because the server is shutting down fails with an error,
+/// and work that panics panics here.
+pub async fn blocking<T, F>(context: &PluginContext, work: F) -> io::Result<T>
+where
+    F: FnOnce() -> io::Result<T> + Send + 'static,
+    T: Send + 'static,
+{
+    let Some(handle) = &context.runtime_handle else {
+        return work();
+    };
+    match handle.spawn_blocking(work).await {
+        Ok(result) => result,
+        Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+        Err(e) => Err(io::Error::other(e)),
+    }
+}
+
+/// Read a whole file, as `std::fs::read`
+pub async fn read(context: &PluginContext, path: impl AsRef<Path>) -> io::Result<Vec<u8>> {
+    let path = path.as_ref().to_path_buf();
+    blocking(context, move || std::fs::read(path)).await
+}
+
+/// Read a whole file as UTF-8 text, as `std::fs::read_to_string`
+pub async fn read_to_string(context: &PluginContext, path: impl AsRef<Path>) -> io::Result<String> {
+    let path = path.as_ref().to_path_buf();
+    blocking(context, move || std::fs::read_to_string(path)).await
+}
+
+/// Replace the contents of a file, as `std::fs::write`
+pub async fn write<C>(context: &PluginContext, path: impl AsRef<Path>, contents: C) -> io::Result<()>
+where
+    C: AsRef<[u8]> + Send + 'static,
+{
+    let path = path.as_ref().to_path_buf();
+    blocking(context, move || std::fs::write(path, contents)).await
+}
+
+/// Metadata of a file, following symbolic links, as `std::fs::metadata`
+pub async fn metadata(context: &PluginContext, path: impl AsRef<Path>) -> io::Result<Metadata> {
+    let path = path.as_ref().to_path_buf();
+    blocking(context, move || std::fs::metadata(path)).await
+}
+
+/// Whether a path exists, as `Path::exists`
+pub async fn exists(context: &PluginContext, path: impl AsRef<Path>) -> bool {
+    metadata(context, path).await.is_ok()
+}
+
+/// Remove a file, as `std::fs::remove_file`
+pub async fn remove_file(context: &PluginContext, path: impl AsRef<Path>) -> io::Result<()> {
+    let path = path.as_ref().to_path_buf();
+    blocking(context, move || std::fs::remove_file(path)).await
+}
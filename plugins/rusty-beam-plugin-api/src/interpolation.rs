@@ -0,0 +1,181 @@
+//! Configuration values read from the environment and secret files
+//!
+//! Plugin configuration lives in HTML files that are often committed, so
+//! secrets are better kept out of them. A value may instead refer to them:
+//! - `${env:NAME}` is replaced by the environment variable `NAME`
+//! - `${file:/run/secrets/db}` is replaced by the contents of the file, without
+//!   its trailing line break
+//!
+//! References may be mixed with text, as in `Bearer ${env:API_TOKEN}`. Other
+//! `${...}` placeholders, such as those of access-log formats, are left as
+//! they are, and `$${env:NAME}` stands for the literal text `${env:NAME}`.
+//!
+//! The server resolves the references before the plugins receive their
+//! configuration, and masks the resolved values wherever it reports on the
+//! configuration: in log records and in the `--check-config` report.
+
+use std::fmt;
+use std::fs;
+
+/// Text that stands in for a resolved value in reports
+pub const MASK: &str = "********";
+
+/// Resolved values shorter than this are not masked, as masking them would
+/// garble reports without hiding much
+const MIN_MASKED_LENGTH: usize = 4;
+
+/// Source prefix of environment variable references
+const ENV_SOURCE: &str = "env:";
+/// Source prefix of file references
+const FILE_SOURCE: &str = "file:";
+
+/// A configuration value with its references resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interpolated {
+    pub value: String,
+    /// The values the references resolved to, which reports must mask
+    /// (see `mask`)
+    pub secrets: Vec<String>,
+}
+
+/// Why the references of a value could not be resolved
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum InterpolationError {
+    /// A reference without its closing `}`
+    Unterminated(String),
+    /// A reference to an environment variable that is not set
+    MissingVariable(String),
+    /// A reference to a file that cannot be read
+    UnreadableFile { path: String, error: String },
+}
+
+impl fmt::Display for InterpolationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InterpolationError::Unterminated(reference) => write!(f, "reference '{}' is missing its closing }}", reference),
+            InterpolationError::MissingVariable(name) => write!(f, "environment variable {} is not set", name),
+            InterpolationError::UnreadableFile { path, error } => write!(f, "secret file {} cannot be read: {}", path, error),
+        }
+    }
+}
+
+impl std::error::Error for InterpolationError {}
+
+/// Resolve the `${env:...}` and `${file:...}` references of a value
+pub fn interpolate(value: &str) -> Result<Interpolated, InterpolationError> {
+    let mut result = String::with_capacity(value.len());
+    let mut secrets = Vec::new();
+    let mut rest = value;
+
+    while let Some(start) = rest.find("${") {
+        let reference = &rest[start + 2..];
+        let is_source = reference.starts_with(ENV_SOURCE) || reference.starts_with(FILE_SOURCE);
+        // `$${` escapes a reference
+        if is_source && rest[..start].ends_with('$') {
+            result.push_str(&rest[..start - 1]);
+            result.push_str("${");
+            rest = reference;
+            continue;
+        }
+        result.push_str(&rest[..start]);
+        if !is_source {
+            result.push_str("${");
+            rest = reference;
+            continue;
+        }
+        let Some(end) = reference.find('}') else {
+            return Err(InterpolationError::Unterminated(format!("${{{}", reference)));
+        };
+        let resolved = resolve(&reference[..end])?;
+        result.push_str(&resolved);
+        if !secrets.contains(&resolved) {
+            secrets.push(resolved);
+        }
+        rest = &reference[end + 1..];
+    }
+    result.push_str(rest);
+
+    Ok(Interpolated { value: result, secrets })
+}
+
+/// The value one reference, without its `${` and `}`, stands for
+fn resolve(reference: &str) -> Result<String, InterpolationError> {
+    if let Some(name) = reference.strip_prefix(ENV_SOURCE) {
+        let name = name.trim();
+        return std::env::var(name).map_err(|_| InterpolationError::MissingVariable(name.to_string()));
+    }
+    let path = reference.strip_prefix(FILE_SOURCE).unwrap_or(reference).trim();
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents.trim_end_matches(['\r', '\n']).to_string()),
+        Err(e) => Err(InterpolationError::UnreadableFile { path: path.to_string(), error: e.to_string() }),
+    }
+}
+
+/// Replace every occurrence of the secrets in a text with `MASK`
+///
+/// Longer secrets are masked first, so one containing another is masked whole.
+/// Secrets shorter than four characters are left alone.
+pub fn mask(text: &str, secrets: &[String]) -> String {
+    let mut secrets: Vec<&String> = secrets.iter().filter(|secret| secret.len() >= MIN_MASKED_LENGTH).collect();
+    secrets.sort_by_key(|secret| std::cmp::Reverse(secret.len()));
+    secrets.into_iter().fold(text.to_string(), |text, secret| text.replace(secret.as_str(), MASK))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_env_references() {
+        std::env::set_var("RUSTY_BEAM_TEST_TOKEN", "s3cr3t-token");
+        let interpolated = interpolate("Bearer ${env:RUSTY_BEAM_TEST_TOKEN}").unwrap();
+        assert_eq!(interpolated.value, "Bearer s3cr3t-token");
+        assert_eq!(interpolated.secrets, vec!["s3cr3t-token".to_string()]);
+
+        // Repeated references record their value once
+        let interpolated = interpolate("${env:RUSTY_BEAM_TEST_TOKEN}:${env: RUSTY_BEAM_TEST_TOKEN }").unwrap();
+        assert_eq!(interpolated.value, "s3cr3t-token:s3cr3t-token");
+        assert_eq!(interpolated.secrets.len(), 1);
+    }
+
+    #[test]
+    fn test_file_references() {
+        let path = std::env::temp_dir().join(format!("rusty-beam-secret-{}", std::process::id()));
+        fs::write(&path, "from-a-file\r\n").unwrap();
+        let interpolated = interpolate(&format!("${{file:{}}}", path.display())).unwrap();
+        assert_eq!(interpolated.value, "from-a-file");
+        assert_eq!(interpolated.secrets, vec!["from-a-file".to_string()]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_missing_sources() {
+        assert_eq!(
+            interpolate("${env:RUSTY_BEAM_TEST_UNSET}"),
+            Err(InterpolationError::MissingVariable("RUSTY_BEAM_TEST_UNSET".to_string()))
+        );
+        assert!(matches!(
+            interpolate("${file:/nonexistent/rusty-beam-secret}"),
+            Err(InterpolationError::UnreadableFile { path, .. }) if path == "/nonexistent/rusty-beam-secret"
+        ));
+        assert_eq!(
+            interpolate("key=${env:RUSTY_BEAM_TEST_TOKEN"),
+            Err(InterpolationError::Unterminated("${env:RUSTY_BEAM_TEST_TOKEN".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_escapes_and_other_placeholders() {
+        let interpolated = interpolate("$${env:HOME} and ${remote_addr} cost $5").unwrap();
+        assert_eq!(interpolated.value, "${env:HOME} and ${remote_addr} cost $5");
+        assert!(interpolated.secrets.is_empty());
+        assert_eq!(interpolate("plain").unwrap().value, "plain");
+    }
+
+    #[test]
+    fn test_mask() {
+        let secrets = vec!["abc".to_string(), "token".to_string(), "token-2".to_string()];
+        assert_eq!(mask("Bearer token-2, token and abc", &secrets), format!("Bearer {}, {} and abc", MASK, MASK));
+        assert_eq!(mask("nothing secret", &[]), "nothing secret");
+    }
+}
@@ -0,0 +1,772 @@
+//! Plugins in a process of their own
+//!
+//! Plugin libraries are loaded into the server, so a plugin that crashes
+//! takes every host down with it; only its panics are caught (see `panics`). A plugin
+//! configured with `isolation` set to `process` is instead run by a helper,
+//! `rusty-beam-plugin-host`, which loads the library in a process of its own.
+//! The server keeps an `IsolatedPlugin` in its place that forwards the
+//! plugin's calls to the helper over a Unix socket.
+//!
+//! When the helper dies, the call it was handling fails: a request is
+//! answered with 502 Bad Gateway and an access check is denied. The next
+//! call starts a new helper, waiting a little longer after each failure in a
+//! row so a plugin that cannot start is not restarted in a loop. A helper
+//! still busy a second after the request's deadline is killed and restarted
+//! the same way.
+//!
+//! Each message is a JSON header followed by a body of raw bytes, each
+//! preceded by its length in big-endian bytes (4 for the header, 8 for the
+//! body). Only what can be copied crosses the socket: the request with its
+//! body and metadata, and the response, whose body is read in full. Request
+//! extensions, upgrade handlers and host services stay behind, so plugins
+//! that upgrade connections or call `HostServices` cannot be isolated, and
+//! the response phase skips upgrades and event streams. An isolated plugin
+//! handles one call at a time.
+
+use crate::log::{self, Level, Logger, PrettySink};
+use crate::{log_error, log_info, log_warn};
+use crate::services::WITHHOLD_ALL;
+use crate::{Capabilities, ConfigIssue, Plugin, PluginContext, PluginRequest, PluginResponse};
+use async_trait::async_trait;
+use hyper::header::{HeaderMap, HeaderName, HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::os::fd::OwnedFd;
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Plugin configuration key choosing how the plugin is isolated
+pub const ISOLATION_KEY: &str = "isolation";
+/// Isolation running the plugin in a process of its own
+pub const PROCESS_ISOLATION: &str = "process";
+/// Plugin configuration key overriding the path of the helper binary
+pub const PLUGIN_HOST_KEY: &str = "pluginHost";
+/// Name of the helper binary, installed next to the server's
+pub const PLUGIN_HOST_BINARY: &str = "rusty-beam-plugin-host";
+
+const FIRST_RESTART_DELAY: Duration = Duration::from_millis(100);
+const MAX_RESTART_DELAY: Duration = Duration::from_secs(10);
+/// How long a call may run past the request's deadline before the helper is killed
+const DEADLINE_GRACE: Duration = Duration::from_secs(1);
+/// How long a helper that closed the socket gets to exit before it is killed
+const EXIT_WAIT: Duration = Duration::from_millis(100);
+/// Headers longer than this mean the stream is corrupt
+const MAX_HEADER_LENGTH: usize = 16 * 1024 * 1024;
+
+/// Whether `config` asks for the plugin to run in a process of its own
+pub fn is_isolated(config: &HashMap<String, String>) -> bool {
+    config.get(ISOLATION_KEY)
+        .is_some_and(|isolation| isolation.trim().eq_ignore_ascii_case(PROCESS_ISOLATION))
+}
+
+/// The helper binary: `pluginHost` if configured, otherwise the one next to
+/// the server's executable
+pub fn host_binary(config: &HashMap<String, String>) -> PathBuf {
+    if let Some(path) = config.get(PLUGIN_HOST_KEY) {
+        return PathBuf::from(path);
+    }
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(PLUGIN_HOST_BINARY)))
+        .unwrap_or_else(|| PathBuf::from(PLUGIN_HOST_BINARY))
+}
+
+/// What the server asks of the helper
+#[derive(Serialize, Deserialize)]
+enum Call {
+    Load { library: String, config: HashMap<String, String>, log_level: String, secrets: Vec<String> },
+    Startup,
+    Shutdown,
+    Request { request: WireRequest, context: WireContext },
+    Response { request: WireRequest, response: WireHead, context: WireContext },
+    CheckAccess { user: Option<String>, method: String, path: String, selector: Option<String>, context: WireContext },
+    WithheldSelectors { user: Option<String>, path: String, context: WireContext },
+    ValidateConfig { context: WireContext },
+}
+
+/// What the helper answers
+#[derive(Serialize, Deserialize)]
+enum Reply {
+    Loaded {
+        name: String,
+        #[serde(default)]
+        capabilities: Capabilities,
+    },
+    Failed { error: String },
+    Done,
+    Request { request: WireRequest, response: Option<WireHead> },
+    Response { response: WireHead },
+    Access { allowed: Option<bool> },
+    Withheld { selectors: Vec<String> },
+    Issues { issues: Vec<ConfigIssue> },
+}
+
+/// A request without its body, which travels as the message body
+#[derive(Serialize, Deserialize)]
+struct WireRequest {
+    method: String,
+    uri: String,
+    headers: Vec<(String, Vec<u8>)>,
+    path: String,
+    canonical_path: Option<PathBuf>,
+    metadata: HashMap<String, String>,
+}
+
+impl WireRequest {
+    fn from_request(request: &PluginRequest) -> Self {
+        Self {
+            method: request.http_request.method().to_string(),
+            uri: request.http_request.uri().to_string(),
+            headers: wire_headers(request.http_request.headers()),
+            path: request.path.clone(),
+            canonical_path: request.canonical_path.clone(),
+            metadata: request.metadata.clone(),
+        }
+    }
+
+    fn into_request(self, body: Vec<u8>) -> PluginRequest {
+        let mut request = PluginRequest::new(Request::new(Body::from(body)), String::new());
+        self.apply_to(&mut request);
+        request
+    }
+
+    /// Copies what the plugin may have changed onto `request`
+    fn apply_to(self, request: &mut PluginRequest) {
+        if let Ok(method) = Method::from_bytes(self.method.as_bytes()) {
+            *request.http_request.method_mut() = method;
+        }
+        if let Ok(uri) = self.uri.parse::<Uri>() {
+            *request.http_request.uri_mut() = uri;
+        }
+        *request.http_request.headers_mut() = header_map(self.headers);
+        request.path = self.path;
+        request.canonical_path = self.canonical_path;
+        request.metadata = self.metadata;
+    }
+}
+
+/// The status and headers of a response, whose body travels as the message body
+#[derive(Serialize, Deserialize)]
+struct WireHead {
+    status: u16,
+    headers: Vec<(String, Vec<u8>)>,
+}
+
+impl WireHead {
+    fn from_response(response: &Response<Body>) -> Self {
+        Self { status: response.status().as_u16(), headers: wire_headers(response.headers()) }
+    }
+
+    fn into_response(self, body: Vec<u8>) -> Response<Body> {
+        let mut response = Response::new(Body::from(body));
+        self.apply_to(&mut response);
+        response
+    }
+
+    fn apply_to(self, response: &mut Response<Body>) {
+        *response.status_mut() = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        *response.headers_mut() = header_map(self.headers);
+    }
+}
+
+/// The parts of a `PluginContext` that can be copied to another process
+#[derive(Serialize, Deserialize)]
+struct WireContext {
+    plugin_config: HashMap<String, String>,
+    host_config: HashMap<String, String>,
+    server_config: HashMap<String, String>,
+    server_metadata: HashMap<String, String>,
+    host_name: String,
+    request_id: String,
+    verbose: bool,
+    /// Time left until the deadline, in milliseconds
+    deadline_ms: Option<u64>,
+}
+
+impl WireContext {
+    fn from_context(context: &PluginContext) -> Self {
+        Self {
+            plugin_config: context.plugin_config.clone(),
+            host_config: context.host_config.clone(),
+            server_config: context.server_config.clone(),
+            server_metadata: context.server_metadata.clone(),
+            host_name: context.host_name.clone(),
+            request_id: context.request_id.clone(),
+            verbose: context.verbose,
+            deadline_ms: context.deadline
+                .map(|deadline| deadline.saturating_duration_since(Instant::now()).as_millis() as u64),
+        }
+    }
+
+    fn into_context(self, runtime: &tokio::runtime::Handle) -> PluginContext {
+        PluginContext {
+            plugin_config: self.plugin_config,
+            host_config: self.host_config,
+            server_config: self.server_config,
+            server_metadata: self.server_metadata,
+            host_name: self.host_name,
+            request_id: self.request_id,
+            runtime_handle: Some(runtime.clone()),
+            verbose: self.verbose,
+            services: None,
+            deadline: self.deadline_ms.map(|ms| Instant::now() + Duration::from_millis(ms)),
+        }
+    }
+}
+
+fn wire_headers(headers: &HeaderMap) -> Vec<(String, Vec<u8>)> {
+    headers.iter()
+        .map(|(name, value)| (name.as_str().to_string(), value.as_bytes().to_vec()))
+        .collect()
+}
+
+fn header_map(headers: Vec<(String, Vec<u8>)>) -> HeaderMap {
+    let mut map = HeaderMap::new();
+    for (name, value) in headers {
+        if let (Ok(name), Ok(value)) = (HeaderName::from_bytes(name.as_bytes()), HeaderValue::from_bytes(&value)) {
+            map.append(name, value);
+        }
+    }
+    map
+}
+
+fn write_message<T: Serialize>(stream: &mut impl Write, message: &T, body: &[u8]) -> io::Result<()> {
+    let header = serde_json::to_vec(message)?;
+    stream.write_all(&(header.len() as u32).to_be_bytes())?;
+    stream.write_all(&header)?;
+    stream.write_all(&(body.len() as u64).to_be_bytes())?;
+    stream.write_all(body)?;
+    stream.flush()
+}
+
+fn read_message<T: DeserializeOwned>(stream: &mut impl Read) -> io::Result<(T, Vec<u8>)> {
+    let mut length = [0; 4];
+    stream.read_exact(&mut length)?;
+    let length = u32::from_be_bytes(length) as usize;
+    if length > MAX_HEADER_LENGTH {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, format!("message header of {} bytes", length)));
+    }
+    let mut header = vec![0; length];
+    stream.read_exact(&mut header)?;
+
+    let mut length = [0; 8];
+    stream.read_exact(&mut length)?;
+    let mut body = Vec::new();
+    stream.take(u64::from_be_bytes(length)).read_to_end(&mut body)?;
+    if body.len() as u64 != u64::from_be_bytes(length) {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok((serde_json::from_slice(&header)?, body))
+}
+
+/// A running helper and the server's end of its socket
+struct HostProcess {
+    child: Child,
+    stream: UnixStream,
+}
+
+impl HostProcess {
+    fn exchange(&mut self, call: &Call, body: &[u8], timeout: Option<Duration>) -> io::Result<(Reply, Vec<u8>)> {
+        self.stream.set_read_timeout(timeout)?;
+        write_message(&mut self.stream, call, body)?;
+        read_message(&mut self.stream)
+    }
+
+    /// How the helper ended, killing it if it has not
+    fn exit_status(&mut self) -> String {
+        let started = Instant::now();
+        while started.elapsed() < EXIT_WAIT {
+            match self.child.try_wait() {
+                Ok(Some(status)) => return status.to_string(),
+                Ok(None) => thread::sleep(Duration::from_millis(5)),
+                Err(e) => return e.to_string(),
+            }
+        }
+        let _ = self.child.kill();
+        match self.child.wait() {
+            Ok(status) => format!("killed, {}", status),
+            Err(e) => e.to_string(),
+        }
+    }
+}
+
+impl Drop for HostProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[derive(Default)]
+struct HostState {
+    process: Option<HostProcess>,
+    /// Whether `on_startup` was called, so a new helper gets it too
+    started: bool,
+    /// Failures since the last successful call
+    failures: u32,
+    /// No new helper is started before this
+    retry_at: Option<Instant>,
+}
+
+impl HostState {
+    fn failed(&mut self) {
+        self.failures += 1;
+        let delay = FIRST_RESTART_DELAY
+            .saturating_mul(1 << self.failures.saturating_sub(1).min(16))
+            .min(MAX_RESTART_DELAY);
+        self.retry_at = Some(Instant::now() + delay);
+    }
+}
+
+/// Starts helpers for a plugin library and passes calls to them
+struct PluginHost {
+    library: String,
+    config: HashMap<String, String>,
+    binary: PathBuf,
+    state: Mutex<HostState>,
+}
+
+impl PluginHost {
+    /// Starts a helper and has it load the plugin, returning what the plugin
+    /// calls itself and declares
+    fn start(&self) -> Result<(HostProcess, String, Capabilities), String> {
+        let (stream, theirs) = UnixStream::pair()
+            .map_err(|e| format!("Failed to create plugin host socket: {}", e))?;
+        let child = Command::new(&self.binary)
+            .stdin(Stdio::from(OwnedFd::from(theirs)))
+            .spawn()
+            .map_err(|e| format!("Failed to start {}: {}", self.binary.display(), e))?;
+        let mut process = HostProcess { child, stream };
+
+        let load = Call::Load {
+            library: self.library.clone(),
+            config: self.config.clone(),
+            log_level: log::logger().level().as_str().to_string(),
+            secrets: log::logger().secrets().to_vec(),
+        };
+        match process.exchange(&load, &[], None) {
+            Ok((Reply::Loaded { name, capabilities }, _)) => Ok((process, name, capabilities)),
+            Ok((Reply::Failed { error }, _)) => Err(error),
+            Ok(_) => Err("Plugin host answered loading with an unexpected reply".to_string()),
+            Err(e) => Err(format!("Plugin host failed to load {} ({}): {}", self.library, process.exit_status(), e)),
+        }
+    }
+
+    /// Passes `call` to the helper, starting a new one if the last one died
+    fn call(&self, call: &Call, body: &[u8], timeout: Option<Duration>) -> Result<(Reply, Vec<u8>), String> {
+        let mut state = self.state.lock().map_err(|_| "Plugin host state is poisoned".to_string())?;
+        match call {
+            Call::Startup => state.started = true,
+            Call::Shutdown => {
+                state.started = false;
+                // A helper that is gone has nothing to shut down
+                if state.process.is_none() {
+                    return Ok((Reply::Done, Vec::new()));
+                }
+            }
+            _ => {}
+        }
+
+        // A helper that died between calls is replaced before this one
+        if let Some(mut process) = state.process.take() {
+            match process.child.try_wait() {
+                Ok(None) => state.process = Some(process),
+                _ => log_error!(PLUGIN_HOST_BINARY, "Plugin host exited ({})", process.exit_status(); library = self.library),
+            }
+        }
+
+        let mut process = match state.process.take() {
+            Some(process) => process,
+            None => {
+                if state.retry_at.is_some_and(|retry_at| Instant::now() < retry_at) {
+                    return Err(format!("Plugin host for {} is restarting", self.library));
+                }
+                match self.restart(state.started) {
+                    Ok(process) => process,
+                    Err(e) => {
+                        state.failed();
+                        return Err(e);
+                    }
+                }
+            }
+        };
+
+        match process.exchange(call, body, timeout) {
+            Ok(reply) => {
+                state.failures = 0;
+                state.retry_at = None;
+                state.process = Some(process);
+                Ok(reply)
+            }
+            Err(e) => {
+                state.failed();
+                let cause = if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) {
+                    "did not answer in time".to_string()
+                } else {
+                    e.to_string()
+                };
+                Err(format!("Plugin host for {} failed ({}): {}", self.library, process.exit_status(), cause))
+            }
+        }
+    }
+
+    /// Starts a helper to replace one that died
+    fn restart(&self, started: bool) -> Result<HostProcess, String> {
+        let (mut process, name, _) = self.start()?;
+        if started {
+            match process.exchange(&Call::Startup, &[], None) {
+                Ok((Reply::Done, _)) => {}
+                Ok(_) => return Err(format!("Plugin host for {} failed to start the plugin", self.library)),
+                Err(e) => {
+                    return Err(format!("Plugin host for {} failed to start the plugin ({}): {}", self.library, process.exit_status(), e));
+                }
+            }
+        }
+        log_info!(name.as_str(), "Restarted plugin host"; library = self.library);
+        Ok(process)
+    }
+}
+
+/// A plugin running in a helper process, see the module documentation
+pub struct IsolatedPlugin {
+    name: String,
+    capabilities: Capabilities,
+    host: Arc<PluginHost>,
+}
+
+impl fmt::Debug for IsolatedPlugin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IsolatedPlugin")
+            .field("name", &self.name)
+            .field("library", &self.host.library)
+            .field("binary", &self.host.binary)
+            .finish()
+    }
+}
+
+impl IsolatedPlugin {
+    /// Starts a helper running the plugin in `library` with `config`
+    ///
+    /// Fails if the helper cannot be started or cannot load the plugin.
+    pub fn spawn(library: &str, config: HashMap<String, String>) -> Result<Self, String> {
+        let host = PluginHost {
+            library: library.to_string(),
+            binary: host_binary(&config),
+            config,
+            state: Mutex::new(HostState::default()),
+        };
+        let (process, name, capabilities) = host.start()?;
+        host.state.lock()
+            .map_err(|_| "Plugin host state is poisoned".to_string())?
+            .process = Some(process);
+        Ok(Self { name, capabilities, host: Arc::new(host) })
+    }
+
+    /// Passes `call` to the helper on a thread of its own, leaving the
+    /// runtime free while the helper works
+    async fn call(&self, call: Call, body: Vec<u8>, timeout: Option<Duration>) -> Result<(Reply, Vec<u8>), String> {
+        let host = Arc::clone(&self.host);
+        let (reply, result) = tokio::sync::oneshot::channel();
+        thread::Builder::new()
+            .name("plugin-host-call".to_string())
+            .spawn(move || {
+                let _ = reply.send(host.call(&call, &body, timeout));
+            })
+            .map_err(|e| format!("Failed to start plugin host call: {}", e))?;
+        result.await.map_err(|_| "Plugin host call ended without a reply".to_string())?
+    }
+
+    fn check(&self, user: Option<&str>, method: &str, path: &str, selector: Option<&str>, context: &PluginContext) -> Option<bool> {
+        let call = Call::CheckAccess {
+            user: user.map(str::to_string),
+            method: method.to_string(),
+            path: path.to_string(),
+            selector: selector.map(str::to_string),
+            context: WireContext::from_context(context),
+        };
+        match self.host.call(&call, &[], call_timeout(context)) {
+            Ok((Reply::Access { allowed }, _)) => allowed,
+            Ok((reply, _)) => {
+                log_error!(&self.name, context, "Access check failed: {}", failure(reply));
+                Some(false)
+            }
+            Err(e) => {
+                // Deny rather than leave an access-control plugin's decision to others
+                log_error!(&self.name, context, "Access check failed: {}", e);
+                Some(false)
+            }
+        }
+    }
+}
+
+/// How long to wait for the helper during a request
+fn call_timeout(context: &PluginContext) -> Option<Duration> {
+    context.deadline.map(|deadline| deadline.saturating_duration_since(Instant::now()) + DEADLINE_GRACE)
+}
+
+fn failure(reply: Reply) -> String {
+    match reply {
+        Reply::Failed { error } => error,
+        _ => "unexpected reply from the plugin host".to_string(),
+    }
+}
+
+fn unavailable_response() -> PluginResponse {
+    Response::builder()
+        .status(StatusCode::BAD_GATEWAY)
+        .header(CONTENT_TYPE, "text/plain")
+        .body(Body::from("Bad Gateway"))
+        .unwrap()
+        .into()
+}
+
+#[async_trait]
+impl Plugin for IsolatedPlugin {
+    async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
+        let body = match request.get_body().await {
+            Ok(body) => body,
+            Err(e) => {
+                log_error!(&self.name, context, "Failed to read request body: {}", e);
+                return Some(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .header(CONTENT_TYPE, "text/plain")
+                    .body(Body::from("Bad Request"))
+                    .unwrap()
+                    .into());
+            }
+        };
+        let call = Call::Request {
+            request: WireRequest::from_request(request),
+            context: WireContext::from_context(context),
+        };
+        match self.call(call, body.to_vec(), call_timeout(context)).await {
+            Ok((Reply::Request { request: changed, response }, body)) => {
+                changed.apply_to(request);
+                response.map(|head| head.into_response(body).into())
+            }
+            Ok((reply, _)) => {
+                log_error!(&self.name, context, "Request failed: {}", failure(reply));
+                Some(unavailable_response())
+            }
+            Err(e) => {
+                log_error!(&self.name, context, "Request failed: {}", e);
+                Some(unavailable_response())
+            }
+        }
+    }
+
+    async fn handle_response(&self, request: &PluginRequest, response: &mut Response<Body>, context: &PluginContext) {
+        // Upgrades cannot leave the server and event streams never end
+        let streaming = response.headers().get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with("text/event-stream"));
+        if response.status() == StatusCode::SWITCHING_PROTOCOLS || streaming {
+            return;
+        }
+
+        let body = std::mem::replace(response.body_mut(), Body::empty());
+        let body = match hyper::body::to_bytes(body).await {
+            Ok(body) => body,
+            Err(e) => {
+                log_error!(&self.name, context, "Failed to read response body: {}", e);
+                return;
+            }
+        };
+        let call = Call::Response {
+            request: WireRequest::from_request(request),
+            response: WireHead::from_response(response),
+            context: WireContext::from_context(context),
+        };
+        match self.call(call, body.to_vec(), call_timeout(context)).await {
+            Ok((Reply::Response { response: head }, changed)) => {
+                head.apply_to(response);
+                *response.body_mut() = Body::from(changed);
+            }
+            result => {
+                let error = match result {
+                    Ok((reply, _)) => failure(reply),
+                    Err(e) => e,
+                };
+                log_error!(&self.name, context, "Response phase failed: {}", error);
+                *response.body_mut() = Body::from(body);
+            }
+        }
+    }
+
+    async fn on_startup(&self) {
+        match self.call(Call::Startup, Vec::new(), None).await {
+            Ok((Reply::Done, _)) => {}
+            Ok((reply, _)) => log_error!(&self.name, "Startup failed: {}", failure(reply)),
+            Err(e) => log_error!(&self.name, "Startup failed: {}", e),
+        }
+    }
+
+    async fn on_shutdown(&self) {
+        match self.call(Call::Shutdown, Vec::new(), None).await {
+            Ok((Reply::Done, _)) => {}
+            Ok((reply, _)) => log_warn!(&self.name, "Shutdown failed: {}", failure(reply)),
+            Err(e) => log_warn!(&self.name, "Shutdown failed: {}", e),
+        }
+    }
+
+    fn check_access(&self, user: Option<&str>, method: &str, path: &str, context: &PluginContext) -> Option<bool> {
+        self.check(user, method, path, None, context)
+    }
+
+    fn check_selector_access(
+        &self,
+        user: Option<&str>,
+        method: &str,
+        path: &str,
+        selector: &str,
+        context: &PluginContext,
+    ) -> Option<bool> {
+        self.check(user, method, path, Some(selector), context)
+    }
+
+    fn withheld_selectors(&self, user: Option<&str>, path: &str, context: &PluginContext) -> Vec<String> {
+        let call = Call::WithheldSelectors {
+            user: user.map(str::to_string),
+            path: path.to_string(),
+            context: WireContext::from_context(context),
+        };
+        match self.host.call(&call, &[], call_timeout(context)) {
+            Ok((Reply::Withheld { selectors }, _)) => selectors,
+            Ok((reply, _)) => {
+                log_error!(&self.name, context, "Withheld selectors failed: {}", failure(reply));
+                vec![WITHHOLD_ALL.to_string()]
+            }
+            Err(e) => {
+                log_error!(&self.name, context, "Withheld selectors failed: {}", e);
+                vec![WITHHOLD_ALL.to_string()]
+            }
+        }
+    }
+
+    fn validate_config(&self, context: &PluginContext) -> Vec<ConfigIssue> {
+        let call = Call::ValidateConfig { context: WireContext::from_context(context) };
+        match self.host.call(&call, &[], None) {
+            Ok((Reply::Issues { issues }, _)) => issues,
+            Ok((reply, _)) => vec![ConfigIssue::error(failure(reply))],
+            Err(e) => vec![ConfigIssue::error(e)],
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities.clone()
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Runs the helper's side of the socket until the server closes it
+///
+/// The first message names the library; `load` loads the plugin from it.
+/// Called by `rusty-beam-plugin-host` with the socket it inherited.
+pub fn serve<L>(mut stream: UnixStream, load: L) -> io::Result<()>
+where
+    L: FnOnce(&str, HashMap<String, String>) -> Result<Box<dyn Plugin>, String>,
+{
+    let runtime = tokio::runtime::Runtime::new()?;
+    let plugin = match read_message(&mut stream)? {
+        (Call::Load { library, config, log_level, secrets }, _) => {
+            // Set before loading, so the library receives it
+            let level = log_level.parse().unwrap_or(Level::Info);
+            log::set_logger(Arc::new(Logger::new(level).with_sink(Arc::new(PrettySink)).with_secrets(secrets)));
+            match load(&library, config) {
+                Ok(plugin) => {
+                    let loaded = Reply::Loaded { name: plugin.name().to_string(), capabilities: plugin.capabilities() };
+                    write_message(&mut stream, &loaded, &[])?;
+                    plugin
+                }
+                Err(error) => return write_message(&mut stream, &Reply::Failed { error }, &[]),
+            }
+        }
+        _ => return Err(io::Error::new(io::ErrorKind::InvalidData, "expected the library to load")),
+    };
+
+    loop {
+        let (call, body) = match read_message(&mut stream) {
+            Ok(message) => message,
+            Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        let (reply, body) = runtime.block_on(dispatch(plugin.as_ref(), call, body, runtime.handle()));
+        write_message(&mut stream, &reply, &body)?;
+    }
+}
+
+/// Carries out one call on the plugin
+async fn dispatch(plugin: &dyn Plugin, call: Call, body: Vec<u8>, runtime: &tokio::runtime::Handle) -> (Reply, Vec<u8>) {
+    match call {
+        Call::Load { .. } => (Reply::Failed { error: "The plugin is already loaded".to_string() }, Vec::new()),
+        Call::Startup => {
+            plugin.on_startup().await;
+            (Reply::Done, Vec::new())
+        }
+        Call::Shutdown => {
+            plugin.on_shutdown().await;
+            (Reply::Done, Vec::new())
+        }
+        Call::Request { request, context } => {
+            let context = context.into_context(runtime);
+            let mut request = request.into_request(body);
+            let response = plugin.handle_request(&mut request, &context).await;
+            let request = WireRequest::from_request(&request);
+            match response {
+                None => (Reply::Request { request, response: None }, Vec::new()),
+                Some(PluginResponse { upgrade: Some(_), .. }) => {
+                    log_error!(plugin.name(), &context, "Upgrade handlers cannot leave the plugin host");
+                    (Reply::Failed { error: "The plugin upgraded the connection".to_string() }, Vec::new())
+                }
+                Some(PluginResponse { response, .. }) => match read_response(response).await {
+                    Ok((response, body)) => (Reply::Request { request, response: Some(response) }, body),
+                    Err(error) => (Reply::Failed { error }, Vec::new()),
+                },
+            }
+        }
+        Call::Response { request, response, context } => {
+            let context = context.into_context(runtime);
+            let request = request.into_request(Vec::new());
+            let mut response = response.into_response(body);
+            crate::run_response_phase(plugin, &request, &mut response, &context).await;
+            match read_response(response).await {
+                Ok((response, body)) => (Reply::Response { response }, body),
+                Err(error) => (Reply::Failed { error }, Vec::new()),
+            }
+        }
+        Call::CheckAccess { user, method, path, selector, context } => {
+            let context = context.into_context(runtime);
+            let allowed = match selector {
+                Some(selector) => plugin.check_selector_access(user.as_deref(), &method, &path, &selector, &context),
+                None => plugin.check_access(user.as_deref(), &method, &path, &context),
+            };
+            (Reply::Access { allowed }, Vec::new())
+        }
+        Call::WithheldSelectors { user, path, context } => {
+            let selectors = plugin.withheld_selectors(user.as_deref(), &path, &context.into_context(runtime));
+            (Reply::Withheld { selectors }, Vec::new())
+        }
+        Call::ValidateConfig { context } => {
+            let issues = plugin.validate_config(&context.into_context(runtime));
+            (Reply::Issues { issues }, Vec::new())
+        }
+    }
+}
+
+async fn read_response(response: Response<Body>) -> Result<(WireHead, Vec<u8>), String> {
+    let head = WireHead::from_response(&response);
+    let body = hyper::body::to_bytes(response.into_body()).await
+        .map_err(|e| format!("Failed to read response body: {}", e))?;
+    Ok((head, body.to_vec()))
+}
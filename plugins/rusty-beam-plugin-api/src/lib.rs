@@ -13,20 +13,47 @@
 //! - **FFI Support**: C-compatible interface for dynamic loading
 //! - **Async Support**: Full async/await compatibility with Tokio
 //! - **Upgrade Handling**: Support for protocol upgrades (WebSocket, etc.)
+//! - **Event Streams**: Helpers for Server-Sent Events
 //!
 //! ## Plugin Lifecycle
 //!
 //! 1. **Loading**: Plugins are loaded dynamically at server startup
-//! 2. **Request Phase**: Plugins process requests in pipeline order
-//! 3. **Response Phase**: All plugins can modify the response
-//! 4. **Upgrade Handling**: Optional protocol upgrade support
+//! 2. **Startup**: `on_startup` is called once the pipeline has been built
+//! 3. **Request Phase**: Plugins process requests in pipeline order
+//! 4. **Response Phase**: All plugins can modify the response
+//! 5. **Upgrade Handling**: Optional protocol upgrade support
+//! 6. **Shutdown**: `on_shutdown` is called after in-flight requests drain
 //!
 //! ## Key Components
 //!
 //! - `Plugin`: The core trait all plugins implement
 //! - `PluginRequest`: Request data passed between plugins
+//! - `Extensions`: Typed values plugins leave for each other on a request
 //! - `PluginResponse`: Response with optional upgrade handler
 //! - `PluginContext`: Configuration and runtime context
+//! - `count_body_bytes`: Helper for measuring streamed response bodies
+//! - `transform_body`, `transform_body_async`, `map_body_chunks`: Helpers for
+//!   rewriting response bodies in the response phase
+//! - `map_body_stream`, `run_response_phase`: Helpers for transforming
+//!   streamed bodies through `Plugin::handle_response_stream`
+//! - `paths::request_file_path`: The file a request addresses, resolved
+//!   safely below the host root
+//! - `Condition`, `ConditionalPlugin`: Configured conditions that decide
+//!   whether a plugin runs for a request
+//! - `IsolatedPlugin`: A plugin run by a helper process, so its crashes
+//!   leave the server running
+//! - `PanicGuard`, `PluginError`: Plugin calls whose panics are caught,
+//!   counted and answered with a 500 naming the plugin
+//! - `sse::channel`, `sse::Event`: Server-Sent Events responses with
+//!   keep-alives and disconnect detection
+//! - `timing`: When the server received a request and how long each plugin
+//!   took with it
+//! - `fs`: File operations run on the server's blocking thread pool, so they
+//!   do not stall its async threads
+//! - `ConfigIssue`: A configuration problem a plugin reports to
+//!   `rusty-beam --check-config`
+//! - `Capabilities`: What a plugin provides to and requires from the plugins
+//!   around it, checked when the server builds a pipeline
 //! - `create_plugin!`: Macro for FFI-compatible plugin creation
 //!
 //! ## Example Plugin
@@ -73,12 +100,39 @@
 
 use hyper::{Body, Request, Response};
 use std::collections::HashMap;
+use std::path::PathBuf;
 use async_trait::async_trait;
 use std::sync::Arc;
 use tokio::sync::Mutex;
 use std::pin::Pin;
 use std::future::Future;
 
+pub mod body;
+pub use body::{CachedBody, SpoolConfig, SpooledBody};
+pub mod capabilities;
+pub use capabilities::Capabilities;
+pub mod condition;
+pub use condition::{Condition, ConditionalPlugin};
+pub mod deadline;
+pub mod extensions;
+pub mod fs;
+pub use extensions::Extensions;
+pub mod interpolation;
+pub mod isolation;
+pub use isolation::IsolatedPlugin;
+pub mod log;
+pub use log::{Level, LogSink, Logger, Record};
+pub mod panics;
+pub use panics::{PanicBreaker, PanicGuard, PluginError, PluginFailures};
+pub mod paths;
+pub use paths::{PathError, SymlinkPolicy};
+pub mod services;
+pub use services::{DispatchFuture, DocumentChange, HostServices, PipelineServices, SelectorError, SelectorOperation, ServerLoad};
+pub mod sse;
+pub mod timing;
+pub mod validation;
+pub use validation::{ConfigIssue, Severity};
+
 /// Handler for upgraded connections (e.g., WebSocket, HTTP/2)
 /// 
 /// This type represents a closure that handles protocol upgrades. When a plugin
@@ -99,6 +153,8 @@ pub type UpgradeHandler = Box<dyn FnOnce(hyper::upgrade::Upgraded) -> Pin<Box<dy
 /// 
 /// Basic response without upgrade:
 /// ```rust
+/// # use hyper::{Body, Response};
+/// # use rusty_beam_plugin_api::PluginResponse;
 /// let response = Response::builder()
 ///     .status(200)
 ///     .body(Body::from("Hello"))
@@ -108,6 +164,8 @@ pub type UpgradeHandler = Box<dyn FnOnce(hyper::upgrade::Upgraded) -> Pin<Box<dy
 /// 
 /// Response with WebSocket upgrade:
 /// ```rust
+/// # use rusty_beam_plugin_api::PluginResponse;
+/// # fn example(switching_protocols_response: hyper::Response<hyper::Body>) {
 /// let plugin_response = PluginResponse {
 ///     response: switching_protocols_response,
 ///     upgrade: Some(Box::new(|upgraded| {
@@ -117,6 +175,7 @@ pub type UpgradeHandler = Box<dyn FnOnce(hyper::upgrade::Upgraded) -> Pin<Box<dy
 ///         })
 ///     })),
 /// };
+/// # }
 /// ```
 pub struct PluginResponse {
     /// The HTTP response to send
@@ -134,11 +193,275 @@ impl From<Response<Body>> for PluginResponse {
     }
 }
 
+/// Callback invoked with the number of body bytes actually streamed
+pub type BodySizeCallback = Box<dyn FnOnce(u64) + Send>;
+
+/// Wrap a response body so the bytes actually sent can be counted
+/// 
+/// Responses without a `Content-Length` (chunked or streamed bodies) have no
+/// size known during the response phase. This helper returns a body that
+/// forwards every chunk unchanged and calls `on_complete` with the total byte
+/// count once the body has been fully streamed, or when it is dropped early
+/// because the client disconnected.
+/// 
+/// # Example
+/// 
+/// ```rust
+/// # use hyper::{Body, Response};
+/// # use rusty_beam_plugin_api::count_body_bytes;
+/// # let mut response = Response::new(Body::from("Hello"));
+/// let body = std::mem::replace(response.body_mut(), Body::empty());
+/// *response.body_mut() = count_body_bytes(body, Box::new(|bytes| {
+///     println!("Sent {} bytes", bytes);
+/// }));
+/// ```
+pub fn count_body_bytes(body: Body, on_complete: BodySizeCallback) -> Body {
+    Body::wrap_stream(CountingStream {
+        inner: body,
+        bytes_sent: 0,
+        on_complete: Some(on_complete),
+    })
+}
+
+/// Stream adapter backing `count_body_bytes`
+struct CountingStream {
+    inner: Body,
+    bytes_sent: u64,
+    on_complete: Option<BodySizeCallback>,
+}
+
+impl CountingStream {
+    /// Report the byte count exactly once
+    fn complete(&mut self) {
+        if let Some(on_complete) = self.on_complete.take() {
+            on_complete(self.bytes_sent);
+        }
+    }
+}
+
+impl futures_core::Stream for CountingStream {
+    type Item = Result<bytes::Bytes, hyper::Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        let poll = Pin::new(&mut self.inner).poll_next(cx);
+        match &poll {
+            std::task::Poll::Ready(Some(Ok(chunk))) => self.bytes_sent += chunk.len() as u64,
+            std::task::Poll::Ready(None) | std::task::Poll::Ready(Some(Err(_))) => self.complete(),
+            std::task::Poll::Pending => {}
+        }
+        poll
+    }
+}
+
+impl Drop for CountingStream {
+    fn drop(&mut self) {
+        self.complete();
+    }
+}
+
+/// Buffer a response body and replace it with a transformed copy
+/// 
+/// The body is read in full and passed to `transform`. If it returns new
+/// content, that becomes the body and `Content-Length` is updated to match.
+/// If it returns `None`, the original body is restored unchanged. Any other
+/// headers the transformation affects (e.g. `Content-Type`) are left to the
+/// caller.
+/// 
+/// Returns `Ok(true)` if the body was replaced. If the body cannot be read
+/// the response is left with an empty body and the error is returned.
+/// 
+/// # Example
+/// 
+/// ```rust
+/// # use rusty_beam_plugin_api::transform_body;
+/// # async fn example(response: &mut hyper::Response<hyper::Body>) -> Result<(), hyper::Error> {
+/// let replaced = transform_body(response, |bytes| {
+///     std::str::from_utf8(bytes).ok().map(|html| html.replace("foo", "bar"))
+/// }).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub async fn transform_body<F, B>(response: &mut Response<Body>, transform: F) -> Result<bool, hyper::Error>
+where
+    F: FnOnce(&bytes::Bytes) -> Option<B>,
+    B: Into<bytes::Bytes>,
+{
+    let original = take_body_bytes(response).await?;
+    let replacement = transform(&original).map(Into::into);
+    Ok(install_transformed_body(response, original, replacement))
+}
+
+/// Async variant of `transform_body` for transformations that need to await
+/// 
+/// The transformation receives a cheap clone of the buffered body.
+pub async fn transform_body_async<F, Fut, B>(response: &mut Response<Body>, transform: F) -> Result<bool, hyper::Error>
+where
+    F: FnOnce(bytes::Bytes) -> Fut,
+    Fut: Future<Output = Option<B>>,
+    B: Into<bytes::Bytes>,
+{
+    let original = take_body_bytes(response).await?;
+    let replacement = transform(original.clone()).await.map(Into::into);
+    Ok(install_transformed_body(response, original, replacement))
+}
+
+/// Transform a response body chunk by chunk as it streams
+/// 
+/// Unlike `transform_body` the body is never buffered, which suits large or
+/// streamed responses. Because the transformed size is not known in advance,
+/// `Content-Length` is removed and the response is sent chunked.
+pub fn map_body_chunks<F>(response: &mut Response<Body>, transform: F)
+where
+    F: FnMut(bytes::Bytes) -> bytes::Bytes + Send + 'static,
+{
+    let body = std::mem::replace(response.body_mut(), Body::empty());
+    response.headers_mut().remove(hyper::header::CONTENT_LENGTH);
+    *response.body_mut() = Body::wrap_stream(MapChunksStream {
+        inner: body,
+        transform: Box::new(transform),
+    });
+}
+
+/// Transform a body chunk by chunk as it streams, finishing at its end
+/// 
+/// `transform` is called with every chunk and then once with `None` when the
+/// body has ended, and returns the bytes to send in their place; empty
+/// results send nothing. Unlike `map_body_chunks` it can hold back bytes
+/// and emit them later, as stream encoders need to. The length of the result
+/// is unknown, so callers remove `Content-Length` from the response head.
+/// 
+/// # Example
+/// 
+/// ```rust
+/// # use rusty_beam_plugin_api::map_body_stream;
+/// # fn example(head: &mut hyper::http::response::Parts, body: hyper::Body) -> hyper::Body {
+/// head.headers.remove(hyper::header::CONTENT_LENGTH);
+/// let mut total = 0;
+/// map_body_stream(body, move |chunk| match chunk {
+///     Some(chunk) => { total += chunk.len(); chunk }
+///     None => bytes::Bytes::from(format!("\n<!-- {} bytes -->", total)),
+/// })
+/// # }
+/// ```
+pub fn map_body_stream<F>(body: Body, transform: F) -> Body
+where
+    F: FnMut(Option<bytes::Bytes>) -> bytes::Bytes + Send + 'static,
+{
+    Body::wrap_stream(MapStream {
+        inner: body,
+        transform: Box::new(transform),
+        finished: false,
+    })
+}
+
+/// Run a plugin's response phase: `handle_response`, then
+/// `handle_response_stream` on the body it left
+pub async fn run_response_phase<P: Plugin + ?Sized>(
+    plugin: &P,
+    request: &PluginRequest,
+    response: &mut Response<Body>,
+    context: &PluginContext,
+) {
+    plugin.handle_response(request, response, context).await;
+    let (mut head, body) = std::mem::take(response).into_parts();
+    let body = plugin.handle_response_stream(request, &mut head, body, context).await;
+    *response = Response::from_parts(head, body);
+}
+
+/// Take the response body, leaving it empty, and read it into memory
+async fn take_body_bytes(response: &mut Response<Body>) -> Result<bytes::Bytes, hyper::Error> {
+    let body = std::mem::replace(response.body_mut(), Body::empty());
+    hyper::body::to_bytes(body).await
+}
+
+/// Install the transformed body, or restore the original if there is none
+fn install_transformed_body(
+    response: &mut Response<Body>,
+    original: bytes::Bytes,
+    replacement: Option<bytes::Bytes>,
+) -> bool {
+    match replacement {
+        Some(bytes) => {
+            response.headers_mut().insert(
+                hyper::header::CONTENT_LENGTH,
+                hyper::header::HeaderValue::from(bytes.len()),
+            );
+            *response.body_mut() = Body::from(bytes);
+            true
+        }
+        None => {
+            *response.body_mut() = Body::from(original);
+            false
+        }
+    }
+}
+
+/// Stream adapter backing `map_body_chunks`
+struct MapChunksStream {
+    inner: Body,
+    transform: Box<dyn FnMut(bytes::Bytes) -> bytes::Bytes + Send>,
+}
+
+impl futures_core::Stream for MapChunksStream {
+    type Item = Result<bytes::Bytes, hyper::Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        match Pin::new(&mut self.inner).poll_next(cx) {
+            std::task::Poll::Ready(Some(Ok(chunk))) => {
+                std::task::Poll::Ready(Some(Ok((self.transform)(chunk))))
+            }
+            other => other,
+        }
+    }
+}
+
+/// Stream adapter backing `map_body_stream`
+struct MapStream {
+    inner: Body,
+    transform: Box<dyn FnMut(Option<bytes::Bytes>) -> bytes::Bytes + Send>,
+    finished: bool,
+}
+
+impl futures_core::Stream for MapStream {
+    type Item = Result<bytes::Bytes, hyper::Error>;
+
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        while !self.finished {
+            let output = match Pin::new(&mut self.inner).poll_next(cx) {
+                std::task::Poll::Ready(Some(Ok(chunk))) => (self.transform)(Some(chunk)),
+                std::task::Poll::Ready(None) => {
+                    self.finished = true;
+                    (self.transform)(None)
+                }
+                std::task::Poll::Ready(Some(Err(e))) => {
+                    self.finished = true;
+                    return std::task::Poll::Ready(Some(Err(e)));
+                }
+                std::task::Poll::Pending => return std::task::Poll::Pending,
+            };
+            if !output.is_empty() {
+                return std::task::Poll::Ready(Some(Ok(output)));
+            }
+        }
+        std::task::Poll::Ready(None)
+    }
+}
+
 /// Data that flows between plugins during request processing
 /// 
 /// This struct contains all the information plugins need to process a request.
 /// It includes the original HTTP request, decoded path information, metadata
-/// for inter-plugin communication, and a cache for the request body.
+/// for inter-plugin communication, typed extensions for values richer than
+/// strings, and a cache for the request body.
 /// 
 /// The request is passed through the plugin pipeline, allowing each plugin to:
 /// - Read request information
@@ -155,12 +478,17 @@ pub struct PluginRequest {
     pub http_request: Box<Request<Body>>,
     /// The decoded URI path
     pub path: String,
-    /// The canonicalized file system path (if applicable)
-    pub canonical_path: Option<String>,
+    /// The file the path addresses below the host root, resolved by the
+    /// server according to the host's symlink policy (see `paths`)
+    pub canonical_path: Option<PathBuf>,
     /// Plugin-to-plugin metadata and state
     pub metadata: HashMap<String, String>,
+    /// Plugin-to-plugin values of any type, one per type (see `extensions`)
+    pub extensions: Extensions,
     /// Cached request body (once extracted)
-    pub body_cache: Arc<Mutex<Option<bytes::Bytes>>>,
+    pub body_cache: Arc<Mutex<Option<CachedBody>>>,
+    /// When to spool a large body to disk instead of holding it in memory
+    pub spool: Option<SpoolConfig>,
 }
 
 impl PluginRequest {
@@ -171,18 +499,28 @@ impl PluginRequest {
             path,
             canonical_path: None,
             metadata: HashMap::new(),
+            extensions: Extensions::new(),
             body_cache: Arc::new(Mutex::new(None)),
+            spool: None,
         }
     }
     
+    /// Spool bodies larger than the threshold of `spool` to disk
+    pub fn with_spool(mut self, spool: SpoolConfig) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+    
     /// Get metadata value set by previous plugins
     /// 
     /// # Example
     /// 
     /// ```rust
+    /// # fn example(request: &rusty_beam_plugin_api::PluginRequest) {
     /// if let Some(user) = request.get_metadata("authenticated_user") {
     ///     println!("Request from user: {}", user);
     /// }
+    /// # }
     /// ```
     pub fn get_metadata(&self, key: &str) -> Option<&str> {
         self.metadata.get(key).map(|s| s.as_str())
@@ -193,7 +531,9 @@ impl PluginRequest {
     /// # Example
     /// 
     /// ```rust
+    /// # fn example(request: &mut rusty_beam_plugin_api::PluginRequest) {
     /// request.set_metadata("content_type".to_string(), "application/json".to_string());
+    /// # }
     /// ```
     pub fn set_metadata(&mut self, key: String, value: String) {
         self.metadata.insert(key, value);
@@ -214,11 +554,64 @@ impl PluginRequest {
         self.http_request.method() == method
     }
     
+    /// The quality the request's Accept header gives a media type named in
+    /// it, or 0 if it doesn't name it
+    pub fn accept_quality(&self, media_type: &str) -> f32 {
+        let Some(accept) = self.http_request.headers().get(hyper::header::ACCEPT).and_then(|v| v.to_str().ok()) else {
+            return 0.0;
+        };
+        accept.split(',')
+            .filter_map(|range| {
+                let mut parts = range.split(';');
+                if !parts.next()?.trim().eq_ignore_ascii_case(media_type) {
+                    return None;
+                }
+                Some(parts
+                    .find_map(|param| param.trim().strip_prefix("q=").and_then(|q| q.parse::<f32>().ok()))
+                    .unwrap_or(1.0))
+            })
+            .fold(0.0, f32::max)
+    }
+    
+    /// Whether the request's Accept header ranks JSON above HTML
+    pub fn prefers_json(&self) -> bool {
+        let json = self.accept_quality("application/json");
+        json > 0.0 && json > self.accept_quality("text/html")
+    }
+    
+    /// Extract the request body, spooling a large body to disk
+    /// 
+    /// The first call consumes the body from the HTTP request and caches it;
+    /// subsequent calls return a handle to the cached body. A body larger
+    /// than the spool threshold is written to a temporary file, which can be
+    /// streamed with `CachedBody::stream` or copied with `CachedBody::copy_to`
+    /// without loading it into memory.
+    /// 
+    /// # Errors
+    /// 
+    /// Returns an error if the body cannot be read (e.g., connection issues)
+    /// or the spool file cannot be written
+    pub async fn body(&mut self) -> Result<CachedBody, String> {
+        let mut cache = self.body_cache.lock().await;
+        
+        // Return cached body if already extracted
+        if let Some(cached_body) = cache.as_ref() {
+            return Ok(cached_body.clone());
+        }
+        
+        // Extract body from the HTTP request
+        let body = std::mem::replace(self.http_request.body_mut(), Body::empty());
+        let cached_body = CachedBody::read(body, self.spool.as_ref()).await?;
+        *cache = Some(cached_body.clone());
+        Ok(cached_body)
+    }
+    
     /// Extract the request body as bytes
     /// 
     /// This method extracts the request body and caches it for subsequent access.
     /// The first call will consume the body from the HTTP request, and subsequent
-    /// calls will return the cached result.
+    /// calls will return the cached result. A spooled body is read back into
+    /// memory; use `body` to handle large bodies as a stream.
     /// 
     /// # Errors
     /// 
@@ -227,26 +620,16 @@ impl PluginRequest {
     /// # Example
     /// 
     /// ```rust
+    /// # async fn example(request: &mut rusty_beam_plugin_api::PluginRequest) -> Result<(), String> {
     /// let body_bytes = request.get_body().await?;
     /// println!("Body size: {} bytes", body_bytes.len());
+    /// # Ok(())
+    /// # }
     /// ```
     pub async fn get_body(&mut self) -> Result<bytes::Bytes, String> {
-        let mut cache = self.body_cache.lock().await;
-        
-        // Return cached body if already extracted
-        if let Some(cached_body) = cache.as_ref() {
-            return Ok(cached_body.clone());
-        }
-        
-        // Extract body from the HTTP request
-        let body = std::mem::replace(self.http_request.body_mut(), Body::empty());
-        match hyper::body::to_bytes(body).await {
-            Ok(bytes) => {
-                *cache = Some(bytes.clone());
-                Ok(bytes)
-            }
-            Err(e) => Err(format!("Failed to read request body: {}", e))
-        }
+        self.body().await?
+            .to_bytes()
+            .map_err(|e| format!("Failed to read spooled request body: {}", e))
     }
     
     /// Get the request body as a UTF-8 string
@@ -289,6 +672,10 @@ pub struct PluginContext {
     pub runtime_handle: Option<tokio::runtime::Handle>,
     /// Whether verbose logging is enabled
     pub verbose: bool,
+    /// Services of the host, such as access checks and message publishing
+    pub services: Option<Arc<dyn HostServices>>,
+    /// When the request's time budget runs out, if it has one
+    pub deadline: Option<std::time::Instant>,
 }
 
 impl std::fmt::Debug for PluginContext {
@@ -302,6 +689,8 @@ impl std::fmt::Debug for PluginContext {
             .field("request_id", &self.request_id)
             .field("runtime_handle", &self.runtime_handle.is_some())
             .field("verbose", &self.verbose)
+            .field("services", &self.services.is_some())
+            .field("deadline", &self.deadline)
             .finish()
     }
 }
@@ -317,9 +706,11 @@ impl PluginContext {
     /// # Example
     /// 
     /// ```rust
+    /// # fn example(context: &rusty_beam_plugin_api::PluginContext) {
     /// if let Some(timeout) = context.get_config("timeout") {
     ///     let timeout_ms: u64 = timeout.parse().unwrap_or(5000);
     /// }
+    /// # }
     /// ```
     pub fn get_config(&self, key: &str) -> Option<&str> {
         self.plugin_config.get(key)
@@ -333,7 +724,9 @@ impl PluginContext {
     /// # Example
     /// 
     /// ```rust
+    /// # fn example(context: &rusty_beam_plugin_api::PluginContext) {
     /// let port = context.get_config_or("port", "8080");
+    /// # }
     /// ```
     pub fn get_config_or<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
         self.get_config(key).unwrap_or(default)
@@ -344,7 +737,9 @@ impl PluginContext {
     /// # Example
     /// 
     /// ```rust
+    /// # fn example(context: &rusty_beam_plugin_api::PluginContext) {
     /// let max_size: usize = context.get_config_parsed("max_size").unwrap_or(1024);
+    /// # }
     /// ```
     pub fn get_config_parsed<T: std::str::FromStr>(&self, key: &str) -> Option<T> {
         self.get_config(key).and_then(|v| v.parse().ok())
@@ -362,35 +757,82 @@ impl PluginContext {
         self.get_config_or("document_root", "./")
     }
     
-    /// Log a message if verbose mode is enabled
+    /// Log a message at debug level, which verbose mode shows
+    /// 
+    /// A leading `[Target]` becomes the target of the record; new code should
+    /// prefer `log_debug!`, which takes the target and fields separately.
     /// 
     /// # Example
     /// 
     /// ```rust
+    /// # fn example(context: &rusty_beam_plugin_api::PluginContext) {
     /// context.log_verbose("[MyPlugin] Processing request");
+    /// # }
     /// ```
     pub fn log_verbose(&self, message: &str) {
-        if self.verbose {
-            println!("{}", message);
+        if log::enabled(Level::Debug) {
+            let (target, message) = log::split_target(message);
+            log::emit(Level::Debug, target, Some(&self.request_id), message.to_string(), Vec::new());
         }
     }
     
-    /// Log a formatted message if verbose mode is enabled
+    /// Log a formatted message at debug level, which verbose mode shows
     /// 
     /// # Example
     /// 
     /// ```rust
+    /// # fn example(context: &rusty_beam_plugin_api::PluginContext, status: u16) {
     /// context.log_verbose_fmt(format_args!("[MyPlugin] Status: {}", status));
+    /// # }
     /// ```
     pub fn log_verbose_fmt(&self, args: std::fmt::Arguments) {
-        if self.verbose {
-            println!("{}", args);
+        if log::enabled(Level::Debug) {
+            self.log_verbose(&args.to_string());
         }
     }
     
     /// Log an error message (always logged, regardless of verbose setting)
+    /// 
+    /// A leading `[Target]` becomes the target of the record, as for `log_verbose`.
     pub fn log_error(&self, message: &str) {
-        eprintln!("[{}] ERROR: {}", self.request_id, message);
+        let (target, message) = log::split_target(message);
+        log::emit(Level::Error, target, Some(&self.request_id), message.to_string(), Vec::new());
+    }
+    
+    /// Time left before the request's deadline, if it has one
+    /// 
+    /// Plugins with timeouts of their own should keep them within this, see
+    /// the `deadline` module.
+    pub fn remaining_time(&self) -> Option<std::time::Duration> {
+        self.deadline.map(|deadline| deadline.saturating_duration_since(std::time::Instant::now()))
+    }
+    
+    /// Publish a message into a channel of the host's message hub
+    /// 
+    /// Returns the number of subscribers the message reached, which is zero
+    /// when the host has no hub.
+    pub fn publish(&self, channel: &str, message: &str) -> usize {
+        self.services.as_ref()
+            .map(|services| services.publish(channel, message))
+            .unwrap_or(0)
+    }
+    
+    /// How loaded the server is, when the plugin runs in one
+    pub fn server_load(&self) -> Option<ServerLoad> {
+        self.services.as_ref().and_then(|services| services.server_load())
+    }
+    
+    /// How often the plugins of the host have panicked, when the plugin runs
+    /// in a server
+    pub fn plugin_failures(&self) -> Vec<PluginFailures> {
+        self.services.as_ref().map(|services| services.plugin_failures()).unwrap_or_default()
+    }
+    
+    /// Announce a change made to a document to the plugins of the host
+    pub fn notify_change(&self, change: &DocumentChange) {
+        if let Some(services) = &self.services {
+            services.notify_change(change, self);
+        }
     }
 }
 
@@ -408,6 +850,11 @@ impl PluginContext {
 /// 2. **Response Phase**: All plugins see the response via `handle_response`
 ///    - Plugins can modify headers, add logging, etc.
 ///    - Cannot replace the response entirely
+///    - Each plugin's `handle_response_stream` follows its `handle_response`
+///      and may wrap the body to transform it as it streams
+/// 
+/// 3. **Lifecycle Hooks**: `on_startup` and `on_shutdown` bracket the
+///    plugin's lifetime and are optional
 /// 
 /// # Thread Safety
 /// 
@@ -451,6 +898,162 @@ pub trait Plugin: Send + Sync + std::fmt::Debug {
         let _ = (request, response, context);
     }
     
+    /// Transform the response body as it streams
+    /// 
+    /// Called right after this plugin's `handle_response`, with the head of
+    /// the response and its body taken apart. The returned body is sent on,
+    /// so a plugin that wraps the body it receives, for example with
+    /// `map_body_stream` or `count_body_bytes`, works chunk by chunk as the
+    /// body is sent instead of buffering it first. Headers the
+    /// transformation invalidates, such as `Content-Length`, are changed
+    /// through `head`. The default returns the body unchanged.
+    /// 
+    /// Hosts call both hooks through `run_response_phase`.
+    async fn handle_response_stream(
+        &self,
+        request: &PluginRequest,
+        head: &mut hyper::http::response::Parts,
+        body: Body,
+        context: &PluginContext,
+    ) -> Body {
+        let _ = (request, head, context);
+        body
+    }
+    
+    /// Called once after the plugin pipeline has been built
+    /// 
+    /// Invoked at server startup and whenever a configuration reload creates
+    /// a fresh plugin instance. Use it to restore persisted state or start
+    /// background work. The default implementation does nothing.
+    async fn on_startup(&self) {
+        // Default implementation does nothing
+    }
+    
+    /// Receive the services of the plugin's host
+    /// 
+    /// Called once before `on_startup`, so plugins that act outside requests,
+    /// such as scheduled tasks, can keep the handle for later. Requests carry
+    /// the same services in their `PluginContext`. The default implementation
+    /// does nothing.
+    fn attach_services(&self, services: Arc<dyn HostServices>) {
+        let _ = services;
+    }
+    
+    /// Called once before the plugin is discarded
+    /// 
+    /// Invoked during graceful shutdown after in-flight requests have drained
+    /// (or the drain timeout has elapsed), and for the previous pipeline on a
+    /// configuration reload, before the new pipeline's `on_startup` so the
+    /// new instances can restore what it persists; the previous pipeline
+    /// serves requests until the new one replaces it. Use it to flush buffers
+    /// and persist state. The default implementation does nothing.
+    async fn on_shutdown(&self) {
+        // Default implementation does nothing
+    }
+    
+    /// Decide whether `user` may use `method` on `path` outside a request
+    /// 
+    /// Called through `HostServices::is_allowed` when another plugin needs an
+    /// access decision, for example before subscribing a client to a message
+    /// channel. `None` for `user` stands for an anonymous user. Access-control
+    /// plugins return their decision; the default returns `None`, leaving the
+    /// decision to other plugins.
+    fn check_access(&self, user: Option<&str>, method: &str, path: &str, context: &PluginContext) -> Option<bool> {
+        let _ = (user, method, path, context);
+        None
+    }
+    
+    /// Receive a message published to a channel
+    /// 
+    /// Called through `HostServices::publish`. Message hubs deliver the
+    /// message to the channel's subscribers and return how many they reached;
+    /// the default ignores it and returns 0.
+    fn publish(&self, channel: &str, message: &str) -> usize {
+        let _ = (channel, message);
+        0
+    }
+    
+    /// React to a change another plugin made to a document
+    /// 
+    /// Called through `HostServices::notify_change`, on the thread of the
+    /// request that made the change and before it is answered, so return
+    /// quickly and leave slow work to a thread of the plugin. The default
+    /// ignores the change.
+    fn on_change(&self, change: &DocumentChange, context: &PluginContext) {
+        let _ = (change, context);
+    }
+    
+    /// Decide whether `user` may use `method` on the elements `selector`
+    /// picks from `path` outside a request
+    /// 
+    /// Called through `HostServices::apply_selector` before the operation is
+    /// carried out. The default falls back to `check_access`, so plugins
+    /// without selector rules decide on the path alone.
+    fn check_selector_access(
+        &self,
+        user: Option<&str>,
+        method: &str,
+        path: &str,
+        selector: &str,
+        context: &PluginContext,
+    ) -> Option<bool> {
+        let _ = selector;
+        self.check_access(user, method, path, context)
+    }
+    
+    /// Carry out a selector operation outside a request
+    /// 
+    /// Called through `HostServices::apply_selector` once access was allowed.
+    /// Plugins that serve selector requests return the outcome; the default
+    /// returns `None`, leaving the operation to other plugins.
+    fn apply_selector(&self, operation: &SelectorOperation, context: &PluginContext) -> Option<Result<String, SelectorError>> {
+        let _ = (operation, context);
+        None
+    }
+    
+    /// Selectors of the elements of `path` that `user` may not read, though
+    /// the document itself may be read
+    /// 
+    /// Called through `HostServices::withheld_selectors` by plugins that
+    /// reveal a document's content other than by serving it, such as search
+    /// snippets. Access-control plugins that hide or redact elements return
+    /// their selectors; the default withholds nothing.
+    fn withheld_selectors(&self, user: Option<&str>, path: &str, context: &PluginContext) -> Vec<String> {
+        let _ = (user, path, context);
+        Vec::new()
+    }
+    
+    /// Report problems in the plugin's configuration
+    /// 
+    /// Called by `rusty-beam --check-config` on a freshly created plugin,
+    /// without `on_startup`, to find problems such as files the plugin will
+    /// not be able to read before the configuration is deployed. `context`
+    /// carries the host and server configuration. The default reports
+    /// nothing.
+    fn validate_config(&self, context: &PluginContext) -> Vec<ConfigIssue> {
+        let _ = context;
+        Vec::new()
+    }
+    
+    /// How often the plugin has panicked, if its calls are guarded by a
+    /// `PanicBreaker`
+    /// 
+    /// The server's wrapper around plugin libraries reports this, and
+    /// plugins running others, such as the directory plugin, merge what
+    /// those report; other plugins keep the default, None.
+    fn failures(&self) -> Option<PluginFailures> {
+        None
+    }
+    
+    /// What the plugin provides to the plugins after it and requires from
+    /// those before it
+    /// 
+    /// The server checks the order of each pipeline against these when it
+    /// builds it, see `capabilities`. The default declares nothing.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+    
     /// Plugin name for identification and logging
     /// 
     /// This should return a unique, descriptive name for the plugin.
@@ -501,12 +1104,12 @@ pub fn parse_plugin_config(config_ptr: *const c_char) -> HashMap<String, String>
         match CStr::from_ptr(config_ptr).to_str() {
             Ok(config_str) => {
                 serde_json::from_str(config_str).unwrap_or_else(|e| {
-                    eprintln!("[Plugin API] Failed to parse config JSON: {}", e);
+                    crate::log_error!("plugin-api", "Failed to parse config JSON: {}", e);
                     HashMap::new()
                 })
             }
             Err(e) => {
-                eprintln!("[Plugin API] Invalid UTF-8 in config string: {}", e);
+                crate::log_error!("plugin-api", "Invalid UTF-8 in config string: {}", e);
                 HashMap::new()
             }
         }
@@ -523,14 +1126,26 @@ pub fn parse_plugin_config(config_ptr: *const c_char) -> HashMap<String, String>
 /// Place this at the end of your plugin module:
 /// 
 /// ```rust
+/// # use rusty_beam_plugin_api::{create_plugin, Plugin};
+/// # use std::collections::HashMap;
+/// # #[derive(Debug)]
+/// # struct MyPlugin;
+/// # impl MyPlugin {
+/// #     fn new(_config: HashMap<String, String>) -> Self { MyPlugin }
+/// # }
+/// # #[async_trait::async_trait]
+/// # impl Plugin for MyPlugin {
+/// #     fn name(&self) -> &str { "my-plugin" }
+/// # }
 /// create_plugin!(MyPlugin);
 /// ```
 /// 
 /// This expands to a `create_plugin` function that:
 /// 1. Parses JSON configuration from C string
 /// 2. Creates a new plugin instance
-/// 3. Boxes it properly for FFI safety
-/// 4. Returns a raw pointer
+/// 3. Wraps it in a `PanicGuard`, so its panics fail only the call
+/// 4. Boxes it properly for FFI safety
+/// 5. Returns a raw pointer, or null if creating the plugin panicked
 /// 
 /// # Requirements
 /// 
@@ -543,10 +1158,24 @@ macro_rules! create_plugin {
         #[no_mangle]
         pub extern "C" fn create_plugin(config: *const std::os::raw::c_char) -> *mut std::ffi::c_void {
             let config_map = rusty_beam_plugin_api::parse_plugin_config(config);
-            let plugin = <$plugin_type>::new(config_map);
+            let breaker = rusty_beam_plugin_api::PanicBreaker::from_config(&config_map);
+            // A panic must not unwind out of an extern "C" function
+            let Ok(plugin) = std::panic::catch_unwind(|| <$plugin_type>::new(config_map)) else {
+                return std::ptr::null_mut();
+            };
             // Box the plugin as a trait object first, then box again for FFI safety
-            let boxed: Box<dyn rusty_beam_plugin_api::Plugin> = Box::new(plugin);
+            let boxed: Box<dyn rusty_beam_plugin_api::Plugin> =
+                Box::new(rusty_beam_plugin_api::PanicGuard::new(plugin, breaker));
             Box::into_raw(Box::new(boxed)) as *mut std::ffi::c_void
         }
+
+        /// Receives the server's logger, see `rusty_beam_plugin_api::log`
+        ///
+        /// # Safety
+        /// `logger` must come from `rusty_beam_plugin_api::log::logger_into_raw`.
+        #[no_mangle]
+        pub unsafe extern "C" fn set_plugin_logger(logger: *const std::ffi::c_void) {
+            rusty_beam_plugin_api::log::set_logger_from_raw(logger);
+        }
     };
 }
\ No newline at end of file
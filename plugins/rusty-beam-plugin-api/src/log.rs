@@ -0,0 +1,448 @@
+//! Structured logging for the server and its plugins
+//!
+//! Log records carry a level, a target naming the plugin or subsystem that
+//! wrote them, the id of the request being handled if there is one, a message
+//! and key-value fields. They are handed to the sinks of the active `Logger`:
+//! human readable lines on stdout and stderr, JSON lines, or syslog.
+//!
+//! Every plugin library holds its own copy of this crate, so the server hands
+//! its logger to each library it loads through the `set_plugin_logger`
+//! function exported by `create_plugin!`. Plugins log through the
+//! `log_error!`, `log_warn!`, `log_info!` and `log_debug!` macros:
+//!
+//! ```ignore
+//! log_info!(self.name(), "Loaded {} rules", rules.len());
+//! log_warn!(self.name(), context, "Rejected request"; path = request.path);
+//! ```
+//!
+//! Passing the `PluginContext` of a request attaches its request id; fields
+//! follow the message after a semicolon.
+
+use std::ffi::c_void;
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Name of the function plugin libraries export to receive the server's logger
+pub const SET_LOGGER_FUNCTION: &[u8] = b"set_plugin_logger";
+
+/// Syslog socket on most Unix systems
+#[cfg(unix)]
+const SYSLOG_SOCKET: &str = "/dev/log";
+
+/// Syslog facility of the records, "daemon"
+#[cfg(unix)]
+const SYSLOG_FACILITY: u8 = 3;
+
+/// The logger of this copy of the crate; `None` until the server sets one
+static LOGGER: RwLock<Option<Arc<Logger>>> = RwLock::new(None);
+
+/// Severity of a log record, from most to least severe
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Level {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Level {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Level::Error => "error",
+            Level::Warn => "warn",
+            Level::Info => "info",
+            Level::Debug => "debug",
+        }
+    }
+
+    /// Syslog severity of the level
+    #[cfg(unix)]
+    fn syslog_severity(&self) -> u8 {
+        match self {
+            Level::Error => 3,
+            Level::Warn => 4,
+            Level::Info => 6,
+            Level::Debug => 7,
+        }
+    }
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Level {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "error" => Ok(Level::Error),
+            "warn" | "warning" => Ok(Level::Warn),
+            "info" => Ok(Level::Info),
+            "debug" | "verbose" => Ok(Level::Debug),
+            other => Err(format!("Unknown log level '{}'", other)),
+        }
+    }
+}
+
+/// One log record
+#[derive(Debug, Clone)]
+pub struct Record {
+    pub timestamp: SystemTime,
+    pub level: Level,
+    /// Plugin or subsystem that wrote the record
+    pub target: String,
+    /// Request being handled when the record was written
+    pub request_id: Option<String>,
+    pub message: String,
+    pub fields: Vec<(String, String)>,
+}
+
+impl Record {
+    pub fn new(level: Level, target: impl Into<String>, message: impl Into<String>) -> Self {
+        Self {
+            timestamp: SystemTime::now(),
+            level,
+            target: target.into(),
+            request_id: None,
+            message: message.into(),
+            fields: Vec::new(),
+        }
+    }
+
+    pub fn with_request_id(mut self, request_id: impl Into<String>) -> Self {
+        self.request_id = Some(request_id.into());
+        self
+    }
+
+    pub fn with_field(mut self, key: impl Into<String>, value: impl fmt::Display) -> Self {
+        self.fields.push((key.into(), value.to_string()));
+        self
+    }
+
+    /// The record as a single JSON object
+    pub fn to_json(&self) -> serde_json::Value {
+        let fields: serde_json::Map<String, serde_json::Value> = self.fields.iter()
+            .map(|(key, value)| (key.clone(), serde_json::Value::String(value.clone())))
+            .collect();
+        serde_json::json!({
+            "timestamp": unix_time(self.timestamp),
+            "level": self.level.as_str(),
+            "target": self.target,
+            "request_id": self.request_id,
+            "message": self.message,
+            "fields": fields,
+        })
+    }
+
+    /// The record as a human readable line
+    pub fn to_pretty(&self) -> String {
+        let mut line = match self.level {
+            Level::Error => format!("[{}] ERROR: {}", self.target, self.message),
+            Level::Warn => format!("[{}] WARNING: {}", self.target, self.message),
+            Level::Info | Level::Debug => format!("[{}] {}", self.target, self.message),
+        };
+        for (key, value) in &self.fields {
+            line.push_str(&format!(" {}={}", key, quote_value(value)));
+        }
+        if let Some(request_id) = &self.request_id {
+            line.push_str(&format!(" request_id={}", request_id));
+        }
+        line
+    }
+}
+
+/// Seconds since the Unix epoch, with millisecond precision
+fn unix_time(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_millis() as f64 / 1000.0)
+        .unwrap_or(0.0)
+}
+
+/// Quote a field value for a pretty line if it would not read as one word
+fn quote_value(value: &str) -> String {
+    if value.is_empty() || value.contains(|c: char| c.is_whitespace() || c == '"' || c == '=') {
+        format!("{:?}", value)
+    } else {
+        value.to_string()
+    }
+}
+
+/// Destination of log records
+pub trait LogSink: Send + Sync {
+    fn write(&self, record: &Record);
+
+    /// Write out anything buffered
+    fn flush(&self) {}
+}
+
+/// Human readable lines, errors and warnings on stderr and the rest on stdout
+#[derive(Debug, Default)]
+pub struct PrettySink;
+
+impl LogSink for PrettySink {
+    fn write(&self, record: &Record) {
+        match record.level {
+            Level::Error | Level::Warn => eprintln!("{}", record.to_pretty()),
+            Level::Info | Level::Debug => println!("{}", record.to_pretty()),
+        }
+    }
+}
+
+/// One JSON object per line, on stderr or appended to a file
+pub struct JsonSink {
+    out: Mutex<Box<dyn Write + Send>>,
+}
+
+impl JsonSink {
+    pub fn stderr() -> Self {
+        Self { out: Mutex::new(Box::new(io::stderr())) }
+    }
+
+    /// Append records to `path`, creating the file if needed
+    pub fn file(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { out: Mutex::new(Box::new(io::LineWriter::new(file))) })
+    }
+}
+
+impl LogSink for JsonSink {
+    fn write(&self, record: &Record) {
+        if let Ok(mut out) = self.out.lock() {
+            let _ = writeln!(out, "{}", record.to_json());
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(mut out) = self.out.lock() {
+            let _ = out.flush();
+        }
+    }
+}
+
+/// Records sent to the local syslog daemon
+#[cfg(unix)]
+pub struct SyslogSink {
+    socket: std::os::unix::net::UnixDatagram,
+    tag: String,
+}
+
+#[cfg(unix)]
+impl SyslogSink {
+    /// Connect to the syslog socket, tagging records with `tag`
+    pub fn new(tag: &str) -> io::Result<Self> {
+        let socket = std::os::unix::net::UnixDatagram::unbound()?;
+        socket.connect(SYSLOG_SOCKET)?;
+        Ok(Self { socket, tag: tag.to_string() })
+    }
+}
+
+#[cfg(unix)]
+impl LogSink for SyslogSink {
+    fn write(&self, record: &Record) {
+        let priority = SYSLOG_FACILITY * 8 + record.level.syslog_severity();
+        let line = format!("<{}>{}[{}]: {}", priority, self.tag, std::process::id(), record.to_pretty());
+        let _ = self.socket.send(line.as_bytes());
+    }
+}
+
+/// A minimum level and the sinks records at or above it go to
+pub struct Logger {
+    level: Level,
+    sinks: Vec<Arc<dyn LogSink>>,
+    /// Values masked in every record, such as secrets read into the configuration
+    secrets: Vec<String>,
+}
+
+impl Logger {
+    /// A logger without sinks, which discards everything until one is added
+    pub fn new(level: Level) -> Self {
+        Self { level, sinks: Vec::new(), secrets: Vec::new() }
+    }
+
+    pub fn with_sink(mut self, sink: Arc<dyn LogSink>) -> Self {
+        self.sinks.push(sink);
+        self
+    }
+
+    /// Mask these values wherever they appear in a record's message or fields
+    pub fn with_secrets(mut self, secrets: Vec<String>) -> Self {
+        self.secrets = secrets;
+        self
+    }
+
+    pub fn level(&self) -> Level {
+        self.level
+    }
+
+    pub fn secrets(&self) -> &[String] {
+        &self.secrets
+    }
+
+    /// Whether records of `level` reach the sinks
+    pub fn enabled(&self, level: Level) -> bool {
+        level <= self.level
+    }
+
+    pub fn log(&self, record: &Record) {
+        if !self.enabled(record.level) {
+            return;
+        }
+        let masked = self.masked(record);
+        let record = masked.as_ref().unwrap_or(record);
+        for sink in &self.sinks {
+            sink.write(record);
+        }
+    }
+
+    /// A copy of the record with the secrets masked, if it contains any
+    fn masked(&self, record: &Record) -> Option<Record> {
+        let contains_secret = |text: &str| self.secrets.iter().any(|secret| text.contains(secret.as_str()));
+        if !contains_secret(&record.message) && !record.fields.iter().any(|(_, value)| contains_secret(value)) {
+            return None;
+        }
+        let mut masked = record.clone();
+        masked.message = crate::interpolation::mask(&record.message, &self.secrets);
+        for (_, value) in &mut masked.fields {
+            *value = crate::interpolation::mask(value, &self.secrets);
+        }
+        Some(masked)
+    }
+
+    pub fn flush(&self) {
+        for sink in &self.sinks {
+            sink.flush();
+        }
+    }
+}
+
+impl Default for Logger {
+    /// Pretty lines for records at info level and above
+    fn default() -> Self {
+        Logger::new(Level::Info).with_sink(Arc::new(PrettySink))
+    }
+}
+
+impl fmt::Debug for Logger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Logger")
+            .field("level", &self.level)
+            .field("sinks", &self.sinks.len())
+            .field("secrets", &self.secrets.len())
+            .finish()
+    }
+}
+
+/// Make `logger` the logger of this copy of the crate
+pub fn set_logger(logger: Arc<Logger>) {
+    if let Ok(mut current) = LOGGER.write() {
+        *current = Some(logger);
+    }
+}
+
+/// The active logger, or the default one if none was set
+pub fn logger() -> Arc<Logger> {
+    LOGGER.read().ok()
+        .and_then(|current| current.clone())
+        .unwrap_or_else(|| Arc::new(Logger::default()))
+}
+
+/// Whether records of `level` would be written
+pub fn enabled(level: Level) -> bool {
+    match LOGGER.read().ok().and_then(|current| current.as_ref().map(|logger| logger.enabled(level))) {
+        Some(enabled) => enabled,
+        None => level <= Level::Info,
+    }
+}
+
+/// Write a record through the active logger
+pub fn emit(level: Level, target: &str, request_id: Option<&str>, message: String, fields: Vec<(&str, String)>) {
+    let mut record = Record::new(level, target, message);
+    record.request_id = request_id.map(str::to_string);
+    record.fields = fields.into_iter().map(|(key, value)| (key.to_string(), value)).collect();
+    logger().log(&record);
+}
+
+/// Hand the active logger over a library boundary, see `set_logger_from_raw`
+pub fn logger_into_raw() -> *const c_void {
+    Arc::into_raw(logger()) as *const c_void
+}
+
+/// Take the logger handed over by `logger_into_raw` in another copy of the crate
+///
+/// # Safety
+/// `logger` must come from `logger_into_raw` of a copy of this crate built
+/// by the same compiler, and may only be passed here once.
+pub unsafe fn set_logger_from_raw(logger: *const c_void) {
+    if !logger.is_null() {
+        set_logger(Arc::from_raw(logger as *const Logger));
+    }
+}
+
+/// Split a legacy "[Target] message" line into its target and message
+pub(crate) fn split_target(message: &str) -> (&str, &str) {
+    message.strip_prefix('[')
+        .and_then(|rest| rest.split_once("] "))
+        .unwrap_or(("plugin", message))
+}
+
+/// Write a log record at the given level
+///
+/// The target comes first, then optionally the `PluginContext` of the request,
+/// then the message as for `format!`, then optionally `;` and `key = value`
+/// fields.
+#[macro_export]
+macro_rules! log_event {
+    ($level:expr, $target:expr, $fmt:literal $(, $arg:expr)* $(,)? $(; $($key:ident = $value:expr),+ $(,)?)?) => {
+        if $crate::log::enabled($level) {
+            $crate::log::emit(
+                $level,
+                &$target,
+                None,
+                format!($fmt $(, $arg)*),
+                vec![$($((stringify!($key), ($value).to_string())),+)?],
+            );
+        }
+    };
+    ($level:expr, $target:expr, $context:expr, $fmt:literal $(, $arg:expr)* $(,)? $(; $($key:ident = $value:expr),+ $(,)?)?) => {
+        if $crate::log::enabled($level) {
+            $crate::log::emit(
+                $level,
+                &$target,
+                Some($context.request_id.as_str()),
+                format!($fmt $(, $arg)*),
+                vec![$($((stringify!($key), ($value).to_string())),+)?],
+            );
+        }
+    };
+}
+
+/// Write a log record at error level, see `log_event!`
+#[macro_export]
+macro_rules! log_error {
+    ($($args:tt)+) => { $crate::log_event!($crate::log::Level::Error, $($args)+) };
+}
+
+/// Write a log record at warning level, see `log_event!`
+#[macro_export]
+macro_rules! log_warn {
+    ($($args:tt)+) => { $crate::log_event!($crate::log::Level::Warn, $($args)+) };
+}
+
+/// Write a log record at info level, see `log_event!`
+#[macro_export]
+macro_rules! log_info {
+    ($($args:tt)+) => { $crate::log_event!($crate::log::Level::Info, $($args)+) };
+}
+
+/// Write a log record at debug level, see `log_event!`
+#[macro_export]
+macro_rules! log_debug {
+    ($($args:tt)+) => { $crate::log_event!($crate::log::Level::Debug, $($args)+) };
+}
@@ -0,0 +1,518 @@
+//! Panics in plugin calls
+//!
+//! Plugin libraries run in the server's process, so a panic in a plugin
+//! would unwind into the server and drop the connection it was serving.
+//! `create_plugin!` instead wraps every plugin in a `PanicGuard`, which makes
+//! each call through a `PanicBreaker` that catches the panic and turns it
+//! into a `PluginError` naming the plugin. A request whose plugin panicked is
+//! answered with a 500 saying which plugin failed; the panic message only
+//! goes to the log. A panic in the response phase replaces the response the
+//! same way, and an access check that panics denies.
+//!
+//! Each plugin library has its own copy of the standard library, and a copy
+//! aborts the process on a panic raised by another, so panics are caught
+//! inside the library that raised them and never cross into the server.
+//!
+//! The breaker counts the plugin's panics. A plugin configured with
+//! `panicLimit` is disabled after that many panics in a row: until the
+//! configuration is reloaded, requests reaching it are answered with 503
+//! Service Unavailable, its access checks deny, so a broken access-control
+//! plugin never lets requests through, and its response phase is skipped.
+//! A request that passes through the plugin without a panic resets the run.
+//!
+//! Health checks read the counts through `Plugin::failures`. A plugin that
+//! runs others, such as the directory plugin, reports theirs with its own.
+//!
+//! Panics only unwind in builds with `panic = "unwind"`, which the server's
+//! release profile uses for this reason. A plugin that may crash outright,
+//! rather than panic, still needs `isolation` set to `process`.
+
+use crate::log_error;
+use crate::services::WITHHOLD_ALL;
+use crate::{Capabilities, ConfigIssue, DocumentChange, HostServices, Plugin, PluginContext, PluginRequest, PluginResponse, SelectorError, SelectorOperation};
+use async_trait::async_trait;
+use hyper::{header::CONTENT_TYPE, Body, Response, StatusCode};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::pin;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::task::Poll;
+
+/// Configuration key of the panics in a row after which a plugin is disabled
+pub const CONFIG_KEY_PANIC_LIMIT: &str = "panicLimit";
+/// Prefix of the request metadata marking that the named plugin panicked
+const METADATA_PANICKED_PREFIX: &str = "panicked:";
+
+/// A plugin call that failed instead of returning
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PluginError {
+    /// The plugin panicked during the call
+    Panicked { plugin: String, message: String },
+    /// The plugin was disabled after too many panics in a row
+    Disabled { plugin: String },
+}
+
+impl PluginError {
+    /// Name of the plugin that failed
+    pub fn plugin(&self) -> &str {
+        match self {
+            Self::Panicked { plugin, .. } | Self::Disabled { plugin } => plugin,
+        }
+    }
+
+    /// The status of the response a request gets when its plugin failed
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Panicked { .. } => StatusCode::INTERNAL_SERVER_ERROR,
+            Self::Disabled { .. } => StatusCode::SERVICE_UNAVAILABLE,
+        }
+    }
+
+    /// The response a request gets when its plugin failed, naming the plugin
+    /// but not the panic message
+    pub fn response(&self) -> Response<Body> {
+        let body = match self {
+            Self::Panicked { plugin, .. } => format!("Plugin '{}' failed", plugin),
+            Self::Disabled { plugin } => format!("Plugin '{}' is disabled", plugin),
+        };
+        Response::builder()
+            .status(self.status())
+            .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+            .body(Body::from(body))
+            .unwrap_or_else(|_| Response::new(Body::empty()))
+    }
+}
+
+impl fmt::Display for PluginError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Panicked { plugin, message } => write!(f, "plugin {} panicked: {}", plugin, message),
+            Self::Disabled { plugin } => write!(f, "plugin {} is disabled after panicking repeatedly", plugin),
+        }
+    }
+}
+
+impl std::error::Error for PluginError {}
+
+/// Run a plugin's future, catching a panic in any of its polls
+pub async fn catch<F: Future>(plugin: &str, future: F) -> Result<F::Output, PluginError> {
+    let mut future = pin!(future);
+    std::future::poll_fn(|cx| match panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(cx))) {
+        Ok(Poll::Ready(output)) => Poll::Ready(Ok(output)),
+        Ok(Poll::Pending) => Poll::Pending,
+        Err(payload) => Poll::Ready(Err(panicked(plugin, payload))),
+    }).await
+}
+
+/// Make a plugin's synchronous call, catching a panic
+pub fn catch_sync<T>(plugin: &str, call: impl FnOnce() -> T) -> Result<T, PluginError> {
+    panic::catch_unwind(AssertUnwindSafe(call)).map_err(|payload| panicked(plugin, payload))
+}
+
+fn panicked(plugin: &str, payload: Box<dyn Any + Send>) -> PluginError {
+    let message = payload.downcast_ref::<&str>().map(|message| message.to_string())
+        .or_else(|| payload.downcast_ref::<String>().cloned())
+        .unwrap_or_else(|| "unknown panic".to_string());
+    PluginError::Panicked { plugin: plugin.to_string(), message }
+}
+
+/// How often a plugin has panicked, as reported to health checks
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PluginFailures {
+    pub plugin: String,
+    /// Panics since the plugin was loaded
+    pub panics: u64,
+    /// Panics since the last request that passed through the plugin without one
+    pub consecutive_panics: u32,
+    pub disabled: bool,
+}
+
+impl PluginFailures {
+    /// These failures together with another plugin's, such as one nested in
+    /// this one: panics add up, the longer run counts, either being disabled
+    /// counts, and the name is that of whichever have panicked
+    pub fn merge(self, other: PluginFailures) -> PluginFailures {
+        let plugin = match (self.panics, other.panics) {
+            (_, 0) => self.plugin,
+            (0, _) => other.plugin,
+            _ => format!("{}, {}", self.plugin, other.plugin),
+        };
+        PluginFailures {
+            plugin,
+            panics: self.panics + other.panics,
+            consecutive_panics: self.consecutive_panics.max(other.consecutive_panics),
+            disabled: self.disabled || other.disabled,
+        }
+    }
+}
+
+/// Catches and counts a plugin's panics, disabling it after `panicLimit`
+/// panics in a row
+#[derive(Debug, Default)]
+pub struct PanicBreaker {
+    /// Panics in a row that disable the plugin, or None to never disable it
+    limit: Option<u32>,
+    panics: AtomicU64,
+    consecutive_panics: AtomicU32,
+    disabled: AtomicBool,
+}
+
+impl PanicBreaker {
+    pub fn from_config(config: &HashMap<String, String>) -> Self {
+        let limit = config.get(CONFIG_KEY_PANIC_LIMIT)
+            .and_then(|limit| limit.trim().parse::<u32>().ok())
+            .filter(|limit| *limit > 0);
+        Self { limit, ..Self::default() }
+    }
+
+    pub fn is_disabled(&self) -> bool {
+        self.disabled.load(Ordering::SeqCst)
+    }
+
+    /// Note that a request passed through the plugin without a panic, ending
+    /// the run of panics
+    pub fn succeeded(&self) {
+        self.consecutive_panics.store(0, Ordering::SeqCst);
+    }
+
+    /// Run a call's future unless the plugin is disabled, counting a panic
+    pub async fn call<F: Future>(&self, plugin: &str, future: F) -> Result<F::Output, PluginError> {
+        if self.is_disabled() {
+            return Err(PluginError::Disabled { plugin: plugin.to_string() });
+        }
+        self.record(catch(plugin, future).await)
+    }
+
+    /// Make a synchronous call unless the plugin is disabled, counting a panic
+    pub fn call_sync<T>(&self, plugin: &str, call: impl FnOnce() -> T) -> Result<T, PluginError> {
+        if self.is_disabled() {
+            return Err(PluginError::Disabled { plugin: plugin.to_string() });
+        }
+        self.record(catch_sync(plugin, call))
+    }
+
+    /// The plugin's panics so far
+    pub fn failures(&self, plugin: &str) -> PluginFailures {
+        PluginFailures {
+            plugin: plugin.to_string(),
+            panics: self.panics.load(Ordering::SeqCst),
+            consecutive_panics: self.consecutive_panics.load(Ordering::SeqCst),
+            disabled: self.is_disabled(),
+        }
+    }
+
+    fn record<T>(&self, outcome: Result<T, PluginError>) -> Result<T, PluginError> {
+        let Err(error) = &outcome else {
+            return outcome;
+        };
+        let panics = self.panics.fetch_add(1, Ordering::SeqCst) + 1;
+        let in_a_row = self.consecutive_panics.fetch_add(1, Ordering::SeqCst) + 1;
+        log_error!(error.plugin(), "{} ({} in a row, {} in all)", error, in_a_row, panics);
+        if self.limit.is_some_and(|limit| in_a_row >= limit) && !self.disabled.swap(true, Ordering::SeqCst) {
+            log_error!(error.plugin(), "Disabling plugin after {} panics in a row", in_a_row);
+        }
+        outcome
+    }
+}
+
+/// A plugin whose every call is made through a `PanicBreaker`
+#[derive(Debug)]
+pub struct PanicGuard<P> {
+    plugin: P,
+    breaker: PanicBreaker,
+}
+
+impl<P: Plugin> PanicGuard<P> {
+    pub fn new(plugin: P, breaker: PanicBreaker) -> Self {
+        Self { plugin, breaker }
+    }
+}
+
+#[async_trait]
+impl<P: Plugin> Plugin for PanicGuard<P> {
+    async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
+        let outcome = self.breaker.call(self.plugin.name(), self.plugin.handle_request(request, context)).await;
+        outcome.unwrap_or_else(|error| {
+            // The response phase of this request must not end the run
+            request.set_metadata(format!("{}{}", METADATA_PANICKED_PREFIX, self.plugin.name()), "true".to_string());
+            Some(error.response().into())
+        })
+    }
+
+    async fn handle_response(&self, request: &PluginRequest, response: &mut Response<Body>, context: &PluginContext) {
+        if self.breaker.is_disabled() {
+            return;
+        }
+        let outcome = self.breaker.call(self.plugin.name(), self.plugin.handle_response(request, response, context)).await;
+        match outcome {
+            Ok(()) if request.get_metadata(&format!("{}{}", METADATA_PANICKED_PREFIX, self.plugin.name())).is_none() => {
+                self.breaker.succeeded();
+            }
+            Ok(()) => {}
+            // A plugin that panicked may have left the response half rewritten
+            Err(error) => *response = error.response(),
+        }
+    }
+
+    async fn handle_response_stream(
+        &self,
+        request: &PluginRequest,
+        head: &mut hyper::http::response::Parts,
+        body: Body,
+        context: &PluginContext,
+    ) -> Body {
+        if self.breaker.is_disabled() {
+            return body;
+        }
+        let outcome = self.breaker.call(self.plugin.name(), self.plugin.handle_response_stream(request, head, body, context)).await;
+        outcome.unwrap_or_else(|error| {
+            let (parts, body) = error.response().into_parts();
+            *head = parts;
+            body
+        })
+    }
+
+    async fn on_startup(&self) {
+        let _ = self.breaker.call(self.plugin.name(), self.plugin.on_startup()).await;
+    }
+
+    fn attach_services(&self, services: Arc<dyn HostServices>) {
+        let _ = self.breaker.call_sync(self.plugin.name(), || self.plugin.attach_services(services));
+    }
+
+    // Access checks of a plugin that failed deny, so access control never
+    // fails open
+    fn check_access(&self, user: Option<&str>, method: &str, path: &str, context: &PluginContext) -> Option<bool> {
+        self.breaker.call_sync(self.plugin.name(), || self.plugin.check_access(user, method, path, context))
+            .unwrap_or(Some(false))
+    }
+
+    fn publish(&self, channel: &str, message: &str) -> usize {
+        self.breaker.call_sync(self.plugin.name(), || self.plugin.publish(channel, message))
+            .unwrap_or(0)
+    }
+
+    fn on_change(&self, change: &DocumentChange, context: &PluginContext) {
+        let _ = self.breaker.call_sync(self.plugin.name(), || self.plugin.on_change(change, context));
+    }
+
+    fn check_selector_access(
+        &self,
+        user: Option<&str>,
+        method: &str,
+        path: &str,
+        selector: &str,
+        context: &PluginContext,
+    ) -> Option<bool> {
+        self.breaker.call_sync(self.plugin.name(), || self.plugin.check_selector_access(user, method, path, selector, context))
+            .unwrap_or(Some(false))
+    }
+
+    fn withheld_selectors(&self, user: Option<&str>, path: &str, context: &PluginContext) -> Vec<String> {
+        self.breaker.call_sync(self.plugin.name(), || self.plugin.withheld_selectors(user, path, context))
+            .unwrap_or_else(|_| vec![WITHHOLD_ALL.to_string()])
+    }
+
+    fn apply_selector(&self, operation: &SelectorOperation, context: &PluginContext) -> Option<Result<String, SelectorError>> {
+        self.breaker.call_sync(self.plugin.name(), || self.plugin.apply_selector(operation, context))
+            .unwrap_or_else(|error| Some(Err(SelectorError::new(
+                error.status().as_u16(),
+                format!("Plugin '{}' failed", error.plugin()),
+            ))))
+    }
+
+    async fn on_shutdown(&self) {
+        let _ = self.breaker.call(self.plugin.name(), self.plugin.on_shutdown()).await;
+    }
+
+    fn validate_config(&self, context: &PluginContext) -> Vec<ConfigIssue> {
+        self.breaker.call_sync(self.plugin.name(), || self.plugin.validate_config(context))
+            .unwrap_or_else(|error| vec![ConfigIssue::error(error.to_string())])
+    }
+
+    fn failures(&self) -> Option<PluginFailures> {
+        let failures = self.breaker.failures(self.plugin.name());
+        Some(match self.plugin.failures() {
+            Some(nested) => failures.merge(nested),
+            None => failures,
+        })
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.plugin.capabilities()
+    }
+
+    fn name(&self) -> &str {
+        self.plugin.name()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::Request;
+
+    /// Panics on requests for `/panic` and in the response phase of `/panic-late`
+    #[derive(Debug)]
+    struct FlakyPlugin;
+
+    #[async_trait]
+    impl Plugin for FlakyPlugin {
+        async fn handle_request(&self, request: &mut PluginRequest, _context: &PluginContext) -> Option<PluginResponse> {
+            if request.path == "/panic" {
+                panic!("request phase failed");
+            }
+            None
+        }
+
+        async fn handle_response(&self, request: &PluginRequest, _response: &mut Response<Body>, _context: &PluginContext) {
+            if request.path == "/panic-late" {
+                panic!("response phase failed");
+            }
+        }
+
+        fn check_access(&self, _user: Option<&str>, _method: &str, _path: &str, _context: &PluginContext) -> Option<bool> {
+            panic!("access check failed");
+        }
+
+        fn name(&self) -> &str {
+            "flaky"
+        }
+    }
+
+    fn guard(panic_limit: Option<&str>) -> PanicGuard<FlakyPlugin> {
+        let config = panic_limit
+            .map(|limit| HashMap::from([(CONFIG_KEY_PANIC_LIMIT.to_string(), limit.to_string())]))
+            .unwrap_or_default();
+        PanicGuard::new(FlakyPlugin, PanicBreaker::from_config(&config))
+    }
+
+    fn create_context() -> PluginContext {
+        PluginContext {
+            plugin_config: HashMap::new(),
+            server_config: HashMap::new(),
+            server_metadata: HashMap::new(),
+            host_config: HashMap::new(),
+            host_name: "localhost".to_string(),
+            request_id: "test-request".to_string(),
+            runtime_handle: None,
+            verbose: false,
+            services: None,
+            deadline: None,
+        }
+    }
+
+    fn create_request(path: &str) -> PluginRequest {
+        PluginRequest::new(Request::get(path).body(Body::empty()).unwrap(), path.to_string())
+    }
+
+    /// Runs a request through both phases of the guarded plugin
+    async fn exchange(guard: &PanicGuard<FlakyPlugin>, path: &str) -> Response<Body> {
+        let context = create_context();
+        let mut request = create_request(path);
+        let mut response = match guard.handle_request(&mut request, &context).await {
+            Some(plugin_response) => plugin_response.response,
+            None => Response::new(Body::from("ok")),
+        };
+        guard.handle_response(&request, &mut response, &context).await;
+        response
+    }
+
+    #[tokio::test]
+    async fn test_panics_become_responses_naming_the_plugin() {
+        let guard = guard(None);
+
+        let response = exchange(&guard, "/panic").await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "Plugin 'flaky' failed");
+
+        // A half-rewritten response is replaced the same way
+        let response = exchange(&guard, "/panic-late").await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+
+        assert_eq!(exchange(&guard, "/").await.status(), StatusCode::OK);
+        let failures = guard.failures().unwrap();
+        assert_eq!(failures.panics, 2);
+        assert_eq!(failures.consecutive_panics, 0);
+        assert!(!failures.disabled);
+    }
+
+    #[tokio::test]
+    async fn test_plugins_are_disabled_after_panic_limit_panics_in_a_row() {
+        let guard = guard(Some("3"));
+
+        exchange(&guard, "/panic").await;
+        exchange(&guard, "/panic").await;
+        // A request passing through without a panic ends the run
+        exchange(&guard, "/").await;
+        exchange(&guard, "/panic").await;
+        exchange(&guard, "/panic").await;
+        assert!(!guard.failures().unwrap().disabled);
+
+        exchange(&guard, "/panic").await;
+        let failures = guard.failures().unwrap();
+        assert_eq!(failures.panics, 5);
+        assert!(failures.disabled);
+
+        // Every request is now refused, panicking or not
+        let response = exchange(&guard, "/").await;
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(body, "Plugin 'flaky' is disabled");
+    }
+
+    #[test]
+    fn test_access_checks_that_panic_deny() {
+        let guard = guard(None);
+        assert_eq!(guard.check_access(Some("alice"), "GET", "/", &create_context()), Some(false));
+        assert_eq!(guard.failures().unwrap().panics, 1);
+    }
+
+    /// Runs a guarded `FlakyPlugin`, as a directory plugin runs its nested
+    /// plugins
+    #[derive(Debug)]
+    struct NestingPlugin(PanicGuard<FlakyPlugin>);
+
+    #[async_trait]
+    impl Plugin for NestingPlugin {
+        async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
+            self.0.handle_request(request, context).await
+        }
+
+        fn failures(&self) -> Option<PluginFailures> {
+            self.0.failures()
+        }
+
+        fn name(&self) -> &str {
+            "nesting"
+        }
+    }
+
+    #[tokio::test]
+    async fn test_panics_of_nested_plugins_are_reported() {
+        let outer = PanicGuard::new(NestingPlugin(guard(Some("1"))), PanicBreaker::default());
+        let healthy = PluginFailures { plugin: "nesting".to_string(), panics: 0, consecutive_panics: 0, disabled: false };
+        assert_eq!(outer.failures(), Some(healthy.clone()));
+
+        let response = outer.handle_request(&mut create_request("/panic"), &create_context()).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let flaky = PluginFailures { plugin: "flaky".to_string(), panics: 1, consecutive_panics: 1, disabled: true };
+        assert_eq!(outer.failures(), Some(flaky.clone()));
+
+        // Plugins that both panicked are named together
+        let failures = PluginFailures { panics: 2, consecutive_panics: 0, ..healthy }.merge(flaky);
+        assert_eq!(failures, PluginFailures { plugin: "nesting, flaky".to_string(), panics: 3, consecutive_panics: 1, disabled: true });
+    }
+
+    #[test]
+    fn test_panic_messages_are_kept() {
+        let error = catch_sync("flaky", || panic!("formatted {}", 42)).unwrap_err();
+        assert_eq!(error, PluginError::Panicked { plugin: "flaky".to_string(), message: "formatted 42".to_string() });
+        assert_eq!(catch_sync("flaky", || 7), Ok(7));
+    }
+}
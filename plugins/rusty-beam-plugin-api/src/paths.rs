@@ -0,0 +1,346 @@
+//! Mapping request paths onto the files of a host
+//!
+//! Before anything else, the server normalizes the path of every request
+//! with `normalize_request_path`, so plugins comparing paths as strings,
+//! such as the authorization and directory plugins, see one spelling of each
+//! path: percent-encoding is decoded once, control characters are refused,
+//! dot segments and repeated slashes are collapsed and the text is brought
+//! into Unicode normalization form C. `%2E%2E`, `//admin` and a decomposed
+//! `é` then cannot name a file under a path a rule does not match.
+//!
+//! The server resolves the path of every request against the root of its
+//! host once, before the pipeline runs, and stores the result in
+//! `PluginRequest::canonical_path`. Requests whose path would leave the root
+//! are answered with 403 Forbidden and never reach a plugin. Plugins that
+//! serve files call `request_file_path`, which returns that path, or resolves
+//! the request itself when it was built outside the pipeline.
+//!
+//! How symbolic links are treated is set per host with the `symlinkPolicy`
+//! property:
+//!
+//! - `deny-escape` (default): links are followed, and a path whose target
+//!   lies outside the root is refused
+//! - `allow-within-root`: a link inside the root is served wherever it
+//!   points, so a site can link in a directory shared with another host;
+//!   `..` segments still cannot leave the root
+
+use crate::{PluginContext, PluginRequest};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Host configuration key of the symbolic link policy
+pub const CONFIG_KEY_SYMLINK_POLICY: &str = "symlinkPolicy";
+
+/// Host configuration key of the document root
+pub const CONFIG_KEY_HOST_ROOT: &str = "hostRoot";
+
+/// File a path ending in `/` resolves to
+pub const INDEX_FILE_NAME: &str = "index.html";
+
+/// How symbolic links below a host root are treated
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SymlinkPolicy {
+    /// Follow links, refusing those whose target is outside the root
+    #[default]
+    DenyEscape,
+    /// Follow any link that is itself inside the root
+    AllowWithinRoot,
+}
+
+impl SymlinkPolicy {
+    /// The policy configured for the host of a request
+    ///
+    /// An unknown value falls back to the default, which is the stricter
+    /// policy; the server reports such values when it loads its configuration.
+    pub fn from_context(context: &PluginContext) -> Self {
+        context.host_config.get(CONFIG_KEY_SYMLINK_POLICY)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default()
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SymlinkPolicy::DenyEscape => "deny-escape",
+            SymlinkPolicy::AllowWithinRoot => "allow-within-root",
+        }
+    }
+}
+
+impl FromStr for SymlinkPolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "deny-escape" => Ok(SymlinkPolicy::DenyEscape),
+            "allow-within-root" => Ok(SymlinkPolicy::AllowWithinRoot),
+            other => Err(format!("Unknown symlink policy '{}'", other)),
+        }
+    }
+}
+
+/// Why a request path has no file below the root
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PathError {
+    /// The path, or a link on it, leads outside the root
+    Escapes,
+    /// The root itself does not exist or cannot be read
+    RootUnavailable,
+}
+
+impl fmt::Display for PathError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PathError::Escapes => write!(f, "path leads outside the document root"),
+            PathError::RootUnavailable => write!(f, "document root is not available"),
+        }
+    }
+}
+
+impl std::error::Error for PathError {}
+
+/// Why a request path was refused by `normalize_request_path`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvalidPath {
+    /// The path is not a valid percent-encoding of UTF-8 text
+    Encoding,
+    /// The decoded path contains a control character, such as NUL
+    ControlCharacter,
+    /// The path does not start with `/`
+    NotAbsolute,
+}
+
+impl fmt::Display for InvalidPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InvalidPath::Encoding => write!(f, "path is not valid percent-encoded UTF-8"),
+            InvalidPath::ControlCharacter => write!(f, "path contains a control character"),
+            InvalidPath::NotAbsolute => write!(f, "path does not start with '/'"),
+        }
+    }
+}
+
+impl std::error::Error for InvalidPath {}
+
+/// Bring the raw path of a request URI into the form plugins see
+///
+/// Percent-encoding is decoded exactly once, so `%252E` stays `%2E`. `.`
+/// segments and empty segments are dropped and `..` removes the segment
+/// before it, never going above `/`, as RFC 3986 removes dot segments. A
+/// trailing `/`, which names a directory's index file, is kept, also when
+/// the last segment was a dot segment. The result is in Unicode
+/// normalization form C.
+pub fn normalize_request_path(raw_path: &str) -> Result<String, InvalidPath> {
+    if !raw_path.starts_with('/') {
+        return Err(InvalidPath::NotAbsolute);
+    }
+    let decoded = percent_decode(raw_path).ok_or(InvalidPath::Encoding)?;
+    if decoded.chars().any(char::is_control) {
+        return Err(InvalidPath::ControlCharacter);
+    }
+
+    let mut segments: Vec<&str> = Vec::new();
+    let mut trailing_slash = false;
+    for segment in decoded.split('/') {
+        trailing_slash = matches!(segment, "" | "." | "..");
+        match segment {
+            "" | "." => {}
+            ".." => {
+                segments.pop();
+            }
+            name => segments.push(name),
+        }
+    }
+
+    let mut path = String::with_capacity(decoded.len());
+    for segment in &segments {
+        path.push('/');
+        path.push_str(segment);
+    }
+    if trailing_slash || segments.is_empty() {
+        path.push('/');
+    }
+    Ok(icu_normalizer::ComposingNormalizerBorrowed::new_nfc().normalize(&path).into_owned())
+}
+
+/// Decode `%XX` escapes, failing on malformed escapes and invalid UTF-8
+fn percent_decode(text: &str) -> Option<String> {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        if bytes[index] == b'%' {
+            let hex = text.get(index + 1..index + 3)
+                .filter(|hex| hex.bytes().all(|b| b.is_ascii_hexdigit()))?;
+            decoded.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            decoded.push(bytes[index]);
+            index += 1;
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+/// Resolve a decoded request path against a root directory
+///
+/// A path ending in `/` names the directory's index file. The file need not
+/// exist, so paths of files about to be created resolve too.
+pub fn resolve(root: &Path, request_path: &str, policy: SymlinkPolicy) -> Result<PathBuf, PathError> {
+    let root = root.canonicalize().map_err(|_| PathError::RootUnavailable)?;
+    resolve_within(&root, request_path, policy)
+}
+
+/// Resolve a decoded request path against a root that is already canonical
+pub fn resolve_within(root: &Path, request_path: &str, policy: SymlinkPolicy) -> Result<PathBuf, PathError> {
+    let mut relative = PathBuf::new();
+    for segment in request_path.split('/') {
+        match segment {
+            "" | "." => {}
+            ".." => {
+                if !relative.pop() {
+                    return Err(PathError::Escapes);
+                }
+            }
+            name => relative.push(name),
+        }
+    }
+    if request_path.ends_with('/') {
+        relative.push(INDEX_FILE_NAME);
+    }
+
+    let path = root.join(relative);
+    match policy {
+        SymlinkPolicy::AllowWithinRoot => Ok(path),
+        SymlinkPolicy::DenyEscape => match canonicalize_existing(&path) {
+            Some(resolved) if resolved.starts_with(root) => Ok(resolved),
+            _ => Err(PathError::Escapes),
+        },
+    }
+}
+
+/// The file a request addresses
+///
+/// Returns the path the server resolved for the request if there is one,
+/// otherwise resolves the request path against the host root, or against
+/// `default_root` for plugins running without a host.
+pub fn request_file_path(
+    request: &PluginRequest,
+    context: &PluginContext,
+    default_root: &str,
+) -> Result<PathBuf, PathError> {
+    if let Some(path) = &request.canonical_path {
+        return Ok(path.clone());
+    }
+    let root = context.host_config.get(CONFIG_KEY_HOST_ROOT)
+        .map(String::as_str)
+        .unwrap_or(default_root);
+    resolve(Path::new(root), &request.path, SymlinkPolicy::from_context(context))
+}
+
+/// Canonicalize the part of a path that exists and append the rest
+///
+/// Returns None if a missing part is a dangling link, whose target could be
+/// created anywhere.
+fn canonicalize_existing(path: &Path) -> Option<PathBuf> {
+    let mut missing = Vec::new();
+    let mut current = path;
+    loop {
+        if let Ok(canonical) = current.canonicalize() {
+            return Some(missing.iter().rev().fold(canonical, |path, name| path.join(name)));
+        }
+        if current.symlink_metadata().is_ok() {
+            return None;
+        }
+        missing.push(current.file_name()?);
+        current = current.parent()?;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A fresh directory for one test, canonical so resolved paths compare
+    fn test_root(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("rusty-beam-paths-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&root);
+        std::fs::create_dir_all(&root).unwrap();
+        root.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn test_normalize_request_path() {
+        let normalize = |path| normalize_request_path(path).unwrap();
+        assert_eq!(normalize("/"), "/");
+        assert_eq!(normalize("/docs/index.html"), "/docs/index.html");
+        assert_eq!(normalize("//admin"), "/admin");
+        assert_eq!(normalize("/a/./b/../c"), "/a/c");
+        assert_eq!(normalize("/../../etc/passwd"), "/etc/passwd");
+        assert_eq!(normalize("/%2E%2E/admin/"), "/admin/");
+        assert_eq!(normalize("/docs/.."), "/");
+        assert_eq!(normalize("/docs/."), "/docs/");
+        // Escapes are decoded once only
+        assert_eq!(normalize("/%252E%252E/admin"), "/%2E%2E/admin");
+        // A decomposed é becomes the composed one
+        assert_eq!(normalize("/cafe%CC%81.html"), "/caf\u{e9}.html");
+    }
+
+    #[test]
+    fn test_invalid_request_paths() {
+        assert_eq!(normalize_request_path("admin"), Err(InvalidPath::NotAbsolute));
+        assert_eq!(normalize_request_path("/a%2"), Err(InvalidPath::Encoding));
+        assert_eq!(normalize_request_path("/a%zz"), Err(InvalidPath::Encoding));
+        assert_eq!(normalize_request_path("/%FF"), Err(InvalidPath::Encoding));
+        assert_eq!(normalize_request_path("/a%00.html"), Err(InvalidPath::ControlCharacter));
+        assert_eq!(normalize_request_path("/a%0A"), Err(InvalidPath::ControlCharacter));
+    }
+
+    #[test]
+    fn test_resolve_within_root() {
+        let root = test_root("resolve");
+        std::fs::create_dir(root.join("docs")).unwrap();
+
+        assert_eq!(resolve(&root, "/", SymlinkPolicy::DenyEscape), Ok(root.join("index.html")));
+        assert_eq!(resolve(&root, "/docs/", SymlinkPolicy::DenyEscape), Ok(root.join("docs/index.html")));
+        // Files about to be created resolve too
+        assert_eq!(resolve(&root, "/docs/new/page.html", SymlinkPolicy::DenyEscape), Ok(root.join("docs/new/page.html")));
+        assert_eq!(resolve(&root, "/docs/../../outside", SymlinkPolicy::DenyEscape), Err(PathError::Escapes));
+        assert_eq!(resolve(&root, "/../outside", SymlinkPolicy::AllowWithinRoot), Err(PathError::Escapes));
+        assert_eq!(resolve(&root.join("missing"), "/", SymlinkPolicy::DenyEscape), Err(PathError::RootUnavailable));
+
+        let _ = std::fs::remove_dir_all(&root);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlink_policies() {
+        let root = test_root("symlinks");
+        let outside = test_root("symlinks-outside");
+        std::fs::write(outside.join("secret.html"), "secret").unwrap();
+        std::fs::write(root.join("page.html"), "page").unwrap();
+        std::os::unix::fs::symlink(&outside, root.join("shared")).unwrap();
+        std::os::unix::fs::symlink(root.join("page.html"), root.join("alias.html")).unwrap();
+        std::os::unix::fs::symlink(outside.join("missing"), root.join("dangling")).unwrap();
+
+        // Links within the root are followed under either policy
+        assert_eq!(resolve(&root, "/alias.html", SymlinkPolicy::DenyEscape), Ok(root.join("page.html")));
+        assert_eq!(resolve(&root, "/shared/secret.html", SymlinkPolicy::DenyEscape), Err(PathError::Escapes));
+        assert_eq!(resolve(&root, "/dangling/new.html", SymlinkPolicy::DenyEscape), Err(PathError::Escapes));
+        assert_eq!(
+            resolve(&root, "/shared/secret.html", SymlinkPolicy::AllowWithinRoot),
+            Ok(root.join("shared/secret.html"))
+        );
+
+        let _ = std::fs::remove_dir_all(&root);
+        let _ = std::fs::remove_dir_all(&outside);
+    }
+
+    #[test]
+    fn test_symlink_policy_names() {
+        assert_eq!("Deny-Escape".parse(), Ok(SymlinkPolicy::DenyEscape));
+        assert_eq!(" allow-within-root ".parse(), Ok(SymlinkPolicy::AllowWithinRoot));
+        assert!("follow".parse::<SymlinkPolicy>().is_err());
+        assert_eq!(SymlinkPolicy::default().as_str(), "deny-escape");
+    }
+}
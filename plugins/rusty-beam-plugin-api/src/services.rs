@@ -0,0 +1,282 @@
+//! Services the server offers plugins outside the request pipeline
+//!
+//! The `PluginContext` of every request carries a handle to the services of
+//! its host. Through it a plugin can ask whether a user may do something
+//! other than the request at hand, for example subscribe to a message channel,
+//! publish messages into the channels of a message hub, and read or change
+//! the elements of a document by selector.
+//!
+//! Plugins that act outside requests, such as scheduled tasks, receive the
+//! services of their host once at startup through `Plugin::attach_services`,
+//! and can run requests of their own through the pipeline with `dispatch`.
+//!
+//! Plugins reporting on the server, such as health checks, can read the
+//! number of connections and requests it handles with `server_load`, and how
+//! often the host's plugins panicked with `plugin_failures`.
+//!
+//! Plugins that change documents announce the changes with `notify_change`,
+//! so others can act on them, for example regenerate a page derived from the
+//! changed documents.
+//!
+//! Paths given to `is_allowed` and `apply_selector` are normalized as request
+//! paths are before they reach the plugins (see
+//! `paths::normalize_request_path`), so `/public/../secret.html` is decided
+//! and read as `/secret.html`; a path that cannot be normalized is refused.
+//!
+//! The server answers these by consulting the plugins of the host's pipeline:
+//! access questions go to `Plugin::check_access`, where the first plugin with
+//! an opinion decides, publications go to every plugin's `Plugin::publish`,
+//! selector operations go to `Plugin::apply_selector` once
+//! `Plugin::check_selector_access` allowed them, and changes go to every
+//! plugin's `Plugin::on_change`.
+
+use crate::paths::normalize_request_path;
+use crate::{Plugin, PluginContext, PluginFailures};
+use hyper::{Body, Request, Response, StatusCode};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+/// Withheld selector covering every element, reported for a plugin that
+/// could not tell which elements it withholds
+pub const WITHHOLD_ALL: &str = "*";
+
+/// Response of a request run through the pipeline by `HostServices::dispatch`
+pub type DispatchFuture = Pin<Box<dyn Future<Output = Response<Body>> + Send>>;
+
+/// Services available to the plugins of one host
+pub trait HostServices: Send + Sync {
+    /// Whether `user` may use `method` on `path`; `None` stands for an
+    /// anonymous user
+    ///
+    /// When no plugin decides, access is allowed, just as a request passes a
+    /// pipeline without access control.
+    fn is_allowed(&self, user: Option<&str>, method: &str, path: &str, context: &PluginContext) -> bool;
+
+    /// Deliver a message to the subscribers of a channel, returning how many
+    /// subscribers it reached
+    fn publish(&self, channel: &str, message: &str) -> usize;
+
+    /// Apply a selector operation on behalf of `user`, returning the selected
+    /// or updated elements
+    ///
+    /// The operation is subject to the same access rules as the equivalent
+    /// HTTP request with a selector `Range` header.
+    fn apply_selector(
+        &self,
+        user: Option<&str>,
+        operation: &SelectorOperation,
+        context: &PluginContext,
+    ) -> Result<String, SelectorError>;
+
+    /// Selectors of the elements of `path` that `user` may not read, from
+    /// every plugin of the host
+    ///
+    /// Plugins revealing a document's content outside a GET of it must leave
+    /// these elements out. The default withholds nothing.
+    fn withheld_selectors(&self, user: Option<&str>, path: &str, context: &PluginContext) -> Vec<String> {
+        let _ = (user, path, context);
+        Vec::new()
+    }
+
+    /// Announce a change made to a document to every plugin of the host
+    ///
+    /// The default announces it to nobody.
+    fn notify_change(&self, change: &DocumentChange, context: &PluginContext) {
+        let _ = (change, context);
+    }
+
+    /// How loaded the server is; the default, outside a server, reports nothing
+    fn server_load(&self) -> Option<ServerLoad> {
+        None
+    }
+
+    /// How often the plugins of the host have panicked, for those whose
+    /// calls are guarded; the default reports none
+    fn plugin_failures(&self) -> Vec<PluginFailures> {
+        Vec::new()
+    }
+
+    /// Run a request through the host's pipeline as if a client had sent it
+    ///
+    /// The request passes every plugin, access control included, so it must
+    /// carry whatever credentials the path requires. The returned future does
+    /// not depend on the caller's runtime and may be blocked on from any thread.
+    fn dispatch(&self, request: Request<Body>) -> DispatchFuture {
+        let _ = request;
+        Box::pin(async {
+            Response::builder()
+                .status(StatusCode::NOT_IMPLEMENTED)
+                .body(Body::from("Internal requests are not supported"))
+                .unwrap()
+        })
+    }
+}
+
+/// The connections and requests a server is handling at one moment
+///
+/// The counts include the request asking for them. A limit of `None` means
+/// the server accepts any number.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ServerLoad {
+    pub connections: usize,
+    pub max_connections: Option<usize>,
+    pub in_flight_requests: usize,
+    pub max_in_flight_requests: Option<usize>,
+    /// Requests answered with a 503 since the server started because too
+    /// many were in flight
+    pub rejected_requests: u64,
+}
+
+impl ServerLoad {
+    /// Whether the server is at one of its limits, pausing new connections
+    /// or rejecting new requests
+    pub fn is_saturated(&self) -> bool {
+        self.max_connections.is_some_and(|max| self.connections >= max)
+            || self.max_in_flight_requests.is_some_and(|max| self.in_flight_requests >= max)
+    }
+}
+
+/// A selector request made outside the request pipeline
+///
+/// `method` carries the HTTP method of the equivalent request: `GET` reads
+/// the selected elements, `PUT` replaces them, `POST` appends to them and
+/// `DELETE` removes them.
+#[derive(Debug, Clone)]
+pub struct SelectorOperation {
+    pub method: String,
+    pub path: String,
+    pub selector: String,
+    pub content: Option<String>,
+}
+
+impl SelectorOperation {
+    pub fn new(method: &str, path: &str, selector: &str) -> Self {
+        Self {
+            method: method.to_string(),
+            path: path.to_string(),
+            selector: selector.to_string(),
+            content: None,
+        }
+    }
+
+    /// Set the content a `PUT` or `POST` writes
+    pub fn with_content(mut self, content: impl Into<String>) -> Self {
+        self.content = Some(content.into());
+        self
+    }
+}
+
+/// A change made to the elements a selector picks from a document
+///
+/// `method` names the operation as the HTTP method of the request that made
+/// it: `PUT` replaced the elements, `POST` appended to them, `PATCH` merged
+/// attributes into them and `DELETE` removed them.
+#[derive(Debug, Clone)]
+pub struct DocumentChange {
+    pub method: String,
+    pub path: String,
+    pub selector: String,
+    /// The changed elements as they are now; empty after `DELETE`
+    pub content: String,
+    /// The elements before the change, when the plugin making it knows them
+    pub previous_content: Option<String>,
+    /// The user who made the change; `None` stands for an anonymous user
+    pub user: Option<String>,
+}
+
+/// Why a selector operation failed, as the HTTP status the equivalent
+/// request would have received
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SelectorError {
+    pub status: u16,
+    pub message: String,
+}
+
+impl SelectorError {
+    pub fn new(status: u16, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+}
+
+impl std::fmt::Display for SelectorError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} ({})", self.message, self.status)
+    }
+}
+
+impl std::error::Error for SelectorError {}
+
+/// Host services answered by the plugins of a pipeline
+pub struct PipelineServices {
+    plugins: Vec<Arc<dyn Plugin>>,
+}
+
+impl PipelineServices {
+    pub fn new(plugins: Vec<Arc<dyn Plugin>>) -> Self {
+        Self { plugins }
+    }
+}
+
+impl HostServices for PipelineServices {
+    fn is_allowed(&self, user: Option<&str>, method: &str, path: &str, context: &PluginContext) -> bool {
+        let Ok(path) = normalize_request_path(path) else {
+            return false;
+        };
+        self.plugins.iter()
+            .find_map(|plugin| plugin.check_access(user, method, &path, context))
+            .unwrap_or(true)
+    }
+
+    fn publish(&self, channel: &str, message: &str) -> usize {
+        self.plugins.iter()
+            .map(|plugin| plugin.publish(channel, message))
+            .sum()
+    }
+
+    fn notify_change(&self, change: &DocumentChange, context: &PluginContext) {
+        for plugin in &self.plugins {
+            plugin.on_change(change, context);
+        }
+    }
+
+    fn apply_selector(
+        &self,
+        user: Option<&str>,
+        operation: &SelectorOperation,
+        context: &PluginContext,
+    ) -> Result<String, SelectorError> {
+        let path = normalize_request_path(&operation.path)
+            .map_err(|e| SelectorError::new(400, format!("Invalid path: {}", e)))?;
+        let operation = &SelectorOperation { path, ..operation.clone() };
+        let allowed = self.plugins.iter()
+            .find_map(|plugin| plugin.check_selector_access(
+                user, &operation.method, &operation.path, &operation.selector, context,
+            ))
+            .unwrap_or(true);
+        if !allowed {
+            return Err(SelectorError::new(403, "Access denied"));
+        }
+
+        self.plugins.iter()
+            .find_map(|plugin| plugin.apply_selector(operation, context))
+            .unwrap_or_else(|| Err(SelectorError::new(501, "No plugin handles selector operations")))
+    }
+
+    fn withheld_selectors(&self, user: Option<&str>, path: &str, context: &PluginContext) -> Vec<String> {
+        let Ok(path) = normalize_request_path(path) else {
+            return Vec::new();
+        };
+        let mut selectors: Vec<String> = Vec::new();
+        for selector in self.plugins.iter().flat_map(|plugin| plugin.withheld_selectors(user, &path, context)) {
+            if !selectors.contains(&selector) {
+                selectors.push(selector);
+            }
+        }
+        selectors
+    }
+
+    fn plugin_failures(&self) -> Vec<PluginFailures> {
+        self.plugins.iter().filter_map(|plugin| plugin.failures()).collect()
+    }
+}
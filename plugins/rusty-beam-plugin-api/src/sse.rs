@@ -0,0 +1,252 @@
+//! Server-Sent Events
+//!
+//! `channel` answers a request with a `text/event-stream` response and returns
+//! the `EventSender` that feeds it. Events are formatted by `Event`, which
+//! splits multi-line data into several `data:` fields and keeps names and ids
+//! on one line. The sender notices when the client goes away: hyper drops the
+//! response body, and `send` then fails with `SendError::Disconnected`, so a
+//! plugin can forget the subscriber.
+//!
+//! Proxies and browsers close connections that stay silent, so a stream may
+//! be given a keep-alive interval, after which a comment is written whenever
+//! no event was sent. Plugins cannot use the server's timers, so keep-alives
+//! are written by one background thread per plugin library, which runs only
+//! while some stream needs it.
+
+use bytes::Bytes;
+use futures_core::Stream;
+use hyper::{Body, Response, StatusCode};
+use std::fmt;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+/// Content type of an event stream
+pub const CONTENT_TYPE_EVENT_STREAM: &str = "text/event-stream";
+
+/// Header carrying the id of the last event a reconnecting client received
+pub const LAST_EVENT_ID_HEADER: &str = "last-event-id";
+
+/// Events a stream buffers for a slow client before sends fail
+const STREAM_CAPACITY: usize = 256;
+
+/// How often the keep-alive thread checks its streams
+const KEEP_ALIVE_TICK: Duration = Duration::from_secs(1);
+
+/// Text of the keep-alive comment
+const KEEP_ALIVE_COMMENT: &str = "keep-alive";
+
+/// One event of a stream
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    event: Option<String>,
+    id: Option<String>,
+    retry: Option<Duration>,
+    data: Option<String>,
+}
+
+impl Event {
+    /// An unnamed event, which clients receive as a `message`
+    pub fn new(data: impl Into<String>) -> Self {
+        Self { data: Some(data.into()), ..Self::default() }
+    }
+
+    /// An event carrying only a reconnection delay for the client
+    pub fn retry_only(retry: Duration) -> Self {
+        Self { retry: Some(retry), ..Self::default() }
+    }
+
+    /// Set the event name, which clients listen for with `addEventListener`
+    pub fn event(mut self, name: impl Into<String>) -> Self {
+        self.event = Some(name.into());
+        self
+    }
+
+    /// Set the id a reconnecting client sends back in `Last-Event-ID`
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(id.into());
+        self
+    }
+
+    /// Set how long the client waits before reconnecting
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+impl fmt::Display for Event {
+    /// The event in wire format, ending with the blank line that dispatches it
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", single_line(event))?;
+        }
+        if let Some(id) = &self.id {
+            // An id containing NUL is ignored by clients
+            writeln!(f, "id: {}", single_line(id).replace('\0', ""))?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+        if let Some(data) = &self.data {
+            for line in data.split('\n') {
+                writeln!(f, "data: {}", line.strip_suffix('\r').unwrap_or(line))?;
+            }
+        }
+        writeln!(f)
+    }
+}
+
+/// A comment in wire format, which clients ignore
+pub fn comment(text: &str) -> String {
+    text.split('\n').map(|line| format!(": {}\n", line)).collect::<String>() + "\n"
+}
+
+/// The `Last-Event-ID` a reconnecting client sent, if any
+pub fn last_event_id(request: &hyper::Request<Body>) -> Option<&str> {
+    request.headers().get(LAST_EVENT_ID_HEADER)?.to_str().ok()
+}
+
+/// Why an event could not be sent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SendError {
+    /// The client closed the connection
+    Disconnected,
+    /// The client is not reading and the stream's buffer is full
+    Full,
+}
+
+impl fmt::Display for SendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SendError::Disconnected => write!(f, "client disconnected"),
+            SendError::Full => write!(f, "client is not reading events"),
+        }
+    }
+}
+
+impl std::error::Error for SendError {}
+
+/// Writes events to one client's stream
+///
+/// Clones feed the same stream. Sending never waits, so it can be done from
+/// any thread or plugin phase.
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    sender: mpsc::Sender<Bytes>,
+    last_sent: Arc<Mutex<Instant>>,
+}
+
+impl EventSender {
+    /// Send an event
+    pub fn send(&self, event: &Event) -> Result<(), SendError> {
+        self.send_raw(event.to_string())
+    }
+
+    /// Send a comment, e.g. to tell the client the stream is still alive
+    pub fn comment(&self, text: &str) -> Result<(), SendError> {
+        self.send_raw(comment(text))
+    }
+
+    /// Whether the client has gone away
+    pub fn is_closed(&self) -> bool {
+        self.sender.is_closed()
+    }
+
+    fn send_raw(&self, text: String) -> Result<(), SendError> {
+        self.sender.try_send(Bytes::from(text)).map_err(|e| match e {
+            mpsc::error::TrySendError::Closed(_) => SendError::Disconnected,
+            mpsc::error::TrySendError::Full(_) => SendError::Full,
+        })?;
+        *self.last_sent.lock().unwrap_or_else(|e| e.into_inner()) = Instant::now();
+        Ok(())
+    }
+}
+
+/// Open an event stream
+///
+/// Returns the sender feeding the stream and the 200 response to answer the
+/// request with. With a keep-alive interval a comment is written whenever
+/// the stream has been silent that long.
+pub fn channel(keep_alive: Option<Duration>) -> (EventSender, Response<Body>) {
+    let (sender, receiver) = mpsc::channel(STREAM_CAPACITY);
+    let sender = EventSender { sender, last_sent: Arc::new(Mutex::new(Instant::now())) };
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", CONTENT_TYPE_EVENT_STREAM)
+        .header("Cache-Control", "no-cache")
+        // Stop reverse proxies such as nginx holding events back
+        .header("X-Accel-Buffering", "no")
+        .body(Body::wrap_stream(EventStream { receiver }))
+        .unwrap();
+
+    if let Some(interval) = keep_alive.filter(|interval| !interval.is_zero()) {
+        register_keep_alive(sender.clone(), interval);
+    }
+    (sender, response)
+}
+
+/// Response body of a stream
+struct EventStream {
+    receiver: mpsc::Receiver<Bytes>,
+}
+
+impl Stream for EventStream {
+    type Item = io::Result<Bytes>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx).map(|chunk| chunk.map(Ok))
+    }
+}
+
+/// Streams whose keep-alives are due, and whether the thread writing them runs
+#[derive(Default)]
+struct KeepAlives {
+    streams: Vec<(EventSender, Duration)>,
+    running: bool,
+}
+
+static KEEP_ALIVES: OnceLock<Mutex<KeepAlives>> = OnceLock::new();
+
+fn register_keep_alive(sender: EventSender, interval: Duration) {
+    let keep_alives = KEEP_ALIVES.get_or_init(|| Mutex::new(KeepAlives::default()));
+    let mut state = keep_alives.lock().unwrap_or_else(|e| e.into_inner());
+    state.streams.push((sender, interval));
+    if !state.running {
+        state.running = true;
+        thread::Builder::new()
+            .name("sse-keep-alive".to_string())
+            .spawn(move || write_keep_alives(keep_alives))
+            .expect("failed to spawn the SSE keep-alive thread");
+    }
+}
+
+/// Body of the keep-alive thread, which ends once no stream is open
+fn write_keep_alives(keep_alives: &Mutex<KeepAlives>) {
+    loop {
+        thread::sleep(KEEP_ALIVE_TICK);
+        let mut state = keep_alives.lock().unwrap_or_else(|e| e.into_inner());
+        state.streams.retain(|(sender, interval)| {
+            let silent_for = sender.last_sent.lock().unwrap_or_else(|e| e.into_inner()).elapsed();
+            if silent_for < *interval {
+                return !sender.is_closed();
+            }
+            // A full buffer means events are queued, which keeps the connection busy
+            sender.comment(KEEP_ALIVE_COMMENT) != Err(SendError::Disconnected)
+        });
+        if state.streams.is_empty() {
+            state.running = false;
+            return;
+        }
+    }
+}
+
+/// A field value with line breaks removed, which would end the field
+fn single_line(value: &str) -> String {
+    value.replace(['\r', '\n'], "")
+}
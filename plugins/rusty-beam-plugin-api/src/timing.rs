@@ -0,0 +1,84 @@
+//! Request timing
+//!
+//! The server notes in each request's metadata when it received the request,
+//! how long each plugin's `handle_request` took, and how long it took until a
+//! plugin produced the response. The response phase runs after that, so
+//! plugins logging or reporting latency there read these values rather than
+//! keeping clocks of their own, and measure from the same moment whatever
+//! their place in the pipeline.
+//!
+//! Times are kept as text like all metadata: `REQUEST_START` as microseconds
+//! since the Unix epoch, `TTFB` as microseconds since the start, and
+//! `PLUGIN_DURATIONS` as comma-separated `name=microseconds` pairs in
+//! pipeline order.
+
+use crate::PluginRequest;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Metadata key of when the server received the request
+pub const REQUEST_START: &str = "request_start";
+/// Metadata key of how long after the start a plugin produced the response
+pub const TTFB: &str = "ttfb";
+/// Metadata key of how long each plugin's `handle_request` took
+pub const PLUGIN_DURATIONS: &str = "plugin_durations";
+
+/// Microseconds since the Unix epoch
+pub fn now_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|since| since.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// Note when the request was received, unless that is already known
+pub fn record_start(request: &mut PluginRequest) {
+    if !request.has_metadata(REQUEST_START) {
+        request.set_metadata(REQUEST_START.to_string(), now_micros().to_string());
+    }
+}
+
+/// When the request was received, in microseconds since the Unix epoch
+pub fn request_start(request: &PluginRequest) -> Option<u64> {
+    request.get_metadata(REQUEST_START)?.parse().ok()
+}
+
+/// Time since the request was received
+pub fn elapsed(request: &PluginRequest) -> Option<Duration> {
+    request_start(request).map(|start| Duration::from_micros(now_micros().saturating_sub(start)))
+}
+
+/// Note that a response has been produced
+pub fn record_ttfb(request: &mut PluginRequest) {
+    if let Some(elapsed) = elapsed(request) {
+        request.set_metadata(TTFB.to_string(), elapsed.as_micros().to_string());
+    }
+}
+
+/// How long after the start a plugin produced the response
+pub fn ttfb(request: &PluginRequest) -> Option<Duration> {
+    request.get_metadata(TTFB)?.parse().ok().map(Duration::from_micros)
+}
+
+/// Add how long a plugin's `handle_request` took
+pub fn record_plugin_duration(request: &mut PluginRequest, plugin: &str, duration: Duration) {
+    // Separators in names would make the list ambiguous
+    let plugin: String = plugin.chars().map(|c| if c == ',' || c == '=' { '_' } else { c }).collect();
+    let entry = format!("{}={}", plugin, duration.as_micros());
+    let durations = match request.get_metadata(PLUGIN_DURATIONS) {
+        Some(durations) if !durations.is_empty() => format!("{},{}", durations, entry),
+        _ => entry,
+    };
+    request.set_metadata(PLUGIN_DURATIONS.to_string(), durations);
+}
+
+/// How long each plugin's `handle_request` took, in pipeline order
+pub fn plugin_durations(request: &PluginRequest) -> Vec<(String, Duration)> {
+    request.get_metadata(PLUGIN_DURATIONS)
+        .unwrap_or_default()
+        .split(',')
+        .filter_map(|entry| {
+            let (plugin, micros) = entry.rsplit_once('=')?;
+            Some((plugin.to_string(), Duration::from_micros(micros.parse().ok()?)))
+        })
+        .collect()
+}
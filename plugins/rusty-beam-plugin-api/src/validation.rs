@@ -0,0 +1,89 @@
+//! Configuration checks
+//!
+//! `rusty-beam --check-config` creates every configured plugin without
+//! serving requests and asks each one, through `Plugin::validate_config`, for
+//! the problems it can see in its configuration, such as files it will not be
+//! able to read. Problems are reported as `ConfigIssue`s: errors fail the
+//! check, warnings are only shown. Anything a plugin logs at warning level or
+//! above while it is created is reported too, so plugins that already log bad
+//! values need not repeat them here.
+
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// How serious a configuration problem is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Severity {
+    /// The server would start, but probably not as intended
+    Warning,
+    /// Part of the configuration cannot work
+    Error,
+}
+
+impl Severity {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// A problem found in a plugin's configuration
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ConfigIssue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl ConfigIssue {
+    pub fn error(message: impl Into<String>) -> Self {
+        Self { severity: Severity::Error, message: message.into() }
+    }
+
+    pub fn warning(message: impl Into<String>) -> Self {
+        Self { severity: Severity::Warning, message: message.into() }
+    }
+}
+
+impl fmt::Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.severity.as_str(), self.message)
+    }
+}
+
+/// Check that the file a configuration key names can be read
+///
+/// `value` may be a plain path or a `file://` URL, as plugins accept both.
+pub fn check_readable_file(key: &str, value: &str) -> Option<ConfigIssue> {
+    let path = value.strip_prefix("file://").unwrap_or(value);
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_dir() => Some(ConfigIssue::error(format!("{} {} is a directory", key, path))),
+        Ok(_) => fs::File::open(path).err()
+            .map(|e| ConfigIssue::error(format!("{} {} cannot be read: {}", key, path, e))),
+        Err(e) => Some(ConfigIssue::error(format!("{} {} cannot be read: {}", key, path, e))),
+    }
+}
+
+/// Check that a file a configuration key names can be created or appended to
+pub fn check_writable_file(key: &str, value: &str) -> Option<ConfigIssue> {
+    let path = Path::new(value.strip_prefix("file://").unwrap_or(value));
+    if path.is_dir() {
+        return Some(ConfigIssue::error(format!("{} {} is a directory", key, path.display())));
+    }
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    match fs::metadata(directory) {
+        Ok(metadata) if metadata.permissions().readonly() => Some(ConfigIssue::error(
+            format!("{} {}: directory {} is read-only", key, path.display(), directory.display()),
+        )),
+        Ok(_) => None,
+        Err(e) => Some(ConfigIssue::error(
+            format!("{} {}: directory {} is not available: {}", key, path.display(), directory.display(), e),
+        )),
+    }
+}
@@ -0,0 +1,281 @@
+//! Schema Validation Plugin for Rusty Beam
+//!
+//! This plugin keeps the microdata of HTML documents conforming to declared
+//! schemas. It checks every write before the file or selector handler makes
+//! it, and rejects writes that would leave a document with items breaking
+//! their schema.
+//!
+//! ## Features
+//! - **Schema Registry**: Schemas are Schema and Property items in an HTML
+//!   file, so the registry is itself a document the server can serve and edit
+//! - **Whole-File Writes**: A PUT is checked by its body, a POST by the file
+//!   with the body appended, as the file handler writes them
+//! - **Selector Writes**: A PUT or POST with a selector `Range` is applied to
+//!   a copy of the document, which is checked as it would be written
+//! - **Structured Errors**: Rejected writes are answered with a 422 listing
+//!   every violation
+//!
+//! ## Configuration
+//! - `schemas`: The schema registry file, as a `file://` URL or path (required)
+//! - `strict`: Whether properties a schema does not declare are violations
+//!   (default: false)
+//! - `root_dir`: Directory files are resolved in for plugins running without
+//!   a host (default: ".")
+//!
+//! ## Validation
+//! Every item of the resulting document whose item type has a schema is
+//! checked, nested items included; items of other types are left alone.
+//! A property must occur as often as its cardinality allows and each value
+//! must be of the declared type. The registry is read again when the file
+//! changes. Only `.html` and `.htm` files are checked.
+//!
+//! Writes made through `HostServices::apply_selector` bypass the pipeline and
+//! are not checked.
+//!
+//! ## Pipeline Integration
+//! Place the plugin after authorization, so unauthorized writes are refused
+//! before documents are read, and before the selector and file handlers.
+
+mod registry;
+
+use async_trait::async_trait;
+use dom_query::Document;
+use hyper::header::{CONTENT_TYPE, RANGE};
+use hyper::{Body, Method, Response, StatusCode};
+use registry::{Registry, Violation};
+use regex::Regex;
+use rusty_beam_plugin_api::{create_plugin, log_error, log_info, paths, Plugin, PluginContext, PluginRequest, PluginResponse};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+// Default configuration values
+const DEFAULT_PLUGIN_NAME: &str = "schema-validation";
+const DEFAULT_ROOT_DIR: &str = ".";
+
+// Configuration keys
+const CONFIG_KEY_NAME: &str = "name";
+const CONFIG_KEY_SCHEMAS: &str = "schemas";
+const CONFIG_KEY_STRICT: &str = "strict";
+const CONFIG_KEY_ROOT_DIR: &str = "root_dir";
+
+// Selector writes
+const SELECTOR_PREFIX: &str = "selector=";
+const MICRODATA_PSEUDO_PATTERN: &str = r#":(itemtype|itemprop|itemid)\(\s*(?:"([^"]*)"|'([^']*)'|([^\s)]*))\s*\)"#;
+const ITEMSCOPE_PSEUDO: &str = ":itemscope";
+const MARKER: &str = "__RUSTY_BEAM_SCHEMA_VALIDATION_MARKER__";
+
+// Whole-file writes the file handler does not append
+const CONTENT_TYPE_MULTIPART: &str = "multipart/form-data";
+const QUERY_RESTORE: &str = "restore=";
+
+// Content types and messages
+const CONTENT_TYPE_JSON: &str = "application/json";
+const MSG_INVALID_DOCUMENT: &str = "Document does not conform to its schemas";
+
+/// The registry as last read, with the modification time of its file
+#[derive(Debug)]
+struct LoadedRegistry {
+    registry: Arc<Registry>,
+    modified: Option<SystemTime>,
+}
+
+/// How a write changes a document
+enum Write {
+    /// The body becomes the whole file
+    Replace,
+    /// The body is appended to the file
+    Append,
+    /// The body replaces the element a selector picks
+    ReplaceSelected(String),
+    /// The body is appended to the element a selector picks
+    AppendToSelected(String),
+}
+
+/// Plugin rejecting writes that break the schemas of a document's microdata
+#[derive(Debug)]
+pub struct SchemaValidationPlugin {
+    name: String,
+    root_dir: String,
+    schemas_path: Option<PathBuf>,
+    strict: bool,
+    registry: RwLock<LoadedRegistry>,
+}
+
+impl SchemaValidationPlugin {
+    pub fn new(config: HashMap<String, String>) -> Self {
+        let name = config.get(CONFIG_KEY_NAME).cloned().unwrap_or_else(|| DEFAULT_PLUGIN_NAME.to_string());
+        let schemas_path = config.get(CONFIG_KEY_SCHEMAS)
+            .map(|schemas| PathBuf::from(schemas.strip_prefix("file://").unwrap_or(schemas)));
+        if schemas_path.is_none() {
+            log_error!(name, "No schemas configured, writes will not be validated");
+        }
+
+        let plugin = Self {
+            root_dir: config.get(CONFIG_KEY_ROOT_DIR).cloned().unwrap_or_else(|| DEFAULT_ROOT_DIR.to_string()),
+            schemas_path,
+            strict: config.get(CONFIG_KEY_STRICT).and_then(|v| v.parse().ok()).unwrap_or(false),
+            registry: RwLock::new(LoadedRegistry { registry: Arc::new(Registry::default()), modified: None }),
+            name,
+        };
+        plugin.registry();
+        plugin
+    }
+
+    /// The current registry, read again if its file changed since last time
+    ///
+    /// A registry that fails to load is logged and the previous one kept.
+    fn registry(&self) -> Arc<Registry> {
+        let Some(path) = &self.schemas_path else {
+            return self.registry.read().unwrap().registry.clone();
+        };
+        let modified = fs::metadata(path).and_then(|metadata| metadata.modified()).ok();
+        {
+            let loaded = self.registry.read().unwrap();
+            if loaded.modified.is_some() && loaded.modified == modified {
+                return loaded.registry.clone();
+            }
+        }
+
+        let mut loaded = self.registry.write().unwrap();
+        match fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|html| Registry::parse(&html)) {
+            Ok(registry) => {
+                log_info!(self.name, "Loaded schema registry"; path = path.display(), schemas = registry.len());
+                loaded.registry = Arc::new(registry);
+                loaded.modified = modified;
+            }
+            Err(e) => {
+                log_error!(self.name, "Failed to load schema registry: {}", e; path = path.display());
+                // Retried once the file changes again
+                loaded.modified = modified;
+            }
+        }
+        loaded.registry.clone()
+    }
+
+    /// How a request writes its file, or None if it is not a checked write
+    fn write_of(&self, request: &PluginRequest) -> Option<Write> {
+        let method = request.http_request.method();
+        if method != Method::PUT && method != Method::POST {
+            return None;
+        }
+        let range = request.http_request.headers().get(RANGE).and_then(|value| value.to_str().ok());
+        if let Some(selector) = range.and_then(|range| range.trim().strip_prefix(SELECTOR_PREFIX)) {
+            let selector = urlencoding::decode(selector).ok()?;
+            let selector = translate_microdata_pseudo_selectors(&selector);
+            return Some(if method == Method::PUT {
+                Write::ReplaceSelected(selector)
+            } else {
+                Write::AppendToSelected(selector)
+            });
+        }
+        if method == Method::PUT {
+            return Some(Write::Replace);
+        }
+
+        // Uploads and version restores write other content than the body
+        let is_upload = request.http_request.headers().get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .is_some_and(|value| value.starts_with(CONTENT_TYPE_MULTIPART));
+        let is_restore = request.http_request.uri().query()
+            .is_some_and(|query| query.split('&').any(|pair| pair.starts_with(QUERY_RESTORE)));
+        (!is_upload && !is_restore).then_some(Write::Append)
+    }
+
+    /// The document a write would leave, or None if the write cannot be
+    /// applied, which the handler making it reports
+    fn resulting_document(&self, path: &Path, write: &Write, body: &str) -> Option<String> {
+        let current = || fs::read_to_string(path).ok();
+        match write {
+            Write::Replace => Some(body.to_string()),
+            Write::Append => Some(current().unwrap_or_default() + body),
+            Write::ReplaceSelected(selector) | Write::AppendToSelected(selector) => {
+                let document = Document::from(current()?);
+                let element = document.try_select(selector)?.first();
+                // Content is put in place as text, so fragments such as table
+                // rows are parsed in their final context
+                if matches!(write, Write::ReplaceSelected(_)) {
+                    element.replace_with_html(MARKER);
+                } else {
+                    element.append_html(MARKER);
+                }
+                Some(document.html().replace(MARKER, body))
+            }
+        }
+    }
+
+    fn violations_response(&self, violations: &[Violation]) -> Response<Body> {
+        let body = serde_json::json!({
+            "error": MSG_INVALID_DOCUMENT,
+            "violations": violations.iter().map(Violation::to_json).collect::<Vec<_>>(),
+        });
+        Response::builder()
+            .status(StatusCode::UNPROCESSABLE_ENTITY)
+            .header(CONTENT_TYPE, CONTENT_TYPE_JSON)
+            .body(Body::from(body.to_string()))
+            .unwrap()
+    }
+}
+
+/// Translate microdata pseudo-selectors into plain attribute selectors, as
+/// the selector handler does
+fn translate_microdata_pseudo_selectors(selector: &str) -> String {
+    let pseudo_regex = Regex::new(MICRODATA_PSEUDO_PATTERN).expect("valid pseudo-selector pattern");
+    let translated = pseudo_regex.replace_all(selector, |caps: &regex::Captures| {
+        let attribute = &caps[1];
+        let value = caps.get(2)
+            .or_else(|| caps.get(3))
+            .or_else(|| caps.get(4))
+            .map(|m| m.as_str())
+            .unwrap_or("")
+            .replace('\\', "\\\\")
+            .replace('"', "\\\"");
+        let operator = if attribute == "itemid" { "=" } else { "~=" };
+        format!(r#"[{}{}"{}"]"#, attribute, operator, value)
+    });
+    translated.replace(ITEMSCOPE_PSEUDO, "[itemscope]")
+}
+
+#[async_trait]
+impl Plugin for SchemaValidationPlugin {
+    async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
+        let write = self.write_of(request)?;
+        let path = paths::request_file_path(request, context, &self.root_dir).ok()?;
+        let is_html = path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("html") || extension.eq_ignore_ascii_case("htm"));
+        if !is_html {
+            return None;
+        }
+
+        let registry = self.registry();
+        if registry.is_empty() {
+            return None;
+        }
+        // Bodies that are not text are left to the handler to refuse
+        let body = request.get_body_string().await.ok()?;
+        let document = self.resulting_document(&path, &write, &body)?;
+
+        let violations = match registry.validate(&document, self.strict) {
+            Ok(violations) => violations,
+            Err(e) => {
+                context.log_verbose(&format!("[{}] Failed to extract microdata from {}: {}", self.name, request.path, e));
+                return None;
+            }
+        };
+        if violations.is_empty() {
+            return None;
+        }
+        context.log_verbose(&format!("[{}] Rejected write to {} with {} violations", self.name, request.path, violations.len()));
+        Some(self.violations_response(&violations).into())
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// Export the plugin creation function
+create_plugin!(SchemaValidationPlugin);
@@ -0,0 +1,322 @@
+//! The schema registry and the checks it makes of a document's microdata
+//!
+//! A registry is an HTML file holding one `Schema` item per validated item
+//! type. The schema's `id` is the item type it describes and each of its
+//! `property` items declares a property with a `name`, a `type`, a
+//! `cardinality` and optionally `required`:
+//!
+//! ```html
+//! <div itemscope itemtype="https://rustybeam.net/schema/Schema">
+//!     <span itemprop="id">https://example.com/Person</span>
+//!     <div itemprop="property" itemscope itemtype="https://rustybeam.net/schema/Property">
+//!         <span itemprop="name">name</span>
+//!         <span itemprop="type">Text</span>
+//!         <span itemprop="cardinality">1</span>
+//!     </div>
+//! </div>
+//! ```
+//!
+//! Types are `Text`, `HTML`, `Number`, `Boolean`, `URL` and `DateTime`, or
+//! the item type, or last segment of it, a nested item must have.
+//! Cardinalities are `1`, `0..1`, `0..n`, `1..n` or any other `min..max`.
+//! A schema with a `parent` inherits the parent's properties, which it may
+//! redeclare.
+
+use microdata_extract::{MicrodataExtractor, MicrodataItem, MicrodataValue};
+use std::collections::{HashMap, HashSet};
+use url::Url;
+
+// Item types of the registry itself
+const SCHEMA_ITEMTYPE: &str = "https://rustybeam.net/schema/Schema";
+
+// Value types checked by their text
+const TYPE_TEXT: &str = "Text";
+const TYPE_HTML: &str = "HTML";
+const TYPE_NUMBER: &str = "Number";
+const TYPE_BOOLEAN: &str = "Boolean";
+const TYPE_URL: &str = "URL";
+const TYPE_DATETIME: &str = "DateTime";
+
+/// How often a property may occur on an item
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cardinality {
+    pub min: usize,
+    /// None stands for any number
+    pub max: Option<usize>,
+}
+
+impl Cardinality {
+    const ANY: Cardinality = Cardinality { min: 0, max: None };
+
+    fn parse(value: &str) -> Result<Self, String> {
+        let value = value.trim();
+        let bound = |bound: &str| -> Result<Option<usize>, String> {
+            match bound.trim() {
+                "n" | "*" => Ok(None),
+                number => number.parse().map(Some).map_err(|_| format!("invalid cardinality '{}'", value)),
+            }
+        };
+        if value.eq_ignore_ascii_case("optional") {
+            return Ok(Cardinality { min: 0, max: Some(1) });
+        }
+        let (min, max) = match value.split_once("..") {
+            Some((min, max)) => (bound(min)?, bound(max)?),
+            None => (bound(value)?, bound(value)?),
+        };
+        let min = min.ok_or_else(|| format!("invalid cardinality '{}'", value))?;
+        if max.is_some_and(|max| max < min) {
+            return Err(format!("invalid cardinality '{}'", value));
+        }
+        Ok(Cardinality { min, max })
+    }
+
+    fn describe(&self) -> String {
+        match self.max {
+            Some(max) if max == self.min => format!("exactly {}", max),
+            Some(max) if self.min == 0 => format!("at most {}", max),
+            Some(max) => format!("between {} and {}", self.min, max),
+            None => format!("at least {}", self.min),
+        }
+    }
+}
+
+/// A property a schema declares
+#[derive(Debug, Clone)]
+pub struct PropertyRule {
+    pub name: String,
+    pub value_type: String,
+    pub cardinality: Cardinality,
+}
+
+#[derive(Debug, Clone)]
+struct Schema {
+    parent: Option<String>,
+    properties: Vec<PropertyRule>,
+}
+
+/// A way a document's microdata breaks its schemas
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Violation {
+    pub itemtype: String,
+    pub itemid: Option<String>,
+    /// None for violations of the item as a whole
+    pub property: Option<String>,
+    pub message: String,
+}
+
+impl Violation {
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "itemtype": self.itemtype,
+            "itemid": self.itemid,
+            "property": self.property,
+            "message": self.message,
+        })
+    }
+}
+
+/// The schemas of a registry file, by the item type they describe
+#[derive(Debug, Clone, Default)]
+pub struct Registry {
+    schemas: HashMap<String, Schema>,
+}
+
+impl Registry {
+    /// Read the schemas from the HTML of a registry file
+    pub fn parse(html: &str) -> Result<Self, String> {
+        let items = MicrodataExtractor::new().extract(html).map_err(|e| e.to_string())?;
+        let mut schemas = HashMap::new();
+        for item in items.iter().flat_map(all_items) {
+            if item.item_type() != Some(SCHEMA_ITEMTYPE) {
+                continue;
+            }
+            let Some(id) = item.get_property("id").filter(|id| !id.trim().is_empty()) else {
+                return Err("a schema has no id".to_string());
+            };
+            let mut properties = Vec::new();
+            for property in item.get_nested_items("property") {
+                let name = property.get_property("name").unwrap_or_default().trim().to_string();
+                if name.is_empty() {
+                    return Err(format!("a property of schema {} has no name", id));
+                }
+                let mut cardinality = match property.get_property("cardinality") {
+                    Some(cardinality) => Cardinality::parse(&cardinality)
+                        .map_err(|e| format!("property {} of schema {}: {}", name, id, e))?,
+                    None => Cardinality::ANY,
+                };
+                if property.get_property("required").is_some_and(|required| required.trim().eq_ignore_ascii_case("true")) {
+                    cardinality.min = cardinality.min.max(1);
+                }
+                properties.push(PropertyRule {
+                    name,
+                    value_type: property.get_property("type").unwrap_or_else(|| TYPE_TEXT.to_string()).trim().to_string(),
+                    cardinality,
+                });
+            }
+            let parent = item.get_property("parent").map(|parent| parent.trim().to_string()).filter(|parent| !parent.is_empty());
+            schemas.insert(id.trim().to_string(), Schema { parent, properties });
+        }
+        Ok(Self { schemas })
+    }
+
+    pub fn len(&self) -> usize {
+        self.schemas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+
+    /// The properties declared for an item type, inherited ones included,
+    /// or None if the type has no schema
+    pub fn rules(&self, itemtype: &str) -> Option<Vec<&PropertyRule>> {
+        if !self.schemas.contains_key(itemtype) {
+            return None;
+        }
+        let mut rules: Vec<&PropertyRule> = Vec::new();
+        let mut seen = HashSet::new();
+        let mut current = Some(itemtype);
+        while let Some(id) = current {
+            // A parent chain that loops ends where it started
+            if !seen.insert(id) {
+                break;
+            }
+            let Some(schema) = self.schemas.get(id) else { break };
+            for rule in &schema.properties {
+                if !rules.iter().any(|known| known.name == rule.name) {
+                    rules.push(rule);
+                }
+            }
+            current = schema.parent.as_deref();
+        }
+        Some(rules)
+    }
+
+    /// Check every item of a document, nested ones included, against the
+    /// schema of its type; items of types without a schema are not checked
+    ///
+    /// With `strict`, properties a schema does not declare are violations too.
+    pub fn validate(&self, html: &str, strict: bool) -> Result<Vec<Violation>, String> {
+        let items = MicrodataExtractor::new().extract(html).map_err(|e| e.to_string())?;
+        let mut violations = Vec::new();
+        for item in items.iter().flat_map(all_items) {
+            self.validate_item(item, strict, &mut violations);
+        }
+        Ok(violations)
+    }
+
+    fn validate_item(&self, item: &MicrodataItem, strict: bool, violations: &mut Vec<Violation>) {
+        let Some(itemtype) = item.item_type() else { return };
+        let Some(rules) = self.rules(itemtype) else { return };
+        let violation = |property: Option<&str>, message: String| Violation {
+            itemtype: itemtype.to_string(),
+            itemid: item.item_id().map(str::to_string),
+            property: property.map(str::to_string),
+            message,
+        };
+
+        for rule in &rules {
+            let values = item.get_properties(&rule.name);
+            let count = values.len();
+            let too_few = count < rule.cardinality.min;
+            let too_many = rule.cardinality.max.is_some_and(|max| count > max);
+            if too_few || too_many {
+                violations.push(violation(Some(&rule.name), format!(
+                    "expected {} value{}, found {}",
+                    rule.cardinality.describe(),
+                    if rule.cardinality.min == 1 && rule.cardinality.max == Some(1) { "" } else { "s" },
+                    count,
+                )));
+            }
+            for value in values {
+                if let Err(message) = self.check_type(value.value(), &rule.value_type) {
+                    violations.push(violation(Some(&rule.name), message));
+                }
+            }
+        }
+
+        if strict {
+            let mut reported = HashSet::new();
+            for name in item.property_names() {
+                if !rules.iter().any(|rule| rule.name == name) && reported.insert(name) {
+                    violations.push(violation(Some(name), "property is not declared by the schema".to_string()));
+                }
+            }
+        }
+    }
+
+    /// Whether a value is of a declared type
+    fn check_type(&self, value: &MicrodataValue, value_type: &str) -> Result<(), String> {
+        let text = match value {
+            MicrodataValue::Item(item) => {
+                return match value_type {
+                    TYPE_TEXT | TYPE_HTML | TYPE_NUMBER | TYPE_BOOLEAN | TYPE_URL | TYPE_DATETIME => {
+                        Err(format!("expected {}, found an item", value_type))
+                    }
+                    _ if item.item_type().is_some_and(|itemtype| is_item_of_type(itemtype, value_type)) => Ok(()),
+                    _ => Err(format!(
+                        "expected an item of type {}, found {}",
+                        value_type,
+                        item.item_type().unwrap_or("an untyped item"),
+                    )),
+                };
+            }
+            other => other.as_string(),
+        };
+        let text = text.trim();
+        let valid = match value_type {
+            TYPE_TEXT | TYPE_HTML => true,
+            TYPE_NUMBER => matches!(value, MicrodataValue::Number(_)) || text.parse::<f64>().is_ok_and(f64::is_finite),
+            TYPE_BOOLEAN => matches!(value, MicrodataValue::Boolean(_)) || text.eq_ignore_ascii_case("true") || text.eq_ignore_ascii_case("false"),
+            TYPE_URL => matches!(value, MicrodataValue::Url(_)) || Url::parse(text).is_ok() || text.starts_with('/'),
+            TYPE_DATETIME => is_datetime(text),
+            _ => return Err(format!("expected an item of type {}, found text", value_type)),
+        };
+        if valid {
+            Ok(())
+        } else {
+            Err(format!("expected {}, found '{}'", value_type, text))
+        }
+    }
+}
+
+/// An item and every item nested in its properties
+fn all_items(item: &MicrodataItem) -> Vec<&MicrodataItem> {
+    let mut items = vec![item];
+    for property in item.properties() {
+        if let Some(nested) = property.as_item() {
+            items.extend(all_items(nested));
+        }
+    }
+    items
+}
+
+/// Whether an item type satisfies a declared type, given either in full or
+/// as the last segment of the type URL
+fn is_item_of_type(itemtype: &str, declared: &str) -> bool {
+    itemtype.split_whitespace().any(|itemtype| {
+        itemtype == declared || itemtype.trim_end_matches('/').rsplit('/').next() == Some(declared)
+    })
+}
+
+/// Whether text is an ISO 8601 date, optionally with a time and offset
+fn is_datetime(text: &str) -> bool {
+    let bytes = text.as_bytes();
+    let digits = |range: std::ops::Range<usize>| bytes.get(range).is_some_and(|part| part.iter().all(u8::is_ascii_digit));
+    if !(digits(0..4) && bytes.get(4) == Some(&b'-') && digits(5..7) && bytes.get(7) == Some(&b'-') && digits(8..10)) {
+        return false;
+    }
+    match bytes.get(10) {
+        None => true,
+        Some(b'T') | Some(b' ') => {
+            let time = &text[11..];
+            let time = time.trim_end_matches('Z');
+            let time = time.rsplit_once(['+', '-']).map_or(time, |(time, _)| time);
+            let parts: Vec<&str> = time.split(':').collect();
+            (2..=3).contains(&parts.len())
+                && parts[..2].iter().all(|part| part.len() == 2 && part.bytes().all(|b| b.is_ascii_digit()))
+                && parts.get(2).is_none_or(|seconds| seconds.parse::<f64>().is_ok())
+        }
+        _ => false,
+    }
+}
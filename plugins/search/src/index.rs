@@ -0,0 +1,425 @@
+//! Inverted index of the HTML documents below a document root
+//!
+//! Every `.html` and `.htm` file is split into lowercase words from three
+//! places, weighted by how much they say about the document: its `<title>`,
+//! the values of its microdata properties, and the text of its body. Words
+//! of a property value are also kept as `property:word`, and the last
+//! segment of each item type as `type:segment`, so a query can ask for a
+//! word in a particular property or for documents holding a kind of item.
+//!
+//! Documents are ranked with BM25, counting a word by the weight of the
+//! places it appears in, so a word in the title outweighs the same word
+//! in the body.
+//!
+//! The index holds whole documents. A hit on a document with elements the
+//! searching user may not read is checked again against the document
+//! without them (see `recheck`), so neither the result nor its snippet
+//! reveals them.
+
+use dom_query::{Document, Matcher};
+use microdata_extract::{MicrodataExtractor, MicrodataItem};
+use rusty_beam_plugin_api::condition::glob_match;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+
+/// Weights of a word by where it appears
+const WEIGHT_TITLE: f64 = 3.0;
+const WEIGHT_PROPERTY: f64 = 2.0;
+const WEIGHT_BODY: f64 = 1.0;
+
+/// BM25 parameters: how quickly repeating a word stops adding to the score,
+/// and how much long documents are held back
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+/// Body text kept per document for snippets, in bytes
+const MAX_STORED_TEXT: usize = 20_000;
+/// Characters of a snippet shown before and after the first matching word
+const SNIPPET_BEFORE: usize = 60;
+const SNIPPET_AFTER: usize = 140;
+
+const HTML_EXTENSIONS: &[&str] = &["html", "htm"];
+const INDEX_FILE: &str = "index.html";
+const PREFIX_TYPE: &str = "type";
+
+/// What is indexed below a root
+#[derive(Debug)]
+pub(crate) struct IndexSettings {
+    /// Larger files are left out
+    pub(crate) max_file_size: u64,
+    /// Patterns of file names or paths left out
+    pub(crate) exclude: Vec<String>,
+}
+
+impl IndexSettings {
+    fn excludes(&self, relative: &str) -> bool {
+        let name = relative.rsplit('/').next().unwrap_or(relative);
+        self.exclude.iter().any(|pattern| glob_match(pattern, name) || glob_match(pattern, relative))
+    }
+}
+
+/// A parsed query: every term must appear in a matching document
+#[derive(Debug)]
+pub(crate) struct Query {
+    /// Plain words and `property:word` terms
+    terms: Vec<String>,
+}
+
+impl Query {
+    pub(crate) fn parse(query: &str) -> Self {
+        let mut terms = Vec::new();
+        for part in query.split_whitespace() {
+            match part.split_once(':') {
+                Some((property, value)) if !property.is_empty() => {
+                    let property = property.to_lowercase();
+                    terms.extend(words(value).map(|word| format!("{}:{}", property, word)));
+                }
+                _ => terms.extend(words(part)),
+            }
+        }
+        terms.dedup();
+        Self { terms }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Words to find in the body text for a snippet
+    fn plain_words(&self) -> impl Iterator<Item = &str> {
+        self.terms.iter().map(|term| term.rsplit(':').next().unwrap_or(term))
+    }
+}
+
+/// A document matching a query
+#[derive(Debug, Clone)]
+pub(crate) struct Hit {
+    /// URL path of the document
+    pub(crate) path: String,
+    pub(crate) title: String,
+    pub(crate) snippet: String,
+    pub(crate) score: f64,
+    /// Microdata item types the document holds
+    pub(crate) types: Vec<String>,
+}
+
+#[derive(Debug)]
+struct IndexedDocument {
+    title: String,
+    /// Body text with runs of white space collapsed
+    text: String,
+    types: Vec<String>,
+    modified: Option<SystemTime>,
+    /// Weighted number of words, for BM25's length normalization
+    length: f64,
+    /// Weight of each term in the document
+    terms: HashMap<String, f64>,
+}
+
+/// The index of one document root
+#[derive(Debug)]
+pub(crate) struct SearchIndex {
+    root: PathBuf,
+    /// Documents by URL path
+    documents: HashMap<String, IndexedDocument>,
+    /// URL paths of the documents holding each term
+    postings: HashMap<String, HashSet<String>>,
+    total_length: f64,
+    /// When the root was last walked for changed files
+    scanned: Option<Instant>,
+}
+
+impl SearchIndex {
+    pub(crate) fn new(root: PathBuf) -> Self {
+        Self { root, documents: HashMap::new(), postings: HashMap::new(), total_length: 0.0, scanned: None }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// When the root was last walked, if ever
+    pub(crate) fn scanned(&self) -> Option<Instant> {
+        self.scanned
+    }
+
+    /// Walk the root, indexing new and modified documents and dropping those
+    /// that are gone; returns how many documents changed
+    pub(crate) fn scan(&mut self, settings: &IndexSettings) -> usize {
+        let mut found = Vec::new();
+        collect_documents(&self.root, "", &mut found);
+
+        let mut changed = 0;
+        let mut seen = HashSet::new();
+        for (relative, path) in found {
+            if settings.excludes(&relative) {
+                continue;
+            }
+            let url_path = format!("/{}", relative);
+            let Ok(metadata) = fs::metadata(&path) else { continue };
+            if metadata.len() > settings.max_file_size {
+                continue;
+            }
+            seen.insert(url_path.clone());
+            let modified = metadata.modified().ok();
+            if self.documents.get(&url_path).is_some_and(|document| document.modified.is_some() && document.modified == modified) {
+                continue;
+            }
+            if let Ok(html) = fs::read_to_string(&path) {
+                self.insert(url_path, index_document(&html, modified));
+                changed += 1;
+            }
+        }
+
+        let gone: Vec<String> = self.documents.keys().filter(|path| !seen.contains(*path)).cloned().collect();
+        changed += gone.len();
+        for path in gone {
+            self.remove(&path);
+        }
+        self.scanned = Some(Instant::now());
+        changed
+    }
+
+    /// Index the document at a URL path again, or drop it if it is gone or
+    /// no longer indexed
+    pub(crate) fn update(&mut self, url_path: &str, settings: &IndexSettings) {
+        let url_path = if url_path.ends_with('/') { format!("{}{}", url_path, INDEX_FILE) } else { url_path.to_string() };
+        let relative = url_path.trim_start_matches('/');
+        let indexable = !relative.split('/').any(|segment| segment.is_empty() || segment.starts_with('.') || segment == "..")
+            && is_html(relative)
+            && !settings.excludes(relative);
+        let path = self.root.join(relative);
+        let metadata = fs::symlink_metadata(&path).ok().filter(|metadata| metadata.is_file());
+
+        match metadata {
+            Some(metadata) if indexable && metadata.len() <= settings.max_file_size => {
+                match fs::read_to_string(&path) {
+                    Ok(html) => self.insert(url_path, index_document(&html, metadata.modified().ok())),
+                    Err(_) => self.remove(&url_path),
+                }
+            }
+            _ => self.remove(&url_path),
+        }
+    }
+
+    /// Documents holding every term of a query, best first
+    pub(crate) fn search(&self, query: &Query) -> Vec<Hit> {
+        let Some(mut candidates) = query.terms.iter()
+            .map(|term| self.postings.get(term))
+            .collect::<Option<Vec<_>>>()
+        else {
+            return Vec::new();
+        };
+        candidates.sort_by_key(|paths| paths.len());
+        let Some((rarest, others)) = candidates.split_first() else {
+            return Vec::new();
+        };
+
+        let count = self.documents.len() as f64;
+        let average_length = if count > 0.0 { self.total_length / count } else { 0.0 };
+        let mut hits: Vec<Hit> = rarest.iter()
+            .filter(|path| others.iter().all(|paths| paths.contains(*path)))
+            .filter_map(|path| {
+                let document = self.documents.get(path)?;
+                let score = query.terms.iter()
+                    .map(|term| {
+                        let frequency = document.terms.get(term).copied().unwrap_or(0.0);
+                        let containing = self.postings.get(term).map_or(0, HashSet::len) as f64;
+                        let idf = (1.0 + (count - containing + 0.5) / (containing + 0.5)).ln();
+                        let normalization = if average_length > 0.0 { document.length / average_length } else { 1.0 };
+                        idf * frequency * (BM25_K1 + 1.0)
+                            / (frequency + BM25_K1 * (1.0 - BM25_B + BM25_B * normalization))
+                    })
+                    .sum();
+                Some(Hit {
+                    path: path.clone(),
+                    title: document.title.clone(),
+                    snippet: snippet(&document.text, query),
+                    score,
+                    types: document.types.clone(),
+                })
+            })
+            .collect();
+        hits.sort_by(|a, b| b.score.total_cmp(&a.score).then_with(|| a.path.cmp(&b.path)));
+        hits
+    }
+
+    fn insert(&mut self, url_path: String, document: IndexedDocument) {
+        self.remove(&url_path);
+        for term in document.terms.keys() {
+            self.postings.entry(term.clone()).or_default().insert(url_path.clone());
+        }
+        self.total_length += document.length;
+        self.documents.insert(url_path, document);
+    }
+
+    fn remove(&mut self, url_path: &str) {
+        let Some(document) = self.documents.remove(url_path) else { return };
+        self.total_length -= document.length;
+        for term in document.terms.keys() {
+            if let Some(paths) = self.postings.get_mut(term) {
+                paths.remove(url_path);
+                if paths.is_empty() {
+                    self.postings.remove(term);
+                }
+            }
+        }
+    }
+}
+
+/// Check a hit again against its document with the `withheld` elements
+/// taken out, returning it with its title, snippet and types as they read
+/// without them, or None if the document no longer matches the query
+///
+/// A document that cannot be read or a selector that cannot be parsed drops
+/// the hit, as what it would reveal cannot be told.
+pub(crate) fn recheck(root: &Path, hit: Hit, query: &Query, withheld: &[String]) -> Option<Hit> {
+    let html = fs::read_to_string(root.join(hit.path.trim_start_matches('/'))).ok()?;
+    let document = Document::from(html.as_str());
+    for selector in withheld {
+        document.select_matcher(&Matcher::new(selector).ok()?).remove();
+    }
+    let indexed = index_document(&document.html(), None);
+    if !query.terms.iter().all(|term| indexed.terms.contains_key(term)) {
+        return None;
+    }
+    Some(Hit {
+        snippet: snippet(&indexed.text, query),
+        title: indexed.title,
+        types: indexed.types,
+        ..hit
+    })
+}
+
+/// Lowercase words of a text
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+fn is_html(name: &str) -> bool {
+    Path::new(name).extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| HTML_EXTENSIONS.contains(&extension.to_lowercase().as_str()))
+}
+
+/// HTML files below a directory, by path relative to the root, skipping
+/// hidden files and directories and symbolic links
+fn collect_documents(directory: &Path, prefix: &str, found: &mut Vec<(String, PathBuf)>) {
+    let Ok(read_dir) = fs::read_dir(directory) else { return };
+    for child in read_dir.filter_map(|child| child.ok()) {
+        let (Ok(name), Ok(file_type)) = (child.file_name().into_string(), child.file_type()) else { continue };
+        if name.starts_with('.') {
+            continue;
+        }
+        let relative = format!("{}{}", prefix, name);
+        if file_type.is_dir() {
+            collect_documents(&child.path(), &format!("{}/", relative), found);
+        } else if file_type.is_file() && is_html(&name) {
+            found.push((relative, child.path()));
+        }
+    }
+}
+
+/// Split a document into weighted terms
+fn index_document(html: &str, modified: Option<SystemTime>) -> IndexedDocument {
+    let mut terms: HashMap<String, f64> = HashMap::new();
+    let mut add = |term: String, weight: f64| *terms.entry(term).or_default() += weight;
+
+    let document = Document::from(html);
+    let title = collapse_whitespace(&document.select("title").text());
+    document.select("script, style, template, head").remove();
+    let mut text = collapse_whitespace(&document.select("body").text());
+
+    let mut length = 0.0;
+    for word in words(&title) {
+        add(word, WEIGHT_TITLE);
+        length += WEIGHT_TITLE;
+    }
+    for word in words(&text) {
+        add(word, WEIGHT_BODY);
+        length += WEIGHT_BODY;
+    }
+
+    let mut types = Vec::new();
+    let items = MicrodataExtractor::with_settings(false, true).extract(html).unwrap_or_default();
+    for item in &items {
+        index_item(item, &mut types, &mut |term, weight| {
+            add(term, weight);
+            length += weight;
+        });
+    }
+
+    if text.len() > MAX_STORED_TEXT {
+        let mut end = MAX_STORED_TEXT;
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+        text.truncate(end);
+    }
+    IndexedDocument { title, text, types, modified, length, terms }
+}
+
+/// Add the terms of a microdata item and the items nested in it
+fn index_item(item: &MicrodataItem, types: &mut Vec<String>, add: &mut dyn FnMut(String, f64)) {
+    for item_type in item.item_type().into_iter().flat_map(str::split_whitespace) {
+        if !types.iter().any(|known| known == item_type) {
+            types.push(item_type.to_string());
+        }
+        let segment = item_type.trim_end_matches('/').rsplit(['/', '#']).next().unwrap_or(item_type);
+        for word in words(segment) {
+            add(format!("{}:{}", PREFIX_TYPE, word), WEIGHT_PROPERTY);
+        }
+    }
+    for property in item.properties() {
+        if let Some(nested) = property.as_item() {
+            index_item(nested, types, add);
+            continue;
+        }
+        let name = property.name().to_lowercase();
+        for word in words(&property.value_as_string()) {
+            add(format!("{}:{}", name, word), WEIGHT_PROPERTY);
+            add(word, WEIGHT_PROPERTY);
+        }
+    }
+}
+
+fn collapse_whitespace(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// The stretch of a document's text around the first word of a query
+fn snippet(text: &str, query: &Query) -> String {
+    let wanted: HashSet<&str> = query.plain_words().collect();
+    let mut start = None;
+    let mut word_start = None;
+    for (index, c) in text.char_indices().chain(std::iter::once((text.len(), ' '))) {
+        match (c.is_alphanumeric(), word_start) {
+            (true, None) => word_start = Some(index),
+            (false, Some(begin)) => {
+                if wanted.contains(text[begin..index].to_lowercase().as_str()) {
+                    start = Some(begin);
+                    break;
+                }
+                word_start = None;
+            }
+            _ => {}
+        }
+    }
+
+    let start = start.unwrap_or(0);
+    let from = text[..start].char_indices().rev().nth(SNIPPET_BEFORE.saturating_sub(1)).map_or(0, |(index, _)| index);
+    let to = text[start..].char_indices().nth(SNIPPET_AFTER).map_or(text.len(), |(index, _)| start + index);
+    let mut snippet = String::new();
+    if from > 0 {
+        snippet.push('…');
+    }
+    snippet.push_str(text[from..to].trim());
+    if to < text.len() {
+        snippet.push('…');
+    }
+    snippet
+}
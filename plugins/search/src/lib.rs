@@ -0,0 +1,517 @@
+//! Search Plugin for Rusty Beam
+//!
+//! This plugin keeps an inverted index of the HTML documents below the host
+//! root, their text and their microdata, and answers queries for them at a
+//! search path with ranked results.
+//!
+//! ## Features
+//! - **Full-Text Search**: Titles, body text and microdata property values
+//!   are indexed, with words in titles and properties ranked above words in
+//!   the body (see the `index` module)
+//! - **Microdata Queries**: `property:word` finds documents with the word in
+//!   a microdata property of that name, `type:segment` documents holding an
+//!   item whose type ends in that segment, e.g. `type:person`
+//! - **Incremental Updates**: Successful PUT, POST, PATCH, DELETE, COPY and
+//!   MOVE requests, and changes announced by other plugins such as the
+//!   selector-handler, re-index the documents they touched
+//! - **Access Control**: Results only name documents the requesting user
+//!   may GET, as checked by the host's access-control plugins, and documents
+//!   with elements withheld from the user only match, and are only quoted,
+//!   by what the user may read of them
+//! - **HTML or JSON**: Results are a page of `SearchResult` microdata items,
+//!   or JSON for clients asking for it
+//!
+//! ## Configuration
+//! - `search_path`: Path answering queries (default: "/search")
+//! - `root_dir`: Document root used when the host has no `hostRoot`
+//! - `default_limit`: Results per page when the query names none (default: 10)
+//! - `max_limit`: Most results per page a query may ask for (default: 100)
+//! - `rescan_interval`: Seconds after which a query first walks the document
+//!   root for files changed other than through the pipeline, 0 for never
+//!   again after the first query (default: 300)
+//! - `max_file_size`: Largest file indexed, in bytes (default: 1048576)
+//! - `exclude`: Comma-separated patterns of file names or paths never
+//!   indexed (default: "users.html, *.env, *.conf, *.key, *.pem")
+//!
+//! ## Queries
+//! `GET /search?q=...` takes these query parameters:
+//! - `q`: Words that must all appear in a result, in any letter case
+//! - `limit`, `offset`: Which page of results to return
+//! - `format`: `json` or `html`, overriding the `Accept` header
+//!
+//! The index of a document root is built by the first query and kept in
+//! memory. Hidden files and directories and symbolic links are skipped.
+//!
+//! ## Pipeline Placement
+//! Place the plugin after authentication, so results are checked for the
+//! requesting user, and before the file-handler. Writes made by plugins
+//! placed before it do not reach its response phase; they are picked up by
+//! the next rescan.
+
+mod index;
+
+use async_trait::async_trait;
+use hyper::header::{HeaderValue, ALLOW, CACHE_CONTROL, CONTENT_TYPE, VARY};
+use hyper::{Body, Method, Response, StatusCode};
+use index::{recheck, Hit, IndexSettings, Query, SearchIndex};
+use rusty_beam_plugin_api::{fs, log_warn, paths, DocumentChange, Plugin, PluginContext, PluginRequest, PluginResponse};
+use serde_json::json;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+// Default configuration values
+const DEFAULT_PLUGIN_NAME: &str = "search";
+const DEFAULT_SEARCH_PATH: &str = "/search";
+const DEFAULT_ROOT_DIR: &str = ".";
+const DEFAULT_LIMIT: usize = 10;
+const DEFAULT_MAX_LIMIT: usize = 100;
+const DEFAULT_RESCAN_INTERVAL_SECONDS: u64 = 300;
+const DEFAULT_MAX_FILE_SIZE: u64 = 1024 * 1024;
+const DEFAULT_EXCLUDE: &str = "users.html, *.env, *.conf, *.key, *.pem";
+
+const CONFIG_KEY_HOST_ROOT: &str = "hostRoot";
+
+const CONTENT_TYPE_HTML: &str = "text/html";
+const CONTENT_TYPE_JSON: &str = "application/json";
+
+/// Methods answered at the search path
+const SEARCH_METHODS: &str = "GET, HEAD, OPTIONS";
+
+/// Methods whose successful responses change the documents at their path
+const CHANGE_METHODS: &[&str] = &["PUT", "POST", "PATCH", "DELETE", "COPY", "MOVE"];
+
+const SCHEMA_SEARCH_RESULTS: &str = "https://rustybeam.net/schema/SearchResults";
+const SCHEMA_SEARCH_RESULT: &str = "https://rustybeam.net/schema/SearchResult";
+
+/// Plugin answering full-text queries over the host's documents
+#[derive(Debug)]
+pub struct SearchPlugin {
+    name: String,
+    search_path: String,
+    root_dir: String,
+    default_limit: usize,
+    max_limit: usize,
+    /// How old a walk of the root may be before a query walks it again;
+    /// None to walk it only once
+    rescan_interval: Option<Duration>,
+    settings: Arc<IndexSettings>,
+    /// Indexes by document root
+    indexes: Arc<Mutex<HashMap<PathBuf, SearchIndex>>>,
+}
+
+impl SearchPlugin {
+    pub fn new(config: HashMap<String, String>) -> Self {
+        let name = config.get("name").cloned().unwrap_or_else(|| DEFAULT_PLUGIN_NAME.to_string());
+        let number = |key: &str, default: u64| config.get(key)
+            .and_then(|value| value.trim().parse().ok())
+            .unwrap_or(default);
+        let search_path = config.get("search_path")
+            .map(|path| path.trim().to_string())
+            .filter(|path| path.starts_with('/'))
+            .unwrap_or_else(|| DEFAULT_SEARCH_PATH.to_string());
+        let exclude = config.get("exclude").map(String::as_str).unwrap_or(DEFAULT_EXCLUDE)
+            .split(',')
+            .map(|pattern| pattern.trim().to_string())
+            .filter(|pattern| !pattern.is_empty())
+            .collect();
+        let rescan_interval = number("rescan_interval", DEFAULT_RESCAN_INTERVAL_SECONDS);
+        let max_limit = (number("max_limit", DEFAULT_MAX_LIMIT as u64) as usize).max(1);
+
+        Self {
+            search_path,
+            root_dir: config.get("root_dir").cloned().unwrap_or_else(|| DEFAULT_ROOT_DIR.to_string()),
+            default_limit: (number("default_limit", DEFAULT_LIMIT as u64) as usize).clamp(1, max_limit),
+            max_limit,
+            rescan_interval: (rescan_interval > 0).then(|| Duration::from_secs(rescan_interval)),
+            settings: Arc::new(IndexSettings {
+                max_file_size: number("max_file_size", DEFAULT_MAX_FILE_SIZE),
+                exclude,
+            }),
+            indexes: Arc::new(Mutex::new(HashMap::new())),
+            name,
+        }
+    }
+
+    /// The document root of the host a request is for
+    fn root(&self, context: &PluginContext) -> PathBuf {
+        let root = context.host_config.get(CONFIG_KEY_HOST_ROOT).unwrap_or(&self.root_dir);
+        PathBuf::from(root.strip_prefix("file://").unwrap_or(root))
+    }
+
+    /// Run a query against the index of the host's root, building the index
+    /// or walking the root again first if it is due
+    async fn search(&self, query: Query, context: &PluginContext) -> std::io::Result<Vec<Hit>> {
+        let root = self.root(context);
+        let indexes = Arc::clone(&self.indexes);
+        let settings = Arc::clone(&self.settings);
+        let rescan_interval = self.rescan_interval;
+        let (hits, scan) = fs::blocking(context, move || {
+            let mut indexes = indexes.lock().unwrap_or_else(|e| e.into_inner());
+            let index = indexes.entry(root.clone()).or_insert_with(|| SearchIndex::new(root.clone()));
+            let due = match index.scanned() {
+                None => true,
+                Some(scanned) => rescan_interval.is_some_and(|interval| scanned.elapsed() >= interval),
+            };
+            let scan = due.then(|| (index.scan(&settings), index.len()));
+            Ok((index.search(&query), scan))
+        }).await?;
+        if let Some((changed, documents)) = scan {
+            context.log_verbose(&format!("[Search] Indexed {} ({} documents, {} changed)", self.root(context).display(), documents, changed));
+        }
+        Ok(hits)
+    }
+
+    /// The hits the requesting user may see, as they may see them
+    ///
+    /// Hits on documents the user may not GET are dropped. Those on documents
+    /// with elements withheld from the user are checked again without them.
+    async fn visible_hits(&self, hits: Vec<Hit>, text: &str, request: &PluginRequest, context: &PluginContext) -> std::io::Result<Vec<Hit>> {
+        let Some(services) = context.services.as_ref() else {
+            return Ok(hits);
+        };
+        let user = request.get_metadata("authenticated_user");
+        let hits: Vec<(Hit, Vec<String>)> = hits.into_iter()
+            .filter(|hit| services.is_allowed(user, "GET", &hit.path, context))
+            .map(|hit| {
+                let withheld = services.withheld_selectors(user, &hit.path, context);
+                (hit, withheld)
+            })
+            .collect();
+        if hits.iter().all(|(_, withheld)| withheld.is_empty()) {
+            return Ok(hits.into_iter().map(|(hit, _)| hit).collect());
+        }
+
+        let root = self.root(context);
+        let query = Query::parse(text);
+        fs::blocking(context, move || {
+            Ok(hits.into_iter()
+                .filter_map(|(hit, withheld)| if withheld.is_empty() {
+                    Some(hit)
+                } else {
+                    recheck(&root, hit, &query, &withheld)
+                })
+                .collect())
+        }).await
+    }
+
+    /// Index the document at a URL path again, if its root has an index yet
+    async fn reindex(&self, url_paths: Vec<String>, context: &PluginContext) {
+        let root = self.root(context);
+        let indexes = Arc::clone(&self.indexes);
+        let settings = Arc::clone(&self.settings);
+        let result = fs::blocking(context, move || {
+            let mut indexes = indexes.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(index) = indexes.get_mut(&root) {
+                for url_path in &url_paths {
+                    index.update(url_path, &settings);
+                }
+            }
+            Ok(())
+        }).await;
+        if let Err(e) = result {
+            log_warn!(self.name, "Failed to update the search index: {}", e);
+        }
+    }
+
+    async fn handle_search(&self, request: &PluginRequest, context: &PluginContext) -> Response<Body> {
+        let method = request.http_request.method();
+        if method == Method::OPTIONS {
+            return Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .header(ALLOW, SEARCH_METHODS)
+                .body(Body::empty())
+                .unwrap();
+        }
+        if method != Method::GET && method != Method::HEAD {
+            return Response::builder()
+                .status(StatusCode::METHOD_NOT_ALLOWED)
+                .header(ALLOW, SEARCH_METHODS)
+                .header(CONTENT_TYPE, "text/plain; charset=utf-8")
+                .body(Body::from("Method not allowed"))
+                .unwrap();
+        }
+
+        let params: HashMap<String, String> = request.http_request.uri().query()
+            .map(|query| url::form_urlencoded::parse(query.as_bytes()).into_owned().collect())
+            .unwrap_or_default();
+        let json = match params.get("format").map(String::as_str) {
+            Some("json") => true,
+            Some("html") => false,
+            _ => request.prefers_json(),
+        };
+        let text = params.get("q").map(|q| q.trim().to_string()).unwrap_or_default();
+        let query = Query::parse(&text);
+        if query.is_empty() {
+            return error_response(StatusCode::BAD_REQUEST, "Missing search query: add ?q=words", json);
+        }
+        let limit = params.get("limit")
+            .and_then(|limit| limit.parse::<usize>().ok())
+            .unwrap_or(self.default_limit)
+            .clamp(1, self.max_limit);
+        let offset = params.get("offset").and_then(|offset| offset.parse::<usize>().ok()).unwrap_or(0);
+
+        let hits = match self.search(query, context).await {
+            Ok(hits) => hits,
+            Err(e) => {
+                log_warn!(self.name, "Search failed: {}", e);
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Search failed", json);
+            }
+        };
+        let hits = match self.visible_hits(hits, &text, request, context).await {
+            Ok(hits) => hits,
+            Err(e) => {
+                log_warn!(self.name, "Search failed: {}", e);
+                return error_response(StatusCode::INTERNAL_SERVER_ERROR, "Search failed", json);
+            }
+        };
+        context.log_verbose(&format!("[Search] {} results for {:?}", hits.len(), text));
+
+        let page = Page { query: &text, total: hits.len(), offset, limit, hits: hits.iter().skip(offset).take(limit).collect() };
+        let (content_type, body) = if json {
+            (CONTENT_TYPE_JSON, page.to_json(&self.search_path))
+        } else {
+            (CONTENT_TYPE_HTML, page.to_html(&self.search_path))
+        };
+        let length = body.len();
+        let body = if method == Method::HEAD { Body::empty() } else { Body::from(body) };
+        Response::builder()
+            .status(StatusCode::OK)
+            .header(CONTENT_TYPE, format!("{}; charset=utf-8", content_type))
+            .header("Content-Length", length)
+            // Results depend on who asks
+            .header(CACHE_CONTROL, "private, no-cache")
+            .header(VARY, "Accept")
+            .body(body)
+            .unwrap()
+    }
+}
+
+#[async_trait]
+impl Plugin for SearchPlugin {
+    async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
+        if request.path != self.search_path {
+            return None;
+        }
+        Some(self.handle_search(request, context).await.into())
+    }
+
+    async fn handle_response(&self, request: &PluginRequest, response: &mut Response<Body>, context: &PluginContext) {
+        let method = request.http_request.method().as_str();
+        if !CHANGE_METHODS.contains(&method) || !response.status().is_success() {
+            return;
+        }
+        let mut changed = vec![request.path.clone()];
+        changed.extend(destination_path(request));
+        self.reindex(changed, context).await;
+    }
+
+    fn on_change(&self, change: &DocumentChange, context: &PluginContext) {
+        let root = self.root(context);
+        let mut indexes = self.indexes.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(index) = indexes.get_mut(&root) {
+            index.update(&change.path, &self.settings);
+        }
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// One page of the results of a query
+struct Page<'a> {
+    query: &'a str,
+    /// Results the user may see, on every page
+    total: usize,
+    offset: usize,
+    limit: usize,
+    hits: Vec<&'a Hit>,
+}
+
+impl Page<'_> {
+    fn to_json(&self, search_path: &str) -> String {
+        let results: Vec<_> = self.hits.iter()
+            .map(|hit| json!({
+                "url": hit.path,
+                "title": hit.title,
+                "snippet": hit.snippet,
+                "score": hit.score,
+                "types": hit.types,
+            }))
+            .collect();
+        let mut body = json!({
+            "query": self.query,
+            "total": self.total,
+            "offset": self.offset,
+            "limit": self.limit,
+            "results": results,
+        });
+        if let Some(next) = self.next_offset() {
+            body["next"] = json!(self.page_url(search_path, next));
+        }
+        body.to_string()
+    }
+
+    fn to_html(&self, search_path: &str) -> String {
+        let query = escape_html(self.query);
+        let mut html = format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Search: {query}</title>\n</head>\n<body>\n\
+             <form action=\"{action}\" method=\"get\"><input type=\"search\" name=\"q\" value=\"{query}\"> <button>Search</button></form>\n\
+             <section itemscope itemtype=\"{SCHEMA_SEARCH_RESULTS}\">\n\
+             <p><data itemprop=\"total\" value=\"{total}\">{total}</data> results for <q itemprop=\"query\">{query}</q></p>\n<ol start=\"{start}\">\n",
+            action = escape_html(search_path),
+            total = self.total,
+            start = self.offset + 1,
+        );
+        for hit in &self.hits {
+            let title = if hit.title.is_empty() { hit.path.as_str() } else { hit.title.as_str() };
+            html.push_str(&format!(
+                "<li itemprop=\"result\" itemscope itemtype=\"{SCHEMA_SEARCH_RESULT}\">\n\
+                 <a itemprop=\"url\" href=\"{url}\"><span itemprop=\"name\">{title}</span></a>\n\
+                 <p itemprop=\"snippet\">{snippet}</p>\n<data itemprop=\"score\" value=\"{score:.4}\"></data>\n</li>\n",
+                url = escape_html(&hit.path),
+                title = escape_html(title),
+                snippet = escape_html(&hit.snippet),
+                score = hit.score,
+            ));
+        }
+        html.push_str("</ol>\n");
+        if let Some(next) = self.next_offset() {
+            html.push_str(&format!("<a rel=\"next\" href=\"{}\">Next results</a>\n", escape_html(&self.page_url(search_path, next))));
+        }
+        html.push_str("</section>\n</body>\n</html>\n");
+        html
+    }
+
+    fn next_offset(&self) -> Option<usize> {
+        let next = self.offset + self.limit;
+        (next < self.total).then_some(next)
+    }
+
+    fn page_url(&self, search_path: &str, offset: usize) -> String {
+        let query = url::form_urlencoded::Serializer::new(String::new())
+            .append_pair("q", self.query)
+            .append_pair("offset", &offset.to_string())
+            .append_pair("limit", &self.limit.to_string())
+            .finish();
+        format!("{}?{}", search_path, query)
+    }
+}
+
+fn error_response(status: StatusCode, message: &str, json: bool) -> Response<Body> {
+    let (content_type, body) = if json {
+        (CONTENT_TYPE_JSON, json!({ "error": message }).to_string())
+    } else {
+        ("text/plain", message.to_string())
+    };
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, format!("{}; charset=utf-8", content_type))
+        .header(VARY, HeaderValue::from_static("Accept"))
+        .body(Body::from(body))
+        .unwrap()
+}
+
+/// The normalized path of a COPY or MOVE destination
+fn destination_path(request: &PluginRequest) -> Option<String> {
+    let destination = request.http_request.headers().get("Destination")?.to_str().ok()?;
+    let uri: hyper::Uri = destination.parse().ok()?;
+    paths::normalize_request_path(uri.path()).ok()
+}
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+// Export the plugin creation function; tests link the directory plugin,
+// which exports the same symbols
+#[cfg(not(test))]
+rusty_beam_plugin_api::create_plugin!(SearchPlugin);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use directory::DirectoryPlugin;
+    use rusty_beam_plugin_api::PipelineServices;
+
+    /// An access-control stand-in that withholds salaries from everyone
+    /// except carol
+    #[derive(Debug)]
+    struct Redactor;
+
+    #[async_trait]
+    impl Plugin for Redactor {
+        fn withheld_selectors(&self, user: Option<&str>, _path: &str, _context: &PluginContext) -> Vec<String> {
+            if user == Some("carol") {
+                Vec::new()
+            } else {
+                vec![".salary".to_string()]
+            }
+        }
+
+        fn name(&self) -> &str {
+            "redactor"
+        }
+    }
+
+    fn create_test_context(root: &std::path::Path, plugins: Vec<Arc<dyn Plugin>>) -> PluginContext {
+        PluginContext {
+            plugin_config: HashMap::new(),
+            server_config: HashMap::new(),
+            server_metadata: HashMap::new(),
+            host_config: HashMap::from([(CONFIG_KEY_HOST_ROOT.to_string(), root.to_string_lossy().to_string())]),
+            host_name: "test-host".to_string(),
+            request_id: "test-request".to_string(),
+            runtime_handle: None,
+            verbose: false,
+            services: Some(Arc::new(PipelineServices::new(plugins))),
+            deadline: None,
+        }
+    }
+
+    async fn search(plugin: &SearchPlugin, query: &str, user: &str, context: &PluginContext) -> serde_json::Value {
+        let uri = format!("/search?format=json&q={}", query);
+        let http_request = hyper::Request::builder().uri(uri.as_str()).body(Body::empty()).unwrap();
+        let mut request = PluginRequest::new(http_request, "/search".to_string());
+        request.metadata.insert("authenticated_user".to_string(), user.to_string());
+        let response = plugin.handle_request(&mut request, context).await.unwrap().response;
+        assert_eq!(response.status(), StatusCode::OK);
+        serde_json::from_slice(&hyper::body::to_bytes(response.into_body()).await.unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_authorization_nested_in_a_directory_withholds_from_results() {
+        let root = std::env::temp_dir().join(format!("rusty-beam-search-nested-{}", std::process::id()));
+        std::fs::create_dir_all(root.join("staff")).unwrap();
+        std::fs::write(root.join("staff/bob.html"), r#"<html><body><p>Bob works here</p> <p class="salary">Bob earns plenty</p></body></html>"#).unwrap();
+        std::fs::write(root.join("about.html"), r#"<html><body><p>About plenty</p> <p class="salary">Not in the directory</p></body></html>"#).unwrap();
+        let staff = DirectoryPlugin::new_with_nested_plugins(
+            HashMap::from([("directory".to_string(), "/staff".to_string())]),
+            vec![Arc::new(Redactor)],
+        );
+        let context = create_test_context(&root, vec![Arc::new(staff)]);
+        let plugin = SearchPlugin::new(HashMap::new());
+
+        let urls = |results: &serde_json::Value| -> Vec<String> {
+            results["results"].as_array().unwrap().iter()
+                .map(|result| result["url"].as_str().unwrap().to_string())
+                .collect()
+        };
+        // Withheld elements neither match nor are quoted inside the directory
+        let results = search(&plugin, "plenty", "alice", &context).await;
+        assert_eq!(urls(&results), ["/about.html"]);
+        let results = search(&plugin, "bob", "alice", &context).await;
+        assert_eq!(urls(&results), ["/staff/bob.html"]);
+        assert!(!results["results"][0]["snippet"].as_str().unwrap().contains("earns"));
+        // Users the nested plugin withholds nothing from see everything
+        let results = search(&plugin, "plenty", "carol", &context).await;
+        assert_eq!(urls(&results).len(), 2);
+
+        std::fs::remove_dir_all(&root).ok();
+    }
+}
@@ -3,19 +3,24 @@
 //! This plugin implements the core CSS selector-based HTML manipulation functionality
 //! that makes Rusty Beam unique. It intercepts HTTP requests with Range headers
 //! containing CSS selectors and performs targeted operations on HTML elements.
+//! XML and Markdown files are edited through the same protocol.
 //!
 //! ## Features
 //! - **CSS Selector Support**: Use any valid CSS selector to target HTML elements
-//! - **Multiple Operations**: GET (retrieve), PUT (replace), POST (append), DELETE (remove)
+//! - **XML and Markdown**: XPath or CSS selectors on `.xml` files, CSS selectors on `.md` files
+//! - **Multiple Operations**: GET (retrieve), PUT (replace), POST (append), PATCH (edit), DELETE (remove)
 //! - **Special Element Handling**: Preserves structure for table, list, and body elements
 //! - **Security**: Path traversal protection and file validation
 //! - **Metadata Propagation**: Shares operation details with other plugins (e.g., WebSocket)
+//! - **Microdata Index**: Lists the microdata items of a document as JSON-LD
 //!
 //! ## HTTP Methods
 //! - **GET**: Retrieve the HTML content of elements matching the selector
 //! - **PUT**: Replace the content of matching elements with request body
 //! - **POST**: Append content to matching elements
+//! - **PATCH**: Apply structured DOM operations from a JSON body to the matching element
 //! - **DELETE**: Remove matching elements from the document
+//! - **OPTIONS**: Describe the selector capabilities of the target resource
 //!
 //! ## Range Header Format
 //! ```
@@ -23,6 +28,108 @@
 //! ```
 //! The selector value should be URL-encoded if it contains special characters.
 //!
+//! A GET returns the first matching element. To page through the matches,
+//! follow the selector with `start` (counting from 0) and `count`
+//! parameters, e.g. `Range: selector=li;start=20;count=10`. The matches in
+//! the page are returned one per line, and `Content-Range` gives their
+//! positions and the total number of matches, e.g. `selector li 20-29/57`.
+//! A page past the last match is answered with `416 Range Not Satisfiable`
+//! and a `Content-Range` of `selector li */57`. Other methods always apply
+//! to every match and refuse paging parameters with `400 Bad Request`.
+//!
+//! ## PATCH Operations
+//! The PATCH body is a JSON operation object or an array of them, applied in
+//! order to the first element matching the selector:
+//! - `{"op": "set-attribute", "name": "class", "value": "done"}`
+//! - `{"op": "remove-attribute", "name": "hidden"}`
+//! - `{"op": "insert-before", "html": "<li>New</li>"}`
+//! - `{"op": "insert-after", "html": "<li>New</li>"}`
+//! - `{"op": "wrap", "html": "<div class=\"card\"></div>"}`
+//! - `{"op": "unwrap"}` replaces the element with its children and must be last
+//! - `{"op": "set-text", "text": "Plain text content"}`
+//!
+//! The response contains the element's HTML after the operations are applied.
+//!
+//! ## XML and Markdown Documents
+//! Files ending in `.xml` are parsed as XML, so element names keep their case
+//! and no HTML structure is added. Selectors may be CSS or, when they start
+//! with `/`, a simple XPath location path such as `/rss/channel/item[2]` or
+//! `//entry[@id='a']`, which is mapped onto CSS. Request bodies and PATCH
+//! fragments must be well-formed XML, and responses are `application/xml`.
+//!
+//! Files ending in `.md` or `.markdown` are converted to HTML, the selector
+//! is applied to the resulting DOM, and the file is written back as Markdown.
+//! PUT and POST bodies are Markdown, responses are `text/markdown`, and the
+//! fragments in PATCH operations are HTML. List items in a body join the
+//! target list rather than nesting a new one. Formatting is normalized on
+//! write, e.g. `*emphasis*` becomes `_emphasis_`.
+//!
+//! Any other file type answers selector requests with `416 Range Not Satisfiable`.
+//!
+//! ## Previous Content
+//! A PUT response also returns the element it replaced, so editors can offer
+//! undo. The `previous_content` configuration chooses how:
+//! - `header` (default): percent-encoded in the `X-Previous-Content` header
+//! - `multipart`: a `multipart/mixed` body whose parts, named `current` and
+//!   `previous`, hold the new and the replaced element; suits large elements
+//!   that would exceed header size limits
+//! - `none`: the body is the new element only
+//!
+//! Either way the replaced element is stored in the `previous_content` request
+//! metadata for plugins that report changes.
+//!
+//! ## Conditional Writes
+//! A selector GET carries an `ETag` computed from the matched element, or
+//! from the page of matches when paging, so it changes whenever the element
+//! does. A PUT, POST, PATCH or DELETE with an `If-Match` header is only made
+//! if the first element its selector matches still has one of the listed
+//! ETags, or if the header is `*`; otherwise it is answered with
+//! `412 Precondition Failed` and the element's current ETag, so two people
+//! editing one element cannot silently overwrite each other. PUT, POST and
+//! PATCH responses carry the ETag of the element as it was written, ready
+//! for the next edit. Selector writes to a file are made one at a time, so
+//! the check and the write cannot be interleaved with another selector write.
+//!
+//! ## Capability Discovery
+//! An OPTIONS request with a selector Range lets clients feature-detect the
+//! selector protocol per resource without modifying it. For an HTML, XML or
+//! Markdown file the response carries `Accept-Ranges: selector`, the selector
+//! methods in `Allow` and `Accept-Patch: application/json`. For any other file
+//! it carries `Accept-Ranges: none` and only `OPTIONS` is allowed. Either way
+//! the `X-Selector-Target` header reports `html`, `xml`, `markdown` or `non-html`.
+//!
+//! ## Allowed Methods
+//! The `allowed_methods` configuration restricts the selector methods served
+//! to a comma-separated list, e.g. `GET` for a read-only site; OPTIONS is
+//! always allowed. A selector request with any other method, including a
+//! microdata GET when GET is left out, is answered with
+//! `405 Method Not Allowed` before the file is touched, and its `Allow`
+//! header, like the one of OPTIONS, lists only the allowed methods. Requests
+//! without a selector are passed on as usual. To allow writes under one path
+//! only, configure the top-level plugin with `allowed_methods` set to `GET`
+//! and place a directory plugin for that path, e.g. `/drafts`, before it,
+//! with a nested selector handler allowing `GET, PUT, POST, PATCH, DELETE`.
+//!
+//! ## Microdata Index
+//! A GET with a `microdata` query parameter, e.g. `/people.html?microdata`,
+//! answers with the microdata items of an HTML document as JSON-LD (see the
+//! `microdata` module), so applications storing data in pages can read it
+//! without selectors. `itemtype=<url>` keeps only the top-level items of
+//! that type, and a selector Range only the items within the matched
+//! elements. Other file types are answered with `406 Not Acceptable`.
+//!
+//! ## Selector Syntax
+//! Standard CSS selectors are supported, including `:not()` and `:has()`. In
+//! addition, the following microdata-aware pseudo-selectors are translated to
+//! attribute selectors before matching:
+//! - `:itemscope` becomes `[itemscope]`
+//! - `:itemtype("https://schema.org/Person")` becomes `[itemtype~="https://schema.org/Person"]`
+//! - `:itemprop("email")` becomes `[itemprop~="email"]`
+//! - `:itemid("urn:isbn:0-330-34032-8")` becomes `[itemid="urn:isbn:0-330-34032-8"]`
+//!
+//! Selectors that cannot be parsed are rejected with `400 Bad Request` and a
+//! message describing the parse error.
+//!
 //! ## Examples
 //! ```bash
 //! # Get content of element with id="header"
@@ -34,10 +141,36 @@
 //! # Append to element with class="content"
 //! curl -X POST -H "Range: selector=.content" -d "<p>New paragraph</p>" http://localhost:3000/page.html
 //!
+//! # Mark the first task as done
+//! curl -X PATCH -H "Range: selector=li.task" -H "Content-Type: application/json" \
+//!      -d '{"op": "set-attribute", "name": "class", "value": "task done"}' http://localhost:3000/tasks.html
+//!
 //! # Delete all elements with class="temporary"
 //! curl -X DELETE -H "Range: selector=.temporary" http://localhost:3000/page.html
+//!
+//! # Check whether a resource supports selector operations
+//! curl -X OPTIONS -I -H "Range: selector=body" http://localhost:3000/page.html
+//!
+//! # Get the second item of an RSS feed using XPath
+//! curl -H "Range: selector=/rss/channel/item[2]" http://localhost:3000/feed.xml
+//!
+//! # Replace the first heading of a Markdown document
+//! curl -X PUT -H "Range: selector=h1" -d "# New Title" http://localhost:3000/README.md
+//!
+//! # List the Person items of a page as JSON-LD
+//! curl "http://localhost:3000/people.html?microdata&itemtype=https://schema.org/Person"
+//!
+//! # Get the email of every Person item
+//! curl -H 'Range: selector=:itemtype("https://schema.org/Person") :itemprop("email")' http://localhost:3000/people.html
 //! ```
 //!
+//! ## Template Documents
+//! With `template` and `template_paths` configured, the paths matching
+//! `template_paths` (e.g. `/pages/*.html`) are documents composed from the
+//! template and fragment files rather than files of their own. A GET or HEAD
+//! without a selector returns the composition, and selector writes are split
+//! back into the fragments they changed; see the `templates` module.
+//!
 //! ## Special Element Handling
 //! The plugin uses marker-based replacement for elements that require special
 //! handling to preserve HTML structure integrity (tables, lists, body, etc.).
@@ -46,20 +179,30 @@
 //! - Works with file-handler plugin for serving HTML files
 //! - Provides metadata for websocket plugin to broadcast changes
 //! - Respects security settings from authorization plugins
+//! - Serves selector operations other plugins make through
+//!   `HostServices::apply_selector`, such as the document API of server-side scripts
 
-use rusty_beam_plugin_api::{Plugin, PluginRequest, PluginContext, PluginResponse, create_plugin};
+use rusty_beam_plugin_api::{fs, log_error, paths, DocumentChange, Plugin, PluginRequest, PluginContext, PluginResponse, SelectorError, SelectorOperation, create_plugin};
 use async_trait::async_trait;
-use hyper::{Body, Response, StatusCode, Method, header::RANGE};
+use tokio::sync::Mutex;
+use hyper::{Body, Response, StatusCode, Method, header::{HeaderValue, ETAG, IF_MATCH, RANGE}};
+use sha2::{Digest, Sha256};
 use std::collections::HashMap;
 use std::path::Path;
-use std::fs;
-use dom_query::Document;
+use dom_query::{Document, Matcher, NodeRef, Selection};
+use pulldown_cmark::{Options, Parser};
 use regex::Regex;
+use serde::Deserialize;
+
+mod microdata;
+mod templates;
+mod xml;
+
+use templates::Templates;
 
 // Constants
 const DEFAULT_PLUGIN_NAME: &str = "selector-handler";
 const DEFAULT_ROOT_DIR: &str = ".";
-const INDEX_FILE_NAME: &str = "index.html";
 const MARKER_PREFIX: &str = "__RUSTY_BEAM_";
 const MARKER_SUFFIX: &str = "_MARKER_";
 
@@ -68,26 +211,238 @@ const ERROR_NO_ELEMENTS_MATCHED: &str = "No elements matched the selector";
 const ERROR_FILE_NOT_FOUND: &str = "File not found";
 const ERROR_ACCESS_DENIED: &str = "Access denied";
 const ERROR_INVALID_REQUEST_BODY: &str = "Invalid request body";
-const ERROR_RANGE_NOT_SATISFIABLE: &str = "Range Not Satisfiable: CSS selectors can only be used with HTML files";
+const ERROR_RANGE_NOT_SATISFIABLE: &str = "Range Not Satisfiable: selectors can only be used with HTML, XML and Markdown files";
 const ERROR_METHOD_NOT_ALLOWED: &str = "Method not allowed for selector operations";
+const ERROR_INVALID_SELECTOR: &str = "Invalid selector";
+const ERROR_INVALID_PATCH: &str = "Invalid PATCH operations";
+const ERROR_OPERATION_AFTER_UNWRAP: &str = "unwrap must be the last operation";
+const ERROR_MICRODATA_NOT_HTML: &str = "Microdata can only be extracted from HTML files";
+const ERROR_MICRODATA_EXTRACTION: &str = "Failed to extract microdata";
+const ERROR_PAGING_GET_ONLY: &str = "start and count can only be used with GET";
+const ERROR_PAGE_NOT_SATISFIABLE: &str = "Range Not Satisfiable: no matched elements in the requested page";
+const ERROR_PRECONDITION_FAILED: &str = "Precondition Failed: the element has changed";
+
+// Element ETags
+const IF_MATCH_ANY: &str = "*";
+/// Bytes of the element's SHA-256 digest kept in its ETag
+const ETAG_DIGEST_BYTES: usize = 16;
+
+// Microdata pseudo-selectors, e.g. :itemprop("email")
+const MICRODATA_PSEUDO_PATTERN: &str = r#":(itemtype|itemprop|itemid)\(\s*(?:"([^"]*)"|'([^']*)'|([^\s)]*))\s*\)"#;
+const ITEMSCOPE_PSEUDO: &str = ":itemscope";
 
 // Content types
 const CONTENT_TYPE_HTML: &str = "text/html";
+const CONTENT_TYPE_XML: &str = "application/xml";
+const CONTENT_TYPE_MARKDOWN: &str = "text/markdown";
 const CONTENT_TYPE_PLAIN: &str = "text/plain";
+const CONTENT_TYPE_JSON: &str = "application/json";
+const CONTENT_TYPE_JSON_LD: &str = "application/ld+json";
+
+// Selector methods other than OPTIONS, which is always allowed
+const SELECTOR_OPERATION_METHODS: &[Method] = &[Method::GET, Method::PUT, Method::POST, Method::PATCH, Method::DELETE];
+const CONFIG_KEY_ALLOWED_METHODS: &str = "allowed_methods";
+
+// Capability headers for OPTIONS
+const NON_HTML_METHODS: &str = "OPTIONS";
+const ACCEPT_RANGES_SELECTOR: &str = "selector";
+const ACCEPT_RANGES_NONE: &str = "none";
+const HEADER_SELECTOR_TARGET: &str = "X-Selector-Target";
+const SELECTOR_TARGET_HTML: &str = "html";
+const SELECTOR_TARGET_XML: &str = "xml";
+const SELECTOR_TARGET_MARKDOWN: &str = "markdown";
+const SELECTOR_TARGET_NON_HTML: &str = "non-html";
+
+// Methods whose successful requests change the document
+const CHANGE_METHODS: &[Method] = &[Method::PUT, Method::POST, Method::PATCH, Method::DELETE];
+
+// Previous content of a PUT
+const CONFIG_KEY_PREVIOUS_CONTENT: &str = "previous_content";
+const HEADER_PREVIOUS_CONTENT: &str = "X-Previous-Content";
+const METADATA_PREVIOUS_CONTENT: &str = "previous_content";
+const MULTIPART_BOUNDARY_PREFIX: &str = "rusty-beam-previous-";
+const CONTENT_TYPE_MULTIPART_MIXED: &str = "multipart/mixed";
+
+// Paging parameters following the selector in a Range header
+const PARAMETER_START: &str = "start";
+const PARAMETER_COUNT: &str = "count";
+
+// Microdata index
+const QUERY_MICRODATA: &str = "microdata";
+const QUERY_ITEMTYPE: &str = "itemtype";
+
+/// A structured document format the selector protocol can edit
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum DocumentFormat {
+    Html,
+    Xml,
+    Markdown,
+}
+
+impl DocumentFormat {
+    /// Determine the format from a file's extension
+    fn from_path(path: &str) -> Option<Self> {
+        let extension = Path::new(path).extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "html" | "htm" => Some(Self::Html),
+            "xml" => Some(Self::Xml),
+            "md" | "markdown" => Some(Self::Markdown),
+            _ => None,
+        }
+    }
+    
+    fn content_type(self) -> &'static str {
+        match self {
+            Self::Html => CONTENT_TYPE_HTML,
+            Self::Xml => CONTENT_TYPE_XML,
+            Self::Markdown => CONTENT_TYPE_MARKDOWN,
+        }
+    }
+    
+    fn selector_target(self) -> &'static str {
+        match self {
+            Self::Html => SELECTOR_TARGET_HTML,
+            Self::Xml => SELECTOR_TARGET_XML,
+            Self::Markdown => SELECTOR_TARGET_MARKDOWN,
+        }
+    }
+}
+
+/// How a PUT response returns the element it replaced
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+enum PreviousContentMode {
+    /// Percent-encoded in the `X-Previous-Content` header
+    #[default]
+    Header,
+    /// As the second part of a `multipart/mixed` body
+    Multipart,
+    /// Not returned, only recorded in the request metadata
+    None,
+}
+
+impl std::str::FromStr for PreviousContentMode {
+    type Err = String;
+    
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "header" => Ok(Self::Header),
+            "multipart" => Ok(Self::Multipart),
+            "none" => Ok(Self::None),
+            other => Err(format!("Unknown previous_content mode '{}'", other)),
+        }
+    }
+}
+
+/// A structured DOM operation carried in a PATCH body
+#[derive(Debug, Deserialize)]
+#[serde(tag = "op", rename_all = "kebab-case")]
+enum PatchOperation {
+    SetAttribute { name: String, value: String },
+    RemoveAttribute { name: String },
+    InsertBefore { html: String },
+    InsertAfter { html: String },
+    Wrap { html: String },
+    Unwrap,
+    SetText { text: String },
+}
+
+/// Part of the matched elements a GET asks for with `start` and `count`
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Paging {
+    start: Option<usize>,
+    count: Option<usize>,
+}
+
+impl Paging {
+    fn is_requested(&self) -> bool {
+        self.start.is_some() || self.count.is_some()
+    }
+    
+    /// The matches in the page, if it holds any of `total` matches
+    fn window(&self, total: usize) -> Option<std::ops::Range<usize>> {
+        let start = self.start.unwrap_or(0);
+        let end = self.count.map_or(total, |count| start.saturating_add(count).min(total));
+        (start < end).then_some(start..end)
+    }
+}
 
 /// Plugin for CSS selector-based HTML manipulation
 #[derive(Debug)]
 pub struct SelectorHandlerPlugin {
     name: String,
     root_dir: String,
+    previous_content: PreviousContentMode,
+    /// Selector methods served, besides OPTIONS
+    allowed_methods: Vec<Method>,
+    /// Held from reading a file to writing it back, so conditional writes
+    /// check the content they replace
+    write_lock: Mutex<()>,
+    /// Paths served as a template filled with fragment files
+    templates: Option<Templates>,
 }
 
 impl SelectorHandlerPlugin {
     pub fn new(config: HashMap<String, String>) -> Self {
         let name = config.get("name").cloned().unwrap_or_else(|| DEFAULT_PLUGIN_NAME.to_string());
         let root_dir = config.get("root_dir").cloned().unwrap_or_else(|| DEFAULT_ROOT_DIR.to_string());
+        let previous_content = match config.get(CONFIG_KEY_PREVIOUS_CONTENT).map(|value| value.parse()) {
+            Some(Ok(mode)) => mode,
+            Some(Err(message)) => {
+                log_error!(name, "{}, using header", message);
+                PreviousContentMode::default()
+            }
+            None => PreviousContentMode::default(),
+        };
+        let allowed_methods = match config.get(CONFIG_KEY_ALLOWED_METHODS) {
+            Some(value) => Self::parse_allowed_methods(&name, value),
+            None => SELECTOR_OPERATION_METHODS.to_vec(),
+        };
+        
+        let templates = Templates::from_config(&name, &config);
         
-        Self { name, root_dir }
+        Self { name, root_dir, previous_content, allowed_methods, write_lock: Mutex::new(()), templates }
+    }
+    
+    /// The selector methods a comma-separated `allowed_methods` value lists,
+    /// in their usual order; unknown methods are logged and left out
+    fn parse_allowed_methods(name: &str, value: &str) -> Vec<Method> {
+        let listed: Vec<String> = value.split(',')
+            .map(|method| method.trim().to_uppercase())
+            .filter(|method| !method.is_empty())
+            .collect();
+        for method in &listed {
+            if method != Method::OPTIONS.as_str() && !SELECTOR_OPERATION_METHODS.iter().any(|known| known.as_str() == method) {
+                log_error!(name, "Unknown method '{}' in {}, ignoring it", method, CONFIG_KEY_ALLOWED_METHODS);
+            }
+        }
+        SELECTOR_OPERATION_METHODS.iter()
+            .filter(|method| listed.iter().any(|listed| listed == method.as_str()))
+            .cloned()
+            .collect()
+    }
+    
+    /// Whether selector requests with a method are served
+    fn is_method_allowed(&self, method: &Method) -> bool {
+        method == Method::OPTIONS || self.allowed_methods.contains(method)
+    }
+    
+    /// The `Allow` header of selector resources: the allowed methods and OPTIONS
+    fn allow_header(&self) -> String {
+        self.allowed_methods.iter()
+            .chain(std::iter::once(&Method::OPTIONS))
+            .map(Method::as_str)
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+    
+    /// Refuse a selector request whose method isn't allowed
+    fn method_not_allowed(&self) -> Response<Body> {
+        Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header("Allow", self.allow_header())
+            .header("Content-Type", CONTENT_TYPE_PLAIN)
+            .body(Body::from(ERROR_METHOD_NOT_ALLOWED))
+            .unwrap()
     }
     
     /// Handle special HTML elements that require preservation of structure
@@ -157,19 +512,256 @@ impl SelectorHandlerPlugin {
         }
     }
     
-    /// Parse Range header for CSS selector
-    fn parse_selector_from_range(&self, range_header: &str) -> Option<String> {
+    /// Replace or append to the first matching element, returning the new file
+    /// contents and the updated element in the document's own format
+    fn apply_content(
+        &self,
+        format: DocumentFormat,
+        document: &Document,
+        selector: &str,
+        new_content: &str,
+        operation: &str  // "replace" or "append"
+    ) -> Result<(String, String), String> {
+        match format {
+            DocumentFormat::Html => Ok(self.apply_content_with_special_handling(document, selector, new_content, operation)),
+            DocumentFormat::Markdown => {
+                let html = self.markdown_fragment(document, selector, new_content, operation);
+                let (document_html, updated_html) = self.apply_content_with_special_handling(document, selector, &html, operation);
+                Ok((self.markdown_document(&document_html), self.html_to_markdown(&updated_html)))
+            }
+            DocumentFormat::Xml => {
+                let fragment = xml::parse_fragment(new_content, document)?;
+                let selection = document.select(selector).first();
+                let element = selection.nodes().first().ok_or(ERROR_NO_ELEMENTS_MATCHED)?;
+                let nodes = xml::import_fragment(&document.tree, &fragment);
+                match operation {
+                    "replace" => {
+                        for node in &nodes {
+                            element.insert_before(node);
+                        }
+                        element.remove_from_parent();
+                    }
+                    "append" => {
+                        for node in &nodes {
+                            element.append_child(node);
+                        }
+                    }
+                    _ => unreachable!("Invalid operation: {}", operation)
+                }
+                
+                let updated_xml = self.render_element(format, &document.select(selector).first());
+                Ok((xml::serialize_document(document), updated_xml))
+            }
+        }
+    }
+    
+    /// Parse file contents into a DOM
+    fn load_document(&self, format: DocumentFormat, content: &str) -> Document {
+        match format {
+            DocumentFormat::Html => Document::from(content),
+            DocumentFormat::Xml => xml::parse_document(content),
+            DocumentFormat::Markdown => Document::from(self.markdown_to_html(content)),
+        }
+    }
+    
+    /// Serialize a DOM back into file contents
+    fn document_content(&self, format: DocumentFormat, document: &Document) -> String {
+        match format {
+            DocumentFormat::Html => document.html().to_string(),
+            DocumentFormat::Xml => xml::serialize_document(document),
+            DocumentFormat::Markdown => self.markdown_document(&document.html()),
+        }
+    }
+    
+    /// Render the first element of a selection in the document's own format
+    fn render_element(&self, format: DocumentFormat, selection: &Selection) -> String {
+        selection.nodes().first()
+            .map(|node| self.render_node(format, node))
+            .unwrap_or_default()
+    }
+    
+    /// Render an element in the document's own format
+    fn render_node(&self, format: DocumentFormat, node: &NodeRef) -> String {
+        let rendered = match format {
+            DocumentFormat::Xml => xml::serialize_node(node),
+            _ => self.render_node_html(format, node),
+        };
+        rendered.trim_end().to_string()
+    }
+    
+    /// Render an element of an HTML or Markdown DOM
+    fn render_node_html(&self, format: DocumentFormat, node: &NodeRef) -> String {
+        if format == DocumentFormat::Markdown {
+            self.html_to_markdown(&node.html())
+        } else {
+            node.html().to_string()
+        }
+    }
+    
+    /// Convert a Markdown request body to HTML for the target element. List
+    /// items join a target list, or replace a target item, rather than nesting
+    /// a new list inside it.
+    fn markdown_fragment(&self, document: &Document, selector: &str, markdown: &str, operation: &str) -> String {
+        let html = self.markdown_to_html(markdown);
+        let target = document.select(selector).first().nodes().first()
+            .and_then(|node| node.node_name())
+            .unwrap_or_default();
+        let joins_list = match operation {
+            "append" => matches!(&*target, "ul" | "ol"),
+            _ => &*target == "li",
+        };
+        
+        let fragment = Document::from(html.as_str());
+        let blocks = fragment.select("body > *");
+        if joins_list && blocks.length() == 1 && blocks.is("ul, ol") {
+            blocks.inner_html().to_string()
+        } else {
+            html
+        }
+    }
+    
+    fn markdown_to_html(&self, markdown: &str) -> String {
+        let parser = Parser::new_ext(markdown, Options::ENABLE_TABLES | Options::ENABLE_STRIKETHROUGH);
+        let mut html = String::new();
+        pulldown_cmark::html::push_html(&mut html, parser);
+        html
+    }
+    
+    fn html_to_markdown(&self, html: &str) -> String {
+        let options = htmd::options::Options {
+            bullet_list_marker: htmd::options::BulletListMarker::Dash,
+            ul_bullet_spacing: 1,
+            ol_number_spacing: 1,
+            ..Default::default()
+        };
+        htmd::HtmlToMarkdown::builder()
+            .options(options)
+            .build()
+            .convert(html)
+            .unwrap_or_default()
+            .trim_end()
+            .to_string()
+    }
+    
+    /// Convert a whole HTML document produced from Markdown back to Markdown
+    fn markdown_document(&self, document_html: &str) -> String {
+        let document = Document::from(document_html);
+        let markdown = self.html_to_markdown(&document.select("body").inner_html());
+        format!("{}\n", markdown)
+    }
+    
+    /// Parse Range header for CSS selector and the paging parameters after it
+    fn parse_selector_from_range(&self, range_header: &str) -> Option<(String, Paging)> {
         let selector_regex = Regex::new(r"selector=(.*)").ok()?;
         let captures = selector_regex.captures(range_header)?;
         captures.get(1).map(|m| {
+            // Parameters are split off first, so an encoded ';' stays in the selector
+            let (selector, paging) = Self::split_paging(m.as_str());
             // URL decode the selector value
-            urlencoding::decode(m.as_str()).unwrap_or_else(|_| m.as_str().into()).into_owned()
+            let selector = urlencoding::decode(selector).unwrap_or_else(|_| selector.into()).into_owned();
+            (selector, paging)
         })
     }
     
-    /// Check if file is HTML
-    fn is_html_file(&self, path: &str) -> bool {
-        path.ends_with(".html") || path.ends_with(".htm")
+    /// Split `;start=N` and `;count=N` off the end of a raw selector value
+    ///
+    /// Anything else after a `;` is left as part of the selector.
+    fn split_paging(value: &str) -> (&str, Paging) {
+        let mut selector = value;
+        let mut paging = Paging::default();
+        while let Some((rest, parameter)) = selector.rsplit_once(';') {
+            let Some((name, number)) = parameter.split_once('=') else { break };
+            let Ok(number) = number.trim().parse::<usize>() else { break };
+            match name.trim() {
+                PARAMETER_START if paging.start.is_none() => paging.start = Some(number),
+                PARAMETER_COUNT if paging.count.is_none() => paging.count = Some(number),
+                _ => break,
+            }
+            selector = rest;
+        }
+        (selector.trim_end(), paging)
+    }
+    
+    /// Translate microdata pseudo-selectors into plain attribute selectors
+    fn translate_microdata_pseudo_selectors(&self, selector: &str) -> String {
+        let pseudo_regex = Regex::new(MICRODATA_PSEUDO_PATTERN).expect("valid pseudo-selector pattern");
+        let translated = pseudo_regex.replace_all(selector, |caps: &regex::Captures| {
+            let attribute = &caps[1];
+            let value = caps.get(2)
+                .or_else(|| caps.get(3))
+                .or_else(|| caps.get(4))
+                .map(|m| m.as_str())
+                .unwrap_or("")
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"");
+            // itemtype and itemprop are space-separated token lists
+            let operator = if attribute == "itemid" { "=" } else { "~=" };
+            format!(r#"[{}{}"{}"]"#, attribute, operator, value)
+        });
+        translated.replace(ITEMSCOPE_PSEUDO, "[itemscope]")
+    }
+    
+    /// Translate and validate a selector, returning a parse error message on failure
+    fn resolve_selector(&self, selector: &str, format: Option<DocumentFormat>) -> Result<String, String> {
+        // An empty selector is not an error, it simply matches nothing
+        if selector.is_empty() {
+            return Ok(String::new());
+        }
+        
+        let resolved = if format == Some(DocumentFormat::Xml) && xml::is_xpath(selector) {
+            xml::xpath_to_css(selector)
+                .map_err(|detail| format!("{} '{}': {}", ERROR_INVALID_SELECTOR, selector, detail))?
+        } else {
+            self.translate_microdata_pseudo_selectors(selector)
+        };
+        let parse_error = Matcher::new(&resolved).err().map(|e| {
+            format!("{:?} at column {}", e.kind, e.location.column)
+        });
+        match parse_error {
+            None => Ok(resolved),
+            Some(detail) => Err(format!("{} '{}': {}", ERROR_INVALID_SELECTOR, selector, detail)),
+        }
+    }
+    
+    /// Returns the decoded value of a query string parameter, empty if it has none
+    fn query_param(request: &PluginRequest, name: &str) -> Option<String> {
+        request.http_request.uri().query()?
+            .split('&')
+            .filter_map(|pair| {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                (key == name).then(|| value.replace('+', " "))
+            })
+            .next()
+            .map(|value| urlencoding::decode(&value).map(|v| v.into_owned()).unwrap_or(value))
+    }
+    
+    /// Refuse a write whose `If-Match` names neither `*` nor the current
+    /// ETag of the first matched element
+    fn check_if_match(&self, request: &PluginRequest, format: DocumentFormat, matches: &Selection) -> Option<Response<Body>> {
+        let if_match = request.http_request.headers().get(IF_MATCH)?.to_str().unwrap_or_default();
+        let current = element_etag(&self.render_element(format, matches));
+        if if_match.split(',').map(str::trim).any(|tag| tag == IF_MATCH_ANY || tag == current) {
+            return None;
+        }
+        Some(Response::builder()
+            .status(StatusCode::PRECONDITION_FAILED)
+            .header("Content-Type", CONTENT_TYPE_PLAIN)
+            .header(ETAG, current)
+            .body(Body::from(ERROR_PRECONDITION_FAILED))
+            .unwrap())
+    }
+    
+    /// Add the ETag a GET of the selector would now return to a write's
+    /// response, unless the selector no longer matches
+    fn with_element_etag(&self, mut response: Response<Body>, format: DocumentFormat, content: &str, selector: &str) -> Response<Body> {
+        let document = self.load_document(format, content);
+        let etag = document.try_select(selector)
+            .map(|matches| element_etag(&self.render_element(format, &matches)))
+            .and_then(|etag| HeaderValue::from_str(&etag).ok());
+        if let Some(etag) = etag {
+            response.headers_mut().insert(ETAG, etag);
+        }
+        response
     }
     
     /// Get body content from request
@@ -177,43 +769,112 @@ impl SelectorHandlerPlugin {
         request.get_body_string().await
     }
     
-    /// Build file path from request
-    fn build_file_path(&self, request: &PluginRequest, context: &PluginContext) -> String {
-        let root_dir = context.host_config.get("hostRoot")
-            .unwrap_or(&self.root_dir);
-        let mut file_path = format!("{}{}", root_dir, request.path);
-        
-        // If path ends with '/', append 'index.html'
-        if file_path.ends_with('/') {
-            file_path.push_str(INDEX_FILE_NAME);
+    /// Build file path from request, refusing paths that leave the host root
+    fn build_file_path(&self, request: &PluginRequest, context: &PluginContext) -> Result<String, Response<Body>> {
+        match paths::request_file_path(request, context, &self.root_dir) {
+            Ok(path) => Ok(path.to_string_lossy().into_owned()),
+            Err(_) => Err(Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::from(ERROR_ACCESS_DENIED))
+                .unwrap()),
         }
-        
-        file_path
     }
     
-    /// Perform security check on file path
-    fn check_path_security(&self, file_path: &str, context: &PluginContext) -> Result<(), Response<Body>> {
-        let path = Path::new(file_path);
-        let root_dir = context.host_config.get("hostRoot")
-            .unwrap_or(&self.root_dir);
-        
-        if let Ok(canonical) = path.canonicalize() {
-            let root_canonical = Path::new(root_dir).canonicalize()
-                .unwrap_or_else(|_| Path::new(".").to_path_buf());
-            if !canonical.starts_with(&root_canonical) {
-                return Err(Response::builder()
-                    .status(StatusCode::FORBIDDEN)
-                    .body(Body::from(ERROR_ACCESS_DENIED))
-                    .unwrap());
+    /// The name a request path fills the template with, if it is a template document
+    fn template_document_name<'a>(&self, request_path: &'a str) -> Option<&'a str> {
+        self.templates.as_ref()?.document_name(request_path)
+    }
+    
+    /// The directory template and fragment paths are resolved against
+    fn host_root<'a>(&'a self, context: &'a PluginContext) -> &'a Path {
+        Path::new(context.host_config.get(paths::CONFIG_KEY_HOST_ROOT).unwrap_or(&self.root_dir))
+    }
+    
+    /// Compose a template document
+    async fn compose_document(&self, context: &PluginContext, request_path: &str) -> Option<std::io::Result<templates::Composition>> {
+        let templates = self.templates.as_ref()?;
+        let name = templates.document_name(request_path)?;
+        Some(templates.compose(context, self.host_root(context), name).await)
+    }
+    
+    /// Read the document a request addresses, composing template documents
+    async fn read_document(&self, context: &PluginContext, request_path: &str, file_path: &str) -> std::io::Result<String> {
+        match self.compose_document(context, request_path).await {
+            Some(composition) => composition.map(|composition| composition.html),
+            None => fs::read_to_string(context, file_path).await,
+        }
+    }
+    
+    /// Write back the document a request addresses
+    ///
+    /// A template document is composed again and the fragments the new
+    /// content changed are written; changes outside its fragments are
+    /// refused with `409 Conflict`. Callers hold the write lock.
+    async fn write_document(&self, context: &PluginContext, request_path: &str, file_path: &str, content: String) -> Result<(), Response<Body>> {
+        let written = match self.compose_document(context, request_path).await {
+            Some(Ok(composition)) => {
+                let templates = self.templates.as_ref().expect("template documents are configured");
+                templates.write(context, &composition, &content).await
             }
+            Some(Err(e)) => Err(e),
+            None => fs::write(context, file_path, content).await.map(|_| true),
+        };
+        match written {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Response::builder()
+                .status(StatusCode::CONFLICT)
+                .header("Content-Type", CONTENT_TYPE_PLAIN)
+                .body(Body::from(templates::ERROR_OUTSIDE_FRAGMENTS))
+                .unwrap()),
+            Err(e) => Err(Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header("Content-Type", CONTENT_TYPE_PLAIN)
+                .body(Body::from(format!("Failed to write file: {}", e)))
+                .unwrap()),
         }
-        Ok(())
     }
     
-    /// Check if file exists
-    fn check_file_exists(&self, file_path: &str) -> Result<(), Response<Body>> {
+    /// Answer a GET or HEAD without a selector for a template document with
+    /// its composition; other requests are passed on
+    async fn handle_template_document(&self, request: &PluginRequest, context: &PluginContext) -> Option<Response<Body>> {
+        let method = request.http_request.method();
+        if method != Method::GET && method != Method::HEAD {
+            return None;
+        }
+        let has_selector = request.http_request.headers().get(RANGE)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| self.parse_selector_from_range(header))
+            .is_some();
+        if has_selector {
+            return None;
+        }
+        let html = match self.compose_document(context, &request.path).await? {
+            Ok(composition) => composition.html,
+            Err(e) => {
+                context.log_verbose(&format!("[selector-handler] Failed to compose {}: {}", request.path, e));
+                return Some(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .header("Content-Type", CONTENT_TYPE_PLAIN)
+                    .body(Body::from(ERROR_FILE_NOT_FOUND))
+                    .unwrap());
+            }
+        };
+        let etag = element_etag(&html);
+        let length = html.len();
+        let body = if method == Method::HEAD { Body::empty() } else { Body::from(html) };
+        Some(Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", CONTENT_TYPE_HTML)
+            .header("Content-Length", length)
+            .header(ETAG, etag)
+            .body(body)
+            .unwrap())
+    }
+    
+    /// Check if file exists; template documents are checked when they are composed
+    fn check_file_exists(&self, request_path: &str, file_path: &str) -> Result<(), Response<Body>> {
         let path = Path::new(file_path);
-        if !path.exists() {
+        if self.template_document_name(request_path).is_none() && !path.exists() {
             return Err(Response::builder()
                 .status(StatusCode::NOT_FOUND)
                 .header("Content-Type", CONTENT_TYPE_PLAIN)
@@ -223,37 +884,165 @@ impl SelectorHandlerPlugin {
         Ok(())
     }
     
-    /// Validate that file is HTML
-    fn validate_html_file(&self, file_path: &str, selector: &str) -> Result<(), Response<Body>> {
-        let filename = Path::new(file_path).file_name()
-            .and_then(|n| n.to_str())
-            .unwrap_or(file_path);
-            
-        if !self.is_html_file(filename) {
-            return Err(Response::builder()
+    /// Validate that file is a structured document, returning its format
+    fn validate_document_format(&self, file_path: &str, selector: &str) -> Result<DocumentFormat, Response<Body>> {
+        DocumentFormat::from_path(file_path).ok_or_else(|| {
+            Response::builder()
                 .status(StatusCode::RANGE_NOT_SATISFIABLE)
                 .header("Content-Type", CONTENT_TYPE_PLAIN)
                 .header("Content-Range", format!("selector {}", selector))
                 .body(Body::from(ERROR_RANGE_NOT_SATISFIABLE))
-                .unwrap());
-        }
-        Ok(())
+                .unwrap()
+        })
     }
     
     /// Common file validation logic
     fn validate_file_for_selector(
         &self, 
+        request_path: &str,
         file_path: &str, 
-        selector: &str, 
-        context: &PluginContext
-    ) -> Result<(), Response<Body>> {
-        self.check_path_security(file_path, context)?;
-        self.check_file_exists(file_path)?;
-        self.validate_html_file(file_path, selector)?;
-        Ok(())
+        selector: &str
+    ) -> Result<DocumentFormat, Response<Body>> {
+        self.check_file_exists(request_path, file_path)?;
+        self.validate_document_format(file_path, selector)
     }
     
-    async fn handle_selector_get(&self, request: &PluginRequest, selector: &str, context: &PluginContext) -> Option<Response<Body>> {
+    /// Serve a request carrying a selector Range, passing it on otherwise
+    async fn handle_selector_request(
+        &self,
+        request: &mut PluginRequest,
+        context: &PluginContext,
+        previous_content: PreviousContentMode,
+    ) -> Option<PluginResponse> {
+        if request.http_request.method() == Method::GET && Self::query_param(request, QUERY_MICRODATA).is_some() {
+            if !self.is_method_allowed(&Method::GET) {
+                return Some(self.method_not_allowed().into());
+            }
+            return Some(self.handle_microdata_get(request, context).await.into());
+        }
+        
+        if let Some(response) = self.handle_template_document(request, context).await {
+            return Some(response.into());
+        }
+        
+        // Check for Range header with CSS selector
+        let range_header = match request.http_request.headers().get(RANGE) {
+            Some(header) => match header.to_str() {
+                Ok(header_str) => header_str,
+                Err(_) => return None, // Pass through if invalid header
+            },
+            None => return None, // No Range header, pass through
+        };
+        
+        // Parse selector from Range header
+        let (selector, paging) = match self.parse_selector_from_range(range_header) {
+            Some(parsed) => parsed,
+            None => return None, // Not a selector range, pass through
+        };
+        
+        // Methods the configuration leaves out are refused before the file
+        // is looked at
+        if !self.is_method_allowed(request.http_request.method()) {
+            context.log_verbose(&format!("[selector-handler] {} is not allowed", request.http_request.method()));
+            return Some(self.method_not_allowed().into());
+        }
+        
+        // Writes always apply to every match, so a page of them is refused
+        // rather than silently widened
+        if paging.is_requested() && request.http_request.method() != Method::GET {
+            return Some(Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-Type", CONTENT_TYPE_PLAIN)
+                .body(Body::from(ERROR_PAGING_GET_ONLY))
+                .unwrap()
+                .into());
+        }
+        
+        // Reject selectors that would otherwise panic or silently match nothing
+        let format = self.build_file_path(request, context).ok()
+            .and_then(|file_path| DocumentFormat::from_path(&file_path));
+        let selector = match self.resolve_selector(&selector, format) {
+            Ok(resolved) => resolved,
+            Err(message) => {
+                context.log_verbose(&format!("[selector-handler] {}", message));
+                return Some(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .header("Content-Type", CONTENT_TYPE_PLAIN)
+                    .body(Body::from(message))
+                    .unwrap()
+                    .into());
+            }
+        };
+        
+        let response = match *request.http_request.method() {
+            Method::GET => self.handle_selector_get(request, &selector, paging, context).await,
+            Method::PUT => self.handle_selector_put(request, &selector, context, previous_content).await,
+            Method::POST => self.handle_selector_post(request, &selector, context).await,
+            Method::PATCH => self.handle_selector_patch(request, &selector, context).await,
+            Method::DELETE => self.handle_selector_delete(request, &selector, context).await,
+            Method::OPTIONS => self.handle_selector_options(request, context).await,
+            _ => Some(self.method_not_allowed()),
+        };
+        if let Some(response) = &response {
+            self.notify_change(request, response, context);
+        }
+        response.map(|r| r.into())
+    }
+    
+    /// Answer a GET for a page of the matched elements
+    ///
+    /// The page's elements are returned one after another, and
+    /// `Content-Range` gives their positions among all matches and the
+    /// number of matches, e.g. `selector li 20-29/57`.
+    fn paged_response(&self, format: DocumentFormat, matches: &Selection, selector: &str, paging: Paging) -> Response<Body> {
+        let nodes = matches.nodes();
+        let Some(window) = paging.window(nodes.len()) else {
+            return Response::builder()
+                .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                .header("Content-Type", CONTENT_TYPE_PLAIN)
+                .header("Content-Range", format!("selector {} */{}", selector, nodes.len()))
+                .body(Body::from(ERROR_PAGE_NOT_SATISFIABLE))
+                .unwrap();
+        };
+        let content_range = format!("selector {} {}-{}/{}", selector, window.start, window.end - 1, nodes.len());
+        let page: Vec<String> = nodes[window].iter().map(|node| self.render_node(format, node)).collect();
+        let body = page.join("\n");
+        Response::builder()
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header("Content-Type", format.content_type())
+            .header("Content-Range", content_range)
+            .header(ETAG, element_etag(&body))
+            .body(Body::from(body))
+            .unwrap()
+    }
+    
+    /// Announce a successful write to the plugins of the host
+    fn notify_change(&self, request: &PluginRequest, response: &Response<Body>, context: &PluginContext) {
+        if !response.status().is_success() || !CHANGE_METHODS.contains(request.http_request.method()) {
+            return;
+        }
+        let Some(selector) = request.get_metadata("applied_selector") else {
+            return;
+        };
+        let selected = request.get_metadata("selected_content").unwrap_or_default().to_string();
+        // The selected content of a DELETE is what it removed
+        let (content, previous_content) = if request.is_method(&Method::DELETE) {
+            (String::new(), Some(selected))
+        } else {
+            (selected, request.get_metadata(METADATA_PREVIOUS_CONTENT).map(str::to_string))
+        };
+        let change = DocumentChange {
+            method: request.method().to_string(),
+            path: request.path.clone(),
+            selector: selector.to_string(),
+            content,
+            previous_content,
+            user: request.get_metadata("authenticated_user").map(str::to_string),
+        };
+        context.notify_change(&change);
+    }
+    
+    async fn handle_selector_get(&self, request: &PluginRequest, selector: &str, paging: Paging, context: &PluginContext) -> Option<Response<Body>> {
         // Handle empty selector
         if selector.is_empty() {
             return Some(Response::builder()
@@ -263,18 +1052,22 @@ impl SelectorHandlerPlugin {
                 .unwrap());
         }
         
-        let file_path = self.build_file_path(request, context);
+        let file_path = match self.build_file_path(request, context) {
+            Ok(file_path) => file_path,
+            Err(response) => return Some(response),
+        };
         context.log_verbose(&format!("[selector-handler] GET request - file_path: {}", file_path));
         
         // Validate file
-        if let Err(response) = self.validate_file_for_selector(&file_path, selector, context) {
-            return Some(response);
-        }
+        let format = match self.validate_file_for_selector(&request.path, &file_path, selector) {
+            Ok(format) => format,
+            Err(response) => return Some(response),
+        };
         
-        match fs::read_to_string(&file_path) {
+        match self.read_document(context, &request.path, &file_path).await {
             Ok(html_content) => {
                 context.log_verbose(&format!("[selector-handler] Successfully read file: {}", file_path));
-                let document = Document::from(html_content.as_str());
+                let document = self.load_document(format, &html_content);
                 
                 // Validate selector first
                 let element = document.try_select(selector);
@@ -286,14 +1079,17 @@ impl SelectorHandlerPlugin {
                         .unwrap());
                 }
                 
-                let final_element = document.select(selector);
-                let html_output = final_element.html().to_string();
-                let trimmed_output = html_output.trim_end().to_string();
+                if paging.is_requested() {
+                    return Some(self.paged_response(format, &document.select(selector), selector, paging));
+                }
+                
+                let trimmed_output = self.render_element(format, &document.select(selector));
                 
                 Some(Response::builder()
                     .status(StatusCode::PARTIAL_CONTENT)
-                    .header("Content-Type", CONTENT_TYPE_HTML)
+                    .header("Content-Type", format.content_type())
                     .header("Content-Range", format!("selector {}", selector))
+                    .header(ETAG, element_etag(&trimmed_output))
                     .body(Body::from(trimmed_output))
                     .unwrap())
             }
@@ -308,14 +1104,24 @@ impl SelectorHandlerPlugin {
         }
     }
     
-    async fn handle_selector_put(&self, request: &mut PluginRequest, selector: &str, context: &PluginContext) -> Option<Response<Body>> {
-        let file_path = self.build_file_path(request, context);
+    async fn handle_selector_put(
+        &self,
+        request: &mut PluginRequest,
+        selector: &str,
+        context: &PluginContext,
+        previous_content: PreviousContentMode,
+    ) -> Option<Response<Body>> {
+        let file_path = match self.build_file_path(request, context) {
+            Ok(file_path) => file_path,
+            Err(response) => return Some(response),
+        };
         context.log_verbose(&format!("[selector-handler] PUT request - file_path: {}", file_path));
         
         // Validate file
-        if let Err(response) = self.validate_file_for_selector(&file_path, selector, context) {
-            return Some(response);
-        }
+        let format = match self.validate_file_for_selector(&request.path, &file_path, selector) {
+            Ok(format) => format,
+            Err(response) => return Some(response),
+        };
         
         // Get new content from request body
         let new_content = match self.get_request_body(request).await {
@@ -329,49 +1135,58 @@ impl SelectorHandlerPlugin {
             }
         };
         
-        match fs::read_to_string(&file_path) {
+        let _write_guard = self.write_lock.lock().await;
+        match self.read_document(context, &request.path, &file_path).await {
             Ok(html_content) => {
                 // Do all DOM processing in a block to ensure it completes before async operations
-                let (final_content_string, updated_element_html) = {
-                    let document = Document::from(html_content.as_str());
+                let applied = {
+                    let document = self.load_document(format, &html_content);
                     
                     // Validate selector first
-                    let element = document.try_select(selector);
-                    if element.is_none() {
-                        return Some(Response::builder()
-                            .status(StatusCode::NOT_FOUND)
-                            .header("Content-Type", "text/plain")
-                            .body(Body::from("No elements matched the selector"))
-                            .unwrap());
+                    let element = match document.try_select(selector) {
+                        Some(element) => element,
+                        None => {
+                            return Some(Response::builder()
+                                .status(StatusCode::NOT_FOUND)
+                                .header("Content-Type", "text/plain")
+                                .body(Body::from("No elements matched the selector"))
+                                .unwrap());
+                        }
+                    };
+                    if let Some(response) = self.check_if_match(request, format, &element) {
+                        return Some(response);
                     }
+                    let previous_html = self.render_element(format, &element.first());
                     
                     // Use shared method for handling special elements
-                    self.apply_content_with_special_handling(&document, selector, &new_content, "replace")
+                    self.apply_content(format, &document, selector, &new_content, "replace")
+                        .map(|(document_html, updated_html)| (document_html, updated_html, previous_html))
+                };
+                let (final_content_string, updated_element_html, previous_element_html) = match applied {
+                    Ok(result) => result,
+                    Err(message) => {
+                        return Some(Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .header("Content-Type", CONTENT_TYPE_PLAIN)
+                            .body(Body::from(message))
+                            .unwrap());
+                    }
                 };
                 
                 // Write the modified HTML back to the file
-                match fs::write(&file_path, final_content_string) {
+                match self.write_document(context, &request.path, &file_path, final_content_string.clone()).await {
                     Ok(_) => {
                         // Set metadata for other plugins (like WebSocket) to use
                         request.set_metadata("applied_selector".to_string(), selector.to_string());
                         request.set_metadata("selected_content".to_string(), updated_element_html.clone());
                         request.set_metadata("posted_content".to_string(), new_content.clone());
+                        request.set_metadata(METADATA_PREVIOUS_CONTENT.to_string(), previous_element_html.clone());
                         
                         // Return just the updated element HTML, not the entire document
-                        Some(Response::builder()
-                            .status(StatusCode::PARTIAL_CONTENT)
-                            .header("Content-Type", CONTENT_TYPE_HTML)
-                            .header("Content-Range", format!("selector {}", selector))
-                            .body(Body::from(updated_element_html))
-                            .unwrap())
-                    }
-                    Err(e) => {
-                        Some(Response::builder()
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .header("Content-Type", "text/plain")
-                            .body(Body::from(format!("Failed to write file: {}", e)))
-                            .unwrap())
+                        let response = self.put_response(format, selector, updated_element_html, &previous_element_html, previous_content);
+                        Some(self.with_element_etag(response, format, &final_content_string, selector))
                     }
+                    Err(response) => Some(response),
                 }
             }
             Err(_) => {
@@ -384,14 +1199,70 @@ impl SelectorHandlerPlugin {
         }
     }
     
+    /// Build the 206 response to a PUT, carrying the replaced element as configured
+    fn put_response(
+        &self,
+        format: DocumentFormat,
+        selector: &str,
+        updated: String,
+        previous: &str,
+        mode: PreviousContentMode,
+    ) -> Response<Body> {
+        let builder = Response::builder()
+            .status(StatusCode::PARTIAL_CONTENT)
+            .header("Content-Range", format!("selector {}", selector));
+        match mode {
+            PreviousContentMode::Header => builder
+                .header("Content-Type", format.content_type())
+                .header(HEADER_PREVIOUS_CONTENT, urlencoding::encode(previous).into_owned())
+                .body(Body::from(updated))
+                .unwrap(),
+            PreviousContentMode::Multipart => {
+                let boundary = Self::multipart_boundary(&[&updated, previous]);
+                let mut body = String::new();
+                for (name, content) in [("current", updated.as_str()), ("previous", previous)] {
+                    body.push_str(&format!(
+                        "--{}\r\nContent-Type: {}\r\nContent-Disposition: inline; name=\"{}\"\r\n\r\n{}\r\n",
+                        boundary, format.content_type(), name, content
+                    ));
+                }
+                body.push_str(&format!("--{}--\r\n", boundary));
+                builder
+                    .header("Content-Type", format!("{}; boundary={}", CONTENT_TYPE_MULTIPART_MIXED, boundary))
+                    .body(Body::from(body))
+                    .unwrap()
+            }
+            PreviousContentMode::None => builder
+                .header("Content-Type", format.content_type())
+                .body(Body::from(updated))
+                .unwrap(),
+        }
+    }
+    
+    /// A multipart boundary that occurs in none of the parts
+    fn multipart_boundary(parts: &[&str]) -> String {
+        let mut counter = 0u32;
+        loop {
+            let boundary = format!("{}{}-{}", MULTIPART_BOUNDARY_PREFIX, std::process::id(), counter);
+            if !parts.iter().any(|part| part.contains(&boundary)) {
+                return boundary;
+            }
+            counter += 1;
+        }
+    }
+    
     async fn handle_selector_post(&self, request: &mut PluginRequest, selector: &str, context: &PluginContext) -> Option<Response<Body>> {
-        let file_path = self.build_file_path(request, context);
+        let file_path = match self.build_file_path(request, context) {
+            Ok(file_path) => file_path,
+            Err(response) => return Some(response),
+        };
         context.log_verbose(&format!("[selector-handler] POST request - file_path: {}", file_path));
         
         // Validate file
-        if let Err(response) = self.validate_file_for_selector(&file_path, selector, context) {
-            return Some(response);
-        }
+        let format = match self.validate_file_for_selector(&request.path, &file_path, selector) {
+            Ok(format) => format,
+            Err(response) => return Some(response),
+        };
         
         // Get new content from request body
         let new_content = match self.get_request_body(request).await {
@@ -405,49 +1276,58 @@ impl SelectorHandlerPlugin {
             }
         };
         
-        match fs::read_to_string(&file_path) {
+        let _write_guard = self.write_lock.lock().await;
+        match self.read_document(context, &request.path, &file_path).await {
             Ok(html_content) => {
                 // Do all DOM processing in a block to ensure it completes before async operations
-                let (final_content_string, updated_element_html) = {
-                    let document = Document::from(html_content.as_str());
+                let applied = {
+                    let document = self.load_document(format, &html_content);
                     
                     // Validate selector first
-                    let element = document.try_select(selector);
-                    if element.is_none() {
+                    let Some(element) = document.try_select(selector) else {
                         return Some(Response::builder()
                             .status(StatusCode::NOT_FOUND)
                             .header("Content-Type", "text/plain")
                             .body(Body::from("No elements matched the selector"))
                             .unwrap());
+                    };
+                    if let Some(response) = self.check_if_match(request, format, &element) {
+                        return Some(response);
                     }
                     
                     // Use shared method for handling special elements
-                    self.apply_content_with_special_handling(&document, selector, &new_content, "append")
+                    self.apply_content(format, &document, selector, &new_content, "append")
+                };
+                let (final_content_string, updated_element_html) = match applied {
+                    Ok(result) => result,
+                    Err(message) => {
+                        return Some(Response::builder()
+                            .status(StatusCode::BAD_REQUEST)
+                            .header("Content-Type", CONTENT_TYPE_PLAIN)
+                            .body(Body::from(message))
+                            .unwrap());
+                    }
                 };
                 
                 // Write the modified HTML back to the file
-                match fs::write(&file_path, final_content_string) {
+                match self.write_document(context, &request.path, &file_path, final_content_string.clone()).await {
                     Ok(_) => {
                         // Set metadata for other plugins (like WebSocket) to use
                         request.set_metadata("applied_selector".to_string(), selector.to_string());
                         request.set_metadata("selected_content".to_string(), updated_element_html.clone());
                         request.set_metadata("posted_content".to_string(), new_content.clone());
                         
-                        // For POST, return just the posted content, not the entire target element
-                        Some(Response::builder()
+                        // For POST, return just the posted content, not the entire
+                        // target element, though the ETag is the target's
+                        let response = Response::builder()
                             .status(StatusCode::PARTIAL_CONTENT)
-                            .header("Content-Type", CONTENT_TYPE_HTML)
+                            .header("Content-Type", format.content_type())
                             .header("Content-Range", format!("selector {}", selector))
                             .body(Body::from(new_content))
-                            .unwrap())
-                    }
-                    Err(e) => {
-                        Some(Response::builder()
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .header("Content-Type", "text/plain")
-                            .body(Body::from(format!("Failed to write file: {}", e)))
-                            .unwrap())
+                            .unwrap();
+                        Some(self.with_element_etag(response, format, &final_content_string, selector))
                     }
+                    Err(response) => Some(response),
                 }
             }
             Err(_) => {
@@ -460,40 +1340,244 @@ impl SelectorHandlerPlugin {
         }
     }
     
+    /// Parse a PATCH body into a list of operations
+    fn parse_patch_operations(&self, body: &str) -> Result<Vec<PatchOperation>, String> {
+        let value: serde_json::Value = serde_json::from_str(body)
+            .map_err(|e| format!("{}: {}", ERROR_INVALID_PATCH, e))?;
+        let operations = if value.is_array() {
+            serde_json::from_value(value)
+        } else {
+            serde_json::from_value(value).map(|op| vec![op])
+        };
+        match operations {
+            Ok(operations) if operations.is_empty() => Err(format!("{}: no operations given", ERROR_INVALID_PATCH)),
+            Ok(operations) => Ok(operations),
+            Err(e) => Err(format!("{}: {}", ERROR_INVALID_PATCH, e)),
+        }
+    }
+    
+    /// Apply PATCH operations to the first matching element, returning the
+    /// updated document and the element's resulting markup
+    fn apply_patch_operations(
+        &self,
+        format: DocumentFormat,
+        document: &Document,
+        selector: &str,
+        operations: &[PatchOperation]
+    ) -> Result<(String, String), String> {
+        let selection = document.select(selector).first();
+        let element = selection.nodes().first().ok_or(ERROR_NO_ELEMENTS_MATCHED)?;
+        
+        let mut unwrapped_html = None;
+        for operation in operations {
+            if unwrapped_html.is_some() {
+                return Err(format!("{}: {}", ERROR_INVALID_PATCH, ERROR_OPERATION_AFTER_UNWRAP));
+            }
+            if format == DocumentFormat::Xml {
+                unwrapped_html = self.apply_xml_patch_operation(document, element, operation)?;
+                continue;
+            }
+            match operation {
+                PatchOperation::SetAttribute { name, value } => element.set_attr(name, value),
+                PatchOperation::RemoveAttribute { name } => element.remove_attr(name),
+                PatchOperation::InsertBefore { html } => element.before_html(html.as_str()),
+                PatchOperation::InsertAfter { html } => element.after_html(html.as_str()),
+                PatchOperation::Wrap { html } => element.wrap_html(html.as_str()),
+                PatchOperation::SetText { text } => element.set_text(text.as_str()),
+                PatchOperation::Unwrap => {
+                    let contents = element.inner_html().to_string();
+                    element.replace_with_html(contents.as_str());
+                    unwrapped_html = Some(contents);
+                }
+            }
+        }
+        
+        let updated_html = match (format, unwrapped_html) {
+            (DocumentFormat::Markdown, Some(contents)) => self.html_to_markdown(&contents),
+            (_, Some(contents)) => contents,
+            (DocumentFormat::Xml, None) => xml::serialize_node(element),
+            (_, None) => self.render_node_html(format, element),
+        };
+        Ok((self.document_content(format, document), updated_html.trim_end().to_string()))
+    }
+    
+    /// Apply one PATCH operation to an XML element, parsing fragments as XML.
+    /// Returns the element's contents once it has been unwrapped.
+    fn apply_xml_patch_operation(
+        &self,
+        document: &Document,
+        element: &NodeRef,
+        operation: &PatchOperation
+    ) -> Result<Option<String>, String> {
+        match operation {
+            PatchOperation::SetAttribute { name, value } => element.set_attr(name, value),
+            PatchOperation::RemoveAttribute { name } => element.remove_attr(name),
+            PatchOperation::SetText { text } => element.set_text(text.as_str()),
+            PatchOperation::InsertBefore { html } => {
+                let fragment = xml::parse_fragment(html, document)?;
+                for node in xml::import_fragment(&document.tree, &fragment) {
+                    element.insert_before(&node);
+                }
+            }
+            PatchOperation::InsertAfter { html } => {
+                let fragment = xml::parse_fragment(html, document)?;
+                for node in xml::import_fragment(&document.tree, &fragment).iter().rev() {
+                    element.insert_after(node);
+                }
+            }
+            PatchOperation::Wrap { html } => {
+                let fragment = xml::parse_fragment(html, document)?;
+                let wrapper = xml::import_fragment(&document.tree, &fragment)
+                    .into_iter()
+                    .find(|node| node.is_element())
+                    .ok_or_else(|| format!("{}: wrap requires an element", ERROR_INVALID_PATCH))?;
+                // The element goes inside the innermost first descendant
+                let mut innermost = wrapper.clone();
+                while let Some(child) = innermost.first_element_child() {
+                    innermost = child;
+                }
+                element.insert_before(&wrapper);
+                innermost.append_child(element);
+            }
+            PatchOperation::Unwrap => {
+                let contents = xml::serialize_children(element);
+                for child in element.children() {
+                    element.insert_before(&child);
+                }
+                element.remove_from_parent();
+                return Ok(Some(contents));
+            }
+        }
+        Ok(None)
+    }
+    
+    async fn handle_selector_patch(&self, request: &mut PluginRequest, selector: &str, context: &PluginContext) -> Option<Response<Body>> {
+        let file_path = match self.build_file_path(request, context) {
+            Ok(file_path) => file_path,
+            Err(response) => return Some(response),
+        };
+        context.log_verbose(&format!("[selector-handler] PATCH request - file_path: {}", file_path));
+        
+        // Validate file
+        let format = match self.validate_file_for_selector(&request.path, &file_path, selector) {
+            Ok(format) => format,
+            Err(response) => return Some(response),
+        };
+        
+        // Parse operations from request body
+        let operations = match self.get_request_body(request).await {
+            Ok(body) => self.parse_patch_operations(&body),
+            Err(_) => Err(ERROR_INVALID_REQUEST_BODY.to_string()),
+        };
+        let operations = match operations {
+            Ok(operations) => operations,
+            Err(message) => {
+                return Some(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .header("Content-Type", CONTENT_TYPE_PLAIN)
+                    .body(Body::from(message))
+                    .unwrap());
+            }
+        };
+        
+        let _write_guard = self.write_lock.lock().await;
+        let html_content = match self.read_document(context, &request.path, &file_path).await {
+            Ok(content) => content,
+            Err(_) => {
+                return Some(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .header("Content-Type", CONTENT_TYPE_PLAIN)
+                    .body(Body::from(ERROR_FILE_NOT_FOUND))
+                    .unwrap());
+            }
+        };
+        
+        // Do all DOM processing in a block to ensure it completes before async operations
+        let patched = {
+            let document = self.load_document(format, &html_content);
+            let Some(element) = document.try_select(selector) else {
+                return Some(Response::builder()
+                    .status(StatusCode::NOT_FOUND)
+                    .header("Content-Type", CONTENT_TYPE_PLAIN)
+                    .body(Body::from(ERROR_NO_ELEMENTS_MATCHED))
+                    .unwrap());
+            };
+            if let Some(response) = self.check_if_match(request, format, &element) {
+                return Some(response);
+            }
+            self.apply_patch_operations(format, &document, selector, &operations)
+        };
+        let (final_content_string, updated_element_html) = match patched {
+            Ok(result) => result,
+            Err(message) => {
+                return Some(Response::builder()
+                    .status(StatusCode::BAD_REQUEST)
+                    .header("Content-Type", CONTENT_TYPE_PLAIN)
+                    .body(Body::from(message))
+                    .unwrap());
+            }
+        };
+        
+        // Write the modified HTML back to the file
+        match self.write_document(context, &request.path, &file_path, final_content_string.clone()).await {
+            Ok(_) => {
+                // Set metadata for other plugins (like WebSocket) to use
+                request.set_metadata("applied_selector".to_string(), selector.to_string());
+                request.set_metadata("selected_content".to_string(), updated_element_html.clone());
+                
+                let response = Response::builder()
+                    .status(StatusCode::PARTIAL_CONTENT)
+                    .header("Content-Type", format.content_type())
+                    .header("Content-Range", format!("selector {}", selector))
+                    .body(Body::from(updated_element_html))
+                    .unwrap();
+                Some(self.with_element_etag(response, format, &final_content_string, selector))
+            }
+            Err(response) => Some(response),
+        }
+    }
+    
     async fn handle_selector_delete(&self, request: &mut PluginRequest, selector: &str, context: &PluginContext) -> Option<Response<Body>> {
-        let file_path = self.build_file_path(request, context);
+        let file_path = match self.build_file_path(request, context) {
+            Ok(file_path) => file_path,
+            Err(response) => return Some(response),
+        };
         context.log_verbose(&format!("[selector-handler] DELETE request - file_path: {}", file_path));
         
         // Validate file
-        if let Err(response) = self.validate_file_for_selector(&file_path, selector, context) {
-            return Some(response);
-        }
+        let format = match self.validate_file_for_selector(&request.path, &file_path, selector) {
+            Ok(format) => format,
+            Err(response) => return Some(response),
+        };
         
-        match fs::read_to_string(&file_path) {
+        let _write_guard = self.write_lock.lock().await;
+        match self.read_document(context, &request.path, &file_path).await {
             Ok(html_content) => {
                 // Do all DOM processing in a block to ensure it completes before async operations
                 let final_content_string = {
-                    let document = Document::from(html_content.as_str());
+                    let document = self.load_document(format, &html_content);
                     
                     // Validate selector first
-                    let element = document.try_select(selector);
-                    if element.is_none() {
+                    let Some(element) = document.try_select(selector) else {
                         return Some(Response::builder()
                             .status(StatusCode::NOT_FOUND)
                             .header("Content-Type", "text/plain")
                             .body(Body::from("No elements matched the selector"))
                             .unwrap());
+                    };
+                    if let Some(response) = self.check_if_match(request, format, &element) {
+                        return Some(response);
                     }
                     
                     // Get the content before removing
-                    let removed_content = document.select(selector).first().html().to_string();
+                    let removed_content = self.render_element(format, &document.select(selector));
                     document.select(selector).first().remove();
                     
-                    (document.html().to_string(), removed_content)
+                    (self.document_content(format, &document), removed_content)
                 };
                 
                 // Write the modified HTML back to the file
-                match fs::write(&file_path, final_content_string.0.clone()) {
+                match self.write_document(context, &request.path, &file_path, final_content_string.0.clone()).await {
                     Ok(_) => {
                         // Set metadata for other plugins (like WebSocket) to use
                         request.set_metadata("applied_selector".to_string(), selector.to_string());
@@ -505,13 +1589,7 @@ impl SelectorHandlerPlugin {
                             .body(Body::from(""))
                             .unwrap())
                     }
-                    Err(e) => {
-                        Some(Response::builder()
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .header("Content-Type", "text/plain")
-                            .body(Body::from(format!("Failed to write file: {}", e)))
-                            .unwrap())
-                    }
+                    Err(response) => Some(response),
                 }
             }
             Err(_) => {
@@ -523,39 +1601,152 @@ impl SelectorHandlerPlugin {
             }
         }
     }
+    
+    /// Serve the microdata items of an HTML document as JSON-LD
+    ///
+    /// A selector Range limits the index to the items within the matched
+    /// elements, and an `itemtype` parameter to the items of one type.
+    async fn handle_microdata_get(&self, request: &PluginRequest, context: &PluginContext) -> Response<Body> {
+        let file_path = match self.build_file_path(request, context) {
+            Ok(file_path) => file_path,
+            Err(response) => return response,
+        };
+        context.log_verbose(&format!("[selector-handler] Microdata request - file_path: {}", file_path));
+        
+        if let Err(response) = self.check_file_exists(&request.path, &file_path) {
+            return response;
+        }
+        if DocumentFormat::from_path(&file_path) != Some(DocumentFormat::Html) {
+            return Response::builder()
+                .status(StatusCode::NOT_ACCEPTABLE)
+                .header("Content-Type", CONTENT_TYPE_PLAIN)
+                .body(Body::from(ERROR_MICRODATA_NOT_HTML))
+                .unwrap();
+        }
+        
+        let selector = request.http_request.headers().get(RANGE)
+            .and_then(|header| header.to_str().ok())
+            .and_then(|header| self.parse_selector_from_range(header))
+            .map(|(selector, _)| self.resolve_selector(&selector, Some(DocumentFormat::Html)));
+        let html = match self.read_document(context, &request.path, &file_path).await {
+            Ok(html) => html,
+            Err(_) => return Response::builder()
+                .status(StatusCode::NOT_FOUND)
+                .header("Content-Type", CONTENT_TYPE_PLAIN)
+                .body(Body::from(ERROR_FILE_NOT_FOUND))
+                .unwrap(),
+        };
+        
+        let items = match selector {
+            None => microdata::extract_items(&html),
+            Some(Err(message)) => return Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .header("Content-Type", CONTENT_TYPE_PLAIN)
+                .body(Body::from(message))
+                .unwrap(),
+            Some(Ok(selector)) => {
+                let document = Document::from(html.as_str());
+                let elements = match document.try_select(&selector) {
+                    Some(elements) => elements,
+                    None => return Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .header("Content-Type", CONTENT_TYPE_PLAIN)
+                        .body(Body::from(ERROR_NO_ELEMENTS_MATCHED))
+                        .unwrap(),
+                };
+                elements.iter()
+                    .map(|element| microdata::extract_items(&element.html()))
+                    .collect::<Result<Vec<_>, _>>()
+                    .map(|items| items.concat())
+            }
+        };
+        let mut items = match items {
+            Ok(items) => items,
+            Err(e) => return Response::builder()
+                .status(StatusCode::INTERNAL_SERVER_ERROR)
+                .header("Content-Type", CONTENT_TYPE_PLAIN)
+                .body(Body::from(format!("{}: {}", ERROR_MICRODATA_EXTRACTION, e)))
+                .unwrap(),
+        };
+        if let Some(item_type) = Self::query_param(request, QUERY_ITEMTYPE) {
+            items.retain(|item| microdata::has_type(item, &item_type));
+        }
+        
+        Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", CONTENT_TYPE_JSON_LD)
+            .body(Body::from(microdata::to_json_ld(&items).to_string()))
+            .unwrap()
+    }
+    
+    /// Describe the selector capabilities of the target resource
+    async fn handle_selector_options(&self, request: &PluginRequest, context: &PluginContext) -> Option<Response<Body>> {
+        let file_path = match self.build_file_path(request, context) {
+            Ok(file_path) => file_path,
+            Err(response) => return Some(response),
+        };
+        context.log_verbose(&format!("[selector-handler] OPTIONS request - file_path: {}", file_path));
+        
+        if let Err(response) = self.check_file_exists(&request.path, &file_path) {
+            return Some(response);
+        }
+        
+        let response = match DocumentFormat::from_path(&file_path) {
+            Some(format) => Response::builder()
+                .status(StatusCode::OK)
+                .header("Allow", self.allow_header())
+                .header("Accept-Ranges", ACCEPT_RANGES_SELECTOR)
+                .header("Accept-Patch", CONTENT_TYPE_JSON)
+                .header(HEADER_SELECTOR_TARGET, format.selector_target()),
+            None => Response::builder()
+                .status(StatusCode::OK)
+                .header("Allow", NON_HTML_METHODS)
+                .header("Accept-Ranges", ACCEPT_RANGES_NONE)
+                .header(HEADER_SELECTOR_TARGET, SELECTOR_TARGET_NON_HTML),
+        };
+        
+        Some(response.body(Body::empty()).unwrap())
+    }
+}
+
+/// The strong ETag of a rendered element: part of its SHA-256 digest in hex
+fn element_etag(rendered: &str) -> String {
+    format!("\"{}\"", hex::encode(&Sha256::digest(rendered.as_bytes())[..ETAG_DIGEST_BYTES]))
 }
 
 #[async_trait]
 impl Plugin for SelectorHandlerPlugin {
     async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
-        // Check for Range header with CSS selector
-        let range_header = match request.http_request.headers().get(RANGE) {
-            Some(header) => match header.to_str() {
-                Ok(header_str) => header_str,
-                Err(_) => return None, // Pass through if invalid header
-            },
-            None => return None, // No Range header, pass through
-        };
-        
-        // Parse selector from Range header
-        let selector = match self.parse_selector_from_range(range_header) {
-            Some(sel) => sel,
-            None => return None, // Not a selector range, pass through
+        self.handle_selector_request(request, context, self.previous_content).await
+
+    }
+    
+    fn apply_selector(&self, operation: &SelectorOperation, context: &PluginContext) -> Option<Result<String, SelectorError>> {
+        // Run the operation as the equivalent selector request; its body is in
+        // memory and its file I/O runs on the blocking pool, so waiting here
+        // does not depend on this thread
+        let http_request = hyper::Request::builder()
+            .method(operation.method.as_str())
+            .uri(operation.path.as_str())
+            .header(RANGE, format!("selector={}", urlencoding::encode(&operation.selector)))
+            .body(Body::from(operation.content.clone().unwrap_or_default()));
+        let http_request = match http_request {
+            Ok(http_request) => http_request,
+            Err(e) => return Some(Err(SelectorError::new(StatusCode::BAD_REQUEST.as_u16(), e.to_string()))),
         };
+        let mut request = PluginRequest::new(http_request, operation.path.clone());
         
-        match *request.http_request.method() {
-            Method::GET => self.handle_selector_get(request, &selector, context).await.map(|r| r.into()),
-            Method::PUT => self.handle_selector_put(request, &selector, context).await.map(|r| r.into()),
-            Method::POST => self.handle_selector_post(request, &selector, context).await.map(|r| r.into()),
-            Method::DELETE => self.handle_selector_delete(request, &selector, context).await.map(|r| r.into()),
-            _ => {
-                Some(Response::builder()
-                    .status(StatusCode::METHOD_NOT_ALLOWED)
-                    .body(Body::from(ERROR_METHOD_NOT_ALLOWED))
-                    .unwrap()
-                    .into())
-            }
-        }
+        futures::executor::block_on(async {
+            let response = self.handle_selector_request(&mut request, context, PreviousContentMode::None).await?.response;
+            let status = response.status();
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap_or_default();
+            let body = String::from_utf8_lossy(&body).into_owned();
+            Some(if status.is_success() {
+                Ok(body)
+            } else {
+                Err(SelectorError::new(status.as_u16(), body))
+            })
+        })
     }
     
     fn name(&self) -> &str {
@@ -0,0 +1,101 @@
+//! Microdata index of HTML documents as JSON-LD
+//!
+//! Items are extracted with microdata-extract and written as JSON-LD node
+//! objects inside a `@graph`. Every item with a type gets a `@context` whose
+//! `@vocab` is the namespace of its first type, the type up to its last `/`
+//! or `#`, so `name` on a `https://schema.org/Person` expands to
+//! `https://schema.org/name`. Nested items repeat the context only when their
+//! vocabulary differs from the enclosing item's. Property names that are
+//! already absolute URLs are kept as they are.
+//!
+//! Values map onto JSON-LD as follows:
+//! - text, dates and times become strings
+//! - numbers and booleans keep their JSON type
+//! - URLs from `href`, `src` and similar attributes become `{"@id": url}`
+//! - nested items become nested node objects
+//!
+//! A property given once is written as a single value, one given several
+//! times as an array.
+
+use microdata_extract::{MicrodataExtractor, MicrodataItem, MicrodataValue};
+use serde_json::{json, Map, Value};
+
+/// Extract the items of an HTML document or fragment
+///
+/// Item types need not be valid URLs; item ids must be, as the extractor
+/// refuses items with any other id.
+pub fn extract_items(html: &str) -> Result<Vec<MicrodataItem>, String> {
+    MicrodataExtractor::with_settings(false, false)
+        .extract(html)
+        .map_err(|e| e.to_string())
+}
+
+/// Whether one of an item's types is `item_type`
+pub fn has_type(item: &MicrodataItem, item_type: &str) -> bool {
+    item.item_type()
+        .is_some_and(|types| types.split_whitespace().any(|t| t == item_type))
+}
+
+/// The JSON-LD document describing a list of top-level items
+pub fn to_json_ld(items: &[MicrodataItem]) -> Value {
+    let nodes: Vec<Value> = items.iter().map(|item| item_to_node(item, None)).collect();
+    json!({ "@graph": nodes })
+}
+
+/// The vocabulary an item's property names belong to
+fn vocabulary(item: &MicrodataItem) -> Option<&str> {
+    let first_type = item.item_type()?.split_whitespace().next()?;
+    let end = first_type.rfind(['/', '#'])?;
+    Some(&first_type[..=end])
+}
+
+/// Convert an item to a node object, adding a context unless it shares the
+/// vocabulary of the item it is nested in
+fn item_to_node(item: &MicrodataItem, parent_vocabulary: Option<&str>) -> Value {
+    let mut node = Map::new();
+    let vocabulary = vocabulary(item);
+    if let Some(vocabulary) = vocabulary.filter(|v| Some(*v) != parent_vocabulary) {
+        node.insert("@context".to_string(), json!({ "@vocab": vocabulary }));
+    }
+    if let Some(item_id) = item.item_id() {
+        node.insert("@id".to_string(), Value::String(item_id.to_string()));
+    }
+    if let Some(item_type) = item.item_type() {
+        let types: Vec<&str> = item_type.split_whitespace().collect();
+        let types = match types.as_slice() {
+            [single] => json!(single),
+            _ => json!(types),
+        };
+        node.insert("@type".to_string(), types);
+    }
+
+    // Untyped items have no vocabulary of their own, so keep their parent's
+    let vocabulary = vocabulary.or(parent_vocabulary);
+    for property in item.properties() {
+        let value = value_to_json(property.value(), vocabulary);
+        match node.get_mut(property.name()) {
+            Some(Value::Array(values)) => values.push(value),
+            Some(existing) => {
+                let first = existing.take();
+                *existing = Value::Array(vec![first, value]);
+            }
+            None => {
+                node.insert(property.name().to_string(), value);
+            }
+        }
+    }
+
+    Value::Object(node)
+}
+
+/// Convert a property value to JSON-LD
+fn value_to_json(value: &MicrodataValue, vocabulary: Option<&str>) -> Value {
+    match value {
+        MicrodataValue::Text(text) => Value::String(text.clone()),
+        MicrodataValue::DateTime(date_time) => Value::String(date_time.clone()),
+        MicrodataValue::Url(url) => json!({ "@id": url.as_str() }),
+        MicrodataValue::Item(item) => item_to_node(item, vocabulary),
+        MicrodataValue::Number(number) => json!(number),
+        MicrodataValue::Boolean(boolean) => Value::Bool(*boolean),
+    }
+}
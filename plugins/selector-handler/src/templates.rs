@@ -0,0 +1,206 @@
+//! Template documents composed from fragment files
+//!
+//! Paths matching `template_paths` are not files of their own. Their HTML is
+//! the `template` file with every slot, an element carrying a
+//! `data-fragment` attribute, filled with the file the attribute names.
+//! `{name}` in the attribute stands for the part of the request path the
+//! `*` of the pattern matched, so with `/pages/*.html` a request for
+//! `/pages/about.html` fills `data-fragment="/content/{name}.html"` from
+//! `/content/about.html`.
+//!
+//! Selector writes are split back along the slots: each fragment whose slot
+//! content changed is written, and a write that changes the document outside
+//! the slots is refused, as the template is shared by every document.
+
+use dom_query::{Document, NodeRef};
+use rusty_beam_plugin_api::{fs, log_error, paths, PluginContext};
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_KEY_TEMPLATE: &str = "template";
+pub const CONFIG_KEY_TEMPLATE_PATHS: &str = "template_paths";
+
+/// Attribute naming the file that fills a slot
+const FRAGMENT_ATTRIBUTE: &str = "data-fragment";
+const FRAGMENT_SELECTOR: &str = "[data-fragment]";
+/// Placeholder for the matched part of the request path
+const NAME_PLACEHOLDER: &str = "{name}";
+/// Template documents are always HTML
+const DOCUMENT_EXTENSION: &str = ".html";
+
+pub const ERROR_OUTSIDE_FRAGMENTS: &str = "Conflict: the change is outside the document's fragments";
+
+/// A request path pattern with at most one `*`
+#[derive(Debug)]
+struct PathPattern {
+    prefix: String,
+    /// What follows the `*`, or `None` for a pattern matching one path
+    suffix: Option<String>,
+}
+
+impl PathPattern {
+    fn parse(pattern: &str) -> Option<Self> {
+        match pattern.split_once('*') {
+            Some((_, suffix)) if suffix.contains('*') => None,
+            Some((prefix, suffix)) => Some(Self { prefix: prefix.to_string(), suffix: Some(suffix.to_string()) }),
+            None => Some(Self { prefix: pattern.to_string(), suffix: None }),
+        }
+    }
+
+    /// The part of a path the `*` matched, empty for a pattern without one
+    fn matched_name<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let rest = path.strip_prefix(self.prefix.as_str())?;
+        match &self.suffix {
+            None => rest.is_empty().then_some(rest),
+            Some(suffix) => {
+                let name = rest.strip_suffix(suffix.as_str())?;
+                let valid = !name.is_empty() && name != "." && name != ".." && !name.contains('/');
+                valid.then_some(name)
+            }
+        }
+    }
+}
+
+/// The template and the request paths composed from it
+#[derive(Debug)]
+pub struct Templates {
+    /// Path of the template below the host root
+    template: String,
+    patterns: Vec<PathPattern>,
+}
+
+/// A template document as it was composed
+#[derive(Debug)]
+pub struct Composition {
+    pub html: String,
+    /// Files of the slots, in document order
+    fragments: Vec<PathBuf>,
+}
+
+impl Templates {
+    /// Read the configuration; `None` when template documents are not configured
+    pub fn from_config(plugin_name: &str, config: &HashMap<String, String>) -> Option<Self> {
+        let template = config.get(CONFIG_KEY_TEMPLATE).map(|v| v.trim()).filter(|v| !v.is_empty());
+        let patterns = config.get(CONFIG_KEY_TEMPLATE_PATHS).map(|v| v.trim()).filter(|v| !v.is_empty());
+        let (template, patterns) = match (template, patterns) {
+            (Some(template), Some(patterns)) => (template, patterns),
+            (None, None) => return None,
+            _ => {
+                log_error!(plugin_name, "{} and {} must be configured together, template documents are disabled",
+                    CONFIG_KEY_TEMPLATE, CONFIG_KEY_TEMPLATE_PATHS);
+                return None;
+            }
+        };
+        let mut parsed = Vec::new();
+        for pattern in patterns.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match PathPattern::parse(pattern) {
+                Some(parsed_pattern) if pattern.ends_with(DOCUMENT_EXTENSION) => parsed.push(parsed_pattern),
+                _ => log_error!(plugin_name, "Ignoring template path '{}': patterns must end in {} and have at most one *",
+                    pattern, DOCUMENT_EXTENSION),
+            }
+        }
+        if parsed.is_empty() {
+            return None;
+        }
+        Some(Self { template: template.to_string(), patterns: parsed })
+    }
+
+    /// The name a request path fills the template with, if it is a template document
+    pub fn document_name<'a>(&self, path: &'a str) -> Option<&'a str> {
+        self.patterns.iter().find_map(|pattern| pattern.matched_name(path))
+    }
+
+    /// Compose the document a name fills the template with
+    ///
+    /// A missing template or fragment is a `NotFound` error, so the document
+    /// does not exist until all its parts do.
+    pub async fn compose(&self, context: &PluginContext, root: &Path, name: &str) -> io::Result<Composition> {
+        let policy = paths::SymlinkPolicy::from_context(context);
+        let template_path = paths::resolve(root, &self.template, policy).map_err(not_found)?;
+        let template = fs::read_to_string(context, &template_path).await?;
+
+        let (skeleton, fragment_paths) = {
+            let document = Document::from(template.as_str());
+            let slots = slot_nodes(&document);
+            let fragment_paths: Vec<String> = slots.iter()
+                .map(|slot| slot.attr(FRAGMENT_ATTRIBUTE).unwrap_or_default().replace(NAME_PLACEHOLDER, name))
+                .collect();
+            for slot in &slots {
+                slot.remove_children();
+            }
+            (document.html().to_string(), fragment_paths)
+        };
+
+        let mut fragments = Vec::with_capacity(fragment_paths.len());
+        let mut contents = Vec::with_capacity(fragment_paths.len());
+        for fragment_path in fragment_paths {
+            let path = paths::resolve(root, &fragment_path, policy).map_err(not_found)?;
+            contents.push(fs::read_to_string(context, &path).await?);
+            fragments.push(path);
+        }
+
+        let html = {
+            let document = Document::from(skeleton.as_str());
+            for (slot, content) in slot_nodes(&document).iter().zip(&contents) {
+                slot.set_html(content.as_str());
+            }
+            document.html().to_string()
+        };
+        Ok(Composition { html, fragments })
+    }
+
+    /// Write the fragments of a composition that a new version of the
+    /// document changed
+    ///
+    /// Returns `Ok(false)` without writing anything when the new version
+    /// changes the document outside its slots.
+    pub async fn write(&self, context: &PluginContext, composition: &Composition, new_html: &str) -> io::Result<bool> {
+        let changed = match changed_fragments(composition, new_html) {
+            Some(changed) => changed,
+            None => return Ok(false),
+        };
+        for (path, content) in changed {
+            fs::write(context, path, content).await?;
+        }
+        Ok(true)
+    }
+}
+
+/// The slots of a document, leaving out those inside another slot
+fn slot_nodes(document: &Document) -> Vec<NodeRef<'_>> {
+    document.select(FRAGMENT_SELECTOR).nodes().iter()
+        .filter(|node| !node.ancestors_it(None).any(|ancestor| ancestor.has_attr(FRAGMENT_ATTRIBUTE)))
+        .cloned()
+        .collect()
+}
+
+/// A document's HTML with its slots emptied, and the slots' contents
+fn split(html: &str) -> (String, Vec<String>) {
+    let document = Document::from(html.trim_end());
+    let slots = slot_nodes(&document);
+    let contents = slots.iter().map(|slot| slot.inner_html().to_string()).collect();
+    for slot in &slots {
+        slot.remove_children();
+    }
+    (document.html().to_string(), contents)
+}
+
+/// The fragment files to write for a new version of a composition, or
+/// `None` if it changes more than the slots' contents
+fn changed_fragments<'a>(composition: &'a Composition, new_html: &str) -> Option<Vec<(&'a PathBuf, String)>> {
+    let (skeleton, contents) = split(&composition.html);
+    let (new_skeleton, new_contents) = split(new_html);
+    if new_skeleton != skeleton || new_contents.len() != contents.len() {
+        return None;
+    }
+    Some(composition.fragments.iter()
+        .zip(contents.into_iter().zip(new_contents))
+        .filter(|(_, (old, new))| old != new)
+        .map(|(path, (_, new))| (path, new))
+        .collect())
+}
+
+fn not_found(error: paths::PathError) -> io::Error {
+    io::Error::new(io::ErrorKind::NotFound, error.to_string())
+}
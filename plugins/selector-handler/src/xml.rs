@@ -0,0 +1,392 @@
+//! XML support for the selector protocol
+//!
+//! XML files are parsed with xml5ever into the same DOM the HTML handlers
+//! use, so CSS selectors match them unchanged. Unlike the HTML parser there is
+//! no error recovery that would add `<html>` and `<body>`, treat `<link>` as a
+//! void element or lowercase names, and documents are serialized back as XML.
+//!
+//! The parser does not keep namespace declarations as attributes, so prefixed
+//! declarations are restored onto the document element after parsing. The
+//! serializer declares any other namespace where an element uses it.
+//!
+//! Selectors starting with `/` are read as XPath and mapped onto CSS. Only
+//! the location path subset with a CSS equivalent is supported:
+//! - `/` and `//` steps, e.g. `/feed/entry` or `//item`
+//! - name tests and `*`; namespace prefixes are ignored, names match by local part
+//! - `[@attr]` and `[@attr='value']` predicates
+//! - positional `[n]` and `[last()]` predicates
+
+use dom_query::{Document, NodeData, NodeRef, Tree};
+use regex::Regex;
+use std::collections::HashMap;
+use xml5ever::driver::{parse_document as parse_xml, XmlParseOpts};
+use xml5ever::tendril::TendrilSink;
+
+/// Element wrapping fragments so that several top-level nodes can be parsed
+const FRAGMENT_ROOT: &str = "rusty-beam-fragment";
+
+// Prefixed namespace declarations, e.g. xmlns:dc="http://purl.org/dc/elements/1.1/"
+const NAMESPACE_DECLARATION_PATTERN: &str = r#"xmlns:([A-Za-z_][\w.-]*)\s*=\s*(?:"([^"]*)"|'([^']*)')"#;
+const DECLARATION_PREFIX: &str = "xmlns:";
+
+/// Parse a complete XML document
+pub fn parse_document(xml: &str) -> Document {
+    let document = parse_xml(Document::default(), XmlParseOpts::default()).one(xml);
+
+    // Keep declarations of prefixes the document does not use yet
+    if let Some(root) = document.root().first_element_child() {
+        let declaration_regex = Regex::new(NAMESPACE_DECLARATION_PATTERN).expect("valid declaration pattern");
+        for captures in declaration_regex.captures_iter(xml) {
+            let name = format!("{}{}", DECLARATION_PREFIX, &captures[1]);
+            let namespace = captures.get(2).or_else(|| captures.get(3)).map(|m| m.as_str()).unwrap_or("");
+            if !root.has_attr(&name) {
+                root.set_attr(&name, namespace);
+            }
+        }
+    }
+    document
+}
+
+/// Namespace declarations restored by `parse_document`, as (prefix, namespace)
+fn declared_namespaces(element: &dom_query::Element) -> impl Iterator<Item = (Option<String>, String)> + '_ {
+    element.attrs.iter()
+        .filter(|attr| attr.name.prefix.is_none())
+        .filter_map(|attr| {
+            let prefix = attr.name.local.strip_prefix(DECLARATION_PREFIX)?;
+            Some((Some(prefix.to_string()), attr.value.to_string()))
+        })
+}
+
+/// Parse an XML fragment such as a request body, rejecting malformed markup.
+/// Namespace prefixes bound anywhere in `context` may be used in the fragment.
+pub fn parse_fragment(xml: &str, context: &Document) -> Result<Document, String> {
+    let declarations: String = namespace_bindings(context)
+        .into_iter()
+        .map(|(prefix, namespace)| format!(" {}=\"{}\"", declaration_name(prefix.as_deref()), escape(&namespace, true)))
+        .collect();
+    let document = parse_document(&format!("<{}{}>{}</{}>", FRAGMENT_ROOT, declarations, xml, FRAGMENT_ROOT));
+    let errors = document.errors.borrow();
+    match errors.first() {
+        Some(error) => Err(format!("Malformed XML: {}", error)),
+        None => {
+            drop(errors);
+            Ok(document)
+        }
+    }
+}
+
+/// Collect the namespace bound to each prefix used in a document, taking the
+/// default namespace from the document element
+fn namespace_bindings(document: &Document) -> Scope {
+    let mut bindings = HashMap::new();
+    for (index, element) in document.select("*").nodes().iter().enumerate() {
+        element.query(|node| {
+            if let NodeData::Element(element) = &node.data {
+                for (prefix, namespace) in declared_namespaces(element) {
+                    bindings.entry(prefix).or_insert(namespace);
+                }
+                let names = std::iter::once(&element.name).chain(element.attrs.iter().map(|attr| &attr.name));
+                for name in names {
+                    let prefix = name.prefix.as_ref().map(|prefix| prefix.to_string());
+                    if (prefix.is_some() || index == 0) && !name.ns.is_empty() {
+                        bindings.entry(prefix).or_insert_with(|| name.ns.to_string());
+                    }
+                }
+            }
+        });
+    }
+    bindings.into_iter().collect()
+}
+
+/// Copy the top-level nodes of a parsed fragment into another tree
+pub fn import_fragment<'a>(tree: &'a Tree, fragment: &Document) -> Vec<NodeRef<'a>> {
+    fragment.root()
+        .first_element_child()
+        .map(|wrapper| wrapper.children().iter().map(|node| import_node(tree, node)).collect())
+        .unwrap_or_default()
+}
+
+/// Deep-copy a node from another document into `tree`, without a parent
+fn import_node<'a>(tree: &'a Tree, source: &NodeRef) -> NodeRef<'a> {
+    let data = source.query(|node| node.data.clone()).unwrap_or(NodeData::Fragment);
+    let copy = NodeRef::new(tree.create_node(data), tree);
+    for child in source.children() {
+        copy.append_child(&import_node(tree, &child));
+    }
+    copy
+}
+
+/// Namespace bindings in scope while serializing, innermost last
+type Scope = Vec<(Option<String>, String)>;
+
+/// Serialize a whole document as XML
+pub fn serialize_document(document: &Document) -> String {
+    let mut output = String::new();
+    for child in document.root().children() {
+        write_node(&child, &mut output, &mut Scope::new());
+        // The parser drops whitespace outside the document element
+        if !child.is_text() {
+            output.push('\n');
+        }
+    }
+    output.trim_end().to_string()
+}
+
+/// Serialize a single node, including its descendants, as XML
+pub fn serialize_node(node: &NodeRef) -> String {
+    let mut output = String::new();
+    write_node(node, &mut output, &mut Scope::new());
+    output
+}
+
+/// Serialize only the children of a node as XML
+pub fn serialize_children(node: &NodeRef) -> String {
+    let mut output = String::new();
+    for child in node.children() {
+        write_node(&child, &mut output, &mut Scope::new());
+    }
+    output
+}
+
+fn write_node(node: &NodeRef, output: &mut String, scope: &mut Scope) {
+    let Some(data) = node.query(|node| node.data.clone()) else {
+        return;
+    };
+    match data {
+        NodeData::Element(element) => {
+            let name = qualified_name(element.name.prefix.as_deref(), &element.name.local);
+            output.push('<');
+            output.push_str(&name);
+
+            // Declare any namespace not already bound here or by an ancestor
+            let scope_depth = scope.len();
+            scope.extend(declared_namespaces(&element));
+            let names = std::iter::once(&element.name)
+                .chain(element.attrs.iter().map(|attr| &attr.name).filter(|name| name.prefix.is_some()));
+            for name in names {
+                let prefix = name.prefix.as_ref().map(|prefix| prefix.to_string());
+                if prefix.as_deref() == Some("xml") {
+                    continue;
+                }
+                let bound = scope.iter().rev()
+                    .find(|(bound_prefix, _)| *bound_prefix == prefix)
+                    .map(|(_, namespace)| namespace.as_str())
+                    .unwrap_or("");
+                if bound != &*name.ns {
+                    output.push_str(&format!(" {}=\"{}\"", declaration_name(prefix.as_deref()), escape(&name.ns, true)));
+                    scope.push((prefix, name.ns.to_string()));
+                }
+            }
+
+            for attr in &element.attrs {
+                output.push(' ');
+                output.push_str(&qualified_name(attr.name.prefix.as_deref(), &attr.name.local));
+                output.push_str("=\"");
+                output.push_str(&escape(&attr.value, true));
+                output.push('"');
+            }
+            let children = node.children();
+            if children.is_empty() {
+                output.push_str("/>");
+            } else {
+                output.push('>');
+                for child in &children {
+                    write_node(child, output, scope);
+                }
+                output.push_str("</");
+                output.push_str(&name);
+                output.push('>');
+            }
+            scope.truncate(scope_depth);
+        }
+        NodeData::Text { contents } => output.push_str(&escape(&contents, false)),
+        NodeData::Comment { contents } => {
+            output.push_str("<!--");
+            output.push_str(&contents);
+            output.push_str("-->");
+        }
+        NodeData::ProcessingInstruction { target, contents } => {
+            output.push_str("<?");
+            output.push_str(&target);
+            if !contents.is_empty() {
+                output.push(' ');
+                output.push_str(contents.trim_end_matches('?'));
+            }
+            output.push_str("?>");
+        }
+        NodeData::Doctype { name, public_id, system_id } => {
+            output.push_str("<!DOCTYPE ");
+            output.push_str(&name);
+            if !public_id.is_empty() {
+                output.push_str(&format!(" PUBLIC \"{}\" \"{}\"", public_id, system_id));
+            } else if !system_id.is_empty() {
+                output.push_str(&format!(" SYSTEM \"{}\"", system_id));
+            }
+            output.push('>');
+        }
+        NodeData::Document | NodeData::Fragment => {
+            for child in node.children() {
+                write_node(&child, output, scope);
+            }
+        }
+    }
+}
+
+fn declaration_name(prefix: Option<&str>) -> String {
+    match prefix {
+        Some(prefix) => format!("xmlns:{}", prefix),
+        None => "xmlns".to_string(),
+    }
+}
+
+fn qualified_name(prefix: Option<&str>, local: &str) -> String {
+    match prefix {
+        Some(prefix) if !prefix.is_empty() => format!("{}:{}", prefix, local),
+        _ => local.to_string(),
+    }
+}
+
+fn escape(value: &str, attribute: bool) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' if !attribute => escaped.push_str("&gt;"),
+            '"' if attribute => escaped.push_str("&quot;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Check whether a selector should be read as XPath rather than CSS
+pub fn is_xpath(selector: &str) -> bool {
+    selector.starts_with('/')
+}
+
+/// Map an XPath location path onto the equivalent CSS selector
+pub fn xpath_to_css(xpath: &str) -> Result<String, String> {
+    let mut parser = XPathParser { input: xpath, position: 0 };
+    let mut css = String::new();
+
+    while !parser.at_end() {
+        let descendant = if parser.eat("//") {
+            true
+        } else if parser.eat("/") {
+            false
+        } else {
+            return Err(parser.error("expected '/' or '//'"));
+        };
+        let first = css.is_empty();
+        if !first {
+            css.push_str(if descendant { " " } else { " > " });
+        }
+
+        // An absolute path starts at the document element
+        if first && !descendant {
+            css.push_str(":root > ");
+        }
+        let name = parser.name_test()?;
+        css.push_str(&name);
+
+        while parser.eat("[") {
+            css.push_str(&parser.predicate(&name)?);
+            if !parser.eat("]") {
+                return Err(parser.error("expected ']'"));
+            }
+        }
+    }
+
+    if css.is_empty() {
+        return Err(parser.error("empty path"));
+    }
+    Ok(css)
+}
+
+struct XPathParser<'a> {
+    input: &'a str,
+    position: usize,
+}
+
+impl XPathParser<'_> {
+    fn at_end(&self) -> bool {
+        self.position >= self.input.len()
+    }
+
+    fn rest(&self) -> &str {
+        &self.input[self.position..]
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        if self.rest().starts_with(token) {
+            self.position += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn error(&self, message: &str) -> String {
+        format!("{} at column {}", message, self.position + 1)
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &str {
+        let start = self.position;
+        let length = self.rest().find(|c: char| !predicate(c)).unwrap_or(self.rest().len());
+        self.position += length;
+        &self.input[start..self.position]
+    }
+
+    /// A name token, with any namespace prefix dropped
+    fn name(&mut self) -> Result<String, String> {
+        let name = self.take_while(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+        let local = name.rsplit(':').next().unwrap_or(name);
+        if local.is_empty() || local.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') {
+            return Err(self.error("expected a name"));
+        }
+        Ok(local.to_string())
+    }
+
+    fn name_test(&mut self) -> Result<String, String> {
+        if self.eat("*") {
+            Ok("*".to_string())
+        } else {
+            self.name()
+        }
+    }
+
+    fn predicate(&mut self, name_test: &str) -> Result<String, String> {
+        // Positions count siblings with the same name, or all elements for `*`
+        let (nth, last) = if name_test == "*" {
+            ("nth-child", "last-child")
+        } else {
+            ("nth-of-type", "last-of-type")
+        };
+
+        if self.eat("@") {
+            let attribute = self.name()?;
+            if !self.eat("=") {
+                return Ok(format!("[{}]", attribute));
+            }
+            let quote = match self.rest().chars().next() {
+                Some(quote @ ('\'' | '"')) => quote,
+                _ => return Err(self.error("expected a quoted value")),
+            };
+            self.position += 1;
+            let value = self.take_while(|c| c != quote).to_string();
+            if !self.eat(&quote.to_string()) {
+                return Err(self.error("unterminated string"));
+            }
+            Ok(format!("[{}=\"{}\"]", attribute, value.replace('\\', "\\\\").replace('"', "\\\"")))
+        } else if self.eat("last()") {
+            Ok(format!(":{}", last))
+        } else {
+            let index = self.take_while(|c| c.is_ascii_digit());
+            match index.parse::<usize>() {
+                Ok(index) if index > 0 => Ok(format!(":{}({})", nth, index)),
+                _ => Err(self.error("unsupported predicate")),
+            }
+        }
+    }
+}
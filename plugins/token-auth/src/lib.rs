@@ -0,0 +1,402 @@
+//! Token Authentication Plugin for Rusty Beam
+//!
+//! This plugin authenticates API clients by the bearer tokens they present
+//! (RFC 6750), checked against a token store in an HTML file that holds
+//! only hashes of the tokens. Tokens are created and revoked through HTTP
+//! endpoints, which the authorization plugin protects like any other path.
+//!
+//! ## Features
+//! - **Bearer Tokens**: `Authorization: Bearer <token>` identifies a request
+//!   as the token's owner, with the token's scopes as roles
+//! - **Hashed Store**: Only the SHA-256 of each token is stored, so the
+//!   token file does not give tokens away
+//! - **Expiry**: Tokens can expire at a given time
+//! - **Token Endpoints**: Tokens are listed, created and revoked over HTTP
+//!
+//! ## Configuration
+//! - `name`: Plugin instance name (default: "token-auth")
+//! - `tokenfile`: HTML file holding the token store (file:// URL or path,
+//!   required)
+//! - `token_path`: Path serving the token endpoints (default: none, disabled)
+//! - `allowed_scopes`: Comma-separated scopes new tokens may carry (default:
+//!   the roles of the user creating the token)
+//! - `token_days`: Days new tokens are valid unless the request says
+//!   otherwise, 0 for never (default: 90)
+//! - `admin_role`: Role whose users may list and revoke every user's tokens
+//!   (default: "administrators")
+//!
+//! ## Token Endpoints
+//! Under `token_path`:
+//! - `GET` lists the authenticated user's tokens as JSON, without their hashes
+//! - `POST` with a form body creates a token for the authenticated user,
+//!   with optional `id`, `scope` (repeatable) and `days` fields, and
+//!   answers 201 with the token, the only time it is shown
+//! - `DELETE {token_path}/{id}` revokes one of the authenticated user's tokens
+//!
+//! Users with the `admin_role` role list and revoke the tokens of every user.
+//!
+//! ## Request Metadata
+//! Requests with a valid token get:
+//! - `authenticated_user`: The owner of the token
+//! - `authenticated_user_roles`: The token's scopes, comma-separated
+//! - `token_id`: The id of the token
+//!
+//! Tokens of this plugin start with `rbk_`. Requests presenting one that is
+//! unknown or expired are answered 401, while other bearer tokens, such as
+//! those of the OAuth2 plugin, are left to the plugins they belong to.
+//!
+//! ## Pipeline Integration
+//! Authentication has to happen before the authorization plugin, but the
+//! token endpoints should be protected by it. Configure two instances with
+//! the same `tokenfile`: one before the authorization plugin, and one with
+//! `token_path` after it.
+
+mod store;
+
+use async_trait::async_trait;
+use chrono::{Duration as ChronoDuration, Utc};
+use hyper::header::{AUTHORIZATION, CACHE_CONTROL, CONTENT_TYPE, LOCATION, WWW_AUTHENTICATE};
+use hyper::{Body, Method, Response, StatusCode};
+use rusty_beam_plugin_api::validation::check_readable_file;
+use rusty_beam_plugin_api::{capabilities, create_plugin, log_error, log_info, Capabilities, ConfigIssue, Plugin, PluginContext, PluginRequest, PluginResponse};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use store::{hash_token, StoredToken, TokenStore, Tokens};
+
+// Default configuration values
+const DEFAULT_PLUGIN_NAME: &str = "token-auth";
+const DEFAULT_TOKEN_DAYS: i64 = 90;
+const DEFAULT_ADMIN_ROLE: &str = "administrators";
+
+// Configuration keys
+const CONFIG_KEY_NAME: &str = "name";
+const CONFIG_KEY_TOKEN_FILE: &str = "tokenfile";
+const CONFIG_KEY_TOKEN_PATH: &str = "token_path";
+const CONFIG_KEY_ALLOWED_SCOPES: &str = "allowed_scopes";
+const CONFIG_KEY_TOKEN_DAYS: &str = "token_days";
+const CONFIG_KEY_ADMIN_ROLE: &str = "admin_role";
+
+// Tokens
+const TOKEN_PREFIX: &str = "rbk_";
+const TOKEN_HEX_LENGTH: usize = 64;
+const MAX_ID_LENGTH: usize = 64;
+
+// Request metadata
+const AUTHENTICATED_USER_METADATA: &str = "authenticated_user";
+const AUTHENTICATED_USER_ROLES_METADATA: &str = "authenticated_user_roles";
+const TOKEN_ID_METADATA: &str = "token_id";
+
+// Content types
+const CONTENT_TYPE_JSON: &str = "application/json";
+const CONTENT_TYPE_PLAIN: &str = "text/plain; charset=utf-8";
+
+/// Plugin authenticating requests by bearer token
+#[derive(Debug)]
+pub struct TokenAuthPlugin {
+    name: String,
+    store: Option<TokenStore>,
+    token_path: Option<String>,
+    /// Scopes new tokens may carry; `None` limits them to the creator's roles
+    allowed_scopes: Option<Vec<String>>,
+    /// Days new tokens are valid by default; 0 never expires them
+    token_days: i64,
+    /// Role whose users manage every user's tokens
+    admin_role: String,
+}
+
+impl TokenAuthPlugin {
+    pub fn new(config: HashMap<String, String>) -> Self {
+        let name = config.get(CONFIG_KEY_NAME).cloned().unwrap_or_else(|| DEFAULT_PLUGIN_NAME.to_string());
+
+        let store = config.get(CONFIG_KEY_TOKEN_FILE)
+            .map(|file| TokenStore::new(PathBuf::from(file.strip_prefix("file://").unwrap_or(file))));
+        let token_path = config.get(CONFIG_KEY_TOKEN_PATH)
+            .map(|path| path.trim_end_matches('/').to_string())
+            .filter(|path| path.starts_with('/'));
+        let allowed_scopes = config.get(CONFIG_KEY_ALLOWED_SCOPES).map(|scopes| split_list(scopes));
+        let token_days = match config.get(CONFIG_KEY_TOKEN_DAYS).map(|days| days.trim().parse::<i64>()) {
+            None => DEFAULT_TOKEN_DAYS,
+            Some(Ok(days)) if days >= 0 => days,
+            Some(_) => {
+                log_error!(name, "Invalid token_days value, using {}", DEFAULT_TOKEN_DAYS);
+                DEFAULT_TOKEN_DAYS
+            }
+        };
+
+        let admin_role = config.get(CONFIG_KEY_ADMIN_ROLE)
+            .map(|role| role.trim().to_string())
+            .filter(|role| !role.is_empty())
+            .unwrap_or_else(|| DEFAULT_ADMIN_ROLE.to_string());
+
+        Self { name, store, token_path, allowed_scopes, token_days, admin_role }
+    }
+
+    /// The tokens in the store, or why they could not be read
+    fn load_tokens(&self) -> Result<Tokens, String> {
+        match &self.store {
+            Some(store) => store.load(),
+            None => Err("no tokenfile is configured".to_string()),
+        }
+    }
+
+    /// Check the bearer token of a request, recording its owner in the metadata
+    ///
+    /// Returns the 401 to answer with if the token is one of this plugin's
+    /// but is not valid.
+    fn authenticate(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<Response<Body>> {
+        let token = bearer_token(request).filter(|token| token.starts_with(TOKEN_PREFIX))?.to_string();
+        if !is_token(&token) {
+            return Some(invalid_token_response("malformed token"));
+        }
+        let tokens = match self.load_tokens() {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                log_error!(self.name, context, "Cannot check API token: {}", e);
+                return Some(invalid_token_response("token cannot be checked"));
+            }
+        };
+        let Some(stored) = tokens.find(&hash_token(&token)) else {
+            context.log_verbose("[TokenAuth] Rejected unknown or expired API token");
+            return Some(invalid_token_response("unknown or expired token"));
+        };
+
+        context.log_verbose(&format!("[TokenAuth] User '{}' authenticated with token '{}'", stored.owner, stored.id));
+        request.metadata.insert(AUTHENTICATED_USER_METADATA.to_string(), stored.owner.clone());
+        request.metadata.insert(AUTHENTICATED_USER_ROLES_METADATA.to_string(), stored.scopes.join(","));
+        request.metadata.insert(TOKEN_ID_METADATA.to_string(), stored.id.clone());
+        None
+    }
+
+    /// Whether the request's user may list and revoke every user's tokens
+    fn is_admin(&self, request: &PluginRequest) -> bool {
+        request.get_metadata(AUTHENTICATED_USER_ROLES_METADATA)
+            .is_some_and(|roles| split_list(roles).contains(&self.admin_role))
+    }
+
+    /// Answer a request for the token endpoints, if it is one
+    async fn handle_endpoint(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<Response<Body>> {
+        let token_path = self.token_path.as_deref()?;
+        if request.path == token_path {
+            return match *request.http_request.method() {
+                Method::GET => Some(self.list_tokens(request)),
+                Method::POST => Some(self.create_token(request, context).await),
+                _ => None,
+            };
+        }
+        let id = request.path.strip_prefix(token_path)?.strip_prefix('/')?.to_string();
+        match *request.http_request.method() {
+            Method::DELETE if !id.is_empty() => Some(self.revoke_token(request, &id, context)),
+            _ => None,
+        }
+    }
+
+    fn list_tokens(&self, request: &PluginRequest) -> Response<Body> {
+        let Some(user) = request.get_metadata(AUTHENTICATED_USER_METADATA) else {
+            return text_response(StatusCode::UNAUTHORIZED, "Tokens can only be managed by authenticated users");
+        };
+        let owner = (!self.is_admin(request)).then_some(user);
+        match self.load_tokens() {
+            Ok(tokens) => {
+                let list: Vec<serde_json::Value> = tokens.tokens.iter()
+                    .filter(|token| owner.is_none_or(|owner| token.owner == owner))
+                    .map(StoredToken::to_json)
+                    .collect();
+                json_response(StatusCode::OK, serde_json::Value::Array(list))
+            }
+            Err(e) => {
+                log_error!(self.name, "Cannot list API tokens: {}", e);
+                text_response(StatusCode::INTERNAL_SERVER_ERROR, "Token store unavailable")
+            }
+        }
+    }
+
+    async fn create_token(&self, request: &mut PluginRequest, context: &PluginContext) -> Response<Body> {
+        let Some(owner) = request.metadata.get(AUTHENTICATED_USER_METADATA).cloned() else {
+            return text_response(StatusCode::UNAUTHORIZED, "Tokens can only be created by authenticated users");
+        };
+        let roles = request.metadata.get(AUTHENTICATED_USER_ROLES_METADATA).map(|roles| split_list(roles)).unwrap_or_default();
+        let body = match request.get_body().await {
+            Ok(body) => body,
+            Err(e) => return text_response(StatusCode::BAD_REQUEST, &format!("Failed to read request body: {}", e)),
+        };
+        let fields: Vec<(String, String)> = url::form_urlencoded::parse(&body).into_owned().collect();
+        let field = |name: &str| fields.iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+
+        let id = field("id").unwrap_or_else(|| format!("{:08x}", rand::random::<u32>()));
+        if !is_valid_id(&id) {
+            return text_response(StatusCode::BAD_REQUEST, "Token ids may only contain letters, digits, '.', '_' and '-'");
+        }
+        let scopes: Vec<String> = fields.iter()
+            .filter(|(key, _)| key == "scope")
+            .flat_map(|(_, value)| split_list(value))
+            .collect();
+        let permitted = self.allowed_scopes.as_ref().unwrap_or(&roles);
+        if let Some(scope) = scopes.iter().find(|scope| !permitted.contains(scope)) {
+            return text_response(StatusCode::FORBIDDEN, &format!("Tokens may not carry the scope '{}'", scope));
+        }
+        let days = match field("days").map(|days| days.parse::<i64>()) {
+            None => self.token_days,
+            Some(Ok(days)) if (0..=36500).contains(&days) => days,
+            Some(_) => return text_response(StatusCode::BAD_REQUEST, "days must be a number of days, or 0 for a token that never expires"),
+        };
+
+        let Some(store) = &self.store else {
+            return text_response(StatusCode::INTERNAL_SERVER_ERROR, "Token store unavailable");
+        };
+        match self.load_tokens() {
+            Ok(tokens) if tokens.get(&id).is_some() => {
+                return text_response(StatusCode::CONFLICT, &format!("A token with the id '{}' already exists", id));
+            }
+            Ok(_) => {}
+            Err(e) => {
+                log_error!(self.name, "Cannot create API token: {}", e);
+                return text_response(StatusCode::INTERNAL_SERVER_ERROR, "Token store unavailable");
+            }
+        }
+
+        let token = new_token();
+        let stored = StoredToken {
+            id,
+            owner,
+            token_hash: hash_token(&token),
+            scopes,
+            expires: (days > 0).then(|| Utc::now() + ChronoDuration::days(days)),
+        };
+        if let Err(e) = store.add(&stored) {
+            log_error!(self.name, "Cannot create API token: {}", e);
+            return text_response(StatusCode::INTERNAL_SERVER_ERROR, "Token store unavailable");
+        }
+        log_info!(self.name, context, "Created API token"; id = stored.id, owner = stored.owner);
+
+        let mut answer = stored.to_json();
+        answer["token"] = serde_json::Value::String(token);
+        let mut response = json_response(StatusCode::CREATED, answer);
+        if let Ok(location) = format!("{}/{}", request.path, stored.id).parse() {
+            response.headers_mut().insert(LOCATION, location);
+        }
+        response
+    }
+
+    fn revoke_token(&self, request: &PluginRequest, id: &str, context: &PluginContext) -> Response<Body> {
+        let Some(user) = request.get_metadata(AUTHENTICATED_USER_METADATA) else {
+            return text_response(StatusCode::UNAUTHORIZED, "Tokens can only be managed by authenticated users");
+        };
+        let owner = (!self.is_admin(request)).then_some(user);
+        let Some(store) = &self.store else {
+            return text_response(StatusCode::INTERNAL_SERVER_ERROR, "Token store unavailable");
+        };
+        // Other users' tokens are answered as missing, so their ids stay private
+        match store.remove(id, owner) {
+            Ok(true) => {
+                log_info!(self.name, context, "Revoked API token"; id = id);
+                Response::builder()
+                    .status(StatusCode::NO_CONTENT)
+                    .body(Body::empty())
+                    .unwrap()
+            }
+            Ok(false) => text_response(StatusCode::NOT_FOUND, &format!("No token with the id '{}'", id)),
+            Err(e) => {
+                log_error!(self.name, "Cannot revoke API token: {}", e);
+                text_response(StatusCode::INTERNAL_SERVER_ERROR, "Token store unavailable")
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl Plugin for TokenAuthPlugin {
+    async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
+        // A user authenticated by an earlier plugin keeps that identity
+        if !request.metadata.contains_key(AUTHENTICATED_USER_METADATA) {
+            if let Some(response) = self.authenticate(request, context) {
+                return Some(response.into());
+            }
+        }
+        self.handle_endpoint(request, context).await.map(Into::into)
+    }
+
+    fn validate_config(&self, _context: &PluginContext) -> Vec<ConfigIssue> {
+        let Some(store) = &self.store else {
+            return vec![ConfigIssue::error("no tokenfile is configured, so no token is accepted")];
+        };
+        if let Some(issue) = check_readable_file(CONFIG_KEY_TOKEN_FILE, &store.path().display().to_string()) {
+            return vec![issue];
+        }
+        match store.load() {
+            Ok(tokens) => tokens.problems.into_iter().map(ConfigIssue::warning).collect(),
+            Err(e) => vec![ConfigIssue::error(e)],
+        }
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::new(&[capabilities::AUTHENTICATION], &[])
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// The token of an `Authorization: Bearer` header
+fn bearer_token(request: &PluginRequest) -> Option<&str> {
+    let value = request.http_request.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    let (scheme, token) = value.trim().split_once(' ')?;
+    scheme.eq_ignore_ascii_case("bearer").then(|| token.trim())
+}
+
+/// A new token: 256 random bits from the thread's CSPRNG, as prefixed hex
+fn new_token() -> String {
+    format!("{}{:032x}{:032x}", TOKEN_PREFIX, rand::random::<u128>(), rand::random::<u128>())
+}
+
+/// Whether a presented token has the form of those `new_token` returns
+fn is_token(token: &str) -> bool {
+    token.strip_prefix(TOKEN_PREFIX).is_some_and(|hex| {
+        hex.len() == TOKEN_HEX_LENGTH && hex.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+    })
+}
+
+fn is_valid_id(id: &str) -> bool {
+    id.len() <= MAX_ID_LENGTH && id.bytes().all(|b| b.is_ascii_alphanumeric() || matches!(b, b'.' | b'_' | b'-'))
+}
+
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// The 401 for an invalid token, with the challenge RFC 6750 describes
+fn invalid_token_response(description: &str) -> Response<Body> {
+    Response::builder()
+        .status(StatusCode::UNAUTHORIZED)
+        .header(WWW_AUTHENTICATE, format!("Bearer error=\"invalid_token\", error_description=\"{}\"", description))
+        .header(CONTENT_TYPE, CONTENT_TYPE_PLAIN)
+        .header(CACHE_CONTROL, "no-store")
+        .body(Body::from("401 Unauthorized: Invalid API token"))
+        .unwrap()
+}
+
+fn text_response(status: StatusCode, text: &str) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, CONTENT_TYPE_PLAIN)
+        .header(CACHE_CONTROL, "no-store")
+        .body(Body::from(text.to_string()))
+        .unwrap()
+}
+
+fn json_response(status: StatusCode, json: serde_json::Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header(CONTENT_TYPE, CONTENT_TYPE_JSON)
+        .header(CACHE_CONTROL, "no-store")
+        .body(Body::from(json.to_string()))
+        .unwrap()
+}
+
+create_plugin!(TokenAuthPlugin);
@@ -0,0 +1,233 @@
+//! Token store
+//!
+//! Tokens are kept as `https://rustybeam.net/schema/ApiToken` microdata
+//! items in an HTML file, like users and authorization rules:
+//! - `id`: Name of the token, used to revoke it (required, unique)
+//! - `owner`: Username requests with the token are made as (required)
+//! - `tokenHash`: Hex SHA-256 of the token (required)
+//! - `scope`: Scope of the token, which becomes a role of its requests;
+//!   there can be several
+//! - `expires`: When the token stops being accepted, as an RFC 3339 time or
+//!   a date (default: never)
+//!
+//! The file is read on every lookup, so edits take effect immediately and
+//! every plugin instance naming the file sees the same tokens. Items the
+//! plugin adds copy the markup of the last item in the file, a table row or
+//! a `div`, so hand-written files keep their layout.
+
+use chrono::{DateTime, NaiveDate, SecondsFormat, Utc};
+use dom_query::Document;
+use microdata_extract::{MicrodataExtractor, MicrodataItem};
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+pub const SCHEMA_API_TOKEN: &str = "https://rustybeam.net/schema/ApiToken";
+const NEW_TOKEN_MARKER: &str = "__RUSTY_BEAM_NEW_API_TOKEN_";
+
+/// A token as recorded in the store, which never holds the token itself
+#[derive(Debug, Clone)]
+pub struct StoredToken {
+    pub id: String,
+    pub owner: String,
+    pub token_hash: String,
+    pub scopes: Vec<String>,
+    pub expires: Option<DateTime<Utc>>,
+}
+
+impl StoredToken {
+    fn from_item(item: &MicrodataItem) -> Result<Self, String> {
+        let property = |name: &str| item.get_property(name)
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty());
+
+        let id = property("id").ok_or("API token without an id")?;
+        let owner = property("owner").ok_or_else(|| format!("API token '{}' has no owner", id))?;
+        let token_hash = property("tokenHash")
+            .map(|hash| hash.to_lowercase())
+            .filter(|hash| hash.len() == 64 && hash.bytes().all(|b| b.is_ascii_hexdigit()))
+            .ok_or_else(|| format!("API token '{}' needs a tokenHash of 64 hex digits", id))?;
+        let expires = match property("expires") {
+            Some(expires) => Some(parse_time(&expires)
+                .ok_or_else(|| format!("API token '{}' has an invalid expiry '{}'", id, expires))?),
+            None => None,
+        };
+        let scopes = item.get_property_values("scope").iter()
+            .map(|scope| scope.trim().to_string())
+            .filter(|scope| !scope.is_empty())
+            .collect();
+
+        Ok(Self { id, owner, token_hash, scopes, expires })
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires.is_some_and(|expires| expires <= Utc::now())
+    }
+
+    /// The token as listed by the token endpoints
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "id": self.id,
+            "owner": self.owner,
+            "scopes": self.scopes,
+            "expires": self.expires.map(format_time),
+        })
+    }
+
+    /// The token as an item, in the markup of a table row or a `div`
+    fn to_html(&self, as_row: bool) -> String {
+        let (item, property) = if as_row { ("tr", "td") } else { ("div", "span") };
+        let mut html = format!("<{} itemscope itemtype=\"{}\">", item, SCHEMA_API_TOKEN);
+        html.push_str(&format!("<{0} itemprop=\"id\">{1}</{0}>", property, html_escape(&self.id)));
+        html.push_str(&format!("<{0} itemprop=\"owner\">{1}</{0}>", property, html_escape(&self.owner)));
+        html.push_str(&format!("<{0} itemprop=\"tokenHash\">{1}</{0}>", property, self.token_hash));
+        let scopes: String = self.scopes.iter()
+            .map(|scope| format!("<li itemprop=\"scope\">{}</li>", html_escape(scope)))
+            .collect();
+        html.push_str(&format!("<{0}><ul>{1}</ul></{0}>", property, scopes));
+        let expires = self.expires.map(format_time).unwrap_or_default();
+        html.push_str(&format!("<{0} itemprop=\"expires\">{1}</{0}>", property, expires));
+        html.push_str(&format!("</{}>", item));
+        html
+    }
+}
+
+/// Tokens in the store, and the items that were not valid tokens
+#[derive(Debug, Default)]
+pub struct Tokens {
+    pub tokens: Vec<StoredToken>,
+    pub problems: Vec<String>,
+}
+
+impl Tokens {
+    /// The unexpired token with a hash, compared in constant time
+    pub fn find(&self, token_hash: &str) -> Option<&StoredToken> {
+        self.tokens.iter()
+            .filter(|token| constant_time_eq(token.token_hash.as_bytes(), token_hash.as_bytes()))
+            .find(|token| !token.is_expired())
+    }
+
+    pub fn get(&self, id: &str) -> Option<&StoredToken> {
+        self.tokens.iter().find(|token| token.id == id)
+    }
+}
+
+/// The HTML file tokens are kept in
+#[derive(Debug)]
+pub struct TokenStore {
+    path: PathBuf,
+    /// Held while the file is rewritten, so concurrent changes are not lost
+    write_lock: Mutex<()>,
+}
+
+impl TokenStore {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, write_lock: Mutex::new(()) }
+    }
+
+    pub fn path(&self) -> &std::path::Path {
+        &self.path
+    }
+
+    /// Read the tokens in the file
+    pub fn load(&self) -> Result<Tokens, String> {
+        let content = std::fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read token file: {}", e))?;
+        let items = MicrodataExtractor::new().extract(&content)
+            .map_err(|e| format!("Failed to parse token file: {}", e))?;
+
+        let mut tokens = Tokens::default();
+        for item in items.iter().filter(|item| item.item_type() == Some(SCHEMA_API_TOKEN)) {
+            match StoredToken::from_item(item) {
+                Ok(token) if tokens.get(&token.id).is_some() => {
+                    tokens.problems.push(format!("API token id '{}' is used more than once", token.id));
+                }
+                Ok(token) => tokens.tokens.push(token),
+                Err(e) => tokens.problems.push(e),
+            }
+        }
+        Ok(tokens)
+    }
+
+    /// Add a token after the last one in the file
+    pub fn add(&self, token: &StoredToken) -> Result<(), String> {
+        self.rewrite(|document| {
+            let items = document.select(&item_selector());
+            let Some(last) = items.nodes().last() else {
+                document.select("body").append_html(token.to_html(false));
+                return Some(document.html().to_string());
+            };
+            // Table rows do not survive being parsed as a fragment, so the
+            // item is put in place of a marker in the serialized document
+            let as_row = last.node_name().is_some_and(|name| name.eq_ignore_ascii_case("tr"));
+            let marker = format!("{}{}__", NEW_TOKEN_MARKER, std::process::id());
+            last.after_html(marker.as_str());
+            Some(document.html().replace(&marker, &token.to_html(as_row)))
+        })
+    }
+
+    /// Remove a token, only if `owner` owns it when given, returning whether
+    /// the file had it
+    pub fn remove(&self, id: &str, owner: Option<&str>) -> Result<bool, String> {
+        let mut removed = false;
+        self.rewrite(|document| {
+            for item in document.select(&item_selector()).iter() {
+                if item.select(r#"[itemprop="id"]"#).text().trim() == id
+                    && owner.is_none_or(|owner| item.select(r#"[itemprop="owner"]"#).text().trim() == owner)
+                {
+                    item.remove();
+                    removed = true;
+                }
+            }
+            removed.then(|| document.html().to_string())
+        })?;
+        Ok(removed)
+    }
+
+    /// Apply a change to the file, writing back the content the change returns
+    fn rewrite(&self, change: impl FnOnce(&Document) -> Option<String>) -> Result<(), String> {
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+        let content = std::fs::read_to_string(&self.path)
+            .map_err(|e| format!("Failed to read token file: {}", e))?;
+        let Some(content) = change(&Document::from(content.as_str())) else {
+            return Ok(());
+        };
+        std::fs::write(&self.path, content)
+            .map_err(|e| format!("Failed to write token file: {}", e))
+    }
+}
+
+/// Hex SHA-256 of a token, as recorded in the store
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+/// Parse an RFC 3339 time, or a date meaning its start in UTC
+pub fn parse_time(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Some(time.with_timezone(&Utc));
+    }
+    NaiveDate::parse_from_str(value, "%Y-%m-%d").ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc())
+}
+
+pub fn format_time(time: DateTime<Utc>) -> String {
+    time.to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn item_selector() -> String {
+    format!(r#"[itemtype="{}"]"#, SCHEMA_API_TOKEN)
+}
+
+/// Compares two byte strings in time independent of where they differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+fn html_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,450 @@
+//! WebSocket Hub Plugin for Rusty Beam
+//!
+//! This plugin is a general-purpose publish/subscribe hub over WebSocket.
+//! Where the websocket plugin broadcasts DOM updates to the clients viewing a
+//! document, the hub carries arbitrary messages on named channels, published
+//! by clients or by other plugins.
+//!
+//! ## Features
+//! - **Named Channels**: Clients subscribe through the connection URL or by
+//!   sending subscribe messages
+//! - **Channel Authorization**: Subscribing and publishing are checked against
+//!   the host's access-control plugins, such as the authorization plugin
+//! - **Server-Side Publishing**: Other plugins publish with
+//!   `PluginContext::publish`
+//!
+//! ## Configuration
+//! - `path`: URL path the hub is served at (default: "/hub")
+//! - `channel_path`: Path prefix under which channel permissions are checked
+//!   (default: the value of `path`)
+//! - `max_subscriptions`: Channels one connection may subscribe to (default: 32)
+//! - `queue_size`: Messages buffered for a slow connection before further
+//!   messages to it are dropped (default: 256)
+//!
+//! ## Connecting
+//! A WebSocket connection to `/hub/news` subscribes to the channel `news`, and
+//! one to `/hub?channel=news&channel=alerts` to both channels. The connection
+//! is refused with 403 if any of them may not be subscribed to. Channel names
+//! are made of letters, digits, `-`, `_`, `.` and `~`, and may be divided
+//! into segments with `/`.
+//!
+//! ## Messages
+//! Clients send JSON text frames:
+//! - `{"action": "subscribe", "channel": "news"}`
+//! - `{"action": "unsubscribe", "channel": "news"}`
+//! - `{"action": "publish", "channel": "news", "data": "..."}`
+//!
+//! The hub answers each with a `subscribed`, `unsubscribed`, `published` or
+//! `error` frame, and delivers publications as
+//! `{"type": "message", "channel": "news", "data": "..."}`. Data that is not
+//! a string is delivered as its JSON text.
+//!
+//! ## Channel Authorization
+//! A channel's permissions are those of the path `channel_path` followed by
+//! the channel name: subscribing to `news` needs GET on `/hub/news` and
+//! publishing needs POST on it, for the user the connecting request was
+//! authenticated as. Permissions are checked when subscribing and on every
+//! publish. Messages published by plugins are not checked.
+
+use async_trait::async_trait;
+use futures_util::{SinkExt, StreamExt};
+use hyper::{Body, Response, StatusCode};
+use rusty_beam_plugin_api::{create_plugin, Plugin, PluginContext, PluginRequest, PluginResponse, UpgradeHandler};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc;
+use tokio_tungstenite::{
+    tungstenite::{handshake::derive_accept_key, protocol::Role, Message as WsMessage},
+    WebSocketStream,
+};
+
+// Defaults
+const DEFAULT_PLUGIN_NAME: &str = "websocket-hub";
+const DEFAULT_PATH: &str = "/hub";
+const DEFAULT_MAX_SUBSCRIPTIONS: usize = 32;
+const DEFAULT_QUEUE_SIZE: usize = 256;
+const MAX_CHANNEL_LENGTH: usize = 128;
+
+// Channel permissions
+const SUBSCRIBE_METHOD: &str = "GET";
+const PUBLISH_METHOD: &str = "POST";
+
+// WebSocket handshake
+const WEBSOCKET_VERSION: &str = "13";
+const WEBSOCKET_PROTOCOL: &str = "websocket";
+const WS_KEY_HEADER: &str = "Sec-WebSocket-Key";
+const WS_VERSION_HEADER: &str = "Sec-WebSocket-Version";
+const WS_ACCEPT_HEADER: &str = "Sec-WebSocket-Accept";
+
+// Request parameters and metadata
+const CHANNEL_QUERY_PARAM: &str = "channel";
+const AUTHENTICATED_USER_KEY: &str = "authenticated_user";
+
+// Error codes sent to clients
+const ERROR_FORBIDDEN: &str = "forbidden";
+const ERROR_INVALID_CHANNEL: &str = "invalid_channel";
+const ERROR_INVALID_MESSAGE: &str = "invalid_message";
+const ERROR_TOO_MANY_SUBSCRIPTIONS: &str = "too_many_subscriptions";
+
+/// Senders of the connections subscribed to each channel
+type Subscribers = HashMap<String, HashMap<u64, mpsc::Sender<String>>>;
+
+/// Channel state shared with the connections' upgrade handlers
+#[derive(Debug)]
+struct Hub {
+    path: String,
+    channel_path: String,
+    max_subscriptions: usize,
+    queue_size: usize,
+    subscribers: Mutex<Subscribers>,
+    next_connection: AtomicU64,
+}
+
+/// One client connection's view of the hub
+struct Connection {
+    id: u64,
+    sender: mpsc::Sender<String>,
+    subscriptions: HashSet<String>,
+    user: Option<String>,
+    context: PluginContext,
+}
+
+impl Hub {
+    /// Extract the channel named by the path, or None if the path is not the hub's
+    fn channel_from_path<'a>(&self, path: &'a str) -> Option<&'a str> {
+        let rest = path.strip_prefix(self.path.as_str())?;
+        if rest.is_empty() {
+            return Some("");
+        }
+        rest.strip_prefix('/')
+    }
+
+    /// The path whose permissions govern a channel
+    fn permission_path(&self, channel: &str) -> String {
+        format!("{}/{}", self.channel_path, channel)
+    }
+
+    /// Ask the host's access-control plugins whether a channel operation is allowed
+    fn is_allowed(&self, user: Option<&str>, method: &str, channel: &str, context: &PluginContext) -> bool {
+        match &context.services {
+            Some(services) => services.is_allowed(user, method, &self.permission_path(channel), context),
+            None => true,
+        }
+    }
+
+    fn subscribe(&self, channel: &str, connection: u64, sender: &mpsc::Sender<String>) {
+        self.subscribers.lock().unwrap()
+            .entry(channel.to_string())
+            .or_default()
+            .insert(connection, sender.clone());
+    }
+
+    fn unsubscribe(&self, channel: &str, connection: u64) {
+        let mut subscribers = self.subscribers.lock().unwrap();
+        if let Some(connections) = subscribers.get_mut(channel) {
+            connections.remove(&connection);
+            if connections.is_empty() {
+                subscribers.remove(channel);
+            }
+        }
+    }
+
+    /// Queue a message for every subscriber of a channel, returning how many it reached
+    fn deliver(&self, channel: &str, message: &str) -> usize {
+        let frame = json!({
+            "type": "message",
+            "channel": channel,
+            "data": message,
+        }).to_string();
+
+        let subscribers = self.subscribers.lock().unwrap();
+        let Some(connections) = subscribers.get(channel) else {
+            return 0;
+        };
+        // A connection whose queue is full misses the message rather than
+        // holding up the publisher
+        connections.values()
+            .filter(|sender| sender.try_send(frame.clone()).is_ok())
+            .count()
+    }
+
+    /// Answer one text frame from a client
+    fn handle_client_message(&self, text: &str, connection: &mut Connection) -> Value {
+        let Ok(message) = serde_json::from_str::<Value>(text) else {
+            return error_frame(ERROR_INVALID_MESSAGE, None);
+        };
+        let action = message.get("action").and_then(Value::as_str);
+        let Some(channel) = message.get("channel").and_then(Value::as_str) else {
+            return error_frame(ERROR_INVALID_MESSAGE, None);
+        };
+        if !is_valid_channel(channel) {
+            return error_frame(ERROR_INVALID_CHANNEL, Some(channel));
+        }
+        let user = connection.user.as_deref();
+
+        match action {
+            Some("subscribe") => {
+                if !connection.subscriptions.contains(channel) {
+                    if connection.subscriptions.len() >= self.max_subscriptions {
+                        return error_frame(ERROR_TOO_MANY_SUBSCRIPTIONS, Some(channel));
+                    }
+                    if !self.is_allowed(user, SUBSCRIBE_METHOD, channel, &connection.context) {
+                        return error_frame(ERROR_FORBIDDEN, Some(channel));
+                    }
+                    self.subscribe(channel, connection.id, &connection.sender);
+                    connection.subscriptions.insert(channel.to_string());
+                }
+                json!({"type": "subscribed", "channel": channel})
+            }
+            Some("unsubscribe") => {
+                if connection.subscriptions.remove(channel) {
+                    self.unsubscribe(channel, connection.id);
+                }
+                json!({"type": "unsubscribed", "channel": channel})
+            }
+            Some("publish") => {
+                let data = match message.get("data") {
+                    Some(Value::String(data)) => data.clone(),
+                    Some(data) => data.to_string(),
+                    None => return error_frame(ERROR_INVALID_MESSAGE, Some(channel)),
+                };
+                if !self.is_allowed(user, PUBLISH_METHOD, channel, &connection.context) {
+                    return error_frame(ERROR_FORBIDDEN, Some(channel));
+                }
+                let recipients = self.deliver(channel, &data);
+                json!({"type": "published", "channel": channel, "recipients": recipients})
+            }
+            _ => error_frame(ERROR_INVALID_MESSAGE, Some(channel)),
+        }
+    }
+
+    /// Serve one client from its upgraded connection until it goes away
+    async fn run_connection(
+        &self,
+        mut ws_stream: WebSocketStream<hyper::upgrade::Upgraded>,
+        channels: Vec<String>,
+        user: Option<String>,
+        context: PluginContext,
+    ) {
+        let (sender, mut receiver) = mpsc::channel(self.queue_size);
+        let mut connection = Connection {
+            id: self.next_connection.fetch_add(1, Ordering::SeqCst),
+            sender,
+            subscriptions: HashSet::new(),
+            user,
+            context,
+        };
+        for channel in channels {
+            self.subscribe(&channel, connection.id, &connection.sender);
+            connection.subscriptions.insert(channel);
+        }
+        connection.context.log_verbose(&format!(
+            "[WebSocketHub] Connection {} subscribed to {:?}",
+            connection.id, connection.subscriptions
+        ));
+
+        loop {
+            tokio::select! {
+                message = ws_stream.next() => {
+                    let reply = match message {
+                        Some(Ok(WsMessage::Text(text))) => {
+                            WsMessage::Text(self.handle_client_message(&text, &mut connection).to_string())
+                        }
+                        Some(Ok(WsMessage::Ping(data))) => WsMessage::Pong(data),
+                        Some(Ok(WsMessage::Close(_))) | Some(Err(_)) | None => break,
+                        Some(Ok(_)) => continue,
+                    };
+                    if ws_stream.send(reply).await.is_err() {
+                        break;
+                    }
+                }
+                // The connection holds a sender itself, so the queue never closes
+                Some(frame) = receiver.recv() => {
+                    if ws_stream.send(WsMessage::Text(frame)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+        }
+
+        for channel in &connection.subscriptions {
+            self.unsubscribe(channel, connection.id);
+        }
+        connection.context.log_verbose(&format!("[WebSocketHub] Connection {} closed", connection.id));
+    }
+}
+
+/// Whether a channel name is made of allowed segments
+fn is_valid_channel(channel: &str) -> bool {
+    !channel.is_empty()
+        && channel.len() <= MAX_CHANNEL_LENGTH
+        && channel.split('/').all(|segment| {
+            !segment.is_empty()
+                && segment != "."
+                && segment != ".."
+                && segment.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '~'))
+        })
+}
+
+/// A frame reporting why a client message was refused
+fn error_frame(error: &str, channel: Option<&str>) -> Value {
+    json!({"type": "error", "error": error, "channel": channel})
+}
+
+/// A JSON error response refusing a connection
+fn error_response(status: StatusCode, error: &str, channel: Option<&str>) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("Content-Type", "application/json")
+        .body(Body::from(json!({"error": error, "channel": channel}).to_string()))
+        .unwrap()
+}
+
+/// Plugin serving publish/subscribe channels over WebSocket
+#[derive(Debug)]
+pub struct WebSocketHubPlugin {
+    name: String,
+    hub: Arc<Hub>,
+}
+
+impl WebSocketHubPlugin {
+    pub fn new(config: HashMap<String, String>) -> Self {
+        let name = config.get("name").cloned().unwrap_or_else(|| DEFAULT_PLUGIN_NAME.to_string());
+
+        let path = config.get("path")
+            .map(|v| format!("/{}", v.trim().trim_matches('/')))
+            .filter(|v| v != "/")
+            .unwrap_or_else(|| DEFAULT_PATH.to_string());
+
+        let channel_path = config.get("channel_path")
+            .map(|v| v.trim().trim_end_matches('/').to_string())
+            .unwrap_or_else(|| path.clone());
+
+        let max_subscriptions = config.get("max_subscriptions")
+            .and_then(|v| v.parse().ok())
+            .filter(|&max: &usize| max > 0)
+            .unwrap_or(DEFAULT_MAX_SUBSCRIPTIONS);
+
+        let queue_size = config.get("queue_size")
+            .and_then(|v| v.parse().ok())
+            .filter(|&size: &usize| size > 0)
+            .unwrap_or(DEFAULT_QUEUE_SIZE);
+
+        Self {
+            name,
+            hub: Arc::new(Hub {
+                path,
+                channel_path,
+                max_subscriptions,
+                queue_size,
+                subscribers: Mutex::new(HashMap::new()),
+                next_connection: AtomicU64::new(1),
+            }),
+        }
+    }
+
+    /// Collect the channels a connecting request subscribes to
+    fn requested_channels(&self, request: &PluginRequest, path_channel: &str) -> Vec<String> {
+        let mut channels: Vec<String> = Vec::new();
+        if !path_channel.is_empty() {
+            channels.push(path_channel.to_string());
+        }
+        if let Some(query) = request.http_request.uri().query() {
+            for (key, value) in url::form_urlencoded::parse(query.as_bytes()) {
+                if key == CHANNEL_QUERY_PARAM && !channels.iter().any(|channel| *channel == value) {
+                    channels.push(value.into_owned());
+                }
+            }
+        }
+        channels
+    }
+
+    /// Check a connecting request and accept it as a WebSocket
+    fn handle_connect(
+        &self,
+        request: &PluginRequest,
+        channels: &[String],
+        user: Option<&str>,
+        context: &PluginContext,
+    ) -> Response<Body> {
+        let headers = request.http_request.headers();
+        let header = |name| headers.get(name).and_then(|v| v.to_str().ok());
+        let is_upgrade = header(hyper::header::CONNECTION.as_str())
+            .is_some_and(|v| v.to_ascii_lowercase().contains("upgrade"))
+            && header(hyper::header::UPGRADE.as_str()).is_some_and(|v| v.eq_ignore_ascii_case(WEBSOCKET_PROTOCOL))
+            && header(WS_VERSION_HEADER) == Some(WEBSOCKET_VERSION);
+        let key = match header(WS_KEY_HEADER) {
+            Some(key) if is_upgrade => key,
+            _ => {
+                return Response::builder()
+                    .status(StatusCode::UPGRADE_REQUIRED)
+                    .header(hyper::header::UPGRADE, WEBSOCKET_PROTOCOL)
+                    .header(hyper::header::CONNECTION, "Upgrade")
+                    .body(Body::from("This endpoint only accepts WebSocket connections"))
+                    .unwrap();
+            }
+        };
+
+        if let Some(channel) = channels.iter().find(|channel| !is_valid_channel(channel)) {
+            return error_response(StatusCode::BAD_REQUEST, ERROR_INVALID_CHANNEL, Some(channel));
+        }
+        if channels.len() > self.hub.max_subscriptions {
+            return error_response(StatusCode::BAD_REQUEST, ERROR_TOO_MANY_SUBSCRIPTIONS, None);
+        }
+        if let Some(channel) = channels.iter()
+            .find(|channel| !self.hub.is_allowed(user, SUBSCRIBE_METHOD, channel, context))
+        {
+            context.log_verbose(&format!("[WebSocketHub] Subscription to '{}' denied", channel));
+            return error_response(StatusCode::FORBIDDEN, ERROR_FORBIDDEN, Some(channel));
+        }
+
+        Response::builder()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(hyper::header::CONNECTION, "Upgrade")
+            .header(hyper::header::UPGRADE, WEBSOCKET_PROTOCOL)
+            .header(WS_ACCEPT_HEADER, derive_accept_key(key.as_bytes()))
+            .body(Body::empty())
+            .unwrap()
+    }
+}
+
+#[async_trait]
+impl Plugin for WebSocketHubPlugin {
+    async fn handle_request(&self, request: &mut PluginRequest, context: &PluginContext) -> Option<PluginResponse> {
+        let path_channel = self.hub.channel_from_path(&request.path)?;
+        let channels = self.requested_channels(request, path_channel);
+        let user = request.get_metadata(AUTHENTICATED_USER_KEY).map(str::to_string);
+        let response = self.handle_connect(request, &channels, user.as_deref(), context);
+        if response.status() != StatusCode::SWITCHING_PROTOCOLS {
+            return Some(response.into());
+        }
+
+        let hub = Arc::clone(&self.hub);
+        let context = context.clone();
+        let upgrade_handler: UpgradeHandler = Box::new(move |upgraded| {
+            Box::pin(async move {
+                let ws_stream = WebSocketStream::from_raw_socket(upgraded, Role::Server, None).await;
+                hub.run_connection(ws_stream, channels, user, context).await;
+                Ok(())
+            })
+        });
+
+        Some(PluginResponse {
+            response,
+            upgrade: Some(upgrade_handler),
+        })
+    }
+
+    fn publish(&self, channel: &str, message: &str) -> usize {
+        self.hub.deliver(channel, message)
+    }
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+// Export the plugin creation function
+create_plugin!(WebSocketHubPlugin);
@@ -10,14 +10,45 @@
 //! - Real-time broadcasting of content updates
 //! - Connection health monitoring with ping/pong support
 //! - Efficient connection management using DashMap
+//! - Selector subscriptions with an initial snapshot and per-subscription
+//!   permission checks
+//!
+//! ## Selector Subscriptions
+//! Besides the StreamItem broadcasts for the document it connected to, a
+//! client can follow the elements a selector picks from any document by
+//! sending JSON text frames:
+//! - `{"subscribe": {"path": "/list.html", "selector": "#items"}}`
+//! - `{"unsubscribe": {"path": "/list.html", "selector": "#items"}}`
+//!
+//! A subscription is answered with a snapshot of the elements the selector
+//! currently matches, `{"type": "snapshot", "path": ..., "selector": ...,
+//! "content": "..."}`, whose content is empty while nothing matches. After
+//! every write to the document the elements are read again, and if they
+//! changed the client receives `{"type": "delta", "path": ..., "selector":
+//! ..., "method": "PUT", "content": "..."}` with the method of the write and
+//! the elements as they are now. An unsubscription is answered with
+//! `{"type": "unsubscribed", ...}`.
+//!
+//! Snapshots and deltas are read as a selector GET by the user the connecting
+//! request was authenticated as, so the authorization plugin decides for
+//! every subscription, and again for every delta. A subscription the user may
+//! not read is refused, or ended when the rules change, with
+//! `{"type": "error", "error": "forbidden", ...}`. Other errors are
+//! `invalid_message`, `too_many_subscriptions` and `unavailable`, the latter
+//! when the document cannot be read. Text frames without a `subscribe` or
+//! `unsubscribe` member are ignored.
 
 use async_trait::async_trait;
 use dashmap::DashMap;
 use hyper::{Body, Response, StatusCode};
-use rusty_beam_plugin_api::{create_plugin, Plugin, PluginContext, PluginRequest, PluginResponse, UpgradeHandler};
-use std::collections::HashMap;
+use rusty_beam_plugin_api::{
+    create_plugin, log_debug, paths::normalize_request_path, DocumentChange, Plugin, PluginContext,
+    PluginRequest, PluginResponse, SelectorOperation, UpgradeHandler,
+};
+use serde_json::{json, Value};
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, mpsc};
 use tokio_tungstenite::{
     tungstenite::{
         handshake::derive_accept_key,
@@ -56,7 +87,19 @@ const WS_ACCEPT_HEADER: &str = "Sec-WebSocket-Accept";
 const STREAM_ITEM_SCHEMA: &str = "http://rustybeam.net/StreamItem";
 
 // HTTP methods that trigger broadcasts to WebSocket clients
-const BROADCAST_METHODS: &[&str] = &["PUT", "POST", "DELETE"];
+const BROADCAST_METHODS: &[&str] = &["PUT", "POST", "PATCH", "DELETE"];
+
+// Selector subscriptions
+const MAX_SUBSCRIPTIONS: usize = 32;
+const SUBSCRIBE_METHOD: &str = "GET";
+const AUTHENTICATED_USER_KEY: &str = "authenticated_user";
+
+// Error codes sent to clients
+const ERROR_FORBIDDEN: &str = "forbidden";
+const ERROR_INVALID_MESSAGE: &str = "invalid_message";
+const ERROR_INVALID_PATH: &str = "invalid_path";
+const ERROR_TOO_MANY_SUBSCRIPTIONS: &str = "too_many_subscriptions";
+const ERROR_UNAVAILABLE: &str = "unavailable";
 
 /// WebSocket plugin that manages real-time connections and broadcasts updates
 #[derive(Debug, Clone)]
@@ -72,6 +115,60 @@ struct ConnectionState {
     url: String,
     /// Channel sender for broadcasting messages to this connection
     tx: broadcast::Sender<WsMessage>,
+    /// Normalized URLs of the documents this connection has selector subscriptions on
+    documents: HashSet<String>,
+    /// Channel sender for the changes made to those documents
+    changes: mpsc::Sender<DocumentChange>,
+}
+
+/// The elements a selector picks from a document, followed by one connection
+#[derive(Debug)]
+struct Subscription {
+    path: String,
+    selector: String,
+    /// The elements as last sent to the client
+    content: String,
+}
+
+/// Why the elements of a subscription could not be read
+enum ReadError {
+    Forbidden,
+    Unavailable,
+}
+
+/// The client of one connection and its selector subscriptions
+struct Subscriber {
+    user: Option<String>,
+    context: PluginContext,
+    subscriptions: Vec<Subscription>,
+}
+
+impl Subscriber {
+    /// Read the elements a selector picks from a document on behalf of the client
+    ///
+    /// A document or selector matching nothing reads as no elements, so a
+    /// client can follow elements that do not exist yet.
+    fn read(&self, path: &str, selector: &str) -> Result<String, ReadError> {
+        let Some(services) = &self.context.services else {
+            return Err(ReadError::Unavailable);
+        };
+        let operation = SelectorOperation::new(SUBSCRIBE_METHOD, path, selector);
+        match services.apply_selector(self.user.as_deref(), &operation, &self.context) {
+            Ok(content) => Ok(content),
+            Err(e) if e.status == StatusCode::NOT_FOUND.as_u16() => Ok(String::new()),
+            Err(e) if e.status == StatusCode::UNAUTHORIZED.as_u16() || e.status == StatusCode::FORBIDDEN.as_u16() => {
+                Err(ReadError::Forbidden)
+            }
+            Err(_) => Err(ReadError::Unavailable),
+        }
+    }
+
+    fn position(&self, path: &str, selector: &str) -> Option<usize> {
+        let document = WebSocketPlugin::normalize_url(path);
+        self.subscriptions.iter().position(|subscription| {
+            subscription.selector == selector && WebSocketPlugin::normalize_url(&subscription.path) == document
+        })
+    }
 }
 
 
@@ -150,10 +247,15 @@ impl WebSocketPlugin {
 
         let connection_id = Uuid::new_v4().to_string();
         let url = request.http_request.uri().to_string();
+        // Selector subscriptions are read on behalf of the connecting user
+        let subscriber = Subscriber {
+            user: request.get_metadata(AUTHENTICATED_USER_KEY).map(str::to_string),
+            context: context.clone(),
+            subscriptions: Vec::new(),
+        };
         
         // Create upgrade handler to manage the WebSocket connection
         let plugin = self.clone();
-        let verbose = context.verbose;
         let upgrade_handler: UpgradeHandler = Box::new(move |upgraded| {
             let connection_id = connection_id.clone();
             let url = url.clone();
@@ -167,7 +269,7 @@ impl WebSocketPlugin {
                 ).await;
                 
                 // Handle the WebSocket lifecycle
-                plugin.handle_websocket_connection(connection_id, url, ws_stream, verbose).await;
+                plugin.handle_websocket_connection(connection_id, url, subscriber, ws_stream).await;
                 
                 Ok(())
             })
@@ -184,21 +286,22 @@ impl WebSocketPlugin {
         &self, 
         connection_id: String, 
         url: String,
+        mut subscriber: Subscriber,
         mut ws_stream: WebSocketStream<hyper::upgrade::Upgraded>,
-        verbose: bool
     ) {
         let normalized_url = Self::normalize_url(&url);
-        if verbose {
-            println!("[WebSocket] Connection established: {} for {}", connection_id, normalized_url);
-        }
+        log_debug!(DEFAULT_PLUGIN_NAME, "Connection established"; connection = connection_id, url = normalized_url);
         
         // Create broadcast channel for sending messages to this connection
         let (tx, mut rx) = broadcast::channel::<WsMessage>(CONNECTION_CHANNEL_SIZE);
+        let (changes, mut changes_rx) = mpsc::channel::<DocumentChange>(CONNECTION_CHANNEL_SIZE);
         
         // Register the connection with automatic URL subscription
         let connection = ConnectionState {
             url: normalized_url,
             tx: tx.clone(),
+            documents: HashSet::new(),
+            changes,
         };
         
         self.connections.insert(connection_id.clone(), connection);
@@ -212,7 +315,7 @@ impl WebSocketPlugin {
                 msg = ws_stream.next() => {
                     match msg {
                         Some(msg) => {
-                            if !self.handle_websocket_message(&connection_id, &mut ws_stream, msg, verbose).await {
+                            if !self.handle_websocket_message(&connection_id, &mut subscriber, &mut ws_stream, msg).await {
                                 break;
                             }
                         }
@@ -225,28 +328,39 @@ impl WebSocketPlugin {
                     match msg {
                         Ok(msg) => {
                             if ws_stream.send(msg).await.is_err() {
-                                if verbose {
-                                    println!("[WebSocket] Failed to send message to {}, closing connection", connection_id);
-                                }
+                                log_debug!(DEFAULT_PLUGIN_NAME, "Failed to send message, closing connection"; connection = connection_id);
                                 break;
                             }
                         }
                         Err(_) => {
-                            if verbose {
-                                println!("[WebSocket] Broadcast channel closed for {}", connection_id);
-                            }
+                            log_debug!(DEFAULT_PLUGIN_NAME, "Broadcast channel closed"; connection = connection_id);
                             break;
                         }
                     }
                 }
+                
+                // Send deltas for the selector subscriptions on a changed document;
+                // the connection holds a sender itself, so the channel never closes
+                Some(change) = changes_rx.recv() => {
+                    let frames = self.apply_change(&connection_id, &mut subscriber, &change);
+                    let mut sent = true;
+                    for frame in frames {
+                        if ws_stream.send(WsMessage::Text(frame.to_string())).await.is_err() {
+                            sent = false;
+                            break;
+                        }
+                    }
+                    if !sent {
+                        log_debug!(DEFAULT_PLUGIN_NAME, "Failed to send delta, closing connection"; connection = connection_id);
+                        break;
+                    }
+                }
             }
         }
         
         // Connection cleanup
         self.connections.remove(&connection_id);
-        if verbose {
-            println!("[WebSocket] Connection cleaned up: {}", connection_id);
-        }
+        log_debug!(DEFAULT_PLUGIN_NAME, "Connection cleaned up"; connection = connection_id);
     }
 
     /// Processes individual WebSocket messages
@@ -255,27 +369,29 @@ impl WebSocketPlugin {
     async fn handle_websocket_message(
         &self,
         connection_id: &str,
+        subscriber: &mut Subscriber,
         ws_stream: &mut WebSocketStream<hyper::upgrade::Upgraded>,
         msg: Result<WsMessage, tokio_tungstenite::tungstenite::Error>,
-        verbose: bool
     ) -> bool {
         match msg {
-            Ok(WsMessage::Text(_text)) => {
-                // Text messages from clients are currently ignored
-                // Clients are automatically subscribed to their connection URL
+            Ok(WsMessage::Text(text)) => {
+                // Clients are automatically subscribed to their connection URL;
+                // text messages other than selector subscriptions are ignored
+                if let Some(reply) = self.handle_subscription_message(connection_id, subscriber, &text)
+                    && ws_stream.send(WsMessage::Text(reply.to_string())).await.is_err()
+                {
+                    log_debug!(DEFAULT_PLUGIN_NAME, "Failed to send reply"; connection = connection_id);
+                    return false;
+                }
             }
             Ok(WsMessage::Close(_)) => {
-                if verbose {
-                    println!("[WebSocket] Connection closed: {}", connection_id);
-                }
+                log_debug!(DEFAULT_PLUGIN_NAME, "Connection closed"; connection = connection_id);
                 return false;
             }
             Ok(WsMessage::Ping(data)) => {
                 // Respond to ping with pong
                 if ws_stream.send(WsMessage::Pong(data)).await.is_err() {
-                    if verbose {
-                        eprintln!("[WebSocket] Failed to send pong to {}", connection_id);
-                    }
+                    log_debug!(DEFAULT_PLUGIN_NAME, "Failed to send pong"; connection = connection_id);
                     return false;
                 }
             }
@@ -284,9 +400,7 @@ impl WebSocketPlugin {
                 // Future: implement timeout monitoring
             }
             Err(e) => {
-                if verbose {
-                    eprintln!("[WebSocket] Error for {}: {}", connection_id, e);
-                }
+                log_debug!(DEFAULT_PLUGIN_NAME, "Connection error: {}", e; connection = connection_id);
                 return false;
             }
             _ => {} // Binary messages ignored
@@ -294,6 +408,114 @@ impl WebSocketPlugin {
         true
     }
 
+
+    /// Answers a selector subscription message, or returns None for other text
+    fn handle_subscription_message(&self, connection_id: &str, subscriber: &mut Subscriber, text: &str) -> Option<Value> {
+        let Ok(Value::Object(message)) = serde_json::from_str::<Value>(text) else {
+            return None;
+        };
+        let (subscribe, target) = match (message.get("subscribe"), message.get("unsubscribe")) {
+            (Some(target), None) => (true, target),
+            (None, Some(target)) => (false, target),
+            (None, None) => return None,
+            (Some(_), Some(_)) => return Some(error_frame(ERROR_INVALID_MESSAGE, None, None)),
+        };
+        let path = target.get("path").and_then(Value::as_str);
+        let selector = target.get("selector").and_then(Value::as_str).map(str::trim).filter(|selector| !selector.is_empty());
+        let (Some(path), Some(selector)) = (path, selector) else {
+            return Some(error_frame(ERROR_INVALID_MESSAGE, None, None));
+        };
+        // The path is decided and followed as the request path it names, so
+        // `/public/../secret.html` is `/secret.html`
+        let Ok(path) = normalize_request_path(path) else {
+            return Some(error_frame(ERROR_INVALID_PATH, Some(path), Some(selector)));
+        };
+        let path = path.as_str();
+        let existing = subscriber.position(path, selector);
+
+        if !subscribe {
+            if let Some(index) = existing {
+                subscriber.subscriptions.remove(index);
+                self.update_documents(connection_id, subscriber);
+            }
+            return Some(json!({"type": "unsubscribed", "path": path, "selector": selector}));
+        }
+
+        if existing.is_none() && subscriber.subscriptions.len() >= MAX_SUBSCRIPTIONS {
+            return Some(error_frame(ERROR_TOO_MANY_SUBSCRIPTIONS, Some(path), Some(selector)));
+        }
+        let content = match subscriber.read(path, selector) {
+            Ok(content) => content,
+            Err(ReadError::Forbidden) => {
+                log_debug!(DEFAULT_PLUGIN_NAME, "Subscription to '{}' on {} denied", selector, path; connection = connection_id);
+                return Some(error_frame(ERROR_FORBIDDEN, Some(path), Some(selector)));
+            }
+            Err(ReadError::Unavailable) => return Some(error_frame(ERROR_UNAVAILABLE, Some(path), Some(selector))),
+        };
+        // Subscribing again refreshes the snapshot
+        match existing {
+            Some(index) => subscriber.subscriptions[index].content = content.clone(),
+            None => {
+                subscriber.subscriptions.push(Subscription {
+                    path: path.to_string(),
+                    selector: selector.to_string(),
+                    content: content.clone(),
+                });
+                self.update_documents(connection_id, subscriber);
+            }
+        }
+        Some(json!({"type": "snapshot", "path": path, "selector": selector, "content": content}))
+    }
+
+    /// Reads the subscriptions on a changed document again, returning the
+    /// frames for those whose elements changed or which may no longer be read
+    fn apply_change(&self, connection_id: &str, subscriber: &mut Subscriber, change: &DocumentChange) -> Vec<Value> {
+        let document = Self::normalize_url(&change.path);
+        let mut frames = Vec::new();
+        let mut ended = Vec::new();
+        for index in 0..subscriber.subscriptions.len() {
+            let subscription = &subscriber.subscriptions[index];
+            if Self::normalize_url(&subscription.path) != document {
+                continue;
+            }
+            match subscriber.read(&subscription.path, &subscription.selector) {
+                Ok(content) if content != subscription.content => {
+                    frames.push(json!({
+                        "type": "delta",
+                        "path": subscription.path,
+                        "selector": subscription.selector,
+                        "method": change.method,
+                        "content": content,
+                    }));
+                    subscriber.subscriptions[index].content = content;
+                }
+                Ok(_) => {}
+                Err(ReadError::Forbidden) => {
+                    frames.push(error_frame(ERROR_FORBIDDEN, Some(&subscription.path), Some(&subscription.selector)));
+                    ended.push(index);
+                }
+                // The document may be readable again after the next write
+                Err(ReadError::Unavailable) => {}
+            }
+        }
+        if !ended.is_empty() {
+            log_debug!(DEFAULT_PLUGIN_NAME, "Ended {} subscription(s) no longer permitted", ended.len(); connection = connection_id);
+            for index in ended.into_iter().rev() {
+                subscriber.subscriptions.remove(index);
+            }
+            self.update_documents(connection_id, subscriber);
+        }
+        frames
+    }
+
+    /// Records which documents a connection's selector subscriptions are on
+    fn update_documents(&self, connection_id: &str, subscriber: &Subscriber) {
+        if let Some(mut connection) = self.connections.get_mut(connection_id) {
+            connection.documents = subscriber.subscriptions.iter()
+                .map(|subscription| Self::normalize_url(&subscription.path))
+                .collect();
+        }
+    }
     
     /// Broadcasts content updates to all connections subscribed to a URL
     async fn broadcast_update(&self, url: &str, selector: &str, content: &str, method: &str) {
@@ -365,7 +587,212 @@ impl Plugin for WebSocketPlugin {
             }
         }
     }
+
+    fn on_change(&self, change: &DocumentChange, _context: &PluginContext) {
+        // The connections read their subscriptions again themselves, so the
+        // write is not held up by them; a connection whose channel is full
+        // has a read pending that will see this change too
+        let document = Self::normalize_url(&change.path);
+        for connection in self.connections.iter() {
+            if connection.documents.contains(&document) {
+                let _ = connection.changes.try_send(change.clone());
+            }
+        }
+    }
+}
+
+/// A frame reporting why a subscription message was refused
+fn error_frame(error: &str, path: Option<&str>, selector: Option<&str>) -> Value {
+    json!({"type": "error", "error": error, "path": path, "selector": selector})
 }
 
 // Export the plugin
-create_plugin!(WebSocketPlugin);
\ No newline at end of file
+create_plugin!(WebSocketPlugin);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusty_beam_plugin_api::{PipelineServices, SelectorError};
+    use std::sync::Mutex;
+
+    /// A selector-handler stand-in whose documents may be read below `/public/` only
+    #[derive(Debug, Default)]
+    struct Documents {
+        elements: Mutex<HashMap<(String, String), String>>,
+    }
+
+    impl Documents {
+        fn write(&self, path: &str, selector: &str, content: &str) {
+            self.elements.lock().unwrap().insert((path.to_string(), selector.to_string()), content.to_string());
+        }
+    }
+
+    #[async_trait]
+    impl Plugin for Documents {
+        fn check_selector_access(
+            &self,
+            _user: Option<&str>,
+            _method: &str,
+            path: &str,
+            _selector: &str,
+            _context: &PluginContext,
+        ) -> Option<bool> {
+            Some(path.starts_with("/public/"))
+        }
+
+        fn apply_selector(&self, operation: &SelectorOperation, _context: &PluginContext) -> Option<Result<String, SelectorError>> {
+            let key = (operation.path.clone(), operation.selector.clone());
+            Some(self.elements.lock().unwrap().get(&key).cloned().ok_or_else(|| SelectorError::new(404, "No elements")))
+        }
+
+        fn name(&self) -> &str {
+            "documents"
+        }
+    }
+
+    fn create_subscriber(documents: Arc<Documents>) -> Subscriber {
+        Subscriber {
+            user: Some("alice".to_string()),
+            context: PluginContext {
+                plugin_config: HashMap::new(),
+                server_config: HashMap::new(),
+                server_metadata: HashMap::new(),
+                host_config: HashMap::new(),
+                host_name: "test-host".to_string(),
+                request_id: "test-request".to_string(),
+                runtime_handle: None,
+                verbose: false,
+                services: Some(Arc::new(PipelineServices::new(vec![documents]))),
+                deadline: None,
+            },
+            subscriptions: Vec::new(),
+        }
+    }
+
+    fn subscribe(path: &str, selector: &str) -> String {
+        json!({"subscribe": {"path": path, "selector": selector}}).to_string()
+    }
+
+    fn change(method: &str, path: &str, selector: &str) -> DocumentChange {
+        DocumentChange {
+            method: method.to_string(),
+            path: path.to_string(),
+            selector: selector.to_string(),
+            content: String::new(),
+            previous_content: None,
+            user: Some("bob".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_subscribe_sends_snapshot() {
+        let plugin = WebSocketPlugin::default();
+        let documents = Arc::new(Documents::default());
+        documents.write("/public/list.html", "#items", "<ul id=\"items\"><li>a</li></ul>");
+        let mut subscriber = create_subscriber(documents);
+
+        let frame = plugin.handle_subscription_message("c1", &mut subscriber, &subscribe("/public/list.html", "#items"));
+        assert_eq!(frame, Some(json!({
+            "type": "snapshot",
+            "path": "/public/list.html",
+            "selector": "#items",
+            "content": "<ul id=\"items\"><li>a</li></ul>",
+        })));
+        assert_eq!(subscriber.subscriptions.len(), 1);
+    }
+
+    #[test]
+    fn test_subscribe_to_missing_elements_sends_empty_snapshot() {
+        let plugin = WebSocketPlugin::default();
+        let mut subscriber = create_subscriber(Arc::new(Documents::default()));
+
+        let frame = plugin.handle_subscription_message("c1", &mut subscriber, &subscribe("/public/list.html", "#items")).unwrap();
+        assert_eq!(frame["type"], "snapshot");
+        assert_eq!(frame["content"], "");
+    }
+
+    #[test]
+    fn test_change_sends_delta() {
+        let plugin = WebSocketPlugin::default();
+        let documents = Arc::new(Documents::default());
+        documents.write("/public/list.html", "#items", "<ul id=\"items\"></ul>");
+        let mut subscriber = create_subscriber(documents.clone());
+        plugin.handle_subscription_message("c1", &mut subscriber, &subscribe("/public/list.html", "#items"));
+
+        documents.write("/public/list.html", "#items", "<ul id=\"items\"><li>b</li></ul>");
+        let frames = plugin.apply_change("c1", &mut subscriber, &change("POST", "/public/list.html", "#items"));
+        assert_eq!(frames, vec![json!({
+            "type": "delta",
+            "path": "/public/list.html",
+            "selector": "#items",
+            "method": "POST",
+            "content": "<ul id=\"items\"><li>b</li></ul>",
+        })]);
+
+        // A write leaving the elements as they were sends nothing
+        let frames = plugin.apply_change("c1", &mut subscriber, &change("PUT", "/public/list.html", "#items"));
+        assert!(frames.is_empty());
+        // Nor does a write to another document
+        documents.write("/public/other.html", "#items", "<ul id=\"items\"><li>c</li></ul>");
+        let frames = plugin.apply_change("c1", &mut subscriber, &change("PUT", "/public/other.html", "#items"));
+        assert!(frames.is_empty());
+    }
+
+    #[test]
+    fn test_denied_subscription() {
+        let plugin = WebSocketPlugin::default();
+        let documents = Arc::new(Documents::default());
+        documents.write("/secret.html", "#key", "<p id=\"key\">s3cret</p>");
+        let mut subscriber = create_subscriber(documents);
+
+        let frame = plugin.handle_subscription_message("c1", &mut subscriber, &subscribe("/secret.html", "#key"));
+        assert_eq!(frame, Some(error_frame(ERROR_FORBIDDEN, Some("/secret.html"), Some("#key"))));
+        assert!(subscriber.subscriptions.is_empty());
+    }
+
+    #[test]
+    fn test_traversal_path_is_normalized_before_access_check() {
+        let plugin = WebSocketPlugin::default();
+        let documents = Arc::new(Documents::default());
+        documents.write("/secret.html", "#key", "<p id=\"key\">s3cret</p>");
+        let mut subscriber = create_subscriber(documents);
+
+        for path in ["/public/../secret.html", "/public/%2e%2e/secret.html"] {
+            let frame = plugin.handle_subscription_message("c1", &mut subscriber, &subscribe(path, "#key"));
+            assert_eq!(frame, Some(error_frame(ERROR_FORBIDDEN, Some("/secret.html"), Some("#key"))), "{}", path);
+        }
+        assert!(subscriber.subscriptions.is_empty());
+
+        let frame = plugin.handle_subscription_message("c1", &mut subscriber, &subscribe("public/list.html", "#key"));
+        assert_eq!(frame, Some(error_frame(ERROR_INVALID_PATH, Some("public/list.html"), Some("#key"))));
+    }
+
+    #[test]
+    fn test_services_normalize_paths() {
+        let documents = Arc::new(Documents::default());
+        documents.write("/secret.html", "#key", "<p id=\"key\">s3cret</p>");
+        let subscriber = create_subscriber(documents);
+        let services = subscriber.context.services.as_ref().unwrap();
+
+        let operation = SelectorOperation::new("GET", "/public/../secret.html", "#key");
+        let denied = services.apply_selector(None, &operation, &subscriber.context);
+        assert_eq!(denied.unwrap_err().status, 403);
+        let operation = SelectorOperation::new("GET", "/public/%ZZ", "#key");
+        assert_eq!(services.apply_selector(None, &operation, &subscriber.context).unwrap_err().status, 400);
+    }
+
+    #[test]
+    fn test_unsubscribe() {
+        let plugin = WebSocketPlugin::default();
+        let documents = Arc::new(Documents::default());
+        let mut subscriber = create_subscriber(documents.clone());
+        plugin.handle_subscription_message("c1", &mut subscriber, &subscribe("/public/list.html", "#items"));
+
+        let message = json!({"unsubscribe": {"path": "/public/list.html", "selector": "#items"}}).to_string();
+        let frame = plugin.handle_subscription_message("c1", &mut subscriber, &message).unwrap();
+        assert_eq!(frame["type"], "unsubscribed");
+        assert!(subscriber.subscriptions.is_empty());
+
+        documents.write("/public/list.html", "#items", "<ul id=\"items\"><li>b</li></ul>");
+        assert!(plugin.apply_change("c1", &mut subscriber, &change("POST", "/public/list.html", "#items")).is_empty());
+    }
+}
@@ -31,6 +31,11 @@ setup_plugin_test() {
     # Copy template directory
     cp -r tests/plugins/template "$host_dir"
     
+    # The token store is rewritten by the tests, so each run gets a fresh copy
+    if [ "$plugin_name" = "token-auth" ]; then
+        cp tests/plugins/configs/token-auth-tokens.html "$host_dir/tokens.html"
+    fi
+    
     echo "Setup complete for $plugin_name"
 }
 
@@ -168,6 +173,18 @@ PLUGINS=(
     "websocket"
     "oauth2"
     "html-prettifier"
+    "content-negotiation"
+    "record-replay"
+    "concurrency-limit"
+    "websocket-hub"
+    "csrf"
+    "schema-validation"
+    "maintenance"
+    "cron"
+    "events"
+    "token-auth"
+    "response-cache"
+    "search"
 )
 
 # Run tests for each plugin
@@ -0,0 +1,170 @@
+//! Configuration checks for CI and pre-deploy verification
+//!
+//! Collects the problems `rusty-beam --check-config` finds into a report:
+//! those of the server itself, such as document roots and TLS files that
+//! cannot be read, those the plugins report through `validate_config`, and
+//! every warning or error logged while the configuration and plugins load.
+
+use crate::config::{ListenerEndpoint, ServerConfig};
+use rusty_beam_plugin_api::interpolation;
+use rusty_beam_plugin_api::log::{Level, LogSink, Record};
+use rusty_beam_plugin_api::validation::{check_readable_file, check_writable_file};
+use rusty_beam_plugin_api::{ConfigIssue, Severity};
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Scope of problems that concern the whole server
+pub const SERVER_SCOPE: &str = "server";
+
+/// Log sink keeping warnings and errors for the report
+#[derive(Default)]
+pub struct CollectingSink {
+    records: Mutex<Vec<Record>>,
+}
+
+impl CollectingSink {
+    /// The records written since the last call, as issues
+    pub fn take_issues(&self) -> Vec<ConfigIssue> {
+        let records = std::mem::take(&mut *self.records.lock().unwrap_or_else(|e| e.into_inner()));
+        records.iter().map(issue_from_record).collect()
+    }
+}
+
+impl LogSink for CollectingSink {
+    fn write(&self, record: &Record) {
+        if matches!(record.level, Level::Error | Level::Warn) {
+            self.records.lock().unwrap_or_else(|e| e.into_inner()).push(record.clone());
+        }
+    }
+}
+
+fn issue_from_record(record: &Record) -> ConfigIssue {
+    let mut message = if record.target == crate::logging::SERVER_TARGET {
+        record.message.clone()
+    } else {
+        format!("{}: {}", record.target, record.message)
+    };
+    if !record.fields.is_empty() {
+        let fields: Vec<String> = record.fields.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+        message = format!("{} ({})", message, fields.join(", "));
+    }
+    match record.level {
+        Level::Error => ConfigIssue::error(message),
+        _ => ConfigIssue::warning(message),
+    }
+}
+
+/// Problems found, grouped by the server or hosts they concern
+#[derive(Default)]
+pub struct CheckReport {
+    scopes: Vec<(String, Vec<ConfigIssue>)>,
+    pub hosts_checked: usize,
+    pub plugins_checked: usize,
+    /// Values read into the configuration that the report masks
+    secrets: Vec<String>,
+}
+
+impl CheckReport {
+    /// A report masking these values wherever its issues mention them
+    pub fn with_secrets(secrets: Vec<String>) -> Self {
+        Self { secrets, ..Self::default() }
+    }
+
+    pub fn add(&mut self, scope: &str, issue: ConfigIssue) {
+        match self.scopes.iter_mut().find(|(name, _)| name == scope) {
+            Some((_, issues)) => issues.push(issue),
+            None => self.scopes.push((scope.to_string(), vec![issue])),
+        }
+    }
+
+    pub fn extend(&mut self, scope: &str, issues: impl IntoIterator<Item = ConfigIssue>) {
+        for issue in issues {
+            self.add(scope, issue);
+        }
+    }
+
+    pub fn count(&self, severity: Severity) -> usize {
+        self.scopes.iter()
+            .flat_map(|(_, issues)| issues)
+            .filter(|issue| issue.severity == severity)
+            .count()
+    }
+
+    /// Writes the report to stdout
+    pub fn print(&self, config_path: &str) {
+        println!("Checking {}", config_path);
+        for (scope, issues) in &self.scopes {
+            println!();
+            println!("{}", scope);
+            for issue in issues {
+                println!("  {}", interpolation::mask(&issue.to_string(), &self.secrets));
+            }
+        }
+        println!();
+        println!(
+            "{} hosts, {} plugins checked: {} errors, {} warnings",
+            self.hosts_checked,
+            self.plugins_checked,
+            self.count(Severity::Error),
+            self.count(Severity::Warning)
+        );
+    }
+}
+
+/// Checks the options of the ServerConfig item
+pub fn check_server(config: &ServerConfig, report: &mut CheckReport) {
+    if config.hosts.is_empty() {
+        report.add(SERVER_SCOPE, ConfigIssue::error("no HostConfig with a hostname and hostRoot is configured"));
+    }
+    if let Some(issue) = check_directory("serverRoot", &config.server_root) {
+        report.add(SERVER_SCOPE, ConfigIssue::warning(issue.message));
+    }
+    // The loader has already logged a lone TLS property
+    if let (Some(certificate), Some(private_key)) = (&config.tls_certificate, &config.tls_private_key) {
+        report.extend(SERVER_SCOPE, check_readable_file("tlsCertificate", certificate));
+        report.extend(SERVER_SCOPE, check_readable_file("tlsPrivateKey", private_key));
+    }
+    if let Some(directory) = &config.body_spool_directory {
+        report.extend(SERVER_SCOPE, check_directory("bodySpoolDirectory", directory));
+    }
+    if let Some(log_file) = &config.log_file {
+        report.extend(SERVER_SCOPE, check_writable_file("logFile", log_file));
+    }
+    if config.listeners.is_empty() {
+        report.add(SERVER_SCOPE, ConfigIssue::error("no usable Listener is configured"));
+    }
+    for listener in &config.listeners {
+        if let ListenerEndpoint::Unix(path) = &listener.endpoint {
+            report.extend(SERVER_SCOPE, check_writable_file("socketPath", &path.to_string_lossy()));
+        }
+        for hostname in listener.hostnames.iter().filter(|hostname| !config.hosts.contains_key(*hostname)) {
+            report.add(SERVER_SCOPE, ConfigIssue::warning(format!(
+                "Listener {} serves hostname {}, which no HostConfig declares",
+                listener.endpoint, hostname
+            )));
+        }
+    }
+}
+
+/// Checks that a host's document root is a directory that can be listed
+pub fn check_host_root(host_root: &str) -> Option<ConfigIssue> {
+    check_directory("hostRoot", host_root)
+}
+
+/// Checks that a plugin library exists
+pub fn check_library(library_path: &str) -> Option<ConfigIssue> {
+    match fs::metadata(library_path) {
+        Ok(metadata) if metadata.is_file() => None,
+        Ok(_) => Some(ConfigIssue::error(format!("plugin library {} is not a file", library_path))),
+        Err(e) => Some(ConfigIssue::error(format!("plugin library {} not found: {}", library_path, e))),
+    }
+}
+
+fn check_directory(key: &str, path: &str) -> Option<ConfigIssue> {
+    if !Path::new(path).is_dir() {
+        return Some(ConfigIssue::error(format!("{} {} is not a directory", key, path)));
+    }
+    fs::read_dir(path).err()
+        .map(|e| ConfigIssue::error(format!("{} {} cannot be read: {}", key, path, e)))
+}
@@ -4,22 +4,30 @@
 //! using microdata attributes. It supports:
 //!
 //! - Server-level configuration (bind address, daemon settings)
+//! - Listeners on several addresses and unix domain sockets
 //! - Host-specific configuration (document root, plugin pipelines)
 //! - Plugin configuration with nested plugin support
+//! - Plugin configuration values read from the environment and secret files
 //! - Security validation for plugin URLs
 //!
 //! The configuration format uses HTML microdata schemas for structured,
 //! human-readable configuration that can be validated and documented.
 
 use crate::log_error;
+use crate::logging::LogFormat;
+use rusty_beam_plugin_api::interpolation;
+use rusty_beam_plugin_api::{Level, SymlinkPolicy};
 use microdata_extract::MicrodataExtractor;
 use std::collections::HashMap;
+use std::fmt;
 use std::fs;
-use std::path::Path;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
 
 // Microdata schema URLs
 const SCHEMA_SERVER_CONFIG: &str = "https://rustybeam.net/schema/ServerConfig";
 const SCHEMA_HOST_CONFIG: &str = "https://rustybeam.net/schema/HostConfig";
+const SCHEMA_LISTENER: &str = "https://rustybeam.net/schema/Listener";
 const SCHEMA_CREDENTIAL: &str = "https://rustybeam.net/schema/Credential";
 
 // Plugin URL schemes
@@ -33,7 +41,12 @@ const DEFAULT_PID_FILE: &str = "/tmp/rusty-beam.pid";
 const DEFAULT_STDOUT_FILE: &str = "/tmp/rusty-beam.stdout";
 const DEFAULT_STDERR_FILE: &str = "/tmp/rusty-beam.stderr";
 const DEFAULT_UMASK: u32 = 0o027;
+const DEFAULT_SHUTDOWN_TIMEOUT_SECS: u64 = 30;
 const DEFAULT_ENCRYPTION: &str = "plaintext";
+const DEFAULT_HTTP2_ENABLED: bool = true;
+const DEFAULT_KEEP_ALIVE: bool = true;
+const DEFAULT_PLUGIN_WATCH_INTERVAL_SECS: u64 = 1;
+const DEFAULT_BODY_SPOOL_THRESHOLD: u64 = 8 * 1024 * 1024;
 
 // Plugin configuration property names
 const COMMON_PLUGIN_PROPERTIES: &[&str] = &["realm", "authfile", "log_file"];
@@ -50,6 +63,8 @@ pub struct PluginConfig {
     pub config: HashMap<String, String>,
     /// Nested plugins for pipeline-type plugins
     pub nested_plugins: Vec<PluginConfig>,
+    /// Expression that must hold for the plugin to run on a request
+    pub condition: Option<String>,
 }
 
 
@@ -87,6 +102,76 @@ pub struct HostConfig {
     pub plugins: Vec<PluginConfig>,
     /// Custom Server header value for this host
     pub server_header: Option<String>,
+    /// Content-Type overrides keyed by lowercase file extension
+    pub mime_types: HashMap<String, String>,
+    /// How symbolic links below the host root are treated
+    pub symlink_policy: SymlinkPolicy,
+    /// File extensions the file handler never serves, comma-separated
+    pub deny_extensions: Option<String>,
+    /// Path patterns the file handler never serves, comma-separated
+    pub deny_paths: Option<String>,
+    /// Whether the file handler serves hidden files: `serve` or `deny`
+    pub hidden_files: Option<String>,
+    /// The host root with links resolved, if it existed when loaded
+    pub canonical_root: Option<PathBuf>,
+}
+
+/// Where a listener accepts connections
+#[derive(Debug, Clone, PartialEq)]
+pub enum ListenerEndpoint {
+    /// An IP address and port
+    Tcp { address: String, port: u16 },
+    /// The path of a unix domain socket
+    Unix(PathBuf),
+}
+
+impl ListenerEndpoint {
+    /// The socket address of a TCP endpoint, accepting IPv6 addresses with
+    /// or without brackets
+    pub fn socket_addr(address: &str, port: u16) -> Result<SocketAddr, String> {
+        let ip = address.trim().trim_start_matches('[').trim_end_matches(']');
+        ip.parse::<IpAddr>()
+            .map(|ip| SocketAddr::new(ip, port))
+            .map_err(|e| format!("Invalid bind address '{}': {}", address, e))
+    }
+}
+
+impl fmt::Display for ListenerEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenerEndpoint::Tcp { address, port } if address.contains(':') && !address.starts_with('[') => {
+                write!(f, "[{}]:{}", address, port)
+            }
+            ListenerEndpoint::Tcp { address, port } => write!(f, "{}:{}", address, port),
+            ListenerEndpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+/// A socket the server accepts connections on
+#[derive(Debug, Clone)]
+pub struct ListenerConfig {
+    pub endpoint: ListenerEndpoint,
+    /// Permissions of a unix domain socket file, such as 0o660
+    pub socket_mode: Option<u32>,
+    /// Lowercase hostnames served on this listener (every host if empty)
+    pub hostnames: Vec<String>,
+}
+
+impl ListenerConfig {
+    /// A listener on an address and port serving every host
+    fn tcp(address: &str, port: u16) -> Self {
+        ListenerConfig {
+            endpoint: ListenerEndpoint::Tcp { address: address.to_string(), port },
+            socket_mode: None,
+            hostnames: Vec::new(),
+        }
+    }
+
+    /// Whether requests for a host are served on this listener
+    pub fn serves(&self, hostname: &str) -> bool {
+        self.hostnames.is_empty() || self.hostnames.iter().any(|served| served == hostname)
+    }
 }
 
 /// Main server configuration loaded from HTML microdata
@@ -97,6 +182,9 @@ pub struct ServerConfig {
     pub bind_address: String,
     /// Port number to bind the server to
     pub bind_port: u16,
+    /// Sockets the server accepts connections on; the declared Listener
+    /// items, or one for `bind_address` and `bind_port` if there are none
+    pub listeners: Vec<ListenerConfig>,
     /// Virtual host configurations keyed by hostname
     pub hosts: HashMap<String, HostConfig>,
     /// Server-wide plugins (reserved for future use)
@@ -120,6 +208,74 @@ pub struct ServerConfig {
     pub daemon_chown_pid_file: Option<bool>,
     /// Working directory for daemon
     pub daemon_working_directory: Option<String>,
+    
+    /// Seconds to wait for in-flight requests to drain on shutdown
+    pub shutdown_timeout: u64,
+    
+    // HTTP protocol options
+    /// Path to a PEM certificate chain; enables TLS together with `tls_private_key`
+    pub tls_certificate: Option<String>,
+    /// Path to the PEM private key for `tls_certificate`
+    pub tls_private_key: Option<String>,
+    /// Whether HTTP/2 is offered (ALPN over TLS, prior knowledge over plaintext)
+    pub http2: bool,
+    /// Whether HTTP/1.1 connections are kept alive between requests
+    pub keep_alive: bool,
+    /// Seconds an HTTP/1.1 connection may wait for the next request's headers
+    pub keep_alive_timeout: Option<u64>,
+    /// Maximum concurrent HTTP/2 streams per connection
+    pub http2_max_concurrent_streams: Option<u32>,
+    /// Initial HTTP/2 stream-level flow control window in bytes
+    pub http2_initial_stream_window_size: Option<u32>,
+    /// Initial HTTP/2 connection-level flow control window in bytes
+    pub http2_initial_connection_window_size: Option<u32>,
+    /// Seconds between HTTP/2 keep-alive pings
+    pub http2_keep_alive_interval: Option<u64>,
+    /// Seconds to wait for a keep-alive ping acknowledgement before closing
+    pub http2_keep_alive_timeout: Option<u64>,
+    
+    // Request options
+    /// Milliseconds plugins may take to answer a request before it gets a 504 (no limit if None)
+    pub request_timeout_ms: Option<u64>,
+    /// Request bodies larger than this many bytes are spooled to disk (0 keeps all in memory)
+    pub body_spool_threshold: u64,
+    /// Directory spooled request bodies are written to (system temp directory if None)
+    pub body_spool_directory: Option<String>,
+    
+    // Load limits
+    /// Connections served at once; beyond it the server stops accepting until one closes (no limit if None)
+    pub max_connections: Option<usize>,
+    /// Requests processed at once; beyond it requests get a 503 (no limit if None)
+    pub max_in_flight_requests: Option<usize>,
+    
+    // Logging options
+    /// How log records of the server and its plugins are written
+    pub log_format: LogFormat,
+    /// Minimum level of log records written (info, or debug in verbose mode, if None)
+    pub log_level: Option<Level>,
+    /// File JSON log records are appended to (stderr if None)
+    pub log_file: Option<String>,
+    
+    // Development options
+    /// Whether changed plugin libraries are reloaded without a restart
+    pub plugin_hot_reload: bool,
+    /// Seconds between checks of plugin libraries for changes
+    pub plugin_watch_interval: u64,
+    
+    // Pipeline options
+    /// Whether plugins are moved after the plugins providing what they require
+    pub plugin_auto_sort: bool,
+    
+    /// Values `${env:...}` and `${file:...}` references in plugin
+    /// configuration resolved to, masked in logs and reports
+    pub secrets: Vec<String>,
+}
+
+impl ServerConfig {
+    /// Whether both halves of the TLS configuration are present
+    pub fn tls_enabled(&self) -> bool {
+        self.tls_certificate.is_some() && self.tls_private_key.is_some()
+    }
 }
 
 impl Default for ServerConfig {
@@ -128,6 +284,7 @@ impl Default for ServerConfig {
             server_root: DEFAULT_SERVER_ROOT.to_string(),
             bind_address: DEFAULT_BIND_ADDRESS.to_string(),
             bind_port: DEFAULT_BIND_PORT,
+            listeners: vec![ListenerConfig::tcp(DEFAULT_BIND_ADDRESS, DEFAULT_BIND_PORT)],
             hosts: HashMap::new(),
             server_wide_plugins: Vec::new(),
             
@@ -140,6 +297,37 @@ impl Default for ServerConfig {
             daemon_stderr: Some(DEFAULT_STDERR_FILE.to_string()),
             daemon_chown_pid_file: Some(true),
             daemon_working_directory: None, // Will be set to config file directory
+            
+            shutdown_timeout: DEFAULT_SHUTDOWN_TIMEOUT_SECS,
+            
+            tls_certificate: None,
+            tls_private_key: None,
+            http2: DEFAULT_HTTP2_ENABLED,
+            keep_alive: DEFAULT_KEEP_ALIVE,
+            keep_alive_timeout: None,
+            http2_max_concurrent_streams: None,
+            http2_initial_stream_window_size: None,
+            http2_initial_connection_window_size: None,
+            http2_keep_alive_interval: None,
+            http2_keep_alive_timeout: None,
+            
+            request_timeout_ms: None,
+            body_spool_threshold: DEFAULT_BODY_SPOOL_THRESHOLD,
+            body_spool_directory: None,
+            
+            max_connections: None,
+            max_in_flight_requests: None,
+            
+            log_format: LogFormat::Pretty,
+            log_level: None,
+            log_file: None,
+            
+            plugin_hot_reload: false,
+            plugin_watch_interval: DEFAULT_PLUGIN_WATCH_INTERVAL_SECS,
+            
+            plugin_auto_sort: false,
+            
+            secrets: Vec::new(),
         }
     }
 }
@@ -192,6 +380,58 @@ fn parse_optional_umask(item: &microdata_extract::MicrodataItem, property: &str)
         .and_then(|s| u32::from_str_radix(&s.trim_start_matches("0o"), 8).ok())
 }
 
+/// Parses an optional numeric property from microdata item
+/// 
+/// Logs and ignores values that are not valid numbers
+fn parse_optional_number<T>(item: &microdata_extract::MicrodataItem, property: &str) -> Option<T>
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = item.get_property(property)?;
+    match value.trim().parse::<T>() {
+        Ok(number) => Some(number),
+        Err(e) => {
+            log_error!("Invalid {} '{}': {}", property, value, e);
+            None
+        }
+    }
+}
+
+/// Parses a Listener item, logging why it is unusable if it is
+fn parse_listener(item: &microdata_extract::MicrodataItem) -> Option<ListenerConfig> {
+    let endpoint = match parse_optional_string(item, "socketPath") {
+        Some(path) => ListenerEndpoint::Unix(PathBuf::from(path.strip_prefix("file://").unwrap_or(&path))),
+        None => {
+            let address = parse_optional_string(item, "bindAddress").unwrap_or_else(|| DEFAULT_BIND_ADDRESS.to_string());
+            let port = match item.get_property("bindPort") {
+                Some(port) => match port.trim().parse::<u16>() {
+                    Ok(port) => port,
+                    Err(e) => {
+                        log_error!("Ignoring Listener with invalid bind port '{}': {}", port, e);
+                        return None;
+                    }
+                },
+                None => DEFAULT_BIND_PORT,
+            };
+            if let Err(e) = ListenerEndpoint::socket_addr(&address, port) {
+                log_error!("Ignoring Listener: {}", e);
+                return None;
+            }
+            ListenerEndpoint::Tcp { address, port }
+        }
+    };
+    Some(ListenerConfig {
+        endpoint,
+        socket_mode: parse_optional_umask(item, "socketMode"),
+        hostnames: item.get_property_values("hostname")
+            .into_iter()
+            .map(|hostname| hostname.trim().to_lowercase())
+            .filter(|hostname| !hostname.is_empty())
+            .collect(),
+    })
+}
+
 /// Loads server configuration from an HTML file using microdata
 /// 
 /// Falls back to default configuration on any errors
@@ -233,9 +473,91 @@ pub fn load_config_from_html(file_path: &str) -> ServerConfig {
             config.daemon_stderr = parse_optional_string(item, "daemonStderr");
             config.daemon_chown_pid_file = parse_optional_bool(item, "daemonChownPidFile");
             config.daemon_working_directory = parse_optional_string(item, "daemonWorkingDirectory");
+            
+            if let Some(shutdown_timeout) = item.get_property("shutdownTimeout") {
+                match shutdown_timeout.parse::<u64>() {
+                    Ok(secs) => config.shutdown_timeout = secs,
+                    Err(e) => { log_error!("Invalid shutdown timeout '{}': {}", shutdown_timeout, e); }
+                }
+            }
+            
+            // Parse HTTP protocol options
+            config.tls_certificate = parse_optional_string(item, "tlsCertificate");
+            config.tls_private_key = parse_optional_string(item, "tlsPrivateKey");
+            if let Some(http2) = parse_optional_bool(item, "http2") {
+                config.http2 = http2;
+            }
+            if let Some(keep_alive) = parse_optional_bool(item, "keepAlive") {
+                config.keep_alive = keep_alive;
+            }
+            config.keep_alive_timeout = parse_optional_number(item, "keepAliveTimeout");
+            config.http2_max_concurrent_streams = parse_optional_number(item, "http2MaxConcurrentStreams");
+            config.http2_initial_stream_window_size = parse_optional_number(item, "http2InitialStreamWindowSize");
+            config.http2_initial_connection_window_size = parse_optional_number(item, "http2InitialConnectionWindowSize");
+            config.http2_keep_alive_interval = parse_optional_number(item, "http2KeepAliveInterval");
+            config.http2_keep_alive_timeout = parse_optional_number(item, "http2KeepAliveTimeout");
+            
+            // Parse request options
+            config.request_timeout_ms = parse_optional_number::<u64>(item, "requestTimeoutMs")
+                .filter(|ms| *ms > 0);
+            if let Some(threshold) = parse_optional_number(item, "bodySpoolThreshold") {
+                config.body_spool_threshold = threshold;
+            }
+            config.body_spool_directory = parse_optional_string(item, "bodySpoolDirectory");
+            
+            // Parse load limits
+            config.max_connections = parse_optional_number::<usize>(item, "maxConnections")
+                .filter(|max| *max > 0);
+            config.max_in_flight_requests = parse_optional_number::<usize>(item, "maxInFlightRequests")
+                .filter(|max| *max > 0);
+            
+            // Parse logging options
+            if let Some(format) = parse_optional_string(item, "logFormat") {
+                match format.parse() {
+                    Ok(format) => config.log_format = format,
+                    Err(e) => { log_error!("{}, using pretty", e); }
+                }
+            }
+            if let Some(level) = parse_optional_string(item, "logLevel") {
+                match level.parse() {
+                    Ok(level) => config.log_level = Some(level),
+                    Err(e) => { log_error!("{}, using info", e); }
+                }
+            }
+            config.log_file = parse_optional_string(item, "logFile");
+            
+            // Parse development options
+            if let Some(plugin_hot_reload) = parse_optional_bool(item, "pluginHotReload") {
+                config.plugin_hot_reload = plugin_hot_reload;
+            }
+            if let Some(interval) = parse_optional_number::<u64>(item, "pluginWatchInterval") {
+                config.plugin_watch_interval = interval.max(1);
+            }
+            
+            // Parse pipeline options
+            if let Some(plugin_auto_sort) = parse_optional_bool(item, "pluginAutoSort") {
+                config.plugin_auto_sort = plugin_auto_sort;
+            }
+            if config.tls_certificate.is_some() != config.tls_private_key.is_some() {
+                log_error!("Both tlsCertificate and tlsPrivateKey are required for TLS, serving plaintext");
+            }
+            config.listeners = vec![ListenerConfig::tcp(&config.bind_address, config.bind_port)];
         }
     }
 
+    // Declared listeners replace the one on bindAddress and bindPort, even
+    // if none of them is usable; they are items of their own or `listener`
+    // properties of the ServerConfig
+    let listener_items: Vec<_> = items.iter()
+        .filter(|item| item.item_type() == Some(SCHEMA_SERVER_CONFIG))
+        .flat_map(|item| item.get_nested_items("listener"))
+        .chain(items.iter())
+        .filter(|item| item.item_type() == Some(SCHEMA_LISTENER))
+        .collect();
+    if !listener_items.is_empty() {
+        config.listeners = listener_items.into_iter().filter_map(parse_listener).collect();
+    }
+
     // Load host configurations from all items
     for item in &items {
         if item.item_type() == Some(SCHEMA_HOST_CONFIG) {
@@ -244,6 +566,23 @@ pub fn load_config_from_html(file_path: &str) -> ServerConfig {
             let hostnames = item.get_property_values("hostname");
             let host_root = item.get_property("hostRoot").unwrap_or_default();
             let server_header = item.get_property("serverHeader");
+            let mime_types = parse_mime_types(item);
+            let symlink_policy = match item.get_property("symlinkPolicy") {
+                Some(value) => value.parse().unwrap_or_else(|e| {
+                    log_error!("{}, using {}", e, SymlinkPolicy::default().as_str());
+                    SymlinkPolicy::default()
+                }),
+                None => SymlinkPolicy::default(),
+            };
+            let deny_extensions = item.get_property("denyExtensions");
+            let deny_paths = item.get_property("denyPaths");
+            let hidden_files = item.get_property("hiddenFiles").filter(|value| {
+                let valid = matches!(value.trim().to_lowercase().as_str(), "serve" | "deny");
+                if !valid {
+                    log_error!("Invalid hiddenFiles '{}', expected serve or deny; serving hidden files", value);
+                }
+                valid
+            });
 
             if hostnames.is_empty() {
                 log_error!("HostConfig missing required hostname property");
@@ -258,13 +597,26 @@ pub fn load_config_from_html(file_path: &str) -> ServerConfig {
             // Configure host for multiple hostnames
 
             // Parse plugin pipeline from the new format
-            let plugins = parse_plugin_pipeline(item);
+            let mut plugins = parse_plugin_pipeline(item);
+            // A plugin missing a secret could let requests through that it
+            // should refuse, so the host is not served at all
+            if let Err(e) = resolve_plugin_references(&mut plugins, &mut config.secrets) {
+                log_error!("HostConfig {} is not served: {}", hostnames.join(", "), e);
+                continue;
+            }
 
             // Create HostConfig once
+            let canonical_root = Path::new(&host_root).canonicalize().ok();
             let host_config = HostConfig {
                 host_root,
                 plugins,
                 server_header,
+                mime_types,
+                symlink_policy,
+                deny_extensions,
+                deny_paths,
+                hidden_files,
+                canonical_root,
             };
 
             // Insert the same HostConfig for each hostname
@@ -279,6 +631,44 @@ pub fn load_config_from_html(file_path: &str) -> ServerConfig {
     config
 }
 
+/// Resolves the `${env:...}` and `${file:...}` references in the properties
+/// of plugins and their nested plugins, collecting the resolved values
+///
+/// Fails on the first property whose references cannot be resolved.
+fn resolve_plugin_references(plugins: &mut [PluginConfig], secrets: &mut Vec<String>) -> Result<(), String> {
+    for plugin in plugins {
+        for (key, value) in plugin.config.iter_mut() {
+            let interpolated = interpolation::interpolate(value)
+                .map_err(|e| format!("plugin {} property {}: {}", plugin.library, key, e))?;
+            *value = interpolated.value;
+            for secret in interpolated.secrets {
+                if !secrets.contains(&secret) {
+                    secrets.push(secret);
+                }
+            }
+        }
+        resolve_plugin_references(&mut plugin.nested_plugins, secrets)?;
+    }
+    Ok(())
+}
+
+/// Parses `mimeType` properties of the form `extension=content/type`
+fn parse_mime_types(item: &microdata_extract::MicrodataItem) -> HashMap<String, String> {
+    let mut mime_types = HashMap::new();
+    for mapping in item.get_property_values("mimeType") {
+        match mapping.split_once('=') {
+            Some((extension, content_type)) if !extension.trim().is_empty() && !content_type.trim().is_empty() => {
+                let extension = extension.trim().trim_start_matches('.').to_lowercase();
+                mime_types.insert(extension, content_type.trim().to_string());
+            }
+            _ => {
+                log_error!("Ignoring invalid mimeType '{}', expected extension=content/type", mapping);
+            }
+        }
+    }
+    mime_types
+}
+
 /// Parses plugin pipeline from host configuration microdata
 /// 
 /// Processes nested plugin structures and filters out duplicates to build
@@ -349,6 +739,7 @@ fn parse_plugin_config(plugin_item: &microdata_extract::MicrodataItem) -> Option
     }
     
     let config = extract_plugin_properties(plugin_item);
+    let condition = get_direct_property(plugin_item, "condition").filter(|condition| !condition.trim().is_empty());
     let plugin_type = library.as_ref().map(|lib| infer_plugin_type(lib));
     let final_library = library.unwrap_or_else(|| PLUGIN_SCHEME_PIPELINE.to_string());
     
@@ -357,6 +748,7 @@ fn parse_plugin_config(plugin_item: &microdata_extract::MicrodataItem) -> Option
         plugin_type,
         config,
         nested_plugins,
+        condition,
     })
 }
 
@@ -397,7 +789,7 @@ fn extract_plugin_properties(plugin_item: &microdata_extract::MicrodataItem) ->
 
 /// Checks if a property is a core plugin property that shouldn't be included in config
 fn is_core_plugin_property(property_name: &str) -> bool {
-    matches!(property_name, "library" | "realm" | "authfile" | "log_file")
+    matches!(property_name, "library" | "realm" | "authfile" | "log_file" | "condition")
 }
 
 /// Extracts a property value that belongs directly to this item, not nested items
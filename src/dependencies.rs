@@ -0,0 +1,149 @@
+//! Plugin order checks
+//!
+//! Plugins declare the capabilities they provide to the plugins after them
+//! and require from those before them (see
+//! `rusty_beam_plugin_api::capabilities`). When a host's pipeline is built,
+//! every requirement is checked: one no plugin before provides is logged as
+//! an error, one only a later plugin provides as a warning, as the pipeline
+//! is probably misordered. Records carry the host, plugin and capability as
+//! fields, and `--check-config` reports them like any other problem.
+//!
+//! With `pluginAutoSort` the pipeline is reordered first: a plugin is moved
+//! after the first plugin providing each capability it requires, and the
+//! configured order is kept otherwise. Plugins requiring each other's
+//! capabilities are left as configured.
+//!
+//! Plugins nested in another, such as a directory plugin's, are created by
+//! that plugin, so only the capabilities their configuration declares are
+//! checked, and they are never reordered. They can rely on the plugins before
+//! the one they are nested in and on those before them in their own list.
+//! A directory plugin declares what its nested plugins provide and what they
+//! require of the plugins before it, so it is checked like any other.
+
+use crate::config::PluginConfig;
+use crate::logging::SERVER_TARGET;
+use rusty_beam_plugin_api::{log_info, log_warn, Capabilities, Plugin};
+use std::collections::HashSet;
+
+/// A plugin of a pipeline as the checks see it
+struct Entry<'a> {
+    name: String,
+    capabilities: Capabilities,
+    nested: &'a [PluginConfig],
+}
+
+impl<'a> Entry<'a> {
+    /// A plugin the server created, declaring its own capabilities
+    fn loaded(plugin: &dyn Plugin, config: &'a PluginConfig) -> Self {
+        Self {
+            name: plugin.name().to_string(),
+            capabilities: plugin.capabilities().configured(&config.config),
+            nested: &config.nested_plugins,
+        }
+    }
+
+    /// A nested plugin, known only by its configuration
+    fn configured(config: &'a PluginConfig) -> Self {
+        let name = config.config.get("name").cloned()
+            .or_else(|| config.plugin_type.clone())
+            .unwrap_or_else(|| config.library.clone());
+        Self {
+            name,
+            capabilities: Capabilities::default().configured(&config.config),
+            nested: &config.nested_plugins,
+        }
+    }
+}
+
+/// Where a plugin is while the pipeline is sorted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Placement {
+    Waiting,
+    /// Its providers are being placed
+    Placing,
+    Placed,
+}
+
+/// Checks a host's pipeline and returns the order to run its plugins in
+///
+/// `plugins` are the plugins that loaded, in configured order, and the
+/// order is given as indices into it. Without `auto_sort` it is the
+/// configured order.
+pub fn check_pipeline(host: &str, plugins: &[(&dyn Plugin, &PluginConfig)], auto_sort: bool) -> Vec<usize> {
+    let entries: Vec<Entry> = plugins.iter().map(|(plugin, config)| Entry::loaded(*plugin, config)).collect();
+    let order: Vec<usize> = if auto_sort {
+        sorted_order(host, &entries)
+    } else {
+        (0..entries.len()).collect()
+    };
+    if order.iter().enumerate().any(|(position, &index)| position != index) {
+        let names: Vec<&str> = order.iter().map(|&index| entries[index].name.as_str()).collect();
+        log_info!(SERVER_TARGET, "Reordered plugins after the plugins providing what they require: {}", names.join(", "); host = host);
+    }
+    let ordered: Vec<&Entry> = order.iter().map(|&index| &entries[index]).collect();
+    check_entries(host, &ordered, &HashSet::new());
+    order
+}
+
+/// Logs the requirements of `entries` that neither `inherited` nor an
+/// entry before provides
+fn check_entries<'a>(host: &str, entries: &[&'a Entry<'a>], inherited: &HashSet<&'a str>) {
+    let mut available = inherited.clone();
+    for (position, entry) in entries.iter().enumerate() {
+        for capability in entry.capabilities.requires.iter().filter(|c| !available.contains(c.as_str())) {
+            let provider = entries[position + 1..].iter()
+                .find(|later| later.capabilities.provides.contains(capability));
+            match provider {
+                Some(provider) => log_warn!(SERVER_TARGET,
+                    "Plugin {} requires {}, which only {} after it provides; move {} before {} or enable pluginAutoSort",
+                    entry.name, capability, provider.name, provider.name, entry.name;
+                    host = host, plugin = entry.name, capability = capability),
+                None => rusty_beam_plugin_api::log_error!(SERVER_TARGET,
+                    "Plugin {} requires {}, which no plugin before it provides", entry.name, capability;
+                    host = host, plugin = entry.name, capability = capability),
+            }
+        }
+        if !entry.nested.is_empty() {
+            let nested: Vec<Entry> = entry.nested.iter().map(Entry::configured).collect();
+            check_entries(host, &nested.iter().collect::<Vec<_>>(), &available);
+        }
+        available.extend(entry.capabilities.provides.iter().map(String::as_str));
+    }
+}
+
+/// The configured order with every plugin moved after the providers of
+/// the capabilities it requires
+fn sorted_order(host: &str, entries: &[Entry]) -> Vec<usize> {
+    let mut placements = vec![Placement::Waiting; entries.len()];
+    let mut order = Vec::with_capacity(entries.len());
+    for index in 0..entries.len() {
+        place(host, entries, index, &mut placements, &mut order);
+    }
+    order
+}
+
+/// Places a plugin, placing the first provider of each capability it
+/// requires before it
+fn place(host: &str, entries: &[Entry], index: usize, placements: &mut [Placement], order: &mut Vec<usize>) {
+    if placements[index] != Placement::Waiting {
+        return;
+    }
+    placements[index] = Placement::Placing;
+    for capability in &entries[index].capabilities.requires {
+        if order.iter().any(|&placed| entries[placed].capabilities.provides.contains(capability)) {
+            continue;
+        }
+        let provider = entries.iter().enumerate()
+            .position(|(other, entry)| other != index && entry.capabilities.provides.contains(capability));
+        match provider {
+            Some(provider) if placements[provider] == Placement::Placing => {
+                log_warn!(SERVER_TARGET, "Plugins {} and {} require each other's capabilities, leaving them as configured",
+                    entries[provider].name, entries[index].name; host = host, capability = capability);
+            }
+            Some(provider) => place(host, entries, provider, placements, order),
+            None => {}
+        }
+    }
+    placements[index] = Placement::Placed;
+    order.push(index);
+}
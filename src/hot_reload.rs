@@ -0,0 +1,199 @@
+//! Hot reloading of plugin libraries during development
+//!
+//! With `pluginHotReload` enabled in the ServerConfig, every plugin library is
+//! loaded from a private copy in the temporary directory, so rebuilding the
+//! original never disturbs code that is already mapped, and loading a new
+//! build never hands back the handle of the old one. A watcher polls the
+//! original libraries of each host's plugins and, once a changed file has
+//! stopped changing, builds a new instance with the same configuration and
+//! swaps it into the pipeline in place of the old one.
+//!
+//! Requests that are already running keep using the old instance. When the
+//! last of them finishes, its `on_shutdown` hook runs and its library is
+//! unloaded. Plugins nested inside another plugin, such as those configured
+//! for the directory plugin, are not watched.
+
+use crate::config::PluginConfig;
+use crate::{AppState, load_plugin, log_error, log_verbose, resolve_library_path};
+use rusty_beam_plugin_api::Plugin;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+
+/// Directory under the system temporary directory holding private copies
+const SHADOW_DIRECTORY: &str = "rusty-beam-plugins";
+/// How often a replaced instance is checked for remaining requests
+const DRAIN_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Makes every private copy name unique within the process
+static SHADOW_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Modification time and size identifying one build of a library
+type LibraryStamp = (SystemTime, u64);
+
+/// Copies a library to a unique temporary path to load it from
+pub fn shadow_copy(library_path: &str) -> Result<String, String> {
+    let file_name = Path::new(library_path)
+        .file_name()
+        .ok_or_else(|| format!("Invalid library path {}", library_path))?;
+    let directory = std::env::temp_dir().join(SHADOW_DIRECTORY);
+    fs::create_dir_all(&directory).map_err(|e| format!("Failed to create {}: {}", directory.display(), e))?;
+
+    let shadow = directory.join(format!(
+        "{}-{}-{}",
+        std::process::id(),
+        SHADOW_COUNTER.fetch_add(1, Ordering::SeqCst),
+        file_name.to_string_lossy()
+    ));
+    fs::copy(library_path, &shadow).map_err(|e| format!("Failed to copy library: {}", e))?;
+    Ok(shadow.to_string_lossy().into_owned())
+}
+
+/// Removes a private copy after loading it; the loaded mapping stays valid
+pub fn remove_shadow_copy(shadow: &str) {
+    let _ = fs::remove_file(shadow);
+}
+
+/// Starts polling plugin libraries for new builds
+pub fn spawn_watcher(app_state: AppState, interval: Duration) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut watcher = LibraryWatcher::default();
+        let mut ticker = tokio::time::interval(interval);
+        loop {
+            ticker.tick().await;
+            for library_path in watcher.poll(&app_state).await {
+                reload_library(&app_state, &library_path).await;
+            }
+        }
+    })
+}
+
+/// Tracks the build of each plugin library that is loaded
+#[derive(Default)]
+struct LibraryWatcher {
+    /// Stamp of the build currently loaded, per library path
+    loaded: HashMap<String, LibraryStamp>,
+    /// Stamp of a new build seen on the previous poll, per library path
+    pending: HashMap<String, LibraryStamp>,
+}
+
+impl LibraryWatcher {
+    /// Returns the libraries with a new build that has stopped changing
+    ///
+    /// A build is only reported once two polls in a row see the same stamp,
+    /// so a library that is still being written is never loaded.
+    async fn poll(&mut self, app_state: &AppState) -> Vec<String> {
+        let library_paths: HashSet<String> = {
+            let config = app_state.config.read().await;
+            if !config.plugin_hot_reload {
+                return Vec::new();
+            }
+            config.hosts.values()
+                .flat_map(|host_config| &host_config.plugins)
+                .map(|plugin_config| resolve_library_path(&plugin_config.library).to_string())
+                .collect()
+        };
+
+        let mut changed = Vec::new();
+        for library_path in library_paths {
+            // The library may briefly be missing while it is rebuilt
+            let Some(stamp) = library_stamp(&library_path) else {
+                continue;
+            };
+            match self.loaded.get(&library_path) {
+                None => {
+                    self.loaded.insert(library_path, stamp);
+                }
+                Some(loaded) if *loaded == stamp => {
+                    self.pending.remove(&library_path);
+                }
+                Some(_) if self.pending.get(&library_path) == Some(&stamp) => {
+                    self.pending.remove(&library_path);
+                    self.loaded.insert(library_path.clone(), stamp);
+                    changed.push(library_path);
+                }
+                Some(_) => {
+                    self.pending.insert(library_path, stamp);
+                }
+            }
+        }
+        changed
+    }
+}
+
+/// Reads the stamp of a library file
+fn library_stamp(library_path: &str) -> Option<LibraryStamp> {
+    let metadata = fs::metadata(library_path).ok()?;
+    Some((metadata.modified().ok()?, metadata.len()))
+}
+
+/// Replaces every running instance of a library with one from its new build
+async fn reload_library(app_state: &AppState, library_path: &str) {
+    // Pipelines only contain the plugins that loaded, so positions match the
+    // configuration only when every plugin of the host loaded
+    let slots: Vec<(String, usize, PluginConfig, Arc<dyn Plugin>)> = {
+        let config = app_state.config.read().await;
+        let pipelines = app_state.host_pipelines.read().await;
+        let mut slots = Vec::new();
+        for (host_name, host_config) in &config.hosts {
+            let Some(pipeline) = pipelines.get(host_name) else {
+                continue;
+            };
+            for (index, plugin_config) in host_config.plugins.iter().enumerate() {
+                if resolve_library_path(&plugin_config.library) != library_path {
+                    continue;
+                }
+                if pipeline.len() != host_config.plugins.len() {
+                    log_error!(
+                        "Cannot hot reload {} for host {}: some of its plugins failed to load, send SIGHUP instead",
+                        library_path, host_name
+                    );
+                    break;
+                }
+                slots.push((host_name.clone(), index, plugin_config.clone(), Arc::clone(&pipeline[index])));
+            }
+        }
+        slots
+    };
+
+    for (host_name, index, plugin_config, current) in slots {
+        let Some(plugin) = load_plugin(&plugin_config, true) else {
+            log_error!("Failed to reload {} for host {}, keeping the running instance", library_path, host_name);
+            continue;
+        };
+        let plugin: Arc<dyn Plugin> = Arc::from(plugin);
+        plugin.on_startup().await;
+
+        // Only swap if a configuration reload hasn't replaced the instance meanwhile
+        let swapped = {
+            let mut pipelines = app_state.host_pipelines.write().await;
+            match pipelines.get_mut(&host_name).and_then(|pipeline| pipeline.get_mut(index)) {
+                Some(slot) if Arc::ptr_eq(slot, &current) => {
+                    *slot = Arc::clone(&plugin);
+                    true
+                }
+                _ => false,
+            }
+        };
+
+        if swapped {
+            println!("Reloaded plugin {} for host {}", library_path, host_name);
+            tokio::spawn(retire(current, library_path.to_string()));
+        } else {
+            plugin.on_shutdown().await;
+        }
+    }
+}
+
+/// Shuts down a replaced instance and unloads its library once no request uses it
+async fn retire(plugin: Arc<dyn Plugin>, library_path: String) {
+    while Arc::strong_count(&plugin) > 1 {
+        tokio::time::sleep(DRAIN_POLL_INTERVAL).await;
+    }
+    plugin.on_shutdown().await;
+    drop(plugin);
+    log_verbose!("Unloaded previous build of {}", library_path);
+}
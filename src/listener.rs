@@ -0,0 +1,80 @@
+//! Sockets the server accepts connections on
+//!
+//! Each `Listener` item of the configuration becomes one socket: a TCP
+//! address and port, IPv4 or IPv6, or a unix domain socket for a reverse
+//! proxy on the same machine. A unix socket file left behind by a server
+//! that is no longer running is replaced, one another server still answers
+//! on is not, and the file is removed when the listener closes. TLS, when
+//! configured, applies to TCP listeners only.
+
+use crate::config::{ListenerConfig, ListenerEndpoint};
+use hyper::server::accept::Accept;
+use hyper::server::conn::AddrIncoming;
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::task::{ready, Context, Poll};
+use tokio::net::{UnixListener, UnixStream};
+
+/// A bound listener
+pub enum BoundListener {
+    Tcp(AddrIncoming),
+    Unix(UnixIncoming),
+}
+
+/// Binds the socket of a listener
+pub fn bind(config: &ListenerConfig) -> Result<BoundListener, String> {
+    match &config.endpoint {
+        ListenerEndpoint::Tcp { address, port } => {
+            let addr = ListenerEndpoint::socket_addr(address, *port)?;
+            AddrIncoming::bind(&addr)
+                .map(BoundListener::Tcp)
+                .map_err(|e| e.to_string())
+        }
+        ListenerEndpoint::Unix(path) => bind_unix(path.clone(), config.socket_mode).map(BoundListener::Unix),
+    }
+}
+
+fn bind_unix(path: PathBuf, socket_mode: Option<u32>) -> Result<UnixIncoming, String> {
+    if let Ok(metadata) = std::fs::symlink_metadata(&path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{} exists and is not a socket", path.display()));
+        }
+        if std::os::unix::net::UnixStream::connect(&path).is_ok() {
+            return Err(format!("Address already in use: {} is served by another process", path.display()));
+        }
+        // Left behind by a server that stopped without removing it
+        std::fs::remove_file(&path).map_err(|e| format!("Failed to remove stale socket {}: {}", path.display(), e))?;
+    }
+
+    let listener = UnixListener::bind(&path).map_err(|e| e.to_string())?;
+    let incoming = UnixIncoming { listener, path };
+    if let Some(mode) = socket_mode {
+        std::fs::set_permissions(&incoming.path, std::fs::Permissions::from_mode(mode))
+            .map_err(|e| format!("Failed to set permissions of {}: {}", incoming.path.display(), e))?;
+    }
+    Ok(incoming)
+}
+
+/// A listening unix domain socket, whose file is removed when it is dropped
+pub struct UnixIncoming {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl Accept for UnixIncoming {
+    type Conn = UnixStream;
+    type Error = io::Error;
+
+    fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let (stream, _) = ready!(self.listener.poll_accept(cx))?;
+        Poll::Ready(Some(Ok(stream)))
+    }
+}
+
+impl Drop for UnixIncoming {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
@@ -0,0 +1,190 @@
+//! Connection and request limits of the server
+//!
+//! The server counts the connections it holds open and the requests it is
+//! processing. With `maxConnections` set it stops accepting once that many
+//! connections are open, leaving further clients in the listen backlog until
+//! one closes. With `maxInFlightRequests` set, requests beyond that many are
+//! answered at once with a 503 instead of entering the pipeline, so an
+//! overloaded server does not keep buffering bodies and starting scripts.
+//!
+//! The counts are offered to plugins as a `ServerLoad` through the host
+//! services, and the limits follow configuration reloads. Connections on
+//! every listener count towards the same limit.
+
+use crate::config::ServerConfig;
+use hyper::server::accept::Accept;
+use hyper::server::conn::AddrStream;
+use rusty_beam_plugin_api::ServerLoad;
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{ready, Context, Poll, Waker};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Stands for "no limit" in the atomic limits
+const UNLIMITED: usize = 0;
+
+/// Counts of open connections and requests in flight, and their limits
+#[derive(Debug, Default)]
+pub struct LoadLimits {
+    connections: AtomicUsize,
+    in_flight_requests: AtomicUsize,
+    rejected_requests: AtomicU64,
+    max_connections: AtomicUsize,
+    max_in_flight_requests: AtomicUsize,
+    /// The accept loop, when it is waiting for a connection to close
+    accept_waker: Mutex<Option<Waker>>,
+}
+
+impl LoadLimits {
+    pub fn new(config: &ServerConfig) -> Arc<Self> {
+        let limits = Arc::new(Self::default());
+        limits.configure(config);
+        limits
+    }
+
+    /// Take the limits of a new configuration
+    pub fn configure(&self, config: &ServerConfig) {
+        self.max_connections.store(config.max_connections.unwrap_or(UNLIMITED), Ordering::Relaxed);
+        self.max_in_flight_requests.store(config.max_in_flight_requests.unwrap_or(UNLIMITED), Ordering::Relaxed);
+        // A raised limit may let the accept loop continue
+        self.wake_acceptor();
+    }
+
+    /// The counts as plugins see them
+    pub fn snapshot(&self) -> ServerLoad {
+        let limit = |max: &AtomicUsize| Some(max.load(Ordering::Relaxed)).filter(|max| *max != UNLIMITED);
+        ServerLoad {
+            connections: self.connections.load(Ordering::Relaxed),
+            max_connections: limit(&self.max_connections),
+            in_flight_requests: self.in_flight_requests.load(Ordering::Relaxed),
+            max_in_flight_requests: limit(&self.max_in_flight_requests),
+            rejected_requests: self.rejected_requests.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Count a request as in flight until the returned guard is dropped, or
+    /// None if the limit is reached and the request must be rejected
+    pub fn start_request(self: &Arc<Self>) -> Option<RequestGuard> {
+        let max = self.max_in_flight_requests.load(Ordering::Relaxed);
+        let admitted = self.in_flight_requests
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |count| {
+                (max == UNLIMITED || count < max).then_some(count + 1)
+            })
+            .is_ok();
+        if admitted {
+            Some(RequestGuard(self.clone()))
+        } else {
+            self.rejected_requests.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+
+    fn at_connection_limit(&self) -> bool {
+        let max = self.max_connections.load(Ordering::Relaxed);
+        max != UNLIMITED && self.connections.load(Ordering::Acquire) >= max
+    }
+
+    fn wake_acceptor(&self) {
+        if let Some(waker) = self.accept_waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+/// A request counted as in flight
+#[derive(Debug)]
+pub struct RequestGuard(Arc<LoadLimits>);
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.0.in_flight_requests.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// A listening socket that stops accepting at the connection limit
+pub struct LimitedIncoming<I> {
+    incoming: I,
+    limits: Arc<LoadLimits>,
+}
+
+impl<I> LimitedIncoming<I> {
+    pub fn new(incoming: I, limits: Arc<LoadLimits>) -> Self {
+        Self { incoming, limits }
+    }
+}
+
+impl<I> Accept for LimitedIncoming<I>
+where
+    I: Accept<Error = io::Error> + Unpin,
+{
+    type Conn = CountedStream<I::Conn>;
+    type Error = io::Error;
+
+    fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = self.get_mut();
+        if this.limits.at_connection_limit() {
+            *this.limits.accept_waker.lock().unwrap() = Some(cx.waker().clone());
+            // A connection may have closed before the waker was in place
+            if this.limits.at_connection_limit() {
+                return Poll::Pending;
+            }
+        }
+        let stream = match ready!(Pin::new(&mut this.incoming).poll_accept(cx)) {
+            Some(Ok(stream)) => stream,
+            Some(Err(e)) => return Poll::Ready(Some(Err(e))),
+            None => return Poll::Ready(None),
+        };
+        this.limits.connections.fetch_add(1, Ordering::AcqRel);
+        Poll::Ready(Some(Ok(CountedStream { stream, limits: this.limits.clone() })))
+    }
+}
+
+/// A connection counted as open until it is dropped
+pub struct CountedStream<S> {
+    stream: S,
+    limits: Arc<LoadLimits>,
+}
+
+impl CountedStream<AddrStream> {
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.stream.remote_addr()
+    }
+}
+
+impl<S> Drop for CountedStream<S> {
+    fn drop(&mut self) {
+        self.limits.connections.fetch_sub(1, Ordering::AcqRel);
+        self.limits.wake_acceptor();
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountedStream<S> {
+    fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountedStream<S> {
+    fn poll_write(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(mut self: Pin<&mut Self>, cx: &mut Context<'_>, bufs: &[io::IoSlice<'_>]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
@@ -1,22 +1,104 @@
-use std::sync::OnceLock;
+use rusty_beam_plugin_api::log::{self, JsonSink, Level, LogSink, Logger, PrettySink};
+use std::str::FromStr;
+use std::sync::{Arc, OnceLock};
 
 static VERBOSE: OnceLock<bool> = OnceLock::new();
 
+/// Target of the records the server itself writes
+pub const SERVER_TARGET: &str = "server";
+
+/// Tag of records sent to syslog
+#[cfg(unix)]
+const SYSLOG_TAG: &str = "rusty-beam";
+
+/// How log records are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// Human readable lines on stdout and stderr
+    #[default]
+    Pretty,
+    /// One JSON object per line
+    Json,
+    /// Messages to the local syslog daemon
+    Syslog,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.trim().to_lowercase().as_str() {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            "syslog" => Ok(LogFormat::Syslog),
+            other => Err(format!("Unknown log format '{}'", other)),
+        }
+    }
+}
+
 pub fn init_logging(verbose: bool) {
     VERBOSE
         .set(verbose)
         .expect("init_logging called multiple times");
+    configure_logging(LogFormat::Pretty, None, None, Vec::new());
 }
 
 pub fn is_verbose() -> bool {
     *VERBOSE.get().unwrap_or(&false)
 }
 
+/// Replace the active logger
+///
+/// Without a level, records at info level and above are written, and debug
+/// records too in verbose mode. JSON records go to `file` if given, stderr
+/// otherwise. `secrets` are masked in every record. Plugin libraries loaded
+/// afterwards receive the new logger.
+pub fn configure_logging(format: LogFormat, level: Option<Level>, file: Option<&str>, secrets: Vec<String>) {
+    let level = level.unwrap_or(if is_verbose() { Level::Debug } else { Level::Info });
+    let sink: Arc<dyn LogSink> = match format {
+        LogFormat::Pretty => Arc::new(PrettySink),
+        LogFormat::Json => match file {
+            Some(path) => match JsonSink::file(path) {
+                Ok(sink) => Arc::new(sink),
+                Err(e) => {
+                    eprintln!("Failed to open log file {}: {}, logging to stderr", path, e);
+                    Arc::new(JsonSink::stderr())
+                }
+            },
+            None => Arc::new(JsonSink::stderr()),
+        },
+        LogFormat::Syslog => match syslog_sink() {
+            Ok(sink) => sink,
+            Err(e) => {
+                eprintln!("Failed to connect to syslog: {}, logging to the console", e);
+                Arc::new(PrettySink)
+            }
+        },
+    };
+    log::set_logger(Arc::new(Logger::new(level).with_sink(sink).with_secrets(secrets)));
+}
+
+#[cfg(unix)]
+fn syslog_sink() -> std::io::Result<Arc<dyn LogSink>> {
+    Ok(Arc::new(log::SyslogSink::new(SYSLOG_TAG)?))
+}
+
+#[cfg(not(unix))]
+fn syslog_sink() -> std::io::Result<Arc<dyn LogSink>> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, "syslog is not available on this platform"))
+}
+
 #[macro_export]
 macro_rules! log_verbose {
     ($($arg:tt)*) => {
-        if $crate::logging::is_verbose() {
-            println!($($arg)*);
+        if rusty_beam_plugin_api::log::enabled(rusty_beam_plugin_api::log::Level::Debug) {
+            rusty_beam_plugin_api::log::emit(
+                rusty_beam_plugin_api::log::Level::Debug,
+                $crate::logging::SERVER_TARGET,
+                None,
+                format!($($arg)*),
+                Vec::new(),
+            );
         }
     };
 }
@@ -24,6 +106,12 @@ macro_rules! log_verbose {
 #[macro_export]
 macro_rules! log_error {
     ($($arg:tt)*) => {
-        eprintln!($($arg)*);
+        rusty_beam_plugin_api::log::emit(
+            rusty_beam_plugin_api::log::Level::Error,
+            $crate::logging::SERVER_TARGET,
+            None,
+            format!($($arg)*),
+            Vec::new(),
+        );
     };
 }
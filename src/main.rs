@@ -29,32 +29,86 @@
 //!
 //! Supports daemon mode with configurable process management, signal handling for
 //! configuration reloads, and graceful shutdown.
+//!
+//! ## Protocols
+//!
+//! HTTP/1.1 and HTTP/2 are served on the same port. With `tlsCertificate` and
+//! `tlsPrivateKey` configured the server speaks HTTPS and negotiates HTTP/2
+//! through ALPN; keep-alive and HTTP/2 flow control are tunable from the
+//! ServerConfig microdata.
+//!
+//! ## Listeners
+//!
+//! The server listens on `bindAddress` and `bindPort`, unless the
+//! configuration declares `Listener` items, which replace them: several
+//! addresses and ports, IPv4 and IPv6, and unix domain sockets, each serving
+//! every host or only the hostnames it lists. Requests for other hosts get a
+//! 404, as for unknown hosts. See the `listener` module.
+//!
+//! ## Hot Reload
+//!
+//! With `pluginHotReload` enabled, plugin libraries that are rebuilt while the
+//! server runs are reloaded in place; see the `hot_reload` module.
+//!
+//! ## Replay
+//!
+//! `rusty-beam --replay <recording> <config-file>` runs the exchanges captured
+//! by the record-replay plugin through the configured pipelines without
+//! opening a socket, reports any whose status or body changed, and exits
+//! non-zero if there were differences.
+//!
+//! ## Checking a Configuration
+//!
+//! `rusty-beam --check-config <config-file>` loads the configuration and
+//! creates every plugin with its settings, without binding a socket or
+//! starting the plugins. It prints the problems found, such as missing plugin
+//! libraries, unreadable document roots and the files plugins report through
+//! `validate_config`, and exits non-zero if any of them is an error.
 
 // Import modules
+mod check;
 mod config;
 mod constants;
+mod dependencies;
+mod hot_reload;
+mod listener;
+mod load;
 mod logging;
+mod replay;
+mod tls;
 
 use async_trait::async_trait;
 use config::PluginConfig;
-use config::{ServerConfig, load_config_from_html};
+use config::{ListenerConfig, ListenerEndpoint, ServerConfig, load_config_from_html};
+use listener::BoundListener;
 use constants::DEFAULT_SERVER_HEADER;
 
 /// Plugin URL scheme constants
 const PLUGIN_SCHEME_PIPELINE: &str = "pipeline://nested";
 const PLUGIN_SCHEME_DIRECTORY_PREFIX: &str = "directory://";
 const PLUGIN_SCHEME_FILE_PREFIX: &str = "file://";
-use rusty_beam_plugin_api::{PluginContext, PluginRequest, PluginResponse};
+
+/// Seconds clients are told to wait before retrying when too many requests are in flight
+const OVERLOAD_RETRY_AFTER_SECS: &str = "1";
+
+use rusty_beam_plugin_api::deadline;
+use rusty_beam_plugin_api::isolation;
+use rusty_beam_plugin_api::paths::{self, PathError};
+use rusty_beam_plugin_api::timing;
+use rusty_beam_plugin_api::{Capabilities, Condition, ConditionalPlugin, ConfigIssue, DispatchFuture, DocumentChange, HostServices, PipelineServices, PluginContext, PluginFailures, PluginRequest, PluginResponse, SelectorError, SelectorOperation, ServerLoad, SpoolConfig};
 
 use futures::stream::StreamExt;
+use hyper::server::accept::Accept;
 use hyper::service::{make_service_fn, service_fn};
 use hyper::{Body, Request, Response, Result, Server, StatusCode};
-use signal_hook::consts::SIGHUP;
+use signal_hook::consts::{SIGHUP, SIGINT, SIGTERM};
 use signal_hook_tokio::Signals;
 use std::collections::HashMap;
 use std::convert::Infallible;
 use std::env;
+use std::mem::ManuallyDrop;
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tokio::sync::RwLock;
 use uuid::Uuid;
 use daemonize::Daemonize;
@@ -62,10 +116,17 @@ use daemonize::Daemonize;
 /// Type alias for host pipelines
 type HostPipelines = HashMap<String, Vec<Arc<dyn rusty_beam_plugin_api::Plugin>>>;
 
+/// Type alias for a listener serving connections until shutdown has drained
+type ListenerServer<'a> = std::pin::Pin<Box<dyn std::future::Future<Output = std::result::Result<(), hyper::Error>> + 'a>>;
+
 /// Command line arguments
 struct Args {
     verbose: bool,
     config_path: String,
+    /// Recording to replay instead of starting the server
+    replay: Option<String>,
+    /// Check the configuration instead of starting the server
+    check_config: bool,
 }
 
 /// Application State using plugin architecture
@@ -74,46 +135,84 @@ struct AppState {
     config: Arc<RwLock<ServerConfig>>,
     host_pipelines: Arc<RwLock<HostPipelines>>,
     config_path: String,
+    limits: Arc<load::LoadLimits>,
 }
 
 impl AppState {
     async fn new(config_path: String) -> Self {
-        let config = load_config_from_html(&config_path);
-        let host_pipelines = create_host_pipelines(&config);
+        let mut config = load_config_from_html(&config_path);
+        configure_server_logging(&config);
+        let host_pipelines = create_host_pipelines(&mut config);
+        let limits = load::LoadLimits::new(&config);
 
-        Self {
+        let state = Self {
             config: Arc::new(RwLock::new(config)),
             host_pipelines: Arc::new(RwLock::new(host_pipelines)),
             config_path,
-        }
+            limits,
+        };
+        start_host_pipelines(&*state.host_pipelines.read().await, &state).await;
+        state
     }
 
     async fn reload(&self) -> std::result::Result<(), String> {
         // Load new configuration
-        let new_config = load_config_from_html(&self.config_path);
-        let new_pipelines = create_host_pipelines(&new_config);
+        let mut new_config = load_config_from_html(&self.config_path);
+        configure_server_logging(&new_config);
+        let new_pipelines = create_host_pipelines(&mut new_config);
+
+        // Let the running plugins persist their state, such as sessions,
+        // before the new ones restore it. They keep serving until the swap:
+        // holding the write lock meanwhile would deadlock plugins whose
+        // shutdown waits for a request they dispatched.
+        {
+            let old_pipelines = self.host_pipelines.read().await.clone();
+            shutdown_host_pipelines(&old_pipelines).await;
+        }
+        start_host_pipelines(&new_pipelines, self).await;
 
         // Atomically update the shared state
+        self.limits.configure(&new_config);
         {
             let mut config_lock = self.config.write().await;
             *config_lock = new_config;
         }
-
-        {
-            let mut pipelines_lock = self.host_pipelines.write().await;
-            *pipelines_lock = new_pipelines;
-        }
+        *self.host_pipelines.write().await = new_pipelines;
 
         Ok(())
     }
+
+    /// Runs the shutdown hooks of every plugin in the active pipelines
+    async fn shutdown(&self) {
+        let pipelines = self.host_pipelines.read().await;
+        shutdown_host_pipelines(&pipelines).await;
+    }
+}
+
+/// Apply the logging options of a configuration before its plugins load
+fn configure_server_logging(config: &ServerConfig) {
+    logging::configure_logging(config.log_format, config.log_level, config.log_file.as_deref(), config.secrets.clone());
 }
 
 /// Create plugin pipelines for each host based on configuration
 /// Dynamically load a plugin from its library path
-fn load_plugin(plugin_config: &PluginConfig) -> Option<Box<dyn rusty_beam_plugin_api::Plugin>> {
+///
+/// With `hot_reload` the library is loaded from a private copy, leaving the
+/// original free to be rebuilt. A plugin configured with a `condition` is
+/// wrapped so the pipeline skips it for requests the condition rejects.
+fn load_plugin(plugin_config: &PluginConfig, hot_reload: bool) -> Option<Box<dyn rusty_beam_plugin_api::Plugin>> {
     use std::ffi::OsStr;
     use std::path::Path;
 
+    // Parse the condition first, so an invalid one never loads the library
+    let condition = match plugin_config.condition.as_deref().map(Condition::parse).transpose() {
+        Ok(condition) => condition,
+        Err(e) => {
+            log_error!("Invalid condition for plugin {}: {}", plugin_config.library, e);
+            return None;
+        }
+    };
+
     // Convert plugin config to plugin config map
     let mut v2_config = HashMap::new();
     for (key, value) in &plugin_config.config {
@@ -131,41 +230,79 @@ fn load_plugin(plugin_config: &PluginConfig) -> Option<Box<dyn rusty_beam_plugin
         }
     }
 
-    let library_url = &plugin_config.library;
+    // All plugins must be loaded from external libraries - no built-ins
+    let library_path = resolve_library_path(&plugin_config.library);
+
+    let path = Path::new(library_path);
+    let extension = path.extension().and_then(OsStr::to_str);
+
+    let plugin = match extension {
+        Some("so") | Some("dll") | Some("dylib") if isolation::is_isolated(&v2_config) => {
+            load_isolated_plugin(library_path, v2_config)?
+        }
+        Some("so") | Some("dll") | Some("dylib") => {
+            load_dynamic_plugin(library_path, v2_config, hot_reload)?
+        }
+        _ => return None, // WASM and other formats not supported
+    };
 
+    match condition {
+        Some(condition) => Some(Box::new(ConditionalPlugin::new(condition, Arc::from(plugin)))),
+        None => Some(plugin),
+    }
+}
+
+/// Maps a plugin library URL to the path of its shared library
+fn resolve_library_path(library_url: &str) -> &str {
     // Map special URLs to actual plugin paths
-    let library_path = match library_url.as_str() {
+    let library_path = match library_url {
         PLUGIN_SCHEME_PIPELINE => "file://./plugins/libpipeline.so",
         url if url.starts_with(PLUGIN_SCHEME_DIRECTORY_PREFIX) => "file://./plugins/libdirectory.so",
         url => url,
     };
 
-    // All plugins must be loaded from external libraries - no built-ins
-
     // Handle file:// URLs
-    let library_path = library_path.strip_prefix(PLUGIN_SCHEME_FILE_PREFIX).unwrap_or(library_path);
+    library_path.strip_prefix(PLUGIN_SCHEME_FILE_PREFIX).unwrap_or(library_path)
+}
 
-    let path = Path::new(library_path);
-    let extension = path.extension().and_then(OsStr::to_str);
 
-    match extension {
-        Some("so") | Some("dll") | Some("dylib") => {
-            load_dynamic_plugin(library_path, v2_config)
+/// Starts a plugin host process running the plugin in a dynamic library
+///
+/// The host loads the library afresh whenever it starts, so isolated plugins
+/// need no shadow copies for hot reload.
+fn load_isolated_plugin(
+    library_path: &str,
+    config: HashMap<String, String>,
+) -> Option<Box<dyn rusty_beam_plugin_api::Plugin>> {
+    match isolation::IsolatedPlugin::spawn(library_path, config) {
+        Ok(plugin) => Some(Box::new(plugin)),
+        Err(e) => {
+            log_error!("Failed to start plugin host for {}: {}", library_path, e);
+            None
         }
-        _ => None, // WASM and other formats not supported
     }
 }
 
-
 /// Loads a plugin from a dynamic library
 fn load_dynamic_plugin(
     library_path: &str,
     config: HashMap<String, String>,
+    hot_reload: bool,
 ) -> Option<Box<dyn rusty_beam_plugin_api::Plugin>> {
-    match create_plugin_instance(library_path, config) {
+    let instance = if hot_reload {
+        hot_reload::shadow_copy(library_path).and_then(|shadow| {
+            let instance = create_plugin_instance(&shadow, config);
+            hot_reload::remove_shadow_copy(&shadow);
+            instance
+        })
+    } else {
+        create_plugin_instance(library_path, config)
+    };
+
+    match instance {
         Ok(plugin) => Some(plugin),
         Err(error) => {
-            eprintln!("Failed to load plugin {}: {}", library_path, error);
+            log_error!("Failed to load plugin {}: {}", library_path, error);
             None
         }
     }
@@ -189,6 +326,11 @@ fn create_plugin_instance(
             unsafe extern "C" fn(*const std::os::raw::c_char) -> *mut std::ffi::c_void,
         > = lib.get(b"create_plugin")
             .map_err(|_| "Plugin missing create_plugin function")?;
+        
+        // Plugins built before the logging facade do not take a logger
+        if let Ok(set_logger) = lib.get::<unsafe extern "C" fn(*const std::ffi::c_void)>(rusty_beam_plugin_api::log::SET_LOGGER_FUNCTION) {
+            set_logger(rusty_beam_plugin_api::log::logger_into_raw());
+        }
             
         let config_json = serde_json::to_string(&config)
             .map_err(|e| format!("Config serialization failed: {}", e))?;
@@ -204,16 +346,33 @@ fn create_plugin_instance(
         let plugin = *plugin_box;
         
         Ok(Box::new(DynamicPluginWrapper {
-            _library: lib,
-            plugin,
+            plugin: ManuallyDrop::new(plugin),
+            upgraded: AtomicBool::new(false),
+            library: ManuallyDrop::new(lib),
         }))
     }
 }
 
 /// Dynamic library plugin wrapper that keeps the library loaded
 struct DynamicPluginWrapper {
-    _library: libloading::Library, // Keep library alive
-    plugin: Box<dyn rusty_beam_plugin_api::Plugin>,
+    plugin: ManuallyDrop<Box<dyn rusty_beam_plugin_api::Plugin>>,
+    /// Set once the plugin has handed out an upgrade handler, whose code may
+    /// still be running after the plugin itself is dropped
+    upgraded: AtomicBool,
+    library: ManuallyDrop<libloading::Library>, // Keep library alive
+}
+
+impl Drop for DynamicPluginWrapper {
+    fn drop(&mut self) {
+        // The plugin must be dropped before the library that contains its code,
+        // and a library whose upgrade handlers may outlive it is never unloaded
+        unsafe {
+            ManuallyDrop::drop(&mut self.plugin);
+            if !self.upgraded.load(Ordering::SeqCst) {
+                ManuallyDrop::drop(&mut self.library);
+            }
+        }
+    }
 }
 
 impl std::fmt::Debug for DynamicPluginWrapper {
@@ -229,7 +388,11 @@ impl rusty_beam_plugin_api::Plugin for DynamicPluginWrapper {
         request: &mut PluginRequest,
         context: &PluginContext,
     ) -> Option<PluginResponse> {
-        self.plugin.handle_request(request, context).await
+        let response = self.plugin.handle_request(request, context).await;
+        if response.as_ref().is_some_and(|response| response.upgrade.is_some()) {
+            self.upgraded.store(true, Ordering::SeqCst);
+        }
+        response
     }
 
     async fn handle_response(
@@ -243,6 +406,73 @@ impl rusty_beam_plugin_api::Plugin for DynamicPluginWrapper {
             .await
     }
 
+    async fn handle_response_stream(
+        &self,
+        request: &PluginRequest,
+        head: &mut hyper::http::response::Parts,
+        body: Body,
+        context: &PluginContext,
+    ) -> Body {
+        self.plugin
+            .handle_response_stream(request, head, body, context)
+            .await
+    }
+
+    async fn on_startup(&self) {
+        self.plugin.on_startup().await
+    }
+
+    fn attach_services(&self, services: Arc<dyn HostServices>) {
+        self.plugin.attach_services(services)
+    }
+
+    async fn on_shutdown(&self) {
+        self.plugin.on_shutdown().await
+    }
+
+    fn check_access(&self, user: Option<&str>, method: &str, path: &str, context: &PluginContext) -> Option<bool> {
+        self.plugin.check_access(user, method, path, context)
+    }
+
+    fn publish(&self, channel: &str, message: &str) -> usize {
+        self.plugin.publish(channel, message)
+    }
+
+    fn on_change(&self, change: &DocumentChange, context: &PluginContext) {
+        self.plugin.on_change(change, context)
+    }
+
+    fn check_selector_access(
+        &self,
+        user: Option<&str>,
+        method: &str,
+        path: &str,
+        selector: &str,
+        context: &PluginContext,
+    ) -> Option<bool> {
+        self.plugin.check_selector_access(user, method, path, selector, context)
+    }
+
+    fn apply_selector(&self, operation: &SelectorOperation, context: &PluginContext) -> Option<std::result::Result<String, SelectorError>> {
+        self.plugin.apply_selector(operation, context)
+    }
+
+    fn withheld_selectors(&self, user: Option<&str>, path: &str, context: &PluginContext) -> Vec<String> {
+        self.plugin.withheld_selectors(user, path, context)
+    }
+
+    fn validate_config(&self, context: &PluginContext) -> Vec<ConfigIssue> {
+        self.plugin.validate_config(context)
+    }
+
+    fn failures(&self) -> Option<PluginFailures> {
+        self.plugin.failures()
+    }
+
+    fn capabilities(&self) -> Capabilities {
+        self.plugin.capabilities()
+    }
+
     fn name(&self) -> &str {
         self.plugin.name()
     }
@@ -276,34 +506,177 @@ fn create_error_response_with_headers(
     builder.body(Body::from(body.to_string())).unwrap()
 }
 
-fn create_host_pipelines(config: &ServerConfig) -> HostPipelines {
+/// Loads the plugins of every host
+///
+/// Each pipeline's order is checked against what its plugins require, see
+/// the `dependencies` module. With `pluginAutoSort` a reordered host's plugin
+/// configurations are put in the new order too, as hot reload finds a
+/// plugin's instance by its position.
+fn create_host_pipelines(config: &mut ServerConfig) -> HostPipelines {
     let mut host_pipelines = HashMap::new();
+    let hot_reload = config.plugin_hot_reload;
+    let auto_sort = config.plugin_auto_sort;
 
     // Create pipelines for each configured host
 
-    for (host_name, host_config) in &config.hosts {
-        let mut pipeline: Vec<Arc<dyn rusty_beam_plugin_api::Plugin>> = Vec::new();
-
-        // Load plugins for this host
+    for (host_name, host_config) in config.hosts.iter_mut() {
+        // Loaded plugins with the position of their configuration
+        let mut loaded: Vec<(Arc<dyn rusty_beam_plugin_api::Plugin>, usize)> = Vec::new();
 
         // Load plugins in order from config
-        for plugin_config in &host_config.plugins {
+        for (index, plugin_config) in host_config.plugins.iter().enumerate() {
             // Attempt to load the plugin
-            if let Some(plugin) = load_plugin(plugin_config) {
+            if let Some(plugin) = load_plugin(plugin_config, hot_reload) {
                 // Plugin loaded successfully
-                pipeline.push(Arc::from(plugin));
+                loaded.push((Arc::from(plugin), index));
             } else {
                 eprintln!("Warning: Failed to load plugin: {}", plugin_config.library);
             }
         }
 
+        let order = {
+            let plugins: Vec<(&dyn rusty_beam_plugin_api::Plugin, &PluginConfig)> = loaded.iter()
+                .map(|(plugin, index)| (plugin.as_ref(), &host_config.plugins[*index]))
+                .collect();
+            dependencies::check_pipeline(host_name, &plugins, auto_sort)
+        };
+        // Hot reload refuses hosts whose plugins did not all load
+        if loaded.len() == host_config.plugins.len() {
+            host_config.plugins = order.iter().map(|&position| host_config.plugins[loaded[position].1].clone()).collect();
+        }
+
         // Pipeline configured for host
+        let pipeline = order.iter().map(|&position| Arc::clone(&loaded[position].0)).collect();
         host_pipelines.insert(host_name.clone(), pipeline);
     }
 
     host_pipelines
 }
 
+/// Hands every plugin in the given pipelines the services of its host and
+/// calls `on_startup`
+async fn start_host_pipelines(host_pipelines: &HostPipelines, app_state: &AppState) {
+    for (host_name, pipeline) in host_pipelines {
+        let services: Arc<dyn HostServices> = Arc::new(ServerServices::new(pipeline.clone(), host_name, app_state));
+        for plugin in pipeline {
+            plugin.attach_services(Arc::clone(&services));
+            plugin.on_startup().await;
+        }
+    }
+}
+
+/// Calls `on_shutdown` on every plugin in the given pipelines
+async fn shutdown_host_pipelines(host_pipelines: &HostPipelines) {
+    for plugin in host_pipelines.values().flatten() {
+        plugin.on_shutdown().await;
+    }
+}
+
+/// Host services of the server: the plugins of a pipeline answer the
+/// questions, and dispatched requests run through the full request path
+struct ServerServices {
+    pipeline: PipelineServices,
+    host_name: String,
+    app_state: AppState,
+    runtime: tokio::runtime::Handle,
+}
+
+impl ServerServices {
+    fn new(pipeline: Vec<Arc<dyn rusty_beam_plugin_api::Plugin>>, host_name: &str, app_state: &AppState) -> Self {
+        Self {
+            pipeline: PipelineServices::new(pipeline),
+            host_name: host_name.to_string(),
+            app_state: app_state.clone(),
+            runtime: tokio::runtime::Handle::current(),
+        }
+    }
+}
+
+impl HostServices for ServerServices {
+    fn is_allowed(&self, user: Option<&str>, method: &str, path: &str, context: &PluginContext) -> bool {
+        self.pipeline.is_allowed(user, method, path, context)
+    }
+
+    fn publish(&self, channel: &str, message: &str) -> usize {
+        self.pipeline.publish(channel, message)
+    }
+
+    fn notify_change(&self, change: &DocumentChange, context: &PluginContext) {
+        self.pipeline.notify_change(change, context)
+    }
+
+    fn apply_selector(
+        &self,
+        user: Option<&str>,
+        operation: &SelectorOperation,
+        context: &PluginContext,
+    ) -> std::result::Result<String, SelectorError> {
+        self.pipeline.apply_selector(user, operation, context)
+    }
+
+    fn withheld_selectors(&self, user: Option<&str>, path: &str, context: &PluginContext) -> Vec<String> {
+        self.pipeline.withheld_selectors(user, path, context)
+    }
+
+    fn server_load(&self) -> Option<ServerLoad> {
+        Some(self.app_state.limits.snapshot())
+    }
+
+    fn plugin_failures(&self) -> Vec<PluginFailures> {
+        self.pipeline.plugin_failures()
+    }
+
+    fn dispatch(&self, mut request: Request<Body>) -> DispatchFuture {
+        // Requests without a Host header go to the host the services belong to
+        if !request.headers().contains_key(hyper::header::HOST)
+            && let Ok(host) = hyper::header::HeaderValue::from_str(&self.host_name)
+        {
+            request.headers_mut().insert(hyper::header::HOST, host);
+        }
+        // Run on the server's runtime, as the caller may be on a thread of its own
+        let task = self.runtime.spawn(process_request_through_pipeline(request, self.app_state.clone(), None));
+        Box::pin(async move {
+            match task.await {
+                Ok(Ok(result)) => result.response,
+                _ => create_error_response(StatusCode::INTERNAL_SERVER_ERROR, "Internal request failed"),
+            }
+        })
+    }
+}
+
+/// The host configuration plugins see in their `PluginContext`
+fn host_config_map(host_config: &config::HostConfig) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    map.insert("hostRoot".to_string(), host_config.host_root.clone());
+    map.insert(paths::CONFIG_KEY_SYMLINK_POLICY.to_string(), host_config.symlink_policy.as_str().to_string());
+    if let Some(server_header) = &host_config.server_header {
+        map.insert("serverHeader".to_string(), server_header.clone());
+    }
+    for (extension, content_type) in &host_config.mime_types {
+        map.insert(format!("mimeType.{}", extension), content_type.clone());
+    }
+    let deny_lists = [
+        ("denyExtensions", &host_config.deny_extensions),
+        ("denyPaths", &host_config.deny_paths),
+        ("hiddenFiles", &host_config.hidden_files),
+    ];
+    for (key, value) in deny_lists {
+        if let Some(value) = value {
+            map.insert(key.to_string(), value.clone());
+        }
+    }
+    map
+}
+
+/// The server configuration plugins see in their `PluginContext`
+fn server_config_map(config: &ServerConfig) -> HashMap<String, String> {
+    let mut map = HashMap::new();
+    map.insert("serverRoot".to_string(), config.server_root.clone());
+    map.insert("bindAddress".to_string(), config.bind_address.clone());
+    map.insert("bindPort".to_string(), config.bind_port.to_string());
+    map
+}
+
 /// Result of processing a request through the plugin pipeline
 struct PipelineResult {
     response: Response<Body>,
@@ -314,9 +687,11 @@ struct PipelineResult {
 async fn process_request_through_pipeline(
     req: Request<Body>,
     app_state: AppState,
+    listener: Option<&ListenerConfig>,
 ) -> Result<PipelineResult> {
     use std::collections::HashMap;
 
+    let received = timing::now_micros();
     let raw_path = req.uri().path();
     let host_name = req
         .headers()
@@ -330,11 +705,12 @@ async fn process_request_through_pipeline(
 
     // Process request for the given host and path
 
-    // Decode percent-encoded URI path (RFC 3986)
-    let path = match urlencoding::decode(raw_path) {
-        Ok(decoded) => decoded.into_owned(),
-        Err(_) => {
-            let response = create_error_response(StatusCode::BAD_REQUEST, "Invalid URI encoding");
+    // Decode and normalize the URI path once, so every plugin compares the same spelling
+    let path = match paths::normalize_request_path(raw_path) {
+        Ok(normalized) => normalized,
+        Err(e) => {
+            log_verbose!("Refusing {}: {}", raw_path, e);
+            let response = create_error_response(StatusCode::BAD_REQUEST, "Invalid request path");
             return Ok(PipelineResult {
                 response,
                 upgrade_handler: None,
@@ -342,10 +718,12 @@ async fn process_request_through_pipeline(
         }
     };
 
-    // Get the plugin pipeline for this host
-    let pipeline = {
+    // Get the plugin pipeline for this host, if the listener serves it
+    let pipeline = if listener.is_none_or(|listener| listener.serves(&host_name)) {
         let host_pipelines = app_state.host_pipelines.read().await;
         host_pipelines.get(&host_name).cloned()
+    } else {
+        None
     };
 
     let pipeline = match pipeline {
@@ -368,15 +746,19 @@ async fn process_request_through_pipeline(
         | &hyper::Method::POST
         | &hyper::Method::PUT
         | &hyper::Method::DELETE
+        | &hyper::Method::PATCH
         | &hyper::Method::OPTIONS => {
             // Supported methods, continue
         }
+        method if matches!(method.as_str(), "COPY" | "MOVE") => {
+            // WebDAV-style copy and move, continue
+        }
         _ => {
             // Unsupported method, return 405
             let response = create_error_response_with_headers(
                 StatusCode::METHOD_NOT_ALLOWED,
                 "Method not allowed",
-                vec![("Allow", "GET, HEAD, POST, PUT, DELETE, OPTIONS")],
+                vec![("Allow", "GET, HEAD, POST, PUT, DELETE, PATCH, OPTIONS, COPY, MOVE")],
             );
             return Ok(PipelineResult {
                 response,
@@ -387,31 +769,47 @@ async fn process_request_through_pipeline(
 
     // Create a PluginRequest
     let mut plugin_request = PluginRequest::new(req, path.clone());
+    plugin_request.set_metadata(timing::REQUEST_START.to_string(), received.to_string());
+    if plugin_request.http_request.extensions().get::<replay::Replayed>().is_some() {
+        plugin_request.set_metadata(replay::REPLAYED_METADATA_KEY.to_string(), "true".to_string());
+    }
 
     // Get host configuration
-    let (host_config_map, server_config_map) = {
+    let (host_config_map, server_config_map, resolved_path, request_timeout) = {
         let config = app_state.config.read().await;
-        let host_config = config
-            .hosts
-            .get(&host_name)
-            .map(|hc| {
-                let mut map = HashMap::new();
-                map.insert("hostRoot".to_string(), hc.host_root.clone());
-                if let Some(server_header) = &hc.server_header {
-                    map.insert("serverHeader".to_string(), server_header.clone());
-                }
-                map
-            })
-            .unwrap_or_default();
+        if config.body_spool_threshold > 0 {
+            let directory = config.body_spool_directory.clone()
+                .map(std::path::PathBuf::from)
+                .unwrap_or_else(std::env::temp_dir);
+            plugin_request.spool = Some(SpoolConfig::new(config.body_spool_threshold, directory));
+        }
+        let host = config.hosts.get(&host_name);
+        // Resolve the file the request addresses once for every plugin
+        let resolved_path = host.map(|hc| match &hc.canonical_root {
+            Some(root) => paths::resolve_within(root, &path, hc.symlink_policy),
+            None => paths::resolve(std::path::Path::new(&hc.host_root), &path, hc.symlink_policy),
+        });
+        let host_config = host.map(host_config_map).unwrap_or_default();
 
-        let mut server_map = HashMap::new();
-        server_map.insert("serverRoot".to_string(), config.server_root.clone());
-        server_map.insert("bindAddress".to_string(), config.bind_address.clone());
-        server_map.insert("bindPort".to_string(), config.bind_port.to_string());
+        let request_timeout = config.request_timeout_ms.map(std::time::Duration::from_millis);
 
-        (host_config, server_map)
+        (host_config, server_config_map(&config), resolved_path, request_timeout)
     };
 
+    match resolved_path {
+        Some(Ok(resolved)) => plugin_request.canonical_path = Some(resolved),
+        Some(Err(PathError::Escapes)) => {
+            log_verbose!("Refusing {}: {}", path, PathError::Escapes);
+            let response = create_error_response(StatusCode::FORBIDDEN, "Access denied");
+            return Ok(PipelineResult {
+                response,
+                upgrade_handler: None,
+            });
+        }
+        // Plugins report a missing root in their own way
+        Some(Err(PathError::RootUnavailable)) | None => {}
+    }
+
     // Create server metadata
     let mut server_metadata = HashMap::new();
     server_metadata.insert("config_file_path".to_string(), app_state.config_path.clone());
@@ -426,19 +824,28 @@ async fn process_request_through_pipeline(
         request_id: Uuid::new_v4().to_string(),
         runtime_handle: Some(tokio::runtime::Handle::current()),
         verbose: logging::is_verbose(),
+        services: Some(Arc::new(ServerServices::new(pipeline.clone(), &host_name, &app_state))),
+        deadline: deadline::tighten(None, request_timeout),
     };
 
     // Execute the plugin pipeline
     let mut final_response = None;
     let mut upgrade_handler = None;
     
-    for (_i, plugin) in pipeline.iter().enumerate() {
-        // Execute plugin in pipeline
+    for plugin in pipeline.iter() {
+        // Execute plugin in pipeline, noting how long it takes
+        let started = std::time::Instant::now();
+        let outcome = deadline::within(
+            plugin_context.deadline,
+            plugin.handle_request(&mut plugin_request, &plugin_context),
+        ).await;
+        timing::record_plugin_duration(&mut plugin_request, plugin.name(), started.elapsed());
+        let outcome = outcome.unwrap_or_else(|_| {
+            log_error!("Plugin {} exceeded the request deadline for {}", plugin.name(), path);
+            Some(deadline::exceeded_response(plugin.name()).into())
+        });
 
-        if let Some(plugin_response) = plugin
-            .handle_request(&mut plugin_request, &plugin_context)
-            .await
-        {
+        if let Some(plugin_response) = outcome {
             // Plugin handled the request
             
             let mut response = plugin_response.response;
@@ -466,9 +873,9 @@ async fn process_request_through_pipeline(
 
     // If we have a response, call handle_response on all plugins
     if let Some(mut response) = final_response {
+        timing::record_ttfb(&mut plugin_request);
         for plugin in pipeline.iter() {
-            plugin
-                .handle_response(&plugin_request, &mut response, &plugin_context)
+            rusty_beam_plugin_api::run_response_phase(plugin.as_ref(), &plugin_request, &mut response, &plugin_context)
                 .await;
         }
         
@@ -489,7 +896,16 @@ async fn process_request_through_pipeline(
 }
 
 /// Handle incoming requests using plugin architecture
-async fn handle_request(req: Request<Body>, app_state: AppState) -> Result<Response<Body>> {
+async fn handle_request(req: Request<Body>, app_state: AppState, listener: Arc<ListenerConfig>) -> Result<Response<Body>> {
+    // Overloaded servers turn requests away before reading their bodies
+    let Some(_in_flight) = app_state.limits.start_request() else {
+        return Ok(create_error_response_with_headers(
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Server is too busy, try again shortly",
+            vec![("Retry-After", OVERLOAD_RETRY_AFTER_SECS)],
+        ));
+    };
+    
     // Check if this might be an upgrade request before processing
     let mut req = req;
     let is_upgrade = req.method() != &hyper::Method::OPTIONS &&
@@ -509,7 +925,7 @@ async fn handle_request(req: Request<Body>, app_state: AppState) -> Result<Respo
         None
     };
     
-    let pipeline_result = process_request_through_pipeline(req, app_state).await?;
+    let pipeline_result = process_request_through_pipeline(req, app_state, Some(&listener)).await?;
     
     // Handle upgrade if present
     if let Some(upgrade_handler) = pipeline_result.upgrade_handler {
@@ -537,6 +953,20 @@ async fn handle_request(req: Request<Body>, app_state: AppState) -> Result<Respo
 fn main() {
     let args = parse_command_line();
     let config_path = validate_config_path(&args.config_path);
+
+    if args.check_config {
+        let passed = run_check(&config_path);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
+    if let Some(recording) = args.replay {
+        logging::init_logging(args.verbose);
+        let runtime = tokio::runtime::Runtime::new().expect("Failed to create tokio runtime");
+        let passed = runtime.block_on(run_replay(config_path, &recording));
+        drop(runtime);
+        std::process::exit(if passed { 0 } else { 1 });
+    }
+
     let config = load_config_from_html(&config_path);
     
     // Daemonize if not in verbose mode
@@ -566,78 +996,257 @@ fn main() {
 async fn async_main(config_path: String, verbose: bool) {
     let app_state = AppState::new(config_path).await;
     
+    // Watch plugin libraries for new builds in development
+    let _watcher_task = {
+        let config = app_state.config.read().await;
+        config.plugin_hot_reload.then(|| hot_reload::spawn_watcher(
+            app_state.clone(),
+            std::time::Duration::from_secs(config.plugin_watch_interval),
+        ))
+    };
+    
     // Set up signal handling
-    let signals_task = setup_signal_handler(app_state.clone());
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+    let _signals_task = setup_signal_handler(app_state.clone(), shutdown_tx);
     
-    // Start server
-    tokio::select! {
-        result = start_http_server(&app_state, verbose) => {
-            if let Err(e) = result {
-                eprintln!("Server error: {}", e);
-                std::process::exit(1);
-            }
+    // Run the server until it has drained after a shutdown signal
+    if let Err(e) = start_http_server(&app_state, verbose, shutdown_rx).await {
+        eprintln!("Server error: {}", e);
+        std::process::exit(1);
+    }
+    
+    // Give plugins a chance to flush logs and persist state
+    app_state.shutdown().await;
+    println!("Shutdown complete");
+}
+
+/// Replays a recording through the pipelines and reports differences
+///
+/// Returns true when every exchange produced the recorded status and body.
+async fn run_replay(config_path: String, recording: &str) -> bool {
+    let exchanges = match replay::load_recording(recording) {
+        Ok(exchanges) => exchanges,
+        Err(e) => {
+            eprintln!("Failed to load recording: {}", e);
+            return false;
         }
-        _ = signals_task => {
-            // Signal handler task ended
+    };
+
+    let app_state = AppState::new(config_path).await;
+    let mut failures = 0;
+
+    for exchange in &exchanges {
+        let result = match exchange.to_request() {
+            Ok(request) => process_request_through_pipeline(request, app_state.clone(), None).await
+                .map_err(|e| e.to_string()),
+            Err(e) => Err(e),
+        };
+        let differences = match result {
+            Ok(pipeline_result) => {
+                let status = pipeline_result.response.status().as_u16();
+                match hyper::body::to_bytes(pipeline_result.response.into_body()).await {
+                    Ok(body) => exchange.differences(status, &body),
+                    Err(e) => vec![format!("failed to read response body: {}", e)],
+                }
+            }
+            Err(e) => vec![e],
+        };
+
+        if differences.is_empty() {
+            println!("PASS {} {} -> {}", exchange.method, exchange.url, exchange.expected_status);
+        } else {
+            failures += 1;
+            println!("FAIL {} {}: {}", exchange.method, exchange.url, differences.join("; "));
         }
     }
+
+    app_state.shutdown().await;
+    println!("{} exchanges replayed, {} passed, {} failed", exchanges.len(), exchanges.len() - failures, failures);
+    failures == 0
 }
 
-/// Sets up SIGHUP signal handler for configuration reload
-fn setup_signal_handler(app_state: AppState) -> tokio::task::JoinHandle<()> {
-    let signals = Signals::new([SIGHUP]).expect("Failed to register signal handler");
+/// Sets up signal handlers
+///
+/// SIGHUP reloads the configuration; SIGTERM and SIGINT begin a graceful
+/// shutdown by notifying the server through `shutdown_tx`.
+fn setup_signal_handler(
+    app_state: AppState,
+    shutdown_tx: tokio::sync::watch::Sender<bool>,
+) -> tokio::task::JoinHandle<()> {
+    let signals = Signals::new([SIGHUP, SIGTERM, SIGINT]).expect("Failed to register signal handler");
     
     tokio::spawn(async move {
         let mut signals = signals;
         while let Some(signal) = signals.next().await {
-            if signal == SIGHUP {
-                println!("Received SIGHUP, reloading configuration...");
-                match app_state.reload().await {
-                    Ok(()) => println!("Configuration reloaded successfully"),
-                    Err(e) => eprintln!("Failed to reload configuration: {}", e),
+            match signal {
+                SIGHUP => {
+                    println!("Received SIGHUP, reloading configuration...");
+                    match app_state.reload().await {
+                        Ok(()) => println!("Configuration reloaded successfully"),
+                        Err(e) => eprintln!("Failed to reload configuration: {}", e),
+                    }
+                }
+                SIGTERM | SIGINT => {
+                    if *shutdown_tx.borrow() {
+                        // Second signal while draining, stop immediately
+                        eprintln!("Received second shutdown signal, exiting immediately");
+                        std::process::exit(1);
+                    }
+                    println!("Received shutdown signal, draining connections...");
+                    let _ = shutdown_tx.send(true);
                 }
+                _ => {}
             }
         }
     })
 }
 
 /// Starts the HTTP server
-async fn start_http_server(app_state: &AppState, verbose: bool) -> std::result::Result<(), hyper::Error> {
+///
+/// Binds every configured listener, serving HTTPS on TCP listeners when a
+/// TLS certificate and key are configured and plain HTTP otherwise. Once
+/// `shutdown_rx` flips to `true` the listeners stop accepting connections
+/// and wait for in-flight requests to complete, for at most the configured
+/// shutdown timeout.
+async fn start_http_server(
+    app_state: &AppState,
+    verbose: bool,
+    shutdown_rx: tokio::sync::watch::Receiver<bool>,
+) -> std::result::Result<(), hyper::Error> {
     let config = app_state.config.read().await;
-    let addr = format!("{}:{}", config.bind_address, config.bind_port)
-        .parse::<std::net::SocketAddr>()
-        .expect("Invalid address format");
+    let drain_timeout = std::time::Duration::from_secs(config.shutdown_timeout);
+    if config.listeners.is_empty() {
+        eprintln!("Failed to start server: no usable Listener is configured");
+        std::process::exit(1);
+    }
     
-    let make_svc = make_service_fn(move |_conn| {
+    let acceptor = match (&config.tls_certificate, &config.tls_private_key) {
+        (Some(certificate), Some(private_key)) => match tls::build_acceptor(certificate, private_key, config.http2) {
+            Ok(acceptor) => Some(acceptor),
+            Err(e) => {
+                eprintln!("Failed to configure TLS: {}", e);
+                std::process::exit(1);
+            }
+        },
+        _ => None,
+    };
+    
+    // Every listener is bound before any serves, so a bad one stops startup
+    let mut servers: Vec<ListenerServer> = Vec::new();
+    for listener_config in &config.listeners {
+        let listener = Arc::new(listener_config.clone());
+        let bound = match listener::bind(listener_config) {
+            Ok(bound) => bound,
+            Err(e) => handle_bind_error(&e, &listener_config.endpoint),
+        };
+        let shutdown_rx = shutdown_rx.clone();
+        match (bound, &acceptor) {
+            (BoundListener::Tcp(incoming), Some(acceptor)) => {
+                let incoming = load::LimitedIncoming::new(incoming, app_state.limits.clone());
+                let builder = configure_protocols(Server::builder(tls::incoming(incoming, acceptor.clone())), &config);
+                servers.push(Box::pin(serve_connections(builder, app_state, listener, shutdown_rx, drain_timeout)));
+            }
+            (BoundListener::Tcp(incoming), None) => {
+                let incoming = load::LimitedIncoming::new(incoming, app_state.limits.clone());
+                let builder = configure_protocols(Server::builder(incoming), &config);
+                servers.push(Box::pin(serve_connections(builder, app_state, listener, shutdown_rx, drain_timeout)));
+            }
+            (BoundListener::Unix(incoming), _) => {
+                let incoming = load::LimitedIncoming::new(incoming, app_state.limits.clone());
+                let builder = configure_protocols(Server::builder(incoming), &config);
+                servers.push(Box::pin(serve_connections(builder, app_state, listener, shutdown_rx, drain_timeout)));
+            }
+        }
+    }
+    drop(config);
+    
+    if verbose {
+        print_startup_info(app_state).await;
+    }
+    
+    futures::future::try_join_all(servers).await.map(|_| ())
+}
+
+/// Applies HTTP/1.1 keep-alive and HTTP/2 tuning from the server configuration
+fn configure_protocols<I>(builder: hyper::server::Builder<I>, config: &ServerConfig) -> hyper::server::Builder<I> {
+    let mut builder = builder
+        .http1_keepalive(config.keep_alive)
+        .http2_max_concurrent_streams(config.http2_max_concurrent_streams)
+        .http2_initial_stream_window_size(config.http2_initial_stream_window_size)
+        .http2_initial_connection_window_size(config.http2_initial_connection_window_size)
+        .http2_keep_alive_interval(config.http2_keep_alive_interval.map(std::time::Duration::from_secs));
+    
+    if let Some(secs) = config.http2_keep_alive_timeout {
+        builder = builder.http2_keep_alive_timeout(std::time::Duration::from_secs(secs));
+    }
+    // The header read timer also covers the wait for the next request on an
+    // idle keep-alive connection
+    if let Some(secs) = config.keep_alive_timeout {
+        builder = builder.http1_header_read_timeout(std::time::Duration::from_secs(secs));
+    }
+    if !config.http2 {
+        builder = builder.http1_only(true);
+    }
+    
+    builder
+}
+
+/// Serves connections from `builder` until shutdown has drained
+async fn serve_connections<I>(
+    builder: hyper::server::Builder<I>,
+    app_state: &AppState,
+    listener: Arc<ListenerConfig>,
+    shutdown_rx: tokio::sync::watch::Receiver<bool>,
+    drain_timeout: std::time::Duration,
+) -> std::result::Result<(), hyper::Error>
+where
+    I: Accept,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    I::Conn: tokio::io::AsyncRead + tokio::io::AsyncWrite + Unpin + Send + 'static,
+{
+    let make_svc = make_service_fn(move |_conn: &I::Conn| {
         let app_state = app_state.clone();
+        let listener = listener.clone();
         async move {
             Ok::<_, Infallible>(service_fn(move |req| {
                 let app_state = app_state.clone();
-                handle_request(req, app_state)
+                handle_request(req, app_state, listener.clone())
             }))
         }
     });
     
-    let server = match Server::try_bind(&addr) {
-        Ok(builder) => builder.serve(make_svc),
-        Err(e) => {
-            handle_bind_error(e, &config.bind_address, config.bind_port);
-        }
-    };
+    let mut graceful_rx = shutdown_rx.clone();
+    let server = builder
+        .serve(make_svc)
+        .with_graceful_shutdown(async move {
+            let _ = graceful_rx.wait_for(|shutdown| *shutdown).await;
+        });
     
-    if verbose {
-        print_startup_info(app_state).await;
+    tokio::pin!(server);
+    let mut shutdown_rx = shutdown_rx;
+    tokio::select! {
+        result = &mut server => return result,
+        _ = shutdown_rx.wait_for(|shutdown| *shutdown) => {}
     }
     
-    server.await
+    // Shutdown requested, allow in-flight requests to finish
+    match tokio::time::timeout(drain_timeout, server).await {
+        Ok(result) => result,
+        Err(_) => {
+            eprintln!(
+                "Shutdown timeout of {}s elapsed, closing remaining connections",
+                drain_timeout.as_secs()
+            );
+            Ok(())
+        }
+    }
 }
 
 /// Handles server bind errors with helpful messages
-fn handle_bind_error(error: hyper::Error, bind_address: &str, bind_port: u16) -> ! {
-    eprintln!("Failed to start server on {}:{}", bind_address, bind_port);
-    eprintln!("Error: {}", error);
+fn handle_bind_error(error_msg: &str, endpoint: &ListenerEndpoint) -> ! {
+    eprintln!("Failed to start server on {}", endpoint);
+    eprintln!("Error: {}", error_msg);
     
-    let error_msg = error.to_string();
     if error_msg.contains("Address already in use") {
         eprintln!("\nAnother process is using this port. Try:");
         eprintln!("  - Stopping the other server");
@@ -656,8 +1265,26 @@ fn handle_bind_error(error: hyper::Error, bind_address: &str, bind_port: u16) ->
 async fn print_startup_info(app_state: &AppState) {
     let config = app_state.config.read().await;
     println!("PID: {}", std::process::id());
-    println!("Rusty Beam server running on http://{}:{}", config.bind_address, config.bind_port);
-    println!("Send SIGHUP to reload configuration");
+    for listener in &config.listeners {
+        let scheme = match listener.endpoint {
+            ListenerEndpoint::Tcp { .. } if config.tls_enabled() => "https://",
+            ListenerEndpoint::Tcp { .. } => "http://",
+            ListenerEndpoint::Unix(_) => "",
+        };
+        let hosts = if listener.hostnames.is_empty() {
+            String::new()
+        } else {
+            format!(" for {}", listener.hostnames.join(", "))
+        };
+        println!("Rusty Beam server running on {}{}{}", scheme, listener.endpoint, hosts);
+    }
+    if config.http2 {
+        println!("HTTP/2 enabled{}", if config.tls_enabled() { " via ALPN" } else { " (prior knowledge)" });
+    }
+    if config.plugin_hot_reload {
+        println!("Plugin hot reload enabled, checking libraries every {}s", config.plugin_watch_interval);
+    }
+    println!("Send SIGHUP to reload configuration, SIGTERM to shut down gracefully");
 }
 
 /// Parses command line arguments
@@ -665,11 +1292,24 @@ fn parse_command_line() -> Args {
     let args: Vec<String> = env::args().collect();
     let mut verbose = false;
     let mut config_path = None;
+    let mut replay = None;
+    let mut check_config = false;
 
     let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
             "-v" | "--verbose" => verbose = true,
+            "--check-config" => check_config = true,
+            "--replay" => {
+                i += 1;
+                match args.get(i) {
+                    Some(recording) => replay = Some(recording.clone()),
+                    None => {
+                        eprintln!("--replay requires a recording file");
+                        std::process::exit(1);
+                    }
+                }
+            }
             arg if !arg.starts_with('-') => {
                 if config_path.is_none() {
                     config_path = Some(arg.to_string());
@@ -686,14 +1326,16 @@ fn parse_command_line() -> Args {
     let config_path = match config_path {
         Some(path) => path,
         None => {
-            eprintln!("Usage: {} [-v|--verbose] <config-file>", args[0]);
+            eprintln!("Usage: {} [-v|--verbose] [--replay <recording>] [--check-config] <config-file>", args[0]);
             eprintln!("Example: {} config/config.html", args[0]);
             eprintln!("         {} -v config/config.html", args[0]);
+            eprintln!("         {} --replay recording.har config/config.html", args[0]);
+            eprintln!("         {} --check-config config/config.html", args[0]);
             std::process::exit(1);
         }
     };
 
-    Args { verbose, config_path }
+    Args { verbose, config_path, replay, check_config }
 }
 
 /// Validates the config file path and returns the absolute path
@@ -754,3 +1396,112 @@ fn daemonize_server(config: &ServerConfig) {
         std::process::exit(1);
     }
 }
+
+/// Checks a configuration without serving it and prints the problems found
+///
+/// Every plugin is created as the server would create it, but neither
+/// attached to a host nor started. Returns true when no problem is an error.
+fn run_check(config_path: &str) -> bool {
+    use rusty_beam_plugin_api::log::{self, Level, Logger};
+
+    // Whatever the loader and the plugins log becomes part of the report
+    let sink = Arc::new(check::CollectingSink::default());
+    log::set_logger(Arc::new(Logger::new(Level::Warn).with_sink(sink.clone())));
+
+    let config = load_config_from_html(config_path);
+    let mut report = check::CheckReport::with_secrets(config.secrets.clone());
+    report.extend(check::SERVER_SCOPE, sink.take_issues());
+    // What the plugins log from now on may contain the secrets read into their configuration
+    log::set_logger(Arc::new(Logger::new(Level::Warn).with_sink(sink.clone()).with_secrets(config.secrets.clone())));
+    check::check_server(&config, &mut report);
+
+    for (host_names, host_config) in distinct_hosts(&config) {
+        let scope = host_names.join(", ");
+        report.hosts_checked += 1;
+        report.extend(&scope, check::check_host_root(&host_config.host_root));
+
+        let context = PluginContext {
+            plugin_config: HashMap::new(),
+            host_config: host_config_map(host_config),
+            server_config: server_config_map(&config),
+            server_metadata: HashMap::from([("config_file_path".to_string(), config_path.to_string())]),
+            host_name: host_names[0].clone(),
+            request_id: String::new(),
+            runtime_handle: None,
+            verbose: false,
+            services: None,
+            deadline: None,
+        };
+
+        let mut loaded = Vec::new();
+        for plugin_config in &host_config.plugins {
+            if !check_plugin_libraries(plugin_config, &scope, &mut report) {
+                continue;
+            }
+            match load_plugin(plugin_config, false) {
+                Some(plugin) => {
+                    let name = plugin.name().to_string();
+                    let issues = plugin.validate_config(&context);
+                    report.extend(&scope, sink.take_issues());
+                    report.extend(&scope, issues.into_iter().map(|issue| ConfigIssue {
+                        message: format!("{}: {}", name, issue.message),
+                        ..issue
+                    }));
+                    loaded.push((plugin, plugin_config));
+                }
+                None => {
+                    report.extend(&scope, sink.take_issues());
+                    report.add(&scope, ConfigIssue::error(format!("plugin {} could not be created", plugin_config.library)));
+                }
+            }
+        }
+
+        // The order is checked as the server would run the pipeline
+        let plugins: Vec<(&dyn rusty_beam_plugin_api::Plugin, &PluginConfig)> = loaded.iter()
+            .map(|(plugin, plugin_config)| (plugin.as_ref(), *plugin_config))
+            .collect();
+        dependencies::check_pipeline(&scope, &plugins, config.plugin_auto_sort);
+        report.extend(&scope, sink.take_issues());
+    }
+
+    report.print(config_path);
+    report.count(rusty_beam_plugin_api::Severity::Error) == 0
+}
+
+/// The configured hosts, with the hostnames of one HostConfig item together
+fn distinct_hosts(config: &ServerConfig) -> Vec<(Vec<String>, &config::HostConfig)> {
+    let mut host_names: Vec<&String> = config.hosts.keys().collect();
+    host_names.sort();
+
+    let mut hosts: Vec<(Vec<String>, &config::HostConfig)> = Vec::new();
+    for host_name in host_names {
+        let host_config = &config.hosts[host_name];
+        let same_item = |other: &config::HostConfig| {
+            other.host_root == host_config.host_root
+                && serde_json::to_string(&other.plugins).ok() == serde_json::to_string(&host_config.plugins).ok()
+        };
+        match hosts.iter_mut().find(|(_, other)| same_item(other)) {
+            Some((names, _)) => names.push(host_name.clone()),
+            None => hosts.push((vec![host_name.clone()], host_config)),
+        }
+    }
+    hosts
+}
+
+/// Reports missing libraries of a plugin and the plugins nested in it
+///
+/// Returns true when every library exists.
+fn check_plugin_libraries(plugin_config: &PluginConfig, scope: &str, report: &mut check::CheckReport) -> bool {
+    report.plugins_checked += 1;
+    let mut found = match check::check_library(resolve_library_path(&plugin_config.library)) {
+        Some(issue) => {
+            report.add(scope, issue);
+            false
+        }
+        None => true,
+    };
+    for nested in &plugin_config.nested_plugins {
+        found &= check_plugin_libraries(nested, scope, report);
+    }
+    found
+}
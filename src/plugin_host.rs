@@ -0,0 +1,56 @@
+//! # Rusty Beam Plugin Host
+//!
+//! Runs one plugin in a process of its own for a server that isolates it
+//! (see `rusty_beam_plugin_api::isolation`). The server starts the host with
+//! its end of a Unix socket as standard input, names the library to load in
+//! the first message and passes the plugin's calls over the socket until it
+//! closes it. If the plugin crashes, only the host dies, and the server
+//! starts a new one.
+
+use rusty_beam_plugin_api::Plugin;
+use std::collections::HashMap;
+use std::os::fd::{AsRawFd, FromRawFd};
+use std::os::unix::net::UnixStream;
+
+fn main() {
+    // The server hands over the socket as standard input
+    let stream = unsafe { UnixStream::from_raw_fd(std::io::stdin().as_raw_fd()) };
+    if let Err(e) = rusty_beam_plugin_api::isolation::serve(stream, load_plugin) {
+        eprintln!("rusty-beam-plugin-host: {}", e);
+        std::process::exit(1);
+    }
+}
+
+/// Loads the plugin from its library, as the server does for plugins it runs itself
+fn load_plugin(library_path: &str, config: HashMap<String, String>) -> Result<Box<dyn Plugin>, String> {
+    use libloading::{Library, Symbol};
+
+    unsafe {
+        let lib = Library::new(library_path)
+            .map_err(|e| format!("Failed to load library: {}", e))?;
+
+        let create_fn: Symbol<
+            unsafe extern "C" fn(*const std::os::raw::c_char) -> *mut std::ffi::c_void,
+        > = lib.get(b"create_plugin")
+            .map_err(|_| "Plugin missing create_plugin function")?;
+
+        // Plugins built before the logging facade do not take a logger
+        if let Ok(set_logger) = lib.get::<unsafe extern "C" fn(*const std::ffi::c_void)>(rusty_beam_plugin_api::log::SET_LOGGER_FUNCTION) {
+            set_logger(rusty_beam_plugin_api::log::logger_into_raw());
+        }
+
+        let config_json = serde_json::to_string(&config)
+            .map_err(|e| format!("Config serialization failed: {}", e))?;
+        let config_cstr = std::ffi::CString::new(config_json)
+            .map_err(|_| "Invalid config string")?;
+
+        let plugin_ptr = create_fn(config_cstr.as_ptr());
+        if plugin_ptr.is_null() {
+            return Err("Plugin creation returned null".to_string());
+        }
+
+        // The host lives as long as its plugin, so the library is never unloaded
+        std::mem::forget(lib);
+        Ok(*Box::from_raw(plugin_ptr as *mut Box<dyn Plugin>))
+    }
+}
@@ -0,0 +1,139 @@
+//! Replay of recorded traffic for regression testing
+//!
+//! Reads recordings written by the record-replay plugin, either a HAR
+//! document or newline-delimited HAR entries, and turns each entry back into
+//! a request that can be run through the plugin pipeline. The status code and
+//! body of the new response are compared with the recorded ones.
+
+use base64::Engine;
+use hyper::{Body, Request};
+use serde_json::Value;
+
+/// Metadata marking replayed requests, so they are not recorded again
+pub const REPLAYED_METADATA_KEY: &str = "replayed";
+const BASE64_ENCODING: &str = "base64";
+
+/// Request extension set on replayed requests
+///
+/// Unlike a header, a client cannot send it, so only the replay runner can
+/// keep a request out of recordings.
+#[derive(Debug, Clone, Copy)]
+pub struct Replayed;
+
+/// A recorded exchange ready to be replayed
+pub struct RecordedExchange {
+    pub method: String,
+    pub url: String,
+    headers: Vec<(String, String)>,
+    body: Vec<u8>,
+    pub expected_status: u16,
+    /// Recorded response body, unless it was truncated when recorded
+    expected_body: Option<Vec<u8>>,
+}
+
+impl RecordedExchange {
+    /// Builds the request to send through the pipeline
+    pub fn to_request(&self) -> Result<Request<Body>, String> {
+        let uri: hyper::Uri = self.url.parse().map_err(|e| format!("Invalid URL {}: {}", self.url, e))?;
+        let path_and_query = uri.path_and_query().map(|pq| pq.as_str()).unwrap_or("/");
+
+        let mut builder = Request::builder()
+            .method(self.method.as_str())
+            .uri(path_and_query);
+        // Recordings lack a Host header when the client used HTTP/2
+        if !self.headers.iter().any(|(name, _)| name.eq_ignore_ascii_case("host"))
+            && let Some(authority) = uri.authority()
+        {
+            builder = builder.header(hyper::header::HOST, authority.as_str());
+        }
+        for (name, value) in &self.headers {
+            // The body is replayed in full, whatever was originally sent
+            if name.eq_ignore_ascii_case("content-length") || name.eq_ignore_ascii_case("transfer-encoding") {
+                continue;
+            }
+            builder = builder.header(name.as_str(), value.as_str());
+        }
+
+        builder
+            .extension(Replayed)
+            .body(Body::from(self.body.clone()))
+            .map_err(|e| format!("Invalid request {} {}: {}", self.method, self.url, e))
+    }
+
+    /// Describes how a replayed response differs from the recording
+    pub fn differences(&self, status: u16, body: &[u8]) -> Vec<String> {
+        let mut differences = Vec::new();
+        if status != self.expected_status {
+            differences.push(format!("status {} (recorded {})", status, self.expected_status));
+        }
+        if let Some(expected_body) = &self.expected_body
+            && body != expected_body.as_slice()
+        {
+            differences.push(format!("body of {} bytes differs (recorded {} bytes)", body.len(), expected_body.len()));
+        }
+        differences
+    }
+}
+
+/// Loads every exchange from a HAR or NDJSON recording
+pub fn load_recording(path: &str) -> Result<Vec<RecordedExchange>, String> {
+    let content = std::fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+
+    let entries: Vec<Value> = match serde_json::from_str::<Value>(&content) {
+        Ok(document) if document.get("log").is_some() => document["log"]["entries"]
+            .as_array()
+            .cloned()
+            .ok_or_else(|| format!("{} has no log.entries", path))?,
+        _ => content.lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(number, line)| serde_json::from_str(line)
+                .map_err(|e| format!("{} line {}: {}", path, number + 1, e)))
+            .collect::<Result<_, _>>()?,
+    };
+
+    entries.iter()
+        .enumerate()
+        .map(|(index, entry)| parse_entry(entry).map_err(|e| format!("Entry {}: {}", index + 1, e)))
+        .collect()
+}
+
+/// Converts one HAR entry into an exchange
+fn parse_entry(entry: &Value) -> Result<RecordedExchange, String> {
+    let request = &entry["request"];
+    let response = &entry["response"];
+
+    let method = request["method"].as_str().ok_or("missing request.method")?.to_string();
+    let url = request["url"].as_str().ok_or("missing request.url")?.to_string();
+    let headers = request["headers"].as_array()
+        .map(|headers| headers.iter()
+            .filter_map(|header| Some((header["name"].as_str()?.to_string(), header["value"].as_str()?.to_string())))
+            .collect())
+        .unwrap_or_default();
+    let body = match request.get("postData") {
+        Some(post_data) => decode_body(post_data)?,
+        None => Vec::new(),
+    };
+
+    let expected_status = response["status"].as_u64().ok_or("missing response.status")? as u16;
+    let content = &response["content"];
+    let expected_body = if content["_truncated"].as_bool().unwrap_or(false) || content.get("text").is_none() {
+        None
+    } else {
+        Some(decode_body(content)?)
+    };
+
+    Ok(RecordedExchange { method, url, headers, body, expected_status, expected_body })
+}
+
+/// Decodes the `text` of a HAR postData or content object
+fn decode_body(value: &Value) -> Result<Vec<u8>, String> {
+    let text = value["text"].as_str().unwrap_or("");
+    if value["encoding"].as_str() == Some(BASE64_ENCODING) {
+        base64::engine::general_purpose::STANDARD
+            .decode(text)
+            .map_err(|e| format!("invalid base64 body: {}", e))
+    } else {
+        Ok(text.as_bytes().to_vec())
+    }
+}
@@ -0,0 +1,123 @@
+//! TLS support for Rusty Beam
+//!
+//! This module loads the PEM certificate and key named in the server
+//! configuration and wraps the listening socket so that hyper receives
+//! already-decrypted connections. ALPN advertises `h2` alongside `http/1.1`
+//! when HTTP/2 is enabled, letting browsers multiplex requests over a single
+//! connection.
+//!
+//! Handshakes run in their own tasks, so a slow or stalled client never holds
+//! up accepting other connections.
+
+use crate::{log_error, log_verbose};
+use hyper::server::accept::{self, Accept};
+use hyper::server::conn::{AddrIncoming, AddrStream};
+use crate::load::{CountedStream, LimitedIncoming};
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_rustls::rustls::{Certificate, PrivateKey, ServerConfig as TlsServerConfig};
+use tokio_rustls::server::TlsStream;
+use tokio_rustls::TlsAcceptor;
+
+// ALPN protocol identifiers
+const ALPN_HTTP2: &[u8] = b"h2";
+const ALPN_HTTP1: &[u8] = b"http/1.1";
+
+// Connection handling
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+const PENDING_CONNECTION_BACKLOG: usize = 128;
+
+/// Builds a TLS acceptor from PEM certificate chain and private key files
+pub fn build_acceptor(certificate_path: &str, private_key_path: &str, http2: bool) -> Result<TlsAcceptor, String> {
+    let certificates = load_certificates(certificate_path)?;
+    let private_key = load_private_key(private_key_path)?;
+
+    let mut tls_config = TlsServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_single_cert(certificates, private_key)
+        .map_err(|e| format!("Invalid certificate or key: {}", e))?;
+
+    tls_config.alpn_protocols = if http2 {
+        vec![ALPN_HTTP2.to_vec(), ALPN_HTTP1.to_vec()]
+    } else {
+        vec![ALPN_HTTP1.to_vec()]
+    };
+
+    Ok(TlsAcceptor::from(Arc::new(tls_config)))
+}
+
+/// Reads every certificate from a PEM file
+fn load_certificates(path: &str) -> Result<Vec<Certificate>, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open certificate {}: {}", path, e))?;
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(file))
+        .map_err(|e| format!("Failed to parse certificate {}: {}", path, e))?;
+
+    if certificates.is_empty() {
+        return Err(format!("No certificates found in {}", path));
+    }
+    Ok(certificates.into_iter().map(Certificate).collect())
+}
+
+/// Reads the first PKCS#8, RSA or EC private key from a PEM file
+fn load_private_key(path: &str) -> Result<PrivateKey, String> {
+    let file = File::open(path).map_err(|e| format!("Failed to open private key {}: {}", path, e))?;
+    let items = rustls_pemfile::read_all(&mut BufReader::new(file))
+        .map_err(|e| format!("Failed to parse private key {}: {}", path, e))?;
+
+    items.into_iter()
+        .find_map(|item| match item {
+            rustls_pemfile::Item::PKCS8Key(key)
+            | rustls_pemfile::Item::RSAKey(key)
+            | rustls_pemfile::Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| format!("No private key found in {}", path))
+}
+
+/// Wraps a listening socket so it yields TLS connections
+pub fn incoming(
+    mut incoming: LimitedIncoming<AddrIncoming>,
+    acceptor: TlsAcceptor,
+) -> impl Accept<Conn = TlsStream<CountedStream<AddrStream>>, Error = io::Error> {
+    let (tx, rx) = tokio::sync::mpsc::channel(PENDING_CONNECTION_BACKLOG);
+
+    tokio::spawn(async move {
+        loop {
+            let stream = futures::future::poll_fn(|cx| Pin::new(&mut incoming).poll_accept(cx)).await;
+            let stream = match stream {
+                Some(Ok(stream)) => stream,
+                Some(Err(e)) => {
+                    log_error!("Failed to accept connection: {}", e);
+                    continue;
+                }
+                None => break,
+            };
+
+            // The server has stopped accepting connections
+            if tx.is_closed() {
+                break;
+            }
+
+            let acceptor = acceptor.clone();
+            let tx = tx.clone();
+            tokio::spawn(async move {
+                let remote_addr = stream.remote_addr();
+                match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                    Ok(Ok(tls_stream)) => {
+                        let _ = tx.send(tls_stream).await;
+                    }
+                    Ok(Err(e)) => log_verbose!("TLS handshake with {} failed: {}", remote_addr, e),
+                    Err(_) => log_verbose!("TLS handshake with {} timed out", remote_addr),
+                }
+            });
+        }
+    });
+
+    accept::from_stream(futures::stream::unfold(rx, |mut rx| async move {
+        rx.recv().await.map(|stream| (Ok(stream), rx))
+    }))
+}
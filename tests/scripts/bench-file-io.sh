@@ -0,0 +1,56 @@
+#!/bin/bash
+#
+# Latency of small GETs while large files are written and read concurrently.
+# Run against a server with the file handler, e.g.
+#   cargo run --release -- tests/plugins/configs/file-handler-config.html
+#   tests/scripts/bench-file-io.sh http://localhost:3000
+#
+# Prints the p50, p99 and maximum time of the small GETs. File I/O on the
+# async threads shows up as a long tail when the large transfers hold them.
+
+BASE_URL="${1:-http://localhost:3000}"
+HOST="${HOST:-localhost}"
+REQUESTS="${REQUESTS:-2000}"
+CONCURRENCY="${CONCURRENCY:-32}"
+LARGE_MB="${LARGE_MB:-64}"
+LARGE_WORKERS="${LARGE_WORKERS:-8}"
+
+WORK_DIR=$(mktemp -d)
+trap 'kill $(jobs -p) 2>/dev/null; rm -rf "$WORK_DIR"' EXIT
+
+head -c $((LARGE_MB * 1024 * 1024)) /dev/urandom > "$WORK_DIR/large.bin"
+curl -s -o /dev/null -H "Host: $HOST" -X PUT --data-binary "small" "$BASE_URL/bench-small.txt"
+
+# Keep large files moving for the whole run
+for i in $(seq 1 "$LARGE_WORKERS"); do
+    (
+        while true; do
+            curl -s -o /dev/null -H "Host: $HOST" -X PUT --data-binary "@$WORK_DIR/large.bin" "$BASE_URL/bench-large-$i.bin"
+            curl -s -o /dev/null -H "Host: $HOST" "$BASE_URL/bench-large-$i.bin"
+        done
+    ) &
+done
+sleep 1
+
+seq 1 "$REQUESTS" | xargs -P "$CONCURRENCY" -I{} \
+    curl -s -o /dev/null -H "Host: $HOST" -w "%{time_total}\n" "$BASE_URL/bench-small.txt" \
+    | sort -n > "$WORK_DIR/times"
+
+kill $(jobs -p) 2>/dev/null
+wait 2>/dev/null
+
+percentile() {
+    local count
+    count=$(wc -l < "$WORK_DIR/times")
+    sed -n "$(( (count * $1 + 99) / 100 ))p" "$WORK_DIR/times"
+}
+
+echo "Small GETs: $(wc -l < "$WORK_DIR/times") with $LARGE_WORKERS concurrent ${LARGE_MB}MB transfers"
+echo "p50: $(percentile 50)s"
+echo "p99: $(percentile 99)s"
+echo "max: $(tail -n 1 "$WORK_DIR/times")s"
+
+for i in $(seq 1 "$LARGE_WORKERS"); do
+    curl -s -o /dev/null -H "Host: $HOST" -X DELETE "$BASE_URL/bench-large-$i.bin"
+done
+curl -s -o /dev/null -H "Host: $HOST" -X DELETE "$BASE_URL/bench-small.txt"